@@ -0,0 +1,41 @@
+//! Lets the user decide, item by item, what hosts get to see and change, so a
+//! single backing filesystem can be exposed differently to different hosts
+//! without wrapping it in a custom `FileSystemOps`.
+//!
+//! The `Authorizer` set with `FakeFatBuilder::authorizer` is asked about every
+//! item whenever its directory is rendered into the device, and about every
+//! item the host created or changed when the host's writes are decoded by
+//! `FakeFat::write_back`.
+
+/// What a host is trying to do to an item.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Operation {
+    /// Listing the item in its directory and reading its contents.
+    Read,
+    /// Creating the item, or changing its contents, in the backing filesystem.
+    Write,
+}
+
+/// What an `Authorizer` allows a host to do to an item.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Access {
+    /// The operation goes ahead as usual.
+    Allow,
+    /// For reads, the item is listed but its contents are not: files appear
+    /// empty and directories appear to have no children. Writes are dropped.
+    Deny,
+    /// For reads, the item is left out of its directory entirely, as if it did
+    /// not exist. Writes are dropped, just like with `Deny`.
+    Hide,
+}
+
+/// Decides what a host may do to the item at a path in the backing
+/// filesystem, like the paths passed to `FileSystemOps`, but without a trailing
+/// `/` for directories.
+///
+/// Since a host can create an item with the same name as one that is hidden
+/// from it, an authorizer that hides an item from reads should refuse writes to
+/// it as well.
+pub type Authorizer = fn(Operation, &str) -> Access;
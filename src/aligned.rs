@@ -0,0 +1,143 @@
+//! Transfer buffers with a guaranteed alignment, for USB and SD card
+//! controllers whose DMA engines can only move data to or from addresses that
+//! are a multiple of their burst size.
+//!
+//! Reading into an `Aligned` buffer writes the contents of the device straight
+//! into it. Nothing is read through an intermediate buffer and nothing is
+//! allocated, so with a backend that keeps its files in memory, such as a
+//! `ManifestFileSystem` serving static data, the only copy made is the one
+//! from the backend into the buffer the DMA engine then transfers from.
+
+use crate::faker::FakeFat;
+use crate::sector::SectorError;
+use crate::traits::FileSystemOps;
+
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// An alignment an `Aligned` buffer can be given, which is one of the marker
+/// types `A4`, `A8`, `A16`, `A32` or `A64`.
+pub trait Alignment: Copy + private::Sealed {
+    /// The alignment, in bytes.
+    const ALIGN: usize;
+}
+
+macro_rules! alignments {
+    ($($name:ident = $align:literal),*) => {
+        $(
+            #[doc = concat!("Aligns an `Aligned` buffer to ", stringify!($align), " bytes.")]
+            #[derive(Copy, Clone, Debug)]
+            #[repr(align($align))]
+            pub struct $name;
+
+            impl private::Sealed for $name {}
+
+            impl Alignment for $name {
+                const ALIGN: usize = $align;
+            }
+        )*
+    };
+}
+
+alignments!(A4 = 4, A8 = 8, A16 = 16, A32 = 32, A64 = 64);
+
+/// A buffer of `N` bytes that starts at an address that is a multiple of the
+/// alignment `A`.
+///
+/// ```ignore
+/// static mut TRANSFER: Aligned<A32, 4096> = Aligned::new();
+/// ```
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct Aligned<A: Alignment, const N: usize> {
+    _align: [A; 0],
+    bytes: [u8; N],
+}
+
+impl<A: Alignment, const N: usize> Aligned<A, N> {
+    /// A zeroed buffer.
+    pub const fn new() -> Self {
+        Aligned {
+            _align: [],
+            bytes: [0; N],
+        }
+    }
+
+    /// The alignment of the buffer, in bytes.
+    pub const fn align(&self) -> usize {
+        A::ALIGN
+    }
+}
+
+impl<A: Alignment, const N: usize> Default for Aligned<A, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: Alignment, const N: usize> Deref for Aligned<A, N> {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl<A: Alignment, const N: usize> DerefMut for Aligned<A, N> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+}
+
+impl<A: Alignment, const N: usize> AsRef<[u8]> for Aligned<A, N> {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl<A: Alignment, const N: usize> AsMut<[u8]> for Aligned<A, N> {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+}
+
+impl<A: Alignment, const N: usize> fmt::Debug for Aligned<A, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Aligned")
+            .field("align", &A::ALIGN)
+            .field("len", &N)
+            .finish()
+    }
+}
+
+impl<T: FileSystemOps> FakeFat<T> {
+    /// Reads up to `N` bytes of the device into `buffer`, starting `idx` bytes
+    /// from the head of the device, exactly like `read_at`.
+    ///
+    /// The device is read straight into `buffer`, without going through an
+    /// intermediate buffer.
+    pub fn read_aligned<A: Alignment, const N: usize>(
+        &mut self,
+        idx: usize,
+        buffer: &mut Aligned<A, N>,
+    ) -> usize {
+        self.read_at(idx, &mut buffer.bytes)
+    }
+
+    /// Reads the sectors from `lba` on into `buffer`, which must be a whole,
+    /// nonzero number of sectors long, like `read_sector` does for a single
+    /// sector.
+    ///
+    /// Nothing is read if any of the sectors are past the end of the device,
+    /// or if the rate limits do not allow reading all of them yet.
+    pub fn read_sectors_aligned<A: Alignment, const N: usize>(
+        &mut self,
+        lba: u32,
+        buffer: &mut Aligned<A, N>,
+    ) -> Result<(), SectorError> {
+        self.read_sectors(lba, &mut buffer.bytes)
+    }
+}
@@ -0,0 +1,477 @@
+//! Async backing filesystems, such as HTTP or S3 clients or flash drivers
+//! running on an embassy executor, and the `AsyncFakeFat` that serves them.
+//!
+//! Rendering the device stays synchronous: `AsyncFakeFat` walks the tree of
+//! the backend into memory when it is built and when it is refreshed, and
+//! before each read it awaits the parts of the files the read covers, after
+//! which the read is answered the same way a `FakeFat` answers it. Nothing in
+//! here depends on a particular executor, so the futures can be awaited from
+//! tokio, embassy or anything else, and they are `Send` whenever the futures of
+//! the backend are.
+//!
+//! Every synchronous `FileSystemOps` is also an `AsyncFileSystemOps` whose
+//! futures complete right away.
+
+#[cfg(feature = "std")]
+use std as alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::builder::FakeFatBuilder;
+use crate::clustermapping::ClusterMapperOps;
+use crate::error::FakeFatError;
+use crate::faker::{FakeFat, OutOfRangeReads};
+use crate::geometry::offset_to_cluster;
+use crate::pathbuffer::PathBuff;
+use crate::sector::SectorError;
+use crate::traits::{DirEntryOps, DirectoryOps, FileMetadata, FileOps, FileSystemOps};
+use crate::writeback::MAX_DEPTH;
+
+use core::convert::Infallible;
+use core::fmt;
+
+/// The async version of `FileOps`.
+// The futures are left without a `Send` bound so that backends on
+// single-threaded executors can hold on to `!Send` state across awaits.
+#[allow(async_fn_in_trait)]
+pub trait AsyncFileOps {
+    /// The error the file reports when it cannot be read.
+    type Error: fmt::Debug;
+
+    /// Reads up to `buffer.len()` bytes from the file starting `offset`
+    /// bytes from the start of the file, returning the number of bytes read.
+    async fn read_at(&mut self, offset: usize, buffer: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+/// The async version of `DirectoryOps`.
+#[allow(async_fn_in_trait)]
+pub trait AsyncDirectoryOps {
+    /// The type of entries that this directory contains.
+    type EntryType: DirEntryOps;
+
+    /// The type of struct the directory uses to iterate over its entries.
+    type IterType: IntoIterator<Item = Self::EntryType>;
+
+    /// The error the directory reports when it cannot be listed.
+    type Error: fmt::Debug;
+
+    /// Lists this directory's entries.
+    async fn entries(&self) -> Result<Self::IterType, Self::Error>;
+}
+
+/// The async version of `FileSystemOps`, for backing filesystems that are
+/// served by an `AsyncFakeFat`.
+#[allow(async_fn_in_trait)]
+pub trait AsyncFileSystemOps {
+    /// The directory struct that this FileSystem uses.
+    type DirectoryType: AsyncDirectoryOps<Error = Self::Error>;
+
+    /// The file struct that this FileSystem uses.
+    type FileType: AsyncFileOps<Error = Self::Error>;
+
+    /// The error the filesystem, its directories and its files report when
+    /// something cannot be read.
+    type Error: fmt::Debug;
+
+    /// Attempts to find a file with the given path.
+    ///
+    /// Returns `Ok(None)` if `path` does not represent an already existing
+    /// non-directory file.
+    async fn get_file(&mut self, path: &str) -> Result<Option<Self::FileType>, Self::Error>;
+
+    /// Attempts to find a directory with the given path.
+    ///
+    /// Returns `Ok(None)` if `path` does not represent an already existing
+    /// non-file directory.
+    async fn get_dir(&mut self, path: &str) -> Result<Option<Self::DirectoryType>, Self::Error>;
+
+    /// Attempts to find metadata about an item with the given path.
+    ///
+    /// Returns `Ok(None)` if `path` does not represent an already existing
+    /// file or directory.
+    async fn get_metadata(&mut self, path: &str) -> Result<Option<FileMetadata>, Self::Error>;
+}
+
+impl<F: FileOps> AsyncFileOps for F {
+    type Error = F::Error;
+
+    async fn read_at(&mut self, offset: usize, buffer: &mut [u8]) -> Result<usize, F::Error> {
+        FileOps::read_at(self, offset, buffer)
+    }
+}
+
+impl<D: DirectoryOps> AsyncDirectoryOps for D {
+    type EntryType = D::EntryType;
+    type IterType = D::IterType;
+    type Error = D::Error;
+
+    async fn entries(&self) -> Result<D::IterType, D::Error> {
+        DirectoryOps::entries(self)
+    }
+}
+
+impl<T: FileSystemOps> AsyncFileSystemOps for T {
+    type DirectoryType = T::DirectoryType;
+    type FileType = T::FileType;
+    type Error = T::Error;
+
+    async fn get_file(&mut self, path: &str) -> Result<Option<T::FileType>, T::Error> {
+        FileSystemOps::get_file(self, path)
+    }
+
+    async fn get_dir(&mut self, path: &str) -> Result<Option<T::DirectoryType>, T::Error> {
+        FileSystemOps::get_dir(self, path)
+    }
+
+    async fn get_metadata(&mut self, path: &str) -> Result<Option<FileMetadata>, T::Error> {
+        FileSystemOps::get_metadata(self, path)
+    }
+}
+
+/// A device backed by an `AsyncFileSystemOps`, whose reads await the parts of
+/// the files they cover.
+///
+/// The device is read only; the options of the builder it is built with apply
+/// as they do to a `FakeFat`, and `fat` gives access to everything about the
+/// device that does not need the backend.
+pub struct AsyncFakeFat<B: AsyncFileSystemOps> {
+    fat: FakeFat<AsyncSnapshot>,
+    backend: B,
+    prefix: PathBuff,
+    backend_error: Option<B::Error>,
+}
+
+impl<B: AsyncFileSystemOps> AsyncFakeFat<B> {
+    /// Builds a device exposing the tree below `path_prefix` in `backend`,
+    /// like `FakeFatBuilder::build` does for a synchronous filesystem.
+    ///
+    /// # Panics
+    /// This function panics if the options of `builder` do not go together.
+    pub async fn build(builder: FakeFatBuilder, mut backend: B, path_prefix: &str) -> Self {
+        let prefix = PathBuff::from_prefix(path_prefix);
+        let mut backend_error = None;
+        let snapshot = AsyncSnapshot::walk(&mut backend, prefix.to_str(), &mut backend_error).await;
+        AsyncFakeFat {
+            fat: builder.build(snapshot, prefix.to_str()),
+            backend,
+            prefix,
+            backend_error,
+        }
+    }
+
+    /// The device as of the last refresh, for everything that does not read the
+    /// contents of files, such as its geometry, layout and statistics.
+    pub fn fat(&self) -> &FakeFat<AsyncSnapshot> {
+        &self.fat
+    }
+
+    /// The backend the device is served from.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Takes the last error the backend reported, like
+    /// `FakeFat::take_backend_error`.
+    pub fn take_backend_error(&mut self) -> Option<B::Error> {
+        self.backend_error.take()
+    }
+
+    /// Reads up to `buffer.len()` bytes of the device into `buffer`, starting
+    /// `idx` bytes from the head of the device, like `FakeFat::read_at`.
+    pub async fn read_at(&mut self, idx: usize, buffer: &mut [u8]) -> usize {
+        self.stage(idx, buffer.len()).await;
+        let read = self.fat.read_at(idx, buffer);
+        self.fat.fs.staged.clear();
+        read
+    }
+
+    /// Reads sector `lba` into `buffer`, like `FakeFat::read_sector`.
+    pub async fn read_sector(&mut self, lba: u32, buffer: &mut [u8]) -> Result<(), SectorError> {
+        let sector_size = self.fat.sector_size();
+        self.stage(lba as usize * sector_size, sector_size).await;
+        let result = self.fat.read_sector(lba, buffer);
+        self.fat.fs.staged.clear();
+        result
+    }
+
+    /// Walks the tree of the backend again and picks up whatever changed in
+    /// it, like `FakeFat::refresh`.
+    ///
+    /// Returns the number of directories whose version was bumped, or fails
+    /// like `FakeFat::refresh`.
+    pub async fn refresh(&mut self) -> Result<usize, FakeFatError> {
+        let snapshot = AsyncSnapshot::walk(
+            &mut self.backend,
+            self.prefix.to_str(),
+            &mut self.backend_error,
+        )
+        .await;
+        self.fat.fs = snapshot;
+        self.fat.refresh()
+    }
+
+    /// Fetches the parts of the files that a read of `len` bytes at `idx`
+    /// covers, a run of consecutive clusters of a file at a time.
+    async fn stage(&mut self, idx: usize, len: usize) {
+        let mut runs: Vec<(String, usize, usize)> = Vec::new();
+        let image_size = self.fat.image_size();
+        let wraps = self.fat.out_of_range_reads == OutOfRangeReads::Wrap;
+        let mut pos = idx;
+        let mut left = len;
+        while left > 0 && image_size > 0 {
+            let start = if wraps { pos % image_size } else { pos };
+            if start >= image_size {
+                break;
+            }
+            let run = left.min(image_size - start);
+            self.file_runs(start, start + run, &mut runs);
+            pos += run;
+            left -= run;
+        }
+        for (path, offset, len) in runs {
+            let data = match self.fetch(&path, offset, len).await {
+                Ok(data) => data,
+                Err(e) => {
+                    self.backend_error = Some(e);
+                    continue;
+                }
+            };
+            self.fat.fs.staged.push(Staged { path, offset, data });
+        }
+    }
+
+    /// Adds the parts of the files in the device range `start..end` to `runs`,
+    /// as their path, offset and length.
+    fn file_runs(&self, start: usize, end: usize, runs: &mut Vec<(String, usize, usize)>) {
+        let bpb = &self.fat.layout.bpb;
+        let start = start.max(bpb.data_start());
+        if start >= end {
+            return;
+        }
+        let cluster_size = bpb.bytes_per_cluster() as usize;
+        let mapper = &self.fat.layout.mapper;
+        for cluster in offset_to_cluster(bpb, start)..=offset_to_cluster(bpb, end - 1) {
+            let (path, position) = match (
+                mapper.get_path_for_cluster(cluster),
+                mapper.chain_position(cluster),
+            ) {
+                (Some(path), Some(position)) => (path, position),
+                _ => continue,
+            };
+            let size = match self.fat.fs.meta.get(snapshot_key(path)) {
+                Some(meta) if !meta.is_directory => meta.size as usize,
+                _ => continue,
+            };
+            let offset = position * cluster_size;
+            if offset >= size {
+                continue;
+            }
+            let len = cluster_size.min(size - offset);
+            match runs.last_mut() {
+                Some((last, last_offset, last_len))
+                    if last.as_str() == path && *last_offset + *last_len == offset =>
+                {
+                    *last_len += len;
+                }
+                _ => runs.push((path.into(), offset, len)),
+            }
+        }
+    }
+
+    /// Reads `len` bytes of the file at `path` from `offset` on, or as many as
+    /// the file has.
+    async fn fetch(&mut self, path: &str, offset: usize, len: usize) -> Result<Vec<u8>, B::Error> {
+        let mut file = match self.backend.get_file(path).await? {
+            Some(file) => file,
+            None => return Ok(Vec::new()),
+        };
+        let mut data = alloc::vec![0; len];
+        let mut read = 0;
+        while read < len {
+            match file.read_at(offset + read, &mut data[read..]).await? {
+                0 => break,
+                cur_read => read += cur_read,
+            }
+        }
+        data.truncate(read);
+        Ok(data)
+    }
+}
+
+/// The tree of the backend of an `AsyncFakeFat` as of its last refresh, along
+/// with the parts of files fetched for the read in progress.
+///
+/// Files read as empty outside of the reads of the `AsyncFakeFat`.
+pub struct AsyncSnapshot {
+    /// The metadata of every item, keyed by its path without trailing
+    /// separators.
+    meta: BTreeMap<String, FileMetadata>,
+    /// The children of every directory that could be listed, keyed like
+    /// `meta`.
+    dirs: BTreeMap<String, Vec<SnapshotEntry>>,
+    staged: Vec<Staged>,
+}
+
+/// Part of a file fetched for the read in progress.
+struct Staged {
+    path: String,
+    offset: usize,
+    data: Vec<u8>,
+}
+
+impl AsyncSnapshot {
+    /// Walks the tree below `root` in `backend`, keeping the last error it
+    /// reports in `backend_error` and leaving out whatever it could not read.
+    async fn walk<B: AsyncFileSystemOps>(
+        backend: &mut B,
+        root: &str,
+        backend_error: &mut Option<B::Error>,
+    ) -> Self {
+        let mut snapshot = AsyncSnapshot {
+            meta: BTreeMap::new(),
+            dirs: BTreeMap::new(),
+            staged: Vec::new(),
+        };
+        match backend.get_metadata(root).await {
+            Ok(Some(meta)) => {
+                snapshot.meta.insert(snapshot_key(root).into(), meta);
+            }
+            Ok(None) => {}
+            Err(e) => *backend_error = Some(e),
+        }
+        let mut pending: Vec<(String, usize)> = alloc::vec![(root.into(), 0)];
+        while let Some((dir, depth)) = pending.pop() {
+            let listing = match backend.get_dir(&dir).await {
+                Ok(Some(directory)) => directory.entries().await,
+                Ok(None) => continue,
+                Err(e) => Err(e),
+            };
+            let listing = match listing {
+                Ok(listing) => listing,
+                Err(e) => {
+                    *backend_error = Some(e);
+                    continue;
+                }
+            };
+            let mut children = Vec::new();
+            for entry in listing {
+                let name = entry.name();
+                let meta = entry.meta();
+                let mut path = dir.clone();
+                path.push_str(name.as_ref());
+                if meta.is_directory {
+                    path.push('/');
+                    if depth < MAX_DEPTH {
+                        pending.push((path.clone(), depth + 1));
+                    }
+                }
+                snapshot.meta.insert(snapshot_key(&path).into(), meta);
+                children.push(SnapshotEntry {
+                    name: name.as_ref().into(),
+                    meta,
+                });
+            }
+            snapshot.dirs.insert(snapshot_key(&dir).into(), children);
+        }
+        snapshot
+    }
+}
+
+/// The key of `path` in an `AsyncSnapshot`.
+fn snapshot_key(path: &str) -> &str {
+    path.trim_end_matches('/')
+}
+
+impl FileSystemOps for AsyncSnapshot {
+    type DirectoryType = SnapshotDir;
+    type FileType = SnapshotFile;
+    type Error = Infallible;
+
+    fn get_file(&mut self, path: &str) -> Result<Option<SnapshotFile>, Infallible> {
+        match self.meta.get(snapshot_key(path)) {
+            Some(meta) if !meta.is_directory => {}
+            _ => return Ok(None),
+        }
+        let parts = self
+            .staged
+            .iter()
+            .filter(|staged| staged.path == path)
+            .map(|staged| (staged.offset, staged.data.clone()))
+            .collect();
+        Ok(Some(SnapshotFile { parts }))
+    }
+
+    fn get_dir(&mut self, path: &str) -> Result<Option<SnapshotDir>, Infallible> {
+        Ok(self
+            .dirs
+            .get(snapshot_key(path))
+            .map(|entries| SnapshotDir {
+                entries: entries.clone(),
+            }))
+    }
+
+    fn get_metadata(&mut self, path: &str) -> Result<Option<FileMetadata>, Infallible> {
+        Ok(self.meta.get(snapshot_key(path)).copied())
+    }
+}
+
+/// A file of an `AsyncSnapshot`, holding the parts of it fetched for the read
+/// in progress.
+pub struct SnapshotFile {
+    parts: Vec<(usize, Vec<u8>)>,
+}
+
+impl FileOps for SnapshotFile {
+    type Error = Infallible;
+
+    fn read_at(&mut self, offset: usize, buffer: &mut [u8]) -> Result<usize, Infallible> {
+        let part = self
+            .parts
+            .iter()
+            .find(|(start, data)| (*start..*start + data.len()).contains(&offset));
+        let (start, data) = match part {
+            Some(part) => part,
+            None => return Ok(0),
+        };
+        let data = &data[offset - start..];
+        let len = data.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&data[..len]);
+        Ok(len)
+    }
+}
+
+/// A directory of an `AsyncSnapshot`.
+pub struct SnapshotDir {
+    entries: Vec<SnapshotEntry>,
+}
+
+impl DirectoryOps for SnapshotDir {
+    type EntryType = SnapshotEntry;
+    type IterType = alloc::vec::IntoIter<SnapshotEntry>;
+    type Error = Infallible;
+
+    fn entries(&self) -> Result<Self::IterType, Infallible> {
+        Ok(self.entries.clone().into_iter())
+    }
+}
+
+/// A child of a `SnapshotDir`.
+#[derive(Clone)]
+pub struct SnapshotEntry {
+    name: String,
+    meta: FileMetadata,
+}
+
+impl DirEntryOps for SnapshotEntry {
+    type NameType = String;
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn meta(&self) -> FileMetadata {
+        self.meta
+    }
+}
@@ -0,0 +1,245 @@
+//! Renders a directory into a FAT image file:
+//!
+//! ```sh
+//! fakefat-dump --label SDCARD --capacity 2G ./rootfs sdcard.img
+//! ```
+//!
+//! Unallocated regions of the image are skipped rather than written when the
+//! output is a regular file, so the file stays sparse on filesystems that
+//! support it.
+
+use fakefat::{FakeFatBuilder, FatType, Preset, StdFileSystem};
+
+use std::convert::TryFrom;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::process;
+
+/// The number of bytes read from the device and written to the output at a time.
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+const USAGE: &str = "\
+Usage: fakefat-dump [OPTIONS] <SOURCE_DIR> <OUTPUT>
+
+Options:
+    -p, --preset <PRESET>         Options suited to a class of hosts: windows-strict,
+                                  linux-lenient or camera-minimal
+    -l, --label <LABEL>           Volume label, up to 11 ASCII characters
+    -i, --volume-id <ID>          Volume serial number in hex, like 1234-ABCD
+    -b, --sector-size <BYTES>     Sector size, a power of two from 512 to 4096
+    -c, --cluster-size <BYTES>    Cluster size, a power of two from the sector size
+                                  to 65536; picked from the capacity if not given
+    -s, --capacity <SIZE>         Total capacity, optionally suffixed with K, M or G;
+                                  twice the size of the source, and at least the
+                                  minimum capacity, if not given
+    -m, --min-capacity <SIZE>     Smallest capacity picked when --capacity is not
+                                  given; defaults to 256M
+    -f, --fat <BITS>              FAT entry width: 12, 16 or 32; picked from the
+                                  capacity if not given
+    -n, --fats <COUNT>            Number of File Allocation Tables; defaults to 2
+    -h, --help                    Print this message";
+
+/// The parsed command line.
+struct Args {
+    builder: FakeFatBuilder,
+    source: String,
+    output: String,
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("{}\n\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = dump(args) {
+        eprintln!("fakefat-dump: {}", e);
+        process::exit(1);
+    }
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+    let mut preset = None;
+    let mut label = None;
+    let mut volume_id = None;
+    let mut sector_size = None;
+    let mut cluster_size = None;
+    let mut capacity = None;
+    let mut min_capacity = None;
+    let mut fat_type = None;
+    let mut fats = None;
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", name))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            "-p" | "--preset" => {
+                let name = value(&arg)?;
+                preset = Some(
+                    Preset::from_name(&name).ok_or_else(|| format!("Unknown preset {:?}", name))?,
+                );
+            }
+            "-l" | "--label" => {
+                let value = value(&arg)?;
+                if value.len() > 11 || !value.is_ascii() {
+                    return Err(format!("Invalid volume label {:?}", value));
+                }
+                label = Some(value);
+            }
+            "-i" | "--volume-id" => {
+                let id = value(&arg)?;
+                volume_id = Some(
+                    u32::from_str_radix(&id.replace('-', ""), 16)
+                        .map_err(|_| format!("Invalid volume id {:?}", id))?,
+                );
+            }
+            "-b" | "--sector-size" => {
+                let size = value(&arg)?;
+                let bytes = parse_size(&size)
+                    .filter(|bytes| bytes.is_power_of_two() && (512..=4096).contains(bytes))
+                    .ok_or_else(|| format!("Invalid sector size {:?}", size))?;
+                sector_size = Some(bytes as u16);
+            }
+            "-c" | "--cluster-size" => {
+                let size = value(&arg)?;
+                let bytes = parse_size(&size)
+                    .filter(|bytes| bytes.is_power_of_two() && (512..=65536).contains(bytes))
+                    .ok_or_else(|| format!("Invalid cluster size {:?}", size))?;
+                cluster_size = Some(bytes);
+            }
+            "-s" | "--capacity" => {
+                let size = value(&arg)?;
+                let bytes =
+                    parse_size(&size).ok_or_else(|| format!("Invalid capacity {:?}", size))?;
+                capacity = Some(bytes);
+            }
+            "-m" | "--min-capacity" => {
+                let size = value(&arg)?;
+                let bytes = parse_size(&size)
+                    .ok_or_else(|| format!("Invalid minimum capacity {:?}", size))?;
+                min_capacity = Some(bytes);
+            }
+            "-f" | "--fat" => {
+                let bits = value(&arg)?;
+                fat_type = Some(match bits.as_str() {
+                    "12" => FatType::Fat12,
+                    "16" => FatType::Fat16,
+                    "32" => FatType::Fat32,
+                    _ => return Err(format!("Invalid FAT type {:?}", bits)),
+                });
+            }
+            "-n" | "--fats" => {
+                let count = value(&arg)?;
+                fats = Some(
+                    count
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|&count| count > 0)
+                        .ok_or_else(|| format!("Invalid FAT count {:?}", count))?,
+                );
+            }
+            flag if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(format!("Unknown option {}", flag));
+            }
+            _ => positional.push(arg),
+        }
+    }
+    // The preset goes first so that the other options override it no matter
+    // where they are on the command line.
+    let mut builder = FakeFatBuilder::new();
+    if let Some(preset) = preset {
+        builder = builder.preset(preset);
+    }
+    if let Some(label) = label {
+        builder = builder.volume_label(&label);
+    }
+    if let Some(id) = volume_id {
+        builder = builder.volume_id(id);
+    }
+    if let Some(bytes) = sector_size {
+        builder = builder.bytes_per_sector(bytes);
+    }
+    if let Some(bytes) = cluster_size {
+        let sector_size = u64::from(sector_size.unwrap_or(512));
+        if bytes < sector_size {
+            return Err(format!(
+                "Cluster size {} is smaller than the sector size",
+                bytes
+            ));
+        }
+        builder = builder.sectors_per_cluster((bytes / sector_size) as u8);
+    }
+    if let Some(bytes) = capacity {
+        builder = builder.total_capacity(bytes);
+    }
+    if let Some(bytes) = min_capacity {
+        builder = builder.min_capacity(bytes);
+    }
+    if let Some(fat_type) = fat_type {
+        builder = builder.fat_type(fat_type);
+    }
+    if let Some(count) = fats {
+        builder = builder.fat_count(count);
+    }
+    builder.validate().map_err(|e| e.to_string())?;
+    match <[String; 2]>::try_from(positional) {
+        Ok([source, output]) => Ok(Args {
+            builder,
+            source,
+            output,
+        }),
+        Err(_) => Err("Expected a source directory and an output path".to_owned()),
+    }
+}
+
+/// Parses a byte count like `4096`, `64K` or `2G`.
+fn parse_size(size: &str) -> Option<u64> {
+    let (digits, multiplier) = match size.char_indices().last()? {
+        (idx, 'k') | (idx, 'K') => (&size[..idx], 1 << 10),
+        (idx, 'm') | (idx, 'M') => (&size[..idx], 1 << 20),
+        (idx, 'g') | (idx, 'G') => (&size[..idx], 1 << 30),
+        _ => (size, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+fn dump(args: Args) -> io::Result<()> {
+    let source = fs::canonicalize(&args.source)?;
+    if !source.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a directory", source.display()),
+        ));
+    }
+    let source = source
+        .to_str()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Source path is not UTF-8"))?;
+    let mut fat = args.builder.build(StdFileSystem::new(), source);
+    let image_size = fat.image_size() as u64;
+
+    let mut output = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&args.output)?;
+    let sparse = output.metadata()?.is_file();
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut chunks = fat.chunks(CHUNK_SIZE).skip_unallocated(sparse);
+    while let Some((offset, data)) = chunks.next_chunk(&mut buffer) {
+        output.seek(SeekFrom::Start(offset as u64))?;
+        output.write_all(data)?;
+    }
+    if sparse {
+        output.set_len(image_size)?;
+    }
+    output.flush()
+}
@@ -1,16 +1,79 @@
 use super::ReadByte;
+use crate::fat::FatType;
+use crate::geometry::{self, DIRENT_SIZE};
 
+const FAT_12_LABEL: [u8; 8] = [b'F', b'A', b'T', b'1', b'2', b' ', b' ', b' '];
+const FAT_16_LABEL: [u8; 8] = [b'F', b'A', b'T', b'1', b'6', b' ', b' ', b' '];
 const FAT_32_LABEL: [u8; 8] = [b'F', b'A', b'T', b'3', b'2', b' ', b' ', b' '];
 const FAT_COUNT: u8 = 2;
 const RESERVED_SECTORS: u16 = 8;
 const MEDIA: u8 = 0xf8;
-const SECTORS_PER_TRACK: u16 = 32; //WHY?
+/// The geometry `mkfs.fat` gives devices it knows nothing about; hosts only
+/// use it to read and write the partition table by cylinder, head and sector.
+const SECTORS_PER_TRACK: u16 = 32;
 const ROOT_DIR_FIRST_CLUSTER: u32 = 2;
-const HEADS: u16 = 64; //WHY?
+const HEADS: u16 = 64;
 const BACKUP_BOOT_SECTOR: u16 = 6; //See above
 const DRIVE_NUM: u8 = 0x80; //Endpoint related?
+/// The label hosts expect on a volume that was never given one.
+const NO_VOLUME_LABEL: [u8; 11] = *b"NO NAME    ";
+/// The OEM name that hosts are the most lenient towards, since it is what
+/// Windows itself writes.
+const OEM_NAME: [u8; 8] = *b"MSWIN4.1";
+/// The offset of the boot code in the boot sector of a FAT12 or FAT16 volume.
+const BOOT_CODE_START: u8 = 0x3E;
+/// The offset of the boot code in the boot sector of a FAT32 volume, which has
+/// a larger BIOS Parameter Block to jump over.
+const FAT32_BOOT_CODE_START: u8 = 0x5A;
 
-/// Represents the metadata present at the head of every FAT32 filesystem.
+/// The fake cylinder, head and sector geometry of a device, which legacy BIOSes
+/// and some embedded hosts use to address it and expect to match the one its
+/// partition table was written with.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChsGeometry {
+    /// The number of heads, between 1 and 255.
+    pub heads: u16,
+    /// The number of sectors per track, between 1 and 63.
+    pub sectors_per_track: u16,
+}
+
+impl Default for ChsGeometry {
+    fn default() -> Self {
+        ChsGeometry {
+            heads: HEADS,
+            sectors_per_track: SECTORS_PER_TRACK,
+        }
+    }
+}
+
+impl ChsGeometry {
+    /// The largest number of cylinders a partition table can address.
+    const MAX_CYLINDERS: u32 = 1024;
+
+    /// The geometry BIOSes using LBA-assist translation report for a disk of
+    /// `total_sectors` sectors: 63 sectors per track, with the fewest heads out
+    /// of 16, 32, 64, 128 and 255 that keep it within 1024 cylinders.
+    pub fn for_sectors(total_sectors: u32) -> Self {
+        let sectors_per_track = 63;
+        let heads = [16, 32, 64, 128]
+            .iter()
+            .copied()
+            .find(|&heads| total_sectors <= Self::MAX_CYLINDERS * heads * sectors_per_track)
+            .unwrap_or(255);
+        ChsGeometry {
+            heads: heads as u16,
+            sectors_per_track: sectors_per_track as u16,
+        }
+    }
+
+    /// Whether hosts can address sectors with this geometry at all.
+    pub fn is_valid(self) -> bool {
+        (1..=255).contains(&self.heads) && (1..=63).contains(&self.sectors_per_track)
+    }
+}
+
+/// Represents the metadata present at the head of every FAT filesystem.
 ///
 /// While it is possible to create one by hand, the values provided by
 /// `BiosParameterBlock::from_sector_information` should suffice for most use cases; generally it is recommended
@@ -18,6 +81,13 @@ const DRIVE_NUM: u8 = 0x80; //Endpoint related?
 /// entire preamble from scratch.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct BiosParameterBlock {
+    /// The name of the system that formatted the volume, padded with spaces;
+    /// defaults to `MSWIN4.1`.
+    ///
+    /// Hosts do not rely on it, but some validators and older versions of
+    /// Windows reject names they do not recognize.
+    pub oem_name: [u8; 8],
+
     /// The number of bytes that the virtual "backing device" reads and writes
     /// at a time; defaults to 512.
     pub bytes_per_sector: u16,
@@ -31,49 +101,72 @@ pub struct BiosParameterBlock {
     pub reserved_sectors: u16,
 
     /// The number of mirrored File Allocation Tables to use in this fake filesystem;
-    /// defaults to 2 since many hosts only support that number.
+    /// defaults to 2 since that is what formatting tools use.
     pub fats: u8,
 
     /// Not sure; defaults to 0xf8.
     pub media: u8,
-    /// Not sure; defaults to 32.
+    /// The number of sectors per track of the fake disk geometry that BIOSes
+    /// and partition tables address the device by; defaults to 32.
     pub sectors_per_track: u16,
-    /// Not sure; defaults to 64.
+    /// The number of heads of the fake disk geometry; defaults to 64.
     pub heads: u16,
     /// Not sure; defaults to 0.
     pub hidden_sectors: u32,
 
+    /// The number of entries in the fixed root directory region of a FAT12 or
+    /// FAT16 volume; defaults to 0, since FAT32 keeps its root directory in a
+    /// regular cluster chain instead.
+    pub root_entries: u16,
+
     /// The size of the filesystem in sectors, including all FATs and the preamble.
+    ///
+    /// Small FAT12 and FAT16 volumes store this in the 16 bit field hosts expect
+    /// instead.
     pub total_sectors_32: u32,
 
     /// The number of sectors that a single File Allocation Table uses.
     /// By default calculated using `default_sectors_per_fat`.
+    ///
+    /// FAT12 and FAT16 volumes store this in the 16 bit field hosts expect
+    /// instead.
     pub sectors_per_fat_32: u32,
 
+    /// The kind of FAT the volume uses; defaults to FAT32.
+    ///
+    /// This has to agree with the number of data clusters the volume has, since
+    /// that is what hosts use to decide the kind of FAT instead.
+    pub fat_type: FatType,
+
     /// Extra filesystem flags.
     ///
     /// Currently only the mirroring flag bit (`0x80`) is used by this crate.
     pub extended_flags: u16,
 
     /// The first cluster of the root directory, usually equal to `reserved_sectors/sectors_per_cluster + 1`.
+    ///
+    /// Only used on FAT32.
     pub root_dir_first_cluster: u32,
 
     /// The sector to find the informational struct containing information about
     /// the free clusters.
+    ///
+    /// Only used on FAT32.
     pub fs_info_sector: u16,
 
-    /// Not sure; defaults to 6.
+    /// The sector holding a backup copy of the boot sector, which is followed
+    /// by a backup copy of the FSInfo sector; defaults to 6.
     ///
-    /// Since the first 8 sectors are allocated as the filesystem header, this
-    /// may be a copy of the raw BIOS bytes that are located at the head of all
-    /// single-partition SCSI drive, but this is not yet confirmed.
+    /// Only used on FAT32, where 0 means there is no backup.
     pub backup_boot_sector: u16,
     /// Not sure; defaults to `0x80`.  
     pub drive_num: u8,
-    /// Not sure; defaults to 0.
+    /// The serial number of the volume, which hosts use to tell volumes with
+    /// the same label apart; defaults to 0.
     pub volume_id: u32,
 
-    /// The label of this filesystem volume.
+    /// The label of this filesystem volume, padded with spaces; defaults to
+    /// `NO NAME`, which is what hosts show for unlabeled volumes.
     pub volume_label: [u8; 11],
 
     /// The current location of the filesystem for the purposes of `Read`/`Write`/`Seek`.
@@ -83,6 +176,7 @@ pub struct BiosParameterBlock {
 impl Default for BiosParameterBlock {
     fn default() -> BiosParameterBlock {
         BiosParameterBlock {
+            oem_name: OEM_NAME,
             bytes_per_sector: 512,
             sectors_per_cluster: 8,
             reserved_sectors: RESERVED_SECTORS,
@@ -91,8 +185,10 @@ impl Default for BiosParameterBlock {
             sectors_per_track: SECTORS_PER_TRACK,
             heads: HEADS,
             hidden_sectors: 0,
+            root_entries: 0,
             total_sectors_32: 0,
             sectors_per_fat_32: 0,
+            fat_type: FatType::Fat32,
 
             extended_flags: 0,
             root_dir_first_cluster: ROOT_DIR_FIRST_CLUSTER,
@@ -100,7 +196,7 @@ impl Default for BiosParameterBlock {
             backup_boot_sector: BACKUP_BOOT_SECTOR,
             drive_num: DRIVE_NUM,
             volume_id: 0,
-            volume_label: [0; 11],
+            volume_label: NO_VOLUME_LABEL,
             read_idx: 0,
         }
     }
@@ -109,8 +205,15 @@ impl Default for BiosParameterBlock {
 impl ReadByte for BiosParameterBlock {
     const SIZE: usize = 512;
     fn read_byte(&self, idx: usize) -> u8 {
-        if idx < 11 {
-            return b'a';
+        if idx < 3 {
+            // A short jump over the BIOS Parameter Block to the boot code.
+            return match idx {
+                0 => 0xEB,
+                1 => self.boot_code_start() - 2,
+                _ => 0x90,
+            };
+        } else if idx < 11 {
+            return self.oem_name[idx - 3];
         } else if idx == 510 {
             return 0x55;
         } else if idx == 511 {
@@ -118,19 +221,19 @@ impl ReadByte for BiosParameterBlock {
         }
         let idx = idx - 11;
         match idx {
-            0 => ((self.bytes_per_sector & 0xFF) as u8),
-            1 => (((self.bytes_per_sector >> 8) & 0xFF) as u8),
+            0 => (self.bytes_per_sector & 0xFF) as u8,
+            1 => ((self.bytes_per_sector >> 8) & 0xFF) as u8,
             2 => self.sectors_per_cluster,
             3 => (self.reserved_sectors & 0xFF) as u8,
             4 => ((self.reserved_sectors >> 8) & 0xFF) as u8,
             5 => self.fats,
-            6 => 0, //(self.root_entries & 0xFF) as u8,
-            7 => 0, // ((self.root_entries >> 8) & 0xFF) as u8,
-            8 => 0, // (self.total_sectors_16 & 0xFF) as u8,
-            9 => 0, //((self.total_sectors_16 >> 8) & 0xFF) as u8,
+            6 => (self.root_entries & 0xFF) as u8,
+            7 => ((self.root_entries >> 8) & 0xFF) as u8,
+            8 => (self.total_sectors_16() & 0xFF) as u8,
+            9 => ((self.total_sectors_16() >> 8) & 0xFF) as u8,
             10 => self.media,
-            11 => 0, // (self.sectors_per_fat_16 & 0xFF) as u8,
-            12 => 0, //((self.sectors_per_fat_16 >> 8) & 0xFF) as u8,
+            11 => (self.sectors_per_fat_16() & 0xFF) as u8,
+            12 => ((self.sectors_per_fat_16() >> 8) & 0xFF) as u8,
             13 => (self.sectors_per_track & 0xFF) as u8,
             14 => ((self.sectors_per_track >> 8) & 0xFF) as u8,
             15 => (self.heads & 0xFF) as u8,
@@ -139,10 +242,12 @@ impl ReadByte for BiosParameterBlock {
             18 => ((self.hidden_sectors >> 8) & 0xFF) as u8,
             19 => ((self.hidden_sectors >> 16) & 0xFF) as u8,
             20 => ((self.hidden_sectors >> 24) & 0xFF) as u8,
-            21 => (self.total_sectors_32 & 0xFF) as u8,
-            22 => ((self.total_sectors_32 >> 8) & 0xFF) as u8,
-            23 => ((self.total_sectors_32 >> 16) & 0xFF) as u8,
-            24 => ((self.total_sectors_32 >> 24) & 0xFF) as u8,
+            21 => (self.large_total_sectors() & 0xFF) as u8,
+            22 => ((self.large_total_sectors() >> 8) & 0xFF) as u8,
+            23 => ((self.large_total_sectors() >> 16) & 0xFF) as u8,
+            24 => ((self.large_total_sectors() >> 24) & 0xFF) as u8,
+            // FAT12 and FAT16 go straight to the extended boot signature.
+            b if self.fat_type != FatType::Fat32 => self.extended_byte(b - 25),
 
             25 => (self.sectors_per_fat_32 & 0xFF) as u8,
             26 => ((self.sectors_per_fat_32 >> 8) & 0xFF) as u8,
@@ -161,23 +266,73 @@ impl ReadByte for BiosParameterBlock {
             39 => (self.backup_boot_sector & 0xFF) as u8,
             40 => ((self.backup_boot_sector >> 8) & 0xFF) as u8,
             _b @ 41..=52 => 0, // self.reserved_0[b - 41],
-            53 => self.drive_num,
-            54 => 0,    //self.reserved_1,
-            55 => 0x29, //self.ext_sig,
-            56 => (self.volume_id & 0xFF) as u8,
-            57 => ((self.volume_id >> 8) & 0xFF) as u8,
-            58 => ((self.volume_id >> 16) & 0xFF) as u8,
-            59 => ((self.volume_id >> 24) & 0xFF) as u8,
-            b @ 60..=70 => self.volume_label[b - 60],
-            b @ 71..=78 => FAT_32_LABEL[b - 71], //self.fs_type_label[b - 71],
-            //79 => 0xaa,
-            //80 => 0x55,
-            _b => 0,
+            b => self.extended_byte(b - 53),
         }
     }
 }
 
 impl BiosParameterBlock {
+    /// The offset of the boot code the boot sector starts by jumping to, right
+    /// after the BIOS Parameter Block.
+    fn boot_code_start(&self) -> u8 {
+        if self.fat_type == FatType::Fat32 {
+            FAT32_BOOT_CODE_START
+        } else {
+            BOOT_CODE_START
+        }
+    }
+
+    /// Reads the byte `idx` bytes into the extended boot signature block, which
+    /// holds the same fields on every kind of FAT but sits at a different offset
+    /// on FAT32.
+    fn extended_byte(&self, idx: usize) -> u8 {
+        match idx {
+            0 => self.drive_num,
+            1 => 0,    //self.reserved_1,
+            2 => 0x29, //self.ext_sig,
+            3 => (self.volume_id & 0xFF) as u8,
+            4 => ((self.volume_id >> 8) & 0xFF) as u8,
+            5 => ((self.volume_id >> 16) & 0xFF) as u8,
+            6 => ((self.volume_id >> 24) & 0xFF) as u8,
+            b @ 7..=17 => self.volume_label[b - 7],
+            b @ 18..=25 => match self.fat_type {
+                FatType::Fat12 => FAT_12_LABEL[b - 18],
+                FatType::Fat16 => FAT_16_LABEL[b - 18],
+                FatType::Fat32 => FAT_32_LABEL[b - 18],
+            },
+            _b => 0,
+        }
+    }
+
+    /// The value of the 16 bit total sector count, which is only used by
+    /// FAT12 and FAT16 volumes small enough to fit.
+    fn total_sectors_16(&self) -> u16 {
+        if self.fat_type != FatType::Fat32 && self.total_sectors_32 <= u32::from(u16::MAX) {
+            self.total_sectors_32 as u16
+        } else {
+            0
+        }
+    }
+
+    /// The value of the 32 bit total sector count, which is only used when the
+    /// 16 bit one is not.
+    fn large_total_sectors(&self) -> u32 {
+        if self.total_sectors_16() == 0 {
+            self.total_sectors_32
+        } else {
+            0
+        }
+    }
+
+    /// The value of the 16 bit FAT size, which is only used by FAT12 and FAT16.
+    fn sectors_per_fat_16(&self) -> u16 {
+        if self.fat_type == FatType::Fat32 {
+            0
+        } else {
+            self.sectors_per_fat_32 as u16
+        }
+    }
+
     /// Constructs a new `BiosParameterBlock` with the given values for
     /// `total_sectors` and `bytes_per_sector` and default values for everything else.
     ///
@@ -187,9 +342,11 @@ impl BiosParameterBlock {
         total_sectors: u32,
         bytes_per_sector: u16,
     ) -> BiosParameterBlock {
-        let mut retval = BiosParameterBlock::default();
-        retval.bytes_per_sector = bytes_per_sector;
-        retval.total_sectors_32 = total_sectors;
+        let mut retval = BiosParameterBlock {
+            bytes_per_sector,
+            total_sectors_32: total_sectors,
+            ..BiosParameterBlock::default()
+        };
         let spf = default_sectors_per_fat(&retval);
         retval.sectors_per_fat_32 = spf;
         retval
@@ -210,11 +367,36 @@ impl BiosParameterBlock {
         u32::from(self.bytes_per_sector) * u32::from(self.sectors_per_cluster)
     }
 
+    /// Whether the volume was given a label, which then also leads its root
+    /// directory as a volume label entry.
+    pub(crate) fn has_volume_label(&self) -> bool {
+        self.volume_label != NO_VOLUME_LABEL && self.volume_label != [b' '; 11]
+    }
+
     /// Returns the starting address of the first File Allocation Table.
     pub fn fat_start(&self) -> usize {
         self.reserved_sectors as usize * self.bytes_per_sector as usize
     }
 
+    /// Returns the starting address of the FSInfo sector, which only FAT32 has.
+    pub(crate) fn fs_info_start(&self) -> usize {
+        self.fs_info_sector as usize * self.bytes_per_sector as usize
+    }
+
+    /// Returns the starting address of the backup copy of the boot sector, or
+    /// `None` if there is none, as is always the case on FAT12 and FAT16.
+    pub(crate) fn backup_boot_start(&self) -> Option<usize> {
+        if self.fat_type != FatType::Fat32 || self.backup_boot_sector == 0 {
+            return None;
+        }
+        Some(self.backup_boot_sector as usize * self.bytes_per_sector as usize)
+    }
+
+    /// The number of bytes in a single File Allocation Table.
+    pub fn fat_bytes(&self) -> usize {
+        self.sectors_per_fat_32 as usize * self.bytes_per_sector as usize
+    }
+
     /// Returns the first index after the end of the final File Allocation Table.
     pub fn fat_end(&self) -> usize {
         self.fat_start()
@@ -222,12 +404,67 @@ impl BiosParameterBlock {
                 * (self.sectors_per_fat_32 as usize)
                 * (self.bytes_per_sector as usize)
     }
+
+    /// The number of sectors the fixed root directory region of a FAT12 or
+    /// FAT16 volume takes up; always 0 on FAT32.
+    pub fn root_dir_sectors(&self) -> u32 {
+        let bytes = u32::from(self.root_entries) * DIRENT_SIZE as u32;
+        bytes.div_ceil(u32::from(self.bytes_per_sector))
+    }
+
+    /// Returns the starting address of the data clusters, right after the
+    /// final File Allocation Table and the fixed root directory region if there
+    /// is one.
+    pub fn data_start(&self) -> usize {
+        self.fat_end() + self.root_dir_sectors() as usize * self.bytes_per_sector as usize
+    }
+
+    /// The number of data clusters on the volume, not counting the fixed root
+    /// directory region of a FAT12 or FAT16 volume.
+    pub fn cluster_count(&self) -> u32 {
+        let data_sectors =
+            self.total_sectors_32 - (self.data_start() / self.bytes_per_sector as usize) as u32;
+        data_sectors / u32::from(self.sectors_per_cluster)
+    }
+
+    /// Returns the starting address of the given cluster, as found by
+    /// `geometry::cluster_to_offset`.
+    pub fn cluster_start(&self, cluster: u32) -> usize {
+        geometry::cluster_to_offset(self, cluster)
+    }
+
+    /// Returns the cluster containing the data section address `idx`, as found
+    /// by `geometry::offset_to_cluster`.
+    pub fn cluster_at(&self, idx: usize) -> u32 {
+        geometry::offset_to_cluster(self, idx)
+    }
+
+    /// The cluster the root directory starts at, which is a pseudo cluster for
+    /// the fixed root directory region of a FAT12 or FAT16 volume.
+    pub(crate) fn root_dir_cluster(&self) -> u32 {
+        geometry::root_dir_cluster(self)
+    }
+
+    /// The first cluster new cluster chains are allocated from.
+    pub(crate) fn allocation_start(&self) -> u32 {
+        if self.fat_type == FatType::Fat32 {
+            self.root_dir_first_cluster
+        } else {
+            geometry::FIRST_DATA_CLUSTER
+        }
+    }
 }
 
 /// Calculates a sane default to use for the size of each File Allocation Table
 /// based on the values of the passed in preamble.
 ///
-/// Currently, this is function uses the formula `(total_sectors_32 - reserved_sectors + 2 * sectors_per_cluster)/(fats + bytes_per_cluster/4)`.
+/// Currently, this is function uses the formula `(total_sectors_32 - reserved_sectors + 2 * sectors_per_cluster)/(fats + bytes_per_cluster/4)`,
+/// rounded up so that every cluster has an entry.
+///
+/// FAT12 and FAT16 volumes also leave out the sectors of the fixed root
+/// directory region, and pack 8/12 and 8/16 entries into each byte instead of
+/// 8/32, which works out to `(total - reserved - root + 2 * sectors_per_cluster) * bits / (8 * bytes_per_cluster + fats * bits)`
+/// for entries of `bits` bits.
 ///
 /// # Explanation
 /// Each FAT32 filesystem is divided between its reserved sectors, its File Allocation Tables, and its data section. Each File Allocation Table needs
@@ -256,8 +493,11 @@ impl BiosParameterBlock {
 ///
 /// ```
 pub fn default_sectors_per_fat(bpb: &BiosParameterBlock) -> u32 {
-    let top = bpb.total_sectors_32 - u32::from(bpb.reserved_sectors)
-        + 2 * u32::from(bpb.sectors_per_cluster);
-    let bottom = u32::from(bpb.fats) + bpb.bytes_per_cluster() / 4;
-    top / bottom
+    let bits = bpb.fat_type.entry_bits() as u64;
+    let data_sectors = u64::from(bpb.total_sectors_32)
+        - u64::from(bpb.reserved_sectors)
+        - u64::from(bpb.root_dir_sectors());
+    let top = (data_sectors + 2 * u64::from(bpb.sectors_per_cluster)) * bits;
+    let bottom = 8 * u64::from(bpb.bytes_per_cluster()) + u64::from(bpb.fats) * bits;
+    top.div_ceil(bottom) as u32
 }
@@ -0,0 +1,1325 @@
+use crate::access::Authorizer;
+use crate::bpb::{default_sectors_per_fat, BiosParameterBlock, ChsGeometry};
+use crate::changeset::{ChangeSet, ChangeSetOps};
+use crate::clustermapping::{ClusterMapper, ClusterMapperOps};
+use crate::datetime::{Date, Time};
+use crate::dedup::{DedupIndex, DedupIndexOps};
+use crate::dircache::{DirCache, DirCacheOps};
+use crate::dirnames::{DirNames, DirNamesOps};
+use crate::dirversion::{DirVersionOps, DirVersions};
+use crate::faker::{
+    cluster_demand, directory_entry_count, tree_size, DirectoryPadding, FakeFat, OutOfRangeReads,
+    TreeSize,
+};
+use crate::fat::{FatEntryValue, FatType};
+use crate::geometry::{dirents_per_cluster, FIRST_DATA_CLUSTER, ROOT_REGION_CLUSTER};
+use crate::layout::{LayoutEstimate, VolumeLayout};
+use crate::pathbuffer::PathBuff;
+use crate::preload::{FileCache, FileCacheOps};
+use crate::ratelimit::{Clock, PathRateLimits, RateLimit, RateLimiter};
+use crate::sanitize::{HiddenEntries, NamePolicy, NamingOptions};
+use crate::scan::{Scan, ScanState};
+use crate::session::{SessionListener, Sessions};
+use crate::shortname::{ShortNameCharset, ShortNameStrategy};
+use crate::traits::{FileSystemLookup, FileSystemOps};
+use crate::volumeinfo::{derived_volume_id, VolumeIdentity};
+use crate::writebuffer::WriteBuffer;
+
+use core::fmt;
+
+/// The cluster size the kind of FAT is picked by when the cluster size is
+/// picked automatically, which is also the smallest cluster FAT32 volumes get.
+const DEFAULT_CLUSTER_SIZE: u64 = 4096;
+
+/// The default of `FakeFatBuilder::min_capacity`, so that even a small backing
+/// filesystem leaves hosts some room. This is also about the smallest FAT32
+/// volume with 4 KiB clusters, so smaller picked capacities get FAT12 or FAT16.
+const DEFAULT_MIN_CAPACITY: u64 = 256 * 1024 * 1024;
+
+/// The smallest fixed root directory region FAT12 and FAT16 volumes get, which
+/// is what formatting tools use for anything but floppies.
+const MIN_ROOT_ENTRIES: usize = 512;
+
+/// FAT12 and FAT16 volumes only reserve the boot sector itself.
+const FAT16_RESERVED_SECTORS: u16 = 1;
+
+/// FAT32 volumes need to reserve at least the boot sector and the FSInfo sector.
+const FAT32_MIN_RESERVED_SECTORS: u16 = 2;
+
+/// The largest cluster size hosts support.
+#[cfg(feature = "alloc")]
+const MAX_CLUSTER_SIZE: u32 = 64 * 1024;
+
+/// Without `alloc`, the change set only has room for clusters of up to a few
+/// sectors, however large the sectors are.
+#[cfg(not(feature = "alloc"))]
+const MAX_CLUSTER_SIZE: u32 = crate::changeset::MAX_CLUSTER_SIZE as u32;
+
+/// The largest capacity `Preset::CameraMinimal` uses; SD cards any larger are
+/// SDXC cards, which hosts expect to be formatted as exFAT instead.
+const SDHC_MAX_CAPACITY: u64 = 32 * 1000 * 1000 * 1000;
+
+/// The cluster size `Preset::CameraMinimal` uses, whatever the sector size.
+const CAMERA_CLUSTER_SIZE: u32 = 32 * 1024;
+
+/// A bundle of builder options suited to a class of hosts, for use with
+/// `FakeFatBuilder::preset`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Preset {
+    /// Exposes names the way Windows would have created them: illegal names
+    /// are trimmed and cleaned up, and lowercase names use the case flags
+    /// Windows NT introduced. This is the same as the defaults.
+    WindowsStrict,
+    /// Keeps names as close to the backing names as possible, which Linux and
+    /// macOS are happy to read: illegal characters and leading and trailing
+    /// spaces and dots are replaced instead of dropped.
+    LinuxLenient,
+    /// Sticks to what the simple FAT drivers in cameras and other embedded
+    /// devices expect of an SD card: 32 KiB clusters, a capacity of at most
+    /// 32 GB, no short name case flags, and no items with illegal names at all.
+    CameraMinimal,
+}
+
+impl Preset {
+    /// Every preset, in the order they are documented.
+    pub const ALL: [Preset; 3] = [
+        Preset::WindowsStrict,
+        Preset::LinuxLenient,
+        Preset::CameraMinimal,
+    ];
+
+    /// The name of the preset, like `"windows-strict"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::WindowsStrict => "windows-strict",
+            Preset::LinuxLenient => "linux-lenient",
+            Preset::CameraMinimal => "camera-minimal",
+        }
+    }
+
+    /// Finds the preset with the given `name`, as returned by `Preset::name`.
+    pub fn from_name(name: &str) -> Option<Preset> {
+        Preset::ALL
+            .iter()
+            .copied()
+            .find(|preset| preset.name() == name)
+    }
+}
+
+/// Configures the layout of a `FakeFat` before it is constructed.
+///
+/// Any option that is not set keeps the same value `FakeFat::new` uses.
+#[derive(Copy, Clone, Debug)]
+pub struct FakeFatBuilder {
+    root_dir_first_cluster: u32,
+    write_protected: bool,
+    append_only: bool,
+    erase_unit_size: u32,
+    out_of_range_reads: OutOfRangeReads,
+    dedup_files: bool,
+    relocate_changed_files: bool,
+    short_name_case_flags: bool,
+    short_name_strategy: ShortNameStrategy,
+    short_name_charset: ShortNameCharset,
+    name_policy: NamePolicy,
+    hidden_entries: HiddenEntries,
+    directory_padding: DirectoryPadding,
+    dir_slack_fill: u8,
+    bytes_per_sector: u16,
+    sectors_per_cluster: Option<u8>,
+    /// The cluster size in bytes a preset calls for, which is only turned into
+    /// sectors once the sector size is known.
+    preset_cluster_size: Option<u32>,
+    reserved_sectors: Option<u16>,
+    fats: u8,
+    volume_label: [u8; 11],
+    volume_id: Option<u32>,
+    identity_seed: Option<u64>,
+    created: Option<(Date, Time)>,
+    oem_name: [u8; 8],
+    /// The geometry to use, or `None` to fit it to the size of the device.
+    chs_geometry: Option<ChsGeometry>,
+    total_capacity: Option<u64>,
+    min_capacity: u64,
+    fat_type: Option<FatType>,
+    authorizer: Option<Authorizer>,
+    session_listener: Option<SessionListener>,
+    read_rate_limit: Option<RateLimit>,
+    path_read_rate_limits: Option<PathRateLimits>,
+    clock: Option<Clock>,
+}
+
+impl Default for FakeFatBuilder {
+    fn default() -> Self {
+        FakeFatBuilder {
+            root_dir_first_cluster: BiosParameterBlock::default().root_dir_first_cluster,
+            write_protected: false,
+            append_only: false,
+            erase_unit_size: 0,
+            out_of_range_reads: OutOfRangeReads::Zeros,
+            dedup_files: false,
+            relocate_changed_files: false,
+            short_name_case_flags: true,
+            short_name_strategy: ShortNameStrategy::default(),
+            short_name_charset: ShortNameCharset::default(),
+            name_policy: NamePolicy::default(),
+            hidden_entries: HiddenEntries::default(),
+            directory_padding: DirectoryPadding::default(),
+            dir_slack_fill: 0,
+            bytes_per_sector: BiosParameterBlock::default().bytes_per_sector,
+            sectors_per_cluster: None,
+            preset_cluster_size: None,
+            reserved_sectors: None,
+            fats: BiosParameterBlock::default().fats,
+            volume_label: BiosParameterBlock::default().volume_label,
+            volume_id: None,
+            identity_seed: None,
+            created: None,
+            oem_name: BiosParameterBlock::default().oem_name,
+            chs_geometry: Some(ChsGeometry::default()),
+            total_capacity: None,
+            min_capacity: DEFAULT_MIN_CAPACITY,
+            fat_type: None,
+            authorizer: None,
+            session_listener: None,
+            read_rate_limit: None,
+            path_read_rate_limits: None,
+            #[cfg(feature = "std")]
+            clock: Some(crate::ratelimit::std_clock),
+            #[cfg(not(feature = "std"))]
+            clock: None,
+        }
+    }
+}
+
+impl FakeFatBuilder {
+    /// Constructs a builder with every option set to its default.
+    pub fn new() -> Self {
+        FakeFatBuilder::default()
+    }
+
+    /// Sets every option `preset` covers to the value it calls for. Options can
+    /// still be changed afterwards, so for example
+    /// `builder.preset(Preset::CameraMinimal).volume_label("CAMERA")` keeps the
+    /// preset's options while also setting a label.
+    ///
+    /// The name policy, short name case flags, cluster size and capacity are
+    /// covered by every preset.
+    pub fn preset(self, preset: Preset) -> Self {
+        let defaults = FakeFatBuilder::default();
+        let builder = FakeFatBuilder {
+            name_policy: defaults.name_policy,
+            short_name_case_flags: defaults.short_name_case_flags,
+            sectors_per_cluster: defaults.sectors_per_cluster,
+            preset_cluster_size: defaults.preset_cluster_size,
+            total_capacity: defaults.total_capacity,
+            ..self
+        };
+        match preset {
+            Preset::WindowsStrict => builder,
+            Preset::LinuxLenient => builder.name_policy(NamePolicy::Replace),
+            Preset::CameraMinimal => FakeFatBuilder {
+                preset_cluster_size: Some(CAMERA_CLUSTER_SIZE),
+                ..builder
+                    .name_policy(NamePolicy::Reject)
+                    .short_name_case_flags(false)
+                    .total_capacity(SDHC_MAX_CAPACITY)
+            },
+        }
+    }
+
+    /// Sets the cluster the root directory starts at; the clusters between the
+    /// start of the data section and the root directory are left unallocated.
+    ///
+    /// Defaults to 2, the first cluster of the data section. Only used on
+    /// FAT32, since FAT12 and FAT16 keep their root directory in a fixed region
+    /// before the data section.
+    ///
+    /// # Panics
+    /// This function panics if `cluster` is not a valid data cluster.
+    pub fn root_dir_first_cluster(mut self, cluster: u32) -> Self {
+        assert!(
+            cluster >= FIRST_DATA_CLUSTER && cluster < u32::from(FatEntryValue::Bad),
+            "Invalid root directory cluster {}",
+            cluster
+        );
+        self.root_dir_first_cluster = cluster;
+        self
+    }
+
+    /// Sets whether the device is exported read-only. A write protected device
+    /// ignores every write and marks all of its files read-only.
+    ///
+    /// Defaults to `false`.
+    pub fn write_protected(mut self, protected: bool) -> Self {
+        self.write_protected = protected;
+        self
+    }
+
+    /// Sets whether the host's writes may only add to the backing filesystem,
+    /// as suits audit and data-logger devices that must be tamper-evident.
+    ///
+    /// New files and directories are still created and existing files can grow,
+    /// but `FakeFat::write_back` leaves files whose existing contents the host
+    /// overwrote or cut short untouched, and reports them as
+    /// `WriteBackError::NotAppended`. Files the host deletes are left in the
+    /// backing filesystem either way.
+    ///
+    /// Defaults to `false`.
+    pub fn append_only(mut self, enabled: bool) -> Self {
+        self.append_only = enabled;
+        self
+    }
+
+    /// Sets the erase unit size of the flash the backing filesystem is stored
+    /// on, such as the block size of a littlefs volume, in bytes.
+    ///
+    /// `FakeFat::write_back` writes file data in whole regions of this size,
+    /// aligned to the start of each file, so that every erase unit the host
+    /// changed is rewritten once instead of once per cluster. Regions are
+    /// never smaller than a cluster. `FakeFat::plan_write_back` lists the
+    /// writes ahead of time.
+    ///
+    /// Defaults to 0, which writes each changed cluster on its own.
+    ///
+    /// # Panics
+    /// This function panics if `bytes` is neither 0 nor a power of two.
+    pub fn erase_unit_size(mut self, bytes: u32) -> Self {
+        assert!(
+            bytes == 0 || bytes.is_power_of_two(),
+            "Invalid erase unit size {}",
+            bytes
+        );
+        self.erase_unit_size = bytes;
+        self
+    }
+
+    /// Sets what the device does with reads that run past its last sector.
+    ///
+    /// The sector-based API always rejects sectors past the end with
+    /// `SectorError::OutOfRange`; this only applies to the byte-based reads.
+    /// Every such read is counted in `SessionStats::out_of_range_reads`.
+    ///
+    /// Defaults to `OutOfRangeReads::Zeros`.
+    pub fn out_of_range_reads(mut self, policy: OutOfRangeReads) -> Self {
+        self.out_of_range_reads = policy;
+        self
+    }
+
+    /// Sets whether backing files with identical contents share a single
+    /// cluster chain on the device, so that their contents only take up space
+    /// once, which also shrinks the picked capacity and the File Allocation
+    /// Tables.
+    ///
+    /// Finding duplicates reads every exposed file in full when the volume is
+    /// laid out, and files are only merged if their contents match byte for
+    /// byte. Since a host writing to one of the files would change all of them,
+    /// this needs a `write_protected` device. `FakeFat::refresh` does not look
+    /// for new duplicates, nor split files that stopped being identical, so
+    /// this suits backing filesystems whose files do not change.
+    ///
+    /// Hosts read shared chains just fine, but tools that check the volume,
+    /// like `chkdsk` or `fsck.fat`, report them as cross-linked files.
+    ///
+    /// Without the `alloc` feature, no duplicates are ever found.
+    ///
+    /// Defaults to `false`.
+    pub fn dedup_files(mut self, enabled: bool) -> Self {
+        self.dedup_files = enabled;
+        self
+    }
+
+    /// Sets whether `FakeFat::refresh` gives files whose contents changed new
+    /// clusters and a newer modification timestamp, even if their size and
+    /// timestamp in the backing filesystem stayed the same.
+    ///
+    /// Hosts cache file contents by cluster and trust a file whose entry looks
+    /// the same to still hold what they cached, so without this they can keep
+    /// serving the old contents of a file rewritten in place. Finding the
+    /// changed files means hashing every file when the device is constructed
+    /// and on every refresh, and `FakeFat::file_generation` counts how many
+    /// times each file was found changed.
+    ///
+    /// Defaults to `false`.
+    pub fn relocate_changed_files(mut self, enabled: bool) -> Self {
+        self.relocate_changed_files = enabled;
+        self
+    }
+
+    /// Sets whether names that are all lowercase, or that only have a lowercase
+    /// name or extension like `readme.txt`, are stored as a single short entry
+    /// with its case flags set instead of with a chain of Long File Name entries.
+    ///
+    /// This saves a directory entry for every such name, but the case flags were
+    /// only introduced with Windows NT; hosts that do not know them show the
+    /// names in uppercase instead.
+    ///
+    /// Defaults to `true`.
+    pub fn short_name_case_flags(mut self, enabled: bool) -> Self {
+        self.short_name_case_flags = enabled;
+        self
+    }
+
+    /// Sets how short names are made up for names that are not valid short
+    /// names, for devices whose hosts expect a particular scheme.
+    ///
+    /// Defaults to `ShortNameStrategy::Numeric`.
+    pub fn short_name_strategy(mut self, strategy: ShortNameStrategy) -> Self {
+        self.short_name_strategy = strategy;
+        self
+    }
+
+    /// Sets how the characters of names that are not ASCII are stored in the
+    /// short names made up for them, for devices whose hosts ignore long names
+    /// but are set up with a known OEM code page.
+    ///
+    /// Defaults to `ShortNameCharset::Ascii`.
+    pub fn short_name_charset(mut self, charset: ShortNameCharset) -> Self {
+        self.short_name_charset = charset;
+        self
+    }
+
+    /// Sets how backing names that are not legal on FAT, like `"foo. "` or
+    /// `" bar"`, are exposed on the device. Use `FakeFat::scan_names` to find out
+    /// which names were affected.
+    ///
+    /// Defaults to `NamePolicy::Trim`, which matches what Windows does.
+    pub fn name_policy(mut self, policy: NamePolicy) -> Self {
+        self.name_policy = policy;
+        self
+    }
+
+    /// Sets how backing items whose metadata marks them as hidden or as system
+    /// items are exposed on the device.
+    ///
+    /// Defaults to `HiddenEntries::Mark`, which lists them with the hidden or
+    /// system attribute set.
+    pub fn hidden_entries(mut self, hidden: HiddenEntries) -> Self {
+        self.hidden_entries = hidden;
+        self
+    }
+
+    /// Sets how many empty entries every directory is given on top of the ones
+    /// its items take up, so that hosts can create items in it without
+    /// growing its cluster chain, which write-back has a harder time
+    /// following.
+    ///
+    /// The fixed root directory region of FAT12 and FAT16 volumes is padded
+    /// too. Directories that grow on `FakeFat::refresh` are padded again for
+    /// their new size.
+    ///
+    /// Defaults to `DirectoryPadding::None`.
+    pub fn directory_padding(mut self, padding: DirectoryPadding) -> Self {
+        self.directory_padding = padding;
+        self
+    }
+
+    /// Sets the byte that fills the slots of each directory after the empty
+    /// entry ending it, up to the end of the directory's last cluster.
+    ///
+    /// Defaults to `0x00`, and can otherwise only be `0xE5`, which marks the
+    /// slots as deleted entries. Since some hosts keep reading past the end
+    /// of a directory, any other fill would show up as garbage entries on
+    /// them, so it is rejected with `BuildError::InvalidSlackFill`.
+    pub fn directory_slack_fill(mut self, fill: u8) -> Self {
+        self.dir_slack_fill = fill;
+        self
+    }
+
+    /// Sets the size of a sector in bytes, which is the unit hosts read and
+    /// write the device in.
+    ///
+    /// Defaults to 512, which is what nearly every host expects of a removable
+    /// device. Larger sectors, like the 2048 bytes of optical media or the 4096
+    /// bytes of 4Kn drives, scale the whole layout, since the reserved region,
+    /// the FATs and the clusters are all counted in sectors.
+    ///
+    /// # Panics
+    /// This function panics if `bytes` is not a power of two between 512 and
+    /// 4096.
+    pub fn bytes_per_sector(mut self, bytes: u16) -> Self {
+        assert!(
+            bytes.is_power_of_two() && (512..=4096).contains(&bytes),
+            "Invalid bytes per sector {}",
+            bytes
+        );
+        self.bytes_per_sector = bytes;
+        self
+    }
+
+    /// Sets the number of sectors in each cluster.
+    ///
+    /// By default the cluster size is picked from the capacity the way
+    /// formatting tools do, making clusters larger where needed to keep the
+    /// capacity and the backing filesystem within the number of clusters the
+    /// kind of FAT can have. Clusters can be at most 64 KiB, or 4 KiB without
+    /// the `alloc` feature.
+    ///
+    /// # Panics
+    /// This function panics if `sectors` is not a power of two between 1 and
+    /// 128.
+    pub fn sectors_per_cluster(mut self, sectors: u8) -> Self {
+        assert!(
+            sectors.is_power_of_two() && sectors <= 128,
+            "Invalid sectors per cluster {}",
+            sectors
+        );
+        self.sectors_per_cluster = Some(sectors);
+        self
+    }
+
+    /// Sets the number of sectors before the File Allocation Tables, including
+    /// the boot sector itself.
+    ///
+    /// Defaults to 8 on FAT32, and to 1 on FAT12 and FAT16. FAT32 needs at
+    /// least 2, to also hold its FSInfo sector, and only gets backup copies of
+    /// its boot sector and FSInfo sector in sectors 6 and 7 with 8 or more, or
+    /// of just the boot sector with 7; if FAT32 ends up being used without
+    /// `fat_type` asking for it, at least 2 sectors are reserved regardless.
+    ///
+    /// # Panics
+    /// This function panics if `sectors` is 0.
+    pub fn reserved_sectors(mut self, sectors: u16) -> Self {
+        assert!(sectors > 0, "Invalid reserved sectors {}", sectors);
+        self.reserved_sectors = Some(sectors);
+        self
+    }
+
+    /// Sets the number of File Allocation Tables on the device, every one of
+    /// which holds the same entries.
+    ///
+    /// Defaults to 2, which is what formatting tools use. A single table halves
+    /// the space the tables take up, which every host supports; more than 2 is
+    /// allowed by the FAT specification but rarely seen.
+    ///
+    /// # Panics
+    /// This function panics if `count` is 0.
+    pub fn fat_count(mut self, count: u8) -> Self {
+        assert!(count > 0, "Invalid FAT count {}", count);
+        self.fats = count;
+        self
+    }
+
+    /// Sets the volume label stored in the boot sector. The label is stored in
+    /// uppercase and padded with spaces, and defaults to `NO NAME`.
+    ///
+    /// Any other label also leads the root directory as a volume label entry,
+    /// which is where most hosts read the name of the drive from.
+    ///
+    /// # Panics
+    /// This function panics if `label` is longer than 11 characters or is not
+    /// ASCII.
+    pub fn volume_label(mut self, label: &str) -> Self {
+        assert!(
+            label.len() <= 11 && label.is_ascii(),
+            "Invalid volume label {:?}",
+            label
+        );
+        self.volume_label = [b' '; 11];
+        for (dst, src) in self.volume_label.iter_mut().zip(label.bytes()) {
+            *dst = src.to_ascii_uppercase();
+        }
+        self
+    }
+
+    /// Sets the volume serial number stored in the boot sector, which hosts
+    /// use to tell volumes apart, for example to notice that a removable
+    /// device was swapped for another one with the same label.
+    ///
+    /// Defaults to one derived from `FakeFatBuilder::identity_seed`, or else
+    /// from the creation time set with `FakeFatBuilder::created`, the way DOS
+    /// derives it when formatting, or to 0 without either; give each device
+    /// exposed to the same host its own.
+    pub fn volume_id(mut self, id: u32) -> Self {
+        self.volume_id = Some(id);
+        self
+    }
+
+    /// Sets the seed the identifiers hosts remember the device by are derived
+    /// from, which `FakeFat::identity` reports, so that a device built with the
+    /// same seed every time keeps the drive letter or mount point it was given.
+    ///
+    /// The volume serial number, and with it the disk signature of an
+    /// `MbrWrapped` device, is derived from it unless `FakeFatBuilder::volume_id`
+    /// is also set.
+    ///
+    /// Defaults to no seed.
+    pub fn identity_seed(mut self, seed: u64) -> Self {
+        self.identity_seed = Some(seed);
+        self
+    }
+
+    /// Sets when the volume was created, which `FakeFat::volume_info` reports
+    /// so that devices in the field can be told apart by when they were
+    /// provisioned.
+    ///
+    /// Unless `FakeFatBuilder::volume_id` is also set, the volume serial number
+    /// is derived from it.
+    pub fn created(mut self, date: Date, time: Time) -> Self {
+        self.created = Some((date, time));
+        self
+    }
+
+    /// Sets the name of the system that formatted the volume, which is stored in
+    /// the boot sector padded with spaces and defaults to `MSWIN4.1`.
+    ///
+    /// Hosts do not rely on it, so it is best left alone unless a host or
+    /// validator expects a specific name.
+    ///
+    /// # Panics
+    /// This function panics if `name` is longer than 8 characters or is not
+    /// ASCII.
+    pub fn oem_name(mut self, name: &str) -> Self {
+        assert!(
+            name.len() <= 8 && name.is_ascii(),
+            "Invalid OEM name {:?}",
+            name
+        );
+        self.oem_name = [b' '; 8];
+        for (dst, src) in self.oem_name.iter_mut().zip(name.bytes()) {
+            *dst = src;
+        }
+        self
+    }
+
+    /// Sets the cylinder, head and sector geometry stored in the boot sector,
+    /// which `MbrWrapped` also writes its partition table with.
+    ///
+    /// Defaults to 64 heads of 32 sectors per track, like `mkfs.fat` uses for
+    /// devices it knows nothing about.
+    ///
+    /// # Panics
+    /// This function panics if `geometry` is not valid.
+    pub fn chs_geometry(mut self, geometry: ChsGeometry) -> Self {
+        assert!(geometry.is_valid(), "Invalid CHS geometry {:?}", geometry);
+        self.chs_geometry = Some(geometry);
+        self
+    }
+
+    /// Fits the cylinder, head and sector geometry to the size of the device,
+    /// as given by `ChsGeometry::for_sectors`, for hosts that expect the
+    /// geometry a BIOS would report for the device.
+    pub fn chs_geometry_for_size(mut self) -> Self {
+        self.chs_geometry = None;
+        self
+    }
+
+    /// Sets the size of the device in bytes, rounded down to a whole number of
+    /// clusters.
+    ///
+    /// The device is still made large enough to hold the backing filesystem if
+    /// it does not fit, and large enough to have the number of clusters hosts
+    /// require of its kind of FAT, such as the 65525 clusters of a FAT32 volume.
+    ///
+    /// Every cluster the backing filesystem does not take up is free space for
+    /// hosts, and FAT32 volumes report how much of it is left in their FSInfo
+    /// sector.
+    ///
+    /// Defaults to twice the space the backing filesystem takes up, but at
+    /// least `min_capacity`, so hosts have room to write without the File
+    /// Allocation Tables of a small backing filesystem growing needlessly large.
+    pub fn total_capacity(mut self, bytes: u64) -> Self {
+        self.total_capacity = Some(bytes);
+        self
+    }
+
+    /// Sets the smallest size in bytes the device is given when its capacity is
+    /// picked from the size of the backing filesystem, rather than set with
+    /// `total_capacity`.
+    ///
+    /// Defaults to 256 MiB. Lower minimums let small backing filesystems get
+    /// small FAT12 or FAT16 devices unless `fat_type` is set; with a minimum of
+    /// 0, the device is twice the size of the backing filesystem, down to the
+    /// smallest volume its kind of FAT allows.
+    pub fn min_capacity(mut self, bytes: u64) -> Self {
+        self.min_capacity = bytes;
+        self
+    }
+
+    /// Sets the `Authorizer` deciding which items hosts can see, read and
+    /// write, for exposing different views of the same backing filesystem or
+    /// enforcing per-file policies.
+    ///
+    /// By default, every item is allowed.
+    pub fn authorizer(mut self, authorizer: Authorizer) -> Self {
+        self.authorizer = Some(authorizer);
+        self
+    }
+
+    /// Sets the `SessionListener` told whenever a host session starts or ends,
+    /// as marked by `FakeFat::session_start` and `FakeFat::session_end`, and
+    /// whenever the host ejects the medium with `FakeFat::eject`.
+    ///
+    /// By default, sessions are not reported anywhere.
+    pub fn session_listener(mut self, listener: SessionListener) -> Self {
+        self.session_listener = Some(listener);
+        self
+    }
+
+    /// Caps how fast hosts can read the contents of files from the backing
+    /// filesystem, across all files together.
+    ///
+    /// By default, reads are not limited.
+    pub fn read_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.read_rate_limit = Some(limit);
+        self
+    }
+
+    /// Sets the `PathRateLimits` capping how fast hosts can read individual
+    /// files, on top of the limit set with `read_rate_limit`.
+    ///
+    /// By default, files are only held to the limit of the whole device.
+    pub fn path_read_rate_limits(mut self, limits: PathRateLimits) -> Self {
+        self.path_read_rate_limits = Some(limits);
+        self
+    }
+
+    /// Sets the `Clock` the rate limits refill by.
+    ///
+    /// Defaults to `std_clock` with the `std` feature; without it, there is no
+    /// clock unless one is set here, and rate limits cannot be used.
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Sets the kind of FAT the device uses.
+    ///
+    /// FAT12 and FAT16 suit small volumes and older hosts, but can only have up
+    /// to 4084 and 65524 clusters respectively, so a larger `total_capacity` is
+    /// cut down to fit. If the backing filesystem does not fit at all, the
+    /// next larger kind that it does fit in is used instead.
+    ///
+    /// By default, FAT32 is used unless `total_capacity` is set, in which case
+    /// the smallest kind of FAT that can have that capacity is used.
+    pub fn fat_type(mut self, fat_type: FatType) -> Self {
+        self.fat_type = Some(fat_type);
+        self
+    }
+
+    /// Constructs the Fake FAT32 device wrapping the given filesystem.
+    /// `path_prefix` represents where in the real filesystem should map to the
+    /// FAT32 device's root directory; for a direct one-to-one mapping, use `"/"`.
+    ///
+    /// Any UTF-8 path type works as the prefix, including `camino::Utf8Path`.
+    /// On Windows, backslashes in the prefix are also separators, so a prefix
+    /// like `C:\data` can be passed as is.
+    ///
+    /// This is the same as passing the `volume_layout` of `fs` to
+    /// `build_with_layout`.
+    ///
+    /// # Panics
+    /// This function panics if the options do not go together, as reported by
+    /// `validate`.
+    pub fn build<T: FileSystemOps>(self, mut fs: T, path_prefix: impl AsRef<str>) -> FakeFat<T> {
+        let layout = self.volume_layout(&mut fs, path_prefix);
+        self.build_with_layout(layout, fs)
+    }
+
+    /// Lays out the volume for the tree below `path_prefix` in `fs` as it is
+    /// right now, without constructing a device to serve it.
+    ///
+    /// # Panics
+    /// This function panics if the options do not go together, as reported by
+    /// `validate`.
+    pub fn volume_layout<T: FileSystemOps>(
+        &self,
+        fs: &mut T,
+        path_prefix: impl AsRef<str>,
+    ) -> VolumeLayout {
+        if let Err(e) = self.validate() {
+            panic!("Invalid FakeFatBuilder options: {}", e);
+        }
+        Scan::new(*self, PathBuff::from_prefix(path_prefix.as_ref())).finish(fs)
+    }
+
+    /// Starts laying out the volume for the tree below `path_prefix` in `fs`,
+    /// without doing any of the work yet, so that it can be done a few
+    /// directories at a time with `ScanState::step` in between other tasks,
+    /// like servicing a USB bus. The device is then constructed with
+    /// `ScanState::finish`.
+    ///
+    /// Running the scan to completion at once gives the same device as `build`.
+    ///
+    /// # Panics
+    /// This function panics if the options do not go together, as reported by
+    /// `validate`.
+    pub fn scan<T: FileSystemOps>(self, fs: T, path_prefix: impl AsRef<str>) -> ScanState<T> {
+        if let Err(e) = self.validate() {
+            panic!("Invalid FakeFatBuilder options: {}", e);
+        }
+        ScanState::new(self, fs, PathBuff::from_prefix(path_prefix.as_ref()))
+    }
+
+    /// Works out the geometry `volume_layout` would give the tree below
+    /// `path_prefix` in `fs` from the metadata of its items alone, without
+    /// opening any files or placing any clusters, for deciding on options or
+    /// rejecting trees that are too large before paying for a full layout.
+    ///
+    /// The estimate matches the layout, except that:
+    ///
+    /// *  Files which `dedup_files` would let share a chain are counted as if
+    ///    they had their own.
+    ///
+    /// *  Volumes that have to grow past the requested capacity to fit the tree
+    ///    allow for the largest gaps the layout could leave between clusters.
+    ///
+    /// So the estimate can only overestimate how much room the tree needs.
+    ///
+    /// # Panics
+    /// This function panics if the options do not go together, as reported by
+    /// `validate`.
+    pub fn estimate_layout<T: FileSystemOps>(
+        &self,
+        fs: &mut T,
+        path_prefix: impl AsRef<str>,
+    ) -> LayoutEstimate {
+        if let Err(e) = self.validate() {
+            panic!("Invalid FakeFatBuilder options: {}", e);
+        }
+        let path_prefix = PathBuff::from_prefix(path_prefix.as_ref());
+        let naming = self.naming_options();
+        let resolved = self.resolve_geometry(fs, &path_prefix, naming, &DedupIndex::new());
+        let mut fat_type = resolved
+            .fat_type
+            .unwrap_or_else(|| resolved.default_fat_type());
+        loop {
+            let mut bpb = resolved.layout(fat_type);
+            let label_entries = usize::from(bpb.has_volume_label());
+            if fat_type != FatType::Fat32 {
+                let root_entries =
+                    root_region_entries(&bpb, fs, &path_prefix, naming, label_entries);
+                if root_entries > usize::from(u16::MAX) {
+                    fat_type = FatType::Fat32;
+                    continue;
+                }
+                bpb.root_entries = root_entries as u16;
+            }
+            let demand = cluster_demand(
+                &path_prefix,
+                fs,
+                naming,
+                u64::from(bpb.bytes_per_cluster()),
+                label_entries,
+                fat_type == FatType::Fat32,
+            );
+            let needed_clusters = demand.dirs + demand.files;
+            if needed_clusters > u64::from(fat_type.max_clusters()) {
+                if let Some(larger) = fat_type.larger() {
+                    fat_type = larger;
+                    continue;
+                }
+            }
+            let requested_clusters = u64::from(resolved.requested_clusters(&bpb));
+            // Only a volume grown to fit the tree depends on where the layout
+            // places its clusters, which the estimate can only bound.
+            let needed_clusters = if needed_clusters > requested_clusters {
+                demand.span()
+            } else {
+                needed_clusters
+            };
+            let clusters = requested_clusters
+                .max(needed_clusters)
+                .max(u64::from(fat_type.min_clusters()))
+                .min(u64::from(fat_type.max_clusters()));
+            set_cluster_count(&mut bpb, clusters as u32);
+            return LayoutEstimate {
+                fat_type,
+                bytes_per_sector: bpb.bytes_per_sector,
+                sectors_per_cluster: bpb.sectors_per_cluster,
+                total_sectors: bpb.total_sectors_32,
+                sectors_per_fat: bpb.sectors_per_fat_32,
+                root_entries: bpb.root_entries,
+                cluster_count: bpb.cluster_count(),
+                directory_clusters: demand.dirs.min(u64::from(u32::MAX)) as u32,
+                file_clusters: demand.files.min(u64::from(u32::MAX)) as u32,
+            };
+        }
+    }
+
+    /// Constructs a device serving `layout` out of `fs`, which should hold the
+    /// same tree the layout was made from, such as a copy of it. Anything that
+    /// changed in the tree since can be picked up with `FakeFat::refresh`.
+    ///
+    /// Only the options that are not part of the layout, such as
+    /// `write_protected`, `append_only` and `session_listener`, are taken from
+    /// the builder.
+    ///
+    /// # Panics
+    /// This function panics if files share cluster chains in `layout` but the
+    /// device is not write protected, or if reads are rate limited without a
+    /// `Clock`.
+    pub fn build_with_layout<T: FileSystemOps>(self, layout: VolumeLayout, fs: T) -> FakeFat<T> {
+        assert!(
+            layout.shared_files == 0 || self.write_protected,
+            "Layouts with shared cluster chains need a write protected device"
+        );
+        assert!(
+            self.clock.is_some()
+                || (self.read_rate_limit.is_none() && self.path_read_rate_limits.is_none()),
+            "Rate limits need a clock to refill by"
+        );
+        let cluster_size = layout.bpb.bytes_per_cluster();
+        let mut retval = FakeFat {
+            fsinfo: layout.fsinfo(),
+            layout,
+            fs,
+            changes: ChangeSet::new(cluster_size),
+            host_detector: None,
+            read_audit: None,
+            rate_limiter: self.rate_limiter(),
+            write_buffer: WriteBuffer::new(),
+            dir_cache: DirCache::new(),
+            file_cache: FileCache::new(),
+            dir_versions: DirVersions::new(),
+            read_idx: 0,
+            write_protected: self.write_protected,
+            append_only: self.append_only,
+            erase_unit_size: self.erase_unit_size,
+            out_of_range_reads: self.out_of_range_reads,
+            backend_error: None,
+            sessions: Sessions::new(self.session_listener, self.clock),
+            created: self.created,
+            identity_seed: self.identity_seed,
+            dir_slack_fill: self.dir_slack_fill,
+            relocate_changed_files: self.relocate_changed_files,
+            ejected: false,
+        };
+        retval.update_dir_versions();
+        retval
+    }
+
+    /// The kind of FAT to first try placing the tree on, once the geometry is
+    /// resolved.
+    pub(crate) fn initial_fat_type(&self) -> FatType {
+        self.fat_type.unwrap_or_else(|| self.default_fat_type())
+    }
+
+    /// An empty volume using `fat_type` to place the tree below `prefix` in
+    /// `fs` on, with the clusters of the fixed root directory region of FAT12
+    /// and FAT16 already placed, or a FAT32 one if the root directory does not
+    /// fit in that region.
+    pub(crate) fn empty_volume<T: FileSystemOps>(
+        &self,
+        fs: &mut T,
+        prefix: &PathBuff,
+        naming: NamingOptions,
+        fat_type: FatType,
+    ) -> (BiosParameterBlock, ClusterMapper) {
+        let mut bpb = self.layout(fat_type);
+        let mut mapper = ClusterMapper::new();
+        if fat_type == FatType::Fat32 {
+            return (bpb, mapper);
+        }
+        let label_entries = usize::from(bpb.has_volume_label());
+        let entries_per_cluster = dirents_per_cluster(&bpb);
+        let root_entries = root_region_entries(&bpb, fs, prefix, naming, label_entries);
+        if root_entries > usize::from(u16::MAX) {
+            return self.empty_volume(fs, prefix, naming, FatType::Fat32);
+        }
+        bpb.root_entries = root_entries as u16;
+        for piece in 0..root_entries / entries_per_cluster {
+            mapper.add_cluster_to_path(prefix.to_str(), ROOT_REGION_CLUSTER + piece as u32);
+        }
+        (bpb, mapper)
+    }
+
+    /// Sizes the volume `bpb` to hold the clusters placed on it, the last of
+    /// which is `max_cluster`, or returns the next larger kind of FAT to place
+    /// them on again if they do not fit.
+    pub(crate) fn fit_volume(
+        &self,
+        mut bpb: BiosParameterBlock,
+        max_cluster: u32,
+    ) -> Result<BiosParameterBlock, FatType> {
+        let fat_type = bpb.fat_type;
+        let needed_clusters = max_cluster + 1 - FIRST_DATA_CLUSTER;
+        if needed_clusters > fat_type.max_clusters() {
+            if let Some(larger) = fat_type.larger() {
+                return Err(larger);
+            }
+        }
+        let clusters = self
+            .requested_clusters(&bpb)
+            .max(needed_clusters)
+            .max(fat_type.min_clusters())
+            .min(fat_type.max_clusters());
+        set_cluster_count(&mut bpb, clusters);
+        if self.chs_geometry.is_none() {
+            let geometry = ChsGeometry::for_sectors(bpb.total_sectors_32);
+            bpb.heads = geometry.heads;
+            bpb.sectors_per_track = geometry.sectors_per_track;
+        }
+        Ok(bpb)
+    }
+
+    /// The options controlling how backing names are exposed on the device.
+    pub(crate) fn naming_options(&self) -> NamingOptions {
+        NamingOptions {
+            policy: self.name_policy,
+            case_flags: self.short_name_case_flags,
+            short_names: self.short_name_strategy,
+            charset: self.short_name_charset,
+            hidden: self.hidden_entries,
+            authorizer: self.authorizer,
+            padding: self.directory_padding,
+        }
+    }
+
+    /// Fills in the capacity and the cluster size if they are to be picked
+    /// automatically, based on how much of the backing filesystem below
+    /// `prefix` is exposed, not counting the files in `dedup` that share the
+    /// clusters of another.
+    fn resolve_geometry<T: FileSystemOps>(
+        self,
+        fs: &mut T,
+        prefix: &PathBuff,
+        naming: NamingOptions,
+        dedup: &DedupIndex,
+    ) -> Self {
+        if !self.needs_tree_size() {
+            return self;
+        }
+        self.sized_for(tree_size(prefix, fs, naming), dedup)
+    }
+
+    /// Whether the capacity or the cluster size are to be picked based on the
+    /// size of the tree.
+    pub(crate) fn needs_tree_size(&self) -> bool {
+        self.requested_sectors_per_cluster().is_none() || self.total_capacity.is_none()
+    }
+
+    /// Whether files with identical contents are to share a chain.
+    pub(crate) fn finds_duplicates(&self) -> bool {
+        self.dedup_files
+    }
+
+    /// Fills in the capacity and the cluster size if they are to be picked
+    /// automatically, for a tree of size `tree` in which the files in `dedup`
+    /// share the clusters of another.
+    pub(crate) fn sized_for(self, mut tree: TreeSize, dedup: &DedupIndex) -> Self {
+        if !self.needs_tree_size() {
+            return self;
+        }
+        tree.bytes -= dedup.duplicate_bytes();
+        tree.files -= u64::from(dedup.duplicate_count());
+        let capacity = self
+            .total_capacity
+            .unwrap_or_else(|| (2 * tree.footprint(DEFAULT_CLUSTER_SIZE)).max(self.min_capacity));
+        // FAT32 stays the default when there was no capacity to go by, unless
+        // a lower `min_capacity` made the picked one too small for it.
+        let fat_type = match (self.fat_type, self.total_capacity) {
+            (Some(fat_type), _) => fat_type,
+            (None, None) if capacity >= DEFAULT_MIN_CAPACITY => FatType::Fat32,
+            (None, None) => FakeFatBuilder {
+                total_capacity: Some(capacity),
+                ..self
+            }
+            .default_fat_type(),
+            (None, Some(_)) => self.default_fat_type(),
+        };
+        let sectors_per_cluster = self
+            .requested_sectors_per_cluster()
+            .unwrap_or_else(|| self.auto_sectors_per_cluster(fat_type, capacity, tree));
+        FakeFatBuilder {
+            sectors_per_cluster: Some(sectors_per_cluster),
+            total_capacity: Some(capacity),
+            fat_type: Some(fat_type),
+            ..self
+        }
+    }
+
+    /// The smallest number of sectors per cluster that keeps both `capacity`
+    /// and `tree` within the number of clusters `fat_type` can have, but on
+    /// FAT32 no fewer than formatting tools would use for `capacity`.
+    fn auto_sectors_per_cluster(&self, fat_type: FatType, capacity: u64, tree: TreeSize) -> u8 {
+        let sector_size = u64::from(self.bytes_per_sector);
+        let max_cluster_size = u64::from(MAX_CLUSTER_SIZE);
+        let max_clusters = u64::from(fat_type.max_clusters());
+        let smallest = match fat_type {
+            FatType::Fat32 => fat32_cluster_size(capacity),
+            FatType::Fat12 | FatType::Fat16 => sector_size,
+        };
+        let mut cluster_size = smallest.min(max_cluster_size).max(sector_size);
+        while cluster_size < max_cluster_size
+            && (capacity / cluster_size > max_clusters
+                || tree.footprint(cluster_size) / cluster_size > max_clusters)
+        {
+            cluster_size *= 2;
+        }
+        (cluster_size / sector_size) as u8
+    }
+
+    /// The number of sectors per cluster, assuming the default cluster size if
+    /// it is to be picked automatically.
+    fn cluster_sectors(&self) -> u8 {
+        self.requested_sectors_per_cluster()
+            .unwrap_or((DEFAULT_CLUSTER_SIZE / u64::from(self.bytes_per_sector)).max(1) as u8)
+    }
+
+    /// The number of sectors per cluster that was asked for, either directly
+    /// or as a cluster size by a preset, or `None` if it is to be picked
+    /// automatically.
+    fn requested_sectors_per_cluster(&self) -> Option<u8> {
+        self.sectors_per_cluster.or_else(|| {
+            self.preset_cluster_size
+                .map(|bytes| (bytes / u32::from(self.bytes_per_sector)).max(1) as u8)
+        })
+    }
+
+    /// The kind of FAT used when `fat_type` is not set.
+    fn default_fat_type(&self) -> FatType {
+        if self.total_capacity.is_none() {
+            return FatType::Fat32;
+        }
+        [FatType::Fat12, FatType::Fat16]
+            .iter()
+            .copied()
+            .find(|&fat_type| {
+                let bpb = self.layout(fat_type);
+                self.requested_clusters(&bpb) <= fat_type.max_clusters()
+            })
+            .unwrap_or(FatType::Fat32)
+    }
+
+    /// Checks that the options set so far go together, which `build` panics
+    /// on otherwise.
+    pub fn validate(&self) -> Result<(), BuildError> {
+        let cluster_size = u32::from(self.bytes_per_sector) * u32::from(self.cluster_sectors());
+        if cluster_size > MAX_CLUSTER_SIZE {
+            return Err(BuildError::ClusterTooLarge {
+                bytes: cluster_size,
+            });
+        }
+        if self.dedup_files && !self.write_protected {
+            return Err(BuildError::DedupNotWriteProtected);
+        }
+        let limits_set = self.read_rate_limit.is_some() || self.path_read_rate_limits.is_some();
+        if limits_set && self.clock.is_none() {
+            return Err(BuildError::NoClock);
+        }
+        if let Some(limit) = self.read_rate_limit {
+            if limit.burst < u32::from(self.bytes_per_sector) {
+                return Err(BuildError::BurstTooSmall {
+                    burst: limit.burst,
+                    sector_size: self.bytes_per_sector,
+                });
+            }
+        }
+        if self.dir_slack_fill != 0x00 && self.dir_slack_fill != 0xE5 {
+            return Err(BuildError::InvalidSlackFill {
+                fill: self.dir_slack_fill,
+            });
+        }
+        if let (Some(FatType::Fat32), Some(reserved)) = (self.fat_type, self.reserved_sectors) {
+            if reserved < FAT32_MIN_RESERVED_SECTORS {
+                return Err(BuildError::TooFewReservedSectors {
+                    reserved,
+                    required: FAT32_MIN_RESERVED_SECTORS,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The rate limit bookkeeping of a device built with these options, or
+    /// `None` if reads are not limited.
+    fn rate_limiter(&self) -> Option<RateLimiter> {
+        if self.read_rate_limit.is_none() && self.path_read_rate_limits.is_none() {
+            return None;
+        }
+        Some(RateLimiter::new(
+            self.clock?,
+            self.read_rate_limit,
+            self.path_read_rate_limits,
+        ))
+    }
+
+    /// The preamble of an empty device using `fat_type`, without its size.
+    fn layout(&self, fat_type: FatType) -> BiosParameterBlock {
+        let mut bpb = BiosParameterBlock {
+            bytes_per_sector: self.bytes_per_sector,
+            sectors_per_cluster: self.cluster_sectors(),
+            fats: self.fats,
+            root_dir_first_cluster: self.root_dir_first_cluster,
+            volume_label: self.volume_label,
+            oem_name: self.oem_name,
+            fat_type,
+            ..BiosParameterBlock::default()
+        };
+        let seeded_id = self
+            .identity_seed
+            .map(|seed| VolumeIdentity::from_seed(seed).volume_id);
+        let derived_id = self
+            .created
+            .map(|(date, time)| derived_volume_id(date, time));
+        bpb.volume_id = self
+            .volume_id
+            .or(seeded_id)
+            .or(derived_id)
+            .unwrap_or(bpb.volume_id);
+        if let Some(geometry) = self.chs_geometry {
+            bpb.heads = geometry.heads;
+            bpb.sectors_per_track = geometry.sectors_per_track;
+        }
+        if fat_type == FatType::Fat32 {
+            if let Some(reserved) = self.reserved_sectors {
+                bpb.reserved_sectors = reserved.max(FAT32_MIN_RESERVED_SECTORS);
+            }
+            if bpb.backup_boot_sector >= bpb.reserved_sectors {
+                bpb.backup_boot_sector = 0;
+            }
+        } else {
+            let entries_per_cluster = dirents_per_cluster(&bpb);
+            bpb.reserved_sectors = self.reserved_sectors.unwrap_or(FAT16_RESERVED_SECTORS);
+            bpb.root_entries = MIN_ROOT_ENTRIES.next_multiple_of(entries_per_cluster) as u16;
+        }
+        bpb
+    }
+
+    /// The number of data clusters that fit in the requested capacity when
+    /// laid out like `bpb`.
+    fn requested_clusters(&self, bpb: &BiosParameterBlock) -> u32 {
+        let sectors_per_cluster = u64::from(bpb.sectors_per_cluster);
+        // `resolve_geometry` always sets a capacity before this is used.
+        let requested_sectors = self.total_capacity.unwrap_or(0) / u64::from(bpb.bytes_per_sector);
+        let mut sized = bpb.clone();
+        sized.total_sectors_32 = requested_sectors.min(u64::from(u32::MAX)) as u32;
+        let overhead = u64::from(sized.reserved_sectors) + u64::from(sized.root_dir_sectors());
+        if u64::from(sized.total_sectors_32) <= overhead {
+            return 0;
+        }
+        let fat_sectors = u64::from(sized.fats) * u64::from(default_sectors_per_fat(&sized));
+        let data_sectors = u64::from(sized.total_sectors_32).saturating_sub(overhead + fat_sectors);
+        (data_sectors / sectors_per_cluster).min(u64::from(u32::MAX)) as u32
+    }
+}
+
+/// The reasons a combination of `FakeFatBuilder` options cannot make a device.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BuildError {
+    /// Clusters would be larger than the 64 KiB hosts support, or without the
+    /// `alloc` feature, than the 4 KiB a change to a cluster can be kept in.
+    ClusterTooLarge {
+        /// The size of a cluster with the requested sector size and sectors
+        /// per cluster.
+        bytes: u32,
+    },
+    /// FAT32 was asked for with too few reserved sectors to hold its FSInfo
+    /// sector.
+    TooFewReservedSectors {
+        /// The requested number of reserved sectors.
+        reserved: u16,
+        /// The least number of reserved sectors FAT32 needs.
+        required: u16,
+    },
+    /// Files were asked to share cluster chains on a device that is not write
+    /// protected.
+    DedupNotWriteProtected,
+    /// Reads were asked to be rate limited without a `Clock` to refill the
+    /// limits by.
+    NoClock,
+    /// The burst of the rate limit of the whole device is smaller than a
+    /// sector, so no sector could ever be read.
+    BurstTooSmall {
+        /// The requested burst.
+        burst: u32,
+        /// The size of a sector.
+        sector_size: u16,
+    },
+    /// The directory slack fill is neither `0x00` nor `0xE5`, so the slots
+    /// after the end of a directory would not read as free.
+    InvalidSlackFill {
+        /// The requested fill.
+        fill: u8,
+    },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::ClusterTooLarge { bytes } => {
+                write!(
+                    f,
+                    "{} byte clusters are larger than the {} bytes supported",
+                    bytes, MAX_CLUSTER_SIZE
+                )
+            }
+            BuildError::TooFewReservedSectors { reserved, required } => write!(
+                f,
+                "FAT32 needs at least {} reserved sectors, not {}",
+                required, reserved
+            ),
+            BuildError::DedupNotWriteProtected => write!(
+                f,
+                "files can only share clusters on a write protected device"
+            ),
+            BuildError::NoClock => write!(f, "rate limits need a clock to refill by"),
+            BuildError::BurstTooSmall { burst, sector_size } => write!(
+                f,
+                "a burst of {} bytes cannot fit a {} byte sector",
+                burst, sector_size
+            ),
+            BuildError::InvalidSlackFill { fill } => write!(
+                f,
+                "directory slack fill {:#04x} would not read as free slots",
+                fill
+            ),
+        }
+    }
+}
+
+/// The cluster size formatting tools give a FAT32 volume of `capacity` bytes.
+fn fat32_cluster_size(capacity: u64) -> u64 {
+    const GIB: u64 = 1 << 30;
+    match capacity {
+        c if c <= 8 * GIB => 4 * 1024,
+        c if c <= 16 * GIB => 8 * 1024,
+        c if c <= 32 * GIB => 16 * 1024,
+        _ => 32 * 1024,
+    }
+}
+
+/// The number of entries the fixed root directory region of a FAT12 or FAT16
+/// volume laid out with `bpb` needs for the root directory `prefix` in `fs`,
+/// on top of `label_entries`, rounded up to whole clusters.
+fn root_region_entries<T: FileSystemOps>(
+    bpb: &BiosParameterBlock,
+    fs: &mut T,
+    prefix: &PathBuff,
+    naming: NamingOptions,
+    label_entries: usize,
+) -> usize {
+    let entries_per_cluster = dirents_per_cluster(bpb);
+    fs.find_dir(prefix.to_str())
+        .map_or(0, |dir| {
+            let names = DirNames::new(naming, &dir, prefix.to_str());
+            directory_entry_count(&dir, prefix.to_str(), &names, naming)
+        })
+        .saturating_add(label_entries)
+        .max(MIN_ROOT_ENTRIES)
+        .next_multiple_of(entries_per_cluster)
+}
+
+/// Sizes `bpb` to have exactly `clusters` data clusters, or as many as fit in
+/// the largest volume the preamble can describe.
+fn set_cluster_count(bpb: &mut BiosParameterBlock, clusters: u32) {
+    let entry_bits = bpb.fat_type.entry_bits() as u64;
+    let sectors_per_cluster = u64::from(bpb.sectors_per_cluster);
+    let overhead = u64::from(bpb.reserved_sectors) + u64::from(bpb.root_dir_sectors());
+    let mut clusters = u64::from(clusters);
+    loop {
+        let fat_bits = (clusters + u64::from(FIRST_DATA_CLUSTER)) * entry_bits;
+        let fat_sectors = fat_bits.div_ceil(8 * u64::from(bpb.bytes_per_sector));
+        let total_sectors =
+            overhead + u64::from(bpb.fats) * fat_sectors + clusters * sectors_per_cluster;
+        if total_sectors <= u64::from(u32::MAX) {
+            bpb.total_sectors_32 = total_sectors as u32;
+            bpb.sectors_per_fat_32 = fat_sectors as u32;
+            return;
+        }
+        clusters -= (total_sectors - u64::from(u32::MAX)).div_ceil(sectors_per_cluster);
+    }
+}
@@ -20,12 +20,19 @@ mod alloc_changeset {
     #[cfg(not(feature = "std"))]
     use alloc::collections::BTreeMap;
     #[cfg(not(feature = "std"))]
+    use alloc::vec;
+    #[cfg(not(feature = "std"))]
+    use alloc::vec::Vec;
+    #[cfg(not(feature = "std"))]
     type Map<K, V> = BTreeMap<K, V>;
 
     #[derive(Clone)]
     pub struct AllocChangeBuff {
         data: Vec<u8>,
         entry: FatEntryValue,
+        dirty: bool,
+        first_epoch: u32,
+        last_epoch: u32,
     }
 
     impl ChangeSetEntry for AllocChangeBuff {
@@ -35,17 +42,28 @@ mod alloc_changeset {
         fn entry(&self) -> FatEntryValue {
             self.entry
         }
+        fn epochs(&self) -> (u32, u32) {
+            (self.first_epoch, self.last_epoch)
+        }
     }
 
     pub struct AllocChangeSet {
         entries: Map<u32, AllocChangeBuff>,
         cluster_size: usize,
+        epoch: u32,
     }
 
     impl AllocChangeSet {
         pub fn entries<'a>(&'a self) -> impl Iterator<Item = (u32, AllocChangeBuff)> + 'a {
             self.entries.iter().map(|(&k, v)| (k, v.clone()))
         }
+
+        pub fn changed_between<'a>(&'a self, from: u32, to: u32) -> impl Iterator<Item = u32> + 'a {
+            self.entries
+                .iter()
+                .filter(move |(_, ent)| ent.changed_between(from, to))
+                .map(|(&k, _)| k)
+        }
     }
 
     impl ChangeSetOps for AllocChangeSet {
@@ -53,16 +71,29 @@ mod alloc_changeset {
             AllocChangeSet {
                 entries: Map::new(),
                 cluster_size: cluster_size as usize,
+                epoch: 0,
             }
         }
 
+        fn epoch(&self) -> u32 {
+            self.epoch
+        }
+
+        fn advance_epoch(&mut self) -> u32 {
+            self.epoch += 1;
+            self.epoch
+        }
+
         fn cluster_entry(&self, cluster: u32) -> Option<FatEntryValue> {
             self.entries.get(&cluster).map(|ent| ent.entry)
         }
 
         fn set_cluster_entry(&mut self, cluster: u32, new_entry: FatEntryValue) {
-            let itm_ref = self.entries.get_mut(&cluster).unwrap();
-            (*itm_ref).entry = new_entry;
+            if let Some(itm_ref) = self.entries.get_mut(&cluster) {
+                itm_ref.entry = new_entry;
+                itm_ref.dirty = true;
+                itm_ref.last_epoch = self.epoch;
+            }
         }
 
         fn cluster_data(&self, cluster: u32) -> Option<&[u8]> {
@@ -70,14 +101,39 @@ mod alloc_changeset {
         }
 
         fn cluster_mut(&mut self, cluster: u32) -> Option<&mut [u8]> {
-            self.entries.get_mut(&cluster).map(|ent| ent.data.as_mut())
+            let epoch = self.epoch;
+            self.entries.get_mut(&cluster).map(|ent| {
+                ent.dirty = true;
+                ent.last_epoch = epoch;
+                ent.data.as_mut()
+            })
         }
 
-        fn insert_cluster(&mut self, cluster: u32, entry: FatEntryValue) -> &mut [u8] {
+        fn is_dirty(&self, cluster: u32) -> bool {
+            self.entries.get(&cluster).is_some_and(|ent| ent.dirty)
+        }
+
+        fn any_dirty(&self) -> bool {
+            self.entries.values().any(|ent| ent.dirty)
+        }
+
+        fn mark_clean(&mut self) {
+            for ent in self.entries.values_mut() {
+                ent.dirty = false;
+            }
+        }
+
+        fn insert_cluster(&mut self, cluster: u32, entry: FatEntryValue) -> Option<&mut [u8]> {
             let data = vec![0; self.cluster_size];
-            let new_change_item = AllocChangeBuff { data, entry };
+            let new_change_item = AllocChangeBuff {
+                data,
+                entry,
+                dirty: true,
+                first_epoch: self.epoch,
+                last_epoch: self.epoch,
+            };
             self.entries.insert(cluster, new_change_item);
-            &mut self.entries.get_mut(&cluster).unwrap().data
+            self.entries.get_mut(&cluster).map(|ent| ent.data.as_mut())
         }
     }
 }
@@ -86,11 +142,14 @@ mod alloc_changeset {
 pub type ChangeSet = noalloc_changeset::NoallocChangeSet;
 #[cfg(not(feature = "alloc"))]
 pub type ChangeBuff = noalloc_changeset::NoallocChangeBuff;
+/// The largest cluster the change set can hold a copy of.
+#[cfg(not(feature = "alloc"))]
+pub const MAX_CLUSTER_SIZE: usize = noalloc_changeset::CLUSTER_BUFFER_SIZE;
 
 #[cfg(not(feature = "alloc"))]
 mod noalloc_changeset {
     use super::*;
-    const CLUSTER_BUFFER_SIZE: usize = 1024 * 4;
+    pub const CLUSTER_BUFFER_SIZE: usize = 1024 * 4;
     const CHANGESET_CAPACITY: usize = 1024;
 
     #[derive(Clone, Copy)]
@@ -98,6 +157,9 @@ mod noalloc_changeset {
         cluster: u32,
         data: [u8; CLUSTER_BUFFER_SIZE],
         entry: FatEntryValue,
+        dirty: bool,
+        first_epoch: u32,
+        last_epoch: u32,
     }
 
     impl Default for NoallocChangeBuff {
@@ -106,6 +168,9 @@ mod noalloc_changeset {
                 cluster: FatEntryValue::Bad.into(),
                 data: [0; CLUSTER_BUFFER_SIZE],
                 entry: FatEntryValue::Free,
+                dirty: false,
+                first_epoch: 0,
+                last_epoch: 0,
             }
         }
     }
@@ -117,6 +182,9 @@ mod noalloc_changeset {
         fn data(&self) -> &[u8] {
             &self.data
         }
+        fn epochs(&self) -> (u32, u32) {
+            (self.first_epoch, self.last_epoch)
+        }
     }
 
     pub struct NoallocChangeIter<'a> {
@@ -149,21 +217,41 @@ mod noalloc_changeset {
 
     pub struct NoallocChangeSet {
         changes: [NoallocChangeBuff; CHANGESET_CAPACITY],
+        epoch: u32,
     }
 
     impl NoallocChangeSet {
         pub fn entries<'a>(&'a self) -> impl Iterator<Item = (u32, NoallocChangeBuff)> + 'a {
             NoallocChangeIter::new(&self.changes)
         }
+
+        pub fn changed_between<'a>(&'a self, from: u32, to: u32) -> impl Iterator<Item = u32> + 'a {
+            let unused: u32 = FatEntryValue::Bad.into();
+            self.changes
+                .iter()
+                .take_while(move |ent| ent.cluster != unused)
+                .filter(move |ent| ent.changed_between(from, to))
+                .map(|ent| ent.cluster)
+        }
     }
 
     impl ChangeSetOps for NoallocChangeSet {
         fn new(_cluster_size: u32) -> Self {
             NoallocChangeSet {
                 changes: [Default::default(); CHANGESET_CAPACITY],
+                epoch: 0,
             }
         }
 
+        fn epoch(&self) -> u32 {
+            self.epoch
+        }
+
+        fn advance_epoch(&mut self) -> u32 {
+            self.epoch += 1;
+            self.epoch
+        }
+
         fn cluster_entry(&self, cluster: u32) -> Option<FatEntryValue> {
             let idx = self
                 .changes
@@ -178,6 +266,8 @@ mod noalloc_changeset {
                 .binary_search_by_key(&cluster, |buff| buff.cluster)
             {
                 self.changes[idx].entry = new_entry;
+                self.changes[idx].dirty = true;
+                self.changes[idx].last_epoch = self.epoch;
             }
         }
 
@@ -194,23 +284,43 @@ mod noalloc_changeset {
                 .changes
                 .binary_search_by_key(&cluster, |buff| buff.cluster)
                 .ok()?;
+            self.changes[idx].dirty = true;
+            self.changes[idx].last_epoch = self.epoch;
             Some(&mut self.changes[idx].data)
         }
-        fn insert_cluster(&mut self, cluster: u32, entry: FatEntryValue) -> &mut [u8] {
+
+        fn is_dirty(&self, cluster: u32) -> bool {
+            self.changes
+                .binary_search_by_key(&cluster, |buff| buff.cluster)
+                .is_ok_and(|idx| self.changes[idx].dirty)
+        }
+
+        fn any_dirty(&self) -> bool {
+            self.changes.iter().any(|change| change.dirty)
+        }
+
+        fn mark_clean(&mut self) {
+            for change in self.changes.iter_mut() {
+                change.dirty = false;
+            }
+        }
+        fn insert_cluster(&mut self, cluster: u32, entry: FatEntryValue) -> Option<&mut [u8]> {
             if let Ok(idx) = self
                 .changes
                 .binary_search_by_key(&cluster, |buff| buff.cluster)
             {
-                &mut self.changes[idx].data
+                Some(&mut self.changes[idx].data)
             } else {
                 let free_idx = self
                     .changes
                     .binary_search_by_key(&FatEntryValue::Bad.into(), |buff| buff.cluster)
-                    .unwrap();
+                    .ok()?;
                 self.changes[free_idx].cluster = cluster;
                 self.changes[free_idx].entry = entry;
+                self.changes[free_idx].dirty = true;
+                self.changes[free_idx].first_epoch = self.epoch;
                 self.changes.sort_unstable_by_key(|buff| buff.cluster);
-                self.cluster_mut(cluster).unwrap()
+                self.cluster_mut(cluster)
             }
         }
     }
@@ -219,6 +329,12 @@ mod noalloc_changeset {
 pub trait ChangeSetOps {
     fn new(cluster_size: u32) -> Self;
 
+    /// The epoch changes are currently being stamped with.
+    fn epoch(&self) -> u32;
+
+    /// Starts a new epoch, returning its number.
+    fn advance_epoch(&mut self) -> u32;
+
     fn cluster_entry(&self, cluster: u32) -> Option<FatEntryValue>;
 
     fn set_cluster_entry(&mut self, cluster: u32, new_entry: FatEntryValue);
@@ -226,7 +342,20 @@ pub trait ChangeSetOps {
     fn cluster_data(&self, cluster: u32) -> Option<&[u8]>;
 
     fn cluster_mut(&mut self, cluster: u32) -> Option<&mut [u8]>;
-    fn insert_cluster(&mut self, cluster: u32, entry: FatEntryValue) -> &mut [u8];
+
+    /// Starts tracking changes to `cluster`, whose FAT entry is `entry`,
+    /// returning its buffer, or `None` if there is no room for another
+    /// cluster.
+    fn insert_cluster(&mut self, cluster: u32, entry: FatEntryValue) -> Option<&mut [u8]>;
+
+    /// Whether `cluster` has been changed since the last `mark_clean`.
+    fn is_dirty(&self, cluster: u32) -> bool;
+
+    /// Whether any cluster has been changed since the last `mark_clean`.
+    fn any_dirty(&self) -> bool;
+
+    /// Marks every change currently in the set as having been persisted.
+    fn mark_clean(&mut self);
 
     // Rust doesn't yet allow for `impl Trait` as part of a trait definition,
     // so since this is trait only really exists for easier compile time checks that
@@ -239,4 +368,18 @@ pub trait ChangeSetOps {
 pub trait ChangeSetEntry {
     fn data(&self) -> &[u8];
     fn entry(&self) -> FatEntryValue;
+
+    /// The epochs the entry was first and last changed in.
+    fn epochs(&self) -> (u32, u32);
+
+    /// Whether the entry may have been changed in any epoch from `from` up to,
+    /// but not including, `to`.
+    ///
+    /// Only the first and last changes are tracked, so an entry that was
+    /// changed both before and after the range but not during it is
+    /// reported as well.
+    fn changed_between(&self, from: u32, to: u32) -> bool {
+        let (first, last) = self.epochs();
+        first < to && last >= from
+    }
 }
@@ -0,0 +1,205 @@
+//! Walks the whole fake image in fixed-size chunks, which is all most
+//! exporters, hashers and uploaders need from the device.
+//!
+//! Since a chunk has to be read into a caller-provided buffer, `ImageChunks` is
+//! a cursor rather than an `Iterator`. `FakeFat::dump` and `FakeFat::dump_with`
+//! wrap it for the common case of copying the whole image somewhere.
+
+use crate::changeset::ChangeSetOps;
+use crate::clustermapping::ClusterMapperOps;
+use crate::faker::{FakeFat, FakerAddress};
+use crate::fat::entries_at;
+use crate::geometry::FIRST_DATA_CLUSTER;
+use crate::traits::FileSystemOps;
+
+use core::ops::Range;
+
+/// The size of the buffer `FakeFat::dump_with` reads the image through.
+const DUMP_BUFFER_SIZE: usize = 4096;
+
+/// A cursor over successive fixed-size chunks of a `FakeFat`'s image.
+///
+/// Constructed via `FakeFat::chunks`.
+pub struct ImageChunks<'a, T: FileSystemOps> {
+    fat: &'a mut FakeFat<T>,
+    chunk_size: usize,
+    next_idx: usize,
+    skip_unallocated: bool,
+}
+
+impl<'a, T: FileSystemOps> ImageChunks<'a, T> {
+    /// Sets whether chunks that only cover unallocated clusters, or the File
+    /// Allocation Table entries of unallocated clusters, are skipped instead of
+    /// yielded. Those chunks are always entirely zero.
+    ///
+    /// Defaults to `false`.
+    pub fn skip_unallocated(mut self, skip: bool) -> Self {
+        self.skip_unallocated = skip;
+        self
+    }
+
+    /// The device offset of the next chunk to be yielded.
+    pub fn position(&self) -> usize {
+        self.next_idx
+    }
+
+    /// Reads the next chunk into `buffer`, returning the chunk's device offset
+    /// and the part of `buffer` it was read into, or `None` once the end of
+    /// the image is reached.
+    ///
+    /// The final chunk is shorter than the others if the image size is not a
+    /// multiple of the chunk size.
+    ///
+    /// # Panics
+    /// This function panics if `buffer` is smaller than the chunk size.
+    pub fn next_chunk<'b>(&mut self, buffer: &'b mut [u8]) -> Option<(usize, &'b [u8])> {
+        assert!(
+            buffer.len() >= self.chunk_size,
+            "Chunk buffer of {} bytes is smaller than the chunk size {}",
+            buffer.len(),
+            self.chunk_size
+        );
+        let image_size = self.fat.image_size();
+        if self.skip_unallocated {
+            while self.next_idx < image_size && self.is_unallocated(self.next_idx) {
+                self.next_idx += self.chunk_size;
+            }
+        }
+        if self.next_idx >= image_size {
+            return None;
+        }
+        let start = self.next_idx;
+        let len = (image_size - start).min(self.chunk_size);
+        let read = self.fat.read_device_at(start, &mut buffer[..len]);
+        self.next_idx += self.chunk_size;
+        Some((start, &buffer[..read]))
+    }
+
+    /// Whether the chunk starting at `start` only covers unallocated clusters or
+    /// their FAT entries.
+    fn is_unallocated(&self, start: usize) -> bool {
+        let end = (start + self.chunk_size).min(self.fat.image_size());
+        let mut idx = start;
+        while idx < end {
+            let (populated, next) = self.fat.populated_extent(idx);
+            if populated {
+                return false;
+            }
+            idx = next;
+        }
+        true
+    }
+}
+
+impl<T: FileSystemOps> FakeFat<T> {
+    /// Finds the first range of the image at or after `from` that can hold
+    /// anything other than zeroes, or `None` if the rest of the image is empty.
+    ///
+    /// Everything outside the returned ranges is unallocated clusters and the
+    /// FAT entries of unallocated clusters, which always read as zero, so
+    /// exporters can leave holes there instead of writing out the zeroes. The
+    /// ranges are not guaranteed to hold any non-zero bytes themselves.
+    ///
+    /// Writes still being batched up by `write_byte` count as populated, even
+    /// before they reach the change set.
+    pub fn next_populated_range(&self, from: usize) -> Option<Range<usize>> {
+        let image_size = self.image_size();
+        let mut start = from;
+        loop {
+            if start >= image_size {
+                return None;
+            }
+            let (populated, next) = self.populated_extent(start);
+            if populated {
+                break;
+            }
+            start = next;
+        }
+        let mut end = start;
+        while end < image_size {
+            let (populated, next) = self.populated_extent(end);
+            if !populated {
+                break;
+            }
+            end = next;
+        }
+        Some(start..end.min(image_size))
+    }
+
+    /// Whether the byte at `idx` can be anything other than zero, along with
+    /// the index the answer might change at; that is the end of the FAT entry
+    /// or cluster containing `idx`, or the start of the FATs.
+    fn populated_extent(&self, idx: usize) -> (bool, usize) {
+        let (populated, next) = self.allocated_extent(idx);
+        let (batch_start, batch) = self.write_buffer.pending();
+        let batched = batch_start < next && idx < batch_start + batch.len();
+        (populated || batched, next)
+    }
+
+    /// Like `populated_extent`, but only going by the allocated clusters and
+    /// the change set.
+    fn allocated_extent(&self, idx: usize) -> (bool, usize) {
+        let is_populated = |cluster: u32| {
+            self.layout.mapper.is_allocated(cluster)
+                || self.changes.cluster_entry(cluster).is_some()
+        };
+        match FakerAddress::from_raw_idx(idx, &self.layout.bpb) {
+            FakerAddress::Fat { offset } => {
+                let entries = entries_at(self.layout.bpb.fat_type, offset);
+                let last_entry_end =
+                    (*entries.end() as usize + 1) * self.layout.bpb.fat_type.entry_bits();
+                let populated = entries
+                    .into_iter()
+                    .any(|cluster| cluster < FIRST_DATA_CLUSTER || is_populated(cluster));
+                let next_offset = (last_entry_end / 8)
+                    .max(offset + 1)
+                    .min(self.layout.bpb.fat_bytes());
+                (populated, idx + next_offset - offset)
+            }
+            FakerAddress::RawData { cluster, offset } => {
+                let cluster_size = self.layout.bpb.bytes_per_cluster() as usize;
+                (is_populated(cluster), idx + cluster_size - offset)
+            }
+            _ => (true, self.layout.bpb.fat_start()),
+        }
+    }
+
+    /// Returns a cursor over the image in successive chunks of `chunk_size`
+    /// bytes, starting at the head of the device.
+    ///
+    /// # Panics
+    /// This function panics if `chunk_size` is 0.
+    pub fn chunks(&mut self, chunk_size: usize) -> ImageChunks<'_, T> {
+        assert!(chunk_size > 0, "Chunk size must be non-zero");
+        ImageChunks {
+            fat: self,
+            chunk_size,
+            next_idx: 0,
+            skip_unallocated: false,
+        }
+    }
+
+    /// Streams the entire image, from the head of the device to its last sector,
+    /// into `sink`, stopping at the first error `sink` returns.
+    ///
+    /// The image is passed to `sink` in pieces of a whole number of sectors, at
+    /// most 4096 bytes each, so no allocation is needed.
+    pub fn dump_with<E, F: FnMut(&[u8]) -> Result<(), E>>(&mut self, mut sink: F) -> Result<(), E> {
+        let sector_size = self.sector_size();
+        let chunk_size = (DUMP_BUFFER_SIZE / sector_size).max(1) * sector_size;
+        let mut buffer = [0u8; DUMP_BUFFER_SIZE];
+        let mut chunks = self.chunks(chunk_size);
+        while let Some((_, data)) = chunks.next_chunk(&mut buffer) {
+            sink(data)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the entire image, from the head of the device to its last sector,
+    /// into `writer`.
+    #[cfg(feature = "std")]
+    pub fn dump<W: std::io::Write>(&mut self, mut writer: W) -> std::io::Result<()> {
+        self.dump_with(|data| writer.write_all(data))?;
+        writer.flush()
+    }
+}
@@ -5,13 +5,14 @@
 //! `ClusterMapperOps` implementations:
 //!
 //! *  In environments without an allocator, each Path -> ClusterChain mapping
-//! is represented by a fixed-size `FileEntry` struct; the Cluster Mapper is backed
-//! by a fixed-size array of entries, with both cluster and path lookups done via
-//! linear search.
+//!    is represented by a fixed-size `FileEntry` struct; the Cluster Mapper is backed
+//!    by a fixed-size array of entries, with both cluster and path lookups done via
+//!    linear search. Items whose chain or path does not fit, or that come after
+//!    every entry is taken, are left off the device.
 //!
 //! *  In environments with an allocator, the Cluster Mapper is backed by a pair of
-//! `HashMaps`: a `HashMap<String, Vec<u32>>` for quick cluster chain lookup, and a
-//! `HashMap<u32, String>` for quick path lookup.
+//!    `BTreeMap`s: a `BTreeMap<String, Vec<u32>>` for quick cluster chain lookup, and a
+//!    `BTreeMap<u32, String>` for quick path lookup.
 //!
 
 pub trait ClusterMapperOps {
@@ -32,22 +33,64 @@ pub trait ClusterMapperOps {
     /// Appends a cluster to the end of the cluster chain associated with the given
     /// `path`; if there is no chain associated with `path` yet, it is created with
     /// `cluster` as its single link.
+    ///
+    /// Does nothing if there is no room left for the cluster, which can be
+    /// checked for ahead of time with `has_room_for`.
     fn add_cluster_to_path(&mut self, path: &str, cluster: u32);
 
+    /// Returns whether `clusters` more clusters can be appended to the chain
+    /// associated with `path`, including creating the chain if there is none.
+    fn has_room_for(&self, path: &str, clusters: usize) -> bool;
+
+    /// Gives `path` the same cluster chain as `owner`, which stays the path
+    /// every cluster of the chain is allocated to.
+    ///
+    /// Returns `false` if `path` already has a chain, if `owner` has none, or if
+    /// chains cannot be shared.
+    fn share_chain(&mut self, path: &str, owner: &str) -> bool;
+
+    /// Cuts the chain associated with `path` down to its first `len` clusters,
+    /// freeing the rest.
+    ///
+    /// Returns `false`, leaving the chain as is, if the chain is shared with
+    /// another path.
+    fn truncate_chain(&mut self, path: &str, len: usize) -> bool;
+
     /// Returns whether a given `cluster` is currently in any allocated cluster chain.
     fn is_allocated(&self, cluster: u32) -> bool;
 
+    /// The number of clusters allocated across every cluster chain.
+    fn allocated_count(&self) -> usize;
+
     /// Attempts to find the chain containing the given cluster, returning `None` otherwise. 
     fn get_chain_with_cluster(&self, cluster: u32) -> Option<Self::ChainIterator> {
         self.get_path_for_cluster(cluster)
             .map(|p| self.get_chain_for_path(p))
     }
 
+    /// Returns whether a given `path` is associated with a chain.
+    fn has_chain(&self, path: &str) -> bool {
+        self.get_chain_head_for_path(path).is_some()
+    }
+
     /// Gets the first cluster in the chain associated with a given path, or 
     /// `None` if the path has not yet been associated with a chain. 
     fn get_chain_head_for_path(&self, path: &str) -> Option<u32> {
         self.get_chain_for_path(path).into_iter().next()
     }
+
+    /// The number of clusters currently allocated for the given path.
+    fn chain_len(&self, path: &str) -> usize {
+        self.get_chain_for_path(path).into_iter().count()
+    }
+
+    /// The number of clusters before `cluster` in the chain it belongs to, or
+    /// `None` if the cluster has not yet been allocated.
+    fn chain_position(&self, cluster: u32) -> Option<usize> {
+        self.get_chain_with_cluster(cluster)?
+            .into_iter()
+            .position(|link| link == cluster)
+    }
 }
 
 #[cfg(not(feature = "alloc"))]
@@ -66,6 +109,7 @@ mod nop_mapper {
         pub const MAX_PATH_LENGTH: usize = 1024;
     }
 
+    #[derive(Clone)]
     pub struct NopClusterMapper {
         entries: [FileEntry; size_constants::MAX_ENTRIES],
     }
@@ -93,10 +137,7 @@ mod nop_mapper {
         }
 
         pub fn chain_count(&self) -> usize {
-            (&self.chain)
-                .iter()
-                .take_while(|&&c| FatEntryValue::from(c) != FatEntryValue::Bad)
-                .count()
+            (&self.chain).iter().take_while(|&&c| is_link(c)).count()
         }
 
         pub fn add_cluster(&mut self, cluster: u32) {
@@ -104,6 +145,12 @@ mod nop_mapper {
         }
     }
 
+    /// Whether `value` is a cluster of a chain rather than one of the markers
+    /// the unused rest of the chain is filled with.
+    fn is_link(value: u32) -> bool {
+        matches!(FatEntryValue::from(value), FatEntryValue::Next(_))
+    }
+
     impl Default for FileEntry {
         fn default() -> FileEntry {
             FileEntry {
@@ -152,7 +199,10 @@ mod nop_mapper {
             (&self.entries)
                 .iter()
                 .enumerate()
-                .find(|(_, ent)| (&ent.path[..path_bytes.len()]) == path_bytes)
+                .find(|(_, ent)| {
+                    ent.path_strlen() == path_bytes.len()
+                        && (&ent.path[..path_bytes.len()]) == path_bytes
+                })
                 .map(|(idx, _)| idx)
         }
 
@@ -164,7 +214,7 @@ mod nop_mapper {
                     let mut chain = (&ent.chain)
                         .iter()
                         .enumerate()
-                        .take_while(|(_, c)| FatEntryValue::from(**c) != FatEntryValue::Bad);
+                        .take_while(|(_, c)| is_link(**c));
                     let cluster_idx = chain.find(|(_, c)| **c == cluster);
                     match cluster_idx {
                         Some((cidx, _)) => Some((path_idx, cidx)),
@@ -208,20 +258,61 @@ mod nop_mapper {
             }
         }
         fn add_cluster_to_path(&mut self, path: &str, cluster: u32) {
-            let existing = self.find_path_entry(path);
-            let entry = match existing {
-                Some(eidx) => &mut self.entries[eidx],
+            if !self.has_room_for(path, 1) {
+                return;
+            }
+            let eidx = match self.find_path_entry(path) {
+                Some(eidx) => eidx,
                 None => {
-                    self.entries[self.entry_count()] = FileEntry::from_path(path);
-                    &mut self.entries[self.entry_count()]
+                    let eidx = self.entry_count();
+                    self.entries[eidx] = FileEntry::from_path(path);
+                    eidx
                 }
             };
-            entry.add_cluster(cluster);
+            self.entries[eidx].add_cluster(cluster);
+        }
+
+        fn has_room_for(&self, path: &str, clusters: usize) -> bool {
+            let chain_len = match self.find_path_entry(path) {
+                Some(eidx) => self.entries[eidx].chain_count(),
+                None if self.entry_count() < size_constants::MAX_ENTRIES
+                    && path.len() <= size_constants::MAX_PATH_LENGTH =>
+                {
+                    0
+                }
+                None => return false,
+            };
+            chain_len + clusters <= size_constants::MAX_CHAIN_LENGTH
+        }
+
+        fn share_chain(&mut self, _path: &str, _owner: &str) -> bool {
+            // Every cluster is looked up by searching the entries, which only
+            // expects to find each cluster in one of them.
+            false
+        }
+
+        fn truncate_chain(&mut self, path: &str, len: usize) -> bool {
+            if let Some(eidx) = self.find_path_entry(path) {
+                let entry = &mut self.entries[eidx];
+                let count = entry.chain_count();
+                if len < count {
+                    entry.chain[len..count].fill(u32::max_value());
+                }
+            }
+            true
         }
 
         fn is_allocated(&self, cluster: u32) -> bool {
             self.find_cluster_entry(cluster).is_some()
         }
+
+        fn allocated_count(&self) -> usize {
+            (&self.entries)
+                .iter()
+                .take_while(|e| e.path_strlen() > 0)
+                .map(FileEntry::chain_count)
+                .sum()
+        }
     }
 }
 #[cfg(feature = "alloc")]
@@ -236,12 +327,13 @@ mod alloc_mapper {
     use std as alloc;
 
     use alloc::borrow::ToOwned;
-    use alloc::collections::HashMap;
+    use alloc::collections::BTreeMap;
     use alloc::string::String;
     use alloc::vec::Vec;
+    #[derive(Clone)]
     pub struct AllocClusterMapper {
-        cluster_mapping: HashMap<u32, String>,
-        path_mapping: HashMap<String, Vec<u32>>,
+        cluster_mapping: BTreeMap<u32, String>,
+        path_mapping: BTreeMap<String, Vec<u32>>,
     }
 
     impl ClusterMapperOps for AllocClusterMapper {
@@ -249,8 +341,8 @@ mod alloc_mapper {
 
         fn new() -> Self {
             AllocClusterMapper {
-                cluster_mapping: HashMap::new(),
-                path_mapping: HashMap::new(),
+                cluster_mapping: BTreeMap::new(),
+                path_mapping: BTreeMap::new(),
             }
         }
         fn get_path_for_cluster(&self, cluster: u32) -> Option<&str> {
@@ -271,8 +363,55 @@ mod alloc_mapper {
             self.cluster_mapping.insert(cluster, path.to_owned());
         }
 
+        fn has_room_for(&self, _path: &str, _clusters: usize) -> bool {
+            true
+        }
+
+        fn has_chain(&self, path: &str) -> bool {
+            self.path_mapping
+                .get(path)
+                .is_some_and(|chain| !chain.is_empty())
+        }
+
+        fn share_chain(&mut self, path: &str, owner: &str) -> bool {
+            if self.path_mapping.contains_key(path) {
+                return false;
+            }
+            let chain = match self.path_mapping.get(owner) {
+                Some(chain) if !chain.is_empty() => chain.clone(),
+                _ => return false,
+            };
+            self.path_mapping.insert(path.to_owned(), chain);
+            true
+        }
+
+        fn truncate_chain(&mut self, path: &str, len: usize) -> bool {
+            let head = match self.path_mapping.get(path).and_then(|chain| chain.first()) {
+                Some(&head) => head,
+                None => return true,
+            };
+            let shared = self.cluster_mapping.get(&head).map(String::as_str) != Some(path)
+                || self
+                    .path_mapping
+                    .iter()
+                    .any(|(other, chain)| other != path && chain.first() == Some(&head));
+            if shared {
+                return false;
+            }
+            if let Some(chain) = self.path_mapping.get_mut(path) {
+                for cluster in chain.drain(len.min(chain.len())..) {
+                    self.cluster_mapping.remove(&cluster);
+                }
+            }
+            true
+        }
+
         fn is_allocated(&self, cluster: u32) -> bool {
             self.cluster_mapping.contains_key(&cluster)
         }
+
+        fn allocated_count(&self) -> usize {
+            self.cluster_mapping.len()
+        }
     }
 }
@@ -0,0 +1,217 @@
+//! Describes what `FakeFat::write_back` is about to do to the backing
+//! filesystem, so callers whose backing store lives on flash can check how
+//! much it will wear before committing.
+//!
+//! Write-back applies one item at a time in the order the device lists them,
+//! finishing each file before moving on to the next. Within a file, data is
+//! written in whole regions of `FakeFatBuilder::erase_unit_size` bytes,
+//! aligned to the start of the file, so that every erase unit the host touched
+//! is rewritten exactly once and in order. Files that shrink are cut short
+//! before their data is written, so the blocks they give up are never
+//! rewritten first.
+//!
+//! Like the Cluster Mapper, there are 2 `CommitOpListOps` implementations
+//! toggled by the used feature flags:
+//!
+//! *  In environments without an allocator, operations are kept in a
+//!    fixed-size array; plans with more operations than it holds only keep
+//!    the first ones, and are not complete.
+//!
+//! *  In environments with an allocator, operations are kept in a
+//!    `Vec<CommitOp>`.
+
+use crate::writeback::WriteBackError;
+
+/// A single operation `FakeFat::write_back` applies to the backing filesystem.
+///
+/// Like `WriteBackError`, items are identified by where they live on the
+/// device, since their paths cannot be stored without an allocator.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CommitOp {
+    /// Create the directory described by slot `entry` of the directory
+    /// starting at `dir_cluster`.
+    CreateDirectory {
+        /// The first cluster of the directory containing the new directory.
+        dir_cluster: u32,
+        /// The index of the new directory's entry in its directory.
+        entry: u32,
+    },
+    /// Create the file described by slot `entry` of the directory starting at
+    /// `dir_cluster`.
+    CreateFile {
+        /// The first cluster of the directory containing the new file.
+        dir_cluster: u32,
+        /// The index of the new file's entry in its directory.
+        entry: u32,
+    },
+    /// Write `len` bytes at `offset` into the file starting at `cluster`.
+    Write {
+        /// The first cluster of the file.
+        cluster: u32,
+        /// The offset into the file of the first byte written.
+        offset: u32,
+        /// The number of bytes written.
+        len: u32,
+    },
+    /// Resize the file starting at `cluster` to `size` bytes.
+    Truncate {
+        /// The first cluster of the file.
+        cluster: u32,
+        /// The size the file is resized to.
+        size: u32,
+    },
+}
+
+/// The operations `FakeFat::write_back` would apply to the backing filesystem,
+/// in the order it would apply them, as found by `FakeFat::plan_write_back`.
+///
+/// The plan only stays accurate until the host writes to the device again.
+pub struct CommitPlan {
+    ops: CommitOpList,
+    complete: bool,
+    region_size: u32,
+    bytes_written: u64,
+    rejected: Option<WriteBackError>,
+}
+
+impl CommitPlan {
+    pub(crate) fn new(region_size: u32) -> Self {
+        CommitPlan {
+            ops: CommitOpList::new(),
+            complete: true,
+            region_size,
+            bytes_written: 0,
+            rejected: None,
+        }
+    }
+
+    /// Adds `op` to the end of the plan.
+    pub(crate) fn push(&mut self, op: CommitOp) {
+        if let CommitOp::Write { len, .. } = op {
+            self.bytes_written += u64::from(len);
+        }
+        self.complete &= self.ops.push(op);
+    }
+
+    pub(crate) fn set_rejected(&mut self, rejected: Option<WriteBackError>) {
+        self.rejected = rejected;
+    }
+
+    /// The operations, in the order they would be applied.
+    pub fn ops(&self) -> &[CommitOp] {
+        self.ops.as_slice()
+    }
+
+    /// Whether the plan would not change the backing filesystem at all.
+    pub fn is_empty(&self) -> bool {
+        self.complete && self.ops().is_empty()
+    }
+
+    /// Whether `ops` holds every operation, which is only ever not the case
+    /// without the `alloc` feature.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// The size of the regions file data is written in, which is the erase
+    /// unit size or the cluster size, whichever is larger.
+    pub fn region_size(&self) -> u32 {
+        self.region_size
+    }
+
+    /// The total number of bytes the plan writes, including those of any
+    /// operations past the end of an incomplete plan.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// The first file an append-only device would leave untouched, which
+    /// `write_back` reports once everything else is applied.
+    pub fn rejected(&self) -> Option<WriteBackError> {
+        self.rejected
+    }
+}
+
+pub trait CommitOpListOps {
+    /// Constructs a list without any operations.
+    fn new() -> Self;
+
+    /// Adds `op` to the end of the list.
+    ///
+    /// Returns `false` if the operation could not be stored.
+    fn push(&mut self, op: CommitOp) -> bool;
+
+    /// The operations in the list.
+    fn as_slice(&self) -> &[CommitOp];
+}
+
+#[cfg(not(feature = "alloc"))]
+pub type CommitOpList = noalloc_commitplan::NoallocCommitOpList;
+#[cfg(not(feature = "alloc"))]
+mod noalloc_commitplan {
+    use super::*;
+
+    const COMMIT_PLAN_CAPACITY: usize = 256;
+
+    pub struct NoallocCommitOpList {
+        len: usize,
+        ops: [CommitOp; COMMIT_PLAN_CAPACITY],
+    }
+
+    impl CommitOpListOps for NoallocCommitOpList {
+        fn new() -> Self {
+            NoallocCommitOpList {
+                len: 0,
+                ops: [CommitOp::Truncate {
+                    cluster: 0,
+                    size: 0,
+                }; COMMIT_PLAN_CAPACITY],
+            }
+        }
+
+        fn push(&mut self, op: CommitOp) -> bool {
+            if self.len >= COMMIT_PLAN_CAPACITY {
+                return false;
+            }
+            self.ops[self.len] = op;
+            self.len += 1;
+            true
+        }
+
+        fn as_slice(&self) -> &[CommitOp] {
+            &self.ops[..self.len]
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub type CommitOpList = alloc_commitplan::AllocCommitOpList;
+#[cfg(feature = "alloc")]
+mod alloc_commitplan {
+    use super::*;
+
+    #[cfg(feature = "std")]
+    use std as alloc;
+
+    use alloc::vec::Vec;
+
+    pub struct AllocCommitOpList {
+        ops: Vec<CommitOp>,
+    }
+
+    impl CommitOpListOps for AllocCommitOpList {
+        fn new() -> Self {
+            AllocCommitOpList { ops: Vec::new() }
+        }
+
+        fn push(&mut self, op: CommitOp) -> bool {
+            self.ops.push(op);
+            true
+        }
+
+        fn as_slice(&self) -> &[CommitOp] {
+            &self.ops
+        }
+    }
+}
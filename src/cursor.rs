@@ -0,0 +1,123 @@
+//! Readers of a shared `FakeFat` that each keep their own position, so that
+//! a host adapter and a debug dump can both stream the device without moving
+//! each other's place, as they would through the `Read` and `Seek`
+//! implementations of `FakeFat` itself.
+
+use crate::faker::FakeFat;
+use crate::traits::FileSystemOps;
+
+use core::cell::RefCell;
+
+/// A position in a `FakeFat` that is read from through `Read` and `Seek`,
+/// independently of any other cursor over the same device.
+///
+/// The device is only borrowed for the duration of each read or seek, so any
+/// number of cursors can be kept around at once, but reading or seeking panics
+/// if the caller is holding on to a borrow of the device at the time:
+///
+/// ```ignore
+/// let fat = RefCell::new(fat);
+/// let mut host = FakeFatCursor::new(&fat);
+/// let mut dump = FakeFatCursor::new(&fat);
+/// ```
+pub struct FakeFatCursor<'a, T: FileSystemOps> {
+    fat: &'a RefCell<FakeFat<T>>,
+    pos: u64,
+}
+
+impl<'a, T: FileSystemOps> FakeFatCursor<'a, T> {
+    /// A cursor at the head of `fat`.
+    pub fn new(fat: &'a RefCell<FakeFat<T>>) -> Self {
+        FakeFatCursor { fat, pos: 0 }
+    }
+
+    /// The offset from the head of the device the next read starts at.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Reads from the current position like the `Read` implementation of
+    /// `FakeFat`, stopping at the end of the device, and moves past what was
+    /// read.
+    #[cfg_attr(not(any(feature = "std", feature = "embedded-io")), allow(dead_code))]
+    fn read_next(&mut self, buf: &mut [u8]) -> usize {
+        let mut fat = self.fat.borrow_mut();
+        let buf = fat.stream_buffer(self.pos, buf);
+        let read = fat.read_at(self.pos as usize, buf);
+        self.pos += read as u64;
+        read
+    }
+
+    /// The length of the device, for seeks from its end.
+    #[cfg_attr(not(any(feature = "std", feature = "embedded-io")), allow(dead_code))]
+    fn device_len(&self) -> u64 {
+        self.fat.borrow().len()
+    }
+}
+
+#[cfg(feature = "std")]
+mod stdio {
+    use super::*;
+    use std::io::{self, Read, Seek, SeekFrom};
+
+    impl<'a, T: FileSystemOps> Read for FakeFatCursor<'a, T> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let read = self.read_next(buf);
+            // Reading nothing would look like the end of the device.
+            if read == 0 && self.pos < self.device_len() && !buf.is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            Ok(read)
+        }
+    }
+    impl<'a, T: FileSystemOps> Seek for FakeFatCursor<'a, T> {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64, io::Error> {
+            let new_pos = match pos {
+                SeekFrom::Start(abs) => Some(abs),
+                SeekFrom::End(off) => self.device_len().checked_add_signed(off),
+                SeekFrom::Current(off) => self.pos.checked_add_signed(off),
+            };
+            self.pos = new_pos.ok_or(io::ErrorKind::InvalidInput)?;
+            Ok(self.pos)
+        }
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+mod embedded {
+    use super::*;
+    use crate::embeddedio::IoError;
+    use embedded_io::{ErrorType, Read, Seek, SeekFrom};
+
+    impl<'a, T: FileSystemOps> ErrorType for FakeFatCursor<'a, T> {
+        type Error = IoError;
+    }
+
+    impl<'a, T: FileSystemOps> Read for FakeFatCursor<'a, T> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+            let read = self.read_next(buf);
+            // Reading nothing would look like the end of the device.
+            if read == 0 && self.pos < self.device_len() && !buf.is_empty() {
+                return Err(IoError::Busy { offset: self.pos });
+            }
+            Ok(read)
+        }
+    }
+
+    impl<'a, T: FileSystemOps> Seek for FakeFatCursor<'a, T> {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64, IoError> {
+            let (from, offset) = match pos {
+                SeekFrom::Start(abs) => {
+                    self.pos = abs;
+                    return Ok(abs);
+                }
+                SeekFrom::End(off) => (self.device_len(), off),
+                SeekFrom::Current(off) => (self.pos, off),
+            };
+            self.pos = from
+                .checked_add_signed(offset)
+                .ok_or(IoError::InvalidSeek { from, offset })?;
+            Ok(self.pos)
+        }
+    }
+}
@@ -210,6 +210,11 @@ impl Time {
         self.second
     }
 
+    /// The tenths of a second since the second, between 0 and 9 inclusive. 
+    pub fn tenths(self) -> u8 {
+        self.tenths
+    }
+
     /// Decodes a low-precision FAT-encoded clock time into a `Time` value. 
     /// 
     /// Due to FAT precision limitations, this means that the resulting `second()`
@@ -229,7 +234,7 @@ impl Time {
     /// 
     /// This byte includes information for both tenths of a second and for `self.second % 2`. 
     pub fn with_hi_res(mut self, hi_res_info: u8) -> Self {
-        debug_assert!((hi_res_info <= 9) || (hi_res_info >= 100 && hi_res_info <= 109));
+        debug_assert!((hi_res_info <= 9) || (100..=109).contains(&hi_res_info));
         self.second -= self.second % 2;
         self.second += hi_res_info / 100;
         self.tenths = hi_res_info % 100;
@@ -268,3 +273,51 @@ impl Time {
             .with_tenths(tenths)
     }
 }
+
+/// Returns the first FAT modification timestamp after `date` and `time`, which
+/// is 2 seconds later since FAT only stores modification times to the even second.
+pub(crate) fn next_fat_timestamp(date: Date, time: Time) -> (Date, Time) {
+    let second = time.second - time.second % 2 + 2;
+    if second < 60 {
+        return (
+            date,
+            Time {
+                second,
+                tenths: 0,
+                ..time
+            },
+        );
+    }
+    if time.minute < 59 {
+        let minute = time.minute + 1;
+        let next_time = Time {
+            minute,
+            ..Time::default()
+        };
+        return (date, next_time.with_hour(time.hour));
+    }
+    if time.hour < 23 {
+        return (date, Time::default().with_hour(time.hour + 1));
+    }
+    let month_ranges = if date.year.is_multiple_of(4) {
+        LEAP_MONTH_RANGES
+    } else {
+        NONLEAP_MONTH_RANGES
+    };
+    let month_idx = usize::from(date.month);
+    let month_days = month_ranges[month_idx] - month_ranges[month_idx - 1];
+    let next_date = if u16::from(date.day) < month_days {
+        date.with_day(date.day + 1)
+    } else if date.month < 12 {
+        date.with_day(1).with_month(date.month + 1)
+    } else {
+        date.with_day(1).with_month(1).with_year(date.year + 1)
+    };
+    (next_date, Time::default())
+}
+
+/// Packs `date` and `time` into a single value that orders the same way the
+/// timestamps do, at the precision FAT stores modification times.
+pub(crate) fn fat_timestamp_key(date: Date, time: Time) -> u32 {
+    (u32::from(date.fat_encode()) << 16) | u32::from(time.fat_encode_simple())
+}
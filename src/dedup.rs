@@ -0,0 +1,216 @@
+//! Backing files with identical contents can share a single cluster chain on
+//! the device, so that the contents only take up space once. Finding them means
+//! hashing every exposed file when the volume is laid out, and then comparing
+//! the files whose size and hash match byte by byte, so that a hash collision
+//! never merges two different files.
+//!
+//! The file that comes first in the order clusters are allocated in owns the
+//! chain, and every later copy of it is given the owner's chain in the Cluster
+//! Mapper instead of one of its own. Since a host writing to a shared cluster
+//! would change every file sharing it, this is only allowed on write protected
+//! devices.
+//!
+//! Like the Cluster Mapper, there are 2 `DedupIndexOps` implementations toggled
+//! by the used feature flags:
+//!
+//! *  In environments without an allocator, there is nowhere to keep the paths
+//!    of the files seen so far, so no duplicates are ever found.
+//!
+//! *  In environments with an allocator, the files seen so far are kept in a
+//!    `BTreeMap<(u32, u64), Vec<String>>` keyed by their size and hash, and the
+//!    duplicates found in a `BTreeMap<String, String>` from their path to the
+//!    path of the file owning their chain.
+
+use crate::dirnames::{DirNames, DirNamesOps};
+use crate::faker::read_padded;
+use crate::pathbuffer::PathBuff;
+use crate::sanitize::NamingOptions;
+use crate::traits::{DirEntryOps, DirectoryListing, DirectoryOps, FileSystemLookup, FileSystemOps};
+
+pub(crate) const FNV_OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+pub(crate) const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+/// The number of bytes read from a backing file at a time when hashing or
+/// comparing it.
+const CHUNK_SIZE: usize = 512;
+
+pub trait DedupIndexOps {
+    /// Constructs an index without any files.
+    fn new() -> Self;
+
+    /// Adds the file at the backing path `path`, which is exposed as `size`
+    /// bytes long, checking whether it is a duplicate of a file added earlier.
+    fn add_file<T: FileSystemOps>(&mut self, fs: &mut T, path: &str, size: u32);
+
+    /// The path of the earlier file that the file at `path` has the same
+    /// contents as, or `None` if it is not a duplicate.
+    fn owner_of(&self, path: &str) -> Option<&str>;
+
+    /// The number of files that are duplicates of an earlier file.
+    fn duplicate_count(&self) -> u32;
+
+    /// The combined size of every file that is a duplicate of an earlier file.
+    fn duplicate_bytes(&self) -> u64;
+}
+
+/// Adds every file exposed directly in the directory `cur` to `index`,
+/// returning whether the directory could be listed.
+///
+/// Visiting directories in the same order `traverse` does adds the files in
+/// the order their clusters are allocated in, so that the owner of each chain
+/// is always allocated before its duplicates. Empty files are skipped, since
+/// they have no chain to share.
+pub(crate) fn add_dir_files<T: FileSystemOps>(
+    index: &mut DedupIndex,
+    cur: &PathBuff,
+    fs: &mut T,
+    naming: NamingOptions,
+) -> bool {
+    let dir = match fs.find_dir(cur.to_str()) {
+        Some(dir) => dir,
+        None => return false,
+    };
+    let names = DirNames::new(naming, &dir, cur.to_str());
+    let exposed = |ent: &<T::DirectoryType as DirectoryOps>::EntryType| {
+        names.get(&dir, cur.to_str(), ent).is_some()
+    };
+    for ent in dir.listing().filter(|ent| !ent.meta().is_directory) {
+        if !exposed(&ent) {
+            continue;
+        }
+        let mut path = cur.clone();
+        path.add_file(ent.name().as_ref());
+        let meta = naming.exposed_meta(path.to_str(), ent.meta());
+        if meta.size > 0 {
+            index.add_file(fs, path.to_str(), meta.size);
+        }
+    }
+    true
+}
+
+/// Hashes the first `size` bytes of the file at `path`, or returns `None` if
+/// there is no such file or it cannot be read.
+pub(crate) fn content_hash<T: FileSystemOps>(fs: &mut T, path: &str, size: u32) -> Option<u64> {
+    let mut file = fs.find_file(path)?;
+    let mut buffer = [0; CHUNK_SIZE];
+    let mut hash = FNV_OFFSET_BASIS;
+    for offset in (0..size as usize).step_by(CHUNK_SIZE) {
+        let len = (size as usize - offset).min(CHUNK_SIZE);
+        read_padded(&mut file, offset, &mut buffer[..len]).ok()?;
+        for &byte in buffer[..len].iter() {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+    Some(hash)
+}
+
+/// Whether the first `size` bytes of the files at `a` and `b` are the same.
+#[cfg_attr(not(feature = "alloc"), allow(dead_code))]
+fn same_contents<T: FileSystemOps>(fs: &mut T, a: &str, b: &str, size: u32) -> bool {
+    let (mut file_a, mut file_b) = match (fs.find_file(a), fs.find_file(b)) {
+        (Some(file_a), Some(file_b)) => (file_a, file_b),
+        _ => return false,
+    };
+    let mut buffer_a = [0; CHUNK_SIZE];
+    let mut buffer_b = [0; CHUNK_SIZE];
+    (0..size as usize).step_by(CHUNK_SIZE).all(|offset| {
+        let len = (size as usize - offset).min(CHUNK_SIZE);
+        read_padded(&mut file_a, offset, &mut buffer_a[..len]).is_ok()
+            && read_padded(&mut file_b, offset, &mut buffer_b[..len]).is_ok()
+            && buffer_a[..len] == buffer_b[..len]
+    })
+}
+
+#[cfg(not(feature = "alloc"))]
+pub type DedupIndex = noalloc_dedup::NoallocDedupIndex;
+#[cfg(not(feature = "alloc"))]
+mod noalloc_dedup {
+    use super::*;
+
+    pub struct NoallocDedupIndex;
+
+    impl DedupIndexOps for NoallocDedupIndex {
+        fn new() -> Self {
+            NoallocDedupIndex
+        }
+
+        fn add_file<T: FileSystemOps>(&mut self, _fs: &mut T, _path: &str, _size: u32) {}
+
+        fn owner_of(&self, _path: &str) -> Option<&str> {
+            None
+        }
+
+        fn duplicate_count(&self) -> u32 {
+            0
+        }
+
+        fn duplicate_bytes(&self) -> u64 {
+            0
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub type DedupIndex = alloc_dedup::AllocDedupIndex;
+#[cfg(feature = "alloc")]
+mod alloc_dedup {
+    use super::*;
+
+    #[cfg(feature = "std")]
+    use std as alloc;
+
+    use alloc::borrow::ToOwned;
+    use alloc::collections::BTreeMap;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    pub struct AllocDedupIndex {
+        by_content: BTreeMap<(u32, u64), Vec<String>>,
+        owners: BTreeMap<String, String>,
+        duplicate_bytes: u64,
+    }
+
+    impl DedupIndexOps for AllocDedupIndex {
+        fn new() -> Self {
+            AllocDedupIndex {
+                by_content: BTreeMap::new(),
+                owners: BTreeMap::new(),
+                duplicate_bytes: 0,
+            }
+        }
+
+        fn add_file<T: FileSystemOps>(&mut self, fs: &mut T, path: &str, size: u32) {
+            let hash = match content_hash(fs, path, size) {
+                Some(hash) => hash,
+                None => return,
+            };
+            // Files with the same hash are only merged if they really match;
+            // the ones that do not become owners of their own.
+            let candidates = self.by_content.entry((size, hash)).or_default();
+            let owner = candidates
+                .iter()
+                .find(|&candidate| same_contents(fs, candidate, path, size))
+                .cloned();
+            match owner {
+                Some(owner) => {
+                    self.owners.insert(path.to_owned(), owner);
+                    self.duplicate_bytes += u64::from(size);
+                }
+                None => candidates.push(path.to_owned()),
+            }
+        }
+
+        fn owner_of(&self, path: &str) -> Option<&str> {
+            self.owners.get(path).map(|owner| owner.as_ref())
+        }
+
+        fn duplicate_count(&self) -> u32 {
+            self.owners.len() as u32
+        }
+
+        fn duplicate_bytes(&self) -> u64 {
+            self.duplicate_bytes
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! The operations every layer of a device offers, whether it is a `FakeFat`
+//! itself or something wrapped around one, so that adapters can be written
+//! once against `Device` and serve whichever layer they are handed.
+
+use crate::error::FakeFatError;
+use crate::faker::FakeFat;
+use crate::mbr::MbrWrapped;
+use crate::traits::FileSystemOps;
+
+/// A block device that can be read and written a byte range at a time.
+pub trait Device {
+    /// The length of the device in bytes.
+    fn len(&self) -> u64;
+
+    /// Whether the device has no bytes at all.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The size of a sector, in bytes.
+    fn sector_size(&self) -> usize;
+
+    /// The number of whole sectors on the device.
+    fn sector_count(&self) -> u32 {
+        (self.len() / self.sector_size() as u64) as u32
+    }
+
+    /// Reads up to `buffer.len()` bytes of the device into `buffer`, starting
+    /// `idx` bytes from the head of the device, returning the number of bytes
+    /// read.
+    fn read_at(&mut self, idx: usize, buffer: &mut [u8]) -> usize;
+
+    /// Writes `data` into the device starting `idx` bytes from the head of the
+    /// device, returning the number of bytes written.
+    fn write_at(&mut self, idx: usize, data: &[u8]) -> Result<usize, FakeFatError>;
+
+    /// Applies any writes the device is still batching up.
+    ///
+    /// Does nothing by default.
+    fn flush(&mut self) -> Result<(), FakeFatError> {
+        Ok(())
+    }
+}
+
+impl<D: Device + ?Sized> Device for &mut D {
+    fn len(&self) -> u64 {
+        (**self).len()
+    }
+    fn sector_size(&self) -> usize {
+        (**self).sector_size()
+    }
+    fn sector_count(&self) -> u32 {
+        (**self).sector_count()
+    }
+    fn read_at(&mut self, idx: usize, buffer: &mut [u8]) -> usize {
+        (**self).read_at(idx, buffer)
+    }
+    fn write_at(&mut self, idx: usize, data: &[u8]) -> Result<usize, FakeFatError> {
+        (**self).write_at(idx, data)
+    }
+    fn flush(&mut self) -> Result<(), FakeFatError> {
+        (**self).flush()
+    }
+}
+
+impl<T: FileSystemOps> Device for FakeFat<T> {
+    fn len(&self) -> u64 {
+        FakeFat::len(self)
+    }
+    fn sector_size(&self) -> usize {
+        FakeFat::sector_size(self)
+    }
+    fn sector_count(&self) -> u32 {
+        FakeFat::sector_count(self)
+    }
+    fn read_at(&mut self, idx: usize, buffer: &mut [u8]) -> usize {
+        FakeFat::read_at(self, idx, buffer)
+    }
+    fn write_at(&mut self, idx: usize, data: &[u8]) -> Result<usize, FakeFatError> {
+        FakeFat::write_at(self, idx, data)
+    }
+    fn flush(&mut self) -> Result<(), FakeFatError> {
+        FakeFat::flush(self)
+    }
+}
+
+impl<T: FileSystemOps> Device for MbrWrapped<T> {
+    fn len(&self) -> u64 {
+        MbrWrapped::len(self)
+    }
+    fn sector_size(&self) -> usize {
+        MbrWrapped::sector_size(self)
+    }
+    fn sector_count(&self) -> u32 {
+        MbrWrapped::sector_count(self)
+    }
+    fn read_at(&mut self, idx: usize, buffer: &mut [u8]) -> usize {
+        MbrWrapped::read_at(self, idx, buffer)
+    }
+    fn write_at(&mut self, idx: usize, data: &[u8]) -> Result<usize, FakeFatError> {
+        MbrWrapped::write_at(self, idx, data)
+    }
+    fn flush(&mut self) -> Result<(), FakeFatError> {
+        MbrWrapped::flush(self)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: FileSystemOps> Device for crate::sandbox::Sandbox<T> {
+    fn len(&self) -> u64 {
+        self.image_size() as u64
+    }
+    fn sector_size(&self) -> usize {
+        crate::sandbox::Sandbox::sector_size(self)
+    }
+    fn read_at(&mut self, idx: usize, buffer: &mut [u8]) -> usize {
+        crate::sandbox::Sandbox::read_at(self, idx, buffer)
+    }
+    fn write_at(&mut self, idx: usize, data: &[u8]) -> Result<usize, FakeFatError> {
+        crate::sandbox::Sandbox::write_at(self, idx, data)
+    }
+}
@@ -0,0 +1,183 @@
+//! Rendering a directory means listing it in the backing filesystem, converting
+//! every child into its FAT entries, and looking up each child's first cluster in
+//! the Cluster Mapper. Since hosts read directories 32 bytes (or less) at a time,
+//! the rendered entries are cached with their first clusters already filled in so
+//! that all of that work happens once per directory instead of once per read.
+//!
+//! The names the items of a directory are exposed under are cached alongside,
+//! since looking up the backing item a host refers to needs them just as often.
+//!
+//! Like the Cluster Mapper, there are 2 `DirCacheOps` implementations toggled by
+//! the used feature flags:
+//!
+//! *  In environments without an allocator, a single directory's entries are
+//!    cached in a fixed-size array; directories that do not fit are rendered
+//!    directly from the backing filesystem on every read as before. Names are
+//!    worked out anew on every lookup there anyway, so they are not kept.
+//!
+//! *  In environments with an allocator, every rendered directory is kept in a
+//!    `BTreeMap<String, Vec<Fat32DirectoryEntry>>` keyed by its path, and the
+//!    names of every directory looked up in another one.
+
+use crate::dirent::Fat32DirectoryEntry;
+use crate::dirnames::DirNames;
+
+pub trait DirCacheOps {
+    /// Constructs an empty cache.
+    fn new() -> Self;
+
+    /// Gets the rendered entries of the directory at `path`, or `None` if they
+    /// have not been cached.
+    fn get(&self, path: &str) -> Option<&[Fat32DirectoryEntry]>;
+
+    /// Stores the rendered entries of the directory at `path`, replacing any
+    /// previously cached version.
+    ///
+    /// Returns `false` if the entries could not be cached.
+    fn insert<I: IntoIterator<Item = Fat32DirectoryEntry>>(&mut self, path: &str, entries: I)
+        -> bool;
+
+    /// Gets the exposed names of the items of the directory at `path`, working
+    /// them out with `names` first if they have not been cached.
+    fn names<F: FnOnce() -> DirNames>(&mut self, path: &str, names: F) -> &DirNames;
+
+    /// Drops the cached names of the directory at `path`, after items were
+    /// added to it in the backing filesystem.
+    fn forget_names(&mut self, path: &str);
+
+    /// Drops every cached directory, so that the next read of each one renders
+    /// it from the backing filesystem again.
+    fn clear(&mut self);
+}
+
+#[cfg(not(feature = "alloc"))]
+pub type DirCache = noalloc_dircache::NoallocDirCache;
+#[cfg(not(feature = "alloc"))]
+mod noalloc_dircache {
+    use super::*;
+
+    const DIR_CACHE_CAPACITY: usize = 128;
+    const MAX_PATH_LENGTH: usize = 1024;
+
+    pub struct NoallocDirCache {
+        path: [u8; MAX_PATH_LENGTH],
+        path_len: Option<usize>,
+        len: usize,
+        entries: [Fat32DirectoryEntry; DIR_CACHE_CAPACITY],
+        names: Option<DirNames>,
+    }
+
+    impl DirCacheOps for NoallocDirCache {
+        fn new() -> Self {
+            NoallocDirCache {
+                path: [0; MAX_PATH_LENGTH],
+                path_len: None,
+                len: 0,
+                entries: [Fat32DirectoryEntry::empty(); DIR_CACHE_CAPACITY],
+                names: None,
+            }
+        }
+
+        fn get(&self, path: &str) -> Option<&[Fat32DirectoryEntry]> {
+            match self.path_len {
+                Some(path_len) if &self.path[..path_len] == path.as_bytes() => {
+                    Some(&self.entries[..self.len])
+                }
+                _ => None,
+            }
+        }
+
+        fn insert<I: IntoIterator<Item = Fat32DirectoryEntry>>(
+            &mut self,
+            path: &str,
+            entries: I,
+        ) -> bool {
+            self.clear();
+            let path_bytes = path.as_bytes();
+            if path_bytes.len() > MAX_PATH_LENGTH {
+                return false;
+            }
+            for ent in entries {
+                if self.len >= DIR_CACHE_CAPACITY {
+                    self.len = 0;
+                    return false;
+                }
+                self.entries[self.len] = ent;
+                self.len += 1;
+            }
+            self.path[..path_bytes.len()].copy_from_slice(path_bytes);
+            self.path_len = Some(path_bytes.len());
+            true
+        }
+
+        fn names<F: FnOnce() -> DirNames>(&mut self, _path: &str, names: F) -> &DirNames {
+            self.names.insert(names())
+        }
+
+        fn forget_names(&mut self, _path: &str) {}
+
+        fn clear(&mut self) {
+            self.path_len = None;
+            self.len = 0;
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub type DirCache = alloc_dircache::AllocDirCache;
+#[cfg(feature = "alloc")]
+mod alloc_dircache {
+    use super::*;
+
+    #[cfg(feature = "std")]
+    use std as alloc;
+
+    use alloc::borrow::ToOwned;
+    use alloc::collections::BTreeMap;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    pub struct AllocDirCache {
+        directories: BTreeMap<String, Vec<Fat32DirectoryEntry>>,
+        names: BTreeMap<String, DirNames>,
+    }
+
+    impl DirCacheOps for AllocDirCache {
+        fn new() -> Self {
+            AllocDirCache {
+                directories: BTreeMap::new(),
+                names: BTreeMap::new(),
+            }
+        }
+
+        fn get(&self, path: &str) -> Option<&[Fat32DirectoryEntry]> {
+            self.directories.get(path).map(|v| v.as_slice())
+        }
+
+        fn insert<I: IntoIterator<Item = Fat32DirectoryEntry>>(
+            &mut self,
+            path: &str,
+            entries: I,
+        ) -> bool {
+            self.directories
+                .insert(path.to_owned(), entries.into_iter().collect());
+            true
+        }
+
+        fn names<F: FnOnce() -> DirNames>(&mut self, path: &str, names: F) -> &DirNames {
+            if !self.names.contains_key(path) {
+                self.names.insert(path.to_owned(), names());
+            }
+            &self.names[path]
+        }
+
+        fn forget_names(&mut self, path: &str) {
+            self.names.remove(path);
+        }
+
+        fn clear(&mut self) {
+            self.directories.clear();
+            self.names.clear();
+        }
+    }
+}
@@ -1,12 +1,9 @@
 use crate::datetime::{Date, Time};
+use crate::geometry::DIRENT_SIZE;
 use crate::shortname::ShortName;
 use crate::ReadByte;
 use core::ops::BitAnd;
 
-/// All directory entries, whether a child entry, Long File Name chain link,
-/// or just empty, span exactly 32 bytes.
-pub(crate) const ENTRY_SIZE: usize = 32;
-
 /// An entry in a directory that represents a child item, as opposed to a Long
 /// File Name.
 #[derive(Clone, Debug, Default, Copy)]
@@ -23,7 +20,7 @@ pub struct FileDirEntry {
 }
 
 impl ReadByte for FileDirEntry {
-    const SIZE: usize = ENTRY_SIZE;
+    const SIZE: usize = DIRENT_SIZE;
     fn read_byte(&self, idx: usize) -> u8 {
         match idx {
             b @ 0..=10 => self.name.read_byte(b),
@@ -51,6 +48,75 @@ impl ReadByte for FileDirEntry {
             _ => 0,
         }
     }
+
+    fn read_at(&self, idx: usize, buffer: &mut [u8]) -> usize {
+        copy_encoded(&self.encode(), idx, buffer)
+    }
+}
+
+impl FileDirEntry {
+    /// Encodes the whole entry at once, the same way `read_byte` does a byte at
+    /// a time.
+    fn encode(&self) -> [u8; DIRENT_SIZE] {
+        let mut bytes = [0; DIRENT_SIZE];
+        self.name.read_at(0, &mut bytes[0..11]);
+        bytes[11] = self.attrs.0;
+        bytes[12] = self.name.case_flag();
+        bytes[13] = self.create_time.fat_encode_hi_res();
+        bytes[14..16].copy_from_slice(&self.create_time.fat_encode_simple().to_le_bytes());
+        bytes[16..18].copy_from_slice(&self.create_date.fat_encode().to_le_bytes());
+        bytes[18..20].copy_from_slice(&self.access_date.fat_encode().to_le_bytes());
+        bytes[20..22].copy_from_slice(&((self.first_cluster >> 16) as u16).to_le_bytes());
+        bytes[22..24].copy_from_slice(&self.modify_time.fat_encode_simple().to_le_bytes());
+        bytes[24..26].copy_from_slice(&self.modify_date.fat_encode().to_le_bytes());
+        bytes[26..28].copy_from_slice(&(self.first_cluster as u16).to_le_bytes());
+        bytes[28..32].copy_from_slice(&self.size.to_le_bytes());
+        bytes
+    }
+}
+
+/// Copies the part of the encoded entry `bytes` starting at `idx` into
+/// `buffer`, returning the number of bytes copied.
+fn copy_encoded(bytes: &[u8; DIRENT_SIZE], idx: usize, buffer: &mut [u8]) -> usize {
+    let start = idx.min(DIRENT_SIZE);
+    let len = (DIRENT_SIZE - start).min(buffer.len());
+    buffer[..len].copy_from_slice(&bytes[start..start + len]);
+    len
+}
+
+/// Renders the slots of a directory whose entries are `entries` into `buffer`,
+/// starting `offset` bytes into the directory.
+///
+/// The slot right after the last entry is an empty entry, which ends the
+/// directory, and every slot after that is filled with `fill`.
+pub(crate) fn render_entries<I: IntoIterator<Item = Fat32DirectoryEntry>>(
+    entries: I,
+    offset: usize,
+    fill: u8,
+    buffer: &mut [u8],
+) {
+    let mut entries = entries.into_iter().fuse();
+    let mut slot = offset / DIRENT_SIZE;
+    let mut entry_count = entries.by_ref().take(slot).count();
+    let mut entry_offset = offset % DIRENT_SIZE;
+    let mut rendered = 0;
+    while rendered < buffer.len() {
+        let out = &mut buffer[rendered..];
+        rendered += match entries.next() {
+            Some(entry) => {
+                entry_count += 1;
+                entry.read_at(entry_offset, out)
+            }
+            None if slot == entry_count => Fat32DirectoryEntry::empty().read_at(entry_offset, out),
+            None => {
+                let len = (DIRENT_SIZE - entry_offset).min(out.len());
+                out[..len].fill(fill);
+                len
+            }
+        };
+        slot += 1;
+        entry_offset = 0;
+    }
 }
 
 #[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug, Default, Hash)]
@@ -62,7 +128,6 @@ impl FileAttributes {
     const SYSTEM: u8 = 0x04;
     const VOLUME_ID: u8 = 0x08;
     const DIRECTORY: u8 = 0x10;
-    const ARCHIVE: u8 = 0x20;
 
     pub fn file() -> FileAttributes {
         FileAttributes(0)
@@ -95,30 +160,6 @@ impl FileAttributes {
         FileAttributes(self.0 | FileAttributes::SYSTEM)
     }
 
-    pub fn and_volume_id(self) -> FileAttributes {
-        FileAttributes(self.0 | FileAttributes::VOLUME_ID)
-    }
-
-    pub fn and_directory(self) -> FileAttributes {
-        FileAttributes(self.0 | FileAttributes::DIRECTORY)
-    }
-
-    pub fn and_archive(self) -> FileAttributes {
-        FileAttributes(self.0 | FileAttributes::ARCHIVE)
-    }
-
-    pub fn is_read_only(self) -> bool {
-        self.0 & FileAttributes::READ_ONLY != 0
-    }
-
-    pub fn is_hidden(self) -> bool {
-        self.0 & FileAttributes::HIDDEN != 0
-    }
-
-    pub fn is_system(self) -> bool {
-        self.0 & FileAttributes::SYSTEM != 0
-    }
-
     pub fn is_volume_id(self) -> bool {
         self.0 & FileAttributes::VOLUME_ID != 0
     }
@@ -127,21 +168,9 @@ impl FileAttributes {
         self.0 & FileAttributes::DIRECTORY != 0
     }
 
-    pub fn is_archive(self) -> bool {
-        self.0 & FileAttributes::ARCHIVE != 0
-    }
-
-    pub fn is_volume_label(self) -> bool {
-        !self.is_long_file_name() && !self.is_directory() && self.is_volume_id()
-    }
-
     pub fn is_file(self) -> bool {
         !self.is_directory() && !self.is_volume_id()
     }
-
-    pub fn is_long_file_name(self) -> bool {
-        self.is_read_only() && self.is_system() && self.is_hidden() && self.is_volume_id()
-    }
 }
 
 impl BitAnd<u8> for FileAttributes {
@@ -170,7 +199,9 @@ pub struct LfnDirEntry {
     pub(crate) entry_num: u8,
     pub(crate) attrs: FileAttributes,
     pub(crate) checksum: u8,
-    pub(crate) name_part: [u8; 13],
+    /// The UTF-16 code units of this part of the name, ended by a `0x0000` and
+    /// padded with `0xFFFF` if the name ends before the part does.
+    pub(crate) name_part: [u16; 13],
 }
 
 impl Default for LfnDirEntry {
@@ -179,34 +210,49 @@ impl Default for LfnDirEntry {
             entry_num: 0,
             attrs: FileAttributes::lfn(),
             checksum: 0,
-            name_part: [0; 13],
+            name_part: [0xFFFF; 13],
         }
     }
 }
 
 impl ReadByte for LfnDirEntry {
-    const SIZE: usize = ENTRY_SIZE;
+    const SIZE: usize = DIRENT_SIZE;
     fn read_byte(&self, idx: usize) -> u8 {
         match idx {
             0 => self.entry_num,
-            1 => self.name_part[0],
-            3 => self.name_part[1],
-            5 => self.name_part[2],
-            7 => self.name_part[3],
-            9 => self.name_part[4],
             11 => self.attrs.0,
             12 => 0,
             13 => self.checksum,
-            14 => self.name_part[5],
-            16 => self.name_part[6],
-            18 => self.name_part[7],
-            20 => self.name_part[8],
-            22 => self.name_part[9],
-            24 => self.name_part[10],
-            28 => self.name_part[11],
-            30 => self.name_part[12],
-            _ => 0,
+            _ => Self::NAME_OFFSETS
+                .iter()
+                .position(|&offset| idx == offset || idx == offset + 1)
+                .map_or(0, |unit| {
+                    self.name_part[unit].to_le_bytes()[idx - Self::NAME_OFFSETS[unit]]
+                }),
+        }
+    }
+
+    fn read_at(&self, idx: usize, buffer: &mut [u8]) -> usize {
+        copy_encoded(&self.encode(), idx, buffer)
+    }
+}
+
+impl LfnDirEntry {
+    /// The offsets of the little-endian code units of `name_part` within the
+    /// entry.
+    pub(crate) const NAME_OFFSETS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+    /// Encodes the whole entry at once, the same way `read_byte` does a byte at
+    /// a time.
+    fn encode(&self) -> [u8; DIRENT_SIZE] {
+        let mut bytes = [0; DIRENT_SIZE];
+        bytes[0] = self.entry_num;
+        bytes[11] = self.attrs.0;
+        bytes[13] = self.checksum;
+        for (&offset, &unit) in Self::NAME_OFFSETS.iter().zip(self.name_part.iter()) {
+            bytes[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
         }
+        bytes
     }
 }
 
@@ -215,13 +261,9 @@ impl ReadByte for LfnDirEntry {
 #[derive(Copy, Clone, Default, Eq, PartialEq, Debug)]
 pub struct EmptyDirEntry {}
 impl ReadByte for EmptyDirEntry {
-    const SIZE: usize = ENTRY_SIZE;
-    fn read_byte(&self, idx: usize) -> u8 {
-        match idx {
-            0 => 0x00,
-            11 => 0x40,
-            _ => 0,
-        }
+    const SIZE: usize = DIRENT_SIZE;
+    fn read_byte(&self, _idx: usize) -> u8 {
+        0
     }
 }
 
@@ -264,7 +306,7 @@ impl Default for Fat32DirectoryEntry {
 }
 
 impl ReadByte for Fat32DirectoryEntry {
-    const SIZE : usize = ENTRY_SIZE;
+    const SIZE : usize = DIRENT_SIZE;
     fn read_byte(&self, idx: usize) -> u8 {
         match self {
             Fat32DirectoryEntry::File(f) => f.read_byte(idx), 
@@ -272,6 +314,14 @@ impl ReadByte for Fat32DirectoryEntry {
             Fat32DirectoryEntry::Empty(f) => f.read_byte(idx), 
         }
     }
+
+    fn read_at(&self, idx: usize, buffer: &mut [u8]) -> usize {
+        match self {
+            Fat32DirectoryEntry::File(f) => f.read_at(idx, buffer),
+            Fat32DirectoryEntry::LongFileName(f) => f.read_at(idx, buffer),
+            Fat32DirectoryEntry::Empty(f) => f.read_at(idx, buffer),
+        }
+    }
 }
 
 impl From<FileDirEntry> for Fat32DirectoryEntry {
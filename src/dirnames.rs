@@ -0,0 +1,176 @@
+//! Works out the names the items of a single backing directory are exposed
+//! under on the device.
+//!
+//! Telling an item apart from the others in its directory means comparing its
+//! sanitized name against theirs, so working names out one item at a time
+//! takes a pass over the whole directory for every item. Instead, the names of
+//! every item of a directory are worked out together whenever that can be kept
+//! around, and the directory cache holds on to them between lookups.
+//!
+//! Like the Cluster Mapper, there are 2 `DirNamesOps` implementations toggled
+//! by the used feature flags:
+//!
+//! *  In environments without an allocator, nothing is kept, and every lookup
+//!    works out the single name it asks for by going over the directory again.
+//!
+//! *  In environments with an allocator, every exposed name is worked out in a
+//!    single pass over the directory and kept in a `BTreeMap` keyed by backing
+//!    name.
+
+use crate::sanitize::{ExposedName, NameAction, NamingOptions};
+use crate::traits::DirectoryOps;
+
+pub(crate) trait DirNamesOps {
+    /// Works out the names of the items of `dir`, the directory at the backing
+    /// path `dir_path`.
+    fn new<D: DirectoryOps>(naming: NamingOptions, dir: &D, dir_path: &str) -> Self;
+
+    /// The name the entry `ent` of `dir`, the directory these names were worked
+    /// out for, is exposed under, along with what had to be done to it, if
+    /// anything.
+    ///
+    /// Returns `None` if the item is not exposed at all.
+    fn get<D: DirectoryOps>(
+        &self,
+        dir: &D,
+        dir_path: &str,
+        ent: &D::EntryType,
+    ) -> Option<(ExposedName, Option<NameAction>)>;
+}
+
+#[cfg(not(feature = "alloc"))]
+pub type DirNames = noalloc_dirnames::NoallocDirNames;
+#[cfg(not(feature = "alloc"))]
+mod noalloc_dirnames {
+    use super::*;
+
+    pub struct NoallocDirNames {
+        naming: NamingOptions,
+    }
+
+    impl DirNamesOps for NoallocDirNames {
+        fn new<D: DirectoryOps>(naming: NamingOptions, _dir: &D, _dir_path: &str) -> Self {
+            NoallocDirNames { naming }
+        }
+
+        fn get<D: DirectoryOps>(
+            &self,
+            dir: &D,
+            dir_path: &str,
+            ent: &D::EntryType,
+        ) -> Option<(ExposedName, Option<NameAction>)> {
+            self.naming.expose_in(dir, dir_path, ent)
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub type DirNames = alloc_dirnames::AllocDirNames;
+#[cfg(feature = "alloc")]
+mod alloc_dirnames {
+    use super::*;
+
+    use crate::access::{Access, Operation};
+    use crate::sanitize::with_number;
+    use crate::traits::{DirEntryOps, DirectoryListing};
+
+    #[cfg(feature = "std")]
+    use std as alloc;
+
+    use alloc::borrow::ToOwned;
+    use alloc::collections::{BTreeMap, BTreeSet};
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    pub struct AllocDirNames {
+        names: BTreeMap<String, (String, Option<NameAction>)>,
+    }
+
+    impl DirNamesOps for AllocDirNames {
+        fn new<D: DirectoryOps>(naming: NamingOptions, dir: &D, dir_path: &str) -> Self {
+            let mut names = BTreeMap::new();
+            if naming.access(Operation::Read, dir_path) != Access::Allow {
+                return AllocDirNames { names };
+            }
+
+            // Every item, with its sanitized name as FAT compares it and whether
+            // sanitizing changed it, if the policy does not reject it, and
+            // whether the item is exposed at all.
+            let items: Vec<_> = dir
+                .listing()
+                .map(|ent| {
+                    let name = ent.name().as_ref().to_owned();
+                    let sanitized = naming
+                        .sanitize(&name)
+                        .map(|(chars, action)| (folded(chars), action.is_some()));
+                    let visible = naming.access_in(Operation::Read, dir_path, &name)
+                        != Access::Hide
+                        && !naming.omits(&ent.meta());
+                    (name, sanitized, visible)
+                })
+                .collect();
+
+            // Numbered names can not clash with the name of any item, exposed or
+            // not.
+            let taken: BTreeSet<&str> = items
+                .iter()
+                .filter_map(|(_, sanitized, _)| sanitized.as_ref())
+                .map(|(folded, _)| folded.as_str())
+                .collect();
+            let mut clashes: BTreeMap<&str, Vec<(bool, &str)>> = BTreeMap::new();
+            for (name, sanitized, visible) in &items {
+                if let (Some((folded, changed)), true) = (sanitized, visible) {
+                    clashes
+                        .entry(folded.as_str())
+                        .or_default()
+                        .push((*changed, name.as_str()));
+                }
+            }
+
+            // Within each set of clashing items, the one that goes first keeps
+            // its name and the rest get the free numbers in order.
+            for clash in clashes.values_mut() {
+                clash.sort_unstable();
+                let mut number = 1;
+                for (rank, &(_, name)) in clash.iter().enumerate() {
+                    let (base, action) = match naming.expose(name) {
+                        Some(exposed) => exposed,
+                        None => continue,
+                    };
+                    if rank == 0 {
+                        names.insert(name.to_owned(), (base.as_ref().to_owned(), action));
+                        continue;
+                    }
+                    let numbered = loop {
+                        number += 1;
+                        match with_number(base.as_ref(), number) {
+                            Some(candidate)
+                                if taken.contains(folded(candidate.as_ref().chars()).as_str()) => {}
+                            candidate => break candidate,
+                        }
+                    };
+                    if let Some(numbered) = numbered {
+                        let exposed = (numbered.as_ref().to_owned(), Some(NameAction::Deduplicated));
+                        names.insert(name.to_owned(), exposed);
+                    }
+                }
+            }
+            AllocDirNames { names }
+        }
+
+        fn get<D: DirectoryOps>(
+            &self,
+            _dir: &D,
+            _dir_path: &str,
+            ent: &D::EntryType,
+        ) -> Option<(ExposedName, Option<NameAction>)> {
+            let (exposed, action) = self.names.get(ent.name().as_ref())?;
+            Some((ExposedName::new(exposed)?, *action))
+        }
+    }
+
+    /// The characters of a name the way FAT compares them, ignoring case.
+    fn folded<I: Iterator<Item = char>>(chars: I) -> String {
+        chars.flat_map(char::to_lowercase).collect()
+    }
+}
@@ -0,0 +1,357 @@
+//! Tracks a version number for every directory, which `FakeFat::refresh` bumps
+//! whenever the directory's rendered contents change.
+//!
+//! Versions are keyed by each directory's first cluster, which is also how
+//! hosts and sync tools identify directories on the device. With
+//! `FakeFatBuilder::relocate_changed_files`, the files are tracked in the same
+//! table, keyed by their first cluster, as generations. Like the Cluster
+//! Mapper, there are 2 `DirVersionOps` implementations toggled by the used
+//! feature flags:
+//!
+//! *  In environments without an allocator, versions are kept in a fixed-size
+//!    array sorted by cluster; directories past its capacity are not tracked.
+//!
+//! *  In environments with an allocator, versions are kept in a
+//!    `BTreeMap<u32, DirVersion>`.
+
+use crate::clustermapping::ClusterMapperOps;
+use crate::datetime::{fat_timestamp_key, next_fat_timestamp, Date, Time};
+use crate::dedup::{DedupIndex, DedupIndexOps};
+use crate::dircache::DirCacheOps;
+use crate::dirent::Fat32DirectoryEntry;
+use crate::dirnames::{DirNames, DirNamesOps};
+use crate::error::FakeFatError;
+use crate::faker::{fix_first_entry, mark_read_only, traverse, DirectoryNewtype, FakeFat};
+use crate::geometry::DIRENT_SIZE;
+use crate::pathbuffer::PathBuff;
+use crate::preload::FileCacheOps;
+use crate::traits::{DirEntryOps, DirectoryListing, FileSystemLookup, FileSystemOps};
+use crate::ReadByte;
+
+const FNV_OFFSET_BASIS: u32 = 0x811C_9DC5;
+const FNV_PRIME: u32 = 0x0100_0193;
+
+/// The version bookkeeping for a single directory.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DirVersion {
+    /// A hash of the directory's rendered entries as of the last refresh.
+    pub fingerprint: u32,
+    /// The number of refreshes that found the directory's contents changed.
+    pub version: u32,
+    /// The modification timestamp reported for the directory since it last
+    /// changed, if it has ever changed.
+    pub modified: Option<(Date, Time)>,
+}
+
+pub trait DirVersionOps {
+    /// Constructs a table without any versions.
+    fn new() -> Self;
+
+    /// Gets the version bookkeeping for the directory starting at `cluster`.
+    fn get(&self, cluster: u32) -> Option<&DirVersion>;
+
+    /// Stores the version bookkeeping for the directory starting at `cluster`.
+    ///
+    /// Returns `false` if the version could not be stored.
+    fn insert(&mut self, cluster: u32, version: DirVersion) -> bool;
+
+    /// Forgets the version bookkeeping for the chain starting at `cluster`.
+    fn remove(&mut self, cluster: u32);
+}
+
+#[cfg(not(feature = "alloc"))]
+pub type DirVersions = noalloc_dirversion::NoallocDirVersions;
+#[cfg(not(feature = "alloc"))]
+mod noalloc_dirversion {
+    use super::*;
+
+    const DIR_VERSION_CAPACITY: usize = 256;
+
+    pub struct NoallocDirVersions {
+        len: usize,
+        entries: [(u32, DirVersion); DIR_VERSION_CAPACITY],
+    }
+
+    impl DirVersionOps for NoallocDirVersions {
+        fn new() -> Self {
+            NoallocDirVersions {
+                len: 0,
+                entries: [(0, DirVersion::default()); DIR_VERSION_CAPACITY],
+            }
+        }
+
+        fn get(&self, cluster: u32) -> Option<&DirVersion> {
+            let entries = &self.entries[..self.len];
+            entries
+                .binary_search_by_key(&cluster, |(c, _)| *c)
+                .ok()
+                .map(|idx| &entries[idx].1)
+        }
+
+        fn insert(&mut self, cluster: u32, version: DirVersion) -> bool {
+            match self.entries[..self.len].binary_search_by_key(&cluster, |(c, _)| *c) {
+                Ok(idx) => {
+                    self.entries[idx].1 = version;
+                    true
+                }
+                Err(_) if self.len >= DIR_VERSION_CAPACITY => false,
+                Err(idx) => {
+                    self.entries.copy_within(idx..self.len, idx + 1);
+                    self.entries[idx] = (cluster, version);
+                    self.len += 1;
+                    true
+                }
+            }
+        }
+
+        fn remove(&mut self, cluster: u32) {
+            if let Ok(idx) = self.entries[..self.len].binary_search_by_key(&cluster, |(c, _)| *c) {
+                self.entries.copy_within(idx + 1..self.len, idx);
+                self.len -= 1;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub type DirVersions = alloc_dirversion::AllocDirVersions;
+#[cfg(feature = "alloc")]
+mod alloc_dirversion {
+    use super::*;
+
+    #[cfg(feature = "std")]
+    use std as alloc;
+
+    use alloc::collections::BTreeMap;
+
+    pub struct AllocDirVersions {
+        versions: BTreeMap<u32, DirVersion>,
+    }
+
+    impl DirVersionOps for AllocDirVersions {
+        fn new() -> Self {
+            AllocDirVersions {
+                versions: BTreeMap::new(),
+            }
+        }
+
+        fn get(&self, cluster: u32) -> Option<&DirVersion> {
+            self.versions.get(&cluster)
+        }
+
+        fn insert(&mut self, cluster: u32, version: DirVersion) -> bool {
+            self.versions.insert(cluster, version);
+            true
+        }
+
+        fn remove(&mut self, cluster: u32) {
+            self.versions.remove(&cluster);
+        }
+    }
+}
+
+impl<T: FileSystemOps> FakeFat<T> {
+    /// Re-reads the backing filesystem, picking up any items that were added or
+    /// grew since the device was constructed or last refreshed, and bumps the
+    /// version of every directory whose contents changed.
+    ///
+    /// Changed directories also get a newer modification timestamp in their
+    /// parent's entry for them, so that hosts and sync tools that only compare
+    /// timestamps notice the change too.
+    ///
+    /// Returns the number of directories whose version was bumped, or fails
+    /// like `flush` if the writes still being batched up can not be applied
+    /// first.
+    pub fn refresh(&mut self) -> Result<usize, FakeFatError> {
+        self.flush()?;
+        let root = self.layout.prefix.clone();
+        let allocated = self.layout.mapper.allocated_count();
+        let max_cluster = traverse(
+            &mut self.layout.mapper,
+            &root,
+            &mut self.fs,
+            &self.layout.bpb,
+            usize::from(self.layout.bpb.has_volume_label()),
+            self.layout.naming,
+            &DedupIndex::new(),
+        );
+        let grown = self.layout.mapper.allocated_count() - allocated;
+        if grown > 0 {
+            self.fsinfo.allocated(grown as u32, max_cluster);
+        }
+        self.layout.max_cluster = self.layout.max_cluster.max(max_cluster);
+        self.dir_cache.clear();
+        self.file_cache.clear();
+        let bumped = self.update_dir_versions();
+        self.debug_validate("refresh");
+        Ok(bumped)
+    }
+
+    /// Gets the current version of the directory at `path` on the device, where
+    /// `"/"` is the device's root directory.
+    ///
+    /// Versions start at 0 when the device is constructed and are only bumped
+    /// by `refresh`. Returns `None` if there is no such directory or if it is
+    /// not being tracked.
+    pub fn dir_version(&mut self, path: impl AsRef<str>) -> Option<u32> {
+        let path = path.as_ref();
+        let mut backing_path = self.layout.prefix.clone();
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            backing_path.add_subdir(component);
+        }
+        let cluster = self
+            .layout
+            .mapper
+            .get_chain_head_for_path(backing_path.to_str())?;
+        self.dir_versions.get(cluster).map(|v| v.version)
+    }
+
+    /// Fingerprints every directory on the device, bumping the versions of the
+    /// ones that changed since the last call.
+    ///
+    /// Returns the number of directories whose version was bumped.
+    pub(crate) fn update_dir_versions(&mut self) -> usize {
+        let root = self.layout.prefix.clone();
+        self.update_dir_version(&root)
+    }
+
+    /// Fingerprints the directory at the backing path `path` and every
+    /// directory below it, bumping the versions of the ones that changed.
+    pub(crate) fn update_dir_version(&mut self, path: &PathBuff) -> usize {
+        let mut changed = 0;
+        let subdirs = match self.fs.find_dir(path.to_str()) {
+            Some(dir) => dir.listing().filter(|ent| ent.meta().is_directory),
+            None => return 0,
+        };
+        for dir in subdirs {
+            let subpath = {
+                let mut r = path.clone();
+                r.add_subdir(dir.name().as_ref());
+                r
+            };
+            changed += self.update_dir_version(&subpath);
+        }
+        // Relocated files get a new first cluster in this directory's entries,
+        // which the fingerprint below picks up.
+        if self.relocate_changed_files {
+            self.update_file_generations(path);
+        }
+
+        let cluster = match self.layout.mapper.get_chain_head_for_path(path.to_str()) {
+            Some(cluster) => cluster,
+            None => return changed,
+        };
+        let (fingerprint, newest_child) = match self.fs.find_dir(path.to_str()) {
+            Some(dir) => self.fingerprint_directory(dir, path.to_str()),
+            None => return changed,
+        };
+        let previous = match self.dir_versions.get(cluster) {
+            Some(previous) => *previous,
+            None => {
+                let initial = DirVersion {
+                    fingerprint,
+                    ..DirVersion::default()
+                };
+                self.dir_versions.insert(cluster, initial);
+                return changed;
+            }
+        };
+        if previous.fingerprint == fingerprint {
+            return changed;
+        }
+
+        let own_modified = self
+            .fs
+            .find_metadata(path.to_str())
+            .map(|meta| (meta.modify_date, meta.modify_time));
+        let candidate = [own_modified, newest_child]
+            .iter()
+            .flatten()
+            .copied()
+            .max_by_key(|&(date, time)| fat_timestamp_key(date, time));
+        let modified = match (candidate, previous.modified) {
+            (Some(cur), Some(prev))
+                if fat_timestamp_key(cur.0, cur.1) > fat_timestamp_key(prev.0, prev.1) =>
+            {
+                cur
+            }
+            (_, Some((date, time))) => next_fat_timestamp(date, time),
+            (Some(cur), None) => cur,
+            (None, None) => next_fat_timestamp(Date::default(), Time::default()),
+        };
+        let updated = DirVersion {
+            fingerprint,
+            version: previous.version.wrapping_add(1),
+            modified: Some(modified),
+        };
+        if self.dir_versions.insert(cluster, updated) {
+            changed += 1;
+        }
+        changed
+    }
+
+    /// Hashes the rendered entries of `directory`, returning the hash and the
+    /// newest modification timestamp among its children.
+    fn fingerprint_directory(
+        &self,
+        directory: T::DirectoryType,
+        path: &str,
+    ) -> (u32, Option<(Date, Time)>) {
+        let naming = self.layout.naming;
+        let names = DirNames::new(naming, &directory, path);
+        let entries = DirectoryNewtype::from(directory)
+            .fat_entries(
+                naming,
+                &names,
+                &self.layout.mapper,
+                path,
+                self.label_entry_for(path),
+            )
+            .map(fix_first_entry(&self.layout.mapper, path))
+            .map(|(fixed, _)| fixed)
+            .map(mark_read_only(self.write_protected));
+        let mut hash = FNV_OFFSET_BASIS;
+        let mut newest: Option<(Date, Time)> = None;
+        for entry in entries {
+            for idx in 0..DIRENT_SIZE {
+                hash ^= u32::from(entry.read_byte(idx));
+                hash = hash.wrapping_mul(FNV_PRIME);
+            }
+            if let Fat32DirectoryEntry::File(file_ent) = entry {
+                let cur = (file_ent.modify_date, file_ent.modify_time);
+                let is_newer = newest.is_none_or(|(date, time)| {
+                    fat_timestamp_key(cur.0, cur.1) > fat_timestamp_key(date, time)
+                });
+                if is_newer {
+                    newest = Some(cur);
+                }
+            }
+        }
+        (hash, newest)
+    }
+}
+
+/// Replaces the modification timestamp of every subdirectory entry, and of
+/// every file entry `FakeFatBuilder::relocate_changed_files` tracks, with the
+/// one recorded when its version was last bumped, if that one is newer.
+pub(crate) fn apply_dir_versions(
+    versions: &DirVersions,
+) -> impl Fn(Fat32DirectoryEntry) -> Fat32DirectoryEntry + '_ {
+    move |entry| match entry {
+        Fat32DirectoryEntry::File(mut file_ent) => {
+            let bumped = versions
+                .get(file_ent.first_cluster)
+                .and_then(|v| v.modified);
+            if let Some((date, time)) = bumped {
+                if fat_timestamp_key(date, time)
+                    > fat_timestamp_key(file_ent.modify_date, file_ent.modify_time)
+                {
+                    file_ent.modify_date = date;
+                    file_ent.modify_time = time;
+                }
+            }
+            Fat32DirectoryEntry::File(file_ent)
+        }
+        other => other,
+    }
+}
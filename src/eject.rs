@@ -0,0 +1,68 @@
+//! Hosts tell a removable disk that they are done with it by ejecting its
+//! medium, which for SCSI is a START STOP UNIT command with the LOEJ bit set
+//! and the START bit clear. Adapters that decode that command call
+//! `FakeFat::eject`, which applies everything the host left batched up and
+//! puts the device in the not-ready state, where adapters report that no
+//! medium is present until it is loaded again.
+//!
+//! The `SessionEvent::Ejected` sent to the `SessionListener` tells the device
+//! when it is safe to power down the backing store. Adapters only know the
+//! backing filesystem through `FileSystemOps`, so when it is writable, it is
+//! up to the device to call `FakeFat::write_back` if the event reports the
+//! device as dirty, or to eject through `FakeFat::eject_and_write_back`
+//! itself.
+
+use crate::error::FakeFatError;
+use crate::faker::FakeFat;
+use crate::session::SessionEvent;
+use crate::traits::{FileSystemOps, FileSystemOpsMut};
+use crate::writeback::WriteBackError;
+
+impl<T: FileSystemOps> FakeFat<T> {
+    /// Marks that the host ejected the medium, applying any writes it left
+    /// batched up and reporting `SessionEvent::Ejected`.
+    ///
+    /// Ejecting a device that is already ejected does nothing. The device
+    /// itself can still be read and written, so that it can be inspected or
+    /// written back, but adapters refuse to access it on the host's behalf
+    /// until `load` is called.
+    ///
+    /// Fails like `FakeFat::flush` if the batched writes can not be applied, in
+    /// which case the medium is not ejected.
+    pub fn eject(&mut self) -> Result<(), FakeFatError> {
+        if self.ejected {
+            return Ok(());
+        }
+        self.flush()?;
+        self.ejected = true;
+        let dirty = self.is_dirty();
+        self.sessions.emit(SessionEvent::Ejected { dirty });
+        Ok(())
+    }
+
+    /// Marks that the medium is back, so that adapters serve the host again.
+    pub fn load(&mut self) {
+        self.ejected = false;
+    }
+
+    /// Whether the host ejected the medium and it was not loaded again since.
+    pub fn is_ejected(&self) -> bool {
+        self.ejected
+    }
+}
+
+impl<T: FileSystemOpsMut> FakeFat<T> {
+    /// Applies everything the host wrote to the backing filesystem with
+    /// `write_back` and then ejects the medium.
+    ///
+    /// The medium is ejected even if applying the host's writes fails, in
+    /// which case `SessionEvent::Ejected` reports the device as still dirty.
+    /// The only exception is `WriteBackError::ChangeSetFull`, where the writes
+    /// the host left batched up can not be applied, which keeps the medium in.
+    pub fn eject_and_write_back(&mut self) -> Result<(), WriteBackError<T::Error>> {
+        let applied = self.write_back();
+        // Ejecting only fails the same way `write_back` already reported.
+        let _ = self.eject();
+        applied
+    }
+}
@@ -0,0 +1,158 @@
+//! `embedded-io` implementations, so that `FakeFat` can be handed directly to
+//! `no_std` I/O stacks the same way the `std::io` implementations allow on
+//! hosted platforms.
+
+use crate::error::FakeFatError;
+use crate::faker::{FakeFat, Region};
+use crate::traits::FileSystemOps;
+use core::fmt;
+use embedded_io::{ErrorKind, ErrorType, Read, Seek, SeekFrom, Write};
+
+/// The ways an `embedded-io` access to a `FakeFat` can fail.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum IoError {
+    /// The seek would move the cursor before the start of the device.
+    InvalidSeek {
+        /// The cursor position before the seek.
+        from: u64,
+        /// The requested offset.
+        offset: i64,
+    },
+    /// The write targets the FAT preamble.
+    ReadOnly {
+        /// The device offset of the write.
+        offset: u64,
+        /// The section of the device the write targets.
+        region: Region,
+    },
+    /// The device is write protected.
+    WriteProtected {
+        /// The device offset of the write.
+        offset: u64,
+    },
+    /// The rate limits do not allow reading anything yet, so the read should be
+    /// tried again later.
+    Busy {
+        /// The device offset of the read.
+        offset: u64,
+    },
+    /// The change set has no room left to keep the write, which only happens
+    /// without the `alloc` feature.
+    ChangeSetFull {
+        /// The device offset of the first byte that could not be kept.
+        offset: u64,
+    },
+    /// The write starts at or past the end of the device.
+    EndOfDevice {
+        /// The device offset of the write.
+        offset: u64,
+    },
+}
+
+impl fmt::Display for IoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IoError::InvalidSeek { from, offset } => {
+                write!(f, "cannot seek by {} from offset {}", offset, from)
+            }
+            IoError::ReadOnly { offset, region } => {
+                write!(f, "offset {} in the {} is read-only", offset, region)
+            }
+            IoError::WriteProtected { offset } => write!(
+                f,
+                "cannot write offset {} of a write protected device",
+                offset
+            ),
+            IoError::Busy { offset } => {
+                write!(f, "offset {} is held back by the rate limits", offset)
+            }
+            IoError::ChangeSetFull { offset } => {
+                write!(f, "no room left to keep the write to offset {}", offset)
+            }
+            IoError::EndOfDevice { offset } => {
+                write!(f, "offset {} is past the end of the device", offset)
+            }
+        }
+    }
+}
+
+impl embedded_io::Error for IoError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            IoError::InvalidSeek { .. } => ErrorKind::InvalidInput,
+            IoError::ReadOnly { .. } | IoError::WriteProtected { .. } => {
+                ErrorKind::PermissionDenied
+            }
+            IoError::Busy { .. } => ErrorKind::Other,
+            IoError::ChangeSetFull { .. } => ErrorKind::OutOfMemory,
+            IoError::EndOfDevice { .. } => ErrorKind::WriteZero,
+        }
+    }
+}
+
+impl<T: FileSystemOps> ErrorType for FakeFat<T> {
+    type Error = IoError;
+}
+
+impl<T: FileSystemOps> Read for FakeFat<T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        let buf = self.stream_buffer(self.read_idx as u64, buf);
+        let read = self.read_at(self.read_idx, buf);
+        // Reading nothing would look like the end of the device.
+        if read == 0 && !buf.is_empty() {
+            return Err(IoError::Busy {
+                offset: self.read_idx as u64,
+            });
+        }
+        self.read_idx += read;
+        Ok(read)
+    }
+}
+
+impl<T: FileSystemOps> Write for FakeFat<T> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+        match self.write_at(self.read_idx, buf) {
+            Ok(written) => {
+                self.read_idx += written;
+                Ok(written)
+            }
+            Err(FakeFatError::ReadOnly { offset, region }) => {
+                Err(IoError::ReadOnly { offset, region })
+            }
+            Err(FakeFatError::ChangeSetFull { offset }) => Err(IoError::ChangeSetFull { offset }),
+            Err(FakeFatError::OutOfRange { .. }) if buf.is_empty() => Ok(0),
+            // `embedded-io` does not allow writing nothing from a non-empty
+            // buffer, so a full device has to be reported as an error.
+            Err(FakeFatError::OutOfRange { offset }) => Err(IoError::EndOfDevice { offset }),
+            Err(_) => Err(IoError::WriteProtected {
+                offset: self.read_idx as u64,
+            }),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        match FakeFat::flush(self) {
+            Err(FakeFatError::ChangeSetFull { offset }) => Err(IoError::ChangeSetFull { offset }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<T: FileSystemOps> Seek for FakeFat<T> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, IoError> {
+        let (from, offset) = match pos {
+            SeekFrom::Start(abs) => {
+                self.read_idx = abs as usize;
+                return Ok(abs);
+            }
+            SeekFrom::End(off) => (self.len(), off),
+            SeekFrom::Current(off) => (self.read_idx as u64, off),
+        };
+        let new_idx = from
+            .checked_add_signed(offset)
+            .ok_or(IoError::InvalidSeek { from, offset })?;
+        self.read_idx = new_idx as usize;
+        Ok(new_idx)
+    }
+}
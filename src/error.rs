@@ -0,0 +1,88 @@
+//! The errors the device's own API reports, so that adapters running in
+//! interrupt handlers or other places that cannot unwind never have to panic.
+//!
+//! The more specific errors of the sector, `embedded-io` and write-back APIs
+//! convert into `FakeFatError`, so code juggling several of them can use `?`
+//! throughout.
+
+use crate::faker::Region;
+use crate::sector::SectorError;
+use crate::writeback::WriteBackError;
+
+use core::fmt;
+
+/// The reasons an operation on a fake device can fail.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FakeFatError {
+    /// The write targets a section of the device that cannot be written to,
+    /// like the FAT preamble.
+    ReadOnly {
+        /// The device offset of the write.
+        offset: u64,
+        /// The section of the device the write targets.
+        region: Region,
+    },
+    /// The device is write protected.
+    WriteProtected {
+        /// The device offset of the write.
+        offset: u64,
+    },
+    /// The write starts past the end of the device.
+    OutOfRange {
+        /// The device offset of the write.
+        offset: u64,
+    },
+    /// The change set has no room left for another changed cluster, which
+    /// only happens without the `alloc` feature. Anything written before
+    /// `offset` was kept.
+    ChangeSetFull {
+        /// The device offset of the first byte that could not be kept.
+        offset: u64,
+    },
+    /// There is no Logical Unit with the requested number.
+    NoSuchUnit {
+        /// The requested Logical Unit Number.
+        lun: u8,
+    },
+    /// A sector-addressed access was rejected.
+    Sector(SectorError),
+    /// Applying host writes to the backing filesystem failed.
+    WriteBack(WriteBackError),
+}
+
+impl fmt::Display for FakeFatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FakeFatError::ReadOnly { offset, region } => {
+                write!(f, "offset {} in the {} is read-only", offset, region)
+            }
+            FakeFatError::WriteProtected { offset } => write!(
+                f,
+                "cannot write offset {} of a write protected device",
+                offset
+            ),
+            FakeFatError::OutOfRange { offset } => {
+                write!(f, "offset {} is past the end of the device", offset)
+            }
+            FakeFatError::ChangeSetFull { offset } => {
+                write!(f, "no room left to keep the write to offset {}", offset)
+            }
+            FakeFatError::NoSuchUnit { lun } => write!(f, "there is no LUN {}", lun),
+            FakeFatError::Sector(err) => err.fmt(f),
+            FakeFatError::WriteBack(err) => err.fmt(f),
+        }
+    }
+}
+
+impl From<SectorError> for FakeFatError {
+    fn from(err: SectorError) -> Self {
+        FakeFatError::Sector(err)
+    }
+}
+
+impl<E> From<WriteBackError<E>> for FakeFatError {
+    fn from(err: WriteBackError<E>) -> Self {
+        FakeFatError::WriteBack(err.without_cause())
+    }
+}
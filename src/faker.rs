@@ -1,58 +1,150 @@
-use crate::bpb::{default_sectors_per_fat, BiosParameterBlock};
+use crate::bpb::BiosParameterBlock;
+use crate::builder::FakeFatBuilder;
 use crate::changeset::{ChangeSet, ChangeSetOps};
 use crate::clustermapping::{ClusterMapper, ClusterMapperOps};
-use crate::dirent::{FileDirEntry, LfnDirEntry, ENTRY_SIZE};
-use crate::fat::{idx_to_cluster, FatEntryValue};
+use crate::datetime::{Date, Time};
+use crate::dedup::{DedupIndex, DedupIndexOps};
+use crate::dircache::{DirCache, DirCacheOps};
+use crate::dirent::{render_entries, FileAttributes, FileDirEntry, LfnDirEntry};
+use crate::dirnames::{DirNames, DirNamesOps};
+use crate::dirversion::{apply_dir_versions, DirVersions};
+use crate::error::FakeFatError;
+use crate::fat::{entries_at, entry_byte, patch_entry, reserved_entry, FatEntryValue, FatType};
 use crate::fsinfo::FsInfoSector;
-use crate::longname::{construct_name_entries, lfn_count_for_name};
+use crate::geometry::{
+    chain_offset, cluster_to_offset, dirents_per_cluster, fat_table_offset, offset_to_cluster,
+    DIRENT_SIZE, FIRST_DATA_CLUSTER, ROOT_REGION_CLUSTER,
+};
+use crate::hostdetect::{HostDetector, HostGuess};
+use crate::layout::VolumeLayout;
+use crate::longname::{construct_name_entries, lfn_count};
 use crate::pathbuffer::PathBuff;
+use crate::preload::{FileCache, FileCacheOps};
+use crate::ratelimit::RateLimiter;
+use crate::readaudit::ReadAudit;
+use crate::sanitize::NamingOptions;
+use crate::scan::DirWalk;
+use crate::session::Sessions;
 use crate::shortname::ShortName;
-use crate::traits::{DirEntryOps, DirectoryOps, FileMetadata, FileOps, FileSystemOps};
+use crate::shortnametable::DirShortNames;
+use crate::traits::{
+    DirEntryOps, DirectoryListing, DirectoryOps, FileMetadata, FileOps, FileSystemLookup,
+    FileSystemOps,
+};
+use crate::writebuffer::WriteBuffer;
 use crate::ReadByte;
 
-use core::num::Wrapping;
+use core::fmt;
+use core::mem::MaybeUninit;
+
+/// The number of bytes copied into the change set at a time when snapshotting
+/// a cluster.
+const SNAPSHOT_CHUNK_SIZE: usize = 512;
+
+/// The size of the intermediate buffer `read_at_uninit` reads through.
+const UNINIT_READ_CHUNK_SIZE: usize = 512;
 
 /// Wraps any filesystem and exposes it as if it was a normal FAT32
 /// device that can be either read byte-by-byte or via the normal `Read` and `Seek`
 /// traits without actually touching the backing filesystem itself.
+///
+/// Where everything is on the device is decided by its `VolumeLayout`, while
+/// the contents are read from the backing filesystem as the host asks for them.
 pub struct FakeFat<T: FileSystemOps> {
-    bpb: BiosParameterBlock,
-    fsinfo: FsInfoSector,
-    fs: T,
-    mapper: ClusterMapper,
-    changes: ChangeSet,
+    pub(crate) layout: VolumeLayout,
+    pub(crate) fsinfo: FsInfoSector,
+    pub(crate) fs: T,
+    pub(crate) changes: ChangeSet,
+    pub(crate) host_detector: Option<HostDetector>,
+    pub(crate) read_audit: Option<ReadAudit>,
+    pub(crate) rate_limiter: Option<RateLimiter>,
+    pub(crate) write_buffer: WriteBuffer,
+    pub(crate) dir_cache: DirCache,
+    pub(crate) file_cache: FileCache,
+    pub(crate) dir_versions: DirVersions,
 
     #[allow(unused)]
-    read_idx: usize,
-    #[allow(unused)]
-    prefix: PathBuff,
+    pub(crate) read_idx: usize,
+    pub(crate) write_protected: bool,
+    pub(crate) append_only: bool,
+    pub(crate) erase_unit_size: u32,
+    pub(crate) out_of_range_reads: OutOfRangeReads,
+    pub(crate) backend_error: Option<T::Error>,
+    pub(crate) sessions: Sessions,
+    pub(crate) created: Option<(Date, Time)>,
+    pub(crate) identity_seed: Option<u64>,
+    pub(crate) dir_slack_fill: u8,
+    pub(crate) relocate_changed_files: bool,
+    pub(crate) ejected: bool,
 }
 
-use core::ops::Index;
+use core::ops::{Index, Range};
 
-fn traverse<T: FileSystemOps>(
+/// How far past the last cluster of a directory `traverse` starts placing the
+/// files in it, which leaves a gap for the directories placed after it.
+pub(crate) const FILE_PLACEMENT_OFFSET: u32 = 12;
+
+/// Allocates clusters to `cur` and everything below it that does not have
+/// enough yet, returning the last cluster allocated.
+///
+/// `leading_entries` is the number of entries `cur` starts with on top of its
+/// children, like the volume label entry of the root directory.
+pub(crate) fn traverse<T: FileSystemOps>(
     mapper: &mut ClusterMapper,
     cur: &PathBuff,
     fs: &mut T,
-    bytes_per_cluster: usize,
+    bpb: &BiosParameterBlock,
+    leading_entries: usize,
+    naming: NamingOptions,
+    dedup: &DedupIndex,
 ) -> u32 {
-    let entry_count: usize = fs
-        .get_dir(cur.to_str())
-        .unwrap()
-        .entries()
-        .into_iter()
-        .map(|ent| 1 + lfn_count_for_name(ent.name().as_ref()))
-        .sum();
-    let needed_bytes = entry_count.max(1) * ENTRY_SIZE;
-    let needed_clusters_raw = needed_bytes / bytes_per_cluster
-        + if needed_bytes % bytes_per_cluster == 0 {
-            0
-        } else {
-            1
-        };
-    let needed_clusters = needed_clusters_raw
-        .saturating_sub(mapper.get_chain_for_path(cur.to_str()).into_iter().count());
-    let mut cur_cluster = 0;
+    let mut max_cluster = bpb.allocation_start();
+    let mut visit = |fs: &mut T, path: &PathBuff, is_root: bool| {
+        let leading = if is_root { leading_entries } else { 0 };
+        let placed = place_dir(mapper, path, fs, bpb, leading, naming, dedup);
+        max_cluster = max_cluster.max(placed.unwrap_or(0));
+        placed.is_some()
+    };
+    let mut walk = DirWalk::new(cur);
+    while walk.visit(fs, naming, &mut visit) {}
+    max_cluster
+}
+
+/// Allocates clusters to the directory `cur` and the files directly in it that
+/// do not have enough yet, returning the last cluster allocated, or `None` if
+/// the directory is left off the device along with everything in it.
+pub(crate) fn place_dir<T: FileSystemOps>(
+    mapper: &mut ClusterMapper,
+    cur: &PathBuff,
+    fs: &mut T,
+    bpb: &BiosParameterBlock,
+    leading_entries: usize,
+    naming: NamingOptions,
+    dedup: &DedupIndex,
+) -> Option<u32> {
+    let bytes_per_cluster = bpb.bytes_per_cluster() as usize;
+    let first_cluster = bpb.allocation_start();
+    // Directories removed since they were listed are left off the device.
+    let dir = fs.find_dir(cur.to_str())?;
+    let names = DirNames::new(naming, &dir, cur.to_str());
+    let entry_count = leading_entries + directory_entry_count(&dir, cur.to_str(), &names, naming);
+    let needed_bytes = entry_count.max(1) * DIRENT_SIZE;
+    let needed_clusters_raw = needed_bytes.div_ceil(bytes_per_cluster);
+    // The fixed root directory region of FAT12 and FAT16 cannot grow.
+    let fixed_size = mapper
+        .get_chain_head_for_path(cur.to_str())
+        .is_some_and(|head| head >= ROOT_REGION_CLUSTER);
+    let needed_clusters = if fixed_size {
+        0
+    } else {
+        needed_clusters_raw.saturating_sub(mapper.chain_len(cur.to_str()))
+    };
+    // Directories the mapper has no room left for are left off the device,
+    // along with everything in them.
+    if !mapper.has_room_for(cur.to_str(), needed_clusters) {
+        return None;
+    }
+    let mut cur_cluster = first_cluster;
     let mut clusters = 0;
     while clusters < needed_clusters {
         while mapper.is_allocated(cur_cluster) {
@@ -64,38 +156,37 @@ fn traverse<T: FileSystemOps>(
 
     let mut max_cluster = cur_cluster;
 
-    let subdirs = fs
-        .get_dir(cur.to_str())
-        .unwrap()
-        .entries()
-        .into_iter()
-        .filter(|ent| ent.meta().is_directory);
-    let subfiles = fs
-        .get_dir(cur.to_str())
-        .unwrap()
-        .entries()
-        .into_iter()
-        .filter(|ent| !ent.meta().is_directory);
+    let subfiles = dir
+        .listing()
+        .filter(|ent| !ent.meta().is_directory)
+        .filter(|ent| names.get(&dir, cur.to_str(), ent).is_some());
     for ent in subfiles {
         let nh = ent.name();
         let path = {
-            let mut r = PathBuff::default();
-            r.add_subdir(cur.to_str());
+            let mut r = cur.clone();
             r.add_file(nh.as_ref());
             r
         };
-        let meta = ent.meta();
-        let needed_subclusters_raw = meta.size as usize / bytes_per_cluster
-            + if meta.size as usize % bytes_per_cluster == 0 {
-                0
-            } else {
-                1
-            };
-        let needed_subclusters = needed_subclusters_raw
-            .saturating_sub(mapper.get_chain_for_path(path.to_str()).into_iter().count());
+        let meta = naming.exposed_meta(path.to_str(), ent.meta());
+        // Empty files have no chain at all rather than a single empty cluster.
+        if meta.size == 0 {
+            continue;
+        }
+        // Duplicates of a file that was allocated earlier share its chain.
+        if let Some(owner) = dedup.owner_of(path.to_str()) {
+            if mapper.share_chain(path.to_str(), owner) {
+                continue;
+            }
+        }
+        let needed_subclusters_raw = (meta.size as usize).div_ceil(bytes_per_cluster);
+        let needed_subclusters =
+            needed_subclusters_raw.saturating_sub(mapper.chain_len(path.to_str()));
+        if !mapper.has_room_for(path.to_str(), needed_subclusters) {
+            continue;
+        }
         let mut clusters = 0;
         while clusters < needed_subclusters {
-            let mut my_offset = cur_cluster + 12;
+            let mut my_offset = cur_cluster + FILE_PLACEMENT_OFFSET;
             while mapper.is_allocated(my_offset) {
                 my_offset += 1;
             }
@@ -104,229 +195,1176 @@ fn traverse<T: FileSystemOps>(
             max_cluster = max_cluster.max(my_offset);
         }
     }
+    Some(max_cluster)
+}
 
-    for dir in subdirs {
-        let path_comp = dir.name();
-        let path = {
-            let mut r = PathBuff::default();
-            r.add_subdir(cur.to_str());
-            r.add_subdir(path_comp.as_ref());
-            r
+/// How much of the backing filesystem is exposed on the device, for sizing the
+/// device to fit.
+#[derive(Copy, Clone, Default, Debug)]
+pub(crate) struct TreeSize {
+    /// The combined size of every file.
+    pub bytes: u64,
+    /// The number of files.
+    pub files: u64,
+    /// The number of directories, including the root directory.
+    pub dirs: u64,
+}
+
+impl TreeSize {
+    /// An upper bound on the space the tree takes up with `cluster_size` byte
+    /// clusters, since every item wastes less than a cluster.
+    pub fn footprint(&self, cluster_size: u64) -> u64 {
+        self.bytes + (self.files + self.dirs) * cluster_size
+    }
+}
+
+/// Measures the tree below `cur`, counting the same items `traverse` would
+/// allocate clusters to.
+pub(crate) fn tree_size<T: FileSystemOps>(
+    cur: &PathBuff,
+    fs: &mut T,
+    naming: NamingOptions,
+) -> TreeSize {
+    let mut size = TreeSize::default();
+    let mut visit = |fs: &mut T, path: &PathBuff, _| size_dir(path, fs, naming, &mut size);
+    let mut walk = DirWalk::new(cur);
+    while walk.visit(fs, naming, &mut visit) {}
+    size
+}
+
+/// Adds the directory `cur` and the files directly in it to `size`, returning
+/// whether the directory could be listed.
+pub(crate) fn size_dir<T: FileSystemOps>(
+    cur: &PathBuff,
+    fs: &mut T,
+    naming: NamingOptions,
+    size: &mut TreeSize,
+) -> bool {
+    size.dirs += 1;
+    let dir = match fs.find_dir(cur.to_str()) {
+        Some(dir) => dir,
+        None => return false,
+    };
+    let names = DirNames::new(naming, &dir, cur.to_str());
+    let exposed = dir
+        .listing()
+        .filter(|ent| !ent.meta().is_directory && names.get(&dir, cur.to_str(), ent).is_some());
+    for ent in exposed {
+        let mut path = cur.clone();
+        path.add_file(ent.name().as_ref());
+        size.bytes += u64::from(naming.exposed_meta(path.to_str(), ent.meta()).size);
+        size.files += 1;
+    }
+    true
+}
+
+/// The number of clusters `traverse` allocates to the directories and to the
+/// files of a tree.
+#[derive(Copy, Clone, Default, Debug)]
+pub(crate) struct ClusterDemand {
+    pub dirs: u64,
+    pub files: u64,
+    /// The number of directories with a chain of their own.
+    pub chains: u64,
+}
+
+impl ClusterDemand {
+    /// An upper bound on the clusters `traverse` spans to place the tree, since
+    /// the gap it leaves after each directory may not get filled.
+    pub fn span(&self) -> u64 {
+        self.dirs + self.files + self.chains * u64::from(FILE_PLACEMENT_OFFSET - 1)
+    }
+}
+
+/// Counts the clusters `traverse` would allocate to everything below `cur`,
+/// and to `cur` itself if it gets a chain of its own, from the metadata of the
+/// backing items alone.
+///
+/// Files that `dedup_files` would let share a chain are counted separately.
+pub(crate) fn cluster_demand<T: FileSystemOps>(
+    cur: &PathBuff,
+    fs: &mut T,
+    naming: NamingOptions,
+    bytes_per_cluster: u64,
+    leading_entries: usize,
+    own_chain: bool,
+) -> ClusterDemand {
+    let mut demand = ClusterDemand::default();
+    let dir = match fs.find_dir(cur.to_str()) {
+        Some(dir) => dir,
+        None => return demand,
+    };
+    let names = DirNames::new(naming, &dir, cur.to_str());
+    if own_chain {
+        let entry_count =
+            leading_entries + directory_entry_count(&dir, cur.to_str(), &names, naming);
+        demand.dirs = ((entry_count.max(1) * DIRENT_SIZE) as u64).div_ceil(bytes_per_cluster);
+        demand.chains = 1;
+    }
+    let exposed = dir
+        .listing()
+        .filter(|ent| names.get(&dir, cur.to_str(), ent).is_some());
+    for ent in exposed {
+        let mut path = cur.clone();
+        if ent.meta().is_directory {
+            path.add_subdir(ent.name().as_ref());
+            let sub = cluster_demand(&path, fs, naming, bytes_per_cluster, 0, true);
+            demand.dirs += sub.dirs;
+            demand.files += sub.files;
+            demand.chains += sub.chains;
+        } else {
+            path.add_file(ent.name().as_ref());
+            let size = naming.exposed_meta(path.to_str(), ent.meta()).size;
+            demand.files += u64::from(size).div_ceil(bytes_per_cluster);
+        }
+    }
+    demand
+}
+
+/// Whether the backing item at `path`, exposed with the metadata `meta`, was
+/// left off the device because the mapper had no room left for its chain.
+pub(crate) fn is_unplaced(mapper: &ClusterMapper, path: &str, meta: &FileMetadata) -> bool {
+    (meta.is_directory || meta.size > 0) && !mapper.has_chain(path)
+}
+
+/// Like `is_unplaced`, but for the child `ent` of the backing directory
+/// `dir_path`.
+pub(crate) fn is_unplaced_child<E: DirEntryOps>(
+    mapper: &ClusterMapper,
+    naming: NamingOptions,
+    dir_path: &str,
+    ent: &E,
+) -> bool {
+    let meta = ent.meta();
+    let mut path = PathBuff::empty();
+    path.add_subdir(dir_path);
+    if meta.is_directory {
+        path.add_subdir(ent.name().as_ref());
+    } else {
+        path.add_file(ent.name().as_ref());
+    }
+    let meta = naming.exposed_meta(path.to_str(), meta);
+    is_unplaced(mapper, path.to_str(), &meta)
+}
+
+/// The number of directory entries, including Long File Name entries, that
+/// `dir`, the directory at the backing path `dir_path`, takes up on the device.
+pub(crate) fn directory_entry_count<D: DirectoryOps>(
+    dir: &D,
+    dir_path: &str,
+    names: &DirNames,
+    naming: NamingOptions,
+) -> usize {
+    let entries = dir
+        .listing()
+        .filter_map(|ent| names.get(dir, dir_path, &ent))
+        .map(|(exposed, _)| 1 + lfn_count(exposed.as_ref(), naming.case_flags))
+        .sum();
+    naming.padding.pad(entries)
+}
+
+/// Reads `buffer.len()` bytes of `file` starting at `offset`, zeroing whatever
+/// the file does not have, exactly like the device shows the file.
+///
+/// If the file fails to read, the rest of `buffer` is zeroed as well and the
+/// error is passed on.
+pub(crate) fn read_padded<F: FileOps>(
+    file: &mut F,
+    offset: usize,
+    buffer: &mut [u8],
+) -> Result<(), F::Error> {
+    let mut read = 0;
+    let mut result = Ok(());
+    while read < buffer.len() {
+        let cur_read = match file.read_at(offset + read, &mut buffer[read..]) {
+            Ok(cur_read) => cur_read,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
         };
-        max_cluster = max_cluster.max(traverse(mapper, &path, fs, bytes_per_cluster));
+        if cur_read == 0 {
+            break;
+        }
+        read += cur_read;
     }
-    max_cluster
+    for byte in buffer[read..].iter_mut() {
+        *byte = 0;
+    }
+    result
 }
 
 impl<T: FileSystemOps> FakeFat<T> {
     /// Constructs a new Fake FAT32 device wrapping the given filesystem.
     /// `path_prefix` represents where in the real filesystem should map to the
     /// FAT32 device's root directory; for a direct one-to-one mapping, use `"/"`.
-    pub fn new(mut fs: T, path_prefix: &str) -> Self {
-        let path_prefix = {
-            let mut r = PathBuff::default();
-            r.add_subdir(path_prefix);
-            r
-        };
-        let mut bpb = BiosParameterBlock::default();
-        bpb.bytes_per_sector = 512;
-        bpb.sectors_per_cluster = 8;
-        let mut mapper = ClusterMapper::new();
-
-        let max_cluster = traverse(
-            &mut mapper,
-            &path_prefix,
-            &mut fs,
-            bpb.bytes_per_cluster() as usize,
-        );
-        let total_clusters = (bpb.root_dir_first_cluster + max_cluster + 1).max(0xAB_CDEF);
-        let total_sectors = u32::from(bpb.sectors_per_cluster) * total_clusters;
-        bpb.total_sectors_32 = total_sectors;
-        let spf = default_sectors_per_fat(&bpb);
-        bpb.sectors_per_fat_32 = spf;
-        let cluster_size = bpb.bytes_per_cluster();
-        Self {
-            bpb,
-            fsinfo: FsInfoSector::default(),
+    ///
+    /// Use `FakeFatBuilder` to change any of the device's defaults.
+    pub fn new(fs: T, path_prefix: impl AsRef<str>) -> Self {
+        FakeFatBuilder::new().build(fs, path_prefix)
+    }
+
+    /// Where everything is on the device.
+    pub fn layout(&self) -> &VolumeLayout {
+        &self.layout
+    }
+
+    /// Moves the device onto a different backing filesystem, which should hold
+    /// the same tree as the current one, keeping its layout and everything the
+    /// host wrote to it.
+    pub fn rebind<U: FileSystemOps>(self, fs: U) -> FakeFat<U> {
+        FakeFat {
+            layout: self.layout,
+            fsinfo: self.fsinfo,
             fs,
-            mapper,
-            changes: ChangeSet::new(cluster_size),
-            read_idx: 0,
-            prefix: path_prefix,
+            changes: self.changes,
+            host_detector: self.host_detector,
+            read_audit: self.read_audit,
+            rate_limiter: self.rate_limiter,
+            write_buffer: self.write_buffer,
+            dir_cache: self.dir_cache,
+            file_cache: self.file_cache,
+            dir_versions: self.dir_versions,
+            read_idx: self.read_idx,
+            write_protected: self.write_protected,
+            append_only: self.append_only,
+            erase_unit_size: self.erase_unit_size,
+            out_of_range_reads: self.out_of_range_reads,
+            backend_error: None,
+            sessions: self.sessions,
+            created: self.created,
+            identity_seed: self.identity_seed,
+            dir_slack_fill: self.dir_slack_fill,
+            relocate_changed_files: self.relocate_changed_files,
+            ejected: self.ejected,
+        }
+    }
+
+    /// Whether the device rejects all writes, which adapters should also report
+    /// to the host (for example through the SCSI MODE SENSE write protect bit).
+    ///
+    /// Set via `FakeFatBuilder::write_protected`.
+    pub fn write_protected(&self) -> bool {
+        self.write_protected
+    }
+
+    /// Whether `write_back` only lets the host add to the backing filesystem.
+    ///
+    /// Set via `FakeFatBuilder::append_only`.
+    pub fn append_only(&self) -> bool {
+        self.append_only
+    }
+
+    /// Takes the most recent error the backing filesystem reported while the
+    /// device was serving the host, if there was one since the last call.
+    ///
+    /// The host cannot be told about these errors, so the items involved are
+    /// shown as missing or zeroed instead. Every such error is also counted in
+    /// `SessionStats::backend_errors`.
+    pub fn take_backend_error(&mut self) -> Option<T::Error> {
+        self.backend_error.take()
+    }
+
+    /// Keeps `error` for `take_backend_error`.
+    pub(crate) fn record_backend_error(&mut self, error: T::Error) {
+        self.sessions.stats.backend_errors += 1;
+        self.backend_error = Some(error);
+    }
+
+    /// The result of a lookup in the backing filesystem, keeping the error for
+    /// `take_backend_error` if it failed.
+    pub(crate) fn found<R>(&mut self, lookup: Result<Option<R>, T::Error>) -> Option<R> {
+        lookup.unwrap_or_else(|e| {
+            self.record_backend_error(e);
+            None
+        })
+    }
+
+    /// The volume label, padded with spaces.
+    ///
+    /// Set via `FakeFatBuilder::volume_label`.
+    pub fn volume_label(&self) -> &[u8; 11] {
+        &self.layout.bpb.volume_label
+    }
+
+    /// The volume serial number.
+    ///
+    /// Set via `FakeFatBuilder::volume_id`, or derived from
+    /// `FakeFatBuilder::identity_seed` or `FakeFatBuilder::created`.
+    pub fn volume_id(&self) -> u32 {
+        self.layout.bpb.volume_id
+    }
+
+    /// The number of data clusters on the device.
+    pub fn cluster_count(&self) -> u32 {
+        self.layout.cluster_count()
+    }
+
+    /// The number of data clusters that are currently free, taking the host's
+    /// writes to the File Allocation Table into account.
+    ///
+    /// FAT32 volumes also report this to the host in their FSInfo sector, so
+    /// hosts see how much of a fixed `total_capacity` is left without having
+    /// to scan the whole table.
+    pub fn free_clusters(&self) -> u32 {
+        self.fsinfo.free_count()
+    }
+
+    /// The size of the device in bytes, as reported to the host.
+    pub fn image_size(&self) -> usize {
+        self.layout.image_size()
+    }
+
+    /// The length of the device in bytes, `total_sectors_32` sectors of
+    /// `bytes_per_sector` bytes each, which is where `Read` reaches its end.
+    ///
+    /// Unlike `image_size`, this cannot overflow on targets with a 32-bit
+    /// `usize` when the device is 4 GiB or larger.
+    pub fn len(&self) -> u64 {
+        let bpb = &self.layout.bpb;
+        u64::from(bpb.total_sectors_32) * u64::from(bpb.bytes_per_sector)
+    }
+
+    /// Whether the device has no bytes at all, which never happens, since even
+    /// a device without any files has its preamble and tables.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The part of `buf` a stream read at `pos` fills, which stops at the end
+    /// of the device whatever `out_of_range_reads` is set to.
+    #[cfg_attr(not(any(feature = "std", feature = "embedded-io")), allow(dead_code))]
+    pub(crate) fn stream_buffer<'a>(&self, pos: u64, buf: &'a mut [u8]) -> &'a mut [u8] {
+        let remaining = self.len().saturating_sub(pos);
+        let len = remaining.min(buf.len() as u64) as usize;
+        &mut buf[..len]
+    }
+
+    /// Starts watching the host's accesses to guess what kind of host it is.
+    ///
+    /// Detection is off by default since it adds a small amount of bookkeeping
+    /// to every read and write.
+    pub fn enable_host_detection(&mut self) {
+        if self.host_detector.is_none() {
+            self.host_detector = Some(HostDetector::new());
         }
     }
 
+    /// The host detector, if `enable_host_detection` has been called.
+    pub fn host_detector(&self) -> Option<&HostDetector> {
+        self.host_detector.as_ref()
+    }
+
+    /// The current best guess of what kind of host is accessing the device, or
+    /// `HostGuess::Unknown` if host detection is not enabled.
+    pub fn host_guess(&self) -> HostGuess {
+        self.host_detector
+            .as_ref()
+            .map_or(HostGuess::Unknown, HostDetector::guess)
+    }
 
     /// Writes a single byte into the FAT32 device, exactly `idx` bytes from the
     /// head of the device.
     ///
-    /// #Panics
-    /// This function panics if the address being written to is read-only or is
-    /// part of the FAT preamble.
-    pub fn write_byte(&mut self, idx: usize, new_byte: u8) {
-        match FakerAddress::from_raw_idx(idx, &self.bpb) {
-            FakerAddress::Fat { cluster, byte } => {
-                if self.changes.cluster_entry(cluster).is_none() {
-                    let chain_opt = self.mapper.get_chain_with_cluster(cluster);
-
-                    let entry_raw =
-                        chain_opt.map(|it| it.into_iter().skip_while(|c| *c != cluster).next());
-                    let old_entry = match entry_raw {
-                        Some(Some(next)) => FatEntryValue::Next(next),
-                        Some(None) => FatEntryValue::End,
-                        None => FatEntryValue::Free,
-                    };
+    /// Writes to the File Allocation Table and the data region are kept in
+    /// memory; use `write_back` to apply them to a writable backing filesystem.
+    ///
+    /// Fails without writing anything if the device is write protected or if
+    /// the address is part of the FAT preamble or past the end of the device.
+    /// Since writes are batched up,
+    /// running out of room in the change set is not reported here; the write
+    /// is dropped when the batch is applied instead.
+    pub fn write_byte(&mut self, idx: usize, new_byte: u8) -> Result<(), FakeFatError> {
+        self.check_writable(idx)?;
+        let window = idx / self.layout.bpb.bytes_per_cluster() as usize;
+        if !self.write_buffer.accepts(idx, window) {
+            self.flush()?;
+        }
+        self.sessions.stats.bytes_written += 1;
+        if let Some(detector) = self.host_detector.as_mut() {
+            detector.observe_write(idx, new_byte, &self.layout.bpb);
+        }
+        self.write_buffer.push(idx, window, new_byte);
+        Ok(())
+    }
 
-                    let cluster_data_buff = self.changes.insert_cluster(cluster, old_entry);
-                    match FakerDataAddress::resolve_raw_data(
-                        cluster,
-                        0,
-                        &self.bpb,
-                        &self.mapper,
-                        &mut self.fs,
-                    ) {
-                        Some(FakerDataAddress::File { mut file, offset }) => {
-                            let _read = file.read_at(
-                                offset,
-                                &mut cluster_data_buff[..self.bpb.bytes_per_cluster() as usize],
-                            );
-                        }
-                        Some(FakerDataAddress::Directory {
-                            directory,
-                            entry,
-                            offset,
-                        }) => {
-                            let mut read_bytes = 0;
-                            let entries = DirectoryNewtype::from(directory)
-                                .fat_entries()
-                                .skip(entry)
-                                .map(fix_first_entry(
-                                    &self.mapper,
-                                    self.mapper.get_path_for_cluster(cluster).unwrap(),
-                                ))
-                                .map(|(fixed, _)| fixed);
-                            for ent in entries {
-                                let start_idx = read_bytes;
-                                let end_idx = (start_idx + Fat32DirectoryEntry::SIZE)
-                                    .min(self.bpb.bytes_per_cluster() as usize);
-                                let current_buffer = &mut cluster_data_buff[start_idx..end_idx];
-                                let current_read = ent.read_at(
-                                    (start_idx + offset) % Fat32DirectoryEntry::SIZE,
-                                    current_buffer,
-                                );
-                                read_bytes += current_read;
-                                if read_bytes >= self.bpb.bytes_per_cluster() as usize {
-                                    break;
-                                }
-                            }
+    /// Fails if the host may not write to `idx`.
+    fn check_writable(&self, idx: usize) -> Result<(), FakeFatError> {
+        let offset = idx as u64;
+        if self.write_protected {
+            return Err(FakeFatError::WriteProtected { offset });
+        }
+        if idx >= self.image_size() {
+            return Err(FakeFatError::OutOfRange { offset });
+        }
+        match FakerAddress::from_raw_idx(idx, &self.layout.bpb) {
+            FakerAddress::Fat { .. } | FakerAddress::RawData { .. } => Ok(()),
+            other => Err(FakeFatError::ReadOnly {
+                offset,
+                region: other.region(),
+            }),
+        }
+    }
+
+    /// Writes `data` into the FAT32 device starting `idx` bytes from the head
+    /// of the device, returning the number of bytes written.
+    ///
+    /// Unlike calling `write_byte` in a loop, each cluster the write touches is
+    /// only resolved once and the data is copied into it a run at a time.
+    ///
+    /// Fails without writing anything if the device is write protected or if
+    /// the write starts in the FAT preamble or past the end of the device. A
+    /// write that runs past the end of the device stops there, and without the
+    /// `alloc` feature, the write can also stop part way through once the
+    /// change set is full, returning the number of bytes applied before that;
+    /// it only fails with `ChangeSetFull` if there was no room for any of them.
+    pub fn write_at(&mut self, idx: usize, data: &[u8]) -> Result<usize, FakeFatError> {
+        self.check_writable(idx)?;
+        self.flush()?;
+        let data = &data[..data.len().min(self.image_size() - idx)];
+        self.sessions.stats.bytes_written += data.len() as u64;
+        if let Some(detector) = self.host_detector.as_mut() {
+            for (offset, &byte) in data.iter().enumerate() {
+                detector.observe_write(idx + offset, byte, &self.layout.bpb);
+            }
+        }
+        let result = self.write_runs(idx, data);
+        self.debug_validate("write_at");
+        result
+    }
+
+    /// Writes `data`, which ends within the device, into the File Allocation
+    /// Table and data region a run at a time, for `write_at`.
+    fn write_runs(&mut self, idx: usize, data: &[u8]) -> Result<usize, FakeFatError> {
+        let cluster_size = self.layout.bpb.bytes_per_cluster() as usize;
+        // Once the change set is full, whatever was applied before is still
+        // reported as written.
+        let full = |applied: usize| match applied {
+            0 => Err(FakeFatError::ChangeSetFull { offset: idx as u64 }),
+            applied => Ok(applied),
+        };
+        let mut written = 0;
+        while written < data.len() {
+            let cur_idx = idx + written;
+            let run = &data[written..];
+            written += match FakerAddress::from_raw_idx(cur_idx, &self.layout.bpb) {
+                FakerAddress::Fat { offset } => {
+                    let len = (self.layout.bpb.fat_bytes() - offset).min(run.len());
+                    for (offset, &new_byte) in run[..len].iter().enumerate() {
+                        if !self.apply_write(cur_idx + offset, new_byte) {
+                            return full(written + offset);
                         }
-                        None => {}
                     }
+                    len
+                }
+                FakerAddress::RawData { cluster, offset } => {
+                    let len = (cluster_size - offset).min(run.len());
+                    self.ensure_changed(cluster);
+                    match self.changes.cluster_mut(cluster) {
+                        Some(buffer) => buffer[offset..offset + len].copy_from_slice(&run[..len]),
+                        None => return full(written),
+                    }
+                    len
+                }
+                other => {
+                    return Err(FakeFatError::ReadOnly {
+                        offset: cur_idx as u64,
+                        region: other.region(),
+                    })
+                }
+            };
+        }
+        Ok(written)
+    }
+
+    /// Applies any writes that are still being batched up by `write_byte` to
+    /// the device.
+    ///
+    /// Reads always see the batched values, so this only needs to be called
+    /// to bound how much work a later write ends up doing.
+    ///
+    /// Without the `alloc` feature, fails with `ChangeSetFull` once the change
+    /// set has no room left, in which case the writes that were not applied
+    /// stay batched up.
+    pub fn flush(&mut self) -> Result<(), FakeFatError> {
+        if self.write_buffer.is_empty() {
+            return Ok(());
+        }
+        let mut batch = core::mem::replace(&mut self.write_buffer, WriteBuffer::new());
+        let (start, data) = batch.pending();
+        let applied = data
+            .iter()
+            .enumerate()
+            .take_while(|&(offset, &new_byte)| self.apply_write(start + offset, new_byte))
+            .count();
+        batch.consume(applied);
+        self.write_buffer = batch;
+        self.debug_validate("flush");
+        if self.write_buffer.is_empty() {
+            Ok(())
+        } else {
+            Err(FakeFatError::ChangeSetFull {
+                offset: (start + applied) as u64,
+            })
+        }
+    }
+
+    /// Whether the host wrote anything that has not been applied to the backing
+    /// filesystem with `write_back` yet.
+    ///
+    /// Until then, FAT16 and FAT32 devices report the volume as not cleanly
+    /// unmounted in the volume flags of FAT entry 1, so that hosts and fsck
+    /// tools know that the backing filesystem does not hold what the host sees.
+    pub fn is_dirty(&self) -> bool {
+        !self.write_buffer.is_empty() || self.changes.any_dirty()
+    }
+
+    /// The epoch the host's writes are currently being stamped with, which
+    /// starts at 0 when the device is constructed.
+    pub fn epoch(&self) -> u32 {
+        self.changes.epoch()
+    }
+
+    /// Ends the current epoch and starts a new one, returning the new epoch's
+    /// number.
+    ///
+    /// Any writes still being batched up are applied first, so that they are
+    /// stamped with the epoch they were made in. Fails like `flush` if they can
+    /// not be, without ending the epoch.
+    pub fn next_epoch(&mut self) -> Result<u32, FakeFatError> {
+        self.flush()?;
+        Ok(self.changes.advance_epoch())
+    }
+
+    /// The clusters the host changed, either the contents of or the FAT entry
+    /// of, in any epoch from `from` up to, but not including, `to`, in no
+    /// particular order.
+    ///
+    /// Only the first and last epoch a cluster was changed in are kept, so a
+    /// cluster changed both before and after the range but not during it is
+    /// included as well. This never misses a change, so applications that
+    /// replicate the device can call this with the epoch of their last sync to
+    /// only look at what changed since, rather than at every pending change.
+    ///
+    /// Fails like `flush` if the writes still being batched up can not be
+    /// applied first.
+    pub fn changes_between(
+        &mut self,
+        from: u32,
+        to: u32,
+    ) -> Result<impl Iterator<Item = u32> + '_, FakeFatError> {
+        self.flush()?;
+        Ok(self.changes.changed_between(from, to))
+    }
+
+    /// Applies a single byte the host wrote, returning `false` if the change
+    /// set had no room left to keep it.
+    fn apply_write(&mut self, idx: usize, new_byte: u8) -> bool {
+        match FakerAddress::from_raw_idx(idx, &self.layout.bpb) {
+            FakerAddress::Fat { offset } => {
+                // On FAT12 a byte can hold parts of two entries.
+                let fat_type = self.layout.bpb.fat_type;
+                for cluster in entries_at(fat_type, offset) {
+                    // The reserved entries are fixed for the fake device.
+                    if cluster < FIRST_DATA_CLUSTER {
+                        continue;
+                    }
+                    self.ensure_changed(cluster);
+                    let old_entry = match self.changes.cluster_entry(cluster) {
+                        Some(old_entry) => old_entry,
+                        None => return false,
+                    };
+                    let existing = old_entry.to_raw(fat_type);
+                    let newval = patch_entry(fat_type, cluster, offset, existing, new_byte);
+                    // The host may only be part way through writing the entry, so
+                    // end of chain markers other than the canonical one are kept
+                    // as they are instead of losing the bits written so far.
+                    let decoded = FatEntryValue::from_raw(newval, fat_type);
+                    let newentry = if decoded.to_raw(fat_type) == newval {
+                        decoded
+                    } else {
+                        FatEntryValue::Next(newval)
+                    };
+                    self.changes.set_cluster_entry(cluster, newentry);
+                    match (old_entry, newentry) {
+                        (FatEntryValue::Free, FatEntryValue::Free) => {}
+                        (FatEntryValue::Free, _) => self.fsinfo.allocated(1, cluster),
+                        (_, FatEntryValue::Free) => self.fsinfo.freed(),
+                        _ => {}
+                    }
+                }
+            }
+            FakerAddress::RawData { cluster, offset } => {
+                self.ensure_changed(cluster);
+                match self.changes.cluster_mut(cluster) {
+                    Some(buffer) => buffer[offset] = new_byte,
+                    None => return false,
                 }
-                let existing: u32 = self.changes.cluster_entry(cluster).unwrap().into();
-                let shift = byte * 8;
-                let existing_masked = existing & !(0xFF << shift);
-                let newval = existing_masked | u32::from(new_byte) << shift;
-                self.changes.set_cluster_entry(cluster, newval.into());
             }
-            _ => {
-                panic!(
-                    "ERROR: Attempting to write {} to address {}, but this address is read-only.",
-                    new_byte, idx
-                );
+            // Writes to the preamble are rejected before they are batched up.
+            _ => {}
+        }
+        true
+    }
+
+    /// Makes sure `cluster` has an entry in the change set, seeding it with the
+    /// cluster's current FAT entry and contents if it does not.
+    fn ensure_changed(&mut self, cluster: u32) {
+        if self.changes.cluster_entry(cluster).is_some() {
+            return;
+        }
+        let old_entry = self.fat_entry(cluster);
+        if self.changes.insert_cluster(cluster, old_entry).is_none() {
+            return;
+        }
+        self.snapshot_cluster(cluster);
+        self.sessions.stats.clusters_changed += 1;
+    }
+
+    /// Copies the current contents of `cluster` into its change set buffer.
+    fn snapshot_cluster(&mut self, cluster: u32) {
+        let cluster_size = self.layout.bpb.bytes_per_cluster() as usize;
+        let mut chunk = [0; SNAPSHOT_CHUNK_SIZE];
+        for chunk_start in (0..cluster_size).step_by(SNAPSHOT_CHUNK_SIZE) {
+            let len = (cluster_size - chunk_start).min(SNAPSHOT_CHUNK_SIZE);
+            self.read_rendered_at(cluster, chunk_start, &mut chunk[..len]);
+            if let Some(buffer) = self.changes.cluster_mut(cluster) {
+                buffer[chunk_start..chunk_start + len].copy_from_slice(&chunk[..len]);
+            }
+        }
+    }
+
+    /// Fills `buffer` with the contents of `cluster` starting `offset` bytes into
+    /// the cluster, as rendered from the backing filesystem and ignoring any
+    /// changes the host has made.
+    ///
+    /// `offset + buffer.len()` must not exceed the size of a cluster.
+    fn read_rendered_at(&mut self, cluster: u32, offset: usize, buffer: &mut [u8]) {
+        // Free clusters read as zeros without asking the caches or the backing
+        // filesystem about them.
+        if self.layout.mapper.get_path_for_cluster(cluster).is_none() {
+            buffer.fill(0);
+            return;
+        }
+        let fill = self.dir_slack_fill;
+        if let Some((entries, start)) = self.cached_dir_entries(cluster) {
+            render_entries(entries.iter().copied(), start + offset, fill, buffer);
+            return;
+        }
+        if let Some(data) = self.file_cache.get(cluster) {
+            buffer.copy_from_slice(&data[offset..offset + buffer.len()]);
+            return;
+        }
+        let resolved = FakerDataAddress::resolve_raw_data(
+            cluster,
+            offset,
+            &self.layout.bpb,
+            &self.layout.mapper,
+            &mut self.fs,
+        );
+        match self.found(resolved) {
+            Some(FakerDataAddress::File { mut file, offset }) => {
+                if let Err(e) = read_padded(&mut file, offset, buffer) {
+                    self.record_backend_error(e);
+                }
+            }
+            Some(FakerDataAddress::Directory {
+                directory,
+                entries,
+                offset,
+            }) => {
+                let path = match self.layout.mapper.get_path_for_cluster(cluster) {
+                    Some(path) => path,
+                    None => {
+                        buffer.fill(0);
+                        return;
+                    }
+                };
+                let label = self.label_entry_for(path);
+                let naming = self.layout.naming;
+                let names = DirNames::new(naming, &directory, path);
+                let rendered = DirectoryNewtype::from(directory)
+                    .fat_entries(naming, &names, &self.layout.mapper, path, label)
+                    .take(entries.end)
+                    .map(fix_first_entry(&self.layout.mapper, path))
+                    .map(|(fixed, _)| fixed)
+                    .map(mark_read_only(self.write_protected))
+                    .map(apply_dir_versions(&self.dir_versions));
+                render_entries(rendered, entries.start * DIRENT_SIZE + offset, fill, buffer);
+            }
+            None => {
+                for byte in buffer.iter_mut() {
+                    *byte = 0;
+                }
             }
         }
     }
 
+    /// Looks up the rendered entries of the directory `cluster` is part of,
+    /// rendering the directory into the cache first if it is not there yet,
+    /// along with how many bytes into the directory `cluster` starts.
+    ///
+    /// Returns `None` if `cluster` is not part of a directory or if the
+    /// directory could not be cached.
+    pub(crate) fn cached_dir_entries(
+        &mut self,
+        cluster: u32,
+    ) -> Option<(&[Fat32DirectoryEntry], usize)> {
+        let path = self.layout.mapper.get_path_for_cluster(cluster)?;
+        if !path.ends_with('/') {
+            return None;
+        }
+        if self.dir_cache.get(path).is_none() {
+            // The directory is listed once up front so that a listing that fails
+            // is not cached as an empty directory. `path` borrows the mapper, so
+            // the error is kept by hand.
+            let listed = match self.fs.get_dir(path) {
+                Ok(Some(directory)) => directory.entries().map(|_| Some(directory)),
+                other => other,
+            };
+            let directory = match listed {
+                Ok(directory) => directory?,
+                Err(e) => {
+                    self.sessions.stats.backend_errors += 1;
+                    self.backend_error = Some(e);
+                    return None;
+                }
+            };
+            let label = self.label_entry_for(path);
+            let naming = self.layout.naming;
+            let names = DirNames::new(naming, &directory, path);
+            let entries = DirectoryNewtype::from(directory)
+                .fat_entries(naming, &names, &self.layout.mapper, path, label)
+                .map(fix_first_entry(&self.layout.mapper, path))
+                .map(|(fixed, _)| fixed)
+                .map(mark_read_only(self.write_protected))
+                .map(apply_dir_versions(&self.dir_versions));
+            if !self.dir_cache.insert(path, entries) {
+                return None;
+            }
+            // Later lookups of the items the host refers to reuse the names.
+            self.dir_cache.names(path, || names);
+        }
+        let range = dir_entry_range(
+            &self.layout.bpb,
+            self.layout.mapper.chain_position(cluster)?,
+        );
+        let entries = self.dir_cache.get(path)?;
+        Some((entries, range.start * DIRENT_SIZE))
+    }
+
+    /// The volume label entry leading the directory at the backing path `path`,
+    /// which only the root directory of a labelled volume has.
+    pub(crate) fn label_entry_for(&self, path: &str) -> Option<FileDirEntry> {
+        if path != self.layout.prefix.to_str() || !self.layout.bpb.has_volume_label() {
+            return None;
+        }
+        let (date, time) = self.created.unwrap_or_default();
+        let mut entry = FileDirEntry::default();
+        entry.name.data = self.layout.bpb.volume_label;
+        entry.attrs = FileAttributes::volume_label();
+        entry.create_date = date;
+        entry.create_time = time;
+        entry.access_date = date;
+        entry.modify_date = date;
+        entry.modify_time = time;
+        Some(entry)
+    }
+
+    /// Gets the File Allocation Table entry for the data cluster `cluster`.
+    fn fat_entry(&self, cluster: u32) -> FatEntryValue {
+        if let Some(changed) = self.changes.cluster_entry(cluster) {
+            changed
+        } else if let Some(cur_chain) = self.layout.mapper.get_chain_with_cluster(cluster) {
+            cur_chain
+                .into_iter()
+                .skip_while(|&l| l != cluster)
+                .nth(1)
+                .map(FatEntryValue::Next)
+                .unwrap_or(FatEntryValue::End)
+        } else {
+            FatEntryValue::Free
+        }
+    }
+
+    /// Gets the raw File Allocation Table entry for `cluster`, including the
+    /// reserved entries 0 and 1, which do not belong to any cluster.
+    fn raw_fat_entry(&self, cluster: u32) -> u32 {
+        let fat_type = self.layout.bpb.fat_type;
+        if cluster < FIRST_DATA_CLUSTER {
+            reserved_entry(fat_type, self.layout.bpb.media, cluster, self.is_dirty())
+        } else {
+            self.fat_entry(cluster).to_raw(fat_type)
+        }
+    }
+
+    /// Gets the byte `offset` bytes into each File Allocation Table, which can
+    /// hold parts of more than one entry.
+    fn fat_byte(&self, offset: usize) -> u8 {
+        let fat_type = self.layout.bpb.fat_type;
+        entries_at(fat_type, offset).fold(0, |byte, cluster| {
+            let raw = self.raw_fat_entry(cluster);
+            byte | entry_byte(fat_type, cluster, offset, raw)
+        })
+    }
+
     /// Reads a single byte out of the FAT32 device, exactly `idx` bytes from the
     /// head of the device.
+    ///
+    /// Single bytes are neither held back by the rate limits nor counted
+    /// against them.
     pub fn read_byte(&mut self, idx: usize) -> u8 {
-        match FakerAddress::from_raw_idx(idx, &self.bpb) {
-            FakerAddress::Bpb(bpb_idx) => self.bpb.read_byte(bpb_idx),
-            FakerAddress::FsInfo(fs_idx) => self.fsinfo.read_byte(fs_idx),
-            FakerAddress::Fat { cluster, byte } => {
-                let cur_value = {
-                    if let Some(changed) = self.changes.cluster_entry(cluster) {
-                        changed
-                    } else if let Some(cur_chain) = self.mapper.get_chain_with_cluster(cluster) {
-                        let next_link = cur_chain.into_iter().skip_while(|&l| l != cluster).next();
-                        next_link.map(|c| c.into()).unwrap_or(FatEntryValue::End)
-                    } else {
-                        FatEntryValue::Free
+        let image_size = self.image_size();
+        if idx >= image_size {
+            self.sessions.stats.out_of_range_reads += 1;
+            match self.out_of_range_reads {
+                OutOfRangeReads::Wrap => return self.read_byte(idx % image_size),
+                OutOfRangeReads::Zeros | OutOfRangeReads::Error => return 0,
+            }
+        }
+        if let Some(detector) = self.host_detector.as_mut() {
+            detector.observe_read(idx, &self.layout.bpb);
+        }
+        self.sessions.stats.bytes_read += 1;
+        let byte = self.read_device_byte(idx);
+        self.audit_read(idx, 1);
+        byte
+    }
+
+    /// Reads up to `buffer.len()` bytes out of the FAT32 device starting `idx`
+    /// bytes from the head of the device, returning the number of bytes read.
+    ///
+    /// Unlike calling `read_byte` in a loop, each region of the device is only
+    /// resolved once per call and file contents are read from the backing
+    /// filesystem in runs instead of a byte at a time.
+    ///
+    /// Fewer bytes, possibly none, are read if the read reaches file contents
+    /// that the rate limits set with `FakeFatBuilder::read_rate_limit` and
+    /// `FakeFatBuilder::path_read_rate_limits` do not allow reading yet.
+    ///
+    /// Free clusters read as zeros unless the host wrote to them. Reads that
+    /// run past the end of the device are handled as set with
+    /// `FakeFatBuilder::out_of_range_reads`.
+    pub fn read_at(&mut self, idx: usize, buffer: &mut [u8]) -> usize {
+        self.read_into(idx, buffer.len(), |fake, source, range| match source {
+            Some(device_idx) => {
+                fake.read_device_at(device_idx, &mut buffer[range]);
+            }
+            None => buffer[range].fill(0),
+        })
+    }
+
+    /// Reads up to `buffer.len()` bytes out of the FAT32 device into a possibly
+    /// uninitialized buffer starting `idx` bytes from the head of the device,
+    /// returning the now-initialized part of `buffer`.
+    ///
+    /// Reads exactly like `read_at`, and counts as a single host access. The
+    /// device is read through a single sector-sized intermediate buffer, so
+    /// large transfer buffers never need to be zero-filled first.
+    pub fn read_at_uninit<'a>(
+        &mut self,
+        idx: usize,
+        buffer: &'a mut [MaybeUninit<u8>],
+    ) -> &'a mut [u8] {
+        let read = self.read_into(idx, buffer.len(), |fake, source, range| {
+            let mut bounce = [0; UNINIT_READ_CHUNK_SIZE];
+            let mut offset = range.start;
+            while offset < range.end {
+                let len = (range.end - offset).min(UNINIT_READ_CHUNK_SIZE);
+                if let Some(device_idx) = source {
+                    fake.read_device_at(device_idx + offset - range.start, &mut bounce[..len]);
+                }
+                for (dest, &byte) in buffer[offset..].iter_mut().zip(bounce[..len].iter()) {
+                    dest.write(byte);
+                }
+                offset += len;
+            }
+        });
+        // Safety: `read_into` filled every byte in `buffer[..read]`.
+        unsafe { &mut *(&mut buffer[..read] as *mut [MaybeUninit<u8>] as *mut [u8]) }
+    }
+
+    /// Reads `len` bytes starting at `idx` the way `read_at` does, returning
+    /// how many were read, but leaves filling the destination to `fill`.
+    ///
+    /// `fill` is handed the device offset to read the bytes at `range` of the
+    /// destination from, or `None` if they are to be zeros.
+    fn read_into(
+        &mut self,
+        idx: usize,
+        len: usize,
+        mut fill: impl FnMut(&mut Self, Option<usize>, Range<usize>),
+    ) -> usize {
+        let image_size = self.image_size();
+        if idx.saturating_add(len) <= image_size {
+            return self.read_in_range(idx, 0, len, &mut fill);
+        }
+        self.sessions.stats.out_of_range_reads += 1;
+        let in_range = image_size.saturating_sub(idx).min(len);
+        let read = self.read_in_range(idx, 0, in_range, &mut fill);
+        if read < in_range {
+            return read;
+        }
+        match self.out_of_range_reads {
+            OutOfRangeReads::Zeros => {
+                fill(self, None, in_range..len);
+                len
+            }
+            OutOfRangeReads::Error => read,
+            OutOfRangeReads::Wrap => {
+                let mut read = read;
+                while read < len {
+                    let start = (idx + read) % image_size;
+                    let run = (image_size - start).min(len - read);
+                    let cur_read = self.read_in_range(start, read, run, &mut fill);
+                    read += cur_read;
+                    if cur_read < run {
+                        break;
                     }
-                };
+                }
+                read
+            }
+        }
+    }
 
-                let entry_bytes: u32 = cur_value.into();
-                let shift = byte * 8;
-                ((entry_bytes & (0xFF << shift)) >> shift) as u8
+    /// Reads `len` bytes starting at `idx`, which are all within the device,
+    /// into the destination of `read_into` starting `offset` bytes into it.
+    fn read_in_range(
+        &mut self,
+        idx: usize,
+        offset: usize,
+        len: usize,
+        fill: &mut impl FnMut(&mut Self, Option<usize>, Range<usize>),
+    ) -> usize {
+        let read = self.read_allowance(idx, len);
+        if let Some(detector) = self.host_detector.as_mut() {
+            let sector_size = self.layout.bpb.bytes_per_sector as usize;
+            let first_sector = idx.next_multiple_of(sector_size);
+            for sector_start in (first_sector..idx + read).step_by(sector_size) {
+                detector.observe_read(sector_start, &self.layout.bpb);
             }
-            FakerAddress::RawData { cluster, offset } => {
-                if let Some(buffer) = self.changes.cluster_data(cluster) {
-                    buffer[offset]
-                } else {
-                    match FakerDataAddress::resolve_raw_data(
-                        cluster,
-                        offset,
-                        &self.bpb,
-                        &self.mapper,
-                        &mut self.fs,
-                    ) {
-                        None => 0,
-                        Some(FakerDataAddress::File { mut file, offset }) => {
-                            file.read_byte(offset).unwrap_or(0)
-                        }
-                        Some(FakerDataAddress::Directory {
-                            directory,
-                            entry,
-                            offset,
-                        }) => DirectoryNewtype::from(directory)
-                            .fat_entries()
-                            .skip(entry)
-                            .map(fix_first_entry(
-                                &self.mapper,
-                                self.mapper.get_path_for_cluster(cluster).unwrap(),
-                            ))
-                            .map(|(fixed, _)| fixed)
-                            .next()
-                            .unwrap_or(Fat32DirectoryEntry::empty())
-                            .read_byte(offset),
+        }
+        fill(self, Some(idx), offset..offset + read);
+        self.sessions.stats.bytes_read += read as u64;
+        self.audit_read(idx, read);
+        self.consume_read_budget(idx, read);
+        read
+    }
+
+    /// Reads the device exactly like `read_at` without counting as a host access.
+    pub(crate) fn read_device_at(&mut self, idx: usize, buffer: &mut [u8]) -> usize {
+        let cluster_size = self.layout.bpb.bytes_per_cluster() as usize;
+        let mut read = 0;
+        while read < buffer.len() {
+            let run = &mut buffer[read..];
+            read += match FakerAddress::from_raw_idx(idx + read, &self.layout.bpb) {
+                FakerAddress::Bpb(bpb_idx) => self.layout.bpb.read_at(bpb_idx, run),
+                FakerAddress::FsInfo(fs_idx) => self.fsinfo.read_at(fs_idx, run),
+                FakerAddress::Reserved(res_idx) => {
+                    // Larger sectors leave a gap between the BPB and the FSInfo,
+                    // and between their backup copies.
+                    let bpb = &self.layout.bpb;
+                    let backup = bpb.backup_boot_start();
+                    let end = [
+                        Some(bpb.fs_info_start()).filter(|_| bpb.fat_type == FatType::Fat32),
+                        backup,
+                        backup.map(|backup| backup + bpb.fs_info_start()),
+                    ]
+                    .iter()
+                    .flatten()
+                    .copied()
+                    .filter(|&start| start > res_idx)
+                    .fold(bpb.fat_start(), usize::min);
+                    let len = (end - res_idx).min(run.len());
+                    for byte in run[..len].iter_mut() {
+                        *byte = 0;
+                    }
+                    len
+                }
+                FakerAddress::Fat { offset } => {
+                    let fat_type = self.layout.bpb.fat_type;
+                    if fat_type == FatType::Fat12 {
+                        run[0] = self.fat_byte(offset);
+                        1
+                    } else {
+                        let entry_size = fat_type.entry_bits() / 8;
+                        let cluster = (offset / entry_size) as u32;
+                        let raw = self.raw_fat_entry(cluster).to_le_bytes();
+                        let byte = offset % entry_size;
+                        let len = (entry_size - byte).min(run.len());
+                        run[..len].copy_from_slice(&raw[byte..byte + len]);
+                        len
                     }
                 }
+                FakerAddress::RawData { cluster, offset } => {
+                    let len = (cluster_size - offset).min(run.len());
+                    if let Some(data) = self.changes.cluster_data(cluster) {
+                        run[..len].copy_from_slice(&data[offset..offset + len]);
+                    } else {
+                        self.read_rendered_at(cluster, offset, &mut run[..len]);
+                    }
+                    len
+                }
+            };
+        }
+        if !self.write_buffer.is_empty() {
+            for (offset, byte) in buffer.iter_mut().enumerate() {
+                if let Some(pending) = self.write_buffer.get(idx + offset) {
+                    *byte = pending;
+                }
+            }
+        }
+        read
+    }
+
+    /// Reads the device exactly like `read_byte` without counting as a host access.
+    pub(crate) fn read_device_byte(&mut self, idx: usize) -> u8 {
+        let mut byte = [0];
+        self.read_device_at(idx, &mut byte);
+        byte[0]
+    }
+}
+
+/// What the device does with reads past its last sector, which hosts make now
+/// and then to align transfers or to read ahead into their caches.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OutOfRangeReads {
+    /// Fill the part of the read past the end with zeros.
+    #[default]
+    Zeros,
+    /// Stop the read at the end of the device, reading fewer bytes than asked
+    /// for, or none at all if the read starts past the end.
+    Error,
+    /// Continue the read from the start of the device, as if the device
+    /// repeated itself.
+    Wrap,
+}
+
+/// How many empty entries every directory is given on top of the ones its
+/// items take up, so that hosts can add items to it without growing its
+/// cluster chain, which is the hardest change for write-back to follow.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DirectoryPadding {
+    /// Only the rest of the directory's last cluster is left empty.
+    #[default]
+    None,
+    /// This many more entries.
+    Entries(u16),
+    /// This percentage of the entries the directory's items take up, rounded
+    /// up.
+    Percent(u16),
+}
+
+impl DirectoryPadding {
+    /// The number of entries a directory whose items take up `entries`
+    /// entries is given.
+    pub(crate) fn pad(self, entries: usize) -> usize {
+        match self {
+            DirectoryPadding::None => entries,
+            DirectoryPadding::Entries(extra) => entries + usize::from(extra),
+            DirectoryPadding::Percent(percent) => {
+                entries + (entries * usize::from(percent)).div_ceil(100)
             }
         }
     }
 }
 
-enum FakerAddress {
+/// The sections the fake device is laid out in.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Region {
+    /// The boot sector holding the BIOS Parameter Block, or its backup copy.
+    BootSector,
+    /// The FSInfo sector, or its backup copy right after the backup boot
+    /// sector.
+    FsInfo,
+    /// The unused reserved sectors before the File Allocation Tables.
+    Reserved,
+    /// The File Allocation Tables.
+    Fat,
+    /// The data clusters.
+    Data,
+    /// The Master Boot Record and the gap before the partition of an
+    /// `MbrWrapped` device.
+    Mbr,
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Region::BootSector => "boot sector",
+            Region::FsInfo => "FSInfo sector",
+            Region::Reserved => "reserved sectors",
+            Region::Fat => "File Allocation Table",
+            Region::Data => "data region",
+            Region::Mbr => "Master Boot Record",
+        };
+        f.write_str(name)
+    }
+}
+
+pub(crate) enum FakerAddress {
     Bpb(usize),
     FsInfo(usize),
-    Fat { cluster: u32, byte: u8 },
+    Reserved(usize),
+    Fat { offset: usize },
     RawData { cluster: u32, offset: usize },
 }
 
 impl FakerAddress {
+    pub fn region(&self) -> Region {
+        match self {
+            FakerAddress::Bpb(_) => Region::BootSector,
+            FakerAddress::FsInfo(_) => Region::FsInfo,
+            FakerAddress::Reserved(_) => Region::Reserved,
+            FakerAddress::Fat { .. } => Region::Fat,
+            FakerAddress::RawData { .. } => Region::Data,
+        }
+    }
+
     pub fn from_raw_idx(idx: usize, bpb: &BiosParameterBlock) -> Self {
-        // The first 1024 bytes are the BPB and the FSInfo
+        // The first two sectors start with the BPB and the FSInfo, which only
+        // FAT32 has.
+        let fs_info_start = bpb.fs_info_start();
         if idx < BiosParameterBlock::SIZE {
             FakerAddress::Bpb(idx)
-        } else if idx < BiosParameterBlock::SIZE + FsInfoSector::SIZE {
-            FakerAddress::FsInfo(idx - BiosParameterBlock::SIZE)
+        } else if bpb.fat_type == FatType::Fat32
+            && idx >= fs_info_start
+            && idx < fs_info_start + FsInfoSector::SIZE
+        {
+            FakerAddress::FsInfo(idx - fs_info_start)
+        }
+        // FAT32 keeps a backup copy of both of them further into the reserved
+        // sectors, laid out the same way.
+        else if let Some(copy) = bpb
+            .backup_boot_start()
+            .filter(|&backup| idx >= backup && idx < bpb.fat_start())
+            .and_then(|backup| match idx - backup {
+                offset if offset < BiosParameterBlock::SIZE => Some(FakerAddress::Bpb(offset)),
+                offset if (fs_info_start..fs_info_start + FsInfoSector::SIZE).contains(&offset) => {
+                    Some(FakerAddress::FsInfo(offset - fs_info_start))
+                }
+                _ => None,
+            })
+        {
+            copy
+        }
+        // The rest of the reserved sectors are unused.
+        else if idx < bpb.fat_start() {
+            FakerAddress::Reserved(idx)
         }
         // Next comes the table of allocations and chains, aka the File Allocation Table.
         else if idx >= bpb.fat_start() && idx < bpb.fat_end() {
-            // Gets the cluster that we need to get the entry of.
-            let cluster = idx_to_cluster(bpb, idx);
-            let byte = (idx % 4) as u8;
-            FakerAddress::Fat { cluster, byte }
+            // Every copy of the table holds the same entries.
+            let offset = fat_table_offset(bpb, idx);
+            FakerAddress::Fat { offset }
         } else {
-            let cluster_size = bpb.bytes_per_cluster() as usize;
-
-            // Our data starts where the FAT ends.
-            let data_begin_offset = bpb.fat_end();
-
-            // The cluster and path we are reading from.
-            let cluster = ((idx - data_begin_offset) / cluster_size) as u32;
-            let offset = (idx - data_begin_offset) % cluster_size;
+            // The cluster and path we are reading from; on FAT12 and FAT16 this
+            // may be a pseudo cluster of the fixed root directory region.
+            let cluster = offset_to_cluster(bpb, idx);
+            let offset = idx - cluster_to_offset(bpb, cluster);
             FakerAddress::RawData { cluster, offset }
         }
     }
@@ -339,11 +1377,23 @@ enum FakerDataAddress<F: FileOps, D: DirectoryOps> {
     },
     Directory {
         directory: D,
-        entry: usize,
+        /// The entry slots of the directory left in the cluster from the one
+        /// being read onwards.
+        entries: Range<usize>,
         offset: usize,
     },
 }
 
+/// The entry slots, counted from the start of a directory, that the cluster
+/// `position` clusters into the directory's chain holds.
+///
+/// Long File Name entries take up slots like any other entry, so a child's
+/// entries can be split across two clusters.
+fn dir_entry_range(bpb: &BiosParameterBlock, position: usize) -> Range<usize> {
+    let per_cluster = dirents_per_cluster(bpb);
+    position * per_cluster..(position + 1) * per_cluster
+}
+
 impl<D: DirectoryOps, F: FileOps> FakerDataAddress<F, D> {
     pub fn resolve_raw_data<
         MapType: ClusterMapperOps,
@@ -354,33 +1404,39 @@ impl<D: DirectoryOps, F: FileOps> FakerDataAddress<F, D> {
         bpb: &BiosParameterBlock,
         mapper: &MapType,
         fs: &mut FS,
-    ) -> Option<Self> {
+    ) -> Result<Option<Self>, FS::Error> {
         // We need to go from offset in the fake device to offset in the real file or directory.
         // To do so, we first convert from device offset to offset in this cluster chain.
-        let cluster_chain = mapper.get_chain_with_cluster(cluster).into_iter().flatten();
-        let clusters_previous = cluster_chain.take_while(|&c| c != cluster).count();
-        let byte_offset = clusters_previous * (bpb.bytes_per_cluster() as usize) + offset;
-        let path = mapper.get_path_for_cluster(cluster)?;
-        let meta = fs.get_metadata(path)?;
+        let (clusters_previous, path) = match (
+            mapper.chain_position(cluster),
+            mapper.get_path_for_cluster(cluster),
+        ) {
+            (Some(clusters_previous), Some(path)) => (clusters_previous, path),
+            _ => return Ok(None),
+        };
+        let byte_offset = chain_offset(bpb, clusters_previous, offset);
+        let meta = match fs.get_metadata(path)? {
+            Some(meta) => meta,
+            None => return Ok(None),
+        };
         if meta.is_directory {
-            Some(FakerDataAddress::Directory {
-                directory: fs.get_dir(path)?,
-                entry: byte_offset / ENTRY_SIZE,
-                offset: (byte_offset % ENTRY_SIZE),
-            })
+            // Only the slots of this cluster are rendered, so that a read never
+            // runs past the end of the cluster into the entries of the next one.
+            let entries = dir_entry_range(bpb, clusters_previous);
+            Ok(fs.get_dir(path)?.map(|directory| FakerDataAddress::Directory {
+                directory,
+                entries: entries.start + offset / DIRENT_SIZE..entries.end,
+                offset: (byte_offset % DIRENT_SIZE),
+            }))
         } else {
-            Some(FakerDataAddress::File {
-                file: fs.get_file(path)?,
+            Ok(fs.get_file(path)?.map(|file| FakerDataAddress::File {
+                file,
                 offset: byte_offset,
-            })
+            }))
         }
     }
 }
 
-pub use stdio::*;
-#[cfg(not(feature = "std"))]
-mod stdio {}
-
 #[cfg(feature = "std")]
 mod stdio {
     use super::*;
@@ -388,13 +1444,14 @@ mod stdio {
 
     impl<T: FileSystemOps> Read for FakeFat<T> {
         fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-            let mut cur_idx = 0;
-            while cur_idx < buf.len() {
-                buf[cur_idx] = self.read_byte(cur_idx + self.read_idx);
-                cur_idx += 1;
+            let buf = self.stream_buffer(self.read_idx as u64, buf);
+            let read = self.read_at(self.read_idx, buf);
+            // Reading nothing would look like the end of the device.
+            if read == 0 && !buf.is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into());
             }
-            self.read_idx += cur_idx;
-            Ok(cur_idx)
+            self.read_idx += read;
+            Ok(read)
         }
     }
     impl<T: FileSystemOps> Seek for FakeFat<T> {
@@ -403,39 +1460,86 @@ mod stdio {
                 SeekFrom::Start(abs) => {
                     self.read_idx = abs as usize;
                 }
-                SeekFrom::End(_back) => {
-                    return Err(io::Error::from(io::ErrorKind::InvalidInput));
+                SeekFrom::End(off) => {
+                    let end = self.len().checked_add_signed(off);
+                    self.read_idx = end.ok_or(io::ErrorKind::InvalidInput)? as usize;
                 }
                 SeekFrom::Current(off) => {
-                    if off < 0 {
-                        self.read_idx -= off.abs() as usize;
+                    self.read_idx = if off < 0 {
+                        self.read_idx.checked_sub(off.unsigned_abs() as usize)
                     } else {
-                        self.read_idx += off.abs() as usize;
+                        self.read_idx.checked_add(off.unsigned_abs() as usize)
                     }
+                    .ok_or(io::ErrorKind::InvalidInput)?;
                 }
             }
             Ok(self.read_idx as u64)
         }
     }
     impl<T: FileSystemOps> Write for FakeFat<T> {
-        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
-            Err(io::ErrorKind::PermissionDenied.into())
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            match self.write_at(self.read_idx, buf) {
+                Ok(written) => {
+                    self.read_idx += written;
+                    Ok(written)
+                }
+                // Writing nothing tells `write_all` the device is full.
+                Err(FakeFatError::OutOfRange { .. }) => Ok(0),
+                Err(FakeFatError::ChangeSetFull { .. }) => Err(io::ErrorKind::OutOfMemory.into()),
+                Err(_) => Err(io::ErrorKind::PermissionDenied.into()),
+            }
         }
         fn flush(&mut self) -> io::Result<()> {
-            Err(io::ErrorKind::PermissionDenied.into())
+            FakeFat::flush(self).map_err(|_| io::ErrorKind::OutOfMemory.into())
         }
     }
 
 }
 use crate::dirent::Fat32DirectoryEntry;
 
-struct DirectoryNewtype<T: DirectoryOps>(T);
+pub(crate) struct DirectoryNewtype<T: DirectoryOps>(T);
 impl<T: DirectoryOps> DirectoryNewtype<T> {
-    pub fn fat_entries(&self) -> impl Iterator<Item = (Fat32DirectoryEntry, Option<T::EntryType>)> {
-        let sys_entries = self.0.entries();
-        let fat_entries = sys_entries.into_iter().map(|ent| {
-            let dirents = file_to_direntries(ent.name().as_ref(), ent.meta());
-            (ent, dirents)
+    /// Renders the entries of the directory at the backing path `path`, whose
+    /// items are exposed under `names`, led by `label` if it is the root
+    /// directory of a labelled volume, leaving out the children `mapper` had no
+    /// room for.
+    pub fn fat_entries<'a>(
+        self,
+        naming: NamingOptions,
+        names: &'a DirNames,
+        mapper: &'a ClusterMapper,
+        path: &str,
+        label: Option<FileDirEntry>,
+    ) -> impl Iterator<Item = (Fat32DirectoryEntry, Option<T::EntryType>)> + 'a
+    where
+        T: 'a,
+    {
+        let sys_entries = self.0.listing();
+        let dir = self.0;
+        let dir_path = {
+            let mut tmp = PathBuff::empty();
+            tmp.add_subdir(path);
+            tmp
+        };
+        let mut short_names = DirShortNames::new(naming, names, &dir, dir_path.to_str());
+        let fat_entries = sys_entries.into_iter().filter_map(move |ent| {
+            let name = ent.name();
+            let (exposed, _) = names.get(&dir, dir_path.to_str(), &ent)?;
+            // Unplaced items still get their short names, so that the items
+            // after them get the same ones everywhere.
+            let short_name = short_names.next(exposed.as_ref());
+            let mut path = dir_path.clone();
+            path.add_file(name.as_ref());
+            let meta = naming.exposed_meta(path.to_str(), ent.meta());
+            if meta.is_directory {
+                path = dir_path.clone();
+                path.add_subdir(name.as_ref());
+            }
+            if is_unplaced(mapper, path.to_str(), &meta) {
+                return None;
+            }
+            let dirents = file_to_direntries(exposed.as_ref(), short_name, meta, naming);
+            Some((ent, dirents))
         });
         let unflattened = fat_entries.map(|(backing_ent, (file_fat_ent, name_ents))| {
             let name_ent_itr = name_ents
@@ -444,17 +1548,18 @@ impl<T: DirectoryOps> DirectoryNewtype<T> {
             let tail = (file_fat_ent.into(), Some(backing_ent));
             name_ent_itr.chain(Some(tail))
         });
-        unflattened.flatten()
+        let label = label.map(|ent| (ent.into(), None));
+        label.into_iter().chain(unflattened.flatten())
     }
 }
 
-fn fix_first_entry<'a, EntryType: DirEntryOps>(
+pub(crate) fn fix_first_entry<'a, EntryType: DirEntryOps>(
     mapper: &'a ClusterMapper,
     base_path: &str,
-) -> impl Fn((Fat32DirectoryEntry, Option<EntryType>)) -> ((Fat32DirectoryEntry, Option<EntryType>)) + 'a
+) -> impl Fn((Fat32DirectoryEntry, Option<EntryType>)) -> (Fat32DirectoryEntry, Option<EntryType>) + 'a
 {
     let base_pathbuff = {
-        let mut tmp = PathBuff::default();
+        let mut tmp = PathBuff::empty();
         tmp.add_subdir(base_path);
         tmp
     };
@@ -468,10 +1573,15 @@ fn fix_first_entry<'a, EntryType: DirEntryOps>(
                 full_path.add_file(full_name.as_ref());
             }
             let mut new_ent = file_ent;
-            new_ent.first_cluster = mapper
-                .get_chain_head_for_path(full_path.to_str())
-                .map(|c| c + 2 as u32) // Add 2 since FAT32 has 2 reserved clusters? I think?
-                .unwrap_or(FatEntryValue::Bad.into());
+            // Empty files point at cluster 0, even if they had a chain back when
+            // they were larger.
+            new_ent.first_cluster = if !file_ent.attrs.is_directory() && file_ent.size == 0 {
+                0
+            } else {
+                mapper
+                    .get_chain_head_for_path(full_path.to_str())
+                    .unwrap_or(0)
+            };
             (Fat32DirectoryEntry::File(new_ent), Some(backing))
         } else {
             pair
@@ -479,6 +1589,23 @@ fn fix_first_entry<'a, EntryType: DirEntryOps>(
     }
 }
 
+/// Sets the read-only attribute on every file entry if `enabled`, so that hosts
+/// that ignore the device-level write protection still treat the files as
+/// read-only.
+///
+/// Directories are left alone, since Windows uses the read-only attribute on a
+/// directory to mean that it has a customized view instead, and so is the
+/// volume label entry.
+pub(crate) fn mark_read_only(enabled: bool) -> impl Fn(Fat32DirectoryEntry) -> Fat32DirectoryEntry {
+    move |entry| match entry {
+        Fat32DirectoryEntry::File(mut file_ent) if enabled && file_ent.attrs.is_file() => {
+            file_ent.attrs = file_ent.attrs.and_read_only();
+            Fat32DirectoryEntry::File(file_ent)
+        }
+        other => other,
+    }
+}
+
 impl<T: DirectoryOps> From<T> for DirectoryNewtype<T> {
     fn from(unwrapped: T) -> DirectoryNewtype<T> {
         DirectoryNewtype(unwrapped)
@@ -495,19 +1622,15 @@ impl<T: DirectoryOps> AsRef<T> for DirectoryNewtype<T> {
     }
 }
 
-fn file_to_direntries(name: &str, meta: FileMetadata) -> (FileDirEntry, LfnChain) {
-    //TODO: check for duplications.
+fn file_to_direntries(
+    name: &str,
+    short_name: ShortName,
+    meta: FileMetadata,
+    naming: NamingOptions,
+) -> (FileDirEntry, LfnChain) {
     let mut fileent = meta.to_dirent();
-    let mut idx = Wrapping(0);
-    for (_charnum, bt) in name.as_bytes().iter().enumerate() {
-        let offset = bt.wrapping_sub(b'A');
-        let bottom_bits = offset & 0xF;
-        idx <<= 1;
-        idx ^= Wrapping(bottom_bits);
-    }
-    let short_name = ShortName::convert_str(name, idx.0);
     fileent.name = short_name;
-    let lfn_length = lfn_count_for_name(name);
+    let lfn_length = lfn_count(name, naming.case_flags);
     let mut allocation = LfnChain::default();
     construct_name_entries(name, fileent, &mut allocation.allocation);
     allocation.len = lfn_length;
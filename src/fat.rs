@@ -1,9 +1,17 @@
 use crate::bpb::BiosParameterBlock;
+use crate::geometry;
+use core::ops::RangeInclusive;
 
 const BAD_ENTRY: u32 = 0x0FFF_FFF7;
 const END_OF_CHAIN: u32 = 0x0FFF_FFFF;
 const FREE_ENTRY: u32 = 0;
 
+/// The bit of FAT entry 1 that FAT16 and FAT32 set when the volume was
+/// unmounted cleanly. The bit below it is set if no disk errors were hit, and
+/// the rest of the entry is an end of chain marker.
+const FAT16_CLEAN_SHUTDOWN: u32 = 0x8000;
+const FAT32_CLEAN_SHUTDOWN: u32 = 0x0800_0000;
+
 /// A single entry in the File Allocation Table, which corresponds to where
 /// a reader would jump to after finishing the current cluster.
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
@@ -47,11 +55,172 @@ impl From<FatEntryValue> for u32 {
 /// Converts a raw device offset to the index of the cluster whose entry is being
 /// searched.
 ///
-/// The `bpb` value is passed for the sake of the reserved byte count and FAT size.
+/// The `bpb` value is passed for the sake of the reserved byte count, FAT size
+/// and entry width. On FAT12, where a byte can hold parts of two entries, this
+/// is the first of them.
 pub fn idx_to_cluster(bpb: &BiosParameterBlock, idx: usize) -> u32 {
-    let reserved_sectors = bpb.reserved_sectors as usize;
-    let reserved_bytes = reserved_sectors * bpb.bytes_per_sector as usize;
-    let fat_offset = (idx - reserved_bytes) % bpb.sectors_per_fat_32 as usize;
-    let entry_cluster = fat_offset / 4;
-    entry_cluster as u32
+    geometry::offset_to_fat_entry(bpb, idx)
+}
+
+/// The kinds of FAT filesystem, which differ in the width of the entries in
+/// their File Allocation Tables.
+///
+/// Hosts decide which kind a volume is purely from its number of data clusters,
+/// so each kind only covers a fixed range of volume sizes for a given cluster
+/// size.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FatType {
+    /// 12 bit entries, for volumes of up to 4084 clusters.
+    Fat12,
+    /// 16 bit entries, for volumes of 4085 to 65524 clusters.
+    Fat16,
+    /// 32 bit entries, of which only the lower 28 bits are used, for volumes of
+    /// at least 65525 clusters.
+    Fat32,
+}
+
+impl FatType {
+    /// The number of bits in each File Allocation Table entry.
+    pub fn entry_bits(self) -> usize {
+        match self {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 32,
+        }
+    }
+
+    /// The fewest data clusters a volume of this type can have.
+    pub fn min_clusters(self) -> u32 {
+        match self {
+            FatType::Fat12 => 1,
+            FatType::Fat16 => 4085,
+            FatType::Fat32 => 65525,
+        }
+    }
+
+    /// The most data clusters a volume of this type can have.
+    pub fn max_clusters(self) -> u32 {
+        match self {
+            FatType::Fat12 => 4084,
+            FatType::Fat16 => 65524,
+            FatType::Fat32 => 0x0FFF_FFF5,
+        }
+    }
+
+    /// The type hosts will consider a volume with `clusters` data clusters to be.
+    pub fn for_cluster_count(clusters: u32) -> FatType {
+        if clusters <= FatType::Fat12.max_clusters() {
+            FatType::Fat12
+        } else if clusters <= FatType::Fat16.max_clusters() {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        }
+    }
+
+    /// The next larger type, if there is one.
+    pub(crate) fn larger(self) -> Option<FatType> {
+        match self {
+            FatType::Fat12 => Some(FatType::Fat16),
+            FatType::Fat16 => Some(FatType::Fat32),
+            FatType::Fat32 => None,
+        }
+    }
+
+    /// The bits of an entry that are actually used.
+    pub(crate) fn entry_mask(self) -> u32 {
+        match self {
+            FatType::Fat12 => 0x0FFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFF_FFFF,
+        }
+    }
+}
+
+impl FatEntryValue {
+    /// Encodes this value as an entry of a File Allocation Table of the given type.
+    pub(crate) fn to_raw(self, fat_type: FatType) -> u32 {
+        u32::from(self) & fat_type.entry_mask()
+    }
+
+    /// Decodes an entry of a File Allocation Table of the given type.
+    pub(crate) fn from_raw(raw: u32, fat_type: FatType) -> FatEntryValue {
+        let mask = fat_type.entry_mask();
+        let bad = mask - 8;
+        match raw & mask {
+            FREE_ENTRY => FatEntryValue::Free,
+            raw if raw == bad => FatEntryValue::Bad,
+            raw if raw > bad => FatEntryValue::End,
+            n => FatEntryValue::Next(n),
+        }
+    }
+}
+
+/// The raw value of the reserved File Allocation Table entry of `cluster`, which
+/// must be 0 or 1, on a volume whose media descriptor is `media`.
+///
+/// Entry 0 holds the media descriptor in its low byte with every other bit set,
+/// and entry 1 is an end of chain marker whose volume flags report the volume
+/// as free of errors, and as cleanly unmounted unless it is `dirty`. FAT12 has
+/// no volume flags.
+pub(crate) fn reserved_entry(fat_type: FatType, media: u8, cluster: u32, dirty: bool) -> u32 {
+    let mask = fat_type.entry_mask();
+    let clean_shutdown = match fat_type {
+        FatType::Fat12 => 0,
+        FatType::Fat16 => FAT16_CLEAN_SHUTDOWN,
+        FatType::Fat32 => FAT32_CLEAN_SHUTDOWN,
+    };
+    match cluster {
+        0 => (mask & !0xFF) | u32::from(media),
+        _ if dirty => mask & !clean_shutdown,
+        _ => mask,
+    }
+}
+
+/// The clusters whose File Allocation Table entries overlap the byte `offset`
+/// bytes into the table.
+///
+/// Entries are packed into the table as a little endian bit stream, so on FAT12
+/// a single byte can hold parts of two entries.
+pub(crate) fn entries_at(fat_type: FatType, offset: usize) -> RangeInclusive<u32> {
+    let bits = fat_type.entry_bits();
+    (offset * 8 / bits) as u32..=((offset * 8 + 7) / bits) as u32
+}
+
+/// The bits of the byte `offset` bytes into a File Allocation Table that come
+/// from the entry of `cluster`, whose raw value is `raw`.
+pub(crate) fn entry_byte(fat_type: FatType, cluster: u32, offset: usize, raw: u32) -> u8 {
+    let entry_start = cluster as usize * fat_type.entry_bits();
+    let byte_start = offset * 8;
+    let raw = u64::from(raw & fat_type.entry_mask());
+    let shifted = if entry_start >= byte_start {
+        raw << (entry_start - byte_start)
+    } else {
+        raw >> (byte_start - entry_start)
+    };
+    shifted as u8
+}
+
+/// Replaces the bits of the entry of `cluster`, whose raw value is `raw`, that
+/// are stored in the byte `offset` bytes into a File Allocation Table with the
+/// matching bits of `byte`, returning the entry's new raw value.
+pub(crate) fn patch_entry(
+    fat_type: FatType,
+    cluster: u32,
+    offset: usize,
+    raw: u32,
+    byte: u8,
+) -> u32 {
+    let entry_start = cluster as usize * fat_type.entry_bits();
+    let byte_start = offset * 8;
+    let (mask, value) = if byte_start >= entry_start {
+        let shift = byte_start - entry_start;
+        (0xFFu64 << shift, u64::from(byte) << shift)
+    } else {
+        let shift = entry_start - byte_start;
+        (0xFFu64 >> shift, u64::from(byte) >> shift)
+    };
+    let mask = mask & u64::from(fat_type.entry_mask());
+    ((u64::from(raw) & !mask) | (value & mask)) as u32
 }
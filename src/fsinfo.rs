@@ -5,21 +5,61 @@ use crate::ReadByte;
 pub struct FsInfoSector {
     free_count: u32,
     next_free: u32,
+    last_cluster: u32,
 }
 
-impl Default for FsInfoSector {
-    fn default() -> FsInfoSector {
+impl FsInfoSector {
+    /// The value reported in place of a hint hosts cannot use, telling them to
+    /// work it out for themselves.
+    pub const UNKNOWN: u32 = 0xFFFF_FFFF;
+
+    /// Constructs an FSInfo sector reporting `free_count` free clusters, with
+    /// hosts told to start looking for free clusters at `next_free` on a
+    /// volume whose last data cluster is `last_cluster`.
+    pub fn new(free_count: u32, next_free: u32, last_cluster: u32) -> FsInfoSector {
         FsInfoSector {
-            free_count: 0xFFFF_FFFF,
-            next_free: 0xFFFF_FFFF,
+            free_count,
+            next_free,
+            last_cluster,
+        }
+    }
+
+    /// The number of free clusters reported to hosts.
+    pub fn free_count(&self) -> u32 {
+        self.free_count
+    }
+
+    /// The cluster hosts are told to start looking for free clusters at, or
+    /// `UNKNOWN` if there is no free cluster at or after the hint, since hosts
+    /// do not wrap an out of range hint around themselves.
+    pub fn next_free(&self) -> u32 {
+        if self.free_count == 0 || self.next_free > self.last_cluster {
+            FsInfoSector::UNKNOWN
+        } else {
+            self.next_free
         }
     }
+
+    /// Keeps the hints up to date after `count` clusters were allocated, the
+    /// last of which is `last`.
+    pub fn allocated(&mut self, count: u32, last: u32) {
+        self.free_count = self.free_count.saturating_sub(count);
+        if self.next_free <= last {
+            self.next_free = last + 1;
+        }
+    }
+
+    /// Keeps the hints up to date after a cluster was freed.
+    pub fn freed(&mut self) {
+        self.free_count += 1;
+    }
 }
 
 impl ReadByte for FsInfoSector {
     const SIZE: usize = 512;
 
     fn read_byte(&self, idx: usize) -> u8 {
+        let next_free = self.next_free();
         match idx {
             0 => 0x52,
             1 => 0x52,
@@ -35,10 +75,10 @@ impl ReadByte for FsInfoSector {
             489 => ((self.free_count >> 8) & 0xFF) as u8,
             490 => ((self.free_count >> 16) & 0xFF) as u8,
             491 => ((self.free_count >> 24) & 0xFF) as u8,
-            492 => (self.next_free & 0xFF) as u8,
-            493 => ((self.next_free >> 8) & 0xFF) as u8,
-            494 => ((self.next_free >> 16) & 0xFF) as u8,
-            495 => ((self.next_free >> 24) & 0xFF) as u8,
+            492 => (next_free & 0xFF) as u8,
+            493 => ((next_free >> 8) & 0xFF) as u8,
+            494 => ((next_free >> 16) & 0xFF) as u8,
+            495 => ((next_free >> 24) & 0xFF) as u8,
             510 => 0x55,
             511 => 0xaa,
             _ => 0,
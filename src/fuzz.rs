@@ -0,0 +1,259 @@
+//! Entry points for fuzzing the read and write paths of a device, so that a
+//! project using the crate can add a `cargo fuzz` target with a single line:
+//!
+//! ```ignore
+//! fuzz_target!(|data: &[u8]| fakefat::fuzz::fuzz_read(data));
+//! ```
+//!
+//! The first two bytes of the input pick how the device is laid out and seed
+//! the contents of its files, which come from a fixed manifest of nested
+//! directories and files of assorted sizes. The rest of the input is a stream
+//! of operations, each an opcode byte, a little endian `u32` offset and a
+//! little endian `u16` length. Writes write the bytes that follow the
+//! operation, which are still read as the operations after it too.
+//! Offsets run a little past the end of the device so that reads past its end
+//! are covered too.
+//!
+//! Besides anything panicking, every operation checks what the device should
+//! always uphold, such as reads within the device reading everything they ask
+//! for and reading the same bytes every time, and panics if it does not. The
+//! `fuzz` feature turns on `debug_validate` as well, so the internal
+//! consistency of the device is checked after every write too.
+
+use crate::builder::FakeFatBuilder;
+use crate::faker::{FakeFat, OutOfRangeReads};
+use crate::fat::FatType;
+use crate::manifest::{DataProvider, ManifestEntry, ManifestFileSystem};
+use crate::sector::SectorError;
+use crate::traits::FileSystemOps;
+
+/// The largest read or write an operation makes.
+const MAX_OP_LEN: usize = 4096;
+
+/// The size of an operation, before the bytes it writes.
+const OP_SIZE: usize = 7;
+
+/// Runs a stream of reads against a device.
+pub fn fuzz_read(data: &[u8]) {
+    let provider = PatternProvider(data.get(1).copied().unwrap_or(0));
+    let entries = manifest();
+    let mut fake = device(data, &entries, &provider);
+    for op in Ops::new(data) {
+        check_read(&mut fake, &op);
+    }
+}
+
+/// Runs a stream of writes, and reads checking them, against a device.
+pub fn fuzz_write(data: &[u8]) {
+    let provider = PatternProvider(data.get(1).copied().unwrap_or(0));
+    let entries = manifest();
+    let mut fake = device(data, &entries, &provider);
+    for op in Ops::new(data) {
+        match op.code % 3 {
+            0 => check_write(&mut fake, &op),
+            1 => {
+                let idx = op.offset(&fake);
+                // Batched writes are only checked by the reads after them.
+                let _ = fake.write_byte(idx, op.payload.first().copied().unwrap_or(0));
+                if op.len % 2 == 0 {
+                    // Only a change set without an allocator can fill up.
+                    let flushed = fake.flush();
+                    assert!(flushed.is_ok() || cfg!(not(feature = "alloc")));
+                }
+            }
+            _ => check_read(&mut fake, &op),
+        }
+        assert!(fake.free_clusters() <= fake.cluster_count());
+    }
+}
+
+/// The items every fuzzed device exposes.
+fn manifest() -> [ManifestEntry<'static>; 9] {
+    [
+        ManifestEntry::file("EMPTY.TXT", 0),
+        ManifestEntry::file("tiny.txt", 1),
+        ManifestEntry::file("A long file name with spaces.data", 5000),
+        ManifestEntry::file("docs/readme.md", 513),
+        ManifestEntry::file("docs/nested/deeper/file.bin", 70_000),
+        ManifestEntry::file("docs/nested/other.bin", 4096),
+        ManifestEntry::directory("empty dir"),
+        ManifestEntry::file("logs/2024/01.log", 12_345),
+        ManifestEntry::file("logs/2024/02.log", 12_345),
+    ]
+}
+
+/// Lays out a device as picked by the first byte of the input.
+fn device<'a>(
+    data: &[u8],
+    entries: &'a [ManifestEntry<'a>],
+    provider: &'a PatternProvider,
+) -> FakeFat<ManifestFileSystem<'a, PatternProvider>> {
+    let config = data.first().copied().unwrap_or(0);
+    let mut builder = FakeFatBuilder::new()
+        .out_of_range_reads(match (config >> 2) & 0b11 {
+            1 => OutOfRangeReads::Error,
+            2 => OutOfRangeReads::Wrap,
+            _ => OutOfRangeReads::Zeros,
+        })
+        .dedup_files(config & 0b1_0000 != 0)
+        .write_protected(config & 0b1_0000 != 0);
+    builder = match config & 0b11 {
+        1 => builder.fat_type(FatType::Fat12),
+        2 => builder.fat_type(FatType::Fat16),
+        3 => builder.fat_type(FatType::Fat32),
+        _ => builder,
+    };
+    if config >> 5 != 7 {
+        builder = builder.sectors_per_cluster(1 << (config >> 5));
+    }
+    builder.build(ManifestFileSystem::new(entries, provider), "/")
+}
+
+/// Reads the device and checks what was read.
+fn check_read<T: FileSystemOps>(fake: &mut FakeFat<T>, op: &Op) {
+    let idx = op.offset(fake);
+    let len = op.len;
+    let image_size = fake.image_size();
+    let mut first = [0; MAX_OP_LEN];
+    let mut second = [0; MAX_OP_LEN];
+    match (op.code >> 2) % 3 {
+        0 => {
+            let read = fake.read_at(idx, &mut first[..len]);
+            let in_range = image_size.saturating_sub(idx).min(len);
+            let expected = match fake.out_of_range_reads {
+                OutOfRangeReads::Error => in_range,
+                OutOfRangeReads::Zeros | OutOfRangeReads::Wrap => len,
+            };
+            assert_eq!(read, expected, "read of {} bytes at {}", len, idx);
+            assert_eq!(fake.read_at(idx, &mut second[..len]), read);
+            assert_eq!(first[..read], second[..read], "reads at {} differ", idx);
+            if fake.out_of_range_reads == OutOfRangeReads::Zeros {
+                assert!(first[in_range..len].iter().all(|&byte| byte == 0));
+            }
+            if read > 0 {
+                assert_eq!(fake.read_byte(idx), first[0], "byte at {}", idx);
+            }
+        }
+        1 => {
+            let byte = fake.read_byte(idx);
+            if idx < image_size {
+                fake.read_at(idx, &mut first[..1]);
+                assert_eq!(byte, first[0], "byte at {}", idx);
+            }
+        }
+        _ => {
+            let sector_size = fake.sector_size();
+            let lba = (idx / sector_size) as u32;
+            let result = fake.read_sector(lba, &mut first[..sector_size]);
+            if lba < fake.sector_count() {
+                assert_eq!(result, Ok(()));
+                let start = lba as usize * sector_size;
+                assert_eq!(fake.read_at(start, &mut second[..sector_size]), sector_size);
+                assert_eq!(first[..sector_size], second[..sector_size]);
+            } else {
+                assert!(matches!(result, Err(SectorError::OutOfRange { .. })));
+            }
+        }
+    }
+}
+
+/// Writes to the device, checking that the write stops at the end of the
+/// device, and that what was written to the clusters reads back.
+fn check_write<T: FileSystemOps>(fake: &mut FakeFat<T>, op: &Op) {
+    let idx = op.offset(fake);
+    let written = match fake.write_at(idx, op.payload) {
+        Ok(written) => written,
+        Err(_) => return,
+    };
+    let expected = op.payload.len().min(fake.image_size() - idx);
+    // Without the `alloc` feature, the write can stop part way through once
+    // the change set is full.
+    if cfg!(feature = "alloc") {
+        assert_eq!(written, expected, "write of {} bytes at {}", expected, idx);
+    } else {
+        assert!(written <= expected, "write at {}", idx);
+    }
+    let mut read_back = [0; MAX_OP_LEN];
+    assert_eq!(fake.read_at(idx, &mut read_back[..written]), written);
+    let clusters = fake
+        .layout()
+        .bpb()
+        .fat_end()
+        .saturating_sub(idx)
+        .min(written);
+    assert_eq!(
+        read_back[clusters..written],
+        op.payload[clusters..written],
+        "write at {} does not read back",
+        idx
+    );
+}
+
+/// A single operation from the input.
+struct Op<'a> {
+    code: u8,
+    offset: u32,
+    /// The length of the operation, at most `MAX_OP_LEN`.
+    len: usize,
+    /// The bytes after the operation, up to its length, which writes write.
+    payload: &'a [u8],
+}
+
+impl Op<'_> {
+    /// The device offset the operation starts at, which is at most a little
+    /// past the end of the device.
+    fn offset<T: FileSystemOps>(&self, fake: &FakeFat<T>) -> usize {
+        let image_size = fake.image_size();
+        self.offset as usize % (image_size + image_size / 16 + 1)
+    }
+}
+
+/// The operations in the input, after its first two bytes.
+struct Ops<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Ops<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Ops {
+            rest: data.get(2..).unwrap_or(&[]),
+        }
+    }
+}
+
+impl<'a> Iterator for Ops<'a> {
+    type Item = Op<'a>;
+    fn next(&mut self) -> Option<Op<'a>> {
+        if self.rest.len() < OP_SIZE {
+            return None;
+        }
+        let (op, rest) = self.rest.split_at(OP_SIZE);
+        let len = usize::from(u16::from_le_bytes([op[5], op[6]])) % MAX_OP_LEN + 1;
+        let payload = &rest[..len.min(rest.len())];
+        self.rest = rest;
+        Some(Op {
+            code: op[0],
+            offset: u32::from_le_bytes([op[1], op[2], op[3], op[4]]),
+            len,
+            payload,
+        })
+    }
+}
+
+/// File contents that depend on the seed, the path and the offset, so that
+/// files and the clusters of a file all differ from each other.
+struct PatternProvider(u8);
+
+impl DataProvider for PatternProvider {
+    type Error = ();
+    fn read_at(&self, path: &str, offset: usize, buffer: &mut [u8]) -> Result<usize, ()> {
+        let salt = path
+            .bytes()
+            .fold(self.0, |acc, byte| acc.rotate_left(3) ^ byte);
+        for (idx, byte) in buffer.iter_mut().enumerate() {
+            let pos = offset + idx;
+            *byte = salt ^ (pos as u8) ^ ((pos >> 9) as u8);
+        }
+        Ok(buffer.len())
+    }
+}
@@ -0,0 +1,160 @@
+//! Hosts cache the contents of a file by the clusters it is in, and only read
+//! them again once its entry changes. A file rewritten in place with the same
+//! size, and with its modification timestamp preserved or too coarse to show
+//! the change, looks exactly the same to them.
+//!
+//! With `FakeFatBuilder::relocate_changed_files`, every file is hashed when it
+//! is first seen and on every `FakeFat::refresh`, and a file whose hash
+//! changed is moved to a new chain past every cluster handed out so far and
+//! given a modification timestamp newer than any it had, bumping its
+//! generation. The hashes, generations and timestamps are kept in the
+//! `DirVersions` table, keyed by the first cluster of each file's chain.
+
+use crate::changeset::ChangeSetOps;
+use crate::clustermapping::ClusterMapperOps;
+use crate::datetime::{fat_timestamp_key, next_fat_timestamp};
+use crate::dedup::content_hash;
+use crate::dircache::DirCacheOps;
+use crate::dirnames::{DirNames, DirNamesOps};
+use crate::dirversion::{DirVersion, DirVersionOps};
+use crate::faker::FakeFat;
+use crate::geometry::FIRST_DATA_CLUSTER;
+use crate::pathbuffer::PathBuff;
+use crate::preload::FileCacheOps;
+use crate::traits::{DirEntryOps, DirectoryListing, FileSystemLookup, FileSystemOps};
+
+impl<T: FileSystemOps> FakeFat<T> {
+    /// Gets the number of times `refresh` found the contents of the file at
+    /// `path` on the device changed, where `"/"` is the device's root
+    /// directory.
+    ///
+    /// Returns `None` if there is no such file on the device, if it is empty,
+    /// or if `FakeFatBuilder::relocate_changed_files` is disabled.
+    pub fn file_generation(&self, path: impl AsRef<str>) -> Option<u32> {
+        if !self.relocate_changed_files {
+            return None;
+        }
+        let mut backing_path = self.layout.prefix.clone();
+        let mut name = None;
+        for component in path.as_ref().split('/').filter(|c| !c.is_empty()) {
+            if let Some(dir_name) = name.replace(component) {
+                backing_path.add_subdir(dir_name);
+            }
+        }
+        backing_path.add_file(name?);
+        let cluster = self
+            .layout
+            .mapper
+            .get_chain_head_for_path(backing_path.to_str())?;
+        self.dir_versions.get(cluster).map(|v| v.version)
+    }
+
+    /// Hashes every file directly in the directory at the backing path `dir`,
+    /// relocating the ones whose hash changed since they were last hashed.
+    pub(crate) fn update_file_generations(&mut self, dir: &PathBuff) {
+        let listing = match self.fs.find_dir(dir.to_str()) {
+            Some(listing) => listing,
+            None => return,
+        };
+        let naming = self.layout.naming;
+        let names = DirNames::new(naming, &listing, dir.to_str());
+        let files = listing
+            .listing()
+            .filter(|ent| !ent.meta().is_directory)
+            .filter(|ent| names.get(&listing, dir.to_str(), ent).is_some());
+        for ent in files {
+            let mut path = dir.clone();
+            path.add_file(ent.name().as_ref());
+            let meta = naming.exposed_meta(path.to_str(), ent.meta());
+            let head = match self.layout.mapper.get_chain_head_for_path(path.to_str()) {
+                Some(head) => head,
+                None => continue,
+            };
+            let hash = match content_hash(&mut self.fs, path.to_str(), meta.size) {
+                Some(hash) => (hash ^ (hash >> 32)) as u32,
+                None => continue,
+            };
+            let previous = match self.dir_versions.get(head) {
+                Some(previous) => *previous,
+                None => {
+                    let initial = DirVersion {
+                        fingerprint: hash,
+                        ..DirVersion::default()
+                    };
+                    self.dir_versions.insert(head, initial);
+                    continue;
+                }
+            };
+            if previous.fingerprint == hash {
+                continue;
+            }
+            let newest = match previous.modified {
+                Some((date, time))
+                    if fat_timestamp_key(date, time)
+                        >= fat_timestamp_key(meta.modify_date, meta.modify_time) =>
+                {
+                    (date, time)
+                }
+                _ => (meta.modify_date, meta.modify_time),
+            };
+            let updated = DirVersion {
+                fingerprint: hash,
+                version: previous.version.wrapping_add(1),
+                modified: Some(next_fat_timestamp(newest.0, newest.1)),
+            };
+            let new_head = self.relocate(&path).unwrap_or(head);
+            if new_head != head {
+                self.dir_versions.remove(head);
+            }
+            self.dir_versions.insert(new_head, updated);
+        }
+    }
+
+    /// Moves the chain of the file at the backing path `path` to free clusters
+    /// past every cluster handed out so far, returning its new first cluster.
+    ///
+    /// Returns `None`, leaving the chain where it is, if the host changed any
+    /// of its clusters, if it is shared with another file, or if there are not
+    /// enough free clusters left past the last one handed out.
+    fn relocate(&mut self, path: &PathBuff) -> Option<u32> {
+        let path = path.to_str();
+        let len = self.layout.mapper.chain_len(path);
+        let mut old_chain = self.layout.mapper.get_chain_for_path(path).into_iter();
+        if old_chain.any(|cluster| self.changes.cluster_entry(cluster).is_some()) {
+            return None;
+        }
+        let last_cluster = FIRST_DATA_CLUSTER + self.layout.bpb.cluster_count() - 1;
+        let start = self.layout.max_cluster + 1;
+        let room = (start..=last_cluster)
+            .filter(|&c| self.is_free(c))
+            .take(len)
+            .count();
+        if room < len || !self.layout.mapper.truncate_chain(path, 0) {
+            return None;
+        }
+        let mut cluster = start;
+        let mut new_head = None;
+        for _ in 0..len {
+            while !self.is_free(cluster) {
+                cluster += 1;
+            }
+            self.layout.mapper.add_cluster_to_path(path, cluster);
+            new_head.get_or_insert(cluster);
+            cluster += 1;
+        }
+        let last = cluster - 1;
+        self.fsinfo.allocated(len as u32, last);
+        for _ in 0..len {
+            self.fsinfo.freed();
+        }
+        self.layout.max_cluster = last;
+        self.dir_cache.clear();
+        self.file_cache.clear();
+        new_head
+    }
+
+    /// Whether neither the backing tree nor the host uses `cluster`.
+    fn is_free(&self, cluster: u32) -> bool {
+        !self.layout.mapper.is_allocated(cluster) && self.changes.cluster_entry(cluster).is_none()
+    }
+}
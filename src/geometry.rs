@@ -0,0 +1,102 @@
+//! The arithmetic that places clusters, directory entries and File Allocation
+//! Table entries on a device, worked out from its `BiosParameterBlock`.
+//!
+//! The fake device uses these same helpers to decide what a host access
+//! touches, so adapters that need to know where on the device something lives,
+//! like a USB stack prioritizing FAT sectors or a tool patching an image, get
+//! exactly the answers the device itself would.
+//!
+//! FAT12 and FAT16 volumes keep their root directory in a fixed region between
+//! the FATs and the data clusters. Offsets in that region map to pseudo
+//! clusters numbered from `ROOT_REGION_CLUSTER`, which have no FAT entries.
+
+use crate::bpb::BiosParameterBlock;
+use crate::fat::{entries_at, FatType};
+
+/// All directory entries, whether a child entry, Long File Name chain link,
+/// or just empty, span exactly 32 bytes.
+pub const DIRENT_SIZE: usize = 32;
+
+/// The first cluster number that refers to the data region; FAT entries 0 and
+/// 1 are reserved.
+pub const FIRST_DATA_CLUSTER: u32 = 2;
+
+/// The pseudo cluster number the start of the fixed root directory region of a
+/// FAT12 or FAT16 volume is mapped as, with each following cluster-sized piece
+/// of the region numbered after it.
+///
+/// The region sits between the FATs and the data clusters and has no FAT
+/// entries, so these numbers are chosen to never collide with a real cluster.
+pub const ROOT_REGION_CLUSTER: u32 = 0x0FF0_0000;
+
+/// Returns the device offset the given cluster starts at.
+///
+/// Since FAT entries 0 and 1 are reserved, cluster 2 is the first cluster of
+/// the data section.
+pub fn cluster_to_offset(bpb: &BiosParameterBlock, cluster: u32) -> usize {
+    let cluster_size = bpb.bytes_per_cluster() as usize;
+    if cluster >= ROOT_REGION_CLUSTER {
+        bpb.fat_end() + (cluster - ROOT_REGION_CLUSTER) as usize * cluster_size
+    } else {
+        bpb.data_start() + (cluster - FIRST_DATA_CLUSTER) as usize * cluster_size
+    }
+}
+
+/// Returns the cluster containing the device offset `idx`, which has to be
+/// past the end of the final File Allocation Table.
+pub fn offset_to_cluster(bpb: &BiosParameterBlock, idx: usize) -> u32 {
+    let cluster_size = bpb.bytes_per_cluster() as usize;
+    if idx < bpb.data_start() {
+        ((idx - bpb.fat_end()) / cluster_size) as u32 + ROOT_REGION_CLUSTER
+    } else {
+        ((idx - bpb.data_start()) / cluster_size) as u32 + FIRST_DATA_CLUSTER
+    }
+}
+
+/// The cluster the root directory starts at, which is `ROOT_REGION_CLUSTER` on
+/// FAT12 and FAT16.
+pub fn root_dir_cluster(bpb: &BiosParameterBlock) -> u32 {
+    if bpb.fat_type == FatType::Fat32 {
+        bpb.root_dir_first_cluster
+    } else {
+        ROOT_REGION_CLUSTER
+    }
+}
+
+/// The number of directory entries that fit in a single cluster.
+pub fn dirents_per_cluster(bpb: &BiosParameterBlock) -> usize {
+    bpb.bytes_per_cluster() as usize / DIRENT_SIZE
+}
+
+/// The offset into a file or directory of the byte `offset` bytes into the
+/// cluster `position` clusters into its chain.
+pub fn chain_offset(bpb: &BiosParameterBlock, position: usize, offset: usize) -> usize {
+    position * bpb.bytes_per_cluster() as usize + offset
+}
+
+/// The offset into a File Allocation Table of the device offset `idx`, which
+/// has to be within one of the tables.
+///
+/// Every copy of the table holds the same entries, so offsets in later copies
+/// map to the same offsets as in the first.
+pub fn fat_table_offset(bpb: &BiosParameterBlock, idx: usize) -> usize {
+    (idx - bpb.fat_start()) % bpb.fat_bytes()
+}
+
+/// Returns the device offset of the first byte of the entry of `cluster` in the
+/// File Allocation Table with index `fat`.
+///
+/// On FAT12, the entries of odd clusters start in the upper half of this byte.
+pub fn fat_entry_offset(bpb: &BiosParameterBlock, fat: u8, cluster: u32) -> usize {
+    let entry_start = cluster as usize * bpb.fat_type.entry_bits() / 8;
+    bpb.fat_start() + usize::from(fat) * bpb.fat_bytes() + entry_start
+}
+
+/// Returns the cluster whose File Allocation Table entry the device offset
+/// `idx` holds, which has to be within one of the tables.
+///
+/// On FAT12, where a byte can hold parts of two entries, this is the first of
+/// them.
+pub fn offset_to_fat_entry(bpb: &BiosParameterBlock, idx: usize) -> u32 {
+    *entries_at(bpb.fat_type, fat_table_offset(bpb, idx)).start()
+}
@@ -0,0 +1,235 @@
+//! Heuristics for guessing what kind of host is driving the fake device.
+//!
+//! Different hosts have recognizable habits when they mount a FAT32 volume:
+//! Windows checks the backup boot sector and drops a `System Volume Information`
+//! directory, Linux flags the volume as dirty inside the boot sector itself, macOS
+//! litters the root with `.fseventsd` and friends, and camera firmware tends to
+//! skip FSInfo entirely and only ever writes plain 8.3 entries. None of these are
+//! definitive on their own, so the detector only counts what it sees and scores
+//! the totals when asked.
+
+use crate::bpb::BiosParameterBlock;
+use crate::fat::FatType;
+use crate::geometry::{fat_table_offset, DIRENT_SIZE};
+
+/// The number of sector reads after which a host that has never touched the
+/// FSInfo sector is assumed to not care about it.
+const CAMERA_READ_THRESHOLD: u32 = 16;
+
+/// Offset of the Linux "volume state" byte inside the boot sector.
+const LINUX_STATE_OFFSET: usize = 0x41;
+
+/// Offset of the same byte on FAT12 and FAT16, which have a shorter boot sector.
+const LINUX_STATE_OFFSET_FAT16: usize = 0x25;
+
+const WINDOWS_MARKERS: [&[u8; 8]; 1] = [b"SYSTEM~1"];
+const MACOS_MARKERS: [&[u8; 8]; 3] = [b"FSEVEN~1", b"SPOTLI~1", b"TRASHE~1"];
+
+/// The kind of host a `HostDetector` believes is accessing the device.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum HostGuess {
+    /// Not enough has happened yet to tell the hosts apart.
+    Unknown,
+    /// Looks like Windows.
+    Windows,
+    /// Looks like macOS.
+    MacOs,
+    /// Looks like the Linux `vfat` driver.
+    Linux,
+    /// Looks like a minimal embedded FAT implementation, such as the ones found
+    /// in cameras and other consumer devices.
+    CameraFirmware,
+}
+
+/// Counters of the characteristic accesses a `HostDetector` has seen so far.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct HostObservations {
+    /// The number of distinct sector reads seen.
+    pub sector_reads: u32,
+    /// The number of reads of the FSInfo sector.
+    pub fsinfo_reads: u32,
+    /// The number of writes to the FSInfo sector.
+    pub fsinfo_writes: u32,
+    /// The number of reads of the backup boot sector.
+    pub backup_boot_reads: u32,
+    /// The number of writes to the boot sector's volume state byte.
+    pub boot_state_writes: u32,
+    /// The number of writes to the reserved entries at the head of each copy of
+    /// the FAT.
+    pub reserved_fat_writes: u32,
+    /// The number of directory entries written with lowercase case flags set.
+    pub case_flag_entries: u32,
+    /// The number of Long File Name entries written.
+    pub lfn_entries: u32,
+    /// The number of plain 8.3 entries written.
+    pub short_entries: u32,
+    /// The number of entries written whose names only Windows creates.
+    pub windows_marker_entries: u32,
+    /// The number of entries written whose names only macOS creates.
+    pub macos_marker_entries: u32,
+}
+
+/// Watches the reads and writes made to a `FakeFat` and guesses which kind of
+/// host is on the other end.
+#[derive(Clone, Debug, Default)]
+pub struct HostDetector {
+    observations: HostObservations,
+    entry_base: usize,
+    entry_mask: u32,
+    entry_buffer: [u8; DIRENT_SIZE],
+    /// Whether the volume has an FSInfo sector to skip in the first place.
+    has_fsinfo: bool,
+}
+
+impl HostDetector {
+    /// Constructs a detector that has not seen any accesses yet.
+    pub fn new() -> Self {
+        HostDetector::default()
+    }
+
+    /// The counters accumulated so far.
+    pub fn observations(&self) -> &HostObservations {
+        &self.observations
+    }
+
+    /// Records a host read of the byte at device offset `idx`.
+    pub fn observe_read(&mut self, idx: usize, bpb: &BiosParameterBlock) {
+        let sector_size = bpb.bytes_per_sector as usize;
+        if !idx.is_multiple_of(sector_size) {
+            return;
+        }
+        let sector = idx / sector_size;
+        self.observations.sector_reads += 1;
+        // Only FAT32 has an FSInfo sector or a backup boot sector.
+        self.has_fsinfo = bpb.fat_type == FatType::Fat32;
+        if !self.has_fsinfo {
+            return;
+        }
+        if sector == bpb.fs_info_sector as usize {
+            self.observations.fsinfo_reads += 1;
+        } else if bpb.backup_boot_sector != 0 && sector == bpb.backup_boot_sector as usize {
+            self.observations.backup_boot_reads += 1;
+        }
+    }
+
+    /// Records a host write of `byte` to device offset `idx`.
+    pub fn observe_write(&mut self, idx: usize, byte: u8, bpb: &BiosParameterBlock) {
+        let sector_size = bpb.bytes_per_sector as usize;
+        let sector = idx / sector_size;
+        let entry_bits = bpb.fat_type.entry_bits();
+        let state_offset = if bpb.fat_type == FatType::Fat32 {
+            LINUX_STATE_OFFSET
+        } else {
+            LINUX_STATE_OFFSET_FAT16
+        };
+        if sector == 0 && idx == state_offset {
+            self.observations.boot_state_writes += 1;
+        } else if bpb.fat_type == FatType::Fat32
+            && sector == bpb.fs_info_sector as usize
+            && idx.is_multiple_of(sector_size)
+        {
+            self.observations.fsinfo_writes += 1;
+        } else if idx >= bpb.fat_start() && idx < bpb.fat_end() {
+            // Every copy of the table starts with the reserved entries, and
+            // only the writes to the byte each entry starts in are counted.
+            let offset = fat_table_offset(bpb, idx);
+            if offset == 0 || offset == entry_bits / 8 {
+                self.observations.reserved_fat_writes += 1;
+            }
+        } else if idx >= bpb.fat_end() {
+            self.observe_data_write(idx, byte, bpb);
+        }
+    }
+
+    fn observe_data_write(&mut self, idx: usize, byte: u8, bpb: &BiosParameterBlock) {
+        let entry_offset = (idx - bpb.fat_end()) % DIRENT_SIZE;
+        let entry_base = idx - entry_offset;
+        if entry_base != self.entry_base {
+            self.entry_base = entry_base;
+            self.entry_mask = 0;
+        }
+        self.entry_buffer[entry_offset] = byte;
+        self.entry_mask |= 1 << entry_offset;
+        if self.entry_mask == u32::MAX {
+            self.entry_mask = 0;
+            self.observe_entry();
+        }
+    }
+
+    fn observe_entry(&mut self) {
+        let raw = &self.entry_buffer;
+        let attrs = raw[11];
+        if attrs == 0x0F {
+            self.observations.lfn_entries += 1;
+            return;
+        }
+        let looks_like_entry = attrs & 0xC0 == 0
+            && raw[0] != 0
+            && raw[..11]
+                .iter()
+                .enumerate()
+                .all(|(idx, &c)| (0x20..0x7F).contains(&c) || (idx == 0 && c == 0xE5));
+        if !looks_like_entry {
+            return;
+        }
+        self.observations.short_entries += 1;
+        if raw[12] & 0x18 != 0 {
+            self.observations.case_flag_entries += 1;
+        }
+        let name = &raw[..8];
+        if WINDOWS_MARKERS.iter().any(|m| &m[..] == name) {
+            self.observations.windows_marker_entries += 1;
+        }
+        if MACOS_MARKERS.iter().any(|m| &m[..] == name) || name.starts_with(b"_") {
+            self.observations.macos_marker_entries += 1;
+        }
+    }
+
+    /// Scores the observations made so far and returns the most likely host.
+    pub fn guess(&self) -> HostGuess {
+        let obs = &self.observations;
+        let windows = 2 * u32::from(obs.backup_boot_reads > 0)
+            + 2 * u32::from(obs.reserved_fat_writes > 0)
+            + 3 * u32::from(obs.windows_marker_entries > 0)
+            + u32::from(obs.fsinfo_writes > 0);
+        let macos = 3 * u32::from(obs.macos_marker_entries > 0)
+            + u32::from(obs.lfn_entries > 0 && obs.case_flag_entries == 0);
+        let linux = 3 * u32::from(obs.boot_state_writes > 0)
+            + u32::from(obs.fsinfo_reads > 0 && obs.backup_boot_reads == 0);
+        let camera = 2
+            * u32::from(
+                self.has_fsinfo
+                    && obs.sector_reads >= CAMERA_READ_THRESHOLD
+                    && obs.fsinfo_reads == 0,
+            )
+            + 2 * u32::from(
+                obs.short_entries > 0 && obs.lfn_entries == 0 && obs.case_flag_entries == 0,
+            );
+
+        let scores = [
+            (windows, HostGuess::Windows),
+            (macos, HostGuess::MacOs),
+            (linux, HostGuess::Linux),
+            (camera, HostGuess::CameraFirmware),
+        ];
+        let (best_score, best_guess) = scores
+            .iter()
+            .copied()
+            .fold((0, HostGuess::Unknown), |best, cur| {
+                if cur.0 > best.0 {
+                    cur
+                } else {
+                    best
+                }
+            });
+        let tied = scores
+            .iter()
+            .filter(|(score, _)| *score == best_score)
+            .count();
+        if best_score == 0 || tied > 1 {
+            HostGuess::Unknown
+        } else {
+            best_guess
+        }
+    }
+}
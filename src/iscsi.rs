@@ -0,0 +1,698 @@
+//! A minimal iSCSI target, so that the fake device can be attached to any
+//! machine with an iSCSI initiator, without USB hardware or Linux-only tools:
+//!
+//! ```sh
+//! iscsiadm -m discovery -t sendtargets -p localhost
+//! iscsiadm -m node -T iqn.2020-01.com.github.ischeinkman:fakefat -p localhost --login
+//! ```
+//!
+//! The target has a single logical unit and handles the SCSI commands in
+//! `crate::scsi`. Only what every initiator supports is negotiated: no
+//! authentication, no digests, a single connection per session, error
+//! recovery level 0, and write data that is always solicited with R2Ts. Like
+//! `NbdServer`, connections are served one at a time.
+
+use crate::faker::FakeFat;
+use crate::ratelimit::read_throttled;
+use crate::scsi::{ScsiDisk, ScsiResponse};
+use crate::traits::FileSystemOps;
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::str;
+
+/// The port iSCSI targets listen on by default.
+pub const ISCSI_DEFAULT_PORT: u16 = 3260;
+
+/// The name the target uses unless `IscsiTarget::set_target_name` is called.
+pub const ISCSI_DEFAULT_TARGET_NAME: &str = "iqn.2020-01.com.github.ischeinkman:fakefat";
+
+const OP_NOP_OUT: u8 = 0x00;
+const OP_SCSI_COMMAND: u8 = 0x01;
+const OP_TASK_MANAGEMENT: u8 = 0x02;
+const OP_LOGIN: u8 = 0x03;
+const OP_TEXT: u8 = 0x04;
+const OP_DATA_OUT: u8 = 0x05;
+const OP_LOGOUT: u8 = 0x06;
+
+const OP_NOP_IN: u8 = 0x20;
+const OP_SCSI_RESPONSE: u8 = 0x21;
+const OP_TASK_MANAGEMENT_RESPONSE: u8 = 0x22;
+const OP_LOGIN_RESPONSE: u8 = 0x23;
+const OP_TEXT_RESPONSE: u8 = 0x24;
+const OP_DATA_IN: u8 = 0x25;
+const OP_LOGOUT_RESPONSE: u8 = 0x26;
+const OP_R2T: u8 = 0x31;
+const OP_REJECT: u8 = 0x3F;
+
+const FLAG_IMMEDIATE: u8 = 0x40;
+const FLAG_FINAL: u8 = 0x80;
+const FLAG_TRANSIT: u8 = 0x80;
+const FLAG_OVERFLOW: u8 = 0x04;
+const FLAG_UNDERFLOW: u8 = 0x02;
+
+const STAGE_FULL_FEATURE: u8 = 3;
+
+const LOGIN_SUCCESS: u16 = 0x0000;
+const LOGIN_TARGET_NOT_FOUND: u16 = 0x0203;
+
+const STATUS_GOOD: u8 = 0x00;
+const STATUS_CHECK_CONDITION: u8 = 0x02;
+const STATUS_BUSY: u8 = 0x08;
+
+const TASK_FUNCTION_COMPLETE: u8 = 0;
+const TASK_FUNCTION_NOT_SUPPORTED: u8 = 5;
+const TASK_TARGET_COLD_RESET: u8 = 7;
+
+const REJECT_COMMAND_NOT_SUPPORTED: u8 = 0x05;
+
+const RESERVED_TAG: u32 = 0xFFFF_FFFF;
+
+/// The size of a Basic Header Segment.
+const BHS_SIZE: usize = 48;
+
+/// The largest data segment the target accepts, which is also the number of
+/// bytes moved between the socket and the device at a time.
+const MAX_RECV_SEGMENT_LENGTH: usize = 64 * 1024;
+
+/// The largest number of bytes the target asks for with a single R2T.
+const MAX_BURST_LENGTH: usize = 256 * 1024;
+
+/// The largest data segment initiators accept unless they say otherwise.
+const DEFAULT_SEGMENT_LENGTH: usize = 8192;
+
+/// A single Protocol Data Unit.
+struct Pdu {
+    bhs: [u8; BHS_SIZE],
+    data: Vec<u8>,
+}
+
+impl Pdu {
+    fn opcode(&self) -> u8 {
+        self.bhs[0] & 0x3F
+    }
+
+    fn itt(&self) -> u32 {
+        self.u32_at(16)
+    }
+
+    fn u32_at(&self, idx: usize) -> u32 {
+        u32::from_be_bytes([
+            self.bhs[idx],
+            self.bhs[idx + 1],
+            self.bhs[idx + 2],
+            self.bhs[idx + 3],
+        ])
+    }
+
+    /// Iterates over the `key=value` pairs of the data segment.
+    fn text(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.data
+            .split(|&b| b == 0)
+            .filter_map(|pair| str::from_utf8(pair).ok())
+            .filter_map(|pair| {
+                let mut parts = pair.splitn(2, '=');
+                Some((parts.next()?, parts.next()?))
+            })
+    }
+}
+
+/// A WRITE command whose data is still arriving.
+struct PendingWrite {
+    itt: u32,
+    lun: [u8; 8],
+    offset: usize,
+    len: usize,
+    received: usize,
+    burst_end: usize,
+    ttt: u32,
+    r2t_sn: u32,
+    expected: usize,
+    actual: usize,
+    failed: bool,
+}
+
+/// The state of a single connection, and of the session it carries.
+struct Connection {
+    full_feature: bool,
+    discovery: bool,
+    portal_group_sent: bool,
+    stat_sn: u32,
+    exp_cmd_sn: u32,
+    max_send_segment: usize,
+    max_burst: usize,
+    next_ttt: u32,
+    pending: Option<PendingWrite>,
+}
+
+impl Connection {
+    fn new() -> Self {
+        Connection {
+            full_feature: false,
+            discovery: false,
+            portal_group_sent: false,
+            stat_sn: 0,
+            exp_cmd_sn: 0,
+            max_send_segment: DEFAULT_SEGMENT_LENGTH,
+            max_burst: MAX_BURST_LENGTH,
+            next_ttt: 0,
+            pending: None,
+        }
+    }
+
+    /// Fills in the sequence numbers of a response, advancing StatSN if the
+    /// response carries a status.
+    ///
+    /// The command window is closed while a WRITE is waiting for data, since
+    /// only one command is processed at a time.
+    fn stamp(&mut self, bhs: &mut [u8; BHS_SIZE], has_status: bool) {
+        let max_cmd_sn = if self.pending.is_some() {
+            self.exp_cmd_sn.wrapping_sub(1)
+        } else {
+            self.exp_cmd_sn
+        };
+        bhs[24..28].copy_from_slice(&self.stat_sn.to_be_bytes());
+        bhs[28..32].copy_from_slice(&self.exp_cmd_sn.to_be_bytes());
+        bhs[32..36].copy_from_slice(&max_cmd_sn.to_be_bytes());
+        if has_status {
+            self.stat_sn = self.stat_sn.wrapping_add(1);
+        }
+    }
+
+    fn next_ttt(&mut self) -> u32 {
+        let ttt = self.next_ttt;
+        self.next_ttt = self.next_ttt.wrapping_add(1) % RESERVED_TAG;
+        ttt
+    }
+
+    /// Answers an operational key both sides negotiate.
+    fn negotiate(&mut self, key: &str, value: &str, response: &mut Vec<u8>) {
+        let number = value.parse::<usize>().ok();
+        match key {
+            "HeaderDigest" | "DataDigest" => {
+                let none = value.split(',').any(|v| v == "None");
+                push_key(response, key, if none { "None" } else { "Reject" });
+            }
+            "MaxRecvDataSegmentLength" => {
+                self.max_send_segment = number.unwrap_or(DEFAULT_SEGMENT_LENGTH).max(512);
+                push_key(response, key, &MAX_RECV_SEGMENT_LENGTH.to_string());
+            }
+            "MaxBurstLength" | "FirstBurstLength" => {
+                let length = number
+                    .unwrap_or(MAX_BURST_LENGTH)
+                    .clamp(512, MAX_BURST_LENGTH);
+                if key == "MaxBurstLength" {
+                    self.max_burst = length;
+                }
+                push_key(response, key, &length.to_string());
+            }
+            "InitialR2T" | "DataPDUInOrder" | "DataSequenceInOrder" => {
+                push_key(response, key, "Yes")
+            }
+            "ImmediateData" | "IFMarker" | "OFMarker" => push_key(response, key, "No"),
+            "MaxOutstandingR2T" | "MaxConnections" => push_key(response, key, "1"),
+            "ErrorRecoveryLevel" | "DefaultTime2Retain" => push_key(response, key, "0"),
+            "DefaultTime2Wait" => push_key(response, key, value),
+            _ => push_key(response, key, "NotUnderstood"),
+        }
+    }
+}
+
+/// Serves a single `FakeFat` as an iSCSI target.
+pub struct IscsiTarget<T: FileSystemOps> {
+    fat: FakeFat<T>,
+    scsi: ScsiDisk,
+    name: String,
+}
+
+impl<T: FileSystemOps> IscsiTarget<T> {
+    /// Wraps the given device, naming the target `ISCSI_DEFAULT_TARGET_NAME`.
+    pub fn new(fat: FakeFat<T>) -> Self {
+        IscsiTarget {
+            fat,
+            scsi: ScsiDisk::new(),
+            name: ISCSI_DEFAULT_TARGET_NAME.to_owned(),
+        }
+    }
+
+    /// The iSCSI Qualified Name initiators log in to.
+    pub fn target_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Changes the iSCSI Qualified Name initiators log in to.
+    pub fn set_target_name(&mut self, name: &str) {
+        self.name = name.to_owned();
+    }
+
+    /// The wrapped device.
+    pub fn fat(&self) -> &FakeFat<T> {
+        &self.fat
+    }
+
+    /// The wrapped device.
+    pub fn fat_mut(&mut self) -> &mut FakeFat<T> {
+        &mut self.fat
+    }
+
+    /// Takes back ownership of the wrapped device.
+    pub fn into_inner(self) -> FakeFat<T> {
+        self.fat
+    }
+
+    /// Listens on `addr` and serves connections one at a time, forever.
+    ///
+    /// Errors on individual connections only end that connection; only
+    /// failures of the listening socket itself are returned.
+    pub fn listen<A: ToSocketAddrs>(&mut self, addr: A) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        loop {
+            let (stream, _) = listener.accept()?;
+            stream.set_nodelay(true)?;
+            let _ = self.serve(stream);
+        }
+    }
+
+    /// Runs the iSCSI protocol over `stream` until the initiator logs out or
+    /// disconnects.
+    ///
+    /// Both discovery sessions, which only list the target, and normal
+    /// sessions are supported. Each normal session is also a session on the
+    /// device, starting once the initiator has logged in.
+    pub fn serve<S: Read + Write>(&mut self, mut stream: S) -> io::Result<()> {
+        let mut conn = Connection::new();
+        let result = self.transmit(&mut stream, &mut conn);
+        if conn.full_feature && !conn.discovery {
+            self.fat
+                .session_end()
+                .map_err(|_| io::ErrorKind::OutOfMemory)?;
+        }
+        self.fat.flush().map_err(|_| io::ErrorKind::OutOfMemory)?;
+        match result {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(()),
+            other => other,
+        }
+    }
+
+    /// Handles PDUs until the connection should be closed.
+    fn transmit<S: Read + Write>(
+        &mut self,
+        stream: &mut S,
+        conn: &mut Connection,
+    ) -> io::Result<()> {
+        let mut buffer = vec![0u8; MAX_RECV_SEGMENT_LENGTH];
+        loop {
+            let pdu = read_pdu(stream)?;
+            let opcode = pdu.opcode();
+            if opcode == OP_LOGIN {
+                if !self.login(stream, conn, &pdu)? {
+                    return Ok(());
+                }
+                continue;
+            }
+            if !conn.full_feature {
+                return Err(io::ErrorKind::InvalidData.into());
+            }
+            let has_cmd_sn = opcode != OP_DATA_OUT;
+            if has_cmd_sn && pdu.bhs[0] & FLAG_IMMEDIATE == 0 {
+                conn.exp_cmd_sn = pdu.u32_at(24).wrapping_add(1);
+            }
+            match opcode {
+                OP_NOP_OUT if pdu.itt() != RESERVED_TAG => {
+                    let mut bhs = response_header(OP_NOP_IN, &pdu);
+                    bhs[20..24].copy_from_slice(&RESERVED_TAG.to_be_bytes());
+                    conn.stamp(&mut bhs, true);
+                    let len = pdu.data.len().min(conn.max_send_segment);
+                    write_pdu(stream, &mut bhs, &pdu.data[..len])?;
+                }
+                OP_NOP_OUT => {}
+                OP_SCSI_COMMAND => self.scsi_command(stream, conn, &pdu, &mut buffer)?,
+                OP_DATA_OUT => self.data_out(stream, conn, &pdu)?,
+                OP_TEXT => {
+                    let mut response = Vec::new();
+                    for (key, value) in pdu.text() {
+                        if key != "SendTargets" {
+                            conn.negotiate(key, value, &mut response);
+                        } else if value == "All" || value == self.name {
+                            push_key(&mut response, "TargetName", &self.name);
+                        }
+                    }
+                    let mut bhs = response_header(OP_TEXT_RESPONSE, &pdu);
+                    bhs[20..24].copy_from_slice(&RESERVED_TAG.to_be_bytes());
+                    conn.stamp(&mut bhs, true);
+                    write_pdu(stream, &mut bhs, &response)?;
+                }
+                OP_TASK_MANAGEMENT => {
+                    let function = pdu.bhs[1] & 0x7F;
+                    let mut bhs = response_header(OP_TASK_MANAGEMENT_RESPONSE, &pdu);
+                    bhs[2] = if function <= TASK_TARGET_COLD_RESET {
+                        conn.pending = None;
+                        TASK_FUNCTION_COMPLETE
+                    } else {
+                        TASK_FUNCTION_NOT_SUPPORTED
+                    };
+                    bhs[8..16].copy_from_slice(&[0; 8]);
+                    conn.stamp(&mut bhs, true);
+                    write_pdu(stream, &mut bhs, &[])?;
+                }
+                OP_LOGOUT => {
+                    let mut bhs = response_header(OP_LOGOUT_RESPONSE, &pdu);
+                    bhs[8..16].copy_from_slice(&[0; 8]);
+                    conn.stamp(&mut bhs, true);
+                    write_pdu(stream, &mut bhs, &[])?;
+                    stream.flush()?;
+                    return Ok(());
+                }
+                _ => {
+                    let mut bhs = [0u8; BHS_SIZE];
+                    bhs[0] = OP_REJECT;
+                    bhs[1] = FLAG_FINAL;
+                    bhs[2] = REJECT_COMMAND_NOT_SUPPORTED;
+                    bhs[16..20].copy_from_slice(&RESERVED_TAG.to_be_bytes());
+                    conn.stamp(&mut bhs, true);
+                    write_pdu(stream, &mut bhs, &pdu.bhs)?;
+                }
+            }
+            stream.flush()?;
+        }
+    }
+
+    /// Handles a single Login request.
+    ///
+    /// Returns whether the connection should stay open.
+    fn login<S: Write>(
+        &mut self,
+        stream: &mut S,
+        conn: &mut Connection,
+        pdu: &Pdu,
+    ) -> io::Result<bool> {
+        if conn.full_feature {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        conn.exp_cmd_sn = pdu.u32_at(24);
+        let transit = pdu.bhs[1] & FLAG_TRANSIT != 0;
+        let current_stage = (pdu.bhs[1] >> 2) & 0x03;
+        let next_stage = pdu.bhs[1] & 0x03;
+
+        let mut status = LOGIN_SUCCESS;
+        let mut response = Vec::new();
+        for (key, value) in pdu.text() {
+            match key {
+                "InitiatorName" | "InitiatorAlias" => {}
+                "SessionType" => conn.discovery = value == "Discovery",
+                "TargetName" if value != self.name => status = LOGIN_TARGET_NOT_FOUND,
+                "TargetName" => {}
+                "AuthMethod" => {
+                    let none = value.split(',').any(|v| v == "None");
+                    push_key(&mut response, key, if none { "None" } else { "Reject" });
+                }
+                _ => conn.negotiate(key, value, &mut response),
+            }
+        }
+        if !conn.discovery && !conn.portal_group_sent {
+            push_key(&mut response, "TargetPortalGroupTag", "1");
+            conn.portal_group_sent = true;
+        }
+
+        let done = status == LOGIN_SUCCESS && transit && next_stage == STAGE_FULL_FEATURE;
+        let mut bhs = [0u8; BHS_SIZE];
+        bhs[0] = OP_LOGIN_RESPONSE;
+        bhs[1] = current_stage << 2;
+        if status == LOGIN_SUCCESS && transit {
+            bhs[1] |= FLAG_TRANSIT | next_stage;
+        }
+        bhs[8..14].copy_from_slice(&pdu.bhs[8..14]);
+        if done {
+            bhs[14..16].copy_from_slice(&1u16.to_be_bytes());
+        }
+        bhs[16..20].copy_from_slice(&pdu.bhs[16..20]);
+        conn.stamp(&mut bhs, true);
+        bhs[36..38].copy_from_slice(&status.to_be_bytes());
+        write_pdu(stream, &mut bhs, &response)?;
+        stream.flush()?;
+        conn.full_feature = done;
+        if done && !conn.discovery {
+            self.fat
+                .session_start()
+                .map_err(|_| io::ErrorKind::OutOfMemory)?;
+        }
+        Ok(status == LOGIN_SUCCESS)
+    }
+
+    /// Handles a SCSI Command request.
+    fn scsi_command<S: Write>(
+        &mut self,
+        stream: &mut S,
+        conn: &mut Connection,
+        pdu: &Pdu,
+        buffer: &mut [u8],
+    ) -> io::Result<()> {
+        let expected = pdu.u32_at(20) as usize;
+        if conn.pending.is_some() {
+            return write_scsi_response(stream, conn, pdu, STATUS_BUSY, &[], 0, (0, 0));
+        }
+        match self.scsi.execute(&mut self.fat, &pdu.bhs[32..48]) {
+            ScsiResponse::Data { data, len } => {
+                let transfer = len.min(expected);
+                let data_sn = write_data_in(stream, conn, pdu, &data[..transfer], 0, 0, true)?;
+                let residual = residual(expected, len);
+                write_scsi_response(stream, conn, pdu, STATUS_GOOD, &[], data_sn, residual)
+            }
+            ScsiResponse::Read { offset, len } => {
+                let transfer = len.min(expected);
+                let chunk_size = buffer.len().min(conn.max_send_segment);
+                let mut sent = 0;
+                let mut data_sn = 0;
+                while sent < transfer {
+                    let burst_end = (sent / conn.max_burst + 1) * conn.max_burst;
+                    let chunk = (transfer.min(burst_end) - sent).min(chunk_size);
+                    read_throttled(
+                        |idx, buffer| self.fat.read_at(idx, buffer),
+                        offset + sent,
+                        &mut buffer[..chunk],
+                    );
+                    let last = sent + chunk == transfer;
+                    data_sn =
+                        write_data_in(stream, conn, pdu, &buffer[..chunk], sent, data_sn, last)?;
+                    sent += chunk;
+                }
+                let residual = residual(expected, len);
+                write_scsi_response(stream, conn, pdu, STATUS_GOOD, &[], data_sn, residual)
+            }
+            ScsiResponse::Write { offset, len } if len.min(expected) > 0 => {
+                let mut lun = [0u8; 8];
+                lun.copy_from_slice(&pdu.bhs[8..16]);
+                conn.pending = Some(PendingWrite {
+                    itt: pdu.itt(),
+                    lun,
+                    offset,
+                    len: len.min(expected),
+                    received: 0,
+                    burst_end: 0,
+                    ttt: 0,
+                    r2t_sn: 0,
+                    expected,
+                    actual: len,
+                    failed: false,
+                });
+                write_r2t(stream, conn)
+            }
+            ScsiResponse::Write { len, .. } => {
+                let residual = residual(expected, len);
+                write_scsi_response(stream, conn, pdu, STATUS_GOOD, &[], 0, residual)
+            }
+            ScsiResponse::Done => {
+                write_scsi_response(stream, conn, pdu, STATUS_GOOD, &[], 0, (0, 0))
+            }
+            ScsiResponse::Failed => {
+                let sense = self.scsi.sense_data();
+                let mut data = [0u8; 20];
+                data[0..2].copy_from_slice(&(sense.len() as u16).to_be_bytes());
+                data[2..].copy_from_slice(&sense);
+                write_scsi_response(stream, conn, pdu, STATUS_CHECK_CONDITION, &data, 0, (0, 0))
+            }
+        }
+    }
+
+    /// Handles a SCSI Data-Out request carrying data for the pending WRITE.
+    fn data_out<S: Write>(
+        &mut self,
+        stream: &mut S,
+        conn: &mut Connection,
+        pdu: &Pdu,
+    ) -> io::Result<()> {
+        let write = match conn.pending.as_mut() {
+            Some(write) if write.itt == pdu.itt() && write.ttt == pdu.u32_at(20) => write,
+            _ => return Ok(()),
+        };
+        let buffer_offset = pdu.u32_at(40) as usize;
+        let usable = write.len.saturating_sub(buffer_offset).min(pdu.data.len());
+        if usable > 0 && !write.failed {
+            let offset = write.offset + buffer_offset;
+            write.failed = !self.scsi.write(&mut self.fat, offset, &pdu.data[..usable]);
+        }
+        write.received += usable;
+        if pdu.bhs[1] & FLAG_FINAL == 0 {
+            return Ok(());
+        }
+        if write.received < write.len {
+            write.received = write.burst_end;
+            return write_r2t(stream, conn);
+        }
+
+        let mut write = conn.pending.take().unwrap();
+        write.failed |= self.fat.flush().is_err();
+        let mut bhs = [0u8; BHS_SIZE];
+        bhs[8..16].copy_from_slice(&write.lun);
+        bhs[16..20].copy_from_slice(&write.itt.to_be_bytes());
+        let request = Pdu {
+            bhs,
+            data: Vec::new(),
+        };
+        let residual = residual(write.expected, write.actual);
+        if write.failed {
+            let sense = self.scsi.sense_data();
+            let mut data = [0u8; 20];
+            data[0..2].copy_from_slice(&(sense.len() as u16).to_be_bytes());
+            data[2..].copy_from_slice(&sense);
+            write_scsi_response(
+                stream,
+                conn,
+                &request,
+                STATUS_CHECK_CONDITION,
+                &data,
+                0,
+                residual,
+            )
+        } else {
+            write_scsi_response(stream, conn, &request, STATUS_GOOD, &[], 0, residual)
+        }
+    }
+}
+
+/// Starts a response to `request`, copying over its LUN and Initiator Task Tag.
+fn response_header(opcode: u8, request: &Pdu) -> [u8; BHS_SIZE] {
+    let mut bhs = [0u8; BHS_SIZE];
+    bhs[0] = opcode;
+    bhs[1] = FLAG_FINAL;
+    bhs[8..20].copy_from_slice(&request.bhs[8..20]);
+    bhs
+}
+
+/// The residual flags and count of a command the initiator expected to move
+/// `expected` bytes for, but that would have moved `actual` bytes.
+fn residual(expected: usize, actual: usize) -> (u8, u32) {
+    if actual < expected {
+        (FLAG_UNDERFLOW, (expected - actual) as u32)
+    } else if actual > expected {
+        (FLAG_OVERFLOW, (actual - expected) as u32)
+    } else {
+        (0, 0)
+    }
+}
+
+/// Asks for the next burst of the pending WRITE's data.
+fn write_r2t<S: Write>(stream: &mut S, conn: &mut Connection) -> io::Result<()> {
+    let ttt = conn.next_ttt();
+    let max_burst = conn.max_burst;
+    let write = match conn.pending.as_mut() {
+        Some(write) => write,
+        None => return Ok(()),
+    };
+    let burst = (write.len - write.received).min(max_burst);
+    write.burst_end = write.received + burst;
+    write.ttt = ttt;
+    let mut bhs = [0u8; BHS_SIZE];
+    bhs[0] = OP_R2T;
+    bhs[1] = FLAG_FINAL;
+    bhs[8..16].copy_from_slice(&write.lun);
+    bhs[16..20].copy_from_slice(&write.itt.to_be_bytes());
+    bhs[20..24].copy_from_slice(&ttt.to_be_bytes());
+    bhs[36..40].copy_from_slice(&write.r2t_sn.to_be_bytes());
+    bhs[40..44].copy_from_slice(&(write.received as u32).to_be_bytes());
+    bhs[44..48].copy_from_slice(&(burst as u32).to_be_bytes());
+    write.r2t_sn += 1;
+    conn.stamp(&mut bhs, false);
+    write_pdu(stream, &mut bhs, &[])
+}
+
+/// Sends `data`, which starts `offset` bytes into the data of the command in
+/// `request`, as a single Data-In PDU, which ends a sequence if it is the
+/// `last` one or fills up a burst.
+///
+/// Returns the DataSN of the next Data-In PDU.
+fn write_data_in<S: Write>(
+    stream: &mut S,
+    conn: &mut Connection,
+    request: &Pdu,
+    data: &[u8],
+    offset: usize,
+    data_sn: u32,
+    last: bool,
+) -> io::Result<u32> {
+    if data.is_empty() {
+        return Ok(data_sn);
+    }
+    let end = offset + data.len();
+    let mut bhs = response_header(OP_DATA_IN, request);
+    if !last && !end.is_multiple_of(conn.max_burst) {
+        bhs[1] = 0;
+    }
+    bhs[20..24].copy_from_slice(&RESERVED_TAG.to_be_bytes());
+    conn.stamp(&mut bhs, false);
+    bhs[24..28].copy_from_slice(&[0; 4]);
+    bhs[36..40].copy_from_slice(&data_sn.to_be_bytes());
+    bhs[40..44].copy_from_slice(&(offset as u32).to_be_bytes());
+    write_pdu(stream, &mut bhs, data)?;
+    Ok(data_sn + 1)
+}
+
+/// Finishes the command in `request` with the given SCSI status.
+fn write_scsi_response<S: Write>(
+    stream: &mut S,
+    conn: &mut Connection,
+    request: &Pdu,
+    status: u8,
+    sense: &[u8],
+    data_sn: u32,
+    (residual_flags, residual_count): (u8, u32),
+) -> io::Result<()> {
+    let mut bhs = response_header(OP_SCSI_RESPONSE, request);
+    bhs[1] |= residual_flags;
+    bhs[3] = status;
+    bhs[8..16].copy_from_slice(&[0; 8]);
+    conn.stamp(&mut bhs, true);
+    bhs[36..40].copy_from_slice(&data_sn.to_be_bytes());
+    bhs[44..48].copy_from_slice(&residual_count.to_be_bytes());
+    write_pdu(stream, &mut bhs, sense)
+}
+
+fn push_key(response: &mut Vec<u8>, key: &str, value: &str) {
+    response.extend_from_slice(key.as_bytes());
+    response.push(b'=');
+    response.extend_from_slice(value.as_bytes());
+    response.push(0);
+}
+
+fn read_pdu<S: Read>(stream: &mut S) -> io::Result<Pdu> {
+    let mut bhs = [0u8; BHS_SIZE];
+    stream.read_exact(&mut bhs)?;
+    let ahs_len = usize::from(bhs[4]) * 4;
+    let data_len = u32::from_be_bytes([0, bhs[5], bhs[6], bhs[7]]) as usize;
+    if data_len > MAX_RECV_SEGMENT_LENGTH {
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+    let mut data = vec![0u8; ahs_len + data_len.next_multiple_of(4)];
+    stream.read_exact(&mut data)?;
+    data.drain(..ahs_len);
+    data.truncate(data_len);
+    Ok(Pdu { bhs, data })
+}
+
+fn write_pdu<S: Write>(stream: &mut S, bhs: &mut [u8; BHS_SIZE], data: &[u8]) -> io::Result<()> {
+    bhs[5..8].copy_from_slice(&(data.len() as u32).to_be_bytes()[1..]);
+    stream.write_all(bhs)?;
+    stream.write_all(data)?;
+    let padding = data.len().next_multiple_of(4) - data.len();
+    stream.write_all(&[0; 3][..padding])
+}
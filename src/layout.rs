@@ -0,0 +1,159 @@
+//! Deciding where everything goes on the device is kept apart from serving it.
+//! A `VolumeLayout` is computed once from a snapshot of the backing filesystem
+//! by `FakeFatBuilder::volume_layout`, and only records the geometry of the
+//! volume and which clusters each backing item is placed in; it never reads the
+//! backing filesystem itself.
+//!
+//! A `FakeFat` then pairs a layout with a live backing filesystem, which is
+//! where the contents of the clusters are read from. Since the layout only
+//! depends on the snapshot it was computed from, the same layout can be served
+//! from any backing filesystem holding the same tree, such as a mirror of it,
+//! and two layouts can be compared to find which clusters moved.
+
+use crate::bpb::BiosParameterBlock;
+use crate::clustermapping::{ClusterMapper, ClusterMapperOps};
+use crate::fat::FatType;
+use crate::fsinfo::FsInfoSector;
+use crate::geometry::FIRST_DATA_CLUSTER;
+use crate::pathbuffer::PathBuff;
+use crate::sanitize::NamingOptions;
+
+/// The mapping from backing items to clusters, and from clusters to bytes, of
+/// a fake FAT volume.
+#[derive(Clone)]
+pub struct VolumeLayout {
+    pub(crate) bpb: BiosParameterBlock,
+    pub(crate) mapper: ClusterMapper,
+    pub(crate) prefix: PathBuff,
+    pub(crate) naming: NamingOptions,
+    /// The last cluster allocated to a backing item.
+    pub(crate) max_cluster: u32,
+    /// The number of files sharing the chain of another file.
+    pub(crate) shared_files: u32,
+}
+
+impl VolumeLayout {
+    /// The BIOS Parameter Block of the volume, which decides where each of its
+    /// regions and clusters starts.
+    pub fn bpb(&self) -> &BiosParameterBlock {
+        &self.bpb
+    }
+
+    /// The path in the backing filesystem that maps to the root directory of
+    /// the volume.
+    pub fn prefix(&self) -> &str {
+        self.prefix.to_str()
+    }
+
+    /// The size of the volume in bytes.
+    pub fn image_size(&self) -> usize {
+        self.bpb.total_sectors_32 as usize * self.bpb.bytes_per_sector as usize
+    }
+
+    /// The number of data clusters on the volume.
+    pub fn cluster_count(&self) -> u32 {
+        self.bpb.cluster_count()
+    }
+
+    /// The number of data clusters allocated to backing items.
+    pub fn allocated_clusters(&self) -> u32 {
+        let root_region_clusters =
+            self.bpb.root_dir_sectors() / u32::from(self.bpb.sectors_per_cluster);
+        self.mapper.allocated_count() as u32 - root_region_clusters
+    }
+
+    /// The number of files that share the cluster chain of an earlier file with
+    /// the same contents instead of having one of their own, as enabled by
+    /// `FakeFatBuilder::dedup_files`.
+    pub fn shared_files(&self) -> u32 {
+        self.shared_files
+    }
+
+    /// The clusters the item at the backing path `path` is placed in, in
+    /// order. Directory paths end in a `/`.
+    ///
+    /// The iterator is empty if the item has no clusters, as is the case for
+    /// empty files and for items that are not on the volume.
+    pub fn chain(&self, path: &str) -> impl Iterator<Item = u32> {
+        self.mapper.get_chain_for_path(path).into_iter()
+    }
+
+    /// The backing path of the item `cluster` is allocated to, or `None` if the
+    /// cluster is free.
+    pub fn path_for_cluster(&self, cluster: u32) -> Option<&str> {
+        self.mapper.get_path_for_cluster(cluster)
+    }
+
+    /// The clusters that are allocated to a different item, or to a different
+    /// part of the same item, in `other` than they are in this layout.
+    ///
+    /// Only the placement of clusters is compared; if the two layouts have
+    /// different BIOS Parameter Blocks, the same cluster also lives at
+    /// different bytes of the two volumes.
+    pub fn diff<'a>(&'a self, other: &'a VolumeLayout) -> impl Iterator<Item = u32> + 'a {
+        let last = self.max_cluster.max(other.max_cluster);
+        (FIRST_DATA_CLUSTER..=last).filter(move |&cluster| {
+            self.path_for_cluster(cluster) != other.path_for_cluster(cluster)
+                || self.mapper.chain_position(cluster) != other.mapper.chain_position(cluster)
+        })
+    }
+
+    /// The FSInfo sector of a freshly served volume, reporting every cluster
+    /// not allocated to a backing item as free, and the first cluster after the
+    /// last allocated one as the place to look for them.
+    pub(crate) fn fsinfo(&self) -> FsInfoSector {
+        FsInfoSector::new(
+            self.cluster_count()
+                .saturating_sub(self.allocated_clusters()),
+            self.max_cluster + 1,
+            self.cluster_count() + FIRST_DATA_CLUSTER - 1,
+        )
+    }
+}
+
+/// The geometry a `VolumeLayout` would get, as estimated by
+/// `FakeFatBuilder::estimate_layout` from the metadata of the backing items
+/// alone.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LayoutEstimate {
+    /// The kind of FAT the volume would use.
+    pub fat_type: FatType,
+    /// The size of each sector in bytes.
+    pub bytes_per_sector: u16,
+    /// The number of sectors in each cluster.
+    pub sectors_per_cluster: u8,
+    /// The number of sectors on the volume.
+    pub total_sectors: u32,
+    /// The number of sectors each copy of the FAT takes up.
+    pub sectors_per_fat: u32,
+    /// The number of entries in the fixed root directory region of FAT12 and
+    /// FAT16 volumes, or 0 on FAT32.
+    pub root_entries: u16,
+    /// The number of data clusters on the volume.
+    pub cluster_count: u32,
+    /// The number of clusters directories would be allocated, not counting a
+    /// fixed root directory region.
+    pub directory_clusters: u32,
+    /// The number of clusters files would be allocated.
+    pub file_clusters: u32,
+}
+
+impl LayoutEstimate {
+    /// The size of the volume in bytes.
+    pub fn image_size(&self) -> u64 {
+        u64::from(self.total_sectors) * u64::from(self.bytes_per_sector)
+    }
+
+    /// The size of each cluster in bytes.
+    pub fn bytes_per_cluster(&self) -> u32 {
+        u32::from(self.bytes_per_sector) * u32::from(self.sectors_per_cluster)
+    }
+
+    /// Whether every backing item fits on the volume. Trees too large for even
+    /// the largest FAT32 volume would have some of their items left out.
+    pub fn fits(&self) -> bool {
+        u64::from(self.directory_clusters) + u64::from(self.file_clusters)
+            <= u64::from(self.cluster_count)
+    }
+}
@@ -1,5 +1,5 @@
 #![warn(missing_docs)]
-#![allow(clippy::identity_conversion)]
+#![allow(clippy::useless_conversion)]
 #![allow(clippy::or_fun_call)]
 #![cfg_attr(not(feature = "std"), no_std)]
 
@@ -31,23 +31,184 @@ pub use longname::*;
 mod fat;
 pub use fat::*;
 
+pub mod geometry;
+
 mod faker;
 pub use faker::*;
 
+mod builder;
+pub use builder::*;
+
+mod layout;
+pub use layout::{LayoutEstimate, VolumeLayout};
+
+mod split;
+pub use split::SplitFileSystem;
+
+mod manifest;
+pub use manifest::{
+    DataProvider, ManifestDir, ManifestDirEntry, ManifestDirIter, ManifestEntry, ManifestFile,
+    ManifestFileSystem,
+};
+
+mod chunks;
+pub use chunks::ImageChunks;
+
+mod cursor;
+pub use cursor::FakeFatCursor;
+
+mod lun;
+pub use lun::*;
+
+mod mbr;
+pub use mbr::MbrWrapped;
+
+mod device;
+pub use device::Device;
+
+#[cfg(target_has_atomic = "8")]
+mod planes;
+#[cfg(target_has_atomic = "8")]
+pub use planes::{ControlPlane, DataPlane, SharedFakeFat};
+
+mod sector;
+pub use sector::SectorError;
+
+mod aligned;
+pub use aligned::{Aligned, Alignment, A16, A32, A4, A64, A8};
+
+mod error;
+pub use error::FakeFatError;
+
+mod access;
+pub use access::{Access, Authorizer, Operation};
+
+mod session;
+pub use session::{SessionEvent, SessionListener, SessionStats};
+
+mod eject;
+
+mod remap;
+
+mod generation;
+
+mod readaudit;
+pub use readaudit::{ReadAudit, ReadRecord};
+
+mod validate;
+pub use validate::Inconsistency;
+
+mod volumeinfo;
+pub use volumeinfo::{VolumeIdentity, VolumeInfo};
+
+mod ratelimit;
+#[cfg(feature = "std")]
+pub use ratelimit::std_clock;
+pub use ratelimit::{Clock, PathRateLimits, RateLimit};
+
+mod sanitize;
+pub use sanitize::{BackingPath, HiddenEntries, NameAction, NameMapping, NamePolicy, NameReport};
+
+mod scanreport;
+pub use scanreport::ScanReport;
+
+mod scan;
+pub use scan::ScanState;
+
+#[cfg(feature = "embedded-io")]
+mod embeddedio;
+#[cfg(feature = "embedded-io")]
+pub use embeddedio::IoError;
+
+#[cfg(feature = "async")]
+mod asyncfat;
+#[cfg(feature = "async")]
+pub use asyncfat::{
+    AsyncDirectoryOps, AsyncFakeFat, AsyncFileOps, AsyncFileSystemOps, AsyncSnapshot, SnapshotDir,
+    SnapshotEntry, SnapshotFile,
+};
+
+#[cfg(feature = "embedded-sdmmc")]
+mod sdmmc;
+#[cfg(feature = "embedded-sdmmc")]
+pub use sdmmc::SdmmcBlockDevice;
+
+#[cfg(feature = "usb-storage")]
+mod usbstorage;
+#[cfg(feature = "usb-storage")]
+pub use usbstorage::UsbStorage;
+
+#[cfg(feature = "nbd")]
+mod nbd;
+#[cfg(feature = "nbd")]
+pub use nbd::{NbdSandboxServer, NbdServer, NBD_DEFAULT_PORT};
+
+#[cfg(any(feature = "usb-storage", feature = "usbip", feature = "iscsi"))]
+mod scsi;
+
+#[cfg(feature = "usbip")]
+mod usbip;
+#[cfg(feature = "usbip")]
+pub use usbip::{UsbIpServer, USBIP_BUS_ID, USBIP_DEFAULT_PORT};
+
+#[cfg(feature = "iscsi")]
+mod iscsi;
+#[cfg(feature = "iscsi")]
+pub use iscsi::{IscsiTarget, ISCSI_DEFAULT_PORT, ISCSI_DEFAULT_TARGET_NAME};
+
+#[cfg(feature = "std")]
+mod sandbox;
+#[cfg(feature = "std")]
+pub use sandbox::Sandbox;
+
 #[cfg(feature = "std")]
 mod stdimpl;
 #[cfg(feature = "std")]
-pub use stdimpl::StdFileSystem;
+pub use stdimpl::{StdDirectory, StdFileSystem, UnreadableItems};
+
+#[cfg(feature = "vfs")]
+mod vfsimpl;
+#[cfg(feature = "vfs")]
+pub use vfsimpl::VfsFileSystem;
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
 
 mod fsinfo;
 pub use fsinfo::*;
 
+mod hostdetect;
+pub use hostdetect::*;
+
 mod clustermapping;
 
 mod pathbuffer;
 
 mod changeset;
 
+mod dircache;
+
+mod preload;
+
+mod dedup;
+
+mod dirversion;
+
+mod shortnametable;
+
+mod dirnames;
+
+mod writebuffer;
+
+mod writeback;
+pub use writeback::WriteBackError;
+
+mod commitplan;
+pub use commitplan::{CommitOp, CommitPlan};
+
 /// Allows to use the structs that represent the sections of the fake filesystem
 /// as a byte slice without having to actually generate the byte slice, since 
 /// much of the time the array the section represents is mostly empty space. 
@@ -7,10 +7,17 @@ use crate::shortname::ShortName;
 /// Note that if `name` can be represented by a normal `ShortName`, this function
 /// will return 0.
 pub fn lfn_count_for_name(name: &str) -> usize {
-    if ShortName::wrap_str(name).is_some() {
-        return 0;
+    lfn_count(name, true)
+}
+
+/// The number of Long File Name directory entries needed to represent the given
+/// `name`, where `case_flags` is whether a `ShortName` may use its case flags to
+/// represent lowercase names.
+pub(crate) fn lfn_count(name: &str, case_flags: bool) -> usize {
+    match ShortName::wrap_str(name) {
+        Some(short_name) if case_flags || short_name.case_flag() == 0 => 0,
+        _ => name.encode_utf16().count().div_ceil(13),
     }
-    name.len() / 13 + if name.len() % 13 != 0 { 1 } else { 0 }
 }
 
 /// Constructs the Long File Name entries for the given `name` and associated File Entry `base`, storing
@@ -23,13 +30,14 @@ pub fn construct_name_entries<EntryType: From<LfnDirEntry>, BuffType: AsMut<[Ent
     base: FileDirEntry,
     mut allocation: BuffType,
 ) {
-    let entries_len = lfn_count_for_name(name);
+    // A base without case flags on a name that needs them means the case flags
+    // were turned off, so the name has to be spelled out in LFN entries instead.
+    let entries_len = lfn_count(name, base.name.case_flag() != 0);
     if entries_len == 0 {
         return;
     }
     let buff = allocation.as_mut();
     let checksum = base.name.lfn_checksum();
-    let entries_len = lfn_count_for_name(name);
     debug_assert!(
         entries_len > 0,
         "Got count-entry mismatch: {} for {}.",
@@ -43,17 +51,26 @@ pub fn construct_name_entries<EntryType: From<LfnDirEntry>, BuffType: AsMut<[Ent
         buff.len()
     );
 
-    for (idx, part) in name.as_bytes().chunks(13).enumerate() {
-        let mut newent = LfnDirEntry::default();
-        newent.entry_num = if idx == entries_len - 1 {
+    // The name is ended by a single NUL unit if it does not fill up its last
+    // entry, and the rest of that entry is padded with 0xFFFF.
+    let mut units = name
+        .encode_utf16()
+        .chain(Some(0x0000))
+        .chain(core::iter::repeat(0xFFFF));
+    for (idx, ent) in buff[..entries_len].iter_mut().enumerate() {
+        let entry_num = if idx == entries_len - 1 {
             0x40 | (1 + idx as u8)
         } else {
             1 + idx as u8
         };
-        newent.checksum = checksum;
-
-        let part_len = part.len();
-        (&mut newent.name_part[..part_len]).copy_from_slice(part);
-        buff[idx] = newent.into();
+        let mut newent = LfnDirEntry {
+            entry_num,
+            checksum,
+            ..LfnDirEntry::default()
+        };
+        for (unit, next) in newent.name_part.iter_mut().zip(&mut units) {
+            *unit = next;
+        }
+        *ent = newent.into();
     }
 }
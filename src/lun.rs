@@ -0,0 +1,101 @@
+//! USB Mass Storage devices can expose several independent units, addressed by
+//! their Logical Unit Number, behind a single interface. `MultiLun` owns one
+//! `FakeFat` per unit and dispatches reads and writes to them by LUN the same
+//! way Mass Storage class drivers do.
+
+use crate::error::FakeFatError;
+use crate::faker::FakeFat;
+use crate::traits::FileSystemOps;
+
+/// The most units a single Mass Storage interface can report.
+pub const MAX_LUNS: usize = 16;
+
+/// A set of `FakeFat` devices exposed as separate Logical Units.
+///
+/// Each unit keeps its own geometry and backing filesystem; LUN `n` is the
+/// `n`th device passed to `MultiLun::new`.
+pub struct MultiLun<T: FileSystemOps, const N: usize> {
+    luns: [FakeFat<T>; N],
+}
+
+impl<T: FileSystemOps, const N: usize> MultiLun<T, N> {
+    /// Constructs a set of Logical Units out of the given devices.
+    ///
+    /// # Panics
+    /// This function panics if there are no devices or more than `MAX_LUNS`.
+    pub fn new(luns: [FakeFat<T>; N]) -> Self {
+        assert!(
+            N > 0 && N <= MAX_LUNS,
+            "A Mass Storage interface needs between 1 and {} LUNs, not {}",
+            MAX_LUNS,
+            N
+        );
+        MultiLun { luns }
+    }
+
+    /// The number of Logical Units.
+    pub fn lun_count(&self) -> usize {
+        N
+    }
+
+    /// The highest valid LUN, as reported by the Mass Storage `GET MAX LUN`
+    /// request.
+    pub fn max_lun(&self) -> u8 {
+        (N - 1) as u8
+    }
+
+    /// Gets the device backing LUN `lun`, or `None` if there is no such unit.
+    pub fn lun(&self, lun: u8) -> Option<&FakeFat<T>> {
+        self.luns.get(usize::from(lun))
+    }
+
+    /// Mutably gets the device backing LUN `lun`, or `None` if there is no such
+    /// unit.
+    pub fn lun_mut(&mut self, lun: u8) -> Option<&mut FakeFat<T>> {
+        self.luns.get_mut(usize::from(lun))
+    }
+
+    /// Reads from LUN `lun` exactly like `FakeFat::read_at`, failing with
+    /// `FakeFatError::NoSuchUnit` if there is no such unit.
+    pub fn read(
+        &mut self,
+        lun: u8,
+        offset: usize,
+        buffer: &mut [u8],
+    ) -> Result<usize, FakeFatError> {
+        let fat = self.lun_mut(lun).ok_or(FakeFatError::NoSuchUnit { lun })?;
+        Ok(fat.read_at(offset, buffer))
+    }
+
+    /// Writes to LUN `lun` exactly like `FakeFat::write_at`, failing with
+    /// `FakeFatError::NoSuchUnit` if there is no such unit.
+    pub fn write(&mut self, lun: u8, offset: usize, data: &[u8]) -> Result<usize, FakeFatError> {
+        let fat = self.lun_mut(lun).ok_or(FakeFatError::NoSuchUnit { lun })?;
+        fat.write_at(offset, data)
+    }
+
+    /// The size in bytes of LUN `lun`, or `None` if there is no such unit.
+    pub fn image_size(&self, lun: u8) -> Option<usize> {
+        self.lun(lun).map(FakeFat::image_size)
+    }
+
+    /// Applies any batched writes on every unit.
+    ///
+    /// Every unit is flushed even if an earlier one fails, in which case the
+    /// first failure is returned.
+    pub fn flush(&mut self) -> Result<(), FakeFatError> {
+        let mut result = Ok(());
+        for fat in self.luns.iter_mut() {
+            let flushed = fat.flush();
+            if result.is_ok() {
+                result = flushed;
+            }
+        }
+        result
+    }
+
+    /// Takes back ownership of the devices.
+    pub fn into_inner(self) -> [FakeFat<T>; N] {
+        self.luns
+    }
+}
@@ -0,0 +1,279 @@
+//! Lets a device be built from a fixed catalog of its contents instead of by
+//! enumerating a live filesystem, which suits firmware that knows what it will
+//! expose at compile or configuration time.
+//!
+//! The catalog is a slice of `ManifestEntry`s giving the path, size and
+//! timestamps of every item, and the bytes of the files are read from a
+//! `DataProvider` when the host asks for them:
+//!
+//! ```ignore
+//! let entries = [
+//!     ManifestEntry::file("README.TXT", 120),
+//!     ManifestEntry::file("logs/boot.log", 4096).modified(date, time),
+//! ];
+//! let fat = FakeFat::new(ManifestFileSystem::new(&entries, &provider), "/");
+//! ```
+//!
+//! Paths are relative to the root of the device and separated by `/`. Every
+//! directory leading up to an entry exists whether or not it is listed, and
+//! gets the FAT epoch as its timestamps unless it is.
+
+use crate::datetime::{Date, Time};
+use crate::traits::{DirEntryOps, DirectoryOps, FileMetadata, FileOps, FileSystemOps};
+
+use core::fmt;
+use core::marker::PhantomData;
+
+/// Where the bytes of the files in a manifest come from.
+pub trait DataProvider {
+    /// The error the provider reports when it cannot read a file, which is
+    /// also the error of the `ManifestFileSystem` using it.
+    type Error: fmt::Debug;
+
+    /// Reads up to `buffer.len()` bytes of the file at `path`, as given in its
+    /// `ManifestEntry`, starting `offset` bytes into the file, returning the
+    /// number of bytes read.
+    ///
+    /// Reading fewer bytes than the file's size in the manifest leaves the rest
+    /// of the file zeroed on the device.
+    fn read_at(&self, path: &str, offset: usize, buffer: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+/// A single item of a manifest.
+#[derive(Copy, Clone, Debug)]
+pub struct ManifestEntry<'a> {
+    /// The path of the item, relative to the root of the device.
+    pub path: &'a str,
+    /// The metadata the item is exposed with.
+    pub meta: FileMetadata,
+}
+
+impl<'a> ManifestEntry<'a> {
+    /// A file `size` bytes long with the FAT epoch as its timestamps.
+    pub fn file(path: &'a str, size: u32) -> Self {
+        let meta = FileMetadata {
+            size,
+            ..FileMetadata::default()
+        };
+        ManifestEntry { path, meta }
+    }
+
+    /// A directory with the FAT epoch as its timestamps.
+    pub fn directory(path: &'a str) -> Self {
+        let meta = FileMetadata {
+            is_directory: true,
+            ..FileMetadata::default()
+        };
+        ManifestEntry { path, meta }
+    }
+
+    /// Sets when the item was created, modified and last accessed.
+    pub fn modified(mut self, date: Date, time: Time) -> Self {
+        self.meta.create_date = date;
+        self.meta.create_time = time;
+        self.meta.modify_date = date;
+        self.meta.modify_time = time;
+        self.meta.access_date = date;
+        self
+    }
+
+    /// The path with any leading or trailing separators removed.
+    fn trimmed_path(&self) -> &'a str {
+        self.path.trim_matches('/')
+    }
+}
+
+/// An implementation of `FileSystemOps` exposing the items of a manifest, with
+/// the contents of its files read from a `DataProvider`.
+///
+/// Lookups search the manifest linearly, so very large catalogs are better off
+/// with a backend that indexes them.
+pub struct ManifestFileSystem<'a, P: DataProvider> {
+    entries: &'a [ManifestEntry<'a>],
+    provider: &'a P,
+}
+
+impl<'a, P: DataProvider> ManifestFileSystem<'a, P> {
+    /// Exposes `entries`, reading the contents of the files from `provider`.
+    pub fn new(entries: &'a [ManifestEntry<'a>], provider: &'a P) -> Self {
+        ManifestFileSystem { entries, provider }
+    }
+
+    /// The items being exposed.
+    pub fn entries(&self) -> &'a [ManifestEntry<'a>] {
+        self.entries
+    }
+
+    fn find(&self, path: &str) -> Option<&'a ManifestEntry<'a>> {
+        let path = path.trim_matches('/');
+        self.entries
+            .iter()
+            .find(|entry| entry.trimmed_path() == path)
+    }
+
+    /// The path of the directory at `path` as a part of the path of an entry
+    /// below it, if there is one, in which case it is a directory even if it is
+    /// not listed itself.
+    fn implied_dir(&self, path: &str) -> Option<&'a str> {
+        let path = path.trim_matches('/');
+        if path.is_empty() {
+            return Some("");
+        }
+        self.entries
+            .iter()
+            .map(ManifestEntry::trimmed_path)
+            .find(|below| relative_path(path, below).is_some())
+            .map(|below| &below[..path.len()])
+    }
+}
+
+// Looking items up in the manifest itself cannot fail; only reading the
+// contents of files from the provider can.
+impl<'a, P: DataProvider> FileSystemOps for ManifestFileSystem<'a, P> {
+    type DirectoryType = ManifestDir<'a, P>;
+    type FileType = ManifestFile<'a, P>;
+    type Error = P::Error;
+
+    fn get_file(&mut self, path: &str) -> Result<Option<Self::FileType>, P::Error> {
+        let file = self
+            .find(path)
+            .filter(|entry| !entry.meta.is_directory)
+            .map(|entry| ManifestFile {
+                path: entry.path,
+                provider: self.provider,
+            });
+        Ok(file)
+    }
+    fn get_dir(&mut self, path: &str) -> Result<Option<Self::DirectoryType>, P::Error> {
+        let path = match self.find(path) {
+            Some(entry) if entry.meta.is_directory => Some(entry.trimmed_path()),
+            Some(_) => None,
+            None => self.implied_dir(path),
+        };
+        Ok(path.map(|path| ManifestDir {
+            entries: self.entries,
+            path,
+            provider: PhantomData,
+        }))
+    }
+    fn get_metadata(&mut self, path: &str) -> Result<Option<FileMetadata>, P::Error> {
+        match self.find(path) {
+            Some(entry) => Ok(Some(entry.meta)),
+            None => Ok(self.implied_dir(path).map(|_| implied_dir_meta())),
+        }
+    }
+}
+
+/// A file of a `ManifestFileSystem`.
+pub struct ManifestFile<'a, P: DataProvider> {
+    path: &'a str,
+    provider: &'a P,
+}
+
+impl<'a, P: DataProvider> FileOps for ManifestFile<'a, P> {
+    type Error = P::Error;
+
+    fn read_at(&mut self, offset: usize, buffer: &mut [u8]) -> Result<usize, P::Error> {
+        self.provider.read_at(self.path, offset, buffer)
+    }
+}
+
+/// A directory of a `ManifestFileSystem`.
+pub struct ManifestDir<'a, P: DataProvider> {
+    entries: &'a [ManifestEntry<'a>],
+    path: &'a str,
+    provider: PhantomData<&'a P>,
+}
+
+impl<'a, P: DataProvider> DirectoryOps for ManifestDir<'a, P> {
+    type EntryType = ManifestDirEntry<'a>;
+    type IterType = ManifestDirIter<'a>;
+    type Error = P::Error;
+
+    fn entries(&self) -> Result<ManifestDirIter<'a>, P::Error> {
+        Ok(ManifestDirIter {
+            entries: self.entries,
+            path: self.path,
+            idx: 0,
+        })
+    }
+}
+
+/// Iterates over the children of a `ManifestDir`, including the directories
+/// that are only implied by the paths of their own children.
+pub struct ManifestDirIter<'a> {
+    entries: &'a [ManifestEntry<'a>],
+    path: &'a str,
+    idx: usize,
+}
+
+impl<'a> Iterator for ManifestDirIter<'a> {
+    type Item = ManifestDirEntry<'a>;
+
+    fn next(&mut self) -> Option<ManifestDirEntry<'a>> {
+        while let Some(entry) = self.entries.get(self.idx) {
+            self.idx += 1;
+            let name = match child_name(self.path, entry.trimmed_path()) {
+                Some(name) => name,
+                None => continue,
+            };
+            // Every child is listed at the first entry it shows up in, whether
+            // that is its own entry or one of an item below it.
+            let seen = self.entries[..self.idx - 1]
+                .iter()
+                .any(|earlier| child_name(self.path, earlier.trimmed_path()) == Some(name));
+            if seen {
+                continue;
+            }
+            // The child's own entry may come after the entry of an item below it.
+            let meta = self.entries[self.idx - 1..]
+                .iter()
+                .find(|own| relative_path(self.path, own.trimmed_path()) == Some(name))
+                .map_or_else(implied_dir_meta, |own| own.meta);
+            return Some(ManifestDirEntry { name, meta });
+        }
+        None
+    }
+}
+
+/// A child of a `ManifestDir`.
+pub struct ManifestDirEntry<'a> {
+    name: &'a str,
+    meta: FileMetadata,
+}
+
+impl<'a> DirEntryOps for ManifestDirEntry<'a> {
+    type NameType = &'a str;
+
+    fn name(&self) -> &'a str {
+        self.name
+    }
+    fn meta(&self) -> FileMetadata {
+        self.meta
+    }
+}
+
+/// The part of `path` below the directory `dir`, or `None` if `path` is not
+/// below `dir`. Both paths are trimmed of separators.
+fn relative_path<'p>(dir: &str, path: &'p str) -> Option<&'p str> {
+    let rest = if dir.is_empty() {
+        path
+    } else {
+        path.strip_prefix(dir)?.strip_prefix('/')?
+    };
+    Some(rest).filter(|rest| !rest.is_empty())
+}
+
+/// The name of the child of the directory `dir` that `path` is or is below,
+/// or `None` if `path` is not below `dir`. Both paths are trimmed of separators.
+fn child_name<'p>(dir: &str, path: &'p str) -> Option<&'p str> {
+    relative_path(dir, path)?.split('/').next()
+}
+
+/// The metadata of a directory that is not listed in the manifest itself.
+fn implied_dir_meta() -> FileMetadata {
+    FileMetadata {
+        is_directory: true,
+        ..FileMetadata::default()
+    }
+}
@@ -0,0 +1,257 @@
+//! Windows treats removable devices without a partition table, known as
+//! superfloppies, with suspicion, and some of its tools refuse to touch them.
+//! `MbrWrapped` puts a Master Boot Record in front of a `FakeFat` so that the
+//! device looks like a normal disk with a single partition holding the volume.
+
+use crate::bpb::BiosParameterBlock;
+use crate::error::FakeFatError;
+use crate::faker::{FakeFat, Region};
+use crate::fat::FatType;
+use crate::traits::FileSystemOps;
+
+/// The number of sectors before the partition, which is just the MBR itself.
+const PARTITION_START: u32 = 1;
+
+/// The offset of the optional disk signature in the MBR.
+const DISK_SIGNATURE_OFFSET: usize = 440;
+
+/// The offset of the first entry of the partition table in the MBR.
+const PARTITION_ENTRY_OFFSET: usize = 446;
+
+/// The offset of the `0x55 0xAA` boot signature in the MBR.
+const BOOT_SIGNATURE_OFFSET: usize = 510;
+
+/// A `FakeFat` behind a Master Boot Record with a single partition.
+///
+/// The MBR takes up the first sector of the device and the volume starts right
+/// after it, so every byte address of the volume is shifted by one sector. The
+/// volume's `hidden_sectors` field is set to match, as hosts expect of a
+/// partitioned volume.
+pub struct MbrWrapped<T: FileSystemOps> {
+    fat: FakeFat<T>,
+    #[allow(unused)]
+    read_idx: usize,
+}
+
+impl<T: FileSystemOps> MbrWrapped<T> {
+    /// Wraps `fat` in a partition table.
+    pub fn new(mut fat: FakeFat<T>) -> Self {
+        fat.layout.bpb.hidden_sectors = PARTITION_START;
+        MbrWrapped { fat, read_idx: 0 }
+    }
+
+    /// The wrapped volume.
+    pub fn inner(&self) -> &FakeFat<T> {
+        &self.fat
+    }
+
+    /// Mutably gets the wrapped volume, whose addresses start at the partition
+    /// rather than at the head of the device.
+    pub fn inner_mut(&mut self) -> &mut FakeFat<T> {
+        &mut self.fat
+    }
+
+    /// Takes back ownership of the volume, which no longer has any hidden
+    /// sectors before it.
+    pub fn into_inner(mut self) -> FakeFat<T> {
+        self.fat.layout.bpb.hidden_sectors = 0;
+        self.fat
+    }
+
+    /// The size of a sector, in bytes.
+    pub fn sector_size(&self) -> usize {
+        self.fat.sector_size()
+    }
+
+    /// The number of sectors on the device, including the MBR.
+    pub fn sector_count(&self) -> u32 {
+        self.fat.sector_count().saturating_add(PARTITION_START)
+    }
+
+    /// The disk signature in the MBR, which Windows remembers the drive letter
+    /// of the partition by, and which is the volume serial number.
+    pub fn disk_signature(&self) -> u32 {
+        self.fat.volume_id()
+    }
+
+    /// The size of the device in bytes, as reported to the host.
+    pub fn image_size(&self) -> usize {
+        self.partition_start() + self.fat.image_size()
+    }
+
+    /// The length of the device in bytes, including the MBR, which is where
+    /// `Read` reaches its end.
+    pub fn len(&self) -> u64 {
+        self.partition_start() as u64 + self.fat.len()
+    }
+
+    /// Whether the device has no bytes at all, which never happens.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reads a single byte out of the device, exactly `idx` bytes from the head
+    /// of the device.
+    pub fn read_byte(&mut self, idx: usize) -> u8 {
+        match idx.checked_sub(self.partition_start()) {
+            Some(volume_idx) => self.fat.read_byte(volume_idx),
+            None => self.mbr_byte(idx),
+        }
+    }
+
+    /// Reads up to `buffer.len()` bytes out of the device starting `idx` bytes
+    /// from the head of the device, returning the number of bytes read.
+    pub fn read_at(&mut self, idx: usize, buffer: &mut [u8]) -> usize {
+        let start = self.partition_start();
+        let mut read = 0;
+        while idx + read < start && read < buffer.len() {
+            buffer[read] = self.mbr_byte(idx + read);
+            read += 1;
+        }
+        if read == buffer.len() {
+            return read;
+        }
+        read + self.fat.read_at(idx + read - start, &mut buffer[read..])
+    }
+
+    /// Writes a single byte into the device, exactly `idx` bytes from the head
+    /// of the device, just like `FakeFat::write_byte`.
+    ///
+    /// Fails without writing anything if the address is part of the MBR, or if
+    /// the volume rejects the write.
+    pub fn write_byte(&mut self, idx: usize, new_byte: u8) -> Result<(), FakeFatError> {
+        let volume_idx = self.volume_idx(idx)?;
+        self.fat.write_byte(volume_idx, new_byte)
+    }
+
+    /// Writes `data` into the device starting `idx` bytes from the head of the
+    /// device, returning the number of bytes written, just like
+    /// `FakeFat::write_at`.
+    ///
+    /// Fails without writing anything if the write starts in the MBR, or if
+    /// the volume rejects the write.
+    pub fn write_at(&mut self, idx: usize, data: &[u8]) -> Result<usize, FakeFatError> {
+        let volume_idx = self.volume_idx(idx)?;
+        self.fat.write_at(volume_idx, data)
+    }
+
+    /// Applies any writes that are still being batched up by `write_byte` to
+    /// the volume, failing like `FakeFat::flush`.
+    pub fn flush(&mut self) -> Result<(), FakeFatError> {
+        self.fat.flush()
+    }
+
+    /// The offset of the partition from the head of the device, in bytes.
+    fn partition_start(&self) -> usize {
+        PARTITION_START as usize * self.fat.sector_size()
+    }
+
+    /// The offset into the volume of `idx`, which the host is writing to.
+    fn volume_idx(&self, idx: usize) -> Result<usize, FakeFatError> {
+        idx.checked_sub(self.partition_start())
+            .ok_or(FakeFatError::ReadOnly {
+                offset: idx as u64,
+                region: Region::Mbr,
+            })
+    }
+
+    /// Gets a byte of the MBR, which is `idx` bytes from the head of the device.
+    fn mbr_byte(&self, idx: usize) -> u8 {
+        let bpb = &self.fat.layout.bpb;
+        match idx {
+            DISK_SIGNATURE_OFFSET..=443 => bpb.volume_id.to_le_bytes()[idx - DISK_SIGNATURE_OFFSET],
+            PARTITION_ENTRY_OFFSET..=461 => {
+                let sectors = self.fat.sector_count();
+                let last = PARTITION_START + sectors.saturating_sub(1);
+                let entry_idx = idx - PARTITION_ENTRY_OFFSET;
+                match entry_idx {
+                    1..=3 => chs_address(bpb, PARTITION_START)[entry_idx - 1],
+                    4 => partition_type(bpb.fat_type),
+                    5..=7 => chs_address(bpb, last)[entry_idx - 5],
+                    8..=11 => PARTITION_START.to_le_bytes()[entry_idx - 8],
+                    12..=15 => sectors.to_le_bytes()[entry_idx - 12],
+                    _ => 0x00,
+                }
+            }
+            BOOT_SIGNATURE_OFFSET => 0x55,
+            511 => 0xAA,
+            _ => 0x00,
+        }
+    }
+}
+
+/// The MBR partition type for a volume using `fat_type`, always the variant
+/// that tells hosts to address the partition by LBA.
+fn partition_type(fat_type: FatType) -> u8 {
+    match fat_type {
+        FatType::Fat12 => 0x01,
+        FatType::Fat16 => 0x0E,
+        FatType::Fat32 => 0x0C,
+    }
+}
+
+/// Encodes `lba` as the 3 byte cylinder, head and sector address the
+/// partition table stores, using the same geometry as the volume's BPB, or as
+/// the largest address if it is out of reach.
+fn chs_address(bpb: &BiosParameterBlock, lba: u32) -> [u8; 3] {
+    let heads = u32::from(bpb.heads.max(1));
+    let sectors_per_track = u32::from(bpb.sectors_per_track.max(1));
+    let cylinder = lba / (heads * sectors_per_track);
+    if cylinder > 1023 {
+        return [0xFE, 0xFF, 0xFF];
+    }
+    let head = (lba / sectors_per_track) % heads;
+    let sector = lba % sectors_per_track + 1;
+    [
+        head as u8,
+        (sector as u8) | ((cylinder >> 2) as u8 & 0xC0),
+        cylinder as u8,
+    ]
+}
+
+#[cfg(feature = "std")]
+mod stdio {
+    use super::*;
+    use std::io::{self, Read, Seek, SeekFrom, Write};
+
+    impl<T: FileSystemOps> Read for MbrWrapped<T> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let remaining = self.len().saturating_sub(self.read_idx as u64);
+            let len = remaining.min(buf.len() as u64) as usize;
+            let buf = &mut buf[..len];
+            let read = self.read_at(self.read_idx, buf);
+            // Reading nothing would look like the end of the device.
+            if read == 0 && !buf.is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.read_idx += read;
+            Ok(read)
+        }
+    }
+    impl<T: FileSystemOps> Seek for MbrWrapped<T> {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64, io::Error> {
+            let idx = match pos {
+                SeekFrom::Start(abs) => Some(abs),
+                SeekFrom::End(off) => self.len().checked_add_signed(off),
+                SeekFrom::Current(off) => (self.read_idx as u64).checked_add_signed(off),
+            };
+            self.read_idx = idx.ok_or(io::ErrorKind::InvalidInput)? as usize;
+            Ok(self.read_idx as u64)
+        }
+    }
+    impl<T: FileSystemOps> Write for MbrWrapped<T> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let start = self.partition_start();
+            if self.read_idx < start {
+                return Err(io::ErrorKind::PermissionDenied.into());
+            }
+            self.fat.read_idx = self.read_idx - start;
+            let written = self.fat.write(buf)?;
+            self.read_idx += written;
+            Ok(written)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            MbrWrapped::flush(self).map_err(|_| io::ErrorKind::OutOfMemory.into())
+        }
+    }
+}
@@ -0,0 +1,404 @@
+//! A minimal Network Block Device server, so that the fake device can be
+//! attached to a Linux machine with `nbd-client` and mounted like any other
+//! disk without needing any USB hardware:
+//!
+//! ```sh
+//! nbd-client -N fakefat localhost 10809 /dev/nbd0
+//! mount /dev/nbd0 /mnt
+//! ```
+//!
+//! Only the fixed newstyle handshake and simple replies are supported, which
+//! every `nbd-client` released in the last decade speaks.
+//!
+//! `NbdServer` serves one client at a time, straight from the device.
+//! `NbdSandboxServer` serves any number of clients at once, each in its own
+//! `Sandbox` over the shared device.
+
+use crate::device::Device;
+use crate::faker::FakeFat;
+use crate::ratelimit::read_throttled;
+use crate::sandbox::Sandbox;
+use crate::traits::FileSystemOps;
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// The port NBD servers listen on by default.
+pub const NBD_DEFAULT_PORT: u16 = 10809;
+
+const NBDMAGIC: u64 = 0x4E42_444D_4147_4943;
+const IHAVEOPT: u64 = 0x4948_4156_454F_5054;
+const OPTION_REPLY_MAGIC: u64 = 0x0003_E889_0455_65A9;
+const REQUEST_MAGIC: u32 = 0x2560_9513;
+const SIMPLE_REPLY_MAGIC: u32 = 0x6744_6698;
+
+const FLAG_FIXED_NEWSTYLE: u16 = 1 << 0;
+const FLAG_NO_ZEROES: u16 = 1 << 1;
+
+const TRANSMISSION_HAS_FLAGS: u16 = 1 << 0;
+const TRANSMISSION_READ_ONLY: u16 = 1 << 1;
+const TRANSMISSION_SEND_FLUSH: u16 = 1 << 2;
+
+const OPT_EXPORT_NAME: u32 = 1;
+const OPT_ABORT: u32 = 2;
+const OPT_LIST: u32 = 3;
+const OPT_INFO: u32 = 6;
+const OPT_GO: u32 = 7;
+
+const REP_ACK: u32 = 1;
+const REP_SERVER: u32 = 2;
+const REP_INFO: u32 = 3;
+const REP_ERR_UNSUP: u32 = (1 << 31) + 1;
+const INFO_EXPORT: u16 = 0;
+
+const CMD_READ: u16 = 0;
+const CMD_WRITE: u16 = 1;
+const CMD_DISC: u16 = 2;
+const CMD_FLUSH: u16 = 3;
+
+const EPERM: u32 = 1;
+const EINVAL: u32 = 22;
+const ENOSPC: u32 = 28;
+
+/// The longest option the server is willing to read from a client.
+const MAX_OPTION_LENGTH: usize = 4096;
+
+/// The number of bytes moved between the socket and the device at a time.
+const TRANSFER_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Serves a single `FakeFat` as an NBD export.
+pub struct NbdServer<T: FileSystemOps> {
+    fat: FakeFat<T>,
+}
+
+impl<T: FileSystemOps> NbdServer<T> {
+    /// Wraps the given device.
+    pub fn new(fat: FakeFat<T>) -> Self {
+        NbdServer { fat }
+    }
+
+    /// The wrapped device.
+    pub fn fat(&self) -> &FakeFat<T> {
+        &self.fat
+    }
+
+    /// The wrapped device.
+    pub fn fat_mut(&mut self) -> &mut FakeFat<T> {
+        &mut self.fat
+    }
+
+    /// Takes back ownership of the wrapped device.
+    pub fn into_inner(self) -> FakeFat<T> {
+        self.fat
+    }
+
+    /// Listens on `addr` and serves clients one at a time, forever.
+    ///
+    /// Errors on individual connections only end that connection; only
+    /// failures of the listening socket itself are returned.
+    pub fn listen<A: ToSocketAddrs>(&mut self, addr: A) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        loop {
+            let (stream, _) = listener.accept()?;
+            stream.set_nodelay(true)?;
+            let _ = self.serve(stream);
+        }
+    }
+
+    /// Runs the NBD protocol over `stream` until the client disconnects.
+    ///
+    /// The export name the client asks for is ignored, since there is only one
+    /// export to hand out. Each client that gets to the transmission phase gets
+    /// its own session on the device.
+    pub fn serve<S: Read + Write>(&mut self, mut stream: S) -> io::Result<()> {
+        if handshake(&mut stream, &self.fat)? {
+            self.fat
+                .session_start()
+                .map_err(|_| io::ErrorKind::OutOfMemory)?;
+            let result = transmit(&mut stream, &mut self.fat);
+            self.fat
+                .session_end()
+                .map_err(|_| io::ErrorKind::OutOfMemory)?;
+            result?;
+        }
+        self.fat.flush().map_err(|_| io::ErrorKind::OutOfMemory)?;
+        Ok(())
+    }
+}
+
+/// Serves a single `FakeFat` as an NBD export to many clients at once.
+///
+/// Every client gets its own `Sandbox` over the device, so clients can write
+/// to the export at the same time without seeing each other's writes. None of
+/// the writes reach the device itself, and they are all dropped when the
+/// client disconnects.
+pub struct NbdSandboxServer<T: FileSystemOps> {
+    fat: Arc<Mutex<FakeFat<T>>>,
+}
+
+impl<T: FileSystemOps> NbdSandboxServer<T> {
+    /// Wraps the given device.
+    pub fn new(fat: FakeFat<T>) -> Self {
+        NbdSandboxServer {
+            fat: Arc::new(Mutex::new(fat)),
+        }
+    }
+
+    /// The shared device.
+    pub fn fat(&self) -> &Arc<Mutex<FakeFat<T>>> {
+        &self.fat
+    }
+
+    /// Runs the NBD protocol over `stream` until the client disconnects, in a
+    /// sandbox of its own.
+    ///
+    /// Each client that gets to the transmission phase gets its own session on
+    /// the device, which ends when the client disconnects.
+    pub fn serve<S: Read + Write>(&self, mut stream: S) -> io::Result<()> {
+        let mut sandbox = Sandbox::new(Arc::clone(&self.fat));
+        if handshake(&mut stream, &sandbox)? {
+            transmit(&mut stream, &mut sandbox)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T> NbdSandboxServer<T>
+where
+    T: FileSystemOps + Send + 'static,
+    T::Error: Send,
+{
+    /// Listens on `addr` and serves every client on a thread of its own,
+    /// forever.
+    ///
+    /// Errors on individual connections only end that connection; only
+    /// failures of the listening socket itself are returned.
+    pub fn listen<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        loop {
+            let (stream, _) = listener.accept()?;
+            stream.set_nodelay(true)?;
+            let server = NbdSandboxServer {
+                fat: Arc::clone(&self.fat),
+            };
+            thread::spawn(move || {
+                let _ = server.serve(stream);
+            });
+        }
+    }
+}
+
+/// Something that can be served as an NBD export.
+trait Export: Device {
+    fn write_protected(&self) -> bool;
+    /// The first byte clients are allowed to write to.
+    fn writable_from(&self) -> usize;
+}
+
+impl<T: FileSystemOps> Export for FakeFat<T> {
+    fn write_protected(&self) -> bool {
+        FakeFat::write_protected(self)
+    }
+    fn writable_from(&self) -> usize {
+        self.layout.bpb.fat_start()
+    }
+}
+
+impl<T: FileSystemOps> Export for Sandbox<T> {
+    fn write_protected(&self) -> bool {
+        Sandbox::write_protected(self)
+    }
+    fn writable_from(&self) -> usize {
+        self.fat_start()
+    }
+}
+
+/// Greets the client and handles the option haggling phase.
+///
+/// Returns whether the client moved on to the transmission phase.
+fn handshake<S: Read + Write, E: Export>(stream: &mut S, export: &E) -> io::Result<bool> {
+    stream.write_all(&NBDMAGIC.to_be_bytes())?;
+    stream.write_all(&IHAVEOPT.to_be_bytes())?;
+    stream.write_all(&(FLAG_FIXED_NEWSTYLE | FLAG_NO_ZEROES).to_be_bytes())?;
+    stream.flush()?;
+    let client_flags = read_u32(stream)?;
+    let no_zeroes = client_flags & u32::from(FLAG_NO_ZEROES) != 0;
+    negotiate(stream, export, no_zeroes)
+}
+
+/// Handles the option haggling phase.
+///
+/// Returns whether the client moved on to the transmission phase.
+fn negotiate<S: Read + Write, E: Export>(
+    stream: &mut S,
+    export: &E,
+    no_zeroes: bool,
+) -> io::Result<bool> {
+    let mut data = [0u8; MAX_OPTION_LENGTH];
+    loop {
+        if read_u64(stream)? != IHAVEOPT {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        let option = read_u32(stream)?;
+        let length = read_u32(stream)? as usize;
+        if length > MAX_OPTION_LENGTH {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        stream.read_exact(&mut data[..length])?;
+        match option {
+            OPT_EXPORT_NAME => {
+                stream.write_all(&(export.len()).to_be_bytes())?;
+                stream.write_all(&transmission_flags(export).to_be_bytes())?;
+                if !no_zeroes {
+                    stream.write_all(&[0; 124])?;
+                }
+                stream.flush()?;
+                return Ok(true);
+            }
+            OPT_ABORT => {
+                write_option_reply(stream, option, REP_ACK, &[])?;
+                return Ok(false);
+            }
+            OPT_LIST => {
+                write_option_reply(stream, option, REP_SERVER, &[0; 4])?;
+                write_option_reply(stream, option, REP_ACK, &[])?;
+            }
+            OPT_INFO | OPT_GO => {
+                let mut info = [0u8; 12];
+                info[0..2].copy_from_slice(&INFO_EXPORT.to_be_bytes());
+                info[2..10].copy_from_slice(&(export.len()).to_be_bytes());
+                info[10..12].copy_from_slice(&transmission_flags(export).to_be_bytes());
+                write_option_reply(stream, option, REP_INFO, &info)?;
+                write_option_reply(stream, option, REP_ACK, &[])?;
+                if option == OPT_GO {
+                    return Ok(true);
+                }
+            }
+            _ => {
+                write_option_reply(stream, option, REP_ERR_UNSUP, &[])?;
+            }
+        }
+    }
+}
+
+/// Handles requests until the client disconnects.
+fn transmit<S: Read + Write, E: Export>(stream: &mut S, export: &mut E) -> io::Result<()> {
+    let mut buffer = vec![0u8; TRANSFER_CHUNK_SIZE];
+    loop {
+        let magic = match read_u32(stream) {
+            Ok(magic) => magic,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if magic != REQUEST_MAGIC {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        let _flags = read_u16(stream)?;
+        let command = read_u16(stream)?;
+        let handle = read_u64(stream)?;
+        let offset = read_u64(stream)? as usize;
+        let length = read_u32(stream)? as usize;
+        let in_range = offset
+            .checked_add(length)
+            .is_some_and(|end| end as u64 <= export.len());
+        match command {
+            CMD_READ if !in_range => write_reply(stream, EINVAL, handle)?,
+            CMD_READ => {
+                write_reply(stream, 0, handle)?;
+                let mut sent = 0;
+                while sent < length {
+                    let chunk = (length - sent).min(TRANSFER_CHUNK_SIZE);
+                    read_throttled(
+                        |idx, buffer| export.read_at(idx, buffer),
+                        offset + sent,
+                        &mut buffer[..chunk],
+                    );
+                    stream.write_all(&buffer[..chunk])?;
+                    sent += chunk;
+                }
+            }
+            CMD_WRITE => {
+                let mut writable = in_range && !export.write_protected();
+                let mut received = 0;
+                while received < length {
+                    let chunk = (length - received).min(TRANSFER_CHUNK_SIZE);
+                    stream.read_exact(&mut buffer[..chunk])?;
+                    // Writes to the FAT preamble, like clients marking the
+                    // volume dirty, are dropped without failing the request.
+                    let start = offset + received;
+                    let skipped = export.writable_from().saturating_sub(start).min(chunk);
+                    if writable && skipped < chunk {
+                        writable = export
+                            .write_at(start + skipped, &buffer[skipped..chunk])
+                            .is_ok_and(|written| written == chunk - skipped);
+                    }
+                    received += chunk;
+                }
+                let error = if !in_range {
+                    EINVAL
+                } else if !writable {
+                    EPERM
+                } else {
+                    0
+                };
+                write_reply(stream, error, handle)?;
+            }
+            CMD_FLUSH => {
+                let error = if export.flush().is_ok() { 0 } else { ENOSPC };
+                write_reply(stream, error, handle)?;
+            }
+            CMD_DISC => return Ok(()),
+            _ => write_reply(stream, EINVAL, handle)?,
+        }
+        stream.flush()?;
+    }
+}
+
+fn transmission_flags<E: Export>(export: &E) -> u16 {
+    let flags = TRANSMISSION_HAS_FLAGS | TRANSMISSION_SEND_FLUSH;
+    if export.write_protected() {
+        flags | TRANSMISSION_READ_ONLY
+    } else {
+        flags
+    }
+}
+
+fn write_option_reply<S: Write>(
+    stream: &mut S,
+    option: u32,
+    reply: u32,
+    data: &[u8],
+) -> io::Result<()> {
+    stream.write_all(&OPTION_REPLY_MAGIC.to_be_bytes())?;
+    stream.write_all(&option.to_be_bytes())?;
+    stream.write_all(&reply.to_be_bytes())?;
+    stream.write_all(&(data.len() as u32).to_be_bytes())?;
+    stream.write_all(data)?;
+    stream.flush()
+}
+
+fn write_reply<S: Write>(stream: &mut S, error: u32, handle: u64) -> io::Result<()> {
+    stream.write_all(&SIMPLE_REPLY_MAGIC.to_be_bytes())?;
+    stream.write_all(&error.to_be_bytes())?;
+    stream.write_all(&handle.to_be_bytes())
+}
+
+fn read_u16<S: Read>(stream: &mut S) -> io::Result<u16> {
+    let mut buf = [0; 2];
+    stream.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+fn read_u32<S: Read>(stream: &mut S) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    stream.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64<S: Read>(stream: &mut S) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    stream.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
@@ -20,10 +20,17 @@ mod with_alloc {
         is_file: bool,
     }
     impl PathBuff {
+        pub fn empty() -> Self {
+            PathBuff {
+                bytes: Vec::new(),
+                is_file: false,
+            }
+        }
+
         pub fn add_subdir(&mut self, component: &str) {
             debug_assert!(!self.is_file);
             self.bytes.extend_from_slice(component.as_bytes());
-            if !self.bytes.ends_with(&[b'/']) {
+            if !self.bytes.ends_with(b"/") {
                 self.bytes.push(b'/');
             }
         }
@@ -37,6 +44,13 @@ mod with_alloc {
         pub fn to_str(&self) -> &str {
             unsafe { from_utf8_unchecked(self.bytes.as_slice()) }
         }
+
+        /// Cuts the path back to its first `len` bytes, which have to end in a
+        /// directory separator.
+        pub fn truncate(&mut self, len: usize) {
+            self.bytes.truncate(len);
+            self.is_file = false;
+        }
     }
 
     impl fmt::Display for PathBuff {
@@ -84,16 +98,25 @@ mod fixed_size {
     }
 
     impl PathBuff {
+        pub fn empty() -> Self {
+            PathBuff {
+                data: [0; ELEMENTS],
+                len: 0,
+                is_file: false,
+            }
+        }
+
         pub fn add_subdir(&mut self, component: &str) {
             debug_assert!(!self.is_file);
             let comp_bytes = component.as_bytes();
             debug_assert!(ELEMENTS - self.len >= comp_bytes.len());
             let data_slice = &mut self.data[self.len .. self.len + comp_bytes.len()];
             data_slice.copy_from_slice(comp_bytes);
-            if !self.data[self.len + comp_bytes.len() - 1] == b'/' {
-                self.data[self.len + comp_bytes.len()] = b'/';
+            self.len += comp_bytes.len();
+            if self.data[..self.len].last() != Some(&b'/') {
+                self.data[self.len] = b'/';
+                self.len += 1;
             }
-            self.len += comp_bytes.len() + 1;
         }
 
         pub fn add_file(&mut self, file_name: &str) {
@@ -108,6 +131,14 @@ mod fixed_size {
         pub fn to_str(&self) -> &str {
             unsafe { from_utf8_unchecked(&self.data[0..self.len]) }
         }
+
+        /// Cuts the path back to its first `len` bytes, which have to end in a
+        /// directory separator.
+        pub fn truncate(&mut self, len: usize) {
+            debug_assert!(len <= self.len);
+            self.len = len;
+            self.is_file = false;
+        }
     }
 
     impl fmt::Display for PathBuff {
@@ -129,3 +160,36 @@ mod fixed_size {
         }
     }
 }
+
+impl PathBuff {
+    /// Constructs the directory path a `FakeFat` with the given path prefix
+    /// exposes as its root.
+    ///
+    /// Backslashes are treated as separators on Windows, so prefixes like
+    /// `C:\data` or `\\server\share` come out as `C:/data/` and
+    /// `//server/share/`. A prefix without a leading separator or drive is
+    /// treated as if it had a leading `/`.
+    pub fn from_prefix(prefix: &str) -> PathBuff {
+        let is_separator = |c: char| c == '/' || (cfg!(windows) && c == '\\');
+        let rest = prefix.trim_start_matches(is_separator);
+        let leading = prefix.len() - rest.len();
+        let is_drive = cfg!(windows)
+            && leading == 0
+            && rest.len() >= 2
+            && rest.as_bytes()[0].is_ascii_alphabetic()
+            && rest.as_bytes()[1] == b':';
+        let mut retval = if is_drive {
+            PathBuff::empty()
+        } else {
+            PathBuff::default()
+        };
+        // Keeps the double separator of a UNC path.
+        if cfg!(windows) && leading >= 2 {
+            retval.add_subdir("/");
+        }
+        for component in rest.split(is_separator).filter(|c| !c.is_empty()) {
+            retval.add_subdir(component);
+        }
+        retval
+    }
+}
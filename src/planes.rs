@@ -0,0 +1,198 @@
+//! A device shared between the interrupt handlers that serve the host and the
+//! main loop that maintains it, the way embedded Mass Storage stacks are built:
+//! sector reads are answered from a USB or DMA completion interrupt, while
+//! refreshing the backing tree, committing writes and changing options happen
+//! in the main loop.
+//!
+//! The two sides get separate handles to a `SharedFakeFat`. The `DataPlane`
+//! only reads sectors and never waits: if the `ControlPlane` is using the
+//! device, the read fails with `SectorError::Busy`, which Mass Storage stacks
+//! already answer by retrying the sector on a later poll. The `ControlPlane`
+//! only touches the device inside `sync`, the explicit synchronization points
+//! at which it waits for a sector read in progress to finish, and after which
+//! the data plane sees the device as the control plane left it. The main loop
+//! can read sectors alongside the data plane the same way, with
+//! `ControlPlane::read_sector`.
+//!
+//! Taking the device is a compare-and-swap of a single flag, so this is only
+//! available on targets with atomic compare-and-swap.
+
+use crate::aligned::{Aligned, Alignment};
+use crate::faker::FakeFat;
+use crate::sector::SectorError;
+use crate::traits::FileSystemOps;
+
+use core::cell::UnsafeCell;
+use core::hint;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// A `FakeFat` that a `DataPlane` and a `ControlPlane` take turns using.
+pub struct SharedFakeFat<T: FileSystemOps> {
+    fat: UnsafeCell<FakeFat<T>>,
+    /// Whether one of the planes is using the device.
+    busy: AtomicBool,
+    /// The geometry of the device as of the last synchronization point, so
+    /// that the data plane can report it without taking the device.
+    sector_size: AtomicU32,
+    sector_count: AtomicU32,
+}
+
+// The device is only ever used by whichever plane set the `busy` flag.
+unsafe impl<T: FileSystemOps> Sync for SharedFakeFat<T> where FakeFat<T>: Send {}
+
+impl<T: FileSystemOps> SharedFakeFat<T> {
+    /// Wraps the given device.
+    pub fn new(fat: FakeFat<T>) -> Self {
+        SharedFakeFat {
+            sector_size: AtomicU32::new(fat.sector_size() as u32),
+            sector_count: AtomicU32::new(fat.sector_count()),
+            fat: UnsafeCell::new(fat),
+            busy: AtomicBool::new(false),
+        }
+    }
+
+    /// Hands out the handles for the two sides of the device.
+    pub fn split(&self) -> (ControlPlane<'_, T>, DataPlane<'_, T>) {
+        (ControlPlane { shared: self }, DataPlane { shared: self })
+    }
+
+    /// Takes back ownership of the wrapped device.
+    pub fn into_inner(self) -> FakeFat<T> {
+        self.fat.into_inner()
+    }
+
+    /// Takes the device if neither plane is using it.
+    fn try_take(&self) -> Option<Taken<'_, T>> {
+        self.busy
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        Some(Taken { shared: self })
+    }
+}
+
+/// Exclusive use of the device, which is given back on drop.
+struct Taken<'a, T: FileSystemOps> {
+    shared: &'a SharedFakeFat<T>,
+}
+
+impl<'a, T: FileSystemOps> Taken<'a, T> {
+    fn fat(&mut self) -> &mut FakeFat<T> {
+        // Only one `Taken` exists at a time, since it is only made by setting
+        // the `busy` flag, which only its drop clears.
+        unsafe { &mut *self.shared.fat.get() }
+    }
+}
+
+impl<'a, T: FileSystemOps> Drop for Taken<'a, T> {
+    fn drop(&mut self) {
+        self.shared.busy.store(false, Ordering::Release);
+    }
+}
+
+/// The side of a `SharedFakeFat` that serves sector reads, which never waits
+/// and so can be used from interrupt handlers.
+pub struct DataPlane<'a, T: FileSystemOps> {
+    shared: &'a SharedFakeFat<T>,
+}
+
+impl<'a, T: FileSystemOps> Clone for DataPlane<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T: FileSystemOps> Copy for DataPlane<'a, T> {}
+
+impl<'a, T: FileSystemOps> DataPlane<'a, T> {
+    /// The size of a sector, in bytes, as of the last synchronization point.
+    pub fn sector_size(&self) -> usize {
+        self.shared.sector_size.load(Ordering::Acquire) as usize
+    }
+
+    /// The number of sectors on the device, as of the last synchronization
+    /// point.
+    pub fn sector_count(&self) -> u32 {
+        self.shared.sector_count.load(Ordering::Acquire)
+    }
+
+    /// Reads sector `lba` into `buffer`, like `FakeFat::read_sector`.
+    ///
+    /// Fails with `SectorError::Busy` without reading anything if the control
+    /// plane, or another read of the data plane, is using the device.
+    pub fn read_sector(&self, lba: u32, buffer: &mut [u8]) -> Result<(), SectorError> {
+        match self.shared.try_take() {
+            Some(mut taken) => taken.fat().read_sector(lba, buffer),
+            None => Err(SectorError::Busy { lba }),
+        }
+    }
+
+    /// Reads the sectors from `lba` on straight into `buffer`, like
+    /// `FakeFat::read_sectors_aligned`, failing like `read_sector` if the
+    /// device is in use.
+    pub fn read_sectors_aligned<A: Alignment, const N: usize>(
+        &self,
+        lba: u32,
+        buffer: &mut Aligned<A, N>,
+    ) -> Result<(), SectorError> {
+        match self.shared.try_take() {
+            Some(mut taken) => taken.fat().read_sectors_aligned(lba, buffer),
+            None => Err(SectorError::Busy { lba }),
+        }
+    }
+}
+
+/// The side of a `SharedFakeFat` that maintains the device, such as
+/// refreshing it, committing writes or changing its options.
+pub struct ControlPlane<'a, T: FileSystemOps> {
+    shared: &'a SharedFakeFat<T>,
+}
+
+impl<'a, T: FileSystemOps> ControlPlane<'a, T> {
+    /// Runs `f` with exclusive use of the device, first waiting for a sector
+    /// read of the data plane in progress to finish.
+    ///
+    /// The data plane's reads fail with `SectorError::Busy` until `f`
+    /// returns, and the geometry it reports is updated after.
+    ///
+    /// This must not be called from an interrupt handler that can interrupt a
+    /// sector read of the data plane on the same core, which would then never
+    /// get to finish.
+    pub fn sync<R>(&self, f: impl FnOnce(&mut FakeFat<T>) -> R) -> R {
+        let mut f = Some(f);
+        loop {
+            if let Some(retval) = self.try_run(&mut f) {
+                return retval;
+            }
+            hint::spin_loop();
+        }
+    }
+
+    /// Reads sector `lba` into `buffer`, like `FakeFat::read_sector`, first
+    /// waiting for a sector read of the data plane in progress to finish.
+    ///
+    /// This lets the main loop read the device alongside the data plane, and
+    /// has the same restriction as `sync` on where it can be called from.
+    pub fn read_sector(&self, lba: u32, buffer: &mut [u8]) -> Result<(), SectorError> {
+        self.sync(|fat| fat.read_sector(lba, buffer))
+    }
+
+    /// Like `sync`, but returns `None` without running `f` if the data plane
+    /// is reading a sector.
+    pub fn try_sync<R>(&self, f: impl FnOnce(&mut FakeFat<T>) -> R) -> Option<R> {
+        self.try_run(&mut Some(f))
+    }
+
+    /// Runs the function in `f` if the device can be taken.
+    fn try_run<R, F: FnOnce(&mut FakeFat<T>) -> R>(&self, f: &mut Option<F>) -> Option<R> {
+        let mut taken = self.shared.try_take()?;
+        let fat = taken.fat();
+        let retval = f.take()?(fat);
+        self.shared
+            .sector_size
+            .store(fat.sector_size() as u32, Ordering::Release);
+        self.shared
+            .sector_count
+            .store(fat.sector_count(), Ordering::Release);
+        Some(retval)
+    }
+}
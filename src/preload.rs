@@ -0,0 +1,195 @@
+//! Hosts read the root directory and a handful of well-known files right after
+//! mounting a device, and a backing filesystem that is slow to open or list
+//! makes those first reads slow too. `FakeFat::preload` lets the user do that
+//! work up front instead, when the device is constructed, so that the first
+//! accesses take as long as every later one.
+//!
+//! Directories are rendered into the directory cache, and the clusters of files
+//! are read into a `FileCache` that `read_at` serves them from afterwards.
+//! Like the Cluster Mapper, there are 2 `FileCacheOps` implementations toggled
+//! by the used feature flags:
+//!
+//! *  In environments without an allocator, there is nowhere to keep file
+//!    contents, so files are only opened to check that they exist, and the
+//!    directory cache only keeps the last directory preloaded.
+//!
+//! *  In environments with an allocator, the contents of each preloaded cluster
+//!    are kept in a `BTreeMap<u32, Box<[u8]>>` keyed by the cluster.
+
+use crate::clustermapping::ClusterMapperOps;
+use crate::faker::{read_padded, FakeFat};
+use crate::traits::FileSystemOps;
+
+pub trait FileCacheOps {
+    /// Constructs an empty cache.
+    fn new() -> Self;
+
+    /// Gets the cached contents of `cluster`, or `None` if it has not been
+    /// cached.
+    fn get(&self, cluster: u32) -> Option<&[u8]>;
+
+    /// Stores `len` bytes of contents for `cluster`, which `fill` writes into
+    /// the buffer it is given, returning whether it could.
+    ///
+    /// Returns `false` if the contents could not be cached, either without
+    /// calling `fill` or because `fill` failed.
+    fn insert_with<F: FnOnce(&mut [u8]) -> bool>(
+        &mut self,
+        cluster: u32,
+        len: usize,
+        fill: F,
+    ) -> bool;
+
+    /// Drops every cached cluster, so that the next read of each one reads the
+    /// backing filesystem again.
+    fn clear(&mut self);
+}
+
+#[cfg(not(feature = "alloc"))]
+pub type FileCache = noalloc_filecache::NoallocFileCache;
+#[cfg(not(feature = "alloc"))]
+mod noalloc_filecache {
+    use super::*;
+
+    pub struct NoallocFileCache;
+
+    impl FileCacheOps for NoallocFileCache {
+        fn new() -> Self {
+            NoallocFileCache
+        }
+
+        fn get(&self, _cluster: u32) -> Option<&[u8]> {
+            None
+        }
+
+        fn insert_with<F: FnOnce(&mut [u8]) -> bool>(
+            &mut self,
+            _cluster: u32,
+            _len: usize,
+            _fill: F,
+        ) -> bool {
+            false
+        }
+
+        fn clear(&mut self) {}
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub type FileCache = alloc_filecache::AllocFileCache;
+#[cfg(feature = "alloc")]
+mod alloc_filecache {
+    use super::*;
+
+    #[cfg(feature = "std")]
+    use std as alloc;
+
+    use alloc::boxed::Box;
+    use alloc::collections::BTreeMap;
+    use alloc::vec;
+
+    pub struct AllocFileCache {
+        clusters: BTreeMap<u32, Box<[u8]>>,
+    }
+
+    impl FileCacheOps for AllocFileCache {
+        fn new() -> Self {
+            AllocFileCache {
+                clusters: BTreeMap::new(),
+            }
+        }
+
+        fn get(&self, cluster: u32) -> Option<&[u8]> {
+            self.clusters.get(&cluster).map(|data| data.as_ref())
+        }
+
+        fn insert_with<F: FnOnce(&mut [u8]) -> bool>(
+            &mut self,
+            cluster: u32,
+            len: usize,
+            fill: F,
+        ) -> bool {
+            let mut data = vec![0; len].into_boxed_slice();
+            if !fill(&mut data) {
+                return false;
+            }
+            self.clusters.insert(cluster, data);
+            true
+        }
+
+        fn clear(&mut self) {
+            self.clusters.clear();
+        }
+    }
+}
+
+impl<T: FileSystemOps> FakeFat<T> {
+    /// Renders the directories and reads the files at `paths` ahead of time,
+    /// so that the host's first reads of them do not have to wait on the
+    /// backing filesystem, returning the number of paths that were found.
+    ///
+    /// Paths are relative to the path prefix of the device, like `/` for its
+    /// root directory or `firmware/update.bin`. Paths that do not exist are
+    /// skipped.
+    ///
+    /// Preloaded files are served from memory until `FakeFat::refresh` is
+    /// called, even if they change in the backing filesystem in the meantime.
+    pub fn preload<'a, I: IntoIterator<Item = &'a str>>(&mut self, paths: I) -> usize {
+        let mut found = 0;
+        for path in paths {
+            let path = path.trim_matches('/');
+            let mut dir_path = self.layout.prefix.clone();
+            if !path.is_empty() {
+                dir_path.add_subdir(path);
+            }
+            if let Some(head) = self
+                .layout
+                .mapper
+                .get_chain_head_for_path(dir_path.to_str())
+            {
+                self.cached_dir_entries(head);
+                found += 1;
+                continue;
+            }
+            let mut file_path = self.layout.prefix.clone();
+            file_path.add_file(path);
+            if self.preload_file(file_path.to_str()) {
+                found += 1;
+            }
+        }
+        found
+    }
+
+    /// Reads every cluster of the file at the backing path `path` into the
+    /// file cache, returning whether the file exists.
+    ///
+    /// Clusters are read up to the first one the backing filesystem fails to
+    /// read, which is left to be read when the host asks for it.
+    fn preload_file(&mut self, path: &str) -> bool {
+        let lookup = self.fs.get_file(path);
+        let mut file = match self.found(lookup) {
+            Some(file) => file,
+            None => return false,
+        };
+        let cluster_size = self.layout.bpb.bytes_per_cluster() as usize;
+        let chain = self.layout.mapper.get_chain_for_path(path);
+        for (position, cluster) in chain.into_iter().enumerate() {
+            let offset = position * cluster_size;
+            let mut failed = None;
+            let cached = self
+                .file_cache
+                .insert_with(cluster, cluster_size, |buffer| {
+                    read_padded(&mut file, offset, buffer)
+                        .map_err(|e| failed = Some(e))
+                        .is_ok()
+                });
+            if let Some(e) = failed {
+                self.record_backend_error(e);
+            }
+            if !cached {
+                break;
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,307 @@
+//! Caps how fast hosts can read the contents of files, so that a host scanning
+//! the whole device cannot use up all of the bandwidth of the backing
+//! filesystem and starve the device's other tasks of it.
+//!
+//! Limits are token buckets: every limit refills at `bytes_per_second` up to
+//! `burst` bytes, and a read of a file is only served as far as both the limit
+//! set with `FakeFatBuilder::read_rate_limit` and the one the
+//! `PathRateLimits` set with `FakeFatBuilder::path_read_rate_limits` gives the
+//! file allow. Only the contents of files from the backing filesystem count;
+//! the preamble, the File Allocation Tables, directories and clusters the host
+//! wrote itself are never read from it, and are never held back.
+//!
+//! A read that runs out of budget is cut short: `FakeFat::read_at` returns
+//! fewer bytes than asked for, `FakeFat::read_sector` fails with
+//! `SectorError::Busy` instead of reading part of a sector, and the network
+//! adapters wait for the budget to refill.
+//!
+//! Only a fixed number of files keep a bucket of their own at a time; when a
+//! new file needs one, the bucket of a file that has been idle long enough to
+//! refill is handed over first, and otherwise that of the file read the
+//! longest time ago.
+
+use crate::changeset::{ChangeSet, ChangeSetOps};
+use crate::clustermapping::ClusterMapperOps;
+use crate::dedup::{FNV_OFFSET_BASIS, FNV_PRIME};
+use crate::faker::{FakeFat, FakerAddress};
+use crate::layout::VolumeLayout;
+use crate::traits::FileSystemOps;
+
+/// The number of files that can keep a bucket of their own at once.
+const PATH_BUCKET_COUNT: usize = 16;
+
+/// How long the network adapters wait before retrying a read that ran out of
+/// budget.
+#[cfg(feature = "std")]
+const THROTTLE_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_millis(1);
+
+/// A cap on how fast hosts can read.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RateLimit {
+    /// The number of bytes that can be read per second on average.
+    pub bytes_per_second: u32,
+    /// The number of bytes that can be read at once after not reading for a
+    /// while, which has to be at least a sector so that `read_sector` can ever
+    /// succeed.
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// A limit of `bytes_per_second`, with bursts of up to a second's worth.
+    pub fn new(bytes_per_second: u32) -> Self {
+        RateLimit {
+            bytes_per_second,
+            burst: bytes_per_second,
+        }
+    }
+
+    /// Sets the number of bytes that can be read at once.
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst;
+        self
+    }
+}
+
+/// Decides how fast hosts can read the file at a path in the backing
+/// filesystem, like the paths passed to `FileSystemOps`, returning `None` for
+/// files that are only held to the limit of the whole device.
+pub type PathRateLimits = fn(&str) -> Option<RateLimit>;
+
+/// Returns the number of milliseconds since some fixed point in time, which
+/// must never go backwards.
+pub type Clock = fn() -> u64;
+
+/// A `Clock` counting from the first time it is called, which is what devices
+/// use unless `FakeFatBuilder::clock` says otherwise.
+#[cfg(feature = "std")]
+pub fn std_clock() -> u64 {
+    use std::sync::OnceLock;
+    use std::time::Instant;
+
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_millis() as u64
+}
+
+/// The budget left under a single limit.
+#[derive(Copy, Clone, Default)]
+struct Bucket {
+    /// The hash of the path of the file the bucket belongs to.
+    key: u64,
+    available: u64,
+    refilled_at: u64,
+}
+
+impl Bucket {
+    fn full(key: u64, limit: RateLimit, now: u64) -> Self {
+        Bucket {
+            key,
+            available: u64::from(limit.burst),
+            refilled_at: now,
+        }
+    }
+
+    /// Adds the budget earned since the last refill.
+    fn refill(&mut self, limit: RateLimit, now: u64) {
+        let rate = u64::from(limit.bytes_per_second);
+        let earned = now.saturating_sub(self.refilled_at) * rate / 1000;
+        if self.available + earned >= u64::from(limit.burst) {
+            self.available = u64::from(limit.burst);
+            self.refilled_at = now;
+        } else if earned > 0 {
+            // Only the time that earned whole bytes is used up, so that
+            // frequent reads still refill slow limits.
+            self.available += earned;
+            self.refilled_at += earned * 1000 / rate;
+        }
+    }
+}
+
+/// The rate limit bookkeeping of a `FakeFat`.
+pub(crate) struct RateLimiter {
+    clock: Clock,
+    limit: Option<RateLimit>,
+    bucket: Bucket,
+    path_limits: Option<PathRateLimits>,
+    path_buckets: [Option<Bucket>; PATH_BUCKET_COUNT],
+}
+
+impl RateLimiter {
+    pub fn new(
+        clock: Clock,
+        limit: Option<RateLimit>,
+        path_limits: Option<PathRateLimits>,
+    ) -> Self {
+        let now = clock();
+        RateLimiter {
+            clock,
+            limit,
+            bucket: limit.map_or_else(Bucket::default, |limit| Bucket::full(0, limit, now)),
+            path_limits,
+            path_buckets: [None; PATH_BUCKET_COUNT],
+        }
+    }
+
+    /// The number of bytes of the file at `path` that can be read right now,
+    /// up to `wanted`.
+    fn allowance(&mut self, path: &str, wanted: usize, now: u64) -> usize {
+        let mut allowed = wanted as u64;
+        if let Some(limit) = self.limit {
+            self.bucket.refill(limit, now);
+            allowed = allowed.min(self.bucket.available);
+        }
+        if let Some(limit) = self.path_limits.and_then(|limits| limits(path)) {
+            let bucket = self.path_bucket(path, limit, now);
+            bucket.refill(limit, now);
+            allowed = allowed.min(bucket.available);
+        }
+        allowed as usize
+    }
+
+    /// Takes `len` bytes read from the file at `path` out of the budget.
+    fn consume(&mut self, path: &str, len: usize, now: u64) {
+        let len = len as u64;
+        if self.limit.is_some() {
+            self.bucket.available = self.bucket.available.saturating_sub(len);
+        }
+        if let Some(limit) = self.path_limits.and_then(|limits| limits(path)) {
+            let bucket = self.path_bucket(path, limit, now);
+            bucket.available = bucket.available.saturating_sub(len);
+        }
+    }
+
+    /// The bucket of the file at `path`, handing it one if it does not have
+    /// one yet.
+    fn path_bucket(&mut self, path: &str, limit: RateLimit, now: u64) -> &mut Bucket {
+        let key = path_key(path);
+        let slot = match self
+            .path_buckets
+            .iter()
+            .position(|b| b.is_some_and(|b| b.key == key))
+        {
+            Some(slot) => slot,
+            None => {
+                let idle = |bucket: &Option<Bucket>| match bucket {
+                    None => true,
+                    Some(bucket) => {
+                        let mut refilled = *bucket;
+                        refilled.refill(limit, now);
+                        refilled.available >= u64::from(limit.burst)
+                    }
+                };
+                let slot = self.path_buckets.iter().position(idle).unwrap_or_else(|| {
+                    let oldest = self
+                        .path_buckets
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|(_, bucket)| bucket.map_or(0, |bucket| bucket.refilled_at));
+                    oldest.map_or(0, |(slot, _)| slot)
+                });
+                self.path_buckets[slot] = Some(Bucket::full(key, limit, now));
+                slot
+            }
+        };
+        self.path_buckets[slot].get_or_insert_with(Bucket::default)
+    }
+}
+
+/// Hashes `path` so that its bucket can be found without keeping the path.
+fn path_key(path: &str) -> u64 {
+    path.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Calls `visit` with the offset into the `len` bytes starting `idx` bytes
+/// from the head of the device, the length and the backing path of every run
+/// of them that is read from a file in the backing filesystem, stopping early
+/// if `visit` returns `false`.
+fn for_each_file_run(
+    layout: &VolumeLayout,
+    changes: &ChangeSet,
+    idx: usize,
+    len: usize,
+    mut visit: impl FnMut(usize, usize, &str) -> bool,
+) {
+    let cluster_size = layout.bpb.bytes_per_cluster() as usize;
+    let end = idx + len;
+    let mut cur = idx.max(layout.bpb.data_start());
+    while cur < end {
+        let (cluster, offset) = match FakerAddress::from_raw_idx(cur, &layout.bpb) {
+            FakerAddress::RawData { cluster, offset } => (cluster, offset),
+            _ => return,
+        };
+        let run = (cluster_size - offset).min(end - cur);
+        let path = match layout.mapper.get_path_for_cluster(cluster) {
+            Some(path) if !path.ends_with('/') && changes.cluster_data(cluster).is_none() => {
+                Some(path)
+            }
+            _ => None,
+        };
+        if let Some(path) = path {
+            if !visit(cur - idx, run, path) {
+                return;
+            }
+        }
+        cur += run;
+    }
+}
+
+impl<T: FileSystemOps> FakeFat<T> {
+    /// The number of bytes at the start of the `len` bytes starting `idx`
+    /// bytes from the head of the device that the rate limits let the host
+    /// read right now.
+    pub(crate) fn read_allowance(&mut self, idx: usize, len: usize) -> usize {
+        let limiter = match self.rate_limiter.as_mut() {
+            Some(limiter) => limiter,
+            None => return len,
+        };
+        let now = (limiter.clock)();
+        let mut allowed = len;
+        for_each_file_run(&self.layout, &self.changes, idx, len, |start, run, path| {
+            let run_allowed = limiter.allowance(path, run, now);
+            if run_allowed < run {
+                allowed = start + run_allowed;
+                return false;
+            }
+            true
+        });
+        allowed
+    }
+
+    /// Takes the `len` bytes starting `idx` bytes from the head of the device
+    /// that the host just read out of the budget of the rate limits.
+    pub(crate) fn consume_read_budget(&mut self, idx: usize, len: usize) {
+        let limiter = match self.rate_limiter.as_mut() {
+            Some(limiter) => limiter,
+            None => return,
+        };
+        let now = (limiter.clock)();
+        for_each_file_run(&self.layout, &self.changes, idx, len, |_, run, path| {
+            limiter.consume(path, run, now);
+            true
+        });
+    }
+}
+
+/// Reads all of `buffer` with `read_at`, starting `idx` bytes from the head of
+/// the device, waiting for the rate limits to allow it whenever nothing could
+/// be read.
+///
+/// `idx + buffer.len()` must not be past the end of the device, since reads
+/// there never make progress.
+#[cfg(feature = "std")]
+pub(crate) fn read_throttled(
+    mut read_at: impl FnMut(usize, &mut [u8]) -> usize,
+    idx: usize,
+    buffer: &mut [u8],
+) {
+    let mut read = 0;
+    while read < buffer.len() {
+        let cur_read = read_at(idx + read, &mut buffer[read..]);
+        if cur_read == 0 {
+            std::thread::sleep(THROTTLE_RETRY_INTERVAL);
+        }
+        read += cur_read;
+    }
+}
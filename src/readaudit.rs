@@ -0,0 +1,243 @@
+//! Keeps track of which files the host actually read, and how much of them,
+//! so that a device can tell whether the host fetched a firmware image or a
+//! manual before acting on it.
+//!
+//! Only the contents of files from the backing filesystem are tracked; reads of
+//! directories, of the File Allocation Tables and of files the host created
+//! itself are not. Like `PathBuff`, `ReadAudit` has 2 implementations toggled
+//! by the used feature flags:
+//!
+//! *  In environments without an allocator, records are kept in a fixed-size
+//!    array; files past its capacity, or with paths too long for it, are not
+//!    tracked.
+//!
+//! *  In environments with an allocator, records are kept in a
+//!    `BTreeMap<String, ReadRecord>`.
+
+use crate::clustermapping::ClusterMapperOps;
+use crate::faker::{FakeFat, FakerAddress};
+use crate::traits::{FileSystemLookup, FileSystemOps};
+
+/// How much of a file the host read, and when.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReadRecord {
+    /// The number of bytes of the file the host read, counting bytes that were
+    /// read more than once every time.
+    pub bytes_read: u64,
+    /// One past the furthest byte of the file the host read, which is the size
+    /// of the file once the host read it through to the end.
+    pub read_end: u32,
+    /// The session the host first read the file in, or `None` if no session
+    /// was active.
+    pub first_session: Option<u32>,
+    /// The session the host last read the file in, or `None` if no session
+    /// was active.
+    pub last_session: Option<u32>,
+}
+
+impl ReadRecord {
+    fn record(&mut self, offset: usize, len: usize, session: Option<u32>) {
+        if self.bytes_read == 0 {
+            self.first_session = session;
+        }
+        self.bytes_read += len as u64;
+        self.read_end = self.read_end.max((offset + len) as u32);
+        self.last_session = session;
+    }
+}
+
+#[cfg(not(feature = "alloc"))]
+pub use noalloc_audit::ReadAudit;
+#[cfg(not(feature = "alloc"))]
+mod noalloc_audit {
+    use super::*;
+    use core::str::from_utf8_unchecked;
+
+    const READ_AUDIT_CAPACITY: usize = 32;
+    const MAX_PATH_LENGTH: usize = 128;
+
+    /// The files the host read, by their path on the device.
+    pub struct ReadAudit {
+        len: usize,
+        paths: [([u8; MAX_PATH_LENGTH], usize); READ_AUDIT_CAPACITY],
+        records: [ReadRecord; READ_AUDIT_CAPACITY],
+    }
+
+    impl ReadAudit {
+        pub(crate) fn new() -> Self {
+            ReadAudit {
+                len: 0,
+                paths: [([0; MAX_PATH_LENGTH], 0); READ_AUDIT_CAPACITY],
+                records: [ReadRecord::default(); READ_AUDIT_CAPACITY],
+            }
+        }
+
+        /// The record of the file at `path` on the device, like
+        /// `/docs/manual.pdf`, or `None` if the host never read it.
+        pub fn get(&self, path: &str) -> Option<ReadRecord> {
+            let path = path.trim_start_matches('/');
+            self.iter()
+                .find(|(cur, _)| cur.trim_start_matches('/') == path)
+                .map(|(_, record)| record)
+        }
+
+        /// Every file the host read, by its path relative to the root of the
+        /// device, like `docs/manual.pdf`, in no particular order.
+        pub fn iter(&self) -> impl Iterator<Item = (&str, ReadRecord)> + '_ {
+            self.paths[..self.len]
+                .iter()
+                .zip(self.records.iter())
+                .map(|((path, len), record)| {
+                    // Only whole `&str`s are ever copied in.
+                    (unsafe { from_utf8_unchecked(&path[..*len]) }, *record)
+                })
+        }
+
+        pub(crate) fn record(
+            &mut self,
+            path: &str,
+            offset: usize,
+            len: usize,
+            session: Option<u32>,
+        ) {
+            let existing = self.paths[..self.len]
+                .iter()
+                .position(|(cur, cur_len)| &cur[..*cur_len] == path.as_bytes());
+            let idx = match existing {
+                Some(idx) => idx,
+                None if self.len >= READ_AUDIT_CAPACITY || path.len() > MAX_PATH_LENGTH => return,
+                None => {
+                    let (buffer, buffer_len) = &mut self.paths[self.len];
+                    buffer[..path.len()].copy_from_slice(path.as_bytes());
+                    *buffer_len = path.len();
+                    self.len += 1;
+                    self.len - 1
+                }
+            };
+            self.records[idx].record(offset, len, session);
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub use alloc_audit::ReadAudit;
+#[cfg(feature = "alloc")]
+mod alloc_audit {
+    use super::*;
+
+    #[cfg(feature = "std")]
+    use std as alloc;
+
+    use alloc::borrow::ToOwned;
+    use alloc::collections::BTreeMap;
+    use alloc::string::String;
+
+    /// The files the host read, by their path on the device.
+    pub struct ReadAudit {
+        records: BTreeMap<String, ReadRecord>,
+    }
+
+    impl ReadAudit {
+        pub(crate) fn new() -> Self {
+            ReadAudit {
+                records: BTreeMap::new(),
+            }
+        }
+
+        /// The record of the file at `path` on the device, like
+        /// `/docs/manual.pdf`, or `None` if the host never read it.
+        pub fn get(&self, path: &str) -> Option<ReadRecord> {
+            let path = path.trim_start_matches('/');
+            self.records.get(path).copied()
+        }
+
+        /// Every file the host read, by its path relative to the root of the
+        /// device, like `docs/manual.pdf`, in no particular order.
+        pub fn iter(&self) -> impl Iterator<Item = (&str, ReadRecord)> + '_ {
+            self.records
+                .iter()
+                .map(|(path, record)| (path.as_str(), *record))
+        }
+
+        pub(crate) fn record(
+            &mut self,
+            path: &str,
+            offset: usize,
+            len: usize,
+            session: Option<u32>,
+        ) {
+            if !self.records.contains_key(path) {
+                self.records.insert(path.to_owned(), ReadRecord::default());
+            }
+            if let Some(record) = self.records.get_mut(path) {
+                record.record(offset, len, session);
+            }
+        }
+    }
+}
+
+impl<T: FileSystemOps> FakeFat<T> {
+    /// Starts keeping track of how much of each file the host reads, forgetting
+    /// everything tracked so far if it was already enabled.
+    pub fn enable_read_audit(&mut self) {
+        self.read_audit = Some(ReadAudit::new());
+    }
+
+    /// The files the host read since `enable_read_audit` was called, or `None`
+    /// if it was not.
+    pub fn read_audit(&self) -> Option<&ReadAudit> {
+        self.read_audit.as_ref()
+    }
+
+    /// Records the host reading `len` bytes of the device starting `idx` bytes
+    /// from its head.
+    pub(crate) fn audit_read(&mut self, idx: usize, len: usize) {
+        if self.read_audit.is_none() {
+            return;
+        }
+        let cluster_size = self.layout.bpb.bytes_per_cluster() as usize;
+        let data_start = self.layout.bpb.data_start();
+        let end = idx + len;
+        let mut cur = idx.max(data_start);
+        while cur < end {
+            let (cluster, offset) = match FakerAddress::from_raw_idx(cur, &self.layout.bpb) {
+                FakerAddress::RawData { cluster, offset } => (cluster, offset),
+                _ => return,
+            };
+            let run = (cluster_size - offset).min(end - cur);
+            self.audit_cluster_read(cluster, offset, run);
+            cur += run;
+        }
+    }
+
+    /// Records the host reading `len` bytes of `cluster` starting `offset`
+    /// bytes into it, if it belongs to a file from the backing filesystem.
+    fn audit_cluster_read(&mut self, cluster: u32, offset: usize, len: usize) {
+        let path = match self.layout.mapper.get_path_for_cluster(cluster) {
+            Some(path) if !path.ends_with('/') => path,
+            _ => return,
+        };
+        let position = match self.layout.mapper.chain_position(cluster) {
+            Some(position) => position,
+            None => return,
+        };
+        let size = match self.fs.find_metadata(path) {
+            Some(meta) => self.layout.naming.exposed_meta(path, meta).size as usize,
+            None => return,
+        };
+        let file_offset = position * self.layout.bpb.bytes_per_cluster() as usize + offset;
+        // The slack after the end of the file is not part of it.
+        let len = len.min(size.saturating_sub(file_offset));
+        if len == 0 {
+            return;
+        }
+        let device_path = path
+            .strip_prefix(self.layout.prefix.to_str())
+            .unwrap_or(path);
+        let session = self.sessions.current;
+        if let Some(audit) = self.read_audit.as_mut() {
+            audit.record(device_path, file_offset, len, session);
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Keeps the clusters of one part of the tree in step with the backing
+//! filesystem after the device is constructed.
+//!
+//! The layout of a device only changes when it is told to look at the backing
+//! filesystem again. `FakeFat::refresh` re-reads the whole tree but only ever
+//! adds clusters, while `FakeFat::remap_path` re-reads a single directory and
+//! everything below it, or a single file, and also frees the clusters of files
+//! that shrank. Either way, every cluster an item keeps stays where it is, so
+//! hosts that cached part of the volume only need to re-read what changed.
+
+use crate::bpb::BiosParameterBlock;
+use crate::changeset::{ChangeSet, ChangeSetOps};
+use crate::clustermapping::{ClusterMapper, ClusterMapperOps};
+use crate::dedup::{DedupIndex, DedupIndexOps};
+use crate::dircache::DirCacheOps;
+use crate::dirnames::{DirNames, DirNamesOps};
+use crate::error::FakeFatError;
+use crate::faker::{place_dir, traverse, FakeFat};
+use crate::fsinfo::FsInfoSector;
+use crate::pathbuffer::PathBuff;
+use crate::preload::FileCacheOps;
+use crate::sanitize::NamingOptions;
+use crate::scan::DirWalk;
+use crate::traits::{DirEntryOps, DirectoryListing, FileSystemLookup, FileSystemOps};
+
+impl<T: FileSystemOps> FakeFat<T> {
+    /// Re-reads the metadata of the item at `path`, where `"/"` is the
+    /// device's root directory, growing or shrinking the cluster chains of the
+    /// item and, for a directory, of everything below it to match the backing
+    /// filesystem.
+    ///
+    /// Chains only ever gain or lose clusters at their end, so the clusters an
+    /// item keeps do not move. Directories never shrink, and neither do files
+    /// sharing a chain through `FakeFatBuilder::dedup_files`. Items that were
+    /// removed from the backing filesystem keep their clusters, as they do
+    /// with `refresh`.
+    ///
+    /// Returns `false` without changing anything if there is no item at `path`,
+    /// and fails like `flush` if the writes still being batched up can not be
+    /// applied first.
+    pub fn remap_path(&mut self, path: impl AsRef<str>) -> Result<bool, FakeFatError> {
+        self.flush()?;
+        let mut parent = self.layout.prefix.clone();
+        let mut name = None;
+        for component in path.as_ref().split('/').filter(|c| !c.is_empty()) {
+            if let Some(dir_name) = name.replace(component) {
+                parent.add_subdir(dir_name);
+            }
+        }
+        let mut dir = parent.clone();
+        if let Some(name) = name {
+            dir.add_subdir(name);
+        }
+        let allocated = self.layout.mapper.allocated_count();
+        let max_cluster = if self.fs.find_dir(dir.to_str()).is_some() {
+            let leading = self.leading_entries(&dir);
+            let max_cluster = traverse(
+                &mut self.layout.mapper,
+                &dir,
+                &mut self.fs,
+                &self.layout.bpb,
+                leading,
+                self.layout.naming,
+                &DedupIndex::new(),
+            );
+            let (layout, changes, fsinfo) = (&mut self.layout, &self.changes, &mut self.fsinfo);
+            let mut walk = DirWalk::new(&dir);
+            while walk.visit(&mut self.fs, layout.naming, |fs, cur, _| {
+                trim_files(
+                    &mut layout.mapper,
+                    fs,
+                    cur,
+                    None,
+                    &layout.bpb,
+                    layout.naming,
+                    changes,
+                    fsinfo,
+                );
+                true
+            }) {}
+            max_cluster
+        } else {
+            let name = match name {
+                Some(name) if self.is_file(&parent, name) => name,
+                _ => return Ok(false),
+            };
+            dir = parent;
+            let leading = self.leading_entries(&dir);
+            let placed = place_dir(
+                &mut self.layout.mapper,
+                &dir,
+                &mut self.fs,
+                &self.layout.bpb,
+                leading,
+                self.layout.naming,
+                &DedupIndex::new(),
+            );
+            trim_files(
+                &mut self.layout.mapper,
+                &mut self.fs,
+                &dir,
+                Some(name),
+                &self.layout.bpb,
+                self.layout.naming,
+                &self.changes,
+                &mut self.fsinfo,
+            );
+            placed.unwrap_or(0)
+        };
+        let grown = self
+            .layout
+            .mapper
+            .allocated_count()
+            .saturating_sub(allocated);
+        if grown > 0 {
+            self.fsinfo.allocated(grown as u32, max_cluster);
+        }
+        self.layout.max_cluster = self.layout.max_cluster.max(max_cluster);
+        self.dir_cache.clear();
+        self.file_cache.clear();
+        self.update_dir_version(&dir);
+        self.debug_validate("remap_path");
+        Ok(true)
+    }
+
+    /// The number of entries the directory at the backing path `dir` starts
+    /// with on top of its children.
+    fn leading_entries(&self, dir: &PathBuff) -> usize {
+        let is_root = dir.to_str() == self.layout.prefix.to_str();
+        usize::from(is_root && self.layout.bpb.has_volume_label())
+    }
+
+    /// Whether the directory at the backing path `dir` has a file named `name`.
+    fn is_file(&mut self, dir: &PathBuff, name: &str) -> bool {
+        let mut path = dir.clone();
+        path.add_file(name);
+        self.fs
+            .find_metadata(path.to_str())
+            .is_some_and(|meta| !meta.is_directory)
+    }
+}
+
+/// Frees the clusters past the end of every file directly in the directory
+/// `dir`, or only of the file named `only`, keeping `fsinfo` up to date.
+///
+/// Clusters whose FAT entry the host changed are left out of the free count,
+/// which already follows what the host wrote.
+#[allow(clippy::too_many_arguments)]
+fn trim_files<T: FileSystemOps>(
+    mapper: &mut ClusterMapper,
+    fs: &mut T,
+    dir: &PathBuff,
+    only: Option<&str>,
+    bpb: &BiosParameterBlock,
+    naming: NamingOptions,
+    changes: &ChangeSet,
+    fsinfo: &mut FsInfoSector,
+) {
+    let bytes_per_cluster = bpb.bytes_per_cluster() as usize;
+    let listing = match fs.find_dir(dir.to_str()) {
+        Some(listing) => listing,
+        None => return,
+    };
+    let names = DirNames::new(naming, &listing, dir.to_str());
+    let files = listing
+        .listing()
+        .filter(|ent| !ent.meta().is_directory)
+        .filter(|ent| only.is_none_or(|only| ent.name().as_ref() == only))
+        .filter(|ent| names.get(&listing, dir.to_str(), ent).is_some());
+    for ent in files {
+        let mut path = dir.clone();
+        path.add_file(ent.name().as_ref());
+        let size = naming.exposed_meta(path.to_str(), ent.meta()).size;
+        let needed = (size as usize).div_ceil(bytes_per_cluster);
+        if mapper.chain_len(path.to_str()) <= needed {
+            continue;
+        }
+        let chain = mapper.get_chain_for_path(path.to_str());
+        if !mapper.truncate_chain(path.to_str(), needed) {
+            continue;
+        }
+        for cluster in chain.into_iter().skip(needed) {
+            if changes.cluster_entry(cluster).is_none() {
+                fsinfo.freed();
+            }
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Lets several hosts use the same device at once without seeing each other's
+//! writes. Each `Sandbox` keeps the sectors its host wrote to in memory and
+//! reads every other sector from a device shared between all of them, which
+//! acts as the frozen base image every sandbox starts out from.
+//!
+//! Nothing a host writes into a sandbox ever reaches the shared device or its
+//! backing filesystem; it is all dropped along with the sandbox.
+
+use crate::error::FakeFatError;
+use crate::faker::{FakeFat, FakerAddress};
+use crate::ratelimit::read_throttled;
+use crate::session::SessionStats;
+use crate::traits::FileSystemOps;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A private, copy-on-write view of a shared `FakeFat`.
+///
+/// Every sandbox is a session of the shared device, which is reported to its
+/// `SessionListener` when the sandbox is created and when it is dropped.
+pub struct Sandbox<T: FileSystemOps> {
+    base: Arc<Mutex<FakeFat<T>>>,
+    sectors: BTreeMap<usize, Box<[u8]>>,
+    sector_size: usize,
+    image_size: usize,
+    fat_start: usize,
+    write_protected: bool,
+    session: u32,
+    stats: SessionStats,
+}
+
+impl<T: FileSystemOps> Sandbox<T> {
+    /// Opens a new sandbox over `base`, starting a new session on it.
+    pub fn new(base: Arc<Mutex<FakeFat<T>>>) -> Self {
+        let (sector_size, image_size, fat_start, write_protected, session) = {
+            let mut fat = lock(&base);
+            // The change set only fills up without an allocator.
+            let _ = fat.flush();
+            (
+                fat.sector_size(),
+                fat.image_size(),
+                fat.layout.bpb.fat_start(),
+                fat.write_protected(),
+                fat.sessions.begin(),
+            )
+        };
+        Sandbox {
+            base,
+            sectors: BTreeMap::new(),
+            sector_size,
+            image_size,
+            fat_start,
+            write_protected,
+            session,
+            stats: SessionStats::default(),
+        }
+    }
+
+    /// The shared device the sandbox reads unchanged sectors from.
+    pub fn base(&self) -> &Arc<Mutex<FakeFat<T>>> {
+        &self.base
+    }
+
+    /// The number of the session the sandbox is.
+    pub fn session(&self) -> u32 {
+        self.session
+    }
+
+    /// What the host did to the sandbox so far. Changed clusters are not
+    /// tracked, since the sandbox only deals in sectors.
+    pub fn stats(&self) -> SessionStats {
+        self.stats
+    }
+
+    /// The size of the device in bytes, as reported to the host.
+    pub fn image_size(&self) -> usize {
+        self.image_size
+    }
+
+    /// The size of a sector, in bytes.
+    pub fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+
+    /// Whether the shared device rejects all writes, in which case so does the
+    /// sandbox.
+    pub fn write_protected(&self) -> bool {
+        self.write_protected
+    }
+
+    /// The first byte of the device the host may write to, right after the FAT
+    /// preamble.
+    pub fn fat_start(&self) -> usize {
+        self.fat_start
+    }
+
+    /// The number of sectors the host changed, which is how many sectors the
+    /// sandbox keeps in memory.
+    pub fn changed_sectors(&self) -> usize {
+        self.sectors.len()
+    }
+
+    /// Drops every change the host made, going back to the shared device as
+    /// it currently is.
+    pub fn discard(&mut self) {
+        self.sectors.clear();
+    }
+
+    /// Reads up to `buffer.len()` bytes out of the sandbox starting `idx` bytes
+    /// from the head of the device, returning the number of bytes read.
+    pub fn read_at(&mut self, idx: usize, buffer: &mut [u8]) -> usize {
+        let len = buffer.len().min(self.image_size.saturating_sub(idx));
+        let mut read = 0;
+        while read < len {
+            let cur_idx = idx + read;
+            let sector = cur_idx / self.sector_size;
+            let offset = cur_idx % self.sector_size;
+            let run = match self.sectors.get(&sector) {
+                Some(data) => {
+                    let run = (self.sector_size - offset).min(len - read);
+                    buffer[read..read + run].copy_from_slice(&data[offset..offset + run]);
+                    run
+                }
+                None => {
+                    // Reads everything up to the next changed sector in one go.
+                    let next_changed = self
+                        .sectors
+                        .range(sector..)
+                        .next()
+                        .map_or(usize::MAX, |(&next, _)| next * self.sector_size);
+                    let run = (next_changed - cur_idx).min(len - read);
+                    lock(&self.base).read_at(cur_idx, &mut buffer[read..read + run])
+                }
+            };
+            if run == 0 {
+                break;
+            }
+            read += run;
+        }
+        self.stats.bytes_read += read as u64;
+        read
+    }
+
+    /// Writes `data` into the sandbox starting `idx` bytes from the head of the
+    /// device, returning the number of bytes written.
+    ///
+    /// Fails without writing anything if the shared device is write protected
+    /// or if the write starts in the FAT preamble, just like
+    /// `FakeFat::write_at`.
+    pub fn write_at(&mut self, idx: usize, data: &[u8]) -> Result<usize, FakeFatError> {
+        let offset = idx as u64;
+        if self.write_protected {
+            return Err(FakeFatError::WriteProtected { offset });
+        }
+        if idx < self.fat_start {
+            let region = FakerAddress::from_raw_idx(idx, &lock(&self.base).layout.bpb).region();
+            return Err(FakeFatError::ReadOnly { offset, region });
+        }
+        let len = data.len().min(self.image_size.saturating_sub(idx));
+        let mut written = 0;
+        while written < len {
+            let cur_idx = idx + written;
+            let sector = cur_idx / self.sector_size;
+            let offset = cur_idx % self.sector_size;
+            let run = (self.sector_size - offset).min(len - written);
+            let sector_start = sector * self.sector_size;
+            let sector_size = self.sector_size;
+            let base = &self.base;
+            let buffer = self.sectors.entry(sector).or_insert_with(|| {
+                let mut contents = vec![0; sector_size].into_boxed_slice();
+                read_throttled(
+                    |idx, buffer| lock(base).read_at(idx, buffer),
+                    sector_start,
+                    &mut contents,
+                );
+                contents
+            });
+            buffer[offset..offset + run].copy_from_slice(&data[written..written + run]);
+            written += run;
+        }
+        self.stats.bytes_written += written as u64;
+        Ok(written)
+    }
+}
+
+impl<T: FileSystemOps> Drop for Sandbox<T> {
+    fn drop(&mut self) {
+        lock(&self.base).sessions.finish(self.session, self.stats);
+    }
+}
+
+/// Locks the shared device, even if another sandbox panicked while holding it,
+/// since sandboxes only ever read from it.
+fn lock<T: FileSystemOps>(base: &Mutex<FakeFat<T>>) -> MutexGuard<'_, FakeFat<T>> {
+    base.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,638 @@
+//! Backing filesystems allow names that FAT does not: Windows silently drops
+//! leading spaces and trailing spaces and dots from names, and never creates
+//! names containing any of `"*/:<>?\|` or control characters. Exposing such names
+//! as-is leaves it up to each host to decide what they mean, so instead every
+//! name goes through a `NamePolicy` before it is rendered into the device.
+//!
+//! Since FAT names are case-insensitive, and since sanitizing can turn
+//! different names like `"foo?"` and `"foo*"` into the same one, several items
+//! in a directory can end up with the same exposed name. All but one of them
+//! then get a number added before their extension, as in `"foo_ (2)"`. Names
+//! that did not need sanitizing win over those that did, and ties go to the
+//! backing name that sorts first, so that the same directory is always exposed
+//! the same way.
+//!
+//! Hosts report paths using these exposed names, or using the short names
+//! derived from them, so `FakeFat::backing_path` translates such paths back to
+//! the items they refer to, and `FakeFat::name_mappings` lists every mapping.
+
+use crate::access::{Access, Authorizer, Operation};
+use crate::dircache::DirCacheOps;
+use crate::dirnames::{DirNames, DirNamesOps};
+use crate::faker::{is_unplaced, is_unplaced_child, DirectoryPadding, FakeFat};
+use crate::pathbuffer::PathBuff;
+use crate::shortname::{ShortName, ShortNameCharset, ShortNameStrategy};
+use crate::shortnametable::DirShortNames;
+use crate::traits::{
+    DirEntryOps, DirectoryListing, DirectoryOps, FileMetadata, FileSystemLookup, FileSystemOps,
+};
+use crate::writeback::MAX_NAME_BYTES;
+
+use core::fmt::{self, Write};
+use core::str::from_utf8_unchecked;
+
+/// The character illegal characters are replaced with.
+const REPLACEMENT_CHAR: char = '_';
+
+/// How names that are not legal on FAT are exposed on the device.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum NamePolicy {
+    /// Drop leading spaces and trailing spaces and dots like Windows does,
+    /// replacing any illegal characters with `_`.
+    #[default]
+    Trim,
+    /// Replace leading spaces, trailing spaces and dots, and illegal characters
+    /// with `_`, which keeps names that only differ by them apart.
+    Replace,
+    /// Hide the item from the device entirely.
+    Reject,
+}
+
+/// How backing items whose metadata marks them as hidden or as system items
+/// are exposed on the device.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HiddenEntries {
+    /// List the item with the hidden or system attribute set, which hosts only
+    /// hide from users that did not ask to see such files.
+    #[default]
+    Mark,
+    /// Leave the item out of the device entirely, as if it did not exist, so
+    /// that it takes up no clusters and no directory entries. Hosts can not
+    /// create or change items with the same name either.
+    Omit,
+}
+
+/// What happened to a name that could not be exposed as-is.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum NameAction {
+    /// Leading or trailing characters were dropped from the name.
+    Trimmed,
+    /// Some of the name's characters were replaced.
+    Replaced,
+    /// The item is not exposed on the device.
+    Rejected,
+    /// A number was added to the name to tell it apart from another item in
+    /// the same directory.
+    Deduplicated,
+    /// The item is not exposed on the device, since there was no room left to
+    /// keep track of its clusters, as can happen without the `alloc` feature.
+    Omitted,
+}
+
+/// A single entry of the report produced by `FakeFat::scan_names`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct NameReport<'a> {
+    /// The path of the item in the backing filesystem.
+    pub path: &'a str,
+    /// The name the item is exposed under, or `None` if it was rejected or
+    /// omitted.
+    pub exposed: Option<&'a str>,
+    /// What was done to the name.
+    pub action: NameAction,
+}
+
+/// A single entry of the table produced by `FakeFat::name_mappings`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct NameMapping<'a> {
+    /// The path of the item in the backing filesystem.
+    pub path: &'a str,
+    /// The path of the item on the device, as made of the long names hosts
+    /// normally show.
+    pub device_path: &'a str,
+    /// The short name of the item, as shown by hosts that ignore long names.
+    pub short_name: &'a str,
+    /// What was done to the name to expose it, if anything.
+    pub action: Option<NameAction>,
+}
+
+/// The path in the backing filesystem that a path on the device refers to, as
+/// found by `FakeFat::backing_path`.
+#[derive(Clone)]
+pub struct BackingPath(PathBuff);
+
+impl BackingPath {
+    /// The path as a string.
+    pub fn as_str(&self) -> &str {
+        self.0.to_str()
+    }
+}
+
+impl AsRef<str> for BackingPath {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Debug for BackingPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for BackingPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The options controlling how backing names become names on the device,
+/// which items get exposed at all, and how many entries the directories holding
+/// them are given.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct NamingOptions {
+    pub policy: NamePolicy,
+    pub case_flags: bool,
+    pub short_names: ShortNameStrategy,
+    pub charset: ShortNameCharset,
+    pub hidden: HiddenEntries,
+    pub authorizer: Option<Authorizer>,
+    pub padding: DirectoryPadding,
+}
+
+/// A name as it is exposed on the device.
+pub(crate) struct ExposedName {
+    data: [u8; MAX_NAME_BYTES],
+    len: usize,
+}
+
+impl ExposedName {
+    /// Wraps `name`, or returns `None` if it is too long to be exposed.
+    #[cfg(feature = "alloc")]
+    pub fn new(name: &str) -> Option<Self> {
+        let mut retval = ExposedName {
+            data: [0; MAX_NAME_BYTES],
+            len: 0,
+        };
+        retval.write_str(name).ok()?;
+        Some(retval)
+    }
+
+    fn push(&mut self, c: char) -> bool {
+        if self.len + c.len_utf8() > self.data.len() {
+            return false;
+        }
+        c.encode_utf8(&mut self.data[self.len..]);
+        self.len += c.len_utf8();
+        true
+    }
+}
+
+impl Write for ExposedName {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if s.chars().all(|c| self.push(c)) {
+            Ok(())
+        } else {
+            Err(fmt::Error)
+        }
+    }
+}
+
+impl AsRef<str> for ExposedName {
+    fn as_ref(&self) -> &str {
+        unsafe { from_utf8_unchecked(&self.data[..self.len]) }
+    }
+}
+
+impl NamingOptions {
+    /// Converts the backing `name` into the name it is exposed under, along with
+    /// what had to be done to it, if anything.
+    ///
+    /// Returns `None` if the item should not be exposed at all.
+    pub fn expose(self, name: &str) -> Option<(ExposedName, Option<NameAction>)> {
+        let (chars, action) = self.sanitize(name)?;
+        let mut exposed = ExposedName {
+            data: [0; MAX_NAME_BYTES],
+            len: 0,
+        };
+        for c in chars {
+            if !exposed.push(c) {
+                return None;
+            }
+        }
+        if exposed.len == 0 {
+            return None;
+        }
+        Some((exposed, action))
+    }
+
+    /// Like `expose`, but for the entry `ent` of `dir`, the directory at the
+    /// backing path `dir_path`, telling its name apart from the names of the
+    /// other entries.
+    ///
+    /// Also returns `None` if the item is hidden and hidden items are omitted,
+    /// if the authorizer hides the item, or if it denies reads of `dir` itself.
+    ///
+    /// This takes several passes over `dir`, which is why `DirNames` works out
+    /// the names of a whole directory at once when there is an allocator.
+    #[cfg(not(feature = "alloc"))]
+    pub fn expose_in<D: DirectoryOps>(
+        self,
+        dir: &D,
+        dir_path: &str,
+        ent: &D::EntryType,
+    ) -> Option<(ExposedName, Option<NameAction>)> {
+        let name = ent.name();
+        let name = name.as_ref();
+        if self.access(Operation::Read, dir_path) != Access::Allow
+            || self.access_in(Operation::Read, dir_path, name) == Access::Hide
+            || self.omits(&ent.meta())
+        {
+            return None;
+        }
+        let (base, action) = self.expose(name)?;
+        let precedence = (action.is_some(), name);
+        let rank = dir
+            .listing()
+            .filter(|ent| {
+                let other = ent.name();
+                let other = other.as_ref();
+                other != name
+                    && self
+                        .sanitized_as(other, base.as_ref())
+                        .is_some_and(|changed| (changed, other) < precedence)
+                    && self.access_in(Operation::Read, dir_path, other) != Access::Hide
+                    && !self.omits(&ent.meta())
+            })
+            .count();
+        if rank == 0 {
+            return Some((base, action));
+        }
+
+        let mut free = 0;
+        let mut number = 1;
+        loop {
+            number += 1;
+            let candidate = with_number(base.as_ref(), number)?;
+            let taken = dir.listing().any(|ent| {
+                self.sanitized_as(ent.name().as_ref(), candidate.as_ref())
+                    .is_some()
+            });
+            if !taken {
+                free += 1;
+                if free == rank {
+                    return Some((candidate, Some(NameAction::Deduplicated)));
+                }
+            }
+        }
+    }
+
+    /// Asks the authorizer whether `operation` is allowed on the item at the
+    /// backing `path`.
+    pub fn access(self, operation: Operation, path: &str) -> Access {
+        let authorizer = match self.authorizer {
+            Some(authorizer) => authorizer,
+            None => return Access::Allow,
+        };
+        let trimmed = path.trim_end_matches('/');
+        authorizer(operation, if trimmed.is_empty() { "/" } else { trimmed })
+    }
+
+    /// The metadata the item at the backing `path` is exposed with, given its
+    /// backing metadata `meta`: files the authorizer denies reads of are empty.
+    pub fn exposed_meta(self, path: &str, mut meta: FileMetadata) -> FileMetadata {
+        if !meta.is_directory && self.access(Operation::Read, path) == Access::Deny {
+            meta.size = 0;
+        }
+        meta
+    }
+
+    /// Whether an item with the backing metadata `meta` is left out of the
+    /// device because it is hidden or a system item.
+    pub fn omits(self, meta: &FileMetadata) -> bool {
+        self.hidden == HiddenEntries::Omit && (meta.is_hidden || meta.is_system)
+    }
+
+    /// Like `access`, but for the item `name` in the backing directory
+    /// `dir_path`.
+    pub fn access_in(self, operation: Operation, dir_path: &str, name: &str) -> Access {
+        if self.authorizer.is_none() {
+            return Access::Allow;
+        }
+        let mut path = PathBuff::empty();
+        path.add_subdir(dir_path);
+        path.add_file(name);
+        self.access(operation, path.to_str())
+    }
+
+    /// Checks whether the backing `name` sanitizes to a name that FAT considers
+    /// the same as `exposed`, returning whether sanitizing changed it if so.
+    #[cfg(not(feature = "alloc"))]
+    fn sanitized_as(self, name: &str, exposed: &str) -> Option<bool> {
+        let (chars, action) = self.sanitize(name)?;
+        if same_chars(chars, exposed.chars()) {
+            Some(action.is_some())
+        } else {
+            None
+        }
+    }
+
+    /// Lazily sanitizes `name`, returning the characters of the sanitized name
+    /// and what was done to it, or `None` if the policy rejects it.
+    pub fn sanitize(
+        self,
+        name: &str,
+    ) -> Option<(impl Iterator<Item = char> + '_, Option<NameAction>)> {
+        let start = name.len() - name.trim_start_matches(' ').len();
+        let end = name.trim_end_matches([' ', '.']).len().max(start);
+        let has_illegal = name.chars().any(is_illegal_char);
+        let needs_trim = start > 0 || end < name.len();
+        let action = match self.policy {
+            _ if !needs_trim && !has_illegal => None,
+            NamePolicy::Reject => return None,
+            NamePolicy::Trim if needs_trim => Some(NameAction::Trimmed),
+            NamePolicy::Trim | NamePolicy::Replace => Some(NameAction::Replaced),
+        };
+        let policy = self.policy;
+        let chars = name.char_indices().filter_map(move |(idx, c)| {
+            let outside = idx < start || idx >= end;
+            if outside && policy == NamePolicy::Trim {
+                None
+            } else if outside || is_illegal_char(c) {
+                Some(REPLACEMENT_CHAR)
+            } else {
+                Some(c)
+            }
+        });
+        Some((chars, action))
+    }
+}
+
+/// Adds ` (number)` to the end of `name`, but before its extension.
+pub(crate) fn with_number(name: &str, number: usize) -> Option<ExposedName> {
+    let split = name.rfind('.').filter(|&idx| idx > 0).unwrap_or(name.len());
+    let (stem, ext) = name.split_at(split);
+    let mut retval = ExposedName {
+        data: [0; MAX_NAME_BYTES],
+        len: 0,
+    };
+    write!(retval, "{} ({}){}", stem, number, ext).ok()?;
+    Some(retval)
+}
+
+/// Compares two names the way FAT does, ignoring case.
+fn same_chars<A: Iterator<Item = char>, B: Iterator<Item = char>>(a: A, b: B) -> bool {
+    let lower = |c: char| c.to_lowercase();
+    a.flat_map(lower).eq(b.flat_map(lower))
+}
+
+/// Checks whether `component` of a path reported by a host refers to the item
+/// with the given short name.
+fn is_short_name(short_name: &ShortName, component: &str, charset: ShortNameCharset) -> bool {
+    let (name, ext) = match component.rfind('.') {
+        Some(idx) => (&component[..idx], &component[idx + 1..]),
+        None => (component, ""),
+    };
+    let (short_stem, short_ext) = short_name_parts(short_name);
+    same_chars(decoded(short_stem, charset), name.chars())
+        && same_chars(decoded(short_ext, charset), ext.chars())
+}
+
+/// Splits a short name into the name and extension hosts show, which unlike
+/// `ShortName::name` keeps any spaces before a numeric tail.
+fn short_name_parts(short_name: &ShortName) -> (&[u8], &[u8]) {
+    let (name, ext) = short_name.as_bytes().split_at(ShortName::SHORT_NAME_LENGTH);
+    (trim_padding(name), trim_padding(ext))
+}
+
+/// Drops the spaces a part of a short name is padded with.
+fn trim_padding(part: &[u8]) -> &[u8] {
+    let len = part.iter().rposition(|&c| c != b' ').map_or(0, |p| p + 1);
+    &part[..len]
+}
+
+/// The characters hosts show for `part` of a short name.
+fn decoded(part: &[u8], charset: ShortNameCharset) -> impl Iterator<Item = char> + '_ {
+    part.iter().map(move |&c| charset.decode(c))
+}
+
+fn is_illegal_char(c: char) -> bool {
+    c < ' ' || "\"*/:<>?\\|".contains(c)
+}
+
+impl<T: FileSystemOps> FakeFat<T> {
+    /// Walks the backing filesystem and calls `report` for every item whose name
+    /// could not be exposed on the device as-is, which is useful for warning
+    /// users about files that were hidden or renamed.
+    pub fn scan_names<F: FnMut(NameReport)>(&mut self, mut report: F) {
+        self.walk_names(|path, exposed| match exposed {
+            Some((_, _, _, None)) => {}
+            Some((_, _, _, Some(NameAction::Omitted))) => report(NameReport {
+                path: path.to_str(),
+                exposed: None,
+                action: NameAction::Omitted,
+            }),
+            Some((_, exposed, _, Some(action))) => report(NameReport {
+                path: path.to_str(),
+                exposed: Some(exposed.as_ref()),
+                action,
+            }),
+            None => report(NameReport {
+                path: path.to_str(),
+                exposed: None,
+                action: NameAction::Rejected,
+            }),
+        });
+    }
+
+    /// Walks the backing filesystem and calls `report` for every item exposed on
+    /// the device, with the names it is exposed under.
+    pub fn name_mappings<F: FnMut(NameMapping)>(&mut self, mut report: F) {
+        let naming = self.layout.naming;
+        self.walk_names(|path, exposed| {
+            if let Some((device_path, _, short_name, action)) = exposed {
+                if action == Some(NameAction::Omitted) {
+                    return;
+                }
+                let mut short_str = ExposedName {
+                    data: [0; MAX_NAME_BYTES],
+                    len: 0,
+                };
+                let (name, ext) = short_name_parts(short_name);
+                for c in decoded(name, naming.charset) {
+                    let _ = short_str.write_char(c);
+                }
+                if !ext.is_empty() {
+                    let _ = short_str.write_char('.');
+                    for c in decoded(ext, naming.charset) {
+                        let _ = short_str.write_char(c);
+                    }
+                }
+                report(NameMapping {
+                    path: path.to_str(),
+                    device_path: device_path.to_str(),
+                    short_name: short_str.as_ref(),
+                    action,
+                });
+            }
+        });
+    }
+
+    /// Finds the item in the backing filesystem that `device_path`, a path on
+    /// the device as reported by a host, refers to.
+    ///
+    /// Each component of `device_path` can be either the long or the short name
+    /// of an item, in any case, and components can be separated by either `/` or
+    /// `\`. Returns `None` if there is no such item.
+    pub fn backing_path(&mut self, device_path: impl AsRef<str>) -> Option<BackingPath> {
+        let mut path = self.layout.prefix.clone();
+        let mut components = device_path
+            .as_ref()
+            .split(['/', '\\'])
+            .filter(|component| !component.is_empty() && *component != ".")
+            .peekable();
+        while let Some(component) = components.next() {
+            let (name, is_directory) = self.backing_child(path.to_str(), component)?;
+            if is_directory {
+                path.add_subdir(name.as_ref());
+            } else if components.peek().is_none() {
+                path.add_file(name.as_ref());
+            } else {
+                return None;
+            }
+        }
+        Some(BackingPath(path))
+    }
+
+    /// Finds the child of the backing directory `dir_path` that a host refers to as
+    /// `component`, returning its backing name and whether it is a directory.
+    ///
+    /// Long names take precedence over short names, since a long name can look
+    /// like the short name of another item.
+    fn backing_child(
+        &mut self,
+        dir_path: &str,
+        component: &str,
+    ) -> Option<(
+        <<T::DirectoryType as DirectoryOps>::EntryType as DirEntryOps>::NameType,
+        bool,
+    )> {
+        let naming = self.layout.naming;
+        let dir = self.fs.find_dir(dir_path)?;
+        let names = self
+            .dir_cache
+            .names(dir_path, || DirNames::new(naming, &dir, dir_path));
+        let mut short_names = DirShortNames::new(naming, names, &dir, dir_path);
+        let mut short_match = None;
+        for ent in dir.listing() {
+            let name = ent.name();
+            let (exposed, _) = match names.get(&dir, dir_path, &ent) {
+                Some(exposed) => exposed,
+                None => continue,
+            };
+            let short_name = short_names.next(exposed.as_ref());
+            if is_unplaced_child(&self.layout.mapper, naming, dir_path, &ent) {
+                continue;
+            }
+            let is_directory = ent.meta().is_directory;
+            if same_chars(exposed.as_ref().chars(), component.chars()) {
+                return Some((name, is_directory));
+            }
+            if short_match.is_none() && is_short_name(&short_name, component, naming.charset) {
+                short_match = Some((name, is_directory));
+            }
+        }
+        short_match
+    }
+
+    /// Walks the backing filesystem and calls `visit` with the backing path of
+    /// every item, along with its path on the device, its exposed and short
+    /// names and what was done to it, or `None` if the item is not exposed.
+    ///
+    /// Items left off the device for lack of room, and everything in them, are
+    /// visited as `NameAction::Omitted`.
+    fn walk_names<F>(&mut self, mut visit: F)
+    where
+        F: FnMut(&PathBuff, Option<(&PathBuff, &ExposedName, &ShortName, Option<NameAction>)>),
+    {
+        let root = self.layout.prefix.clone();
+        self.walk_directory_names(&root, &PathBuff::default(), false, &mut visit);
+    }
+
+    fn walk_directory_names<F>(
+        &mut self,
+        path: &PathBuff,
+        device_path: &PathBuff,
+        omitted: bool,
+        visit: &mut F,
+    ) where
+        F: FnMut(&PathBuff, Option<(&PathBuff, &ExposedName, &ShortName, Option<NameAction>)>),
+    {
+        let dir = match self.fs.find_dir(path.to_str()) {
+            Some(dir) => dir,
+            None => return,
+        };
+        let names = DirNames::new(self.layout.naming, &dir, path.to_str());
+        let mut short_names = DirShortNames::new(self.layout.naming, &names, &dir, path.to_str());
+        for ent in dir.listing() {
+            let name = ent.name();
+            let is_directory = ent.meta().is_directory;
+            let child_path = {
+                let mut r = path.clone();
+                if is_directory {
+                    r.add_subdir(name.as_ref());
+                } else {
+                    r.add_file(name.as_ref());
+                }
+                r
+            };
+            let (exposed, action) = match names.get(&dir, path.to_str(), &ent) {
+                Some(exposed) => exposed,
+                None => {
+                    visit(&child_path, None);
+                    continue;
+                }
+            };
+            let short_name = short_names.next(exposed.as_ref());
+            let child_device_path = {
+                let mut r = device_path.clone();
+                if is_directory {
+                    r.add_subdir(exposed.as_ref());
+                } else {
+                    r.add_file(exposed.as_ref());
+                }
+                r
+            };
+            let meta = self
+                .layout
+                .naming
+                .exposed_meta(child_path.to_str(), ent.meta());
+            let omitted = omitted || is_unplaced(&self.layout.mapper, child_path.to_str(), &meta);
+            let action = if omitted {
+                Some(NameAction::Omitted)
+            } else {
+                action
+            };
+            visit(
+                &child_path,
+                Some((&child_device_path, &exposed, &short_name, action)),
+            );
+            if is_directory {
+                self.walk_directory_names(&child_path, &child_device_path, omitted, visit);
+            }
+        }
+    }
+
+    /// Finds the name of the child of the backing directory `dir_path` that is
+    /// exposed on the device as `exposed`, if there is one.
+    pub(crate) fn backing_name(
+        &mut self,
+        dir_path: &str,
+        exposed: &str,
+    ) -> Option<<<T::DirectoryType as DirectoryOps>::EntryType as DirEntryOps>::NameType> {
+        let naming = self.layout.naming;
+        let dir = self.fs.find_dir(dir_path)?;
+        let names = self
+            .dir_cache
+            .names(dir_path, || DirNames::new(naming, &dir, dir_path));
+        dir.listing()
+            .find(|ent| {
+                names
+                    .get(&dir, dir_path, ent)
+                    .is_some_and(|(cur, _)| cur.as_ref() == exposed)
+            })
+            .map(|ent| ent.name())
+    }
+}
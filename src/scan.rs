@@ -0,0 +1,320 @@
+//! Laying out a volume a few directories at a time, for firmware that runs a
+//! cooperative loop and cannot stop servicing its bus for as long as
+//! `FakeFatBuilder::build` takes to walk a large tree.
+//!
+//! Every walk over the backing tree the layout needs, whether to find
+//! duplicate files, to size the volume or to place clusters, visits the exposed
+//! directories in the order `traverse` always has: each directory before the
+//! ones below it, and subdirectories in the order they are listed in. A
+//! `DirWalk` only keeps the path of the directory it is at, and finds the next
+//! one by listing the current directory and its parents again, so a walk can
+//! stop after any directory and needs neither an allocator nor a stack that
+//! grows with the depth of the tree.
+
+use crate::bpb::BiosParameterBlock;
+use crate::builder::FakeFatBuilder;
+use crate::clustermapping::ClusterMapper;
+use crate::dedup::{add_dir_files, DedupIndex, DedupIndexOps};
+use crate::dirnames::{DirNames, DirNamesOps};
+use crate::faker::{place_dir, size_dir, FakeFat, TreeSize};
+use crate::fat::FatType;
+use crate::layout::VolumeLayout;
+use crate::pathbuffer::PathBuff;
+use crate::sanitize::NamingOptions;
+use crate::traits::{DirEntryOps, DirectoryListing, DirectoryOps, FileSystemLookup, FileSystemOps};
+
+use core::mem;
+
+/// A walk over the exposed directories below a root, visiting each directory
+/// before its subdirectories, which are visited in the order they are listed
+/// in.
+pub(crate) struct DirWalk {
+    /// The directory to visit next, or `None` once the walk is over.
+    cur: Option<PathBuff>,
+    /// The length of the path of the root, which the walk never goes above.
+    root_len: usize,
+}
+
+impl DirWalk {
+    pub(crate) fn new(root: &PathBuff) -> Self {
+        DirWalk {
+            cur: Some(root.clone()),
+            root_len: root.to_str().len(),
+        }
+    }
+
+    /// Visits the next directory with `visit`, which is passed its path and
+    /// whether it is the root, and returns whether to go on to the directories
+    /// below it.
+    ///
+    /// Returns `false` without calling `visit` if the walk is already over.
+    pub(crate) fn visit<T, F>(&mut self, fs: &mut T, naming: NamingOptions, visit: F) -> bool
+    where
+        T: FileSystemOps,
+        F: FnOnce(&mut T, &PathBuff, bool) -> bool,
+    {
+        let mut cur = match self.cur.take() {
+            Some(cur) => cur,
+            None => return false,
+        };
+        let descend = visit(fs, &cur, cur.to_str().len() == self.root_len);
+        if descend {
+            if let Some(name) = next_subdir(fs, naming, cur.to_str(), None) {
+                cur.add_subdir(name.as_ref());
+                self.cur = Some(cur);
+                return true;
+            }
+        }
+        while cur.to_str().len() > self.root_len {
+            let path = cur.to_str();
+            let parent_len = path[..path.len() - 1].rfind('/').map_or(0, |idx| idx + 1);
+            let name = &path[parent_len..path.len() - 1];
+            let sibling = next_subdir(fs, naming, &path[..parent_len], Some(name));
+            cur.truncate(parent_len);
+            if let Some(sibling) = sibling {
+                cur.add_subdir(sibling.as_ref());
+                self.cur = Some(cur);
+                return true;
+            }
+        }
+        true
+    }
+}
+
+/// The backing name of the first exposed subdirectory of the directory at
+/// `dir_path` that is listed after the one named `after`, or of the first one
+/// at all without `after`.
+fn next_subdir<T: FileSystemOps>(
+    fs: &mut T,
+    naming: NamingOptions,
+    dir_path: &str,
+    after: Option<&str>,
+) -> Option<<<T::DirectoryType as DirectoryOps>::EntryType as DirEntryOps>::NameType> {
+    let dir = fs.find_dir(dir_path)?;
+    let names = DirNames::new(naming, &dir, dir_path);
+    let mut subdirs = dir
+        .listing()
+        .filter(|ent| ent.meta().is_directory && names.get(&dir, dir_path, ent).is_some())
+        .map(|ent| ent.name());
+    if let Some(after) = after {
+        subdirs.find(|name| name.as_ref() == after)?;
+    }
+    subdirs.next()
+}
+
+/// The clusters of a volume being placed.
+struct Placement {
+    walk: DirWalk,
+    bpb: BiosParameterBlock,
+    mapper: ClusterMapper,
+    /// The last cluster allocated so far.
+    max_cluster: u32,
+}
+
+/// The part of the layout a scan is at.
+enum Stage {
+    /// Nothing was done yet.
+    Start,
+    /// Adding every exposed file to the index of duplicates.
+    Duplicates(DirWalk),
+    /// Measuring the tree to pick the capacity or the cluster size.
+    Size(DirWalk, TreeSize),
+    /// Placing the clusters of every item.
+    Place(Placement),
+    /// The layout is complete.
+    Done(VolumeLayout),
+}
+
+impl Stage {
+    /// Visits the next directory of the walk the stage is at, returning
+    /// `false` if there is none.
+    fn visit<T: FileSystemOps>(
+        &mut self,
+        fs: &mut T,
+        naming: NamingOptions,
+        dedup: &mut DedupIndex,
+    ) -> bool {
+        match self {
+            Stage::Start | Stage::Done(_) => false,
+            Stage::Duplicates(walk) => walk.visit(fs, naming, |fs, cur, _| {
+                add_dir_files(dedup, cur, fs, naming)
+            }),
+            Stage::Size(walk, size) => {
+                walk.visit(fs, naming, |fs, cur, _| size_dir(cur, fs, naming, size))
+            }
+            Stage::Place(Placement {
+                walk,
+                bpb,
+                mapper,
+                max_cluster,
+            }) => walk.visit(fs, naming, |fs, cur, is_root| {
+                let leading = if is_root {
+                    usize::from(bpb.has_volume_label())
+                } else {
+                    0
+                };
+                let placed = place_dir(mapper, cur, fs, bpb, leading, naming, dedup);
+                *max_cluster = (*max_cluster).max(placed.unwrap_or(0));
+                placed.is_some()
+            }),
+        }
+    }
+}
+
+/// The state of a layout that is worked on a bit at a time, without the
+/// backing filesystem, which `FakeFatBuilder::volume_layout` borrows rather
+/// than owns.
+pub(crate) struct Scan {
+    /// The options of the layout, with the geometry filled in once the tree
+    /// is measured.
+    options: FakeFatBuilder,
+    prefix: PathBuff,
+    naming: NamingOptions,
+    dedup: DedupIndex,
+    stage: Stage,
+    /// The number of directories visited so far, over every walk.
+    visited: usize,
+}
+
+impl Scan {
+    pub(crate) fn new(options: FakeFatBuilder, prefix: PathBuff) -> Self {
+        Scan {
+            naming: options.naming_options(),
+            options,
+            prefix,
+            dedup: DedupIndex::new(),
+            stage: Stage::Start,
+            visited: 0,
+        }
+    }
+
+    /// Visits up to `budget` directories, returning whether the layout is
+    /// complete.
+    pub(crate) fn step<T: FileSystemOps>(&mut self, fs: &mut T, budget: usize) -> bool {
+        let mut visited = 0;
+        loop {
+            if let Stage::Done(_) = self.stage {
+                return true;
+            }
+            if visited == budget {
+                return false;
+            }
+            if self.stage.visit(fs, self.naming, &mut self.dedup) {
+                visited += 1;
+                self.visited += 1;
+            } else {
+                let finished = mem::replace(&mut self.stage, Stage::Start);
+                self.stage = self.next_stage(fs, finished);
+            }
+        }
+    }
+
+    /// Completes the layout, however much of it is left.
+    pub(crate) fn finish<T: FileSystemOps>(mut self, fs: &mut T) -> VolumeLayout {
+        self.step(fs, usize::MAX);
+        match self.stage {
+            Stage::Done(layout) => layout,
+            _ => unreachable!("unlimited steps always complete the layout"),
+        }
+    }
+
+    /// The stage to go on to after `finished`.
+    fn next_stage<T: FileSystemOps>(&mut self, fs: &mut T, finished: Stage) -> Stage {
+        match finished {
+            Stage::Start if self.options.finds_duplicates() => {
+                Stage::Duplicates(DirWalk::new(&self.prefix))
+            }
+            Stage::Start | Stage::Duplicates(_) if self.options.needs_tree_size() => {
+                Stage::Size(DirWalk::new(&self.prefix), TreeSize::default())
+            }
+            Stage::Start | Stage::Duplicates(_) => {
+                self.start_placement(fs, self.options.initial_fat_type())
+            }
+            Stage::Size(_, tree) => {
+                self.options = self.options.sized_for(tree, &self.dedup);
+                self.start_placement(fs, self.options.initial_fat_type())
+            }
+            Stage::Place(placement) => match self
+                .options
+                .fit_volume(placement.bpb, placement.max_cluster)
+            {
+                Ok(bpb) => Stage::Done(VolumeLayout {
+                    bpb,
+                    mapper: placement.mapper,
+                    prefix: self.prefix.clone(),
+                    naming: self.naming,
+                    max_cluster: placement.max_cluster,
+                    shared_files: self.dedup.duplicate_count(),
+                }),
+                Err(larger) => self.start_placement(fs, larger),
+            },
+            Stage::Done(layout) => Stage::Done(layout),
+        }
+    }
+
+    fn start_placement<T: FileSystemOps>(&mut self, fs: &mut T, fat_type: FatType) -> Stage {
+        let (bpb, mapper) = self
+            .options
+            .empty_volume(fs, &self.prefix, self.naming, fat_type);
+        Stage::Place(Placement {
+            walk: DirWalk::new(&self.prefix),
+            max_cluster: bpb.allocation_start(),
+            bpb,
+            mapper,
+        })
+    }
+}
+
+/// A volume being laid out a few directories at a time, made by
+/// `FakeFatBuilder::scan`.
+///
+/// The layout walks the backing tree once to place the clusters of every item.
+/// Before that, it walks the tree once more to find duplicate files if
+/// `dedup_files` is set, and once more to size the volume unless both
+/// `total_capacity` and `sectors_per_cluster` are set. Placing the clusters is
+/// walked again for a larger kind of FAT if the tree does not fit the one it
+/// was placed on.
+pub struct ScanState<T: FileSystemOps> {
+    scan: Scan,
+    fs: T,
+}
+
+impl<T: FileSystemOps> ScanState<T> {
+    pub(crate) fn new(options: FakeFatBuilder, fs: T, prefix: PathBuff) -> Self {
+        ScanState {
+            scan: Scan::new(options, prefix),
+            fs,
+        }
+    }
+
+    /// Visits up to `budget` more directories of the backing tree, returning
+    /// whether the layout is complete, after which `finish` constructs the
+    /// device without any more work.
+    ///
+    /// A visit lists the directory and its parents, and reads the metadata of
+    /// its files, so it takes longer the more entries they have. With
+    /// `dedup_files`, the walk that finds duplicates also reads every file in
+    /// the directory to hash it.
+    pub fn step(&mut self, budget: usize) -> bool {
+        self.scan.step(&mut self.fs, budget)
+    }
+
+    /// Whether the layout is complete.
+    pub fn is_complete(&self) -> bool {
+        matches!(self.scan.stage, Stage::Done(_))
+    }
+
+    /// The number of directories visited so far, over every walk.
+    pub fn visited(&self) -> usize {
+        self.scan.visited
+    }
+
+    /// Constructs the device, first completing the layout if it is not yet,
+    /// however long that takes.
+    pub fn finish(self) -> FakeFat<T> {
+        let ScanState { scan, mut fs } = self;
+        let options = scan.options;
+        let layout = scan.finish(&mut fs);
+        options.build_with_layout(layout, fs)
+    }
+}
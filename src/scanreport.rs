@@ -0,0 +1,276 @@
+//! Sums up what `FakeFat::scan_names` finds, so that a device can log or show
+//! every name it could not expose as-is without collecting the reports itself.
+//!
+//! Every problem is counted by the `NameAction` taken, but how many of them are
+//! kept to be listed depends on the used feature flags:
+//!
+//! *  In environments without an allocator, only the first few problems are
+//!    kept, in a fixed-size array, with paths and names cut short if they do
+//!    not fit; the rest are only counted.
+//!
+//! *  In environments with an allocator, every problem is kept in a `Vec`.
+
+use crate::faker::FakeFat;
+use crate::sanitize::{NameAction, NameReport};
+use crate::traits::FileSystemOps;
+
+use core::fmt;
+
+/// The `NameAction`s in the order their counts are kept in.
+const ACTIONS: [NameAction; 5] = [
+    NameAction::Trimmed,
+    NameAction::Replaced,
+    NameAction::Rejected,
+    NameAction::Deduplicated,
+    NameAction::Omitted,
+];
+
+fn action_idx(action: NameAction) -> usize {
+    match action {
+        NameAction::Trimmed => 0,
+        NameAction::Replaced => 1,
+        NameAction::Rejected => 2,
+        NameAction::Deduplicated => 3,
+        NameAction::Omitted => 4,
+    }
+}
+
+/// Every name `FakeFat::scan_names` could not expose as-is, as produced by
+/// `FakeFat::scan_report`.
+///
+/// The `Display` implementation lists the kept problems one per line, followed
+/// by how many were left out.
+pub struct ScanReport {
+    problems: ProblemList,
+    counts: [u32; ACTIONS.len()],
+}
+
+impl ScanReport {
+    fn new() -> Self {
+        ScanReport {
+            problems: ProblemList::new(),
+            counts: [0; ACTIONS.len()],
+        }
+    }
+
+    fn push(&mut self, report: NameReport) {
+        self.counts[action_idx(report.action)] += 1;
+        self.problems.push(report);
+    }
+
+    /// Whether every name was exposed as-is.
+    pub fn is_clean(&self) -> bool {
+        self.problem_count() == 0
+    }
+
+    /// The number of names that could not be exposed as-is, including the ones
+    /// that are not kept.
+    pub fn problem_count(&self) -> u32 {
+        self.counts.iter().sum()
+    }
+
+    /// The number of names that `action` was taken on.
+    pub fn count(&self, action: NameAction) -> u32 {
+        self.counts[action_idx(action)]
+    }
+
+    /// The problems that were kept, in the order they were found.
+    pub fn problems(&self) -> impl Iterator<Item = NameReport<'_>> + '_ {
+        self.problems.iter()
+    }
+
+    /// The number of problems that were only counted, since there was no room
+    /// left to keep them.
+    pub fn omitted(&self) -> u32 {
+        self.problem_count() - self.problems.len() as u32
+    }
+}
+
+impl fmt::Display for ScanReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_clean() {
+            return write!(f, "every name was exposed as-is");
+        }
+        write!(
+            f,
+            "{} names could not be exposed as-is",
+            self.problem_count()
+        )?;
+        for problem in self.problems() {
+            match (problem.action, problem.exposed) {
+                (NameAction::Trimmed, Some(exposed)) => {
+                    write!(f, "\n  {:?} was trimmed to {:?}", problem.path, exposed)?
+                }
+                (NameAction::Replaced, Some(exposed)) => {
+                    write!(f, "\n  {:?} was renamed to {:?}", problem.path, exposed)?
+                }
+                (NameAction::Deduplicated, Some(exposed)) => write!(
+                    f,
+                    "\n  {:?} was renamed to {:?} to tell it apart",
+                    problem.path, exposed
+                )?,
+                (NameAction::Omitted, _) => write!(
+                    f,
+                    "\n  {:?} was left out, since the device ran out of room",
+                    problem.path
+                )?,
+                _ => write!(f, "\n  {:?} is hidden", problem.path)?,
+            }
+        }
+        if self.omitted() > 0 {
+            write!(f, "\n  and {} more", self.omitted())?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ScanReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut list = f.debug_list();
+        for problem in self.problems() {
+            list.entry(&problem);
+        }
+        list.finish()
+    }
+}
+
+#[cfg(not(feature = "alloc"))]
+use noalloc_problems::ProblemList;
+#[cfg(not(feature = "alloc"))]
+mod noalloc_problems {
+    use super::*;
+    use core::str::from_utf8_unchecked;
+
+    const SCAN_REPORT_CAPACITY: usize = 8;
+    const MAX_TEXT_LENGTH: usize = 128;
+
+    /// A path or name, cut short at a character boundary if it does not fit.
+    #[derive(Copy, Clone)]
+    struct Text {
+        data: [u8; MAX_TEXT_LENGTH],
+        len: usize,
+    }
+
+    impl Text {
+        fn new(text: &str) -> Self {
+            let mut len = text.len().min(MAX_TEXT_LENGTH);
+            while !text.is_char_boundary(len) {
+                len -= 1;
+            }
+            let mut data = [0; MAX_TEXT_LENGTH];
+            data[..len].copy_from_slice(&text.as_bytes()[..len]);
+            Text { data, len }
+        }
+
+        fn as_str(&self) -> &str {
+            // Only whole characters are ever copied in.
+            unsafe { from_utf8_unchecked(&self.data[..self.len]) }
+        }
+    }
+
+    #[derive(Copy, Clone)]
+    struct Problem {
+        path: Text,
+        exposed: Option<Text>,
+        action: NameAction,
+    }
+
+    pub struct ProblemList {
+        len: usize,
+        problems: [Option<Problem>; SCAN_REPORT_CAPACITY],
+    }
+
+    impl ProblemList {
+        pub fn new() -> Self {
+            ProblemList {
+                len: 0,
+                problems: [None; SCAN_REPORT_CAPACITY],
+            }
+        }
+
+        pub fn len(&self) -> usize {
+            self.len
+        }
+
+        pub fn push(&mut self, report: NameReport) {
+            if self.len >= SCAN_REPORT_CAPACITY {
+                return;
+            }
+            self.problems[self.len] = Some(Problem {
+                path: Text::new(report.path),
+                exposed: report.exposed.map(Text::new),
+                action: report.action,
+            });
+            self.len += 1;
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = NameReport<'_>> + '_ {
+            self.problems[..self.len]
+                .iter()
+                .flatten()
+                .map(|problem| NameReport {
+                    path: problem.path.as_str(),
+                    exposed: problem.exposed.as_ref().map(Text::as_str),
+                    action: problem.action,
+                })
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+use alloc_problems::ProblemList;
+#[cfg(feature = "alloc")]
+mod alloc_problems {
+    use super::*;
+
+    #[cfg(feature = "std")]
+    use std as alloc;
+
+    use alloc::borrow::ToOwned;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    pub struct ProblemList {
+        problems: Vec<(String, Option<String>, NameAction)>,
+    }
+
+    impl ProblemList {
+        pub fn new() -> Self {
+            ProblemList {
+                problems: Vec::new(),
+            }
+        }
+
+        pub fn len(&self) -> usize {
+            self.problems.len()
+        }
+
+        pub fn push(&mut self, report: NameReport) {
+            self.problems.push((
+                report.path.to_owned(),
+                report.exposed.map(ToOwned::to_owned),
+                report.action,
+            ));
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = NameReport<'_>> + '_ {
+            self.problems
+                .iter()
+                .map(|(path, exposed, action)| NameReport {
+                    path,
+                    exposed: exposed.as_deref(),
+                    action: *action,
+                })
+        }
+    }
+}
+
+impl<T: FileSystemOps> FakeFat<T> {
+    /// Walks the backing filesystem like `scan_names`, collecting every name
+    /// that could not be exposed as-is into a `ScanReport`.
+    pub fn scan_report(&mut self) -> ScanReport {
+        let mut report = ScanReport::new();
+        self.scan_names(|problem| report.push(problem));
+        report
+    }
+}
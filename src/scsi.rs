@@ -0,0 +1,278 @@
+//! The subset of the SCSI Block Commands that hosts use to mount a removable
+//! disk, shared between the transports that carry SCSI commands to the device.
+//!
+//! Transports hand each Command Descriptor Block to `ScsiDisk::execute` and
+//! then move the data the returned `ScsiResponse` describes over the wire
+//! themselves, since each transport splits data into packets differently.
+
+use crate::error::FakeFatError;
+use crate::faker::FakeFat;
+use crate::traits::FileSystemOps;
+
+/// The standard INQUIRY response: a direct access, removable device claiming
+/// SPC-2 compliance.
+pub(crate) const INQUIRY_RESPONSE: [u8; 36] = [
+    0x00, 0x80, 0x04, 0x02, 0x1F, 0x00, 0x00, 0x00, // Header
+    b'f', b'a', b'k', b'e', b'f', b'a', b't', b' ', // Vendor ID
+    b'F', b'a', b'k', b'e', b' ', b'F', b'A', b'T', // Product ID
+    b'3', b'2', b' ', b'V', b'o', b'l', b'u', b'm', //
+    b'0', b'.', b'1', b' ', // Product revision
+];
+
+/// The fixed-format sense data reporting that nothing went wrong.
+pub(crate) const REQUEST_SENSE_RESPONSE: [u8; 18] = [
+    0x70, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00,
+];
+
+/// The largest response `ScsiDisk` builds in memory.
+pub(crate) const MAX_RESPONSE_SIZE: usize = 36;
+
+/// A sense key and additional sense code describing why a command failed.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub(crate) struct Sense {
+    pub key: u8,
+    pub asc: u8,
+}
+
+impl Sense {
+    pub const NONE: Sense = Sense {
+        key: 0x00,
+        asc: 0x00,
+    };
+    pub const INVALID_COMMAND: Sense = Sense {
+        key: 0x05,
+        asc: 0x20,
+    };
+    pub const INVALID_FIELD: Sense = Sense {
+        key: 0x05,
+        asc: 0x24,
+    };
+    pub const OUT_OF_RANGE: Sense = Sense {
+        key: 0x05,
+        asc: 0x21,
+    };
+    pub const WRITE_PROTECTED: Sense = Sense {
+        key: 0x07,
+        asc: 0x27,
+    };
+    pub const WRITE_ERROR: Sense = Sense {
+        key: 0x03,
+        asc: 0x0C,
+    };
+    pub const MEDIUM_NOT_PRESENT: Sense = Sense {
+        key: 0x02,
+        asc: 0x3A,
+    };
+}
+
+/// What a transport needs to do to finish a command.
+pub(crate) enum ScsiResponse {
+    /// Send the first `len` bytes of `data` to the host.
+    Data {
+        data: [u8; MAX_RESPONSE_SIZE],
+        len: usize,
+    },
+    /// Send `len` bytes of the device starting at byte `offset` to the host.
+    Read { offset: usize, len: usize },
+    /// Receive `len` bytes from the host and pass them to `ScsiDisk::write`,
+    /// starting at byte `offset` of the device.
+    Write { offset: usize, len: usize },
+    /// The command succeeded without any data.
+    Done,
+    /// The command failed; the sense data is kept for the next REQUEST SENSE.
+    Failed,
+}
+
+impl ScsiResponse {
+    fn data(bytes: &[u8], alloc_len: usize) -> ScsiResponse {
+        let len = bytes.len().min(alloc_len);
+        let mut data = [0; MAX_RESPONSE_SIZE];
+        data[..len].copy_from_slice(&bytes[..len]);
+        ScsiResponse::Data { data, len }
+    }
+}
+
+/// The SCSI state of a single logical unit backed by a `FakeFat`.
+pub(crate) struct ScsiDisk {
+    sense: Sense,
+}
+
+impl ScsiDisk {
+    pub fn new() -> Self {
+        ScsiDisk { sense: Sense::NONE }
+    }
+
+    /// Executes the command in `cdb`.
+    ///
+    /// Once the host ejects the medium, only the commands that do not touch it
+    /// succeed until the host or the device loads it again.
+    pub fn execute<T: FileSystemOps>(&mut self, fat: &mut FakeFat<T>, cdb: &[u8]) -> ScsiResponse {
+        let mut padded = [0u8; 16];
+        let cdb_len = cdb.len().min(padded.len());
+        padded[..cdb_len].copy_from_slice(&cdb[..cdb_len]);
+        let cdb = padded;
+        let sector_size = fat.sector_size();
+        let sector_count = fat.sector_count();
+        let be16 = |at: usize| usize::from(u16::from_be_bytes([cdb[at], cdb[at + 1]]));
+        let be32 = |at: usize| u32::from_be_bytes([cdb[at], cdb[at + 1], cdb[at + 2], cdb[at + 3]]);
+
+        let response = match cdb[0] {
+            // TEST UNIT READY, READ FORMAT CAPACITIES, READ CAPACITY(10),
+            // VERIFY(10), SYNCHRONIZE CACHE(10), READ CAPACITY(16), READ(10)
+            // and WRITE(10) without a medium
+            0x00 | 0x23 | 0x25 | 0x2F | 0x35 | 0x9E | 0x28 | 0x2A if fat.is_ejected() => {
+                self.fail(Sense::MEDIUM_NOT_PRESENT)
+            }
+            // START STOP UNIT, which only ejects or loads the medium when the
+            // LOEJ bit is set and no power condition is given
+            0x1B if cdb[4] & 0xF2 == 0x02 => {
+                if cdb[4] & 0x01 != 0 {
+                    fat.load();
+                    ScsiResponse::Done
+                } else if fat.eject().is_ok() {
+                    ScsiResponse::Done
+                } else {
+                    self.fail(Sense::WRITE_ERROR)
+                }
+            }
+            // TEST UNIT READY, START STOP UNIT, PREVENT ALLOW MEDIUM REMOVAL,
+            // VERIFY(10) and SYNCHRONIZE CACHE(10)
+            0x00 | 0x1B | 0x1E | 0x2F | 0x35 => ScsiResponse::Done,
+            // REQUEST SENSE
+            0x03 => {
+                let data = self.sense_data();
+                self.sense = Sense::NONE;
+                return ScsiResponse::data(&data, usize::from(cdb[4]));
+            }
+            // INQUIRY, with only the list of supported Vital Product Data pages
+            // for hosts that ask for those
+            0x12 if cdb[1] & 0x01 == 0 => ScsiResponse::data(&INQUIRY_RESPONSE, be16(3)),
+            0x12 if cdb[2] == 0x00 => ScsiResponse::data(&[0x00, 0x00, 0x00, 0x01, 0x00], be16(3)),
+            0x12 => self.fail(Sense::INVALID_FIELD),
+            // REPORT LUNS, listing only LUN 0
+            0xA0 => {
+                let mut data = [0u8; 16];
+                data[3] = 8;
+                ScsiResponse::data(&data, be32(6) as usize)
+            }
+            // MODE SENSE(6)
+            0x1A => {
+                let header = [0x03, 0x00, self.device_flags(fat), 0x00];
+                ScsiResponse::data(&header, usize::from(cdb[4]))
+            }
+            // MODE SENSE(10)
+            0x5A => {
+                let header = [
+                    0x00,
+                    0x06,
+                    0x00,
+                    self.device_flags(fat),
+                    0x00,
+                    0x00,
+                    0x00,
+                    0x00,
+                ];
+                ScsiResponse::data(&header, be16(7))
+            }
+            // READ FORMAT CAPACITIES
+            0x23 => {
+                let mut data = [0u8; 12];
+                data[3] = 8;
+                data[4..8].copy_from_slice(&sector_count.to_be_bytes());
+                data[8] = 0x02;
+                data[9..12].copy_from_slice(&(sector_size as u32).to_be_bytes()[1..]);
+                ScsiResponse::data(&data, be16(7))
+            }
+            // READ CAPACITY(10)
+            0x25 => {
+                let mut data = [0u8; 8];
+                data[0..4].copy_from_slice(&sector_count.saturating_sub(1).to_be_bytes());
+                data[4..8].copy_from_slice(&(sector_size as u32).to_be_bytes());
+                ScsiResponse::data(&data, data.len())
+            }
+            // SERVICE ACTION IN(16) / READ CAPACITY(16)
+            0x9E if cdb[1] & 0x1F == 0x10 => {
+                let mut data = [0u8; 32];
+                let last_lba = u64::from(sector_count.saturating_sub(1));
+                data[0..8].copy_from_slice(&last_lba.to_be_bytes());
+                data[8..12].copy_from_slice(&(sector_size as u32).to_be_bytes());
+                ScsiResponse::data(&data, be32(10) as usize)
+            }
+            // READ(10) and WRITE(10)
+            op @ 0x28 | op @ 0x2A => {
+                let lba = be32(2);
+                let blocks = be16(7) as u32;
+                if u64::from(lba) + u64::from(blocks) > u64::from(sector_count) {
+                    self.fail(Sense::OUT_OF_RANGE)
+                } else {
+                    let offset = lba as usize * sector_size;
+                    let len = blocks as usize * sector_size;
+                    if op == 0x28 {
+                        ScsiResponse::Read { offset, len }
+                    } else {
+                        ScsiResponse::Write { offset, len }
+                    }
+                }
+            }
+            _ => self.fail(Sense::INVALID_COMMAND),
+        };
+        if !matches!(response, ScsiResponse::Failed) {
+            self.sense = Sense::NONE;
+        }
+        response
+    }
+
+    /// Writes data received for a `ScsiResponse::Write` into the device.
+    ///
+    /// Writes to the FAT preamble, like hosts marking the volume dirty in the
+    /// boot sector or updating FSInfo, are dropped without failing the
+    /// command, since the medium claims to be writable. Writes to a write
+    /// protected device are dropped and fail the command, as do writes the
+    /// device has no room left to keep; the caller should keep receiving the
+    /// rest of the command's data, and report the failure once all of it has
+    /// arrived.
+    pub fn write<T: FileSystemOps>(
+        &mut self,
+        fat: &mut FakeFat<T>,
+        offset: usize,
+        data: &[u8],
+    ) -> bool {
+        match fat.write_at(offset, data) {
+            Ok(written) if written == data.len() => true,
+            Ok(_) | Err(FakeFatError::ChangeSetFull { .. }) => {
+                self.sense = Sense::WRITE_ERROR;
+                false
+            }
+            Err(FakeFatError::ReadOnly { .. }) if offset < fat.layout.bpb.fat_start() => {
+                let skipped = (fat.layout.bpb.fat_start() - offset).min(data.len());
+                skipped == data.len() || self.write(fat, offset + skipped, &data[skipped..])
+            }
+            Err(_) => {
+                self.sense = Sense::WRITE_PROTECTED;
+                false
+            }
+        }
+    }
+
+    /// The fixed-format sense data describing why the last command failed.
+    pub fn sense_data(&self) -> [u8; 18] {
+        let mut data = REQUEST_SENSE_RESPONSE;
+        data[2] = self.sense.key;
+        data[12] = self.sense.asc;
+        data
+    }
+
+    fn fail(&mut self, sense: Sense) -> ScsiResponse {
+        self.sense = sense;
+        ScsiResponse::Failed
+    }
+
+    fn device_flags<T: FileSystemOps>(&self, fat: &FakeFat<T>) -> u8 {
+        if fat.write_protected() {
+            0x80
+        } else {
+            0x00
+        }
+    }
+}
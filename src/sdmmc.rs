@@ -0,0 +1,60 @@
+//! An `embedded_sdmmc::BlockDevice` implementation, so that firmware already
+//! built on `embedded-sdmmc` can mount a fake volume in place of a real SD card.
+
+use crate::faker::FakeFat;
+use crate::sector::SectorError;
+use crate::traits::FileSystemOps;
+
+use core::cell::RefCell;
+use embedded_sdmmc::{Block, BlockCount, BlockDevice, BlockIdx};
+
+/// Exposes a `FakeFat` as an `embedded_sdmmc::BlockDevice`.
+///
+/// `BlockDevice` only hands out shared references, so the device is kept in a
+/// `RefCell`; like a real SD card driver, it must not be accessed from more than
+/// one place at a time.
+///
+/// `embedded-sdmmc` always uses 512 byte blocks, so every access fails with
+/// `SectorError::BadBufferSize` if the device uses a different sector size.
+pub struct SdmmcBlockDevice<T: FileSystemOps> {
+    fat: RefCell<FakeFat<T>>,
+}
+
+impl<T: FileSystemOps> SdmmcBlockDevice<T> {
+    /// Wraps the given device.
+    pub fn new(fat: FakeFat<T>) -> Self {
+        SdmmcBlockDevice {
+            fat: RefCell::new(fat),
+        }
+    }
+
+    /// Takes back ownership of the wrapped device.
+    pub fn into_inner(self) -> FakeFat<T> {
+        self.fat.into_inner()
+    }
+}
+
+impl<T: FileSystemOps> BlockDevice for SdmmcBlockDevice<T> {
+    type Error = SectorError;
+
+    fn read(&self, blocks: &mut [Block], start_block_idx: BlockIdx) -> Result<(), SectorError> {
+        let mut fat = self.fat.borrow_mut();
+        for (offset, block) in blocks.iter_mut().enumerate() {
+            fat.read_sector(start_block_idx.0 + offset as u32, &mut block.contents)?;
+        }
+        Ok(())
+    }
+
+    fn write(&self, blocks: &[Block], start_block_idx: BlockIdx) -> Result<(), SectorError> {
+        let mut fat = self.fat.borrow_mut();
+        for (offset, block) in blocks.iter().enumerate() {
+            fat.write_sector(start_block_idx.0 + offset as u32, &block.contents)?;
+        }
+        Ok(())
+    }
+
+    fn num_blocks(&self) -> Result<BlockCount, SectorError> {
+        let fat = self.fat.borrow();
+        Ok(BlockCount((fat.image_size() / Block::LEN) as u32))
+    }
+}
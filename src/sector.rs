@@ -0,0 +1,172 @@
+//! Block-device style access to the fake device, for USB Mass Storage and SD
+//! card emulation code that addresses the device by Logical Block Address
+//! rather than by byte offset.
+
+use crate::error::FakeFatError;
+use crate::faker::{FakeFat, Region};
+use crate::traits::FileSystemOps;
+
+use core::fmt;
+
+/// The reasons a sector-addressed access can be rejected.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SectorError {
+    /// The buffer is not exactly one sector long, or for reads of several
+    /// sectors, not a whole number of sectors long.
+    BadBufferSize {
+        /// The size of a sector.
+        expected: usize,
+        /// The size of the buffer that was passed in.
+        actual: usize,
+    },
+    /// The sector lies past the end of the device.
+    OutOfRange {
+        /// The requested sector, or for reads of several sectors, the first
+        /// one past the end.
+        lba: u32,
+        /// The number of sectors on the device.
+        sector_count: u32,
+    },
+    /// The sector is part of the FAT preamble, which cannot be written to.
+    ReadOnly {
+        /// The requested sector.
+        lba: u32,
+        /// The section of the device the sector is in.
+        region: Region,
+    },
+    /// The whole device is write protected.
+    WriteProtected {
+        /// The requested sector.
+        lba: u32,
+    },
+    /// The rate limits do not allow reading the whole sector yet, or the
+    /// `ControlPlane` of a `SharedFakeFat` is using the device, so it should be
+    /// read again later.
+    Busy {
+        /// The requested sector.
+        lba: u32,
+    },
+    /// The change set has no room left to keep the sector, which only happens
+    /// without the `alloc` feature.
+    ChangeSetFull {
+        /// The requested sector.
+        lba: u32,
+    },
+}
+
+impl fmt::Display for SectorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SectorError::BadBufferSize { expected, actual } => write!(
+                f,
+                "buffer of {} bytes does not match the {} byte sector size",
+                actual, expected
+            ),
+            SectorError::OutOfRange { lba, sector_count } => write!(
+                f,
+                "sector {} is past the end of the {} sector device",
+                lba, sector_count
+            ),
+            SectorError::ReadOnly { lba, region } => {
+                write!(f, "sector {} in the {} is read-only", lba, region)
+            }
+            SectorError::WriteProtected { lba } => {
+                write!(f, "cannot write sector {} to a write protected device", lba)
+            }
+            SectorError::Busy { lba } => {
+                write!(f, "sector {} cannot be read yet", lba)
+            }
+            SectorError::ChangeSetFull { lba } => {
+                write!(f, "no room left to keep the write to sector {}", lba)
+            }
+        }
+    }
+}
+
+impl<T: FileSystemOps> FakeFat<T> {
+    /// The size of a sector, in bytes.
+    pub fn sector_size(&self) -> usize {
+        self.layout.bpb.bytes_per_sector as usize
+    }
+
+    /// The number of sectors on the device.
+    pub fn sector_count(&self) -> u32 {
+        self.layout.bpb.total_sectors_32
+    }
+
+    /// Reads sector `lba` into `buffer`, which must be exactly one sector long.
+    ///
+    /// Nothing is read if the rate limits do not allow reading the whole
+    /// sector yet.
+    pub fn read_sector(&mut self, lba: u32, buffer: &mut [u8]) -> Result<(), SectorError> {
+        let start = self.sector_start(lba, buffer.len())?;
+        if self.read_allowance(start, buffer.len()) < buffer.len() {
+            return Err(SectorError::Busy { lba });
+        }
+        self.read_at(start, buffer);
+        Ok(())
+    }
+
+    /// Reads the sectors from `lba` on into `buffer`, which must be a whole,
+    /// nonzero number of sectors long.
+    ///
+    /// Nothing is read if the rate limits do not allow reading every sector
+    /// yet.
+    pub(crate) fn read_sectors(&mut self, lba: u32, buffer: &mut [u8]) -> Result<(), SectorError> {
+        let start = self.sectors_start(lba, buffer.len())?;
+        if self.read_allowance(start, buffer.len()) < buffer.len() {
+            return Err(SectorError::Busy { lba });
+        }
+        self.read_at(start, buffer);
+        Ok(())
+    }
+
+    /// Writes `data`, which must be exactly one sector long, into sector `lba`.
+    pub fn write_sector(&mut self, lba: u32, data: &[u8]) -> Result<(), SectorError> {
+        let start = self.sector_start(lba, data.len())?;
+        match self.write_at(start, data) {
+            Ok(written) if written == data.len() => Ok(()),
+            Err(FakeFatError::ReadOnly { region, .. }) => {
+                Err(SectorError::ReadOnly { lba, region })
+            }
+            // Only part of the sector fit in the change set.
+            Ok(_) | Err(FakeFatError::ChangeSetFull { .. }) => {
+                Err(SectorError::ChangeSetFull { lba })
+            }
+            Err(_) => Err(SectorError::WriteProtected { lba }),
+        }
+    }
+
+    /// Validates a sector access, returning the device offset of the sector.
+    fn sector_start(&self, lba: u32, buffer_len: usize) -> Result<usize, SectorError> {
+        if buffer_len != self.sector_size() {
+            Err(SectorError::BadBufferSize {
+                expected: self.sector_size(),
+                actual: buffer_len,
+            })
+        } else {
+            self.sectors_start(lba, buffer_len)
+        }
+    }
+
+    /// Validates an access to the sectors from `lba` on that a buffer of
+    /// `buffer_len` bytes covers, returning the device offset of the first.
+    fn sectors_start(&self, lba: u32, buffer_len: usize) -> Result<usize, SectorError> {
+        let sector_size = self.sector_size();
+        let sectors = buffer_len / sector_size;
+        if sectors == 0 || !buffer_len.is_multiple_of(sector_size) {
+            Err(SectorError::BadBufferSize {
+                expected: sector_size,
+                actual: buffer_len,
+            })
+        } else if u64::from(lba) + sectors as u64 > u64::from(self.sector_count()) {
+            Err(SectorError::OutOfRange {
+                lba: lba.max(self.sector_count()),
+                sector_count: self.sector_count(),
+            })
+        } else {
+            Ok(lba as usize * sector_size)
+        }
+    }
+}
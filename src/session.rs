@@ -0,0 +1,162 @@
+//! A device usually only has one host at a time, but over its lifetime it can
+//! be plugged into several, or be served to several clients one after the
+//! other. Adapters that know when a host attaches, such as when a USB host
+//! enumerates the device or an NBD client connects, can mark each host's
+//! session with `FakeFat::session_start` and `FakeFat::session_end`, which
+//! scopes the per-host state of the device to that session.
+//!
+//! Writes made during a session are kept after it ends, since they still have
+//! to be applied to the backing filesystem with `FakeFat::write_back`.
+
+use crate::error::FakeFatError;
+use crate::faker::FakeFat;
+use crate::hostdetect::HostDetector;
+use crate::ratelimit::Clock;
+use crate::traits::FileSystemOps;
+
+/// A change in the session a device is in, as reported to the
+/// `SessionListener` set with `FakeFatBuilder::session_listener`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SessionEvent {
+    /// A host attached to the device.
+    Started {
+        /// The number of the session, counting up from 1.
+        id: u32,
+    },
+    /// The host of a session detached from the device.
+    Ended {
+        /// The number of the session.
+        id: u32,
+        /// What the host did to the device during the session.
+        stats: SessionStats,
+    },
+    /// The host ejected the medium, as marked by `FakeFat::eject`, after which
+    /// adapters report the device as not ready until the medium is loaded
+    /// again.
+    Ejected {
+        /// Whether the host wrote anything that has not been applied to the
+        /// backing filesystem with `FakeFat::write_back`.
+        dirty: bool,
+    },
+}
+
+/// What a host did to the device during a session.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SessionStats {
+    /// The number of bytes the host read.
+    pub bytes_read: u64,
+    /// The number of bytes the host wrote.
+    pub bytes_written: u64,
+    /// The number of clusters the host changed for the first time since the
+    /// device was constructed.
+    pub clusters_changed: u32,
+    /// The number of reads that ran past the end of the device.
+    pub out_of_range_reads: u32,
+    /// The number of errors the backing filesystem reported while serving the
+    /// host, the latest of which `FakeFat::take_backend_error` returns.
+    pub backend_errors: u32,
+}
+
+/// Called whenever a session starts or ends, or the host ejects the medium.
+pub type SessionListener = fn(SessionEvent);
+
+/// The session bookkeeping of a `FakeFat`.
+#[derive(Default)]
+pub(crate) struct Sessions {
+    pub current: Option<u32>,
+    last_id: u32,
+    pub stats: SessionStats,
+    pub listener: Option<SessionListener>,
+    clock: Option<Clock>,
+    /// When the most recent session started, according to `clock`.
+    pub last_started_at: Option<u64>,
+}
+
+impl Sessions {
+    pub fn new(listener: Option<SessionListener>, clock: Option<Clock>) -> Self {
+        Sessions {
+            listener,
+            clock,
+            ..Sessions::default()
+        }
+    }
+
+    /// The number of sessions started so far.
+    pub fn count(&self) -> u32 {
+        self.last_id
+    }
+
+    /// Hands out the number of a new session and reports that it started.
+    pub fn begin(&mut self) -> u32 {
+        self.last_id = self.last_id.wrapping_add(1);
+        self.last_started_at = self.clock.map(|clock| clock());
+        self.emit(SessionEvent::Started { id: self.last_id });
+        self.last_id
+    }
+
+    /// Reports that session `id` ended after doing `stats`.
+    pub fn finish(&self, id: u32, stats: SessionStats) {
+        self.emit(SessionEvent::Ended { id, stats });
+    }
+
+    /// Tells the listener about `event`.
+    pub fn emit(&self, event: SessionEvent) {
+        if let Some(listener) = self.listener {
+            listener(event);
+        }
+    }
+}
+
+impl<T: FileSystemOps> FakeFat<T> {
+    /// Marks that a new host attached to the device, ending the current session
+    /// first if there is one, and returns the number of the new session.
+    ///
+    /// The session's statistics start from zero, and if host detection is
+    /// enabled, the detector forgets everything it saw of the previous host.
+    ///
+    /// Fails like `session_end` if the current session can not be ended.
+    pub fn session_start(&mut self) -> Result<u32, FakeFatError> {
+        self.session_end()?;
+        if self.host_detector.is_some() {
+            self.host_detector = Some(HostDetector::new());
+        }
+        self.sessions.stats = SessionStats::default();
+        let id = self.sessions.begin();
+        self.sessions.current = Some(id);
+        Ok(id)
+    }
+
+    /// Marks that the host of the current session detached from the device,
+    /// returning what it did during the session, or `None` if there is no
+    /// current session.
+    ///
+    /// Any writes the host left batched up are applied first. Fails like
+    /// `FakeFat::flush` if they can not be, in which case the session is not
+    /// ended.
+    pub fn session_end(&mut self) -> Result<Option<SessionStats>, FakeFatError> {
+        let id = match self.sessions.current {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        self.flush()?;
+        self.sessions.current = None;
+        let stats = self.sessions.stats;
+        self.sessions.finish(id, stats);
+        Ok(Some(stats))
+    }
+
+    /// The number of the current session, or `None` if no host is attached.
+    pub fn session(&self) -> Option<u32> {
+        self.sessions.current
+    }
+
+    /// What the host did to the device so far in the current session.
+    ///
+    /// Accesses made while no session is active count towards the most recent
+    /// session, or towards no session at all if none was started yet.
+    pub fn session_stats(&self) -> SessionStats {
+        self.sessions.stats
+    }
+}
@@ -1,10 +1,13 @@
 use core::cmp;
-use core::str::from_utf8_unchecked;
+use core::num::Wrapping;
 
 use super::ReadByte;
 
 /// Represents a single name allowable in a normal directory entry, which is
-/// an 8 ASCII character name and a 3 ASCII character extention.
+/// an 8 character name and a 3 character extention.
+///
+/// Characters are stored as ASCII, or as bytes from `0x80` up in the OEM code
+/// page picked by a `ShortNameCharset`.
 #[derive(Copy, Clone, Debug)]
 pub struct ShortName {
     /// The characters in this name.
@@ -27,6 +30,121 @@ impl Default for ShortName {
     }
 }
 
+/// Makes up the short name of an item whose exposed name is not a valid short
+/// name itself.
+pub type ShortNameGenerator = fn(&str) -> ShortName;
+
+/// How short names are made up for items whose names are not valid short names,
+/// which is what hosts that ignore long names show.
+///
+/// Both numeric strategies move on to the next free tail whenever a short
+/// name is already taken within the directory, so items never share one.
+///
+/// Like the other hooks the builder takes, a caller-provided scheme is a plain
+/// `ShortNameGenerator` function rather than a trait object, which keeps the
+/// builder and its naming options `Copy` and usable without an allocator.
+#[derive(Copy, Clone, Debug, Default)]
+pub enum ShortNameStrategy {
+    /// Start from a numeric tail derived from a hash of the name, like
+    /// `LONGNA~3.TXT`.
+    Hashed,
+    /// Use the numeric tails Windows and Linux give items with a given basis
+    /// name, starting from `~1`, like `LONGNA~1.TXT` and `LONGNA~2.TXT`.
+    #[default]
+    Numeric,
+    /// Ask a `ShortNameGenerator`, whose short names should use uppercase
+    /// letters. If the short name it makes up is already taken within the
+    /// directory, the item gets the first free numeric tail instead.
+    Custom(ShortNameGenerator),
+}
+
+/// The largest numeric tail that still fits in a short name.
+pub(crate) const MAX_TAIL: u32 = 9_999_999;
+
+/// The numeric tail `ShortNameStrategy::Hashed` tries first for `name`.
+pub(crate) fn hashed_tail(name: &str) -> u32 {
+    let mut idx = Wrapping(0u8);
+    for bt in name.as_bytes().iter() {
+        let offset = bt.wrapping_sub(b'A');
+        let bottom_bits = offset & 0xF;
+        idx <<= 1;
+        idx ^= Wrapping(bottom_bits);
+    }
+    u32::from(idx.0)
+}
+
+/// Maps a character that is not ASCII to the byte an OEM code page stores it
+/// as, from `0x80` up, or to `None` if the code page lacks it.
+pub type OemEncoder = fn(char) -> Option<u8>;
+
+/// Maps a byte of an OEM code page, from `0x80` up, back to its character.
+pub type OemDecoder = fn(u8) -> char;
+
+/// How the characters of names that are not ASCII are stored in the short
+/// names made up for them.
+///
+/// Long names always keep every character, so this only matters to hosts that
+/// ignore long names.
+#[derive(Copy, Clone, Debug, Default)]
+pub enum ShortNameCharset {
+    /// Replace every character that is not ASCII with `_`, like `CAF_.TXT` for
+    /// `café.txt`, which every host shows the same way.
+    #[default]
+    Ascii,
+    /// Store characters in code page 437, the code page of US versions of DOS
+    /// and Windows, like `CAFÉ.TXT` for `café.txt`. Characters it lacks are
+    /// still replaced with `_`.
+    Cp437,
+    /// Store characters in another OEM code page, which should match the one
+    /// the device's hosts are set up with.
+    Custom(OemEncoder, OemDecoder),
+}
+
+impl ShortNameCharset {
+    /// The byte `c`, which is not ASCII, is stored as in a short name, or
+    /// `None` if it has to be replaced.
+    fn encode(self, c: char) -> Option<u8> {
+        let mut upper = c.to_uppercase();
+        let c = match (upper.next(), upper.next()) {
+            (Some(upper), None) => upper,
+            _ => c,
+        };
+        let byte = match self {
+            ShortNameCharset::Ascii => None,
+            ShortNameCharset::Cp437 => CP437_HIGH
+                .iter()
+                .position(|&high| high == c)
+                .map(|idx| idx as u8 + 0x80),
+            ShortNameCharset::Custom(encode, _) => encode(c),
+        };
+        byte.filter(|&byte| byte >= 0x80)
+    }
+
+    /// The character the byte `byte` of a short name stands for.
+    pub(crate) fn decode(self, byte: u8) -> char {
+        if byte < 0x80 {
+            return char::from(byte);
+        }
+        match self {
+            ShortNameCharset::Ascii => char::REPLACEMENT_CHARACTER,
+            ShortNameCharset::Cp437 => CP437_HIGH[usize::from(byte - 0x80)],
+            ShortNameCharset::Custom(_, decode) => decode(byte),
+        }
+    }
+}
+
+/// The characters of code page 437 from `0x80` up.
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
+
 impl PartialEq<ShortName> for ShortName {
     fn eq(&self, other: &Self) -> bool {
         self.name() == other.name() && self.ext() == other.ext()
@@ -37,12 +155,12 @@ impl Eq for ShortName {}
 
 impl PartialOrd for ShortName {
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        self.to_str().partial_cmp(&other.to_str())
+        Some(self.cmp(other))
     }
 }
 impl Ord for ShortName {
     fn cmp(&self, other: &Self) -> cmp::Ordering {
-        self.to_str().cmp(&other.to_str())
+        self.data.cmp(&other.data)
     }
 }
 
@@ -71,7 +189,7 @@ impl ShortName {
 
     /// The length of the non-extension portion of this `ShortName`.
     pub fn name_len(self) -> usize {
-        (&self.data[..8])
+        self.data[..8]
             .iter()
             .take_while(|&&c| !is_end_marker(c.into()))
             .count()
@@ -79,20 +197,20 @@ impl ShortName {
 
     /// The length of the extension portion of this `ShortName`.
     pub fn ext_len(self) -> usize {
-        (&self.data[8..])
+        self.data[8..]
             .iter()
             .take_while(|&&c| !is_end_marker(c.into()))
             .count()
     }
 
-    /// The non-extention portion of this `ShortName`.
-    pub fn name(&self) -> &str {
-        unsafe { from_utf8_unchecked(&self.data[..self.name_len()]) }
+    /// The non-extention portion of this `ShortName`, as stored.
+    pub fn name(&self) -> &[u8] {
+        &self.data[..self.name_len()]
     }
 
-    /// The extention portion of this `ShortName`.
-    pub fn ext(&self) -> &str {
-        unsafe { from_utf8_unchecked(&self.data[8..8 + self.ext_len()]) }
+    /// The extention portion of this `ShortName`, as stored.
+    pub fn ext(&self) -> &[u8] {
+        &self.data[8..8 + self.ext_len()]
     }
 
     /// Returns the FAT32 flag byte for this `ShortName`'s cases. 
@@ -105,12 +223,13 @@ impl ShortName {
         }
     }
 
-    /// Converts the **raw** shortname into a `&str`. 
-    /// 
-    /// This means that the returned value will always be exactly 11 ASCII capital,
-    /// with both the name and extension portion being padded by spaces. 
-    pub fn to_str(&self) -> &str {
-        unsafe { from_utf8_unchecked(&self.data) }
+    /// The **raw** shortname, as stored in its directory entry.
+    ///
+    /// This means that the returned value will always be exactly 11 bytes,
+    /// with both the name and extension portion being padded by spaces, and
+    /// with any characters that are not ASCII stored in an OEM code page.
+    pub fn as_bytes(&self) -> &[u8; 11] {
+        &self.data
     }
 
     /// Attempts to create a `ShortName` out of the passed in `name`.
@@ -123,11 +242,16 @@ impl ShortName {
     /// *  The non-extension portion of `name` does not all have the same case.
     /// *  The extension portion of `name` does not all have the same case.
     /// *  Any of the characters is not one in the list allowed by the FAT filesystem spec.
+    /// *  `name` contains a space or more than one `.`, which hosts would not
+    ///    read back the same way from a short name.
     pub fn wrap_str<T: AsRef<str>>(name: T) -> Option<ShortName> {
         let name: &str = name.as_ref();
         if name.len() > ShortName::SHORT_NAME_FULL_LENGTH || name.is_empty() {
             return None;
         }
+        if name.contains([' ', '\0']) || name.matches('.').count() > 1 {
+            return None;
+        }
 
         let mut retval = ShortName::default();
 
@@ -145,7 +269,7 @@ impl ShortName {
                 retval.lower_name = case == 1;
             }
 
-            retval.data[idx] = char_to_byte(c);
+            retval.data[idx] = char_to_byte(c.to_ascii_uppercase());
         }
         if ext_idx == 0 {
             return None;
@@ -156,7 +280,7 @@ impl ShortName {
         for (idx, c) in name.char_indices().skip(ext_idx + 1) {
             let idx = idx - ext_idx - 1;
             let case = case_val(c);
-            if idx > 2 || !is_valid_char(c) || name_case + case == 3 {
+            if idx > 2 || !is_valid_char(c) || ext_case + case == 3 {
                 return None;
             } else if is_end_marker(c) {
                 break;
@@ -165,15 +289,29 @@ impl ShortName {
                 retval.lower_ext = case == 1;
             }
 
-            retval.data[idx + 8] = char_to_byte(c);
+            retval.data[idx + 8] = char_to_byte(c.to_ascii_uppercase());
         }
         Some(retval)
     }
 
-    /// Converts a passed in `name` to a ShortName, hashing the long name if it
-    /// is not valid. `duplicate_count` represents the offset to add to the hash,
-    /// for use when we expect a collision between multiple long names.
+    /// Converts a passed in `name` to a ShortName, giving it the numeric tail
+    /// `duplicate_count` if it is not valid, as in `LONGNA~1.TXT`. Tails with
+    /// more digits cut the basis name shorter, as in `LONGN~12.TXT`.
+    ///
+    /// Characters that are not ASCII are replaced with `_`.
     pub fn convert_str<T: AsRef<str>>(name: T, duplicate_count: u8) -> ShortName {
+        ShortName::convert_str_in(name, duplicate_count.into(), ShortNameCharset::Ascii)
+    }
+
+    /// Like `convert_str`, but stores characters that are not ASCII as
+    /// `charset` calls for.
+    ///
+    /// Tails past `~9999999` do not fit and are cut down to it.
+    pub fn convert_str_in<T: AsRef<str>>(
+        name: T,
+        duplicate_count: u32,
+        charset: ShortNameCharset,
+    ) -> ShortName {
         let mut retval = ShortName::default();
 
         let name: &str = name.as_ref();
@@ -185,34 +323,29 @@ impl ShortName {
             .rfind(|(_, c)| *c == '.')
             .map(|(idx, _)| idx);
         let (name_part_raw, ext_part_raw) = ext_idx.map_or((name, ""), |idx| name.split_at(idx));
-        let name_part = to_valid_shortname(name_part_raw);
-        for (name_part_idx, c) in name_part.enumerate() {
-            retval.data[name_part_idx] = char_to_byte(c);
-            if name_part_idx > 7 {
-                break;
-            }
-        }
-        let ext_part = to_valid_shortname(ext_part_raw);
-        for (ext_part_idx, c) in ext_part.enumerate() {
-            retval.data[ext_part_idx + 8] = char_to_byte(c);
-            if ext_part_idx + 8 >= retval.data.len() {
-                break;
-            }
-        }
-        if duplicate_count == 0 {
-            retval.data[6] = b'~';
-            retval.data[7] = b'~';
-        } else {
-            let mut suffix_digits_left = duplicate_count;
-            let mut cur_idx = 7;
+        let mut tail = [b'~'; Self::SHORT_NAME_LENGTH];
+        let mut tail_start = Self::SHORT_NAME_LENGTH - 2;
+        if duplicate_count != 0 {
+            let mut suffix_digits_left = duplicate_count.min(MAX_TAIL);
+            tail_start = Self::SHORT_NAME_LENGTH;
             while suffix_digits_left > 0 {
-                let digit = suffix_digits_left % 10;
-                let digit_char = digit + b'0';
-                retval.data[cur_idx] = digit_char;
-                cur_idx -= 1;
+                tail_start -= 1;
+                tail[tail_start] = (suffix_digits_left % 10) as u8 + b'0';
                 suffix_digits_left /= 10;
             }
-            retval.data[cur_idx] = b'~';
+            tail_start -= 1;
+        }
+        let name_part = to_valid_shortname(name_part_raw, charset);
+        let mut name_part_len = 0;
+        for (name_part_idx, c) in name_part.take(tail_start).enumerate() {
+            retval.data[name_part_idx] = c;
+            name_part_len += 1;
+        }
+        let tail = &tail[tail_start..];
+        retval.data[name_part_len..name_part_len + tail.len()].copy_from_slice(tail);
+        let ext_part = to_valid_shortname(ext_part_raw, charset);
+        for (ext_part_idx, c) in ext_part.take(Self::SHORT_NAME_EXT_LENGTH).enumerate() {
+            retval.data[ext_part_idx + Self::SHORT_NAME_LENGTH] = c;
         }
         retval
     }
@@ -269,14 +402,16 @@ fn case_val(inp: char) -> u8 {
     }
 }
 
-fn to_valid_shortname<'a>(raw: &'a str) -> impl Iterator<Item = char> + 'a {
-    raw.chars().filter_map(|c| {
+fn to_valid_shortname(raw: &str, charset: ShortNameCharset) -> impl Iterator<Item = u8> + '_ {
+    raw.chars().filter_map(move |c| {
         if is_end_marker(c) {
             None
-        } else if !is_valid_char(c) {
-            Some('_')
+        } else if is_valid_char(c) {
+            Some(char_to_byte(c.to_ascii_uppercase()))
+        } else if c.is_ascii() {
+            Some(b'_')
         } else {
-            Some(c.to_ascii_uppercase())
+            Some(charset.encode(c).unwrap_or(b'_'))
         }
     })
 }
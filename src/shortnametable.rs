@@ -0,0 +1,174 @@
+//! Hands out the short names of the items in a single directory, making sure
+//! that no two of them get the same one.
+//!
+//! Names that are valid short names themselves keep them, so those are taken
+//! up front. Every other item then gets the first numeric tail, like `~1` or
+//! `~2`, that its basis name is not taken with yet, the way Windows and Linux
+//! pick tails when they create items. Since tails depend on which items came
+//! before, the items of a directory have to be handed their short names in
+//! the order the directory lists them.
+//!
+//! Like the Cluster Mapper, there are 2 `ShortNameTableOps` implementations
+//! toggled by the used feature flags:
+//!
+//! *  In environments without an allocator, taken names are kept in a
+//!    fixed-size array; names past its capacity are not tracked, so items
+//!    after that point can get the short name of an earlier one.
+//!
+//! *  In environments with an allocator, taken names are kept in a
+//!    `BTreeSet<ShortName>`.
+
+use crate::dirnames::{DirNames, DirNamesOps};
+use crate::sanitize::NamingOptions;
+use crate::shortname::{hashed_tail, ShortName, ShortNameStrategy, MAX_TAIL};
+use crate::traits::{DirectoryListing, DirectoryOps};
+
+pub trait ShortNameTableOps {
+    /// Constructs a table without any taken names.
+    fn new() -> Self;
+
+    /// Whether `name` is already taken.
+    fn contains(&self, name: &ShortName) -> bool;
+
+    /// Marks `name` as taken.
+    ///
+    /// Returns `false` if the name could not be stored.
+    fn insert(&mut self, name: ShortName) -> bool;
+}
+
+/// The short names handed out so far within a single directory.
+pub(crate) struct DirShortNames {
+    naming: NamingOptions,
+    taken: ShortNameTable,
+}
+
+impl DirShortNames {
+    /// Starts handing out the short names of the items of `dir`, the backing
+    /// directory at `dir_path` whose items are exposed under `names`, taking
+    /// the names of those whose exposed names are valid short names.
+    pub(crate) fn new<D: DirectoryOps>(
+        naming: NamingOptions,
+        names: &DirNames,
+        dir: &D,
+        dir_path: &str,
+    ) -> Self {
+        let mut taken = ShortNameTable::new();
+        for ent in dir.listing() {
+            let native = names
+                .get(dir, dir_path, &ent)
+                .and_then(|(exposed, _)| ShortName::wrap_str(exposed));
+            if let Some(native) = native {
+                taken.insert(native);
+            }
+        }
+        DirShortNames { naming, taken }
+    }
+
+    /// The short name of the next item of the directory, which is exposed as
+    /// `exposed`.
+    pub(crate) fn next(&mut self, exposed: &str) -> ShortName {
+        let mut short_name = ShortName::wrap_str(exposed).unwrap_or_else(|| self.generate(exposed));
+        if !self.naming.case_flags {
+            short_name.lower_name = false;
+            short_name.lower_ext = false;
+        }
+        short_name
+    }
+
+    /// Makes up a short name for `name`, which is not a valid short name.
+    fn generate(&mut self, name: &str) -> ShortName {
+        let charset = self.naming.charset;
+        let mut tail = match self.naming.short_names {
+            ShortNameStrategy::Hashed => hashed_tail(name),
+            ShortNameStrategy::Numeric => 1,
+            ShortNameStrategy::Custom(generator) => {
+                let short_name = generator(name);
+                if !self.taken.contains(&short_name) {
+                    self.taken.insert(short_name);
+                    return short_name;
+                }
+                1
+            }
+        };
+        loop {
+            let candidate = ShortName::convert_str_in(name, tail, charset);
+            if tail >= MAX_TAIL || !self.taken.contains(&candidate) {
+                self.taken.insert(candidate);
+                return candidate;
+            }
+            tail += 1;
+        }
+    }
+}
+
+#[cfg(not(feature = "alloc"))]
+pub type ShortNameTable = noalloc_shortnametable::NoallocShortNameTable;
+#[cfg(not(feature = "alloc"))]
+mod noalloc_shortnametable {
+    use super::*;
+
+    const SHORT_NAME_TABLE_CAPACITY: usize = 512;
+
+    pub struct NoallocShortNameTable {
+        len: usize,
+        names: [[u8; ShortName::SHORT_NAME_FULL_LENGTH]; SHORT_NAME_TABLE_CAPACITY],
+    }
+
+    impl ShortNameTableOps for NoallocShortNameTable {
+        fn new() -> Self {
+            NoallocShortNameTable {
+                len: 0,
+                names: [[0; ShortName::SHORT_NAME_FULL_LENGTH]; SHORT_NAME_TABLE_CAPACITY],
+            }
+        }
+
+        fn contains(&self, name: &ShortName) -> bool {
+            self.names[..self.len].contains(name.as_bytes())
+        }
+
+        fn insert(&mut self, name: ShortName) -> bool {
+            if self.contains(&name) {
+                return true;
+            }
+            if self.len >= SHORT_NAME_TABLE_CAPACITY {
+                return false;
+            }
+            self.names[self.len] = *name.as_bytes();
+            self.len += 1;
+            true
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub type ShortNameTable = alloc_shortnametable::AllocShortNameTable;
+#[cfg(feature = "alloc")]
+mod alloc_shortnametable {
+    use super::*;
+
+    #[cfg(feature = "std")]
+    use std as alloc;
+
+    use alloc::collections::BTreeSet;
+
+    pub struct AllocShortNameTable {
+        names: BTreeSet<ShortName>,
+    }
+
+    impl ShortNameTableOps for AllocShortNameTable {
+        fn new() -> Self {
+            AllocShortNameTable {
+                names: BTreeSet::new(),
+            }
+        }
+
+        fn contains(&self, name: &ShortName) -> bool {
+            self.names.contains(name)
+        }
+
+        fn insert(&mut self, name: ShortName) -> bool {
+            self.names.insert(name);
+            true
+        }
+    }
+}
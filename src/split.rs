@@ -0,0 +1,72 @@
+//! The backing filesystem of a device does two jobs: it describes the shape of
+//! the tree, which is all a `VolumeLayout` is computed from and which is used
+//! to render directories, and it holds the bytes of the files. A
+//! `SplitFileSystem` lets different things do each, so that for example a
+//! layout can be computed from a manifest of the tree while the file contents
+//! are fetched from a content store that knows nothing about directories.
+
+use crate::traits::{FileMetadata, FileSource, FileSystemOps};
+
+/// A backing filesystem that takes its directories and metadata from `tree`
+/// and the contents of its files from `data`.
+///
+/// Both are looked up with the same backing paths, so `data` has to know the
+/// files by the paths `tree` puts them at. Files whose size in `tree` differs
+/// from the contents `data` has for them are cut off or padded with zeros.
+///
+/// Both also have to report the same kind of error, which is the error of the
+/// combined filesystem.
+pub struct SplitFileSystem<Tree, Data> {
+    tree: Tree,
+    data: Data,
+}
+
+impl<Tree: FileSystemOps, Data: FileSource<Error = Tree::Error>> SplitFileSystem<Tree, Data> {
+    /// Constructs a filesystem with the shape of `tree` and the file contents
+    /// of `data`.
+    pub fn new(tree: Tree, data: Data) -> Self {
+        SplitFileSystem { tree, data }
+    }
+
+    /// The filesystem the shape of the tree comes from.
+    pub fn tree(&self) -> &Tree {
+        &self.tree
+    }
+
+    /// The source the file contents come from.
+    pub fn data(&self) -> &Data {
+        &self.data
+    }
+
+    /// Swaps out the source of the file contents, keeping the shape of the
+    /// tree, and returns the old source.
+    pub fn replace_data(&mut self, data: Data) -> Data {
+        core::mem::replace(&mut self.data, data)
+    }
+
+    /// Splits the filesystem back into the tree and the source of the file
+    /// contents.
+    pub fn into_parts(self) -> (Tree, Data) {
+        (self.tree, self.data)
+    }
+}
+
+impl<Tree, Data> FileSystemOps for SplitFileSystem<Tree, Data>
+where
+    Tree: FileSystemOps,
+    Data: FileSource<Error = Tree::Error>,
+{
+    type DirectoryType = Tree::DirectoryType;
+    type FileType = Data::FileType;
+    type Error = Tree::Error;
+
+    fn get_file(&mut self, path: &str) -> Result<Option<Self::FileType>, Tree::Error> {
+        self.data.fetch_file(path)
+    }
+    fn get_dir(&mut self, path: &str) -> Result<Option<Self::DirectoryType>, Tree::Error> {
+        self.tree.get_dir(path)
+    }
+    fn get_metadata(&mut self, path: &str) -> Result<Option<FileMetadata>, Tree::Error> {
+        self.tree.get_metadata(path)
+    }
+}
@@ -1,97 +1,282 @@
 use crate::datetime::{Date, Time};
-use crate::traits::{DirEntryOps, DirectoryOps, FileMetadata, FileOps, FileSystemOps};
-use std::fs::{self, DirEntry, File, Metadata};
-use std::io::{self, Read, Seek};
-use std::path::PathBuf;
+use crate::traits::{
+    DirEntryOps, DirectoryOps, FileMetadata, FileOps, FileSystemOps, FileSystemOpsMut,
+};
+use std::ffi::OsStr;
+use std::fs::{self, DirEntry, File, Metadata, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
 impl FileOps for File {
-    fn read_at(&mut self, offset: usize, buffer: &mut [u8]) -> usize {
-        self.seek(io::SeekFrom::Start(offset as u64)).unwrap();
-        self.read(buffer).unwrap()
+    type Error = io::Error;
+
+    fn read_at(&mut self, offset: usize, buffer: &mut [u8]) -> io::Result<usize> {
+        self.seek(io::SeekFrom::Start(offset as u64))?;
+        self.read(buffer)
     }
 }
 
 impl DirEntryOps for DirEntry {
     type NameType = String;
     fn name(&self) -> String {
-        self.file_name().into_string().unwrap()
+        self.file_name().to_string_lossy().into_owned()
     }
+    // Entries only get to report their metadata as is, so one that cannot be
+    // read is listed with the FAT epoch and no size rather than left out.
     fn meta(&self) -> FileMetadata {
-        self.metadata().map(get_metadata).unwrap()
+        self.metadata()
+            .map(|mt| get_metadata(mt, &self.file_name()))
+            .unwrap_or_default()
     }
 }
 
 impl DirectoryOps for PathBuf {
     type EntryType = DirEntry;
     type IterType = Vec<DirEntry>;
-    fn entries(&self) -> Vec<DirEntry> {
-        fs::read_dir(&self)
-            .map(|iter| iter.map(Result::unwrap).collect())
-            .unwrap()
+    type Error = io::Error;
+    fn entries(&self) -> io::Result<Vec<DirEntry>> {
+        fs::read_dir(self)?.collect()
+    }
+}
+
+/// What a `StdFileSystem` does with items that exist but cannot be read, like
+/// files and directories without read permission, broken symbolic links, or
+/// items removed while their directory is being listed.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UnreadableItems {
+    /// Leaves them out, as if they did not exist.
+    #[default]
+    Skip,
+    /// Passes the error on, so the device shows the item as missing or empty
+    /// and keeps the error for `FakeFat::take_backend_error`.
+    ///
+    /// A single entry that cannot be read fails the listing of its whole
+    /// directory.
+    Report,
+}
+
+/// A directory of a `StdFileSystem`.
+#[derive(Clone, Debug)]
+pub struct StdDirectory {
+    path: PathBuf,
+    unreadable: UnreadableItems,
+}
+
+impl StdDirectory {
+    /// The path the directory was found at, as passed to the OS.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl DirectoryOps for StdDirectory {
+    type EntryType = DirEntry;
+    type IterType = Vec<DirEntry>;
+    type Error = io::Error;
+    fn entries(&self) -> io::Result<Vec<DirEntry>> {
+        match self.unreadable {
+            UnreadableItems::Report => self.path.entries(),
+            UnreadableItems::Skip => Ok(fs::read_dir(&self.path)
+                .into_iter()
+                .flatten()
+                .filter_map(Result::ok)
+                .filter(is_readable)
+                .collect()),
+        }
+    }
+}
+
+/// Whether the item behind a directory entry can be opened, following
+/// symbolic links.
+///
+/// Only regular files are opened, since opening special files like FIFOs can
+/// block.
+fn is_readable(entry: &DirEntry) -> bool {
+    let path = entry.path();
+    match fs::metadata(&path) {
+        Ok(mt) if mt.is_dir() => fs::read_dir(&path).is_ok(),
+        Ok(mt) if mt.is_file() => File::open(&path).is_ok(),
+        Ok(_) => true,
+        Err(_) => false,
     }
 }
 
 /// An implementation of `FileSystemOps` using Rust's `std::fs` module.
-pub struct StdFileSystem {}
+///
+/// On Windows, absolute paths are passed to the OS in their extended-length
+/// `\\?\` form, so trees nested deeper than `MAX_PATH` can be exposed, and
+/// prefixes can be drive roots like `C:\` or network shares like
+/// `\\server\share`.
+///
+/// Items that cannot be read are handled according to its `UnreadableItems`
+/// policy, which defaults to skipping them.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StdFileSystem {
+    unreadable: UnreadableItems,
+}
 
-impl FileSystemOps for StdFileSystem {
-    type DirectoryType = PathBuf;
-    type FileType = File;
+impl StdFileSystem {
+    /// Creates a filesystem that skips items it cannot read.
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    fn get_file(&mut self, path: &str) -> Option<File> {
-        let raw = File::open(path);
-        match raw {
-            Ok(f) => Some(f),
-            Err(e) => match e.kind() {
-                io::ErrorKind::NotFound => None,
-                _ => {
-                    Result::<(), io::Error>::Err(e).unwrap();
-                    panic!();
-                }
+    /// Creates a filesystem that handles items it cannot read according to
+    /// `unreadable`.
+    pub fn with_unreadable_items(unreadable: UnreadableItems) -> Self {
+        StdFileSystem { unreadable }
+    }
+
+    /// Turns the result of looking up an item into `Ok(None)` if the item
+    /// does not exist, or cannot be read and the policy is to skip it.
+    fn found<T>(&self, lookup: io::Result<T>) -> io::Result<Option<T>> {
+        match lookup {
+            Ok(item) => Ok(Some(item)),
+            Err(e) => match (e.kind(), self.unreadable) {
+                (io::ErrorKind::NotFound | io::ErrorKind::NotADirectory, _) => Ok(None),
+                (_, UnreadableItems::Skip) => Ok(None),
+                (_, UnreadableItems::Report) => Err(e),
             },
         }
     }
-    fn get_dir(&mut self, path: &str) -> Option<PathBuf> {
-        let retval = PathBuf::from(path);
-        let dir_read_res = fs::read_dir(path);
-        match dir_read_res {
-            Ok(_) => Some(retval),
-            Err(e) => match e.kind() {
-                io::ErrorKind::NotFound => None,
-                _ => {
-                    Result::<(), io::Error>::Err(e).unwrap();
-                    panic!();
+}
+
+/// Converts a path built by `FakeFat` into the one to pass to the OS.
+#[cfg(not(windows))]
+fn host_path(path: &str) -> PathBuf {
+    PathBuf::from(path)
+}
+
+/// Converts a path built by `FakeFat` into the one to pass to the OS.
+///
+/// Absolute paths are turned into extended-length paths, which lift the
+/// `MAX_PATH` limit but are passed to the filesystem without any of the usual
+/// normalization, so the separators, empty components and `.` and `..`
+/// components are cleaned up here instead.
+#[cfg(windows)]
+fn host_path(path: &str) -> PathBuf {
+    const VERBATIM: &str = r"\\?\";
+    let normalized = path.replace('/', "\\");
+    let bytes = normalized.as_bytes();
+    let is_drive = bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':';
+    // The components `..` can not remove: the drive, or the server and share.
+    let (mut retval, rest, fixed) = if let Some(rest) = normalized.strip_prefix(VERBATIM) {
+        let fixed = if rest.starts_with(r"UNC\") { 3 } else { 1 };
+        (String::from(VERBATIM), rest, fixed)
+    } else if let Some(rest) = normalized.strip_prefix(r"\\") {
+        (String::from(r"\\?\UNC\"), rest, 2)
+    } else if is_drive {
+        (String::from(VERBATIM), normalized.as_str(), 1)
+    } else {
+        return PathBuf::from(normalized);
+    };
+    let mut components: Vec<&str> = Vec::new();
+    for component in rest.split('\\') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                if components.len() > fixed {
+                    components.pop();
                 }
-            },
+            }
+            component => components.push(component),
         }
     }
+    retval.push_str(&components.join("\\"));
+    // Directories keep their trailing separator, which a bare drive like `C:`
+    // needs to refer to the drive's root.
+    if normalized.ends_with('\\') || components.len() == fixed {
+        retval.push('\\');
+    }
+    PathBuf::from(retval)
+}
 
-    fn get_metadata(&mut self, path: &str) -> Option<FileMetadata> {
-        match fs::metadata(path) {
-            Ok(mt) => Some(get_metadata(mt)),
-            Err(e) => match e.kind() {
-                io::ErrorKind::NotFound => None,
-                _ => {
-                    Result::<(), io::Error>::Err(e).unwrap();
-                    panic!();
-                }
-            },
+impl FileSystemOps for StdFileSystem {
+    type DirectoryType = StdDirectory;
+    type FileType = File;
+    type Error = io::Error;
+
+    fn get_file(&mut self, path: &str) -> io::Result<Option<File>> {
+        self.found(File::open(host_path(path)))
+    }
+    fn get_dir(&mut self, path: &str) -> io::Result<Option<StdDirectory>> {
+        let path = host_path(path);
+        let listing = self.found(fs::read_dir(&path))?;
+        Ok(listing.map(|_| StdDirectory {
+            path,
+            unreadable: self.unreadable,
+        }))
+    }
+
+    fn get_metadata(&mut self, path: &str) -> io::Result<Option<FileMetadata>> {
+        let path = host_path(path);
+        let name = path.file_name().unwrap_or_default();
+        Ok(self
+            .found(fs::metadata(&path))?
+            .map(|mt| get_metadata(mt, name)))
+    }
+}
+
+impl FileSystemOpsMut for StdFileSystem {
+    fn create_file(&mut self, path: &str) -> io::Result<()> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(host_path(path))
+            .map(drop)
+    }
+
+    fn write_at(&mut self, path: &str, offset: usize, data: &[u8]) -> io::Result<()> {
+        let mut file = OpenOptions::new().write(true).open(host_path(path))?;
+        file.seek(io::SeekFrom::Start(offset as u64))?;
+        file.write_all(data)
+    }
+
+    fn truncate(&mut self, path: &str, size: usize) -> io::Result<()> {
+        OpenOptions::new()
+            .write(true)
+            .open(host_path(path))?
+            .set_len(size as u64)
+    }
+
+    fn mkdir(&mut self, path: &str) -> io::Result<()> {
+        let path = host_path(path);
+        match fs::create_dir(&path) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && path.is_dir() => Ok(()),
+            created => created,
         }
     }
+
+    fn remove(&mut self, path: &str) -> io::Result<()> {
+        let path = host_path(path);
+        if fs::symlink_metadata(&path)?.is_dir() {
+            fs::remove_dir(&path)
+        } else {
+            fs::remove_file(&path)
+        }
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> io::Result<()> {
+        fs::rename(host_path(from), host_path(to))
+    }
 }
 
-fn get_metadata(mt: Metadata) -> FileMetadata {
+/// Converts the metadata `mt` of the item named `name`.
+fn get_metadata(mt: Metadata, name: &OsStr) -> FileMetadata {
     let (cdate, ctime) = mt.created().map(sys_time_to_date_time).unwrap_or_default();
     let (mdate, mtime) = mt.modified().map(sys_time_to_date_time).unwrap_or_default();
     let (adate, _) = mt.accessed().map(sys_time_to_date_time).unwrap_or_default();
     let size = if mt.is_file() { mt.len() as u32 } else { 0 };
     let is_read_only = mt.permissions().readonly();
     let is_directory = mt.is_dir();
-    let is_hidden = false; //TODO: Check for dot start?
+    let (is_hidden, is_system) = hidden_and_system(&mt, name);
     FileMetadata {
         is_directory,
         is_hidden,
+        is_system,
         is_read_only,
         create_date: cdate,
         create_time: ctime,
@@ -102,6 +287,28 @@ fn get_metadata(mt: Metadata) -> FileMetadata {
     }
 }
 
+/// Whether the item named `name` with the metadata `mt` is hidden, and whether
+/// it is a system item, going by the item's attributes.
+#[cfg(windows)]
+fn hidden_and_system(mt: &Metadata, _name: &OsStr) -> (bool, bool) {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    const FILE_ATTRIBUTE_SYSTEM: u32 = 0x4;
+    let attributes = mt.file_attributes();
+    (
+        attributes & FILE_ATTRIBUTE_HIDDEN != 0,
+        attributes & FILE_ATTRIBUTE_SYSTEM != 0,
+    )
+}
+
+/// Whether the item named `name` with the metadata `mt` is hidden, which means
+/// its name starts with a dot, and whether it is a system item, which no item
+/// is outside of Windows.
+#[cfg(not(windows))]
+fn hidden_and_system(_mt: &Metadata, name: &OsStr) -> (bool, bool) {
+    (name.to_string_lossy().starts_with('.'), false)
+}
+
 fn sys_time_to_date_time(sys: SystemTime) -> (Date, Time) {
     let millis_since_epoch = sys
         .duration_since(SystemTime::UNIX_EPOCH)
@@ -0,0 +1,307 @@
+//! Checks that a real FAT driver reads the same tree off a device as the
+//! backing filesystem holds, so that the authors of `FileSystemOps`
+//! implementations can validate them with a single call.
+//!
+//! The device is mounted with the `fatfs` crate, which is why this module is
+//! only available with the `testing` feature. Every item exposed on the device
+//! is compared with the backing item it was exposed from:
+//!
+//! *  whether it is a directory or a file,
+//!
+//! *  the size and the contents of files, compared by hash,
+//!
+//! *  and the modification timestamp of files, down to the 2 second precision
+//!    FAT keeps them at.
+//!
+//! The timestamps of directories are not compared, since `FakeFat::refresh`
+//! moves them forward whenever their contents change. Names are compared as
+//! exposed, so items renamed by the `NamePolicy` or hidden by an `Authorizer`
+//! are not mismatches.
+//!
+//! ```no_run
+//! use fakefat::{testing, FakeFatBuilder, StdFileSystem};
+//!
+//! let mut fake = FakeFatBuilder::new().build(StdFileSystem::new(), "/srv/files");
+//! testing::assert_roundtrip(&mut fake);
+//! ```
+
+use crate::datetime::{Date, Time};
+use crate::dedup::{content_hash, FNV_OFFSET_BASIS, FNV_PRIME};
+use crate::faker::FakeFat;
+use crate::traits::{FileSystemLookup, FileSystemOps};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+/// A difference between the backing filesystem and the tree a FAT driver reads
+/// off the device, as found by `check_roundtrip`.
+///
+/// Paths are paths on the device, as made of the long names hosts normally
+/// show.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Mismatch {
+    /// The driver could not mount the device at all.
+    Mount(String),
+    /// The driver failed to read an item off the device.
+    Read {
+        /// The item that could not be read.
+        path: String,
+        /// The error the driver reported.
+        error: String,
+    },
+    /// An item exposed from the backing filesystem is not listed on the device.
+    Missing(String),
+    /// The device lists an item that is not exposed from the backing
+    /// filesystem.
+    Unexpected(String),
+    /// An item is a directory on one side but a file on the other.
+    Kind(String),
+    /// A file has a different size on the device.
+    Size {
+        /// The file whose size differs.
+        path: String,
+        /// The size the file is exposed with.
+        expected: u64,
+        /// The size read off the device.
+        actual: u64,
+    },
+    /// A file has different contents on the device.
+    Contents(String),
+    /// A file has a different modification timestamp on the device, even at
+    /// FAT precision.
+    Modified {
+        /// The file whose timestamp differs.
+        path: String,
+        /// The timestamp of the backing file.
+        expected: (Date, Time),
+        /// The timestamp read off the device.
+        actual: (Date, Time),
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mismatch::Mount(error) => write!(f, "could not mount the device: {}", error),
+            Mismatch::Read { path, error } => write!(f, "could not read {:?}: {}", path, error),
+            Mismatch::Missing(path) => write!(f, "{:?} is missing from the device", path),
+            Mismatch::Unexpected(path) => {
+                write!(
+                    f,
+                    "{:?} is on the device but not in the backing filesystem",
+                    path
+                )
+            }
+            Mismatch::Kind(path) => write!(f, "{:?} is a file on one side only", path),
+            Mismatch::Size {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{:?} is {} bytes on the device instead of {}",
+                path, actual, expected
+            ),
+            Mismatch::Contents(path) => write!(f, "{:?} has different contents", path),
+            Mismatch::Modified {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{:?} was modified at {:?} on the device instead of {:?}",
+                path, actual, expected
+            ),
+        }
+    }
+}
+
+/// What an item exposed from the backing filesystem should look like on the
+/// device.
+struct Expected {
+    is_directory: bool,
+    size: u32,
+    /// The hash of the contents of files, or `None` if the backing file could
+    /// not be opened.
+    hash: Option<u64>,
+    modified: (Date, Time),
+}
+
+/// A view of a device that the driver mounts, which drops every write so that
+/// checking a device never changes it.
+struct ReadOnlyImage<'a, T: FileSystemOps> {
+    fake: &'a mut FakeFat<T>,
+    position: u64,
+}
+
+impl<T: FileSystemOps> Read for ReadOnlyImage<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.fake.read_at(self.position as usize, buf);
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<T: FileSystemOps> Write for ReadOnlyImage<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<T: FileSystemOps> Seek for ReadOnlyImage<'_, T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let size = self.fake.layout().image_size() as i64;
+        let position = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(offset) => size + offset,
+            SeekFrom::Current(offset) => self.position as i64 + offset,
+        };
+        if position < 0 {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        self.position = position as u64;
+        Ok(self.position)
+    }
+}
+
+/// Mounts `fake` with a real FAT driver and compares every item on it with the
+/// backing item it was exposed from, returning the number of items compared.
+///
+/// Nothing is written to the device, so `fake` can keep being used afterwards.
+/// Stops at the first difference found.
+pub fn check_roundtrip<T: FileSystemOps>(fake: &mut FakeFat<T>) -> Result<usize, Mismatch> {
+    let mut expected = expected_items(fake);
+    let image = ReadOnlyImage { fake, position: 0 };
+    let fs = fatfs::FileSystem::new(image, fatfs::FsOptions::new())
+        .map_err(|error| Mismatch::Mount(error.to_string()))?;
+    let compared = compare_dir(fs.root_dir(), "", &mut expected)?;
+    match expected.into_keys().min() {
+        Some(missing) => Err(Mismatch::Missing(missing)),
+        None => Ok(compared),
+    }
+}
+
+/// Like `check_roundtrip`, but for use in tests.
+///
+/// # Panics
+/// This function panics with a description of the first difference found if
+/// the device does not match the backing filesystem.
+pub fn assert_roundtrip<T: FileSystemOps>(fake: &mut FakeFat<T>) {
+    if let Err(mismatch) = check_roundtrip(fake) {
+        panic!("Device does not match its backing filesystem: {}", mismatch);
+    }
+}
+
+/// Collects every item exposed on `fake`, keyed by its path on the device
+/// without a trailing `/`.
+fn expected_items<T: FileSystemOps>(fake: &mut FakeFat<T>) -> HashMap<String, Expected> {
+    let mut paths = Vec::new();
+    fake.name_mappings(|mapping| {
+        let device_path = mapping.device_path.trim_end_matches('/').to_owned();
+        paths.push((mapping.path.to_owned(), device_path));
+    });
+    let naming = fake.layout.naming;
+    let mut expected = HashMap::new();
+    for (path, device_path) in paths {
+        let meta = match fake.fs.find_metadata(&path) {
+            Some(meta) => naming.exposed_meta(&path, meta),
+            None => continue,
+        };
+        let hash = if meta.is_directory {
+            None
+        } else {
+            content_hash(&mut fake.fs, &path, meta.size)
+        };
+        let item = Expected {
+            is_directory: meta.is_directory,
+            size: meta.size,
+            hash,
+            modified: (meta.modify_date, meta.modify_time),
+        };
+        expected.insert(device_path, item);
+    }
+    expected
+}
+
+/// Compares the items listed in `dir`, the directory at `dir_path` on the
+/// device, and everything below them with what is `expected`, removing them
+/// from it and returning how many there were.
+fn compare_dir<T: fatfs::ReadWriteSeek>(
+    dir: fatfs::Dir<T>,
+    dir_path: &str,
+    expected: &mut HashMap<String, Expected>,
+) -> Result<usize, Mismatch> {
+    let read_error = |error: io::Error| Mismatch::Read {
+        path: dir_path.to_owned(),
+        error: error.to_string(),
+    };
+    let mut compared = 0;
+    for entry in dir.iter() {
+        let entry = entry.map_err(read_error)?;
+        let name = entry.file_name();
+        if name == "." || name == ".." {
+            continue;
+        }
+        let path = format!("{}/{}", dir_path, name);
+        let item = expected
+            .remove(&path)
+            .ok_or_else(|| Mismatch::Unexpected(path.clone()))?;
+        if item.is_directory != entry.is_dir() {
+            return Err(Mismatch::Kind(path));
+        }
+        compared += 1;
+        if entry.is_dir() {
+            compared += compare_dir(entry.to_dir(), &path, expected)?;
+            continue;
+        }
+        if u64::from(item.size) != entry.len() {
+            return Err(Mismatch::Size {
+                path,
+                expected: u64::from(item.size),
+                actual: entry.len(),
+            });
+        }
+        let mut contents = Vec::new();
+        if let Err(error) = entry.to_file().read_to_end(&mut contents) {
+            return Err(Mismatch::Read {
+                path,
+                error: error.to_string(),
+            });
+        }
+        if item.hash != Some(hash(&contents)) {
+            return Err(Mismatch::Contents(path));
+        }
+        let modified = entry.modified();
+        let date = ((modified.date.year.saturating_sub(1980)) << 9)
+            | (modified.date.month << 5)
+            | modified.date.day;
+        let time = (modified.time.hour << 11) | (modified.time.min << 5) | (modified.time.sec / 2);
+        let (expected_date, expected_time) = item.modified;
+        if (date, time)
+            != (
+                expected_date.fat_encode(),
+                expected_time.fat_encode_simple(),
+            )
+        {
+            return Err(Mismatch::Modified {
+                path,
+                expected: item.modified,
+                actual: (Date::fat_decode(date), Time::decode(time)),
+            });
+        }
+    }
+    Ok(compared)
+}
+
+/// Hashes `contents` the same way `content_hash` hashes a backing file.
+fn hash(contents: &[u8]) -> u64 {
+    contents.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
@@ -1,6 +1,10 @@
 use crate::datetime::{Date, Time};
 use crate::dirent::{FileAttributes, FileDirEntry};
 
+use core::fmt;
+use core::iter::Flatten;
+use core::result;
+
 /// Metadata associated with a given file or directory.
 #[derive(Copy, Clone, Debug, Default)]
 pub struct FileMetadata {
@@ -11,6 +15,10 @@ pub struct FileMetadata {
     /// Whether or not this child is hidden.
     pub is_hidden: bool,
 
+    /// Whether or not this child belongs to the operating system, like
+    /// Windows' system files.
+    pub is_system: bool,
+
     /// Whether or not this child cannot be written to.
     pub is_read_only: bool,
     /// The time this child was created.
@@ -37,13 +45,6 @@ impl FileMetadata {
     /// By default the returned `FileDirEntry` will have an empty `ShortName`; 
     /// be sure to set it to the correct value before use. 
     pub fn to_dirent(&self) -> FileDirEntry {
-        let mut retval = FileDirEntry::default();
-        retval.create_time = self.create_time;
-        retval.create_date = self.create_date;
-        retval.modify_time = self.modify_time;
-        retval.modify_date = self.modify_date;
-        retval.access_date = self.access_date;
-        retval.size = self.size;
         let attrs = if self.is_directory {
             FileAttributes::directory()
         } else {
@@ -54,13 +55,26 @@ impl FileMetadata {
         } else {
             attrs
         };
+        let attrs = if self.is_system {
+            attrs.and_system()
+        } else {
+            attrs
+        };
         let attrs = if self.is_read_only {
             attrs.and_read_only()
         } else {
             attrs
         };
-        retval.attrs = attrs;
-        retval
+        FileDirEntry {
+            create_time: self.create_time,
+            create_date: self.create_date,
+            modify_time: self.modify_time,
+            modify_date: self.modify_date,
+            access_date: self.access_date,
+            size: self.size,
+            attrs,
+            ..FileDirEntry::default()
+        }
     }
 }
 
@@ -93,60 +107,165 @@ pub trait DirectoryOps {
     /// The type of struct the directory uses to iterate over its entries.
     type IterType: IntoIterator<Item = Self::EntryType>;
 
+    /// The error the directory reports when it cannot be listed.
+    type Error: fmt::Debug;
+
     /// Iterates over this directory's entries.
-    fn entries(&self) -> Self::IterType;
+    fn entries(&self) -> Result<Self::IterType, Self::Error>;
 }
 
 /// Operations of a real backing file.
 pub trait FileOps {
+    /// The error the file reports when it cannot be read.
+    type Error: fmt::Debug;
+
     /// Reads up to `buffer.len()` bytes from the file starting `offset`
     /// bytes from the start of the file, returning the number of bytes read.
     ///
     /// In essence, combines both `Seek::seek` and `Read::read` into a single function.
-    fn read_at(&mut self, offset: usize, buffer: &mut [u8]) -> usize;
+    fn read_at(&mut self, offset: usize, buffer: &mut [u8]) -> Result<usize, Self::Error>;
 
 
     /// Reads a single byte from the file at the given point. 
     /// 
     /// Returns either the byte read or `None` if the `read_at` call did not 
     /// read any bytes. 
-    fn read_byte(&mut self, offset : usize) -> Option<u8> {
+    fn read_byte(&mut self, offset : usize) -> Result<Option<u8>, Self::Error> {
         let mut buffer = [0 ; 1];
-        let read = self.read_at(offset, &mut buffer);
+        let read = self.read_at(offset, &mut buffer)?;
         if read == 0 {
-            None
+            Ok(None)
         }
         else {
-            Some(buffer[0])
+            Ok(Some(buffer[0]))
         }
     }
 }
 
 /// Operations that must be implemented by the real "file system" that will be exposed
 /// as a FAT32 file system. 
+///
+/// Lookups tell items that do not exist, which are `Ok(None)`, apart from
+/// items that could not be looked up, which are `Err`. The device has no way to
+/// tell the host about the errors while it renders itself, so it shows such
+/// items as missing or empty instead and keeps the error for
+/// `FakeFat::take_backend_error`.
 pub trait FileSystemOps {
 
     /// The directory struct that this FileSystem uses. 
-    type DirectoryType: DirectoryOps;
+    type DirectoryType: DirectoryOps<Error = Self::Error>;
     
     /// The file struct that this FileSystem uses. 
-    type FileType: FileOps;
+    type FileType: FileOps<Error = Self::Error>;
+
+    /// The error the filesystem, its directories and its files report when
+    /// something cannot be read.
+    type Error: fmt::Debug;
 
     /// Attempts to find a file with the given path.
     /// 
-    /// Returns `None` if `path` does not represent an already existing 
+    /// Returns `Ok(None)` if `path` does not represent an already existing 
     /// non-directory file. 
-    fn get_file(&mut self, path: &str) -> Option<Self::FileType>;
+    fn get_file(&mut self, path: &str) -> Result<Option<Self::FileType>, Self::Error>;
     /// Attempts to find a directory with the given path.
     /// 
-    /// Returns `None` if `path` does not represent an already existing 
+    /// Returns `Ok(None)` if `path` does not represent an already existing 
     /// non-file directory. 
-    fn get_dir(&mut self, path: &str) -> Option<Self::DirectoryType>;
+    fn get_dir(&mut self, path: &str) -> Result<Option<Self::DirectoryType>, Self::Error>;
 
 
     /// Attempts to find metadata about an item with the given path.
     /// 
-    /// Returns `None` if `path` does not represent an already existing 
+    /// Returns `Ok(None)` if `path` does not represent an already existing 
     /// file or directory. 
-    fn get_metadata(&mut self, path: &str) -> Option<FileMetadata>;
+    fn get_metadata(&mut self, path: &str) -> Result<Option<FileMetadata>, Self::Error>;
+}
+
+/// Something that holds the contents of the files of a backing filesystem
+/// without necessarily knowing how they are arranged, such as a content store
+/// or a remote server. See `SplitFileSystem` for pairing one with whatever
+/// knows the shape of the tree.
+///
+/// Every `FileSystemOps` is also a `FileSource`.
+pub trait FileSource {
+    /// The file struct that this source uses.
+    type FileType: FileOps<Error = Self::Error>;
+
+    /// The error the source and its files report when something cannot be
+    /// read.
+    type Error: fmt::Debug;
+
+    /// Attempts to find the contents of the file at the given path.
+    ///
+    /// Returns `Ok(None)` if the source does not have the file.
+    fn fetch_file(&mut self, path: &str) -> Result<Option<Self::FileType>, Self::Error>;
+}
+
+impl<T: FileSystemOps> FileSource for T {
+    type FileType = T::FileType;
+    type Error = T::Error;
+
+    fn fetch_file(&mut self, path: &str) -> Result<Option<Self::FileType>, Self::Error> {
+        self.get_file(path)
+    }
 }
+
+/// Operations that must be implemented by a backing "file system" that can
+/// accept the writes a host makes to the fake FAT32 device.
+///
+/// Failures are reported with the same `Error` as lookups, which
+/// `FakeFat::write_back` passes on in its `WriteBackError`.
+pub trait FileSystemOpsMut: FileSystemOps {
+    /// Creates an empty file at the given path, leaving existing files untouched.
+    ///
+    /// Fails if there is no file at `path` afterwards.
+    fn create_file(&mut self, path: &str) -> Result<(), Self::Error>;
+
+    /// Writes all of `data` into the file at `path` starting `offset` bytes
+    /// from the start of the file.
+    fn write_at(&mut self, path: &str, offset: usize, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Resizes the file at `path` to exactly `size` bytes.
+    fn truncate(&mut self, path: &str, size: usize) -> Result<(), Self::Error>;
+
+    /// Creates a directory at the given path, leaving existing directories untouched.
+    ///
+    /// Fails if there is no directory at `path` afterwards.
+    fn mkdir(&mut self, path: &str) -> Result<(), Self::Error>;
+
+    /// Removes the file or empty directory at the given path.
+    fn remove(&mut self, path: &str) -> Result<(), Self::Error>;
+
+    /// Moves the file or directory at `from` to `to`, replacing any file
+    /// already at `to`.
+    fn rename(&mut self, from: &str, to: &str) -> Result<(), Self::Error>;
+}
+
+/// Lookups for the code that lays out and renders the device, which has no
+/// way to tell the host about errors and treats items that cannot be read like
+/// items that do not exist.
+pub(crate) trait FileSystemLookup: FileSystemOps {
+    fn find_file(&mut self, path: &str) -> Option<Self::FileType> {
+        self.get_file(path).ok().flatten()
+    }
+
+    fn find_dir(&mut self, path: &str) -> Option<Self::DirectoryType> {
+        self.get_dir(path).ok().flatten()
+    }
+
+    fn find_metadata(&mut self, path: &str) -> Option<FileMetadata> {
+        self.get_metadata(path).ok().flatten()
+    }
+}
+
+impl<T: FileSystemOps> FileSystemLookup for T {}
+
+/// Listings for the code that lays out and renders the device, which treats
+/// directories that cannot be listed like empty ones.
+pub(crate) trait DirectoryListing: DirectoryOps {
+    fn listing(&self) -> Flatten<result::IntoIter<Self::IterType>> {
+        self.entries().into_iter().flatten()
+    }
+}
+
+impl<D: DirectoryOps> DirectoryListing for D {}
@@ -0,0 +1,571 @@
+//! A USB/IP server exposing the fake device as a USB Mass Storage stick, so
+//! that a development machine can attach it over the network and mount it
+//! exactly like real hardware:
+//!
+//! ```sh
+//! modprobe vhci-hcd
+//! usbip attach -r <server address> -b 1-1
+//! ```
+//!
+//! The device speaks the Bulk Only Transport with a single logical unit and
+//! handles the SCSI commands in `crate::scsi`. Like `NbdServer`, clients are
+//! served one at a time.
+
+use crate::faker::FakeFat;
+use crate::ratelimit::read_throttled;
+use crate::scsi::{ScsiDisk, ScsiResponse};
+use crate::traits::FileSystemOps;
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+
+/// The port USB/IP servers listen on by default.
+pub const USBIP_DEFAULT_PORT: u16 = 3240;
+
+/// The bus ID the device is exported under.
+pub const USBIP_BUS_ID: &str = "1-1";
+
+const USBIP_VERSION: u16 = 0x0111;
+const OP_REQ_DEVLIST: u16 = 0x8005;
+const OP_REP_DEVLIST: u16 = 0x0005;
+const OP_REQ_IMPORT: u16 = 0x8003;
+const OP_REP_IMPORT: u16 = 0x0003;
+
+const CMD_SUBMIT: u32 = 0x0001;
+const CMD_UNLINK: u32 = 0x0002;
+const RET_SUBMIT: u32 = 0x0003;
+const RET_UNLINK: u32 = 0x0004;
+
+const DIR_IN: u32 = 1;
+
+const EPIPE: i32 = 32;
+const ECONNRESET: i32 = 104;
+
+const USB_SPEED_HIGH: u32 = 3;
+
+/// The pid.codes test VID and PID, which are free for anyone to use during
+/// development.
+const VENDOR_ID: u16 = 0x1209;
+const PRODUCT_ID: u16 = 0x0001;
+
+const BULK_IN_ENDPOINT: u32 = 1;
+const BULK_OUT_ENDPOINT: u32 = 2;
+
+const DEVICE_DESCRIPTOR: [u8; 18] = [
+    0x12, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00,
+    0x40, // Length, type, USB 2.0, class, max packet
+    0x09, 0x12, 0x01, 0x00, 0x00, 0x01, // Vendor, product, device release
+    0x01, 0x02, 0x03, 0x01, // Strings, configurations
+];
+
+const CONFIGURATION_DESCRIPTOR: [u8; 32] = [
+    0x09, 0x02, 0x20, 0x00, 0x01, 0x01, 0x00, 0x80, 0x32, // Configuration
+    0x09, 0x04, 0x00, 0x00, 0x02, 0x08, 0x06, 0x50, 0x00, // Mass Storage, SCSI, BOT
+    0x07, 0x05, 0x81, 0x02, 0x00, 0x02, 0x00, // Bulk IN 1
+    0x07, 0x05, 0x02, 0x02, 0x00, 0x02, 0x00, // Bulk OUT 2
+];
+
+const STRINGS: [&str; 3] = ["fakefat", "Fake FAT32 Volume", "000000000001"];
+
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+const CBW_SIZE: usize = 31;
+
+/// The number of bytes moved between the socket and the device at a time.
+const TRANSFER_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The status of a Bulk Only Transport command.
+#[derive(Copy, Clone)]
+struct CommandStatus {
+    tag: u32,
+    residue: u32,
+    failed: bool,
+}
+
+/// Where the device is in the Bulk Only Transport's command, data, status
+/// cycle.
+enum Phase {
+    Command,
+    DataIn {
+        response: ScsiResponse,
+        sent: usize,
+        status: CommandStatus,
+    },
+    DataOut {
+        offset: usize,
+        remaining: usize,
+        status: CommandStatus,
+    },
+    Status(CommandStatus),
+}
+
+/// A bulk IN request the host made before the device had anything to send.
+struct PendingIn {
+    seqnum: u32,
+    length: usize,
+}
+
+/// Serves a single `FakeFat` as a USB/IP Mass Storage device.
+pub struct UsbIpServer<T: FileSystemOps> {
+    fat: FakeFat<T>,
+    scsi: ScsiDisk,
+    phase: Phase,
+    pending_in: Option<PendingIn>,
+}
+
+impl<T: FileSystemOps> UsbIpServer<T> {
+    /// Wraps the given device.
+    pub fn new(fat: FakeFat<T>) -> Self {
+        UsbIpServer {
+            fat,
+            scsi: ScsiDisk::new(),
+            phase: Phase::Command,
+            pending_in: None,
+        }
+    }
+
+    /// The wrapped device.
+    pub fn fat(&self) -> &FakeFat<T> {
+        &self.fat
+    }
+
+    /// The wrapped device.
+    pub fn fat_mut(&mut self) -> &mut FakeFat<T> {
+        &mut self.fat
+    }
+
+    /// Takes back ownership of the wrapped device.
+    pub fn into_inner(self) -> FakeFat<T> {
+        self.fat
+    }
+
+    /// Listens on `addr` and serves clients one at a time, forever.
+    ///
+    /// Errors on individual connections only end that connection; only
+    /// failures of the listening socket itself are returned.
+    pub fn listen<A: ToSocketAddrs>(&mut self, addr: A) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        loop {
+            let (stream, _) = listener.accept()?;
+            stream.set_nodelay(true)?;
+            let _ = self.serve(stream);
+        }
+    }
+
+    /// Runs the USB/IP protocol over `stream` until the client disconnects.
+    ///
+    /// Each client that imports the device gets its own session on it.
+    pub fn serve<S: Read + Write>(&mut self, mut stream: S) -> io::Result<()> {
+        self.phase = Phase::Command;
+        self.pending_in = None;
+        let result = match self.negotiate(&mut stream) {
+            Ok(true) => {
+                self.fat
+                    .session_start()
+                    .map_err(|_| io::ErrorKind::OutOfMemory)?;
+                let result = self.transmit(&mut stream);
+                self.fat
+                    .session_end()
+                    .map_err(|_| io::ErrorKind::OutOfMemory)?;
+                result
+            }
+            Ok(false) => Ok(()),
+            Err(e) => Err(e),
+        };
+        self.fat.flush().map_err(|_| io::ErrorKind::OutOfMemory)?;
+        match result {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(()),
+            other => other,
+        }
+    }
+
+    /// Handles the device list and import requests.
+    ///
+    /// Returns whether the client imported the device.
+    fn negotiate<S: Read + Write>(&mut self, stream: &mut S) -> io::Result<bool> {
+        let _version = read_u16(stream)?;
+        let code = read_u16(stream)?;
+        let _status = read_u32(stream)?;
+        match code {
+            OP_REQ_DEVLIST => {
+                stream.write_all(&USBIP_VERSION.to_be_bytes())?;
+                stream.write_all(&OP_REP_DEVLIST.to_be_bytes())?;
+                stream.write_all(&0u32.to_be_bytes())?;
+                stream.write_all(&1u32.to_be_bytes())?;
+                write_device_info(stream)?;
+                stream.write_all(&[0x08, 0x06, 0x50, 0x00])?;
+                stream.flush()?;
+                Ok(false)
+            }
+            OP_REQ_IMPORT => {
+                let mut bus_id = [0u8; 32];
+                stream.read_exact(&mut bus_id)?;
+                let requested = bus_id.split(|&b| b == 0).next().unwrap_or(&[]);
+                let found = requested == USBIP_BUS_ID.as_bytes();
+                stream.write_all(&USBIP_VERSION.to_be_bytes())?;
+                stream.write_all(&OP_REP_IMPORT.to_be_bytes())?;
+                stream.write_all(&u32::from(!found).to_be_bytes())?;
+                if found {
+                    write_device_info(stream)?;
+                }
+                stream.flush()?;
+                Ok(found)
+            }
+            _ => Err(io::ErrorKind::InvalidData.into()),
+        }
+    }
+
+    /// Handles URBs until the client disconnects.
+    fn transmit<S: Read + Write>(&mut self, stream: &mut S) -> io::Result<()> {
+        let mut buffer = vec![0u8; TRANSFER_CHUNK_SIZE];
+        loop {
+            let command = read_u32(stream)?;
+            let seqnum = read_u32(stream)?;
+            let _devid = read_u32(stream)?;
+            let direction = read_u32(stream)?;
+            let endpoint = read_u32(stream)?;
+            let mut rest = [0u8; 28];
+            stream.read_exact(&mut rest)?;
+            match command {
+                CMD_SUBMIT => {
+                    let length = i32::from_be_bytes([rest[4], rest[5], rest[6], rest[7]]).max(0);
+                    let length = length as usize;
+                    let mut setup = [0u8; 8];
+                    setup.copy_from_slice(&rest[20..28]);
+                    if direction == DIR_IN {
+                        self.submit_in(stream, seqnum, endpoint, &setup, length)?;
+                    } else {
+                        self.submit_out(stream, seqnum, endpoint, &setup, length, &mut buffer)?;
+                    }
+                }
+                CMD_UNLINK => {
+                    let unlinked = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]);
+                    let status = match self.pending_in {
+                        Some(ref pending) if pending.seqnum == unlinked => {
+                            self.pending_in = None;
+                            -ECONNRESET
+                        }
+                        _ => 0,
+                    };
+                    write_ret_header(stream, RET_UNLINK, seqnum, status, 0)?;
+                }
+                _ => return Err(io::ErrorKind::InvalidData.into()),
+            }
+            stream.flush()?;
+        }
+    }
+
+    fn submit_in<S: Write>(
+        &mut self,
+        stream: &mut S,
+        seqnum: u32,
+        endpoint: u32,
+        setup: &[u8; 8],
+        length: usize,
+    ) -> io::Result<()> {
+        if endpoint == 0 {
+            let mut data = [0u8; 64];
+            return match control_in(setup, &mut data) {
+                Some(len) => {
+                    let len = len.min(length);
+                    write_ret_header(stream, RET_SUBMIT, seqnum, 0, len)?;
+                    stream.write_all(&data[..len])
+                }
+                None => write_ret_header(stream, RET_SUBMIT, seqnum, -EPIPE, 0),
+            };
+        }
+        if endpoint != BULK_IN_ENDPOINT {
+            return write_ret_header(stream, RET_SUBMIT, seqnum, -EPIPE, 0);
+        }
+        if let Phase::Command | Phase::DataOut { .. } = self.phase {
+            self.pending_in = Some(PendingIn { seqnum, length });
+            return Ok(());
+        }
+        self.bulk_in(stream, seqnum, length)
+    }
+
+    /// Answers a bulk IN request with whatever the current phase has to send.
+    fn bulk_in<S: Write>(&mut self, stream: &mut S, seqnum: u32, length: usize) -> io::Result<()> {
+        match core::mem::replace(&mut self.phase, Phase::Command) {
+            Phase::DataIn {
+                response,
+                sent,
+                status,
+            } => {
+                let total = match response {
+                    ScsiResponse::Data { len, .. } | ScsiResponse::Read { len, .. } => len,
+                    _ => 0,
+                };
+                let total = total.min(status.residue as usize);
+                let chunk = (total - sent).min(length);
+                write_ret_header(stream, RET_SUBMIT, seqnum, 0, chunk)?;
+                match response {
+                    ScsiResponse::Data { ref data, .. } => {
+                        stream.write_all(&data[sent..sent + chunk])?;
+                    }
+                    ScsiResponse::Read { offset, .. } => {
+                        let mut buffer = [0u8; 4096];
+                        let mut written = 0;
+                        while written < chunk {
+                            let len = (chunk - written).min(buffer.len());
+                            read_throttled(
+                                |idx, buffer| self.fat.read_at(idx, buffer),
+                                offset + sent + written,
+                                &mut buffer[..len],
+                            );
+                            stream.write_all(&buffer[..len])?;
+                            written += len;
+                        }
+                    }
+                    _ => {}
+                }
+                let sent = sent + chunk;
+                self.phase = if sent < total && chunk > 0 {
+                    Phase::DataIn {
+                        response,
+                        sent,
+                        status,
+                    }
+                } else {
+                    Phase::Status(CommandStatus {
+                        residue: status.residue.saturating_sub(sent as u32),
+                        ..status
+                    })
+                };
+                Ok(())
+            }
+            Phase::Status(status) => {
+                let mut csw = [0u8; 13];
+                csw[0..4].copy_from_slice(&CSW_SIGNATURE.to_le_bytes());
+                csw[4..8].copy_from_slice(&status.tag.to_le_bytes());
+                csw[8..12].copy_from_slice(&status.residue.to_le_bytes());
+                csw[12] = u8::from(status.failed);
+                let len = csw.len().min(length);
+                write_ret_header(stream, RET_SUBMIT, seqnum, 0, len)?;
+                stream.write_all(&csw[..len])
+            }
+            other => {
+                self.phase = other;
+                write_ret_header(stream, RET_SUBMIT, seqnum, -EPIPE, 0)
+            }
+        }
+    }
+
+    fn submit_out<S: Read + Write>(
+        &mut self,
+        stream: &mut S,
+        seqnum: u32,
+        endpoint: u32,
+        setup: &[u8; 8],
+        length: usize,
+        buffer: &mut [u8],
+    ) -> io::Result<()> {
+        if endpoint == 0 {
+            skip_exact(stream, length, buffer)?;
+            let status = if control_out(setup) {
+                if setup[0] == 0x21 && setup[1] == 0xFF {
+                    self.phase = Phase::Command;
+                }
+                0
+            } else {
+                -EPIPE
+            };
+            return write_ret_header(stream, RET_SUBMIT, seqnum, status, 0);
+        }
+        if endpoint != BULK_OUT_ENDPOINT {
+            skip_exact(stream, length, buffer)?;
+            return write_ret_header(stream, RET_SUBMIT, seqnum, -EPIPE, 0);
+        }
+        match core::mem::replace(&mut self.phase, Phase::Command) {
+            Phase::Command => {
+                let mut cbw = [0u8; CBW_SIZE];
+                let cbw_len = length.min(CBW_SIZE);
+                stream.read_exact(&mut cbw[..cbw_len])?;
+                skip_exact(stream, length - cbw_len, buffer)?;
+                let signature = u32::from_le_bytes([cbw[0], cbw[1], cbw[2], cbw[3]]);
+                if cbw_len != CBW_SIZE || signature != CBW_SIGNATURE {
+                    return write_ret_header(stream, RET_SUBMIT, seqnum, -EPIPE, 0);
+                }
+                write_ret_header(stream, RET_SUBMIT, seqnum, 0, length)?;
+                self.start_command(&cbw);
+            }
+            Phase::DataOut {
+                offset,
+                remaining,
+                mut status,
+            } => {
+                let mut received = 0;
+                while received < length {
+                    let chunk = (length - received).min(buffer.len());
+                    stream.read_exact(&mut buffer[..chunk])?;
+                    let usable = chunk.min(remaining.saturating_sub(received));
+                    if usable > 0 && !status.failed {
+                        let cur = offset + received;
+                        status.failed = !self.scsi.write(&mut self.fat, cur, &buffer[..usable]);
+                    }
+                    received += chunk;
+                }
+                write_ret_header(stream, RET_SUBMIT, seqnum, 0, length)?;
+                let used = length.min(remaining);
+                status.residue = status.residue.saturating_sub(used as u32);
+                self.phase = if used < remaining && length > 0 {
+                    Phase::DataOut {
+                        offset: offset + used,
+                        remaining: remaining - used,
+                        status,
+                    }
+                } else {
+                    status.failed |= self.fat.flush().is_err();
+                    Phase::Status(status)
+                };
+            }
+            other => {
+                self.phase = other;
+                skip_exact(stream, length, buffer)?;
+                return write_ret_header(stream, RET_SUBMIT, seqnum, -EPIPE, 0);
+            }
+        }
+        match self.pending_in.take() {
+            Some(pending) => self.bulk_in(stream, pending.seqnum, pending.length),
+            None => Ok(()),
+        }
+    }
+
+    /// Executes the SCSI command in a Command Block Wrapper.
+    fn start_command(&mut self, cbw: &[u8; CBW_SIZE]) {
+        let tag = u32::from_le_bytes([cbw[4], cbw[5], cbw[6], cbw[7]]);
+        let expected = u32::from_le_bytes([cbw[8], cbw[9], cbw[10], cbw[11]]);
+        let cdb_len = usize::from(cbw[14] & 0x1F).min(16);
+        let mut status = CommandStatus {
+            tag,
+            residue: expected,
+            failed: false,
+        };
+        let response = self.scsi.execute(&mut self.fat, &cbw[15..15 + cdb_len]);
+        self.phase = match response {
+            ScsiResponse::Data { .. } | ScsiResponse::Read { .. } if expected > 0 => {
+                Phase::DataIn {
+                    response,
+                    sent: 0,
+                    status,
+                }
+            }
+            ScsiResponse::Write { offset, len } if expected > 0 => Phase::DataOut {
+                offset,
+                remaining: len.min(expected as usize),
+                status,
+            },
+            ScsiResponse::Failed => {
+                status.failed = true;
+                Phase::Status(status)
+            }
+            _ => Phase::Status(status),
+        };
+    }
+}
+
+/// Answers a standard or class control IN request, returning the length of the
+/// response written into `data`, or `None` to stall the request.
+fn control_in(setup: &[u8; 8], data: &mut [u8; 64]) -> Option<usize> {
+    let request_type = setup[0];
+    let request = setup[1];
+    let value = u16::from_le_bytes([setup[2], setup[3]]);
+    let response: &[u8] = match (request_type, request) {
+        // GET_STATUS
+        (0x80, 0x00) | (0x81, 0x00) | (0x82, 0x00) => &[0, 0],
+        // GET_CONFIGURATION
+        (0x80, 0x08) => &[1],
+        // GET_INTERFACE
+        (0x81, 0x0A) => &[0],
+        // GET MAX LUN
+        (0xA1, 0xFE) => &[0],
+        // GET_DESCRIPTOR
+        (0x80, 0x06) => match (value >> 8, value & 0xFF) {
+            (1, _) => &DEVICE_DESCRIPTOR,
+            (2, _) => &CONFIGURATION_DESCRIPTOR,
+            (3, 0) => &[0x04, 0x03, 0x09, 0x04],
+            (3, idx) => {
+                let string = STRINGS.get(usize::from(idx) - 1)?;
+                let mut len = 2;
+                for unit in string.encode_utf16() {
+                    data[len..len + 2].copy_from_slice(&unit.to_le_bytes());
+                    len += 2;
+                }
+                data[0] = len as u8;
+                data[1] = 0x03;
+                return Some(len);
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+    data[..response.len()].copy_from_slice(response);
+    Some(response.len())
+}
+
+/// Whether a control OUT request is accepted.
+fn control_out(setup: &[u8; 8]) -> bool {
+    matches!(
+        (setup[0], setup[1]),
+        // CLEAR_FEATURE, SET_FEATURE, SET_ADDRESS, SET_CONFIGURATION
+        (0x00, 0x01) | (0x02, 0x01) | (0x00, 0x03) | (0x00, 0x05) | (0x00, 0x09)
+        // SET_INTERFACE, Bulk Only Mass Storage Reset
+        | (0x01, 0x0B) | (0x21, 0xFF)
+    )
+}
+
+fn write_device_info<S: Write>(stream: &mut S) -> io::Result<()> {
+    let mut path = [0u8; 256];
+    let path_str = b"/sys/devices/fakefat/1-1";
+    path[..path_str.len()].copy_from_slice(path_str);
+    let mut bus_id = [0u8; 32];
+    bus_id[..USBIP_BUS_ID.len()].copy_from_slice(USBIP_BUS_ID.as_bytes());
+    stream.write_all(&path)?;
+    stream.write_all(&bus_id)?;
+    stream.write_all(&1u32.to_be_bytes())?;
+    stream.write_all(&1u32.to_be_bytes())?;
+    stream.write_all(&USB_SPEED_HIGH.to_be_bytes())?;
+    stream.write_all(&VENDOR_ID.to_be_bytes())?;
+    stream.write_all(&PRODUCT_ID.to_be_bytes())?;
+    stream.write_all(&0x0100u16.to_be_bytes())?;
+    // The class, subclass and protocol are left to the interface, followed by
+    // the active configuration, the configuration count and the interface count.
+    stream.write_all(&[0x00, 0x00, 0x00, 0x01, 0x01, 0x01])
+}
+
+fn write_ret_header<S: Write>(
+    stream: &mut S,
+    command: u32,
+    seqnum: u32,
+    status: i32,
+    length: usize,
+) -> io::Result<()> {
+    let mut header = [0u8; 48];
+    header[0..4].copy_from_slice(&command.to_be_bytes());
+    header[4..8].copy_from_slice(&seqnum.to_be_bytes());
+    header[20..24].copy_from_slice(&status.to_be_bytes());
+    header[24..28].copy_from_slice(&(length as u32).to_be_bytes());
+    stream.write_all(&header)
+}
+
+/// Reads and discards `len` bytes.
+fn skip_exact<S: Read>(stream: &mut S, mut len: usize, buffer: &mut [u8]) -> io::Result<()> {
+    while len > 0 {
+        let chunk = len.min(buffer.len());
+        stream.read_exact(&mut buffer[..chunk])?;
+        len -= chunk;
+    }
+    Ok(())
+}
+
+fn read_u16<S: Read>(stream: &mut S) -> io::Result<u16> {
+    let mut buf = [0; 2];
+    stream.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+fn read_u32<S: Read>(stream: &mut S) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    stream.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
@@ -0,0 +1,228 @@
+//! Glue between a `FakeFat` and `usbd-storage`'s SCSI over Bulk Only Transport
+//! class, which is the usual way of exposing the fake volume to a USB host.
+//!
+//! The class hands each SCSI command to a closure passed to `Scsi::poll`, and
+//! data-carrying commands come back through that closure over several polls as
+//! the USB packets trickle in or out; `UsbStorage::process_command` keeps track
+//! of how far along the current transfer is between those calls.
+
+use crate::faker::FakeFat;
+use crate::scsi::{INQUIRY_RESPONSE, REQUEST_SENSE_RESPONSE};
+use crate::sector::SectorError;
+use crate::traits::FileSystemOps;
+
+use core::borrow::BorrowMut;
+use usb_device::bus::UsbBus;
+use usbd_storage::subclass::scsi::{Scsi, ScsiCommand};
+use usbd_storage::subclass::Command;
+use usbd_storage::transport::bbb::{BulkOnly, BulkOnlyError};
+use usbd_storage::transport::TransportError;
+
+/// The largest sector size the glue can transfer.
+const MAX_SECTOR_SIZE: usize = 4096;
+
+/// Answers the SCSI commands `usbd-storage` receives using a `FakeFat`.
+///
+/// ```ignore
+/// let mut storage = UsbStorage::new(fat);
+/// loop {
+///     if !usb_dev.poll(&mut [&mut scsi]) {
+///         continue;
+///     }
+///     let _ = scsi.poll(|command| {
+///         let _ = storage.process_command(command);
+///     });
+/// }
+/// ```
+pub struct UsbStorage<T: FileSystemOps> {
+    fat: FakeFat<T>,
+    transfer: Option<Transfer>,
+    sector: [u8; MAX_SECTOR_SIZE],
+}
+
+/// The READ(10) or WRITE(10) currently in progress.
+#[derive(Copy, Clone, Eq, PartialEq)]
+struct Transfer {
+    lba: u64,
+    len: u64,
+    done: usize,
+}
+
+impl<T: FileSystemOps> UsbStorage<T> {
+    /// Wraps the given device.
+    ///
+    /// Sectors larger than 4096 bytes are not supported.
+    pub fn new(fat: FakeFat<T>) -> Self {
+        assert!(fat.sector_size() <= MAX_SECTOR_SIZE);
+        UsbStorage {
+            fat,
+            transfer: None,
+            sector: [0; MAX_SECTOR_SIZE],
+        }
+    }
+
+    /// The wrapped device.
+    pub fn fat(&self) -> &FakeFat<T> {
+        &self.fat
+    }
+
+    /// The wrapped device.
+    pub fn fat_mut(&mut self) -> &mut FakeFat<T> {
+        &mut self.fat
+    }
+
+    /// Takes back ownership of the wrapped device.
+    pub fn into_inner(self) -> FakeFat<T> {
+        self.fat
+    }
+
+    /// Handles a single call of the `Scsi::poll` callback.
+    ///
+    /// READ CAPACITY, READ(10) and WRITE(10) are served from the wrapped
+    /// device, along with the handful of housekeeping commands hosts send
+    /// before they will mount a volume. START STOP UNIT ejects and loads the
+    /// medium through `FakeFat::eject` and `FakeFat::load`. Anything else is
+    /// failed.
+    pub fn process_command<B: UsbBus, Buf: BorrowMut<[u8]>>(
+        &mut self,
+        mut command: Command<ScsiCommand, Scsi<BulkOnly<B, Buf>>>,
+    ) -> Result<(), TransportError<BulkOnlyError>> {
+        let sector_size = self.fat.sector_size();
+        match command.kind {
+            // Once the medium is ejected with `FakeFat::eject`, there is
+            // nothing to be ready with or to transfer.
+            ScsiCommand::TestUnitReady { .. }
+            | ScsiCommand::Read { .. }
+            | ScsiCommand::Write { .. }
+                if self.fat.is_ejected() =>
+            {
+                self.transfer = None;
+                command.fail();
+            }
+            // START STOP UNIT only ejects or loads the medium when the LOEJ
+            // bit is set, and ejects it when the START bit is clear.
+            ScsiCommand::StartStopUnit {
+                load_eject: true,
+                start,
+                ..
+            } => {
+                if start {
+                    self.fat.load();
+                    command.pass();
+                } else if self.fat.eject().is_ok() {
+                    self.transfer = None;
+                    command.pass();
+                } else {
+                    command.fail();
+                }
+            }
+            ScsiCommand::TestUnitReady { .. }
+            | ScsiCommand::StartStopUnit { .. }
+            | ScsiCommand::PreventAllowMediumRemoval { .. } => {
+                command.pass();
+            }
+            ScsiCommand::Inquiry { .. } => {
+                command.try_write_data_all(&INQUIRY_RESPONSE)?;
+                command.pass();
+            }
+            ScsiCommand::RequestSense { .. } => {
+                command.try_write_data_all(&REQUEST_SENSE_RESPONSE)?;
+                command.pass();
+            }
+            ScsiCommand::ModeSense6 { .. } => {
+                let device_flags = if self.fat.write_protected() {
+                    0x80
+                } else {
+                    0x00
+                };
+                command.try_write_data_all(&[0x03, 0x00, device_flags, 0x00])?;
+                command.pass();
+            }
+            ScsiCommand::ReadCapacity10 { .. } => {
+                let last_lba = self.fat.sector_count().saturating_sub(1);
+                let mut data = [0u8; 8];
+                data[0..4].copy_from_slice(&last_lba.to_be_bytes());
+                data[4..8].copy_from_slice(&(sector_size as u32).to_be_bytes());
+                command.try_write_data_all(&data)?;
+                command.pass();
+            }
+            ScsiCommand::ReadCapacity16 { .. } => {
+                let last_lba = u64::from(self.fat.sector_count().saturating_sub(1));
+                let mut data = [0u8; 32];
+                data[0..8].copy_from_slice(&last_lba.to_be_bytes());
+                data[8..12].copy_from_slice(&(sector_size as u32).to_be_bytes());
+                command.try_write_data_all(&data)?;
+                command.pass();
+            }
+            ScsiCommand::Read { lba, len } => {
+                let mut transfer = self.start_transfer(lba, len);
+                let total = len as usize * sector_size;
+                if transfer.done < total {
+                    let sector_lba = lba as usize + transfer.done / sector_size;
+                    let sector_offset = transfer.done % sector_size;
+                    let sector = &mut self.sector[..sector_size];
+                    match self.fat.read_sector(sector_lba as u32, sector) {
+                        Ok(()) => {}
+                        // The sector is read again on a later poll, once the
+                        // rate limits allow it.
+                        Err(SectorError::Busy { .. }) => {
+                            self.transfer = Some(transfer);
+                            return Ok(());
+                        }
+                        Err(_) => {
+                            self.transfer = None;
+                            command.fail();
+                            return Ok(());
+                        }
+                    }
+                    transfer.done += command.write_data(&sector[sector_offset..])?;
+                    self.transfer = Some(transfer);
+                } else {
+                    self.transfer = None;
+                    command.pass();
+                }
+            }
+            ScsiCommand::Write { lba, len } => {
+                let mut transfer = self.start_transfer(lba, len);
+                let total = len as usize * sector_size;
+                if transfer.done < total {
+                    let sector_lba = lba as usize + transfer.done / sector_size;
+                    let sector_offset = transfer.done % sector_size;
+                    let sector = &mut self.sector[..sector_size];
+                    let read = command.read_data(&mut sector[sector_offset..])?;
+                    transfer.done += read;
+                    self.transfer = Some(transfer);
+                    if read > 0
+                        && transfer.done.is_multiple_of(sector_size)
+                        && self.fat.write_sector(sector_lba as u32, sector).is_err()
+                    {
+                        self.transfer = None;
+                        command.fail();
+                        return Ok(());
+                    }
+                }
+                if transfer.done >= total {
+                    self.transfer = None;
+                    if self.fat.flush().is_ok() {
+                        command.pass();
+                    } else {
+                        command.fail();
+                    }
+                }
+            }
+            _ => {
+                command.fail();
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets the progress of the transfer of `len` sectors starting at `lba`,
+    /// starting it over if a different transfer was in progress.
+    fn start_transfer(&self, lba: u64, len: u64) -> Transfer {
+        match self.transfer {
+            Some(cur) if cur.lba == lba && cur.len == len => cur,
+            _ => Transfer { lba, len, done: 0 },
+        }
+    }
+}
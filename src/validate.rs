@@ -0,0 +1,212 @@
+//! Consistency checks of the bookkeeping behind a device: the cluster chains
+//! the backing items were placed on, the clusters the host changed, and the
+//! free space the FSInfo sector reports.
+//!
+//! `FakeFat::validate` runs the checks on demand. With the `debug_validate`
+//! feature, every operation that changes the device also runs them afterwards
+//! and panics at the first inconsistency, naming the operation that caused
+//! it, so that a bug shows up where it happens rather than as a corrupt volume
+//! somewhere down the line. The checks walk every cluster of the volume, which
+//! makes them far too slow to leave enabled outside of debugging.
+
+use crate::changeset::{ChangeSetEntry, ChangeSetOps};
+use crate::clustermapping::ClusterMapperOps;
+use crate::faker::FakeFat;
+use crate::fat::{FatEntryValue, FatType};
+use crate::geometry::{FIRST_DATA_CLUSTER, ROOT_REGION_CLUSTER};
+use crate::traits::FileSystemOps;
+
+use core::fmt;
+use core::ops::Range;
+
+/// An inconsistency in the bookkeeping of a device, as found by
+/// `FakeFat::validate`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Inconsistency {
+    /// The cluster is allocated to an item whose chain does not hold it.
+    UnchainedCluster {
+        /// The allocated cluster.
+        cluster: u32,
+    },
+    /// The cluster is in a chain more than once, or in the chain of an item
+    /// other than the one it is allocated to.
+    CrossLinkedCluster {
+        /// The cluster that is linked to twice.
+        cluster: u32,
+        /// The first cluster of the chain it was found in.
+        head: u32,
+    },
+    /// The cluster is in a chain, or was changed by the host, but is not a
+    /// cluster of the volume.
+    ClusterOutOfRange {
+        /// The out of range cluster.
+        cluster: u32,
+    },
+    /// The change set keeps less data for the cluster than a cluster holds.
+    TruncatedChange {
+        /// The changed cluster.
+        cluster: u32,
+    },
+    /// The number of free clusters the FSInfo sector reports differs from the
+    /// number of free entries in the File Allocation Table.
+    FreeCountMismatch {
+        /// The number the FSInfo sector reports.
+        reported: u32,
+        /// The number of free entries.
+        actual: u32,
+    },
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Inconsistency::UnchainedCluster { cluster } => {
+                write!(f, "cluster {} is allocated but not in its chain", cluster)
+            }
+            Inconsistency::CrossLinkedCluster { cluster, head } => write!(
+                f,
+                "cluster {} is cross-linked in the chain starting at {}",
+                cluster, head
+            ),
+            Inconsistency::ClusterOutOfRange { cluster } => {
+                write!(f, "cluster {} is not part of the volume", cluster)
+            }
+            Inconsistency::TruncatedChange { cluster } => {
+                write!(f, "the change set keeps part of cluster {}", cluster)
+            }
+            Inconsistency::FreeCountMismatch { reported, actual } => write!(
+                f,
+                "FSInfo reports {} free clusters but the FAT has {} free",
+                reported, actual
+            ),
+        }
+    }
+}
+
+impl<T: FileSystemOps> FakeFat<T> {
+    /// Checks that the bookkeeping behind the device is consistent, returning
+    /// the first inconsistency found.
+    ///
+    /// This walks every cluster of the volume, so it takes a while on large
+    /// volumes.
+    pub fn validate(&self) -> Result<(), Inconsistency> {
+        let data_clusters = self.data_clusters();
+        let root_clusters = self.root_region_clusters();
+        let in_range =
+            |cluster: u32| data_clusters.contains(&cluster) || root_clusters.contains(&cluster);
+        let mapper = &self.layout.mapper;
+        let mut allocated = 0;
+        let mut chained = 0;
+        for cluster in data_clusters.clone().chain(root_clusters.clone()) {
+            let path = match mapper.get_path_for_cluster(cluster) {
+                Some(path) => path,
+                None => continue,
+            };
+            allocated += 1;
+            if mapper.get_chain_head_for_path(path) != Some(cluster) {
+                continue;
+            }
+            for link in mapper.get_chain_for_path(path) {
+                if !in_range(link) {
+                    return Err(Inconsistency::ClusterOutOfRange { cluster: link });
+                }
+                if mapper.get_path_for_cluster(link) != Some(path) {
+                    return Err(Inconsistency::CrossLinkedCluster {
+                        cluster: link,
+                        head: cluster,
+                    });
+                }
+                chained += 1;
+            }
+        }
+        if chained != allocated {
+            // Finding which cluster is missing from or repeated in its chain
+            // takes a walk of its chain per cluster, so it is only done once
+            // the counts show that there is one.
+            for cluster in data_clusters.clone().chain(root_clusters.clone()) {
+                self.find_misplaced_link(cluster)?;
+            }
+        }
+        for (cluster, change) in self.changes.entries() {
+            if !in_range(cluster) {
+                return Err(Inconsistency::ClusterOutOfRange { cluster });
+            }
+            if change.data().len() < self.layout.bpb.bytes_per_cluster() as usize {
+                return Err(Inconsistency::TruncatedChange { cluster });
+            }
+        }
+        let actual = data_clusters
+            .filter(|&cluster| match self.changes.cluster_entry(cluster) {
+                Some(entry) => entry == FatEntryValue::Free,
+                None => !mapper.is_allocated(cluster),
+            })
+            .count() as u32;
+        let reported = self.fsinfo.free_count();
+        if reported != actual {
+            return Err(Inconsistency::FreeCountMismatch { reported, actual });
+        }
+        Ok(())
+    }
+
+    /// Runs `validate` after `operation` changed the device if the
+    /// `debug_validate` feature is enabled.
+    ///
+    /// # Panics
+    /// This function panics if the device is inconsistent.
+    #[cfg_attr(not(feature = "debug_validate"), allow(unused_variables))]
+    pub(crate) fn debug_validate(&self, operation: &str) {
+        #[cfg(feature = "debug_validate")]
+        if let Err(e) = self.validate() {
+            panic!("{} left the device inconsistent: {}", operation, e);
+        }
+    }
+
+    /// Checks that `cluster` is in the chain of the item it is allocated to,
+    /// and if it starts the chain, that no cluster is in the chain twice.
+    fn find_misplaced_link(&self, cluster: u32) -> Result<(), Inconsistency> {
+        let mapper = &self.layout.mapper;
+        let path = match mapper.get_path_for_cluster(cluster) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let position = mapper.chain_position(cluster);
+        let chained = position.and_then(|position| {
+            let mut chain = mapper.get_chain_for_path(path).into_iter();
+            chain.nth(position)
+        });
+        if chained != Some(cluster) {
+            return Err(Inconsistency::UnchainedCluster { cluster });
+        }
+        if position != Some(0) {
+            return Ok(());
+        }
+        for (idx, link) in mapper.get_chain_for_path(path).into_iter().enumerate() {
+            if mapper.chain_position(link) != Some(idx) {
+                return Err(Inconsistency::CrossLinkedCluster {
+                    cluster: link,
+                    head: cluster,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The clusters of the data region.
+    fn data_clusters(&self) -> Range<u32> {
+        FIRST_DATA_CLUSTER..FIRST_DATA_CLUSTER + self.layout.bpb.cluster_count()
+    }
+
+    /// The pseudo clusters of the fixed root directory region of FAT12 and
+    /// FAT16 volumes.
+    fn root_region_clusters(&self) -> Range<u32> {
+        let bpb = &self.layout.bpb;
+        let clusters = if bpb.fat_type == FatType::Fat32 {
+            0
+        } else {
+            bpb.root_dir_sectors()
+                .div_ceil(u32::from(bpb.sectors_per_cluster))
+        };
+        ROOT_REGION_CLUSTER..ROOT_REGION_CLUSTER + clusters
+    }
+}
@@ -0,0 +1,195 @@
+//! Lets any filesystem supported by the `vfs` crate, like its in-memory,
+//! overlay or embedded filesystems, back a `FakeFat`.
+//!
+//! `vfs` only tracks the type and length of items, so every item is exposed
+//! with the FAT epoch as its timestamps. It also cannot write into the middle of
+//! a file, so writes and truncations rewrite the whole file.
+
+use crate::traits::{
+    DirEntryOps, DirectoryOps, FileMetadata, FileOps, FileSystemOps, FileSystemOpsMut,
+};
+use std::io::{Read, SeekFrom, Write};
+use vfs::error::VfsErrorKind;
+use vfs::{SeekAndRead, VfsError, VfsFileType, VfsPath, VfsResult};
+
+impl FileOps for Box<dyn SeekAndRead + Send> {
+    type Error = VfsError;
+
+    fn read_at(&mut self, offset: usize, buffer: &mut [u8]) -> VfsResult<usize> {
+        self.seek(SeekFrom::Start(offset as u64))?;
+        let mut read = 0;
+        while read < buffer.len() {
+            match self.read(&mut buffer[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl DirEntryOps for VfsPath {
+    type NameType = String;
+    fn name(&self) -> String {
+        self.filename()
+    }
+    fn meta(&self) -> FileMetadata {
+        self.metadata().map(get_metadata).unwrap_or_default()
+    }
+}
+
+impl DirectoryOps for VfsPath {
+    type EntryType = VfsPath;
+    type IterType = Vec<VfsPath>;
+    type Error = VfsError;
+    fn entries(&self) -> VfsResult<Vec<VfsPath>> {
+        Ok(self.read_dir()?.collect())
+    }
+}
+
+/// An implementation of `FileSystemOps` over a directory of any `vfs`
+/// filesystem.
+///
+/// Paths passed to the `FakeFat` are relative to that directory, so a
+/// `FakeFat` exposing all of it should use `"/"` as its path prefix:
+///
+/// ```ignore
+/// let root = VfsPath::new(MemoryFS::new());
+/// let fat = FakeFat::new(VfsFileSystem::new(root), "/");
+/// ```
+#[derive(Clone, Debug)]
+pub struct VfsFileSystem {
+    root: VfsPath,
+}
+
+impl VfsFileSystem {
+    /// Wraps the directory `root`.
+    pub fn new(root: VfsPath) -> Self {
+        VfsFileSystem { root }
+    }
+
+    /// The wrapped directory.
+    pub fn root(&self) -> &VfsPath {
+        &self.root
+    }
+
+    fn resolve(&self, path: &str) -> VfsResult<VfsPath> {
+        self.root.join(path.trim_matches('/'))
+    }
+
+    /// Finds the item at `path` and its metadata, or `Ok(None)` if there is
+    /// no such item.
+    fn lookup(&self, path: &str) -> VfsResult<Option<(VfsPath, vfs::VfsMetadata)>> {
+        let path = match self.resolve(path) {
+            Ok(path) => path,
+            Err(_) => return Ok(None),
+        };
+        match path.metadata() {
+            Ok(meta) => Ok(Some((path, meta))),
+            Err(e) if matches!(e.kind(), VfsErrorKind::FileNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn file_type(&self, path: &str) -> Option<VfsFileType> {
+        let path = self.resolve(path).ok()?;
+        path.metadata().ok().map(|meta| meta.file_type)
+    }
+
+    /// Reads all of the file at `path`, lets `edit` change it, and writes it back.
+    fn rewrite<F: FnOnce(&mut Vec<u8>)>(&mut self, path: &str, edit: F) -> VfsResult<()> {
+        let path = self.resolve(path)?;
+        let mut contents = Vec::new();
+        path.open_file()?.read_to_end(&mut contents)?;
+        edit(&mut contents);
+        path.create_file()?.write_all(&contents)?;
+        Ok(())
+    }
+}
+
+impl FileSystemOps for VfsFileSystem {
+    type DirectoryType = VfsPath;
+    type FileType = Box<dyn SeekAndRead + Send>;
+    type Error = VfsError;
+
+    fn get_file(&mut self, path: &str) -> VfsResult<Option<Self::FileType>> {
+        match self.lookup(path)? {
+            Some((path, meta)) if meta.file_type == VfsFileType::File => path.open_file().map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn get_dir(&mut self, path: &str) -> VfsResult<Option<VfsPath>> {
+        match self.lookup(path)? {
+            Some((path, meta)) if meta.file_type == VfsFileType::Directory => Ok(Some(path)),
+            _ => Ok(None),
+        }
+    }
+
+    fn get_metadata(&mut self, path: &str) -> VfsResult<Option<FileMetadata>> {
+        Ok(self.lookup(path)?.map(|(_, meta)| get_metadata(meta)))
+    }
+}
+
+impl FileSystemOpsMut for VfsFileSystem {
+    fn create_file(&mut self, path: &str) -> VfsResult<()> {
+        match self.file_type(path) {
+            Some(VfsFileType::File) => Ok(()),
+            Some(VfsFileType::Directory) => Err(VfsErrorKind::DirectoryExists.into()),
+            None => self.resolve(path)?.create_file().map(drop),
+        }
+    }
+
+    fn write_at(&mut self, path: &str, offset: usize, data: &[u8]) -> VfsResult<()> {
+        self.rewrite(path, |contents| {
+            if contents.len() < offset + data.len() {
+                contents.resize(offset + data.len(), 0);
+            }
+            contents[offset..offset + data.len()].copy_from_slice(data);
+        })
+    }
+
+    fn truncate(&mut self, path: &str, size: usize) -> VfsResult<()> {
+        self.rewrite(path, |contents| contents.resize(size, 0))
+    }
+
+    fn mkdir(&mut self, path: &str) -> VfsResult<()> {
+        match self.file_type(path) {
+            Some(VfsFileType::Directory) => Ok(()),
+            Some(VfsFileType::File) => Err(VfsErrorKind::FileExists.into()),
+            None => self.resolve(path)?.create_dir(),
+        }
+    }
+
+    fn remove(&mut self, path: &str) -> VfsResult<()> {
+        let path = self.resolve(path)?;
+        match path.metadata()?.file_type {
+            VfsFileType::Directory => path.remove_dir(),
+            VfsFileType::File => path.remove_file(),
+        }
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> VfsResult<()> {
+        let to_type = self.file_type(to);
+        let (from, to) = (self.resolve(from)?, self.resolve(to)?);
+        let from_type = from.metadata()?.file_type;
+        // vfs refuses to move onto an existing item, so a file in the way has
+        // to be removed first.
+        if to_type == Some(VfsFileType::File) {
+            to.remove_file()?;
+        }
+        match from_type {
+            VfsFileType::Directory => from.move_dir(&to),
+            VfsFileType::File => from.move_file(&to),
+        }
+    }
+}
+
+fn get_metadata(meta: vfs::VfsMetadata) -> FileMetadata {
+    let is_directory = meta.file_type == VfsFileType::Directory;
+    FileMetadata {
+        is_directory,
+        size: if is_directory { 0 } else { meta.len as u32 },
+        ..FileMetadata::default()
+    }
+}
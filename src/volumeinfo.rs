@@ -0,0 +1,111 @@
+//! Volume-level bookkeeping that helps tell devices in the field apart when
+//! debugging them: when the volume was created, and how often and how recently
+//! hosts mounted it.
+//!
+//! Hosts also tell devices apart, to give a device they have seen before the
+//! same drive letter or mount point it had last time. Windows goes by the
+//! disk signature of the MBR, other hosts by the volume serial number or the
+//! GUID of the partition, so a device that should keep its drive letter
+//! derives all of them from a seed with `FakeFatBuilder::identity_seed`.
+//!
+//! Mounts are counted by the sessions marked with `FakeFat::session_start`, so
+//! adapters that do not mark sessions never count any.
+
+use crate::datetime::{Date, Time};
+use crate::faker::FakeFat;
+use crate::traits::FileSystemOps;
+
+/// What is known about the history of a volume.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct VolumeInfo {
+    /// When the volume was created, as set with `FakeFatBuilder::created`, or
+    /// `None` if it was not.
+    pub created: Option<(Date, Time)>,
+    /// The volume serial number.
+    pub volume_id: u32,
+    /// The number of sessions hosts started since the device was constructed.
+    pub mount_count: u32,
+    /// When the most recent session started, in the milliseconds of the
+    /// device's `Clock`, or `None` if no session was started yet or the device
+    /// has no clock.
+    pub last_session_start: Option<u64>,
+}
+
+/// The identifiers hosts remember a device by, derived from a seed.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct VolumeIdentity {
+    /// The volume serial number, which `MbrWrapped` also uses as the disk
+    /// signature.
+    pub volume_id: u32,
+    /// A random (version 4) GUID for the partition holding the volume, in the
+    /// byte order GUID Partition Tables store GUIDs in, for adapters that put
+    /// one in front of the device.
+    pub partition_guid: [u8; 16],
+}
+
+impl VolumeIdentity {
+    /// Derives the identifiers from `seed`, always the same ones for the same
+    /// seed.
+    pub fn from_seed(seed: u64) -> Self {
+        let mut state = seed;
+        let volume_id = (splitmix64(&mut state) >> 32) as u32;
+        let mut partition_guid = [0; 16];
+        partition_guid[..8].copy_from_slice(&splitmix64(&mut state).to_le_bytes());
+        partition_guid[8..].copy_from_slice(&splitmix64(&mut state).to_le_bytes());
+        // The version is the top nibble of the third field, which is stored
+        // little endian, and the variant the top bits of the fourth.
+        partition_guid[7] = (partition_guid[7] & 0x0F) | 0x40;
+        partition_guid[8] = (partition_guid[8] & 0x3F) | 0x80;
+        VolumeIdentity {
+            volume_id,
+            partition_guid,
+        }
+    }
+}
+
+/// The next output of the SplitMix64 generator, which mixes even similar
+/// seeds into unrelated identifiers.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Derives a volume serial number from the time the volume was created, the
+/// same way DOS does when formatting a volume.
+pub(crate) fn derived_volume_id(date: Date, time: Time) -> u32 {
+    let day = (u16::from(date.month()) << 8) | u16::from(date.day());
+    let second = (u16::from(time.second()) << 8) | u16::from(time.tenths() * 10);
+    let minute = (u16::from(time.hour()) << 8) | u16::from(time.minute());
+    let low = day.wrapping_add(second);
+    let high = minute.wrapping_add(date.year());
+    (u32::from(high) << 16) | u32::from(low)
+}
+
+impl<T: FileSystemOps> FakeFat<T> {
+    /// The creation time, serial number and mount history of the volume.
+    pub fn volume_info(&self) -> VolumeInfo {
+        VolumeInfo {
+            created: self.created,
+            volume_id: self.layout.bpb.volume_id,
+            mount_count: self.sessions.count(),
+            last_session_start: self.sessions.last_started_at,
+        }
+    }
+
+    /// The identifiers hosts remember the device by, or `None` unless
+    /// `FakeFatBuilder::identity_seed` was set.
+    ///
+    /// The volume serial number is the one the device uses, which is only
+    /// derived from the seed if `FakeFatBuilder::volume_id` was not set.
+    pub fn identity(&self) -> Option<VolumeIdentity> {
+        let identity = VolumeIdentity::from_seed(self.identity_seed?);
+        Some(VolumeIdentity {
+            volume_id: self.volume_id(),
+            ..identity
+        })
+    }
+}
@@ -0,0 +1,867 @@
+//! Propagates the files and directories a host writes to the fake device back
+//! into the backing filesystem.
+//!
+//! Host writes only ever land in the `ChangeSet`, and the host is free to lay
+//! them out however it likes: new chains in clusters the mapper never handed
+//! out, new entries in the middle of existing directories, and so on. Instead of
+//! trying to interpret individual writes, write-back walks the volume exactly
+//! like a host would, by reading the FAT and directory entries back out of the
+//! device, and then pushes every file that is new or touches a changed cluster
+//! into the backing filesystem.
+
+use crate::access::{Access, Operation};
+use crate::changeset::ChangeSetOps;
+use crate::clustermapping::ClusterMapperOps;
+use crate::commitplan::{CommitOp, CommitPlan};
+use crate::dircache::DirCacheOps;
+use crate::dirent::LfnDirEntry;
+use crate::error::FakeFatError;
+use crate::faker::{is_unplaced_child, read_padded, FakeFat};
+use crate::fat::{FatEntryValue, FatType};
+use crate::geometry::{dirents_per_cluster, DIRENT_SIZE, FIRST_DATA_CLUSTER, ROOT_REGION_CLUSTER};
+use crate::pathbuffer::PathBuff;
+use crate::shortname::{ShortName, ShortNameCharset};
+use crate::traits::{DirEntryOps, DirectoryListing, FileSystemLookup, FileSystemOpsMut};
+
+use core::fmt;
+
+/// The deepest directory nesting write-back will follow, which is already more
+/// than a 260 character FAT path can hold.
+pub(crate) const MAX_DEPTH: usize = 128;
+
+/// The maximum length, in UTF-16 code units, of a Long File Name.
+const MAX_LFN_UNITS: usize = 255;
+
+/// The longest UTF-8 encoding of a `MAX_LFN_UNITS` Long File Name.
+pub(crate) const MAX_NAME_BYTES: usize = MAX_LFN_UNITS * 3;
+
+/// The reasons applying host writes to the backing filesystem can fail.
+///
+/// Items are identified by where their directory entry lives on the device,
+/// since their paths cannot be stored without an allocator.
+///
+/// `E` is the error the backing filesystem reports, which `FakeFatError` and
+/// `CommitPlan` leave out.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WriteBackError<E = ()> {
+    /// The backing filesystem refused to create the file or directory described
+    /// by slot `entry` of the directory starting at `dir_cluster`.
+    CreateFailed {
+        /// The first cluster of the directory containing the item.
+        dir_cluster: u32,
+        /// The index of the item's entry in its directory.
+        entry: u32,
+        /// What the backing filesystem reported.
+        error: E,
+    },
+    /// The backing filesystem did not accept all the bytes written to the file
+    /// starting at `cluster`.
+    WriteFailed {
+        /// The first cluster of the file.
+        cluster: u32,
+        /// The offset into the file of the rejected write.
+        offset: u32,
+        /// What the backing filesystem reported.
+        error: E,
+    },
+    /// The backing filesystem refused to resize the file starting at `cluster`.
+    TruncateFailed {
+        /// The first cluster of the file.
+        cluster: u32,
+        /// The size the file was being resized to.
+        size: u32,
+        /// What the backing filesystem reported.
+        error: E,
+    },
+    /// A cluster chain on the device loops or points outside of the volume, or
+    /// a directory entry has a first cluster outside of it.
+    CorruptChain {
+        /// The cluster whose FAT entry is invalid, or the directory cluster
+        /// holding the invalid directory entry.
+        cluster: u32,
+        /// The invalid FAT entry, or the invalid first cluster.
+        entry: u32,
+    },
+    /// Directories on the device are nested deeper than `MAX_DEPTH`.
+    TooDeep {
+        /// The first cluster of the directory past the limit.
+        cluster: u32,
+    },
+    /// The device is append-only, but the host overwrote or cut short the
+    /// existing contents of the file starting at `cluster`.
+    NotAppended {
+        /// The first cluster of the file.
+        cluster: u32,
+        /// The offset into the file of the first byte that was changed or cut.
+        offset: u32,
+    },
+    /// The host's batched writes could not all be applied to the device before
+    /// reading it back, since its change set has no room left.
+    ChangeSetFull {
+        /// The device offset of the first write that was not applied.
+        offset: u64,
+    },
+}
+
+impl<E> WriteBackError<E> {
+    /// The same error without what the backing filesystem reported.
+    pub fn without_cause(self) -> WriteBackError {
+        match self {
+            WriteBackError::CreateFailed {
+                dir_cluster, entry, ..
+            } => WriteBackError::CreateFailed {
+                dir_cluster,
+                entry,
+                error: (),
+            },
+            WriteBackError::WriteFailed {
+                cluster, offset, ..
+            } => WriteBackError::WriteFailed {
+                cluster,
+                offset,
+                error: (),
+            },
+            WriteBackError::TruncateFailed { cluster, size, .. } => {
+                WriteBackError::TruncateFailed {
+                    cluster,
+                    size,
+                    error: (),
+                }
+            }
+            WriteBackError::CorruptChain { cluster, entry } => {
+                WriteBackError::CorruptChain { cluster, entry }
+            }
+            WriteBackError::TooDeep { cluster } => WriteBackError::TooDeep { cluster },
+            WriteBackError::NotAppended { cluster, offset } => {
+                WriteBackError::NotAppended { cluster, offset }
+            }
+            WriteBackError::ChangeSetFull { offset } => WriteBackError::ChangeSetFull { offset },
+        }
+    }
+}
+
+impl<E> fmt::Display for WriteBackError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WriteBackError::CreateFailed {
+                dir_cluster, entry, ..
+            } => write!(
+                f,
+                "could not create the item in entry {} of the directory at cluster {}",
+                entry, dir_cluster
+            ),
+            WriteBackError::WriteFailed {
+                cluster, offset, ..
+            } => write!(
+                f,
+                "could not write offset {} of the file at cluster {}",
+                offset, cluster
+            ),
+            WriteBackError::TruncateFailed { cluster, size, .. } => write!(
+                f,
+                "could not resize the file at cluster {} to {} bytes",
+                cluster, size
+            ),
+            WriteBackError::CorruptChain { cluster, entry } => write!(
+                f,
+                "cluster {} links to invalid cluster {:#010x}",
+                cluster, entry
+            ),
+            WriteBackError::TooDeep { cluster } => write!(
+                f,
+                "the directory at cluster {} is nested deeper than {} levels",
+                cluster, MAX_DEPTH
+            ),
+            WriteBackError::NotAppended { cluster, offset } => write!(
+                f,
+                "the file at cluster {} was changed at offset {} on an append-only device",
+                cluster, offset
+            ),
+            WriteBackError::ChangeSetFull { offset } => {
+                write!(f, "no room left to keep the write to offset {}", offset)
+            }
+        }
+    }
+}
+
+/// A single 32-byte directory slot, as written by the host.
+pub(crate) enum RawDirEntry {
+    /// The slot marks the end of the directory.
+    End,
+    /// The slot used to hold an entry that has since been deleted.
+    Deleted,
+    /// The slot holds part of a Long File Name.
+    LongName {
+        sequence: u8,
+        checksum: u8,
+        units: [u16; 13],
+    },
+    /// The slot describes a child item.
+    Child {
+        short_name: [u8; ShortName::SHORT_NAME_FULL_LENGTH],
+        case_flag: u8,
+        attrs: u8,
+        first_cluster: u32,
+        size: u32,
+    },
+}
+
+impl RawDirEntry {
+    const ATTR_VOLUME_ID: u8 = 0x08;
+    const ATTR_DIRECTORY: u8 = 0x10;
+    const ATTR_LFN: u8 = 0x0F;
+
+    /// Decodes the raw bytes of a directory slot on a volume of type `fat_type`.
+    ///
+    /// The high word of the first cluster is only used on FAT32, and only the
+    /// bits of it that a FAT entry of `fat_type` has are kept.
+    pub fn decode(raw: &[u8; DIRENT_SIZE], fat_type: FatType) -> RawDirEntry {
+        match raw[0] {
+            0x00 => return RawDirEntry::End,
+            0xE5 => return RawDirEntry::Deleted,
+            _ => {}
+        }
+        let le16 = |idx: usize| u16::from(raw[idx]) | u16::from(raw[idx + 1]) << 8;
+        let attrs = raw[11];
+        if attrs & 0x3F == RawDirEntry::ATTR_LFN {
+            let mut units = [0; 13];
+            for (unit, &offset) in units.iter_mut().zip(LfnDirEntry::NAME_OFFSETS.iter()) {
+                *unit = le16(offset);
+            }
+            RawDirEntry::LongName {
+                sequence: raw[0],
+                checksum: raw[13],
+                units,
+            }
+        } else {
+            let mut short_name = [0; ShortName::SHORT_NAME_FULL_LENGTH];
+            short_name.copy_from_slice(&raw[..ShortName::SHORT_NAME_FULL_LENGTH]);
+            if short_name[0] == 0x05 {
+                short_name[0] = 0xE5;
+            }
+            let high = match fat_type {
+                FatType::Fat32 => u32::from(le16(20)) << 16,
+                FatType::Fat12 | FatType::Fat16 => 0,
+            };
+            RawDirEntry::Child {
+                short_name,
+                case_flag: raw[12],
+                attrs,
+                first_cluster: (u32::from(le16(26)) | high) & fat_type.entry_mask(),
+                size: u32::from(le16(28)) | u32::from(le16(30)) << 16,
+            }
+        }
+    }
+
+    /// Whether this entry describes a subdirectory.
+    pub fn is_directory(&self) -> bool {
+        match self {
+            RawDirEntry::Child { attrs, .. } => attrs & RawDirEntry::ATTR_DIRECTORY != 0,
+            _ => false,
+        }
+    }
+
+    /// Whether this entry is the volume label rather than a real child.
+    pub fn is_volume_label(&self) -> bool {
+        match self {
+            RawDirEntry::Child { attrs, .. } => {
+                attrs & (RawDirEntry::ATTR_VOLUME_ID | RawDirEntry::ATTR_DIRECTORY)
+                    == RawDirEntry::ATTR_VOLUME_ID
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Reassembles the full name of a child from its Long File Name entries, falling
+/// back to the child's short name when there is no valid Long File Name chain.
+pub(crate) struct NameAssembler {
+    units: [u16; MAX_LFN_UNITS + 13],
+    next_sequence: u8,
+    checksum: u8,
+    valid: bool,
+    name: [u8; MAX_NAME_BYTES],
+    name_len: usize,
+}
+
+impl NameAssembler {
+    pub fn new() -> Self {
+        NameAssembler {
+            units: [0; MAX_LFN_UNITS + 13],
+            next_sequence: 0,
+            checksum: 0,
+            valid: false,
+            name: [0; MAX_NAME_BYTES],
+            name_len: 0,
+        }
+    }
+
+    /// Forgets any partially assembled Long File Name.
+    pub fn reset(&mut self) {
+        self.valid = false;
+        self.next_sequence = 0;
+    }
+
+    /// Adds a Long File Name entry to the name currently being assembled.
+    pub fn push_long(&mut self, sequence: u8, checksum: u8, units: &[u16; 13]) {
+        let number = sequence & 0x1F;
+        if sequence & 0x40 != 0 {
+            self.valid = number > 0 && usize::from(number) * 13 <= self.units.len();
+            self.checksum = checksum;
+            for unit in self.units.iter_mut() {
+                *unit = 0xFFFF;
+            }
+        } else if !self.valid || number != self.next_sequence || checksum != self.checksum {
+            self.valid = false;
+        }
+        if self.valid {
+            let start = (usize::from(number) - 1) * 13;
+            self.units[start..start + 13].copy_from_slice(units);
+            self.next_sequence = number - 1;
+        }
+    }
+
+    /// Finishes assembling the name of the child with the given short name,
+    /// returning the child's full name. Characters of the short name that are
+    /// not ASCII are read in `charset`.
+    pub fn finish(
+        &mut self,
+        short_name: &[u8; 11],
+        case_flag: u8,
+        charset: ShortNameCharset,
+    ) -> &str {
+        let checksum = ShortName {
+            data: *short_name,
+            ..ShortName::default()
+        }
+        .lfn_checksum();
+        self.name_len = 0;
+        if self.valid && self.next_sequence == 0 && checksum == self.checksum {
+            let name_units = self
+                .units
+                .iter()
+                .copied()
+                .take_while(|&u| u != 0x0000 && u != 0xFFFF);
+            for c in core::char::decode_utf16(name_units) {
+                let c = c.unwrap_or(core::char::REPLACEMENT_CHARACTER);
+                if self.name_len + c.len_utf8() > self.name.len() {
+                    break;
+                }
+                c.encode_utf8(&mut self.name[self.name_len..]);
+                self.name_len += c.len_utf8();
+            }
+        }
+        if self.name_len == 0 {
+            self.push_short_part(&short_name[..8], case_flag & 0x08 != 0, charset);
+            if short_name[8..].iter().any(|&c| c != b' ') {
+                self.name[self.name_len] = b'.';
+                self.name_len += 1;
+                self.push_short_part(&short_name[8..], case_flag & 0x10 != 0, charset);
+            }
+        }
+        self.reset();
+        core::str::from_utf8(&self.name[..self.name_len]).unwrap_or("_")
+    }
+
+    fn push_short_part(&mut self, part: &[u8], lower: bool, charset: ShortNameCharset) {
+        let trimmed_len = part.iter().rposition(|&c| c != b' ').map_or(0, |p| p + 1);
+        for &c in &part[..trimmed_len] {
+            let c = match charset.decode(c) {
+                c if c < ' ' || c == char::REPLACEMENT_CHARACTER => '_',
+                c if lower => c.to_ascii_lowercase(),
+                c => c,
+            };
+            if self.name_len + c.len_utf8() > self.name.len() {
+                break;
+            }
+            c.encode_utf8(&mut self.name[self.name_len..]);
+            self.name_len += c.len_utf8();
+        }
+    }
+}
+
+/// Follows a single link in a FAT chain, returning `None` at the end of the chain.
+///
+/// `entry` is the raw FAT entry of `cluster` in a table of type `fat_type`, and
+/// `max_cluster` is the highest valid cluster on the volume, used to reject links
+/// that point outside of it.
+pub(crate) fn next_in_chain<E>(
+    cluster: u32,
+    entry: u32,
+    max_cluster: u32,
+    fat_type: FatType,
+) -> Result<Option<u32>, WriteBackError<E>> {
+    match FatEntryValue::from_raw(entry, fat_type) {
+        FatEntryValue::End => Ok(None),
+        FatEntryValue::Next(n) if n >= FIRST_DATA_CLUSTER && n <= max_cluster => Ok(Some(n)),
+        _ => Err(WriteBackError::CorruptChain { cluster, entry }),
+    }
+}
+
+/// The number of bytes copied from the device to the backing filesystem at a time.
+const COPY_CHUNK_SIZE: usize = 512;
+
+impl<T: FileSystemOpsMut> FakeFat<T> {
+    /// Applies every file and directory the host has created or modified on the
+    /// device to the backing filesystem.
+    ///
+    /// Files the host deleted are left untouched in the backing filesystem, as
+    /// are items the authorizer does not allow writes to, along with everything
+    /// in such directories.
+    ///
+    /// On an append-only device, files whose existing contents the host
+    /// overwrote or cut short are left untouched too. Everything else is still
+    /// applied, after which the first such file is reported as `NotAppended`.
+    pub fn write_back(&mut self) -> Result<(), WriteBackError<T::Error>> {
+        self.flush_for_write_back()?;
+        let root_cluster = self.layout.bpb.root_dir_cluster();
+        let root_path = self.layout.prefix.clone();
+        let mut rejected = None;
+        let applied = self.write_back_directory(root_cluster, &root_path, 0, &mut rejected, None);
+        if applied.is_ok() {
+            self.changes.mark_clean();
+        }
+        self.debug_validate("write_back");
+        applied?;
+        rejected.map_or(Ok(()), Err)
+    }
+
+    /// Lists the operations `write_back` would apply to the backing filesystem,
+    /// without applying any of them.
+    ///
+    /// Fails the same way `write_back` does if the volume is corrupt, but not
+    /// for files an append-only device would reject, which are reported by
+    /// `CommitPlan::rejected` instead.
+    pub fn plan_write_back(&mut self) -> Result<CommitPlan, WriteBackError<T::Error>> {
+        self.flush_for_write_back()?;
+        let root_cluster = self.layout.bpb.root_dir_cluster();
+        let root_path = self.layout.prefix.clone();
+        let mut plan = CommitPlan::new(self.region_size() as u32);
+        let mut rejected = None;
+        self.write_back_directory(root_cluster, &root_path, 0, &mut rejected, Some(&mut plan))?;
+        plan.set_rejected(rejected.map(WriteBackError::without_cause));
+        Ok(plan)
+    }
+
+    /// Applies the writes the host left batched up, so that they are read back.
+    fn flush_for_write_back(&mut self) -> Result<(), WriteBackError<T::Error>> {
+        match self.flush() {
+            Err(FakeFatError::ChangeSetFull { offset }) => {
+                Err(WriteBackError::ChangeSetFull { offset })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies the directory starting at `first_cluster` to `path`, keeping
+    /// the first file an append-only device rejects in `rejected`.
+    ///
+    /// If there is a `plan`, operations are added to it instead of applied.
+    fn write_back_directory(
+        &mut self,
+        first_cluster: u32,
+        path: &PathBuff,
+        depth: usize,
+        rejected: &mut Option<WriteBackError<T::Error>>,
+        mut plan: Option<&mut CommitPlan>,
+    ) -> Result<(), WriteBackError<T::Error>> {
+        if depth > MAX_DEPTH {
+            return Err(WriteBackError::TooDeep {
+                cluster: first_cluster,
+            });
+        }
+        let max_cluster = self.max_cluster();
+        let entries_per_cluster = dirents_per_cluster(&self.layout.bpb);
+        let mut names = NameAssembler::new();
+        let dir_cluster = first_cluster;
+        let mut cluster = Some(first_cluster);
+        let mut visited = 0;
+        while let Some(cur) = cluster {
+            visited += 1;
+            if visited > max_cluster {
+                return Err(WriteBackError::CorruptChain {
+                    cluster: cur,
+                    entry: self.read_fat_entry(cur),
+                });
+            }
+            let base = self.layout.bpb.cluster_start(cur);
+            for entry_idx in 0..entries_per_cluster {
+                let entry_number =
+                    ((visited - 1) as usize * entries_per_cluster + entry_idx) as u32;
+                let create_failed = |error| WriteBackError::CreateFailed {
+                    dir_cluster,
+                    entry: entry_number,
+                    error,
+                };
+                let mut raw = [0; DIRENT_SIZE];
+                self.read_device_at(base + entry_idx * DIRENT_SIZE, &mut raw);
+                let entry = RawDirEntry::decode(&raw, self.layout.bpb.fat_type);
+                match entry {
+                    RawDirEntry::End => return Ok(()),
+                    RawDirEntry::Deleted => names.reset(),
+                    RawDirEntry::LongName {
+                        sequence,
+                        checksum,
+                        ref units,
+                    } => names.push_long(sequence, checksum, units),
+                    RawDirEntry::Child {
+                        short_name,
+                        case_flag,
+                        first_cluster,
+                        size,
+                        ..
+                    } => {
+                        if entry.is_volume_label() || short_name[0] == b'.' {
+                            names.reset();
+                            continue;
+                        }
+                        if first_cluster > max_cluster {
+                            return Err(WriteBackError::CorruptChain {
+                                cluster: cur,
+                                entry: first_cluster,
+                            });
+                        }
+                        let is_directory = entry.is_directory();
+                        let exposed =
+                            names.finish(&short_name, case_flag, self.layout.naming.charset);
+                        let backing = self.backing_name(path.to_str(), exposed);
+                        let name = backing.as_ref().map_or(exposed, |n| n.as_ref());
+                        let naming = self.layout.naming;
+                        let mapper = &self.layout.mapper;
+                        let omitted = self.fs.find_dir(path.to_str()).is_some_and(|dir| {
+                            dir.listing().any(|ent| {
+                                ent.name().as_ref() == name
+                                    && (naming.omits(&ent.meta())
+                                        || is_unplaced_child(mapper, naming, path.to_str(), &ent))
+                            })
+                        });
+                        if omitted
+                            || naming.access_in(Operation::Write, path.to_str(), name)
+                                != Access::Allow
+                        {
+                            names.reset();
+                            continue;
+                        }
+                        let mut child_path = path.clone();
+                        if is_directory {
+                            child_path.add_subdir(name);
+                            let exists = self
+                                .fs
+                                .find_metadata(child_path.to_str())
+                                .is_some_and(|meta| meta.is_directory);
+                            if !exists {
+                                match plan.as_deref_mut() {
+                                    Some(plan) => plan.push(CommitOp::CreateDirectory {
+                                        dir_cluster,
+                                        entry: entry_number,
+                                    }),
+                                    None => {
+                                        self.fs
+                                            .mkdir(child_path.to_str())
+                                            .map_err(create_failed)?;
+                                        self.dir_cache.forget_names(path.to_str());
+                                    }
+                                }
+                            }
+                            if first_cluster >= FIRST_DATA_CLUSTER {
+                                self.write_back_directory(
+                                    first_cluster,
+                                    &child_path,
+                                    depth + 1,
+                                    rejected,
+                                    plan.as_deref_mut(),
+                                )?;
+                            }
+                        } else {
+                            child_path.add_file(name);
+                            let existing_size = self
+                                .fs
+                                .find_metadata(child_path.to_str())
+                                .map(|meta| meta.size as usize);
+                            if existing_size.is_none() {
+                                match plan.as_deref_mut() {
+                                    Some(plan) => plan.push(CommitOp::CreateFile {
+                                        dir_cluster,
+                                        entry: entry_number,
+                                    }),
+                                    None => {
+                                        self.fs
+                                            .create_file(child_path.to_str())
+                                            .map_err(create_failed)?;
+                                        self.dir_cache.forget_names(path.to_str());
+                                    }
+                                }
+                            }
+                            if let Some(existing_size) = existing_size.filter(|_| self.append_only)
+                            {
+                                let cut = self.first_overwrite(
+                                    first_cluster,
+                                    size as usize,
+                                    existing_size,
+                                    &child_path,
+                                )?;
+                                if let Some(offset) = cut {
+                                    rejected.get_or_insert(WriteBackError::NotAppended {
+                                        cluster: first_cluster,
+                                        offset: offset as u32,
+                                    });
+                                    names.reset();
+                                    continue;
+                                }
+                            }
+                            self.write_back_file(
+                                first_cluster,
+                                size as usize,
+                                existing_size,
+                                &child_path,
+                                plan.as_deref_mut(),
+                            )?;
+                        }
+                    }
+                }
+            }
+            cluster = self.next_directory_cluster(cur, max_cluster)?;
+        }
+        Ok(())
+    }
+
+    /// Follows a single link in the chain of a directory, which for the fixed
+    /// root directory region of a FAT12 or FAT16 volume just moves on to the
+    /// next piece of the region.
+    fn next_directory_cluster(
+        &mut self,
+        cluster: u32,
+        max_cluster: u32,
+    ) -> Result<Option<u32>, WriteBackError<T::Error>> {
+        if cluster >= ROOT_REGION_CLUSTER {
+            let next = cluster + 1;
+            let in_region = self.layout.bpb.cluster_start(next) < self.layout.bpb.data_start();
+            return Ok(Some(next).filter(|_| in_region));
+        }
+        let fat_entry = self.read_fat_entry(cluster);
+        next_in_chain(cluster, fat_entry, max_cluster, self.layout.bpb.fat_type)
+    }
+
+    /// Copies the file starting at `first_cluster` into `path`, which is
+    /// `existing_size` bytes long in the backing filesystem, or was only just
+    /// created if `None`.
+    ///
+    /// Data is written in whole regions of `region_size` bytes, each of which
+    /// is written if the host changed any of its clusters. If there is a
+    /// `plan`, operations are added to it instead of applied.
+    fn write_back_file(
+        &mut self,
+        first_cluster: u32,
+        size: usize,
+        existing_size: Option<usize>,
+        path: &PathBuff,
+        mut plan: Option<&mut CommitPlan>,
+    ) -> Result<(), WriteBackError<T::Error>> {
+        let max_cluster = self.max_cluster();
+        let cluster_size = self.layout.bpb.bytes_per_cluster() as usize;
+        let region_size = self.region_size();
+        // Shrinking first keeps the data past the new end from being rewritten
+        // on its way out.
+        let shrinks = existing_size.is_some_and(|existing_size| size < existing_size);
+        if shrinks {
+            self.truncate_backing(first_cluster, size, path, plan.as_deref_mut())?;
+        }
+        let mut region_start = if first_cluster >= FIRST_DATA_CLUSTER && size > 0 {
+            Some(first_cluster)
+        } else {
+            None
+        };
+        let mut region_offset = 0;
+        while let Some(start) = region_start {
+            if region_offset >= size {
+                break;
+            }
+            let region_end = (region_offset + region_size).min(size);
+            let mut changed = existing_size.is_none();
+            let mut cluster = Some(start);
+            let mut file_offset = region_offset;
+            while let Some(cur) = cluster.filter(|_| file_offset < region_end) {
+                changed |= self.changes.is_dirty(cur);
+                file_offset += cluster_size;
+                let fat_entry = self.read_fat_entry(cur);
+                cluster = next_in_chain(cur, fat_entry, max_cluster, self.layout.bpb.fat_type)?;
+            }
+            if changed {
+                match plan.as_deref_mut() {
+                    Some(plan) => plan.push(CommitOp::Write {
+                        cluster: first_cluster,
+                        offset: region_offset as u32,
+                        len: (region_end - region_offset) as u32,
+                    }),
+                    None => {
+                        self.copy_region(first_cluster, start, region_offset, region_end, path)?
+                    }
+                }
+            }
+            region_offset += region_size;
+            region_start = cluster;
+        }
+        if !shrinks && existing_size != Some(size) {
+            self.truncate_backing(first_cluster, size, path, plan)?;
+        }
+        Ok(())
+    }
+
+    /// Copies the bytes from `offset` up to `end` of the file starting at
+    /// `first_cluster` into `path`, where the cluster holding `offset` is
+    /// `start`.
+    fn copy_region(
+        &mut self,
+        first_cluster: u32,
+        start: u32,
+        offset: usize,
+        end: usize,
+        path: &PathBuff,
+    ) -> Result<(), WriteBackError<T::Error>> {
+        let max_cluster = self.max_cluster();
+        let cluster_size = self.layout.bpb.bytes_per_cluster() as usize;
+        let mut buffer = [0; COPY_CHUNK_SIZE];
+        let mut cluster = Some(start);
+        let mut file_offset = offset;
+        while let Some(cur) = cluster.filter(|_| file_offset < end) {
+            let base = self.layout.bpb.cluster_start(cur);
+            let cluster_end = (file_offset + cluster_size).min(end);
+            let mut chunk_offset = file_offset;
+            while chunk_offset < cluster_end {
+                let len = (cluster_end - chunk_offset).min(COPY_CHUNK_SIZE);
+                self.read_device_at(base + chunk_offset - file_offset, &mut buffer[..len]);
+                self.fs
+                    .write_at(path.to_str(), chunk_offset, &buffer[..len])
+                    .map_err(|error| WriteBackError::WriteFailed {
+                        cluster: first_cluster,
+                        offset: chunk_offset as u32,
+                        error,
+                    })?;
+                chunk_offset += len;
+            }
+            file_offset += cluster_size;
+            let fat_entry = self.read_fat_entry(cur);
+            cluster = next_in_chain(cur, fat_entry, max_cluster, self.layout.bpb.fat_type)?;
+        }
+        Ok(())
+    }
+
+    /// Resizes `path`, the file starting at `first_cluster`, to `size` bytes,
+    /// or adds doing so to `plan` if there is one.
+    fn truncate_backing(
+        &mut self,
+        first_cluster: u32,
+        size: usize,
+        path: &PathBuff,
+        plan: Option<&mut CommitPlan>,
+    ) -> Result<(), WriteBackError<T::Error>> {
+        match plan {
+            Some(plan) => plan.push(CommitOp::Truncate {
+                cluster: first_cluster,
+                size: size as u32,
+            }),
+            None => self.fs.truncate(path.to_str(), size).map_err(|error| {
+                WriteBackError::TruncateFailed {
+                    cluster: first_cluster,
+                    size: size as u32,
+                    error,
+                }
+            })?,
+        }
+        Ok(())
+    }
+
+    /// The size of the regions file data is written back in, which is the
+    /// erase unit size or the cluster size, whichever is larger.
+    fn region_size(&self) -> usize {
+        (self.erase_unit_size as usize).max(self.layout.bpb.bytes_per_cluster() as usize)
+    }
+
+    /// The offset of the first of the `existing_size` bytes already in the
+    /// backing file at `path` that the host overwrote or cut off, given that
+    /// the file now starts at `first_cluster` and is `size` bytes long, or
+    /// `None` if the host only appended to the file.
+    ///
+    /// Clusters that are still where the file had them and that the host never
+    /// wrote to are not compared. Backing contents that cannot be read count as
+    /// overwritten.
+    fn first_overwrite(
+        &mut self,
+        first_cluster: u32,
+        size: usize,
+        existing_size: usize,
+        path: &PathBuff,
+    ) -> Result<Option<usize>, WriteBackError<T::Error>> {
+        if size < existing_size {
+            return Ok(Some(size));
+        }
+        let max_cluster = self.max_cluster();
+        let cluster_size = self.layout.bpb.bytes_per_cluster() as usize;
+        let mut original = self
+            .layout
+            .mapper
+            .get_chain_for_path(path.to_str())
+            .into_iter();
+        let mut file = match self.fs.find_file(path.to_str()) {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let mut cluster = Some(first_cluster).filter(|&c| c >= FIRST_DATA_CLUSTER);
+        let mut file_offset = 0;
+        while file_offset < existing_size {
+            let cur = match cluster {
+                Some(cur) => cur,
+                None => return Ok(Some(file_offset)),
+            };
+            let moved = original.next() != Some(cur);
+            if moved || self.changes.is_dirty(cur) {
+                let base = self.layout.bpb.cluster_start(cur);
+                let cluster_end = (file_offset + cluster_size).min(existing_size);
+                let mut device = [0; COPY_CHUNK_SIZE];
+                let mut backing = [0; COPY_CHUNK_SIZE];
+                let mut chunk_offset = file_offset;
+                while chunk_offset < cluster_end {
+                    let len = (cluster_end - chunk_offset).min(COPY_CHUNK_SIZE);
+                    self.read_device_at(base + chunk_offset - file_offset, &mut device[..len]);
+                    if read_padded(&mut file, chunk_offset, &mut backing[..len]).is_err() {
+                        return Ok(Some(chunk_offset));
+                    }
+                    let changed = device[..len]
+                        .iter()
+                        .zip(backing[..len].iter())
+                        .position(|(new, old)| new != old);
+                    if let Some(idx) = changed {
+                        return Ok(Some(chunk_offset + idx));
+                    }
+                    chunk_offset += len;
+                }
+            }
+            file_offset += cluster_size;
+            let fat_entry = self.read_fat_entry(cur);
+            cluster = next_in_chain(cur, fat_entry, max_cluster, self.layout.bpb.fat_type)?;
+        }
+        Ok(None)
+    }
+
+    /// Reads the FAT entry of `cluster` exactly as the host would see it.
+    fn read_fat_entry(&mut self, cluster: u32) -> u32 {
+        let fat_type = self.layout.bpb.fat_type;
+        let bit_offset = cluster as usize * fat_type.entry_bits();
+        let len = (bit_offset % 8 + fat_type.entry_bits()).div_ceil(8);
+        let mut raw = [0; 8];
+        self.read_device_at(
+            self.layout.bpb.fat_start() + bit_offset / 8,
+            &mut raw[..len],
+        );
+        (u64::from_le_bytes(raw) >> (bit_offset % 8)) as u32 & fat_type.entry_mask()
+    }
+
+    /// The highest cluster number the volume has room for.
+    fn max_cluster(&self) -> u32 {
+        let data_bytes = (self.layout.bpb.total_sectors_32 as usize
+            * self.layout.bpb.bytes_per_sector as usize)
+            .saturating_sub(self.layout.bpb.data_start());
+        (data_bytes / self.layout.bpb.bytes_per_cluster() as usize) as u32 + FIRST_DATA_CLUSTER - 1
+    }
+}
@@ -0,0 +1,78 @@
+//! Some transports hand writes to the device a single byte at a time, which
+//! means that every write would otherwise go through address resolution and,
+//! for the first write to a cluster, a full snapshot of that cluster from the
+//! backing filesystem. The `WriteBuffer` instead collects runs of contiguous
+//! writes and only hands them off to the changeset once the run is broken,
+//! fills up, crosses into the next cluster-sized window, or is flushed.
+
+/// The maximum number of bytes a single write batch can hold.
+const WRITE_BUFFER_SIZE: usize = 1024 * 4;
+
+pub struct WriteBuffer {
+    start: usize,
+    len: usize,
+    window: usize,
+    data: [u8; WRITE_BUFFER_SIZE],
+}
+
+impl WriteBuffer {
+    /// Constructs an empty buffer.
+    pub fn new() -> Self {
+        WriteBuffer {
+            start: 0,
+            len: 0,
+            window: 0,
+            data: [0; WRITE_BUFFER_SIZE],
+        }
+    }
+
+    /// Whether there are any writes waiting to be applied.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the pending value of the byte at device offset `idx`, if the
+    /// current batch covers it.
+    pub fn get(&self, idx: usize) -> Option<u8> {
+        if idx >= self.start && idx < self.start + self.len {
+            Some(self.data[idx - self.start])
+        } else {
+            None
+        }
+    }
+
+    /// Whether a write to `idx` in the given `window` can be added to the current
+    /// batch without flushing it first.
+    pub fn accepts(&self, idx: usize, window: usize) -> bool {
+        self.is_empty()
+            || (idx == self.start + self.len
+                && window == self.window
+                && self.len < WRITE_BUFFER_SIZE)
+    }
+
+    /// Adds a write to the batch.
+    ///
+    /// The caller is expected to have checked `accepts` first.
+    pub fn push(&mut self, idx: usize, window: usize, byte: u8) {
+        debug_assert!(self.accepts(idx, window));
+        if self.is_empty() {
+            self.start = idx;
+            self.window = window;
+        }
+        self.data[self.len] = byte;
+        self.len += 1;
+    }
+
+    /// The device offset the batch starts at and the bytes written from there.
+    pub fn pending(&self) -> (usize, &[u8]) {
+        (self.start, &self.data[..self.len])
+    }
+
+    /// Drops the first `count` bytes of the batch, once they were applied.
+    pub fn consume(&mut self, count: usize) {
+        let count = count.min(self.len);
+        self.data.copy_within(count..self.len, 0);
+        self.start += count;
+        self.len -= count;
+    }
+}
@@ -0,0 +1,79 @@
+//! Reading the device straight into buffers aligned for DMA transfers.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{
+    Aligned, FakeFat, FakeFatBuilder, SectorError, SharedFakeFat, StdFileSystem, A32, A4,
+};
+
+use std::fs;
+
+fn build(root: &TempDir) -> FakeFat<StdFileSystem> {
+    fs::write(
+        root.0.join("data.bin"),
+        (0..20_000).map(|n| n as u8).collect::<Vec<u8>>(),
+    )
+    .unwrap();
+    FakeFatBuilder::new()
+        .total_capacity(4 * 1024 * 1024)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap())
+}
+
+#[test]
+fn aligned_reads_match_plain_reads() {
+    let root = TempDir::new("aligned-reads");
+    let mut fake = build(&root);
+    let mut image = vec![0; 256 * 1024];
+    assert_eq!(fake.read_at(0, &mut image), image.len());
+
+    let mut buffer = Box::new(Aligned::<A32, 4096>::new());
+    assert_eq!(buffer.as_ptr() as usize % 32, 0);
+    assert_eq!(buffer.align(), 32);
+    for (idx, chunk) in image.chunks(4096).enumerate() {
+        assert_eq!(fake.read_aligned(idx * 4096, &mut buffer), 4096);
+        assert!(&buffer[..] == chunk);
+        assert_eq!(
+            fake.read_sectors_aligned(idx as u32 * 8, &mut buffer),
+            Ok(())
+        );
+        assert!(&buffer[..] == chunk);
+    }
+
+    let shared = SharedFakeFat::new(fake);
+    let (_, data) = shared.split();
+    assert_eq!(data.read_sectors_aligned(8, &mut buffer), Ok(()));
+    assert!(buffer[..] == image[4096..8192]);
+}
+
+#[test]
+fn sector_reads_check_the_buffer_and_range() {
+    let root = TempDir::new("aligned-sectors");
+    let mut fake = build(&root);
+    let sector_count = fake.sector_count();
+
+    let mut partial = Aligned::<A4, 700>::new();
+    assert_eq!(
+        fake.read_sectors_aligned(0, &mut partial),
+        Err(SectorError::BadBufferSize {
+            expected: 512,
+            actual: 700
+        })
+    );
+    let mut empty = Aligned::<A4, 0>::new();
+    assert!(fake.read_sectors_aligned(0, &mut empty).is_err());
+
+    let mut two = Aligned::<A4, 1024>::new();
+    assert_eq!(
+        fake.read_sectors_aligned(sector_count - 2, &mut two),
+        Ok(())
+    );
+    assert_eq!(
+        fake.read_sectors_aligned(sector_count - 1, &mut two),
+        Err(SectorError::OutOfRange {
+            lba: sector_count,
+            sector_count
+        })
+    );
+}
@@ -0,0 +1,186 @@
+//! Serving a device from a backend whose futures do not complete right away.
+#![cfg(all(feature = "async", feature = "std"))]
+
+use fakefat::{
+    AsyncDirectoryOps, AsyncFakeFat, AsyncFileOps, AsyncFileSystemOps, DataProvider, DirectoryOps,
+    FakeFatBuilder, FatType, FileMetadata, FileOps, FileSystemOps, ManifestDir, ManifestEntry,
+    ManifestFile, ManifestFileSystem,
+};
+
+use std::future::Future;
+use std::pin::pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+/// Runs `fut` to completion on the current thread.
+fn block_on<F: Future>(fut: F) -> F::Output {
+    struct Unpark(Thread);
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut fut = pin!(fut);
+    loop {
+        if let Poll::Ready(output) = fut.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+/// Counts the times the backend made its caller wait.
+static WAITS: AtomicUsize = AtomicUsize::new(0);
+
+/// A future that is pending the first time it is polled.
+struct YieldOnce(bool);
+
+impl Future for YieldOnce {
+    type Output = ();
+    fn poll(mut self: std::pin::Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        WAITS.fetch_add(1, Ordering::Relaxed);
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// File contents that depend on the path and the offset, failing for files
+/// named `broken`.
+struct PatternProvider;
+
+impl DataProvider for PatternProvider {
+    type Error = &'static str;
+    fn read_at(&self, path: &str, offset: usize, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        if path.ends_with("broken") {
+            return Err("broken");
+        }
+        let size = manifest()
+            .iter()
+            .find(|entry| entry.path == path)
+            .map_or(0, |entry| entry.meta.size as usize);
+        let len = size.saturating_sub(offset).min(buffer.len());
+        for (idx, byte) in buffer[..len].iter_mut().enumerate() {
+            *byte = (path.len() + offset + idx) as u8 ^ ((offset + idx) >> 9) as u8;
+        }
+        Ok(len)
+    }
+}
+
+/// A manifest whose every access waits first.
+struct Remote<'a>(ManifestFileSystem<'a, PatternProvider>);
+struct RemoteFile<'a>(ManifestFile<'a, PatternProvider>);
+struct RemoteDir<'a>(ManifestDir<'a, PatternProvider>);
+
+impl<'a> AsyncFileSystemOps for Remote<'a> {
+    type DirectoryType = RemoteDir<'a>;
+    type FileType = RemoteFile<'a>;
+    type Error = &'static str;
+
+    async fn get_file(&mut self, path: &str) -> Result<Option<RemoteFile<'a>>, &'static str> {
+        YieldOnce(false).await;
+        Ok(FileSystemOps::get_file(&mut self.0, path)?.map(RemoteFile))
+    }
+
+    async fn get_dir(&mut self, path: &str) -> Result<Option<RemoteDir<'a>>, &'static str> {
+        YieldOnce(false).await;
+        Ok(FileSystemOps::get_dir(&mut self.0, path)?.map(RemoteDir))
+    }
+
+    async fn get_metadata(&mut self, path: &str) -> Result<Option<FileMetadata>, &'static str> {
+        YieldOnce(false).await;
+        FileSystemOps::get_metadata(&mut self.0, path)
+    }
+}
+
+impl AsyncFileOps for RemoteFile<'_> {
+    type Error = &'static str;
+
+    async fn read_at(&mut self, offset: usize, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        YieldOnce(false).await;
+        // Short reads, so that reads of a run of clusters take several calls.
+        let len = buffer.len().min(700);
+        FileOps::read_at(&mut self.0, offset, &mut buffer[..len])
+    }
+}
+
+impl<'a> AsyncDirectoryOps for RemoteDir<'a> {
+    type EntryType = <ManifestDir<'a, PatternProvider> as DirectoryOps>::EntryType;
+    type IterType = <ManifestDir<'a, PatternProvider> as DirectoryOps>::IterType;
+    type Error = &'static str;
+
+    async fn entries(&self) -> Result<Self::IterType, &'static str> {
+        YieldOnce(false).await;
+        DirectoryOps::entries(&self.0)
+    }
+}
+
+fn manifest() -> [ManifestEntry<'static>; 6] {
+    [
+        ManifestEntry::file("tiny.txt", 1),
+        ManifestEntry::file("A long file name.data", 5000),
+        ManifestEntry::file("docs/readme.md", 513),
+        ManifestEntry::file("docs/nested/deeper/file.bin", 70_000),
+        ManifestEntry::file("docs/broken", 1000),
+        ManifestEntry::directory("empty dir"),
+    ]
+}
+
+#[test]
+fn reads_match_a_synchronous_device() {
+    let entries = manifest();
+    let builder = FakeFatBuilder::new()
+        .fat_type(FatType::Fat16)
+        .sectors_per_cluster(2);
+    let mut expected = builder.build(ManifestFileSystem::new(&entries, &PatternProvider), "/");
+    let remote = Remote(ManifestFileSystem::new(&entries, &PatternProvider));
+    let mut fake = block_on(AsyncFakeFat::build(builder, remote, "/"));
+    assert!(WAITS.load(Ordering::Relaxed) > 0);
+    assert_eq!(fake.fat().image_size(), expected.image_size());
+
+    // Enough of the device to cover every file, in reads that straddle
+    // clusters and files.
+    let mut chunk = vec![0; 3000];
+    let mut expected_chunk = vec![0; 3000];
+    let mut idx = 0;
+    while idx < 2 * 1024 * 1024 {
+        let read = block_on(fake.read_at(idx, &mut chunk));
+        assert_eq!(read, expected.read_at(idx, &mut expected_chunk));
+        assert!(chunk[..read] == expected_chunk[..read], "read at {}", idx);
+        idx += read;
+    }
+    let mut sector = vec![0; fake.fat().sector_size()];
+    let mut expected_sector = sector.clone();
+    for lba in 0..64 {
+        block_on(fake.read_sector(lba, &mut sector)).unwrap();
+        expected.read_sector(lba, &mut expected_sector).unwrap();
+        assert!(sector == expected_sector, "sector {}", lba);
+    }
+    assert_eq!(fake.take_backend_error(), Some("broken"));
+    assert_eq!(fake.take_backend_error(), None);
+}
+
+#[test]
+fn synchronous_backends_are_async_too() {
+    let entries = manifest();
+    let builder = FakeFatBuilder::new().fat_type(FatType::Fat12);
+    let mut expected = builder.build(ManifestFileSystem::new(&entries, &PatternProvider), "/");
+    let backend = ManifestFileSystem::new(&entries, &PatternProvider);
+    let mut fake = block_on(AsyncFakeFat::build(builder, backend, "/"));
+    let mut image = vec![0; fake.fat().image_size()];
+    let mut expected_image = image.clone();
+    // Multi-threaded executors only run futures that are `Send`.
+    fn assert_send<T: Send>(_: &T) {}
+    assert_send(&fake.read_at(0, &mut image));
+    assert_eq!(block_on(fake.read_at(0, &mut image)), image.len());
+    expected.read_at(0, &mut expected_image);
+    assert!(image == expected_image);
+    assert_eq!(block_on(fake.refresh()), Ok(0));
+}
@@ -0,0 +1,64 @@
+//! Deciding per item what hosts get to see and change.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{Access, FakeFatBuilder, Operation, StdFileSystem};
+
+use std::fs;
+use std::io::Read;
+
+fn authorize(_operation: Operation, path: &str) -> Access {
+    if path.ends_with("/secret.txt") || path.ends_with("/private") {
+        Access::Deny
+    } else if path.ends_with("/hidden.txt") {
+        Access::Hide
+    } else {
+        Access::Allow
+    }
+}
+
+#[test]
+fn denied_items_are_listed_but_unreadable() {
+    let root = TempDir::new("authorizer");
+    fs::write(root.0.join("public.txt"), b"public").unwrap();
+    fs::write(root.0.join("secret.txt"), b"secret").unwrap();
+    fs::write(root.0.join("hidden.txt"), b"hidden").unwrap();
+    fs::create_dir(root.0.join("private")).unwrap();
+    fs::write(root.0.join("private").join("inner.txt"), b"inner").unwrap();
+
+    let fake = FakeFatBuilder::new()
+        .authorizer(authorize)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    let fat = fatfs::FileSystem::new(fake, fatfs::FsOptions::new()).unwrap();
+    let dir = fat.root_dir();
+    let mut names: Vec<String> = dir.iter().map(|entry| entry.unwrap().file_name()).collect();
+    names.sort();
+    assert_eq!(names, ["private", "public.txt", "secret.txt"]);
+
+    let mut contents = Vec::new();
+    dir.open_file("public.txt")
+        .unwrap()
+        .read_to_end(&mut contents)
+        .unwrap();
+    assert_eq!(contents, b"public");
+
+    let secret = dir
+        .iter()
+        .map(|entry| entry.unwrap())
+        .find(|entry| entry.file_name() == "secret.txt")
+        .unwrap();
+    assert_eq!(secret.len(), 0);
+    contents.clear();
+    secret.to_file().read_to_end(&mut contents).unwrap();
+    assert!(contents.is_empty());
+
+    let private = dir.open_dir("private").unwrap();
+    let children = private
+        .iter()
+        .map(|entry| entry.unwrap().file_name())
+        .filter(|name| name != "." && name != "..")
+        .count();
+    assert_eq!(children, 0);
+}
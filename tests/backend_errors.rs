@@ -0,0 +1,75 @@
+//! Errors reported by the backing filesystem while the device is being read.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFatBuilder, FileMetadata, FileOps, FileSystemOps, StdDirectory, StdFileSystem};
+
+use std::fs::{self, File};
+use std::io;
+
+/// A file whose reads fail if its name says it is broken.
+struct FlakyFile {
+    file: File,
+    broken: bool,
+}
+
+impl FileOps for FlakyFile {
+    type Error = io::Error;
+
+    fn read_at(&mut self, offset: usize, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.broken {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.file.read_at(offset, buffer)
+    }
+}
+
+/// `StdFileSystem`, except that files named `broken*` cannot be read.
+struct FlakyFileSystem(StdFileSystem);
+
+impl FileSystemOps for FlakyFileSystem {
+    type DirectoryType = StdDirectory;
+    type FileType = FlakyFile;
+    type Error = io::Error;
+
+    fn get_file(&mut self, path: &str) -> io::Result<Option<FlakyFile>> {
+        let broken = path.rsplit('/').next().unwrap().starts_with("broken");
+        let file = self.0.get_file(path)?;
+        Ok(file.map(|file| FlakyFile { file, broken }))
+    }
+    fn get_dir(&mut self, path: &str) -> io::Result<Option<StdDirectory>> {
+        self.0.get_dir(path)
+    }
+    fn get_metadata(&mut self, path: &str) -> io::Result<Option<FileMetadata>> {
+        self.0.get_metadata(path)
+    }
+}
+
+#[test]
+fn failed_reads_are_zeroed_and_kept() {
+    let root = TempDir::new("backend-errors");
+    fs::write(root.0.join("working.txt"), b"these bytes can be read").unwrap();
+    fs::write(root.0.join("broken.txt"), b"these bytes cannot be read").unwrap();
+    let mut fs = FlakyFileSystem(StdFileSystem::new());
+    assert!(fs.get_metadata("/no/such/item").unwrap().is_none());
+
+    let mut fake = FakeFatBuilder::new().build(fs, root.0.to_str().unwrap());
+    // Both files are placed within the first few dozen data clusters.
+    let bpb = fake.layout().bpb();
+    let data_start = bpb.data_start();
+    let mut image = vec![0; 64 * bpb.bytes_per_cluster() as usize];
+    let mut read = 0;
+    while read < image.len() {
+        read += fake.read_at(data_start + read, &mut image[read..]);
+    }
+    let contains = |needle: &[u8]| image.windows(needle.len()).any(|window| window == needle);
+    assert!(contains(b"these bytes can be read"));
+    assert!(!contains(b"these bytes cannot be read"));
+
+    let error = fake.take_backend_error().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    assert!(fake.session_stats().backend_errors >= 1);
+    assert!(fake.take_backend_error().is_none());
+}
@@ -0,0 +1,47 @@
+//! The jump instruction and OEM name at the head of the boot sector.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFat, FakeFatBuilder, FatType, StdFileSystem};
+
+fn head(fake: &mut FakeFat<StdFileSystem>) -> [u8; 11] {
+    let mut head = [0; 11];
+    assert_eq!(fake.read_at(0, &mut head), head.len());
+    head
+}
+
+#[test]
+fn jump_skips_the_bios_parameter_block() {
+    let root = TempDir::new("boot-sector-jump");
+    for &(fat_type, jump) in &[
+        (FatType::Fat12, [0xEB, 0x3C, 0x90]),
+        (FatType::Fat16, [0xEB, 0x3C, 0x90]),
+        (FatType::Fat32, [0xEB, 0x58, 0x90]),
+    ] {
+        let mut fake = FakeFatBuilder::new()
+            .fat_type(fat_type)
+            .build(StdFileSystem::new(), root.0.to_str().unwrap());
+        assert_eq!(&head(&mut fake)[..3], &jump, "{:?}", fat_type);
+    }
+}
+
+#[test]
+fn oem_names_are_padded_with_spaces() {
+    let root = TempDir::new("boot-sector-oem");
+    let mut fake = FakeFatBuilder::new().build(StdFileSystem::new(), root.0.to_str().unwrap());
+    assert_eq!(&head(&mut fake)[3..], b"MSWIN4.1");
+
+    for &(name, stored) in &[
+        ("mkfs.fat", b"mkfs.fat"),
+        ("FAKEFAT", b"FAKEFAT "),
+        ("", b"        "),
+    ] {
+        let mut fake = FakeFatBuilder::new()
+            .oem_name(name)
+            .build(StdFileSystem::new(), root.0.to_str().unwrap());
+        assert_eq!(&head(&mut fake)[3..], stored);
+        assert_eq!(&fake.layout().bpb().oem_name, stored);
+    }
+}
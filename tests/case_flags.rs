@@ -0,0 +1,73 @@
+//! Lowercase short names stored with case flags instead of Long File Name
+//! entries.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::geometry::{cluster_to_offset, root_dir_cluster, DIRENT_SIZE};
+use fakefat::{FakeFat, FakeFatBuilder, StdFileSystem};
+
+use std::fs;
+
+const ATTR_LONG_NAME: u8 = 0x0F;
+const LOWERCASE_NAME: u8 = 0x08;
+const LOWERCASE_EXT: u8 = 0x10;
+
+/// The root directory entry of `README.TXT`, along with the attributes of the
+/// entry in the slot before it, if there is one.
+fn readme_entry(fake: &mut FakeFat<StdFileSystem>) -> (Vec<u8>, Option<u8>) {
+    let bpb = fake.layout().bpb().clone();
+    let mut entries = vec![0; bpb.bytes_per_cluster() as usize];
+    let len = entries.len();
+    let start = cluster_to_offset(&bpb, root_dir_cluster(&bpb));
+    assert_eq!(fake.read_at(start, &mut entries), len);
+    let slot = entries
+        .chunks(DIRENT_SIZE)
+        .position(|entry| &entry[..11] == b"README  TXT")
+        .unwrap();
+    let previous = slot
+        .checked_sub(1)
+        .map(|previous| entries[previous * DIRENT_SIZE + 11]);
+    (
+        entries[slot * DIRENT_SIZE..][..DIRENT_SIZE].to_vec(),
+        previous,
+    )
+}
+
+fn listed_names(fake: &mut FakeFat<StdFileSystem>) -> Vec<String> {
+    let fs = fatfs::FileSystem::new(fake, fatfs::FsOptions::new()).unwrap();
+    let names = fs
+        .root_dir()
+        .iter()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    names
+}
+
+#[test]
+fn lowercase_names_use_case_flags() {
+    let root = TempDir::new("case-flags");
+    fs::write(root.0.join("readme.txt"), b"read me").unwrap();
+    let mut fake = FakeFatBuilder::new().build(StdFileSystem::new(), root.0.to_str().unwrap());
+
+    // A single short entry, with no Long File Name entry in front of it.
+    let (entry, previous) = readme_entry(&mut fake);
+    assert_eq!(entry[12], LOWERCASE_NAME | LOWERCASE_EXT);
+    assert_ne!(previous, Some(ATTR_LONG_NAME));
+    assert!(listed_names(&mut fake).contains(&"readme.txt".to_string()));
+}
+
+#[test]
+fn disabled_case_flags_bring_back_long_names() {
+    let root = TempDir::new("case-flags-off");
+    fs::write(root.0.join("readme.txt"), b"read me").unwrap();
+    let mut fake = FakeFatBuilder::new()
+        .short_name_case_flags(false)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+
+    let (entry, previous) = readme_entry(&mut fake);
+    assert_eq!(entry[12], 0);
+    assert_eq!(previous, Some(ATTR_LONG_NAME));
+    assert!(listed_names(&mut fake).contains(&"readme.txt".to_string()));
+}
@@ -0,0 +1,122 @@
+//! The cylinder, head and sector geometry of the boot sector and of the
+//! partition table in front of it.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{ChsGeometry, FakeFat, FakeFatBuilder, FatType, MbrWrapped, StdFileSystem};
+
+/// The largest number of sectors LBA-assist translation reaches with `heads`
+/// heads of 63 sectors, over 1024 cylinders.
+fn reach(heads: u32) -> u32 {
+    1024 * heads * 63
+}
+
+/// The 3 byte cylinder, head and sector address of `lba`, as the partition
+/// table stores it, or the largest address if it is out of reach.
+fn chs_address(heads: u32, sectors_per_track: u32, lba: u32) -> [u8; 3] {
+    let cylinder = lba / (heads * sectors_per_track);
+    if cylinder > 1023 {
+        return [0xFE, 0xFF, 0xFF];
+    }
+    let head = lba / sectors_per_track % heads;
+    let sector = lba % sectors_per_track + 1;
+    [
+        head as u8,
+        sector as u8 | (cylinder >> 2) as u8 & 0xC0,
+        cylinder as u8,
+    ]
+}
+
+/// The heads and sectors per track the boot sector of `fake` holds.
+fn boot_sector_geometry(fake: &mut FakeFat<StdFileSystem>) -> (u16, u16) {
+    let mut boot_sector = [0; 512];
+    fake.read_at(0, &mut boot_sector);
+    (
+        u16::from_le_bytes([boot_sector[26], boot_sector[27]]),
+        u16::from_le_bytes([boot_sector[24], boot_sector[25]]),
+    )
+}
+
+#[test]
+fn lba_assist_picks_the_fewest_heads() {
+    for &(sectors, heads) in &[
+        (0, 16),
+        (reach(16), 16),
+        (reach(16) + 1, 32),
+        (reach(32) + 1, 64),
+        (reach(64) + 1, 128),
+        (reach(128), 128),
+        (reach(128) + 1, 255),
+        (u32::MAX, 255),
+    ] {
+        let geometry = ChsGeometry::for_sectors(sectors);
+        assert_eq!(geometry.heads, heads, "{} sectors", sectors);
+        assert_eq!(geometry.sectors_per_track, 63);
+        assert!(geometry.is_valid());
+    }
+}
+
+#[test]
+fn geometry_fits_the_size_of_the_device() {
+    let root = TempDir::new("chs-for-size");
+    let mut fake = FakeFatBuilder::new()
+        .fat_type(FatType::Fat32)
+        .total_capacity(1024 * 1024 * 1024)
+        .chs_geometry_for_size()
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    let total_sectors = fake.layout().bpb().total_sectors_32;
+    assert!(total_sectors > reach(32) && total_sectors <= reach(64));
+    assert_eq!(boot_sector_geometry(&mut fake), (64, 63));
+
+    // The default geometry does not depend on the size.
+    let mut fake = FakeFatBuilder::new()
+        .fat_type(FatType::Fat32)
+        .total_capacity(1024 * 1024 * 1024)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    let default = ChsGeometry::default();
+    assert_eq!(
+        boot_sector_geometry(&mut fake),
+        (default.heads, default.sectors_per_track)
+    );
+}
+
+#[test]
+fn partition_table_uses_the_boot_sector_geometry() {
+    let root = TempDir::new("chs-mbr");
+    for &size_fitted in &[false, true] {
+        let mut builder = FakeFatBuilder::new()
+            .fat_type(FatType::Fat32)
+            .total_capacity(1024 * 1024 * 1024);
+        if size_fitted {
+            builder = builder.chs_geometry_for_size();
+        }
+        let mut fake = builder.build(StdFileSystem::new(), root.0.to_str().unwrap());
+        let (heads, sectors_per_track) = boot_sector_geometry(&mut fake);
+        let volume_sectors = fake.layout().bpb().total_sectors_32;
+
+        let mut wrapped = MbrWrapped::new(fake);
+        let mut mbr = [0; 512];
+        wrapped.read_at(0, &mut mbr);
+        let entry = &mbr[446..462];
+        let (heads, sectors_per_track) = (u32::from(heads), u32::from(sectors_per_track));
+        assert_eq!(&entry[1..4], &chs_address(heads, sectors_per_track, 1));
+        assert_eq!(
+            &entry[5..8],
+            &chs_address(heads, sectors_per_track, volume_sectors)
+        );
+
+        // The partition's boot sector still holds the same geometry.
+        let mut boot_sector = [0; 512];
+        wrapped.read_at(512, &mut boot_sector);
+        assert_eq!(
+            u32::from(u16::from_le_bytes([boot_sector[26], boot_sector[27]])),
+            heads
+        );
+        assert_eq!(
+            u32::from(u16::from_le_bytes([boot_sector[24], boot_sector[25]])),
+            sectors_per_track
+        );
+    }
+}
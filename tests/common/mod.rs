@@ -0,0 +1,132 @@
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use fakefat::{FakeFat, FakeFatBuilder, StdFileSystem};
+
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+/// A directory under the system temp dir that is removed on drop.
+pub struct TempDir(pub PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("fakefat-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A small device holding a single file, as the transport tests serve it.
+pub fn build(root: &TempDir) -> FakeFat<StdFileSystem> {
+    build_with(root, FakeFatBuilder::new())
+}
+
+/// The device `build` returns, with the rest of its options taken from
+/// `builder`.
+pub fn build_with(root: &TempDir, builder: FakeFatBuilder) -> FakeFat<StdFileSystem> {
+    fs::write(root.0.join("hello.txt"), b"hello").unwrap();
+    builder
+        .total_capacity(2 * 1024 * 1024)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap())
+}
+
+/// The device as a host sees it through one of the transports, which drops
+/// writes to the FAT preamble, such as `fatfs` marking the volume dirty in the
+/// boot sector, but still refuses writes to a write protected device.
+pub struct HostImage<'a>(pub &'a mut FakeFat<StdFileSystem>);
+
+impl Read for HostImage<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for HostImage<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.0.write(buf) {
+            Err(err)
+                if err.kind() == io::ErrorKind::PermissionDenied && !self.0.write_protected() =>
+            {
+                self.0.seek(SeekFrom::Current(buf.len() as i64))?;
+                Ok(buf.len())
+            }
+            other => other,
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Write::flush(self.0)
+    }
+}
+
+impl Seek for HostImage<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+/// A client connection held in memory, which hands the server the bytes the
+/// client sends up front and keeps everything the server sends back.
+pub struct Connection {
+    input: Cursor<Vec<u8>>,
+    pub output: Vec<u8>,
+}
+
+impl Connection {
+    pub fn new(input: Vec<u8>) -> Self {
+        Connection {
+            input: Cursor::new(input),
+            output: Vec::new(),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads what a server sent off the front of its output, with numbers in
+/// network byte order.
+pub struct Replies<'a>(pub &'a [u8]);
+
+impl<'a> Replies<'a> {
+    pub fn take(&mut self, len: usize) -> &'a [u8] {
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        head
+    }
+
+    pub fn u16(&mut self) -> u16 {
+        u16::from_be_bytes(self.take(2).try_into().unwrap())
+    }
+
+    pub fn u32(&mut self) -> u32 {
+        u32::from_be_bytes(self.take(4).try_into().unwrap())
+    }
+
+    pub fn u64(&mut self) -> u64 {
+        u64::from_be_bytes(self.take(8).try_into().unwrap())
+    }
+}
@@ -0,0 +1,48 @@
+//! Several readers streaming the same device, each from its own position.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFatBuilder, FakeFatCursor, StdFileSystem};
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+
+#[test]
+fn cursors_keep_their_own_positions() {
+    let root = TempDir::new("cursor");
+    fs::write(root.0.join("hello.txt"), b"hello").unwrap();
+    let mut fake = FakeFatBuilder::new()
+        .total_capacity(2 * 1024 * 1024)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    let len = fake.len();
+    let mut image = vec![0; len as usize];
+    assert_eq!(fake.read_at(0, &mut image), image.len());
+    let fake = RefCell::new(fake);
+
+    let mut host = FakeFatCursor::new(&fake);
+    let mut dump = FakeFatCursor::new(&fake);
+    let mut sector = [0; 512];
+    host.seek(SeekFrom::Start(4096)).unwrap();
+    host.read_exact(&mut sector).unwrap();
+    assert!(sector[..] == image[4096..4608]);
+    assert_eq!(dump.position(), 0);
+
+    // Reads through the other cursor, and through the device itself, leave
+    // the host's position alone.
+    let mut dumped = Vec::new();
+    assert_eq!(io::copy(&mut dump, &mut dumped).unwrap(), len);
+    assert!(dumped == image);
+    fake.borrow_mut().read_exact(&mut sector).unwrap();
+    host.read_exact(&mut sector).unwrap();
+    assert!(sector[..] == image[4608..5120]);
+    assert_eq!(host.position(), 5120);
+
+    assert_eq!(host.seek(SeekFrom::End(-2)).unwrap(), len - 2);
+    assert_eq!(host.read(&mut sector).unwrap(), 2);
+    assert_eq!(host.read(&mut sector).unwrap(), 0);
+    assert!(host.seek(SeekFrom::Current(-(len as i64) - 1)).is_err());
+    assert_eq!(dump.seek(SeekFrom::Current(-1)).unwrap(), len - 1);
+}
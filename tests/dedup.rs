@@ -0,0 +1,46 @@
+//! Letting backing files with identical contents share a cluster chain.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFat, FakeFatBuilder, FatType, StdFileSystem};
+
+use std::fs;
+use std::io::Read;
+
+fn build(root: &TempDir, dedup: bool) -> FakeFat<StdFileSystem> {
+    FakeFatBuilder::new()
+        .fat_type(FatType::Fat16)
+        .sectors_per_cluster(1)
+        .total_capacity(4 * 1024 * 1024)
+        .write_protected(true)
+        .dedup_files(dedup)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap())
+}
+
+#[test]
+fn identical_files_share_their_clusters() {
+    let root = TempDir::new("dedup");
+    fs::create_dir(root.0.join("copies")).unwrap();
+    fs::write(root.0.join("a.bin"), vec![7; 5000]).unwrap();
+    fs::write(root.0.join("copies").join("b.bin"), vec![7; 5000]).unwrap();
+    // The same size, but different contents.
+    fs::write(root.0.join("c.bin"), vec![8; 5000]).unwrap();
+
+    // Each file takes 10 clusters of 512 bytes, and the copy takes none.
+    let plain = build(&root, false).free_clusters();
+    let fake = build(&root, true);
+    assert_eq!(fake.free_clusters(), plain + 10);
+
+    let fat = fatfs::FileSystem::new(fake, fatfs::FsOptions::new()).unwrap();
+    for (path, byte) in [("a.bin", 7), ("copies/b.bin", 7), ("c.bin", 8)].iter() {
+        let mut contents = Vec::new();
+        fat.root_dir()
+            .open_file(path)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, vec![*byte; 5000]);
+    }
+}
@@ -0,0 +1,65 @@
+//! Reading and writing every layer of a device through the `Device` trait.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{Device, FakeFat, FakeFatBuilder, MbrWrapped, Sandbox, StdFileSystem};
+
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+fn build(root: &TempDir) -> FakeFat<StdFileSystem> {
+    FakeFatBuilder::new()
+        .total_capacity(4 * 1024 * 1024)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap())
+}
+
+/// Reads the whole of `device` a sector at a time.
+fn image<D: Device>(mut device: D) -> Vec<u8> {
+    let mut image = vec![0; device.len() as usize];
+    assert_eq!(
+        image.len(),
+        device.sector_count() as usize * device.sector_size()
+    );
+    let sector_size = device.sector_size();
+    for (lba, sector) in image.chunks_mut(sector_size).enumerate() {
+        assert_eq!(device.read_at(lba * sector_size, sector), sector_size);
+    }
+    image
+}
+
+/// Writes `data` to the first sector of the data region of `device`, which
+/// starts `data_start` bytes in, and reads it back.
+fn round_trip<D: Device>(mut device: D, data_start: usize, data: &[u8]) -> Vec<u8> {
+    assert_eq!(device.write_at(data_start, data), Ok(data.len()));
+    assert_eq!(device.flush(), Ok(()));
+    let mut read_back = vec![0; data.len()];
+    device.read_at(data_start, &mut read_back);
+    read_back
+}
+
+#[test]
+fn every_layer_is_a_device() {
+    let root = TempDir::new("device");
+    fs::write(root.0.join("hello.txt"), b"hello").unwrap();
+    let mut fake = build(&root);
+    let data_start = fake.layout().bpb().data_start();
+    let volume = image(&mut fake);
+
+    let mut wrapped = MbrWrapped::new(build(&root));
+    let partition_start = wrapped.len() as usize - volume.len();
+    // The boot sector of the partition counts the sectors before it.
+    let mbr_data_start = partition_start + data_start;
+    assert!(image(&mut wrapped)[mbr_data_start..] == volume[data_start..]);
+
+    let base = Arc::new(Mutex::new(build(&root)));
+    let mut sandbox = Sandbox::new(Arc::clone(&base));
+    assert_eq!(image(&mut sandbox), volume);
+
+    let data = [0xA5; 16];
+    assert_eq!(round_trip(&mut fake, data_start, &data), data);
+    assert_eq!(round_trip(&mut wrapped, mbr_data_start, &data), data);
+    assert_eq!(round_trip(&mut sandbox, data_start, &data), data);
+    assert!(Device::write_at(&mut fake, 0, &data).is_err());
+}
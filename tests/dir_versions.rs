@@ -0,0 +1,36 @@
+//! Bumping the versions of directories whose contents change across
+//! refreshes.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFatBuilder, StdFileSystem};
+
+use std::fs;
+
+#[test]
+fn refresh_bumps_changed_directories() {
+    let root = TempDir::new("dir-versions");
+    fs::create_dir(root.0.join("changed")).unwrap();
+    fs::create_dir(root.0.join("same")).unwrap();
+    fs::write(root.0.join("same").join("old.txt"), b"old").unwrap();
+    let mut fake = FakeFatBuilder::new().build(StdFileSystem::new(), root.0.to_str().unwrap());
+    assert_eq!(fake.dir_version("/"), Some(0));
+    assert_eq!(fake.dir_version("changed"), Some(0));
+    assert_eq!(fake.dir_version("same"), Some(0));
+    assert_eq!(fake.dir_version("missing"), None);
+
+    // Nothing changed yet.
+    assert_eq!(fake.refresh(), Ok(0));
+    assert_eq!(fake.dir_version("changed"), Some(0));
+
+    fs::write(root.0.join("changed").join("new.txt"), b"new").unwrap();
+    let bumped = fake.refresh().unwrap();
+    assert!(bumped >= 1);
+    assert_eq!(fake.dir_version("/changed/"), Some(1));
+    assert_eq!(fake.dir_version("same"), Some(0));
+
+    assert_eq!(fake.refresh(), Ok(0));
+    assert_eq!(fake.dir_version("changed"), Some(1));
+}
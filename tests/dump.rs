@@ -0,0 +1,57 @@
+//! Dumping the whole image of a device.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFatBuilder, StdFileSystem};
+
+use std::fs;
+use std::io::{Cursor, Read};
+
+#[test]
+fn dumped_images_mount() {
+    let root = TempDir::new("dump");
+    fs::create_dir(root.0.join("dir")).unwrap();
+    let data: Vec<u8> = (0..20_000).map(|n| n as u8).collect();
+    fs::write(root.0.join("dir/data.bin"), &data).unwrap();
+    let mut fake = FakeFatBuilder::new()
+        .total_capacity(4 * 1024 * 1024)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+
+    let mut image = Vec::new();
+    fake.dump(&mut image).unwrap();
+    assert_eq!(image.len(), fake.image_size());
+    let mut expected = vec![0; fake.image_size()];
+    fake.read_at(0, &mut expected);
+    assert!(image == expected);
+
+    let fs = fatfs::FileSystem::new(Cursor::new(image), fatfs::FsOptions::new()).unwrap();
+    let mut contents = Vec::new();
+    fs.root_dir()
+        .open_file("dir/data.bin")
+        .unwrap()
+        .read_to_end(&mut contents)
+        .unwrap();
+    assert_eq!(contents, data);
+}
+
+#[test]
+fn dumps_stop_at_the_first_error() {
+    let root = TempDir::new("dump-error");
+    let mut fake = FakeFatBuilder::new()
+        .total_capacity(4 * 1024 * 1024)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    let mut pieces = Vec::new();
+    let result = fake.dump_with(|data| {
+        pieces.push(data.len());
+        if pieces.len() == 3 {
+            Err("full")
+        } else {
+            Ok(())
+        }
+    });
+    assert_eq!(result, Err("full"));
+    // Whole sectors, as many as fit in 4096 bytes.
+    assert_eq!(pieces, vec![4096; 3]);
+}
@@ -0,0 +1,114 @@
+//! The `fakefat-dump` command line tool.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+
+use std::fs;
+use std::io::Read;
+use std::process::{Command, Output};
+
+/// Runs `fakefat-dump` with `args` on a source directory holding `hello.txt`,
+/// returning what it printed and the image it wrote.
+fn run(name: &str, args: &[&str]) -> (Output, Vec<u8>) {
+    let source = TempDir::new(&format!("{}-source", name));
+    fs::write(source.0.join("hello.txt"), b"hello").unwrap();
+    let out = TempDir::new(&format!("{}-out", name));
+    let image = out.0.join("image.img");
+    let output = Command::new(env!("CARGO_BIN_EXE_fakefat-dump"))
+        .args(args)
+        .arg(&source.0)
+        .arg(&image)
+        .output()
+        .unwrap();
+    let image = fs::read(&image).unwrap_or_default();
+    (output, image)
+}
+
+/// The bytes per sector and sectors per cluster in the BPB of `image`.
+fn geometry(image: &[u8]) -> (u16, u8) {
+    (u16::from_le_bytes([image[11], image[12]]), image[13])
+}
+
+#[test]
+fn images_mount() {
+    let (output, image) = run(
+        "dump-cli-mount",
+        &["--label", "SDCARD", "--volume-id", "1234-ABCD", "-s", "8M"],
+    );
+    assert!(output.status.success());
+    let fs = fatfs::FileSystem::new(std::io::Cursor::new(image), fatfs::FsOptions::new()).unwrap();
+    assert_eq!(fs.volume_label(), "SDCARD");
+    assert_eq!(fs.volume_id(), 0x1234_ABCD);
+    let mut contents = String::new();
+    fs.root_dir()
+        .open_file("hello.txt")
+        .unwrap()
+        .read_to_string(&mut contents)
+        .unwrap();
+    assert_eq!(contents, "hello");
+}
+
+#[test]
+fn cluster_sizes_are_converted_to_sectors() {
+    let (output, image) = run("dump-cli-cluster", &["-s", "8M", "-c", "16K"]);
+    assert!(output.status.success());
+    assert_eq!(geometry(&image), (512, 32));
+
+    let (output, image) = run(
+        "dump-cli-cluster-4k",
+        &["-s", "8M", "-b", "4096", "-c", "16K"],
+    );
+    assert!(output.status.success());
+    assert_eq!(geometry(&image), (4096, 4));
+
+    let (output, image) = run("dump-cli-cluster-small", &["-b", "4096", "-c", "2K"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("smaller than the sector size"));
+    assert!(image.is_empty());
+}
+
+#[test]
+fn options_override_the_preset_wherever_they_are() {
+    // The camera preset picks 32 KiB clusters for any sector size.
+    let (output, image) = run("dump-cli-preset", &["-p", "camera-minimal", "-s", "64M"]);
+    assert!(output.status.success());
+    assert_eq!(geometry(&image), (512, 64));
+    let (output, image) = run(
+        "dump-cli-preset-4k",
+        &["-b", "4096", "--preset", "camera-minimal", "-s", "64M"],
+    );
+    assert!(output.status.success());
+    assert_eq!(geometry(&image), (4096, 8));
+
+    // Options given before the preset still win over it.
+    let (output, image) = run(
+        "dump-cli-preset-override",
+        &["-c", "4K", "-s", "64M", "-p", "camera-minimal"],
+    );
+    assert!(output.status.success());
+    assert_eq!(geometry(&image), (512, 8));
+    // Rather than the 32 GB of the preset.
+    assert!(image.len() <= 64 * 1024 * 1024);
+}
+
+#[test]
+fn bad_arguments_are_reported() {
+    for (args, message) in [
+        (&["-p", "tape"][..], "Unknown preset \"tape\""),
+        (&["--label", "MUCH TOO LONG"][..], "Invalid volume label"),
+        (&["-b", "1000"][..], "Invalid sector size \"1000\""),
+        (&["-f", "64"][..], "Invalid FAT type \"64\""),
+        (&["-n", "0"][..], "Invalid FAT count \"0\""),
+        (&["--frobnicate"][..], "Unknown option --frobnicate"),
+    ]
+    .iter()
+    {
+        let (output, _) = run("dump-cli-bad", args);
+        assert_eq!(output.status.code(), Some(2), "{:?}", args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(message), "{:?}: {}", args, stderr);
+        assert!(stderr.contains("Usage: fakefat-dump"));
+    }
+}
@@ -0,0 +1,62 @@
+//! Ejecting the medium, with and without applying the host's writes first.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{HostImage, TempDir};
+use fakefat::{FakeFat, FakeFatBuilder, SessionEvent, StdFileSystem};
+
+use std::fs;
+use std::io::{Seek, SeekFrom, Write};
+use std::sync::Mutex;
+
+static EVENTS: Mutex<Vec<SessionEvent>> = Mutex::new(Vec::new());
+
+fn record(event: SessionEvent) {
+    EVENTS.lock().unwrap().push(event);
+}
+
+/// Creates `name` on the device the way a host would.
+fn host_creates(fake: &mut FakeFat<StdFileSystem>, name: &str) {
+    fake.seek(SeekFrom::Start(0)).unwrap();
+    let fs = fatfs::FileSystem::new(HostImage(fake), fatfs::FsOptions::new()).unwrap();
+    let mut file = fs.root_dir().create_file(name).unwrap();
+    file.write_all(b"written by the host").unwrap();
+    drop(file);
+    fs.unmount().unwrap();
+}
+
+#[test]
+fn ejecting_reports_whether_writes_are_pending() {
+    let root = TempDir::new("eject");
+    let mut fake = FakeFatBuilder::new()
+        .session_listener(record)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    assert!(!fake.is_ejected());
+
+    host_creates(&mut fake, "first.txt");
+    fake.eject().unwrap();
+    assert!(fake.is_ejected());
+    // Ejecting again is not reported again.
+    fake.eject().unwrap();
+    assert_eq!(
+        *EVENTS.lock().unwrap(),
+        [SessionEvent::Ejected { dirty: true }]
+    );
+    assert!(!root.0.join("first.txt").exists());
+
+    fake.load();
+    assert!(!fake.is_ejected());
+    host_creates(&mut fake, "second.txt");
+    fake.eject_and_write_back().unwrap();
+    assert!(fake.is_ejected());
+    assert_eq!(
+        EVENTS.lock().unwrap()[1..],
+        [SessionEvent::Ejected { dirty: false }]
+    );
+    assert_eq!(
+        fs::read(root.0.join("second.txt")).unwrap(),
+        b"written by the host"
+    );
+    assert!(root.0.join("first.txt").exists());
+}
@@ -0,0 +1,30 @@
+//! Writing the device through `embedded-io`.
+#![cfg(all(feature = "std", feature = "embedded-io"))]
+
+mod common;
+
+use common::TempDir;
+use embedded_io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use fakefat::{FakeFatBuilder, IoError, StdFileSystem};
+
+#[test]
+fn writes_past_the_end_fail() {
+    let root = TempDir::new("embedded-io-write");
+    let mut fake = FakeFatBuilder::new()
+        .total_capacity(2 * 1024 * 1024)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    let len = fake.len();
+
+    // The write that reaches the end of the device is cut short there, and
+    // the one after it fails instead of writing nothing.
+    assert_eq!(fake.seek(SeekFrom::End(-4)).unwrap(), len - 4);
+    let err = fake.write_all(&[0xAB; 16]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::WriteZero);
+    assert_eq!(err, IoError::EndOfDevice { offset: len });
+    assert_eq!(fake.write(&[]).unwrap(), 0);
+
+    fake.seek(SeekFrom::End(-4)).unwrap();
+    let mut buffer = [0; 4];
+    fake.read_exact(&mut buffer).unwrap();
+    assert_eq!(buffer, [0xAB; 4]);
+}
@@ -0,0 +1,48 @@
+//! Telling which clusters the host changed in which epochs, for applications
+//! that replicate the device.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFat, FakeFatBuilder, FatType, StdFileSystem};
+
+fn write_cluster(fake: &mut FakeFat<StdFileSystem>, cluster: u32, byte: u8) {
+    let start = fake.layout().bpb().cluster_start(cluster);
+    fake.write_at(start, &[byte; 16]).unwrap();
+}
+
+fn changed(fake: &mut FakeFat<StdFileSystem>, from: u32, to: u32) -> Vec<u32> {
+    let mut clusters: Vec<u32> = fake.changes_between(from, to).unwrap().collect();
+    clusters.sort_unstable();
+    clusters
+}
+
+#[test]
+fn changes_are_told_apart_by_epoch() {
+    let root = TempDir::new("epochs");
+    let mut fake = FakeFatBuilder::new()
+        .fat_type(FatType::Fat32)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    assert_eq!(fake.epoch(), 0);
+
+    write_cluster(&mut fake, 10, 0xA0);
+    // Writes are batched until the epoch ends, but still land in it.
+    fake.write_byte(fake.layout().bpb().cluster_start(11), 0xA1)
+        .unwrap();
+    assert_eq!(fake.next_epoch().unwrap(), 1);
+    write_cluster(&mut fake, 12, 0xB0);
+    assert_eq!(fake.next_epoch().unwrap(), 2);
+
+    assert_eq!(changed(&mut fake, 0, 1), vec![10, 11]);
+    assert_eq!(changed(&mut fake, 1, 2), vec![12]);
+    assert_eq!(changed(&mut fake, 0, 2), vec![10, 11, 12]);
+    assert_eq!(changed(&mut fake, 2, 3), Vec::<u32>::new());
+
+    // Only the first and last epoch of each cluster are kept, so changing
+    // cluster 10 again makes it look changed in epoch 1 as well.
+    write_cluster(&mut fake, 10, 0xC0);
+    assert_eq!(changed(&mut fake, 2, 3), vec![10]);
+    assert_eq!(changed(&mut fake, 1, 2), vec![10, 12]);
+    assert_eq!(changed(&mut fake, 0, 1), vec![10, 11]);
+}
@@ -0,0 +1,37 @@
+//! Runs the fuzzing entry points over a spread of generated inputs, so that
+//! they are known to accept arbitrary input before a fuzzer ever runs them.
+#![cfg(feature = "fuzz")]
+
+use fakefat::fuzz::{fuzz_read, fuzz_write};
+
+/// An input for every layout, with operations from a fixed pseudo-random
+/// sequence.
+fn inputs() -> impl Iterator<Item = Vec<u8>> {
+    let mut state = 0x2545_F491_4F6C_DD1Du64;
+    (0..=255u8).map(move |config| {
+        let mut input = vec![config, config.wrapping_mul(31)];
+        for _ in 0..200 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            input.push(state as u8);
+        }
+        input
+    })
+}
+
+#[test]
+fn reads_uphold_the_invariants() {
+    for input in inputs() {
+        fuzz_read(&input);
+    }
+    fuzz_read(&[]);
+}
+
+#[test]
+fn writes_uphold_the_invariants() {
+    for input in inputs() {
+        fuzz_write(&input);
+    }
+    fuzz_write(&[3]);
+}
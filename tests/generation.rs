@@ -0,0 +1,92 @@
+//! Moving files whose contents changed in place, so that hosts stop serving
+//! what they cached of them.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFat, FakeFatBuilder, FatType, StdFileSystem};
+
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+/// Rewrites the file at `path` with `contents` without changing its
+/// modification time.
+fn rewrite_in_place(path: &PathBuf, contents: &[u8]) {
+    let modified = fs::metadata(path).unwrap().modified().unwrap();
+    fs::write(path, contents).unwrap();
+    File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+}
+
+/// The first cluster and the raw modification timestamp in the directory
+/// entry with the short name `short_name`.
+fn entry_of(fake: &mut FakeFat<StdFileSystem>, short_name: &[u8; 11]) -> (u16, [u8; 4]) {
+    let mut image = vec![0; fake.layout().bpb().data_start()];
+    fake.read_at(0, &mut image);
+    let at = image
+        .windows(11)
+        .position(|window| window == short_name)
+        .unwrap();
+    let entry = &image[at..at + 32];
+    (
+        u16::from_le_bytes([entry[26], entry[27]]),
+        [entry[22], entry[23], entry[24], entry[25]],
+    )
+}
+
+/// Reads the file at `path` off the device the way a host would.
+fn host_reads(fake: &mut FakeFat<StdFileSystem>, path: &str) -> Vec<u8> {
+    fake.seek(SeekFrom::Start(0)).unwrap();
+    let fs = fatfs::FileSystem::new(fake, fatfs::FsOptions::new()).unwrap();
+    let mut contents = Vec::new();
+    let mut file = fs.root_dir().open_file(path).unwrap();
+    file.read_to_end(&mut contents).unwrap();
+    contents
+}
+
+#[test]
+fn changed_files_move_to_new_clusters() {
+    let root = TempDir::new("generation");
+    let cached = root.0.join("cached.bin");
+    fs::write(&cached, vec![b'a'; 2000]).unwrap();
+    fs::write(root.0.join("same.txt"), b"unchanged").unwrap();
+    let builder = FakeFatBuilder::new()
+        .fat_type(FatType::Fat16)
+        .sectors_per_cluster(1);
+    let mut fake = builder
+        .relocate_changed_files(true)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    let free = fake.free_clusters();
+    let (cached_cluster, cached_time) = entry_of(&mut fake, b"CACHED  BIN");
+    let same = entry_of(&mut fake, b"SAME    TXT");
+    assert_eq!(fake.file_generation("/cached.bin"), Some(0));
+
+    rewrite_in_place(&cached, &[b'b'; 2000]);
+    fake.refresh().unwrap();
+    assert_eq!(fake.file_generation("/cached.bin"), Some(1));
+    assert_eq!(fake.file_generation("same.txt"), Some(0));
+    let (moved_cluster, moved_time) = entry_of(&mut fake, b"CACHED  BIN");
+    assert!(moved_cluster > cached_cluster);
+    assert_ne!(moved_time, cached_time);
+    assert_eq!(entry_of(&mut fake, b"SAME    TXT"), same);
+    assert_eq!(fake.free_clusters(), free);
+    assert_eq!(fake.validate(), Ok(()));
+    assert_eq!(host_reads(&mut fake, "cached.bin"), vec![b'b'; 2000]);
+
+    // Refreshing again without changes leaves everything where it is.
+    fake.refresh().unwrap();
+    assert_eq!(fake.file_generation("/cached.bin"), Some(1));
+    assert_eq!(
+        entry_of(&mut fake, b"CACHED  BIN"),
+        (moved_cluster, moved_time)
+    );
+
+    let untracked = builder.build(StdFileSystem::new(), root.0.to_str().unwrap());
+    assert_eq!(untracked.file_generation("/cached.bin"), None);
+}
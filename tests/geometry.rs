@@ -0,0 +1,75 @@
+//! Finding a file's data and its File Allocation Table entries on the device
+//! with nothing but the helpers of the `geometry` module.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::geometry::{
+    cluster_to_offset, dirents_per_cluster, fat_entry_offset, offset_to_cluster,
+    offset_to_fat_entry, root_dir_cluster, DIRENT_SIZE,
+};
+use fakefat::{FakeFat, FakeFatBuilder, FatType, StdFileSystem};
+
+use std::fs;
+
+fn read_device(fake: &mut FakeFat<StdFileSystem>, idx: usize, len: usize) -> Vec<u8> {
+    let mut buffer = vec![0; len];
+    assert_eq!(fake.read_at(idx, &mut buffer), len);
+    buffer
+}
+
+/// The raw value of the File Allocation Table entry of `cluster` in table `fat`.
+fn fat_entry(fake: &mut FakeFat<StdFileSystem>, fat: u8, cluster: u32) -> u32 {
+    let bpb = fake.layout().bpb().clone();
+    let bits = bpb.fat_type.entry_bits();
+    let raw = read_device(fake, fat_entry_offset(&bpb, fat, cluster), 4);
+    let shift = cluster as usize * bits % 8;
+    (u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) >> shift) & ((1u64 << bits) - 1) as u32
+}
+
+#[test]
+fn helpers_locate_files_on_the_device() {
+    let root = TempDir::new("geometry");
+    let contents: Vec<u8> = (0..10_000).map(|idx| (idx % 253) as u8).collect();
+    fs::write(root.0.join("DATA.BIN"), &contents).unwrap();
+    for &fat_type in &[FatType::Fat12, FatType::Fat16, FatType::Fat32] {
+        let mut fake = FakeFatBuilder::new()
+            .fat_type(fat_type)
+            .build(StdFileSystem::new(), root.0.to_str().unwrap());
+        let bpb = fake.layout().bpb().clone();
+        let cluster_size = bpb.bytes_per_cluster() as usize;
+        assert_eq!(dirents_per_cluster(&bpb) * DIRENT_SIZE, cluster_size);
+
+        let root_start = cluster_to_offset(&bpb, root_dir_cluster(&bpb));
+        assert_eq!(offset_to_cluster(&bpb, root_start), root_dir_cluster(&bpb));
+        let entries = read_device(&mut fake, root_start, cluster_size);
+        let entry = entries
+            .chunks(DIRENT_SIZE)
+            .find(|entry| &entry[..11] == b"DATA    BIN")
+            .unwrap();
+        let first_cluster = u32::from(u16::from_le_bytes([entry[26], entry[27]]))
+            | u32::from(u16::from_le_bytes([entry[20], entry[21]])) << 16;
+
+        let mut cluster = first_cluster;
+        let mut data = Vec::new();
+        while data.len() < contents.len() {
+            let start = cluster_to_offset(&bpb, cluster);
+            assert_eq!(offset_to_cluster(&bpb, start), cluster);
+            assert_eq!(offset_to_cluster(&bpb, start + cluster_size - 1), cluster);
+            data.extend(read_device(&mut fake, start, cluster_size));
+            for fat in 0..bpb.fats {
+                assert_eq!(
+                    offset_to_fat_entry(&bpb, fat_entry_offset(&bpb, fat, cluster)),
+                    cluster
+                );
+            }
+            let next = fat_entry(&mut fake, 0, cluster);
+            assert_eq!(fat_entry(&mut fake, 1, cluster), next);
+            cluster = next;
+        }
+        assert!(cluster >= (0x0FFF_FFF8 & ((1u64 << fat_type.entry_bits()) - 1)) as u32);
+        data.truncate(contents.len());
+        assert_eq!(data, contents);
+    }
+}
@@ -0,0 +1,76 @@
+//! Guessing what kind of host is driving the device from its accesses.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{HostImage, TempDir};
+use fakefat::{FakeFat, FakeFatBuilder, FatType, HostGuess, StdFileSystem};
+
+use std::fs;
+
+const SECTOR: usize = 512;
+
+fn build(root: &TempDir) -> FakeFat<StdFileSystem> {
+    FakeFatBuilder::new()
+        .fat_type(FatType::Fat32)
+        .bytes_per_sector(SECTOR as u16)
+        .total_capacity(64 * 1024 * 1024)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap())
+}
+
+fn read_sector(fake: &mut FakeFat<StdFileSystem>, sector: usize) {
+    let mut buffer = [0; SECTOR];
+    fake.read_at(sector * SECTOR, &mut buffer);
+}
+
+#[test]
+fn detection_is_off_by_default() {
+    let root = TempDir::new("hostdetect-off");
+    let mut fake = build(&root);
+    read_sector(&mut fake, 6);
+    assert!(fake.host_detector().is_none());
+    assert_eq!(fake.host_guess(), HostGuess::Unknown);
+}
+
+#[test]
+fn skipping_fsinfo_looks_like_a_camera() {
+    let root = TempDir::new("hostdetect-camera");
+    let mut fake = build(&root);
+    fake.enable_host_detection();
+    assert_eq!(fake.host_guess(), HostGuess::Unknown);
+
+    // The sectors after the backup boot sector, which are neither FSInfo nor
+    // the backup boot sector itself.
+    for sector in 7..23 {
+        read_sector(&mut fake, sector);
+    }
+    let observations = *fake.host_detector().unwrap().observations();
+    assert_eq!(observations.sector_reads, 16);
+    assert_eq!(observations.fsinfo_reads, 0);
+    assert_eq!(fake.host_guess(), HostGuess::CameraFirmware);
+
+    // Reading FSInfo without ever checking the backup boot sector is what the
+    // Linux driver does instead.
+    read_sector(&mut fake, 1);
+    assert_eq!(fake.host_guess(), HostGuess::Linux);
+}
+
+#[test]
+fn system_volume_information_looks_like_windows() {
+    let root = TempDir::new("hostdetect-windows");
+    fs::write(root.0.join("hello.txt"), b"hello").unwrap();
+    let mut fake = build(&root);
+    fake.enable_host_detection();
+
+    read_sector(&mut fake, 6);
+    let fs = fatfs::FileSystem::new(HostImage(&mut fake), fatfs::FsOptions::new()).unwrap();
+    fs.root_dir()
+        .create_dir("System Volume Information")
+        .unwrap();
+    fs.unmount().unwrap();
+
+    let observations = *fake.host_detector().unwrap().observations();
+    assert!(observations.backup_boot_reads > 0);
+    assert!(observations.windows_marker_entries > 0);
+    assert_eq!(fake.host_guess(), HostGuess::Windows);
+}
@@ -0,0 +1,42 @@
+//! Identifiers that stay the same every time a device is built from the same
+//! seed.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFat, FakeFatBuilder, MbrWrapped, StdFileSystem, VolumeIdentity};
+
+use std::fs;
+
+fn build(root: &TempDir, builder: FakeFatBuilder) -> FakeFat<StdFileSystem> {
+    builder.build(StdFileSystem::new(), root.0.to_str().unwrap())
+}
+
+#[test]
+fn identities_follow_the_seed() {
+    let root = TempDir::new("identity");
+    fs::write(root.0.join("hello.txt"), b"hello").unwrap();
+
+    assert!(build(&root, FakeFatBuilder::new()).identity().is_none());
+
+    let seeded = FakeFatBuilder::new().identity_seed(42);
+    let identity = build(&root, seeded).identity().unwrap();
+    assert_eq!(identity, VolumeIdentity::from_seed(42));
+    assert_eq!(build(&root, seeded).identity(), Some(identity));
+    let other = VolumeIdentity::from_seed(43);
+    assert_ne!(identity.volume_id, other.volume_id);
+    assert_ne!(identity.partition_guid, other.partition_guid);
+    assert_eq!(identity.partition_guid[7] >> 4, 4);
+    assert_eq!(identity.partition_guid[8] >> 6, 0b10);
+
+    let wrapped = MbrWrapped::new(build(&root, seeded));
+    assert_eq!(wrapped.disk_signature(), identity.volume_id);
+    assert_eq!(wrapped.inner().volume_id(), identity.volume_id);
+
+    let fixed = build(&root, seeded.volume_id(0x1234_5678))
+        .identity()
+        .unwrap();
+    assert_eq!(fixed.volume_id, 0x1234_5678);
+    assert_eq!(fixed.partition_guid, identity.partition_guid);
+}
@@ -0,0 +1,160 @@
+//! Serving the device as an iSCSI target.
+#![cfg(feature = "iscsi")]
+
+mod common;
+
+use common::{build, Connection, Replies, TempDir};
+use fakefat::{IscsiTarget, ISCSI_DEFAULT_TARGET_NAME};
+
+use std::convert::TryInto;
+
+const OP_SCSI_COMMAND: u8 = 0x01;
+const OP_LOGIN: u8 = 0x03;
+const OP_LOGOUT: u8 = 0x06;
+const OP_SCSI_RESPONSE: u8 = 0x21;
+const OP_LOGIN_RESPONSE: u8 = 0x23;
+const OP_DATA_IN: u8 = 0x25;
+const OP_LOGOUT_RESPONSE: u8 = 0x26;
+
+const FLAG_IMMEDIATE: u8 = 0x40;
+const FLAG_FINAL: u8 = 0x80;
+const FLAG_READ: u8 = 0x40;
+
+/// Moving from the operational stage straight to the full feature phase.
+const LOGIN_TO_FULL_FEATURE: u8 = 0x80 | (1 << 2) | 3;
+
+const LOGIN_TARGET_NOT_FOUND: u16 = 0x0203;
+
+fn pdu(input: &mut Vec<u8>, bhs: [u8; 48], data: &[u8]) {
+    let mut bhs = bhs;
+    bhs[5..8].copy_from_slice(&(data.len() as u32).to_be_bytes()[1..]);
+    input.extend_from_slice(&bhs);
+    input.extend_from_slice(data);
+    input.resize(input.len() + (4 - data.len() % 4) % 4, 0);
+}
+
+/// Logs in to `target_name` in a single step, asking for no digests.
+fn login(input: &mut Vec<u8>, target_name: &str) {
+    let mut bhs = [0u8; 48];
+    bhs[0] = OP_LOGIN | FLAG_IMMEDIATE;
+    bhs[1] = LOGIN_TO_FULL_FEATURE;
+    bhs[8..14].copy_from_slice(&[0x80, 0, 0, 0, 0, 1]);
+    bhs[16..20].copy_from_slice(&1u32.to_be_bytes());
+    bhs[24..28].copy_from_slice(&1u32.to_be_bytes());
+    let text = format!(
+        "InitiatorName=iqn.2020-01.test:initiator\0TargetName={}\0SessionType=Normal\0\
+         HeaderDigest=None\0MaxRecvDataSegmentLength=65536\0",
+        target_name
+    );
+    pdu(input, bhs, text.as_bytes());
+}
+
+/// Sends the READ command `cdb` as the task `itt`, expecting `expected` bytes.
+fn read_command(input: &mut Vec<u8>, itt: u32, cmd_sn: u32, expected: u32, cdb: &[u8]) {
+    let mut bhs = [0u8; 48];
+    bhs[0] = OP_SCSI_COMMAND;
+    bhs[1] = FLAG_FINAL | FLAG_READ;
+    bhs[16..20].copy_from_slice(&itt.to_be_bytes());
+    bhs[20..24].copy_from_slice(&expected.to_be_bytes());
+    bhs[24..28].copy_from_slice(&cmd_sn.to_be_bytes());
+    bhs[32..32 + cdb.len()].copy_from_slice(cdb);
+    pdu(input, bhs, &[]);
+}
+
+fn logout(input: &mut Vec<u8>, itt: u32, cmd_sn: u32) {
+    let mut bhs = [0u8; 48];
+    bhs[0] = OP_LOGOUT | FLAG_IMMEDIATE;
+    bhs[1] = FLAG_FINAL;
+    bhs[16..20].copy_from_slice(&itt.to_be_bytes());
+    bhs[24..28].copy_from_slice(&cmd_sn.to_be_bytes());
+    pdu(input, bhs, &[]);
+}
+
+/// Reads the next PDU the target sent, checking its opcode and Initiator Task
+/// Tag, and returns its header and data segment.
+fn next_pdu<'a>(replies: &mut Replies<'a>, opcode: u8, itt: u32) -> ([u8; 48], &'a [u8]) {
+    let bhs: [u8; 48] = replies.take(48).try_into().unwrap();
+    assert_eq!(bhs[0] & 0x3F, opcode);
+    assert_eq!(&bhs[16..20], &itt.to_be_bytes());
+    let len = u32::from_be_bytes([0, bhs[5], bhs[6], bhs[7]]) as usize;
+    let data = replies.take(len);
+    replies.take((4 - len % 4) % 4);
+    (bhs, data)
+}
+
+/// Reads the response to the SCSI command `itt`, returning its status.
+fn scsi_response(replies: &mut Replies, itt: u32) -> u8 {
+    let (bhs, _) = next_pdu(replies, OP_SCSI_RESPONSE, itt);
+    bhs[3]
+}
+
+fn keys(data: &[u8]) -> Vec<&str> {
+    data.split(|&b| b == 0)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| std::str::from_utf8(pair).unwrap())
+        .collect()
+}
+
+#[test]
+fn logs_in_and_reads() {
+    let root = TempDir::new("iscsi-read");
+    let mut fake = build(&root);
+    let sectors = fake.image_size() / 512;
+    let mut expected = vec![0; 512];
+    fake.read_at(512, &mut expected);
+
+    let mut input = Vec::new();
+    login(&mut input, ISCSI_DEFAULT_TARGET_NAME);
+    read_command(&mut input, 2, 1, 8, &[0x25]);
+    let mut read = [0u8; 10];
+    read[0] = 0x28;
+    read[2..6].copy_from_slice(&1u32.to_be_bytes());
+    read[7..9].copy_from_slice(&1u16.to_be_bytes());
+    read_command(&mut input, 3, 2, 512, &read);
+    logout(&mut input, 4, 3);
+
+    let mut target = IscsiTarget::new(fake);
+    let mut connection = Connection::new(input);
+    target.serve(&mut connection).unwrap();
+
+    let mut replies = Replies(&connection.output);
+    let (bhs, data) = next_pdu(&mut replies, OP_LOGIN_RESPONSE, 1);
+    assert_eq!(bhs[1], LOGIN_TO_FULL_FEATURE);
+    assert_eq!(&bhs[36..38], &[0, 0]);
+    let keys = keys(data);
+    assert!(keys.contains(&"HeaderDigest=None"));
+    assert!(keys.contains(&"TargetPortalGroupTag=1"));
+
+    // READ CAPACITY(10) answers with the last LBA and the block size.
+    let (_, data) = next_pdu(&mut replies, OP_DATA_IN, 2);
+    assert_eq!(&data[0..4], &(sectors as u32 - 1).to_be_bytes());
+    assert_eq!(&data[4..8], &512u32.to_be_bytes());
+    assert_eq!(scsi_response(&mut replies, 2), 0);
+
+    let (_, data) = next_pdu(&mut replies, OP_DATA_IN, 3);
+    assert_eq!(data, &expected[..]);
+    assert_eq!(scsi_response(&mut replies, 3), 0);
+
+    next_pdu(&mut replies, OP_LOGOUT_RESPONSE, 4);
+    assert!(replies.0.is_empty());
+}
+
+#[test]
+fn unknown_targets_are_refused() {
+    let root = TempDir::new("iscsi-unknown");
+    let mut input = Vec::new();
+    login(&mut input, "iqn.2020-01.test:elsewhere");
+    read_command(&mut input, 2, 1, 8, &[0x25]);
+
+    let mut target = IscsiTarget::new(build(&root));
+    let mut connection = Connection::new(input);
+    target.serve(&mut connection).unwrap();
+
+    // The login fails without moving on to the full feature phase, and the
+    // connection is closed before the command is read.
+    let mut replies = Replies(&connection.output);
+    let (bhs, _) = next_pdu(&mut replies, OP_LOGIN_RESPONSE, 1);
+    assert_eq!(bhs[1] & FLAG_FINAL, 0);
+    assert_eq!(&bhs[36..38], &LOGIN_TARGET_NOT_FOUND.to_be_bytes());
+    assert!(replies.0.is_empty());
+}
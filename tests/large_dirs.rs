@@ -0,0 +1,125 @@
+//! Directories whose entries take up more than a single cluster, read back
+//! through a real FAT driver.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFat, FakeFatBuilder, FatType, StdFileSystem};
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+/// The number of files put in the large directory, enough for its entries to
+/// span several 4 KiB clusters and to run past the first 64 KiB, where their
+/// byte offsets no longer fit in 16 bits.
+const FILE_COUNT: usize = 600;
+
+/// The name of the `idx`th file. The names vary in length so that they take
+/// up different numbers of Long File Name slots, which makes some entries
+/// straddle the boundary between two clusters.
+fn file_name(idx: usize) -> String {
+    format!("{}entry {:04}.txt", "Long ".repeat(idx % 7), idx)
+}
+
+fn file_contents(idx: usize) -> Vec<u8> {
+    format!("contents of file {}\n", idx).into_bytes()
+}
+
+fn populate(root: &Path) {
+    let big = root.join("big");
+    fs::create_dir(&big).unwrap();
+    for idx in 0..FILE_COUNT {
+        fs::write(big.join(file_name(idx)), file_contents(idx)).unwrap();
+    }
+}
+
+fn build(root: &Path) -> FakeFat<StdFileSystem> {
+    FakeFatBuilder::new()
+        .fat_type(FatType::Fat32)
+        .total_capacity(512 * 1024 * 1024)
+        .sectors_per_cluster(8)
+        .build(StdFileSystem::new(), root.to_str().unwrap())
+}
+
+#[test]
+fn lists_every_entry_of_a_multi_cluster_directory() {
+    let root = TempDir::new("large-dir-list");
+    populate(&root.0);
+    let fat = fatfs::FileSystem::new(build(&root.0), fatfs::FsOptions::new()).unwrap();
+    let big = fat.root_dir().open_dir("big").unwrap();
+
+    let mut listed: Vec<String> = big
+        .iter()
+        .map(|entry| entry.unwrap().file_name())
+        .filter(|name| name != "." && name != "..")
+        .collect();
+    listed.sort();
+    let mut expected: Vec<String> = (0..FILE_COUNT).map(file_name).collect();
+    expected.sort();
+    assert_eq!(listed, expected);
+
+    // Opening a file scans the directory from the start, so only a spread of
+    // them is checked to keep the test quick.
+    for idx in (0..FILE_COUNT).step_by(97) {
+        let mut contents = Vec::new();
+        big.open_file(&file_name(idx))
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(
+            contents,
+            file_contents(idx),
+            "contents of {}",
+            file_name(idx)
+        );
+    }
+}
+
+#[test]
+fn unaligned_reads_match_whole_reads() {
+    let root = TempDir::new("large-dir-reads");
+    populate(&root.0);
+    let mut fat = build(&root.0);
+
+    // Covers the FATs, the root directory, the large directory and the files
+    // in it.
+    let len = 3 * 1024 * 1024;
+    let mut whole = vec![0; len];
+    assert_eq!(fat.read_at(0, &mut whole), len);
+
+    // An odd read size makes most reads cross a sector and cluster boundary.
+    let mut pieces = vec![0; len];
+    for (idx, chunk) in pieces.chunks_mut(509).enumerate() {
+        assert_eq!(fat.read_at(idx * 509, chunk), chunk.len());
+    }
+    assert!(whole == pieces, "piecewise reads differ from a whole read");
+}
+
+#[test]
+fn estimate_matches_layout() {
+    let root = TempDir::new("large-dirs-estimate");
+    let big = root.0.join("big");
+    fs::create_dir(&big).unwrap();
+    for idx in 0..FILE_COUNT / 8 {
+        fs::write(big.join(file_name(idx)), file_contents(idx)).unwrap();
+    }
+    for &fat_type in [FatType::Fat12, FatType::Fat16, FatType::Fat32].iter() {
+        let builder = FakeFatBuilder::new()
+            .fat_type(fat_type)
+            .sectors_per_cluster(1);
+        let mut fs = StdFileSystem::new();
+        let estimate = builder.estimate_layout(&mut fs, root.0.to_str().unwrap());
+        let layout = builder.volume_layout(&mut fs, root.0.to_str().unwrap());
+        assert_eq!(estimate.fat_type, layout.bpb().fat_type);
+        assert_eq!(estimate.total_sectors, layout.bpb().total_sectors_32);
+        assert_eq!(estimate.sectors_per_fat, layout.bpb().sectors_per_fat_32);
+        assert_eq!(estimate.cluster_count, layout.cluster_count());
+        assert_eq!(
+            estimate.directory_clusters + estimate.file_clusters,
+            layout.allocated_clusters()
+        );
+        assert!(estimate.fits());
+    }
+}
@@ -0,0 +1,74 @@
+//! Laying out a volume apart from serving it.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFatBuilder, FatType, StdFileSystem};
+
+use std::fs;
+use std::io::Read;
+
+fn builder() -> FakeFatBuilder {
+    FakeFatBuilder::new()
+        .fat_type(FatType::Fat32)
+        .sectors_per_cluster(1)
+        .total_capacity(64 * 1024 * 1024)
+}
+
+#[test]
+fn diff_finds_the_clusters_that_moved() {
+    let root = TempDir::new("layout-diff");
+    let prefix = root.0.to_str().unwrap();
+    let log_path = root.0.join("log.bin");
+    fs::write(&log_path, vec![1; 512]).unwrap();
+    let before = builder().volume_layout(&mut StdFileSystem::new(), prefix);
+    let again = builder().volume_layout(&mut StdFileSystem::new(), prefix);
+    assert_eq!(before.diff(&again).count(), 0);
+
+    // The log grows into 2 more clusters, which are the only ones placed
+    // differently.
+    fs::write(&log_path, vec![1; 1536]).unwrap();
+    let after = builder().volume_layout(&mut StdFileSystem::new(), prefix);
+    let log = log_path.to_str().unwrap();
+    assert_eq!(before.chain(log).count(), 1);
+    let grown: Vec<u32> = after.chain(log).skip(1).collect();
+    assert_eq!(grown.len(), 2);
+    assert_eq!(before.diff(&after).collect::<Vec<_>>(), grown);
+    assert_eq!(after.diff(&before).collect::<Vec<_>>(), grown);
+    for &cluster in grown.iter() {
+        assert_eq!(before.path_for_cluster(cluster), None);
+        assert_eq!(after.path_for_cluster(cluster), Some(log));
+    }
+}
+
+#[test]
+fn devices_can_be_built_from_a_layout() {
+    let root = TempDir::new("layout-build");
+    let prefix = root.0.to_str().unwrap();
+    fs::create_dir(root.0.join("dir")).unwrap();
+    fs::write(root.0.join("dir/log.bin"), vec![7; 3000]).unwrap();
+    let layout = builder().volume_layout(&mut StdFileSystem::new(), prefix);
+
+    let mut built = builder().build(StdFileSystem::new(), prefix);
+    let mut served = builder()
+        .append_only(true)
+        .build_with_layout(layout, StdFileSystem::new());
+    // Options that are not part of the layout still come from the builder.
+    assert!(served.append_only());
+    assert!(!built.append_only());
+    let mut expected = vec![0; built.image_size()];
+    built.read_at(0, &mut expected);
+    let mut image = vec![0; served.image_size()];
+    served.read_at(0, &mut image);
+    assert!(image == expected);
+
+    let fs = fatfs::FileSystem::new(&mut served, fatfs::FsOptions::new()).unwrap();
+    let mut contents = Vec::new();
+    fs.root_dir()
+        .open_file("dir/log.bin")
+        .unwrap()
+        .read_to_end(&mut contents)
+        .unwrap();
+    assert_eq!(contents, vec![7; 3000]);
+}
@@ -0,0 +1,56 @@
+//! Exposing several devices as the Logical Units of one interface.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFat, FakeFatBuilder, FakeFatError, MultiLun, StdFileSystem};
+
+use std::fs;
+
+fn build(root: &TempDir, label: &str) -> FakeFat<StdFileSystem> {
+    fs::write(root.0.join("hello.txt"), label).unwrap();
+    FakeFatBuilder::new()
+        .total_capacity(2 * 1024 * 1024)
+        .volume_label(label)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap())
+}
+
+fn has_label(sector: &[u8], label: &str) -> bool {
+    sector
+        .windows(label.len())
+        .any(|window| window == label.as_bytes())
+}
+
+#[test]
+fn units_are_picked_by_lun() {
+    let first = TempDir::new("lun-first");
+    let second = TempDir::new("lun-second");
+    let mut luns = MultiLun::new([build(&first, "FIRST"), build(&second, "SECOND")]);
+    assert_eq!(luns.lun_count(), 2);
+    assert_eq!(luns.max_lun(), 1);
+
+    let mut sector = [0; 512];
+    assert_eq!(luns.read(0, 0, &mut sector), Ok(512));
+    assert!(has_label(&sector, "FIRST"));
+    assert_eq!(luns.read(1, 0, &mut sector), Ok(512));
+    assert!(has_label(&sector, "SECOND"));
+
+    // Writes only reach the unit they are addressed to.
+    let end = luns.image_size(1).unwrap();
+    assert_eq!(luns.write(1, end - 512, &[0xAA; 16]), Ok(16));
+    luns.read(1, end - 512, &mut sector).unwrap();
+    assert_eq!(&sector[..16], &[0xAA; 16]);
+    luns.read(0, end - 512, &mut sector).unwrap();
+    assert_eq!(&sector[..16], &[0; 16]);
+
+    assert_eq!(luns.image_size(2), None);
+    assert_eq!(
+        luns.read(2, 0, &mut sector),
+        Err(FakeFatError::NoSuchUnit { lun: 2 })
+    );
+    assert_eq!(
+        luns.write(2, 0, &[0]),
+        Err(FakeFatError::NoSuchUnit { lun: 2 })
+    );
+}
@@ -0,0 +1,178 @@
+//! Serving the device over the Network Block Device protocol.
+#![cfg(feature = "nbd")]
+
+mod common;
+
+use common::{build, build_with, Connection, Replies, TempDir};
+use fakefat::{FakeFatBuilder, NbdServer};
+
+const NBDMAGIC: u64 = 0x4E42_444D_4147_4943;
+const IHAVEOPT: u64 = 0x4948_4156_454F_5054;
+const OPTION_REPLY_MAGIC: u64 = 0x0003_E889_0455_65A9;
+const REQUEST_MAGIC: u32 = 0x2560_9513;
+const SIMPLE_REPLY_MAGIC: u32 = 0x6744_6698;
+
+const OPT_GO: u32 = 7;
+const REP_ACK: u32 = 1;
+const REP_INFO: u32 = 3;
+const CMD_READ: u16 = 0;
+const CMD_WRITE: u16 = 1;
+const CMD_DISC: u16 = 2;
+const EPERM: u32 = 1;
+const EINVAL: u32 = 22;
+
+/// A client that asks to go straight to the transmission phase, without the
+/// zeroes padding, and then sends `requests`.
+fn client(requests: &[(u16, u64, u64, u32)]) -> Vec<u8> {
+    let mut input = Vec::new();
+    input.extend_from_slice(&3u32.to_be_bytes());
+    input.extend_from_slice(&IHAVEOPT.to_be_bytes());
+    input.extend_from_slice(&OPT_GO.to_be_bytes());
+    // An empty export name and no information requests.
+    input.extend_from_slice(&6u32.to_be_bytes());
+    input.extend_from_slice(&[0; 6]);
+    for &(command, handle, offset, length) in requests {
+        input.extend_from_slice(&REQUEST_MAGIC.to_be_bytes());
+        input.extend_from_slice(&0u16.to_be_bytes());
+        input.extend_from_slice(&command.to_be_bytes());
+        input.extend_from_slice(&handle.to_be_bytes());
+        input.extend_from_slice(&offset.to_be_bytes());
+        input.extend_from_slice(&length.to_be_bytes());
+    }
+    input
+}
+
+/// Sends `data` to be written at `offset` as the request `handle`.
+fn write_request(input: &mut Vec<u8>, handle: u64, offset: u64, data: &[u8]) {
+    input.extend_from_slice(&REQUEST_MAGIC.to_be_bytes());
+    input.extend_from_slice(&0u16.to_be_bytes());
+    input.extend_from_slice(&CMD_WRITE.to_be_bytes());
+    input.extend_from_slice(&handle.to_be_bytes());
+    input.extend_from_slice(&offset.to_be_bytes());
+    input.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    input.extend_from_slice(data);
+}
+
+/// Checks the greeting and the replies to `OPT_GO`, returning the export size
+/// the server announced.
+fn negotiation(replies: &mut Replies) -> u64 {
+    assert_eq!(replies.u64(), NBDMAGIC);
+    assert_eq!(replies.u64(), IHAVEOPT);
+    replies.u16();
+    assert_eq!(replies.u64(), OPTION_REPLY_MAGIC);
+    assert_eq!(replies.u32(), OPT_GO);
+    assert_eq!(replies.u32(), REP_INFO);
+    assert_eq!(replies.u32(), 12);
+    assert_eq!(replies.u16(), 0);
+    let size = replies.u64();
+    replies.u16();
+    assert_eq!(replies.u64(), OPTION_REPLY_MAGIC);
+    assert_eq!(replies.u32(), OPT_GO);
+    assert_eq!(replies.u32(), REP_ACK);
+    assert_eq!(replies.u32(), 0);
+    size
+}
+
+/// Checks the header of the reply to the request `handle`, returning its
+/// error.
+fn reply(replies: &mut Replies, handle: u64) -> u32 {
+    assert_eq!(replies.u32(), SIMPLE_REPLY_MAGIC);
+    let error = replies.u32();
+    assert_eq!(replies.u64(), handle);
+    error
+}
+
+#[test]
+fn negotiates_and_reads() {
+    let root = TempDir::new("nbd-read");
+    let mut fake = build(&root);
+    let mut expected = vec![0; 4096];
+    fake.read_at(0, &mut expected);
+
+    let mut server = NbdServer::new(fake);
+    let mut connection = Connection::new(client(&[(CMD_READ, 1, 0, 4096), (CMD_DISC, 2, 0, 0)]));
+    server.serve(&mut connection).unwrap();
+
+    let mut replies = Replies(&connection.output);
+    assert_eq!(negotiation(&mut replies), server.fat().image_size() as u64);
+    assert_eq!(reply(&mut replies, 1), 0);
+    assert_eq!(replies.take(4096), &expected[..]);
+    assert!(replies.0.is_empty());
+}
+
+#[test]
+fn out_of_range_reads_fail() {
+    let root = TempDir::new("nbd-out-of-range");
+    let fake = build(&root);
+    let size = fake.image_size() as u64;
+
+    let mut server = NbdServer::new(fake);
+    let mut connection = Connection::new(client(&[
+        (CMD_READ, 1, size - 512, 1024),
+        (CMD_READ, 2, size - 512, 512),
+    ]));
+    server.serve(&mut connection).unwrap();
+
+    // The failed read sends no data, and the connection carries on.
+    let mut replies = Replies(&connection.output);
+    negotiation(&mut replies);
+    assert_eq!(reply(&mut replies, 1), EINVAL);
+    assert_eq!(reply(&mut replies, 2), 0);
+    assert_eq!(replies.take(512).len(), 512);
+    assert!(replies.0.is_empty());
+}
+
+#[test]
+fn preamble_writes_are_dropped() {
+    let root = TempDir::new("nbd-preamble");
+    let mut fake = build(&root);
+    let fat_start = fake.layout().bpb().fat_start();
+    let mut preamble = vec![0; fat_start];
+    fake.read_at(0, &mut preamble);
+
+    // Marking the volume dirty in the boot sector, and a write that runs on
+    // from the preamble into the first File Allocation Table.
+    let mut input = client(&[]);
+    let mut boot_sector = preamble[..512].to_vec();
+    boot_sector[37] |= 0x01;
+    write_request(&mut input, 1, 0, &boot_sector);
+    let mut spanning = preamble[fat_start - 512..].to_vec();
+    spanning.extend_from_slice(&[0xF8, 0xFF, 0xFF, 0xFF, 0xAB, 0xCD]);
+    write_request(&mut input, 2, (fat_start - 512) as u64, &spanning);
+
+    let mut server = NbdServer::new(fake);
+    let mut connection = Connection::new(input);
+    server.serve(&mut connection).unwrap();
+
+    let mut replies = Replies(&connection.output);
+    negotiation(&mut replies);
+    assert_eq!(reply(&mut replies, 1), 0);
+    assert_eq!(reply(&mut replies, 2), 0);
+    assert!(replies.0.is_empty());
+
+    let mut read_back = vec![0; fat_start + 6];
+    server.fat_mut().read_at(0, &mut read_back);
+    assert_eq!(&read_back[..fat_start], &preamble[..]);
+    assert_eq!(&read_back[fat_start + 4..], &[0xAB, 0xCD]);
+}
+
+#[test]
+fn write_protected_devices_refuse_writes() {
+    let root = TempDir::new("nbd-write-protected");
+    let fake = build_with(&root, FakeFatBuilder::new().write_protected(true));
+    let data_start = fake.layout().bpb().data_start() as u64;
+
+    let mut input = client(&[]);
+    write_request(&mut input, 1, 0, &[0; 512]);
+    write_request(&mut input, 2, data_start, &[0xA5; 512]);
+
+    let mut server = NbdServer::new(fake);
+    let mut connection = Connection::new(input);
+    server.serve(&mut connection).unwrap();
+
+    let mut replies = Replies(&connection.output);
+    negotiation(&mut replies);
+    assert_eq!(reply(&mut replies, 1), EPERM);
+    assert_eq!(reply(&mut replies, 2), EPERM);
+    assert!(replies.0.is_empty());
+}
@@ -0,0 +1,96 @@
+//! Reads of the parts of the device that no item takes up, whether free
+//! clusters or past the last sector.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFat, FakeFatBuilder, FakeFatError, FatType, OutOfRangeReads, StdFileSystem};
+
+use std::fs;
+
+fn build(root: &TempDir, policy: OutOfRangeReads) -> FakeFat<StdFileSystem> {
+    FakeFatBuilder::new()
+        .out_of_range_reads(policy)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap())
+}
+
+#[test]
+fn reads_past_the_end_follow_the_policy() {
+    let root = TempDir::new("out-of-range");
+    fs::write(root.0.join("hello.txt"), b"hello").unwrap();
+
+    let mut fake = build(&root, OutOfRangeReads::Zeros);
+    let end = fake.image_size();
+    let mut last = [0; 4];
+    assert_eq!(fake.read_at(end - 4, &mut last), 4);
+    let mut buffer = [0xAA; 8];
+    assert_eq!(fake.read_at(end - 4, &mut buffer), 8);
+    assert_eq!(buffer[..4], last);
+    assert_eq!(buffer[4..], [0; 4]);
+    assert_eq!(fake.read_byte(end + 100), 0);
+    assert_eq!(fake.session_stats().out_of_range_reads, 2);
+
+    let mut fake = build(&root, OutOfRangeReads::Error);
+    let mut buffer = [0xAA; 8];
+    assert_eq!(fake.read_at(end - 4, &mut buffer), 4);
+    assert_eq!(buffer[..4], last);
+    assert_eq!(fake.read_at(end, &mut buffer), 0);
+    assert_eq!(fake.session_stats().out_of_range_reads, 2);
+
+    let mut fake = build(&root, OutOfRangeReads::Wrap);
+    let mut first = [0; 4];
+    fake.read_at(0, &mut first);
+    let mut buffer = [0; 8];
+    assert_eq!(fake.read_at(end - 4, &mut buffer), 8);
+    assert_eq!(buffer[..4], last);
+    assert_eq!(buffer[4..], first);
+    assert_eq!(fake.read_byte(end + 1), first[1]);
+    assert_eq!(fake.session_stats().out_of_range_reads, 2);
+}
+
+#[test]
+fn free_clusters_read_as_zeros() {
+    let root = TempDir::new("out-of-range-free");
+    fs::write(root.0.join("hello.txt"), vec![0x5A; 3000]).unwrap();
+    let mut fake = FakeFatBuilder::new()
+        .fat_type(FatType::Fat16)
+        .total_capacity(8 * 1024 * 1024)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    let bpb = fake.layout().bpb().clone();
+    let end = fake.image_size();
+
+    // Everything after the last allocated cluster is free.
+    let last_cluster = bpb.cluster_count() + 1;
+    let allocated = (2..=last_cluster)
+        .rev()
+        .find(|&cluster| fake.layout().path_for_cluster(cluster).is_some())
+        .unwrap();
+    let free = bpb.cluster_start(allocated + 1);
+    let mut rest = vec![0xAA; end - free];
+    assert_eq!(fake.read_at(free, &mut rest), rest.len());
+    assert!(rest.iter().all(|&byte| byte == 0));
+    assert_eq!(fake.session_stats().out_of_range_reads, 0);
+
+    let last = bpb.cluster_start(last_cluster);
+    fake.write_at(last, &[1, 2, 3]).unwrap();
+    let mut written = [0xAA; 4];
+    assert_eq!(fake.read_at(last, &mut written), 4);
+    assert_eq!(written, [1, 2, 3, 0]);
+}
+
+#[test]
+fn writes_stop_at_the_end() {
+    let root = TempDir::new("out-of-range-writes");
+    let mut fake = build(&root, OutOfRangeReads::Zeros);
+    let end = fake.image_size();
+    assert_eq!(fake.write_at(end - 2, &[1, 2, 3, 4]), Ok(2));
+    assert_eq!(
+        fake.write_at(end, &[1]),
+        Err(FakeFatError::OutOfRange { offset: end as u64 })
+    );
+    assert!(fake.write_byte(end + 7, 1).is_err());
+    let mut last = [0; 2];
+    fake.read_at(end - 2, &mut last);
+    assert_eq!(last, [1, 2]);
+}
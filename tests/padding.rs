@@ -0,0 +1,111 @@
+//! Padding directories with empty entries, so that hosts can add items to them
+//! without growing their chains.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{HostImage, TempDir};
+use fakefat::{BuildError, DirectoryPadding, FakeFat, FakeFatBuilder, FatType, StdFileSystem};
+
+use std::fs;
+
+fn build(root: &TempDir, padding: DirectoryPadding) -> FakeFat<StdFileSystem> {
+    FakeFatBuilder::new()
+        .fat_type(FatType::Fat16)
+        .sectors_per_cluster(1)
+        .total_capacity(8 * 1024 * 1024)
+        .directory_padding(padding)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap())
+}
+
+/// Creates `count` empty files in `dir` the way a host would, which is with a
+/// Long File Name entry and a short entry for each.
+fn host_creates(fake: &mut FakeFat<StdFileSystem>, dir: &str, count: usize) {
+    let fs = fatfs::FileSystem::new(HostImage(fake), fatfs::FsOptions::new()).unwrap();
+    let dir = fs.root_dir().open_dir(dir).unwrap();
+    for idx in 0..count {
+        dir.create_file(&format!("new{}.txt", idx)).unwrap();
+    }
+    drop(dir);
+    fs.unmount().unwrap();
+}
+
+#[test]
+fn padded_directories_take_new_items_in_place() {
+    let root = TempDir::new("padding");
+    let dir = root.0.join("dir");
+    fs::create_dir(&dir).unwrap();
+    for idx in 0..20 {
+        fs::write(dir.join(format!("f{}.txt", idx)), b"x").unwrap();
+    }
+
+    // 512 byte clusters hold 16 entries, so the 20 files and the dot entries
+    // of `dir` fit in 2 clusters, and in 3 with 20 more entries.
+    let unpadded = build(&root, DirectoryPadding::None).free_clusters();
+    let percent = build(&root, DirectoryPadding::Percent(100)).free_clusters();
+    assert_eq!(percent, unpadded - 1);
+
+    // The fixed root directory region of FAT16 takes no clusters.
+    let mut fake = build(&root, DirectoryPadding::Entries(48));
+    let free = fake.free_clusters();
+    assert_eq!(free, unpadded - 3);
+    host_creates(&mut fake, "dir", 20);
+    // Empty files take no clusters, and the 40 entries fit in the 80 slots
+    // of the 5 clusters of `dir`, so it did not have to grow either.
+    assert_eq!(fake.free_clusters(), free);
+    assert_eq!(fake.validate(), Ok(()));
+
+    fake.write_back().unwrap();
+    for idx in 0..20 {
+        assert!(dir.join(format!("new{}.txt", idx)).exists());
+    }
+}
+
+#[test]
+fn directory_slack_reads_as_free_slots() {
+    let root = TempDir::new("padding-slack");
+    fs::create_dir(root.0.join("dir")).unwrap();
+    fs::write(root.0.join("dir/only.txt"), b"x").unwrap();
+    let mut fake = FakeFatBuilder::new()
+        .fat_type(FatType::Fat16)
+        .sectors_per_cluster(1)
+        .total_capacity(8 * 1024 * 1024)
+        .directory_slack_fill(0xE5)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+
+    // `dir` fits in a single 512 byte cluster: the dot entries, `only.txt`,
+    // the entry ending the directory and then the slack.
+    let mut image = vec![0; fake.image_size()];
+    fake.read_at(0, &mut image);
+    let entry = image
+        .chunks(32)
+        .position(|entry| entry.starts_with(b"ONLY    TXT"))
+        .unwrap();
+    let cluster = &image[entry / 16 * 512..entry / 16 * 512 + 512];
+    let slot = entry % 16;
+    assert_eq!(&cluster[(slot + 1) * 32..(slot + 2) * 32], &[0; 32][..]);
+    assert!(cluster[(slot + 2) * 32..].iter().all(|&b| b == 0xE5));
+
+    let fs = fatfs::FileSystem::new(&mut fake, fatfs::FsOptions::new()).unwrap();
+    let names: Vec<String> = fs
+        .root_dir()
+        .open_dir("dir")
+        .unwrap()
+        .iter()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, vec!["only.txt"]);
+}
+
+#[test]
+fn other_slack_fills_are_rejected() {
+    for &fill in &[0x00, 0xE5] {
+        let builder = FakeFatBuilder::new().directory_slack_fill(fill);
+        assert_eq!(builder.validate(), Ok(()));
+    }
+    let builder = FakeFatBuilder::new().directory_slack_fill(0xAA);
+    assert_eq!(
+        builder.validate(),
+        Err(BuildError::InvalidSlackFill { fill: 0xAA })
+    );
+}
@@ -0,0 +1,105 @@
+//! Reading a shared device from another thread while the main thread refreshes
+//! it.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFatBuilder, SectorError, SharedFakeFat, StdFileSystem};
+
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+#[test]
+fn data_plane_reads_around_control_plane_syncs() {
+    let root = TempDir::new("planes");
+    fs::write(root.0.join("first.txt"), b"first").unwrap();
+    let mut fake = FakeFatBuilder::new()
+        .total_capacity(4 * 1024 * 1024)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    let mut boot = vec![0; fake.sector_size()];
+    fake.read_sector(0, &mut boot).unwrap();
+    let sector_count = fake.sector_count();
+
+    let shared = SharedFakeFat::new(fake);
+    let (control, data) = shared.split();
+    assert_eq!(data.sector_count(), sector_count);
+    let mut sector = vec![0; data.sector_size()];
+    data.read_sector(0, &mut sector).unwrap();
+    assert_eq!(sector, boot);
+
+    let busy = control.sync(|fat| {
+        fs::write(root.0.join("second.txt"), b"second").unwrap();
+        let busy = data.read_sector(0, &mut sector);
+        fat.refresh().unwrap();
+        busy
+    });
+    assert_eq!(busy, Err(SectorError::Busy { lba: 0 }));
+
+    let done = AtomicBool::new(false);
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut sector = vec![0; data.sector_size()];
+            let mut reads = 0;
+            while !done.load(Ordering::Relaxed) || reads == 0 {
+                match data.read_sector(reads % data.sector_count(), &mut sector) {
+                    Ok(()) => reads += 1,
+                    Err(SectorError::Busy { .. }) => {}
+                    Err(e) => panic!("unexpected error {:?}", e),
+                }
+            }
+        });
+        for _ in 0..20 {
+            control.sync(|fat| fat.refresh()).unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+    });
+
+    assert!(control.try_sync(|fat| fat.is_dirty()).is_some());
+    let mut fake = shared.into_inner();
+    fake.read_sector(0, &mut sector).unwrap();
+    assert_eq!(sector, boot);
+}
+
+#[test]
+fn both_planes_read_sectors_at_once() {
+    let root = TempDir::new("planes-reads");
+    fs::write(root.0.join("file.bin"), vec![0x5A; 64 * 1024]).unwrap();
+    let mut fake = FakeFatBuilder::new()
+        .total_capacity(4 * 1024 * 1024)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    let sector_size = fake.sector_size();
+    let sectors = 256;
+    let mut expected = vec![0; sectors as usize * sector_size];
+    for (lba, sector) in expected.chunks_mut(sector_size).enumerate() {
+        fake.read_sector(lba as u32, sector).unwrap();
+    }
+
+    let shared = SharedFakeFat::new(fake);
+    let (control, data) = shared.split();
+    let expected = &expected;
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            let mut sector = vec![0; sector_size];
+            let mut lba = 0;
+            while lba < sectors {
+                match data.read_sector(lba, &mut sector) {
+                    Ok(()) => {
+                        let start = lba as usize * sector_size;
+                        assert_eq!(sector, expected[start..start + sector_size]);
+                        lba += 1;
+                    }
+                    Err(SectorError::Busy { .. }) => {}
+                    Err(e) => panic!("unexpected error {:?}", e),
+                }
+            }
+        });
+        let mut sector = vec![0; sector_size];
+        for lba in (0..sectors).rev() {
+            control.read_sector(lba, &mut sector).unwrap();
+            let start = lba as usize * sector_size;
+            assert_eq!(sector, expected[start..start + sector_size]);
+        }
+    });
+}
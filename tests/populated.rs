@@ -0,0 +1,40 @@
+//! Finding the parts of the image that can hold anything other than zeroes.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFatBuilder, StdFileSystem};
+
+use std::fs;
+
+#[test]
+fn batched_writes_are_populated() {
+    let root = TempDir::new("populated");
+    fs::write(root.0.join("hello.txt"), b"hello").unwrap();
+    let mut fake = FakeFatBuilder::new()
+        .total_capacity(2 * 1024 * 1024)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    let mut last = 0..0;
+    while let Some(range) = fake.next_populated_range(last.end) {
+        last = range;
+    }
+
+    // The first free cluster after the last populated one, which the write
+    // stays batched up in.
+    let free = last.end;
+    assert!(free < fake.image_size());
+    fake.write_byte(free, 0xAA).unwrap();
+    let range = fake.next_populated_range(last.end).unwrap();
+    assert!(range.contains(&free));
+
+    let mut buffer = vec![0; 4096];
+    let mut chunks = fake.chunks(buffer.len()).skip_unallocated(true);
+    let mut written = None;
+    while let Some((offset, data)) = chunks.next_chunk(&mut buffer) {
+        if (offset..offset + data.len()).contains(&free) {
+            written = Some(data[free - offset]);
+        }
+    }
+    assert_eq!(written, Some(0xAA));
+}
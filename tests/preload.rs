@@ -0,0 +1,45 @@
+//! Rendering directories and reading files ahead of the host's first reads.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{HostImage, TempDir};
+use fakefat::{FakeFat, FakeFatBuilder, StdFileSystem};
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+
+fn contents(fake: &mut FakeFat<StdFileSystem>, path: &str) -> Vec<u8> {
+    fake.seek(SeekFrom::Start(0)).unwrap();
+    let fs = fatfs::FileSystem::new(HostImage(fake), fatfs::FsOptions::new()).unwrap();
+    let mut contents = Vec::new();
+    fs.root_dir()
+        .open_file(path)
+        .unwrap()
+        .read_to_end(&mut contents)
+        .unwrap();
+    contents
+}
+
+#[test]
+fn preloaded_files_are_served_from_memory() {
+    let root = TempDir::new("preload");
+    fs::create_dir(root.0.join("firmware")).unwrap();
+    let update = root.0.join("firmware").join("update.bin");
+    fs::write(&update, b"version 1").unwrap();
+    fs::write(root.0.join("other.bin"), b"version 1").unwrap();
+    let mut fake = FakeFatBuilder::new().build(StdFileSystem::new(), root.0.to_str().unwrap());
+
+    let found = fake.preload(vec!["/", "firmware/update.bin", "missing.bin"]);
+    assert_eq!(found, 2);
+
+    // Changing the backing files in place only shows through for the files
+    // that were not preloaded, until the device is refreshed.
+    fs::write(&update, b"version 2").unwrap();
+    fs::write(root.0.join("other.bin"), b"version 2").unwrap();
+    assert_eq!(contents(&mut fake, "firmware/update.bin"), b"version 1");
+    assert_eq!(contents(&mut fake, "other.bin"), b"version 2");
+
+    fake.refresh().unwrap();
+    assert_eq!(contents(&mut fake, "firmware/update.bin"), b"version 2");
+}
@@ -0,0 +1,82 @@
+//! Bundles of builder options for classes of hosts.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFatBuilder, Preset, StdFileSystem};
+
+use std::fs;
+
+#[test]
+fn presets_are_found_by_name() {
+    for &preset in Preset::ALL.iter() {
+        assert_eq!(Preset::from_name(preset.name()), Some(preset));
+    }
+    assert_eq!(
+        Preset::from_name("camera-minimal"),
+        Some(Preset::CameraMinimal)
+    );
+    assert_eq!(Preset::from_name("Camera-Minimal"), None);
+    assert_eq!(Preset::from_name(""), None);
+}
+
+#[test]
+fn camera_clusters_are_32_kib_for_any_sector_size() {
+    let root = TempDir::new("preset-camera");
+    for &sector_size in &[512u16, 4096] {
+        let builder = FakeFatBuilder::new()
+            .bytes_per_sector(sector_size)
+            .preset(Preset::CameraMinimal);
+        assert_eq!(builder.validate(), Ok(()));
+        let layout = builder.volume_layout(&mut StdFileSystem::new(), root.0.to_str().unwrap());
+        let bpb = layout.bpb();
+        assert_eq!(bpb.bytes_per_sector, sector_size);
+        assert_eq!(
+            u32::from(bpb.bytes_per_sector) * u32::from(bpb.sectors_per_cluster),
+            32 * 1024
+        );
+    }
+}
+
+#[test]
+fn options_set_after_a_preset_override_it() {
+    let root = TempDir::new("preset-override");
+    let sectors_per_cluster = |builder: FakeFatBuilder| {
+        builder
+            .volume_layout(&mut StdFileSystem::new(), root.0.to_str().unwrap())
+            .bpb()
+            .sectors_per_cluster
+    };
+    let overridden = FakeFatBuilder::new()
+        .preset(Preset::CameraMinimal)
+        .sectors_per_cluster(8);
+    assert_eq!(sectors_per_cluster(overridden), 8);
+    // The preset covers the cluster size, so it replaces one set before it.
+    let replaced = FakeFatBuilder::new()
+        .sectors_per_cluster(8)
+        .preset(Preset::CameraMinimal);
+    assert_eq!(sectors_per_cluster(replaced), 64);
+}
+
+#[test]
+fn name_policies_follow_the_preset() {
+    let root = TempDir::new("preset-names");
+    fs::write(root.0.join("notes."), b"x").unwrap();
+    let names = |preset: Preset| -> Vec<String> {
+        let mut fake = FakeFatBuilder::new()
+            .preset(preset)
+            .total_capacity(2 * 1024 * 1024)
+            .build(StdFileSystem::new(), root.0.to_str().unwrap());
+        let fs = fatfs::FileSystem::new(&mut fake, fatfs::FsOptions::new()).unwrap();
+        let names = fs
+            .root_dir()
+            .iter()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names
+    };
+    assert_eq!(names(Preset::WindowsStrict), vec!["notes"]);
+    assert_eq!(names(Preset::LinuxLenient), vec!["notes_"]);
+    assert!(names(Preset::CameraMinimal).is_empty());
+}
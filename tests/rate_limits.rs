@@ -0,0 +1,140 @@
+//! Capping how fast hosts can read the contents of files.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFat, FakeFatBuilder, RateLimit, SectorError, StdFileSystem};
+
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Fills `root` with `count` files of 2048 bytes, the `n`th of which holds
+/// nothing but the byte `n + 1`.
+fn populate(root: &TempDir, count: u8) {
+    for idx in 0..count {
+        fs::write(root.0.join(format!("f{:02}.bin", idx)), vec![idx + 1; 2048]).unwrap();
+    }
+}
+
+fn builder() -> FakeFatBuilder {
+    FakeFatBuilder::new()
+        .sectors_per_cluster(8)
+        .total_capacity(4 * 1024 * 1024)
+}
+
+/// The offsets of the contents of the `count` files `populate` made, found in
+/// a device without any limits.
+fn file_offsets(root: &TempDir, count: u8) -> Vec<usize> {
+    let mut fake = builder().build(StdFileSystem::new(), root.0.to_str().unwrap());
+    let mut image = vec![0; fake.image_size()];
+    fake.read_at(0, &mut image);
+    (1..=count)
+        .map(|byte| {
+            (0..image.len())
+                .step_by(512)
+                .find(|&offset| image[offset..offset + 2048].iter().all(|&b| b == byte))
+                .unwrap()
+        })
+        .collect()
+}
+
+fn read(fake: &mut FakeFat<StdFileSystem>, offset: usize, len: usize) -> usize {
+    let mut buffer = vec![0; len];
+    fake.read_at(offset, &mut buffer)
+}
+
+#[test]
+fn budget_refills_up_to_the_burst() {
+    static NOW: AtomicU64 = AtomicU64::new(0);
+    fn clock() -> u64 {
+        NOW.load(Ordering::SeqCst)
+    }
+
+    let root = TempDir::new("rate-limits-refill");
+    populate(&root, 1);
+    let file = file_offsets(&root, 1)[0];
+    let mut fake = builder()
+        .clock(clock)
+        .read_rate_limit(RateLimit::new(1000).with_burst(1500))
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+
+    assert_eq!(read(&mut fake, file, 2048), 1500);
+    assert_eq!(read(&mut fake, file, 2048), 0);
+    // Only the contents of files are held back.
+    assert_eq!(read(&mut fake, 0, 2048), 2048);
+
+    NOW.store(250, Ordering::SeqCst);
+    assert_eq!(read(&mut fake, file, 2048), 250);
+    assert_eq!(read(&mut fake, file, 2048), 0);
+
+    // Idling for longer than it takes to refill does not add to the burst.
+    NOW.store(60_000, Ordering::SeqCst);
+    assert_eq!(read(&mut fake, file, 2048), 1500);
+}
+
+#[test]
+fn sector_reads_wait_for_a_whole_sector() {
+    static NOW: AtomicU64 = AtomicU64::new(0);
+    fn clock() -> u64 {
+        NOW.load(Ordering::SeqCst)
+    }
+
+    let root = TempDir::new("rate-limits-sector");
+    populate(&root, 1);
+    let file = file_offsets(&root, 1)[0];
+    let lba = (file / 512) as u32;
+    let mut fake = builder()
+        .clock(clock)
+        .read_rate_limit(RateLimit::new(1000))
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+
+    assert_eq!(read(&mut fake, file, 600), 600);
+    let mut sector = [0; 512];
+    assert_eq!(
+        fake.read_sector(lba, &mut sector),
+        Err(SectorError::Busy { lba })
+    );
+    // The failed read took nothing out of the budget.
+    NOW.store(112, Ordering::SeqCst);
+    assert_eq!(fake.read_sector(lba, &mut sector), Ok(()));
+    assert_eq!(sector, [1; 512]);
+}
+
+#[test]
+fn idle_buckets_are_handed_over_before_the_oldest() {
+    static NOW: AtomicU64 = AtomicU64::new(0);
+    fn clock() -> u64 {
+        NOW.load(Ordering::SeqCst)
+    }
+    fn limits(_path: &str) -> Option<RateLimit> {
+        Some(RateLimit::new(1000))
+    }
+
+    // One more file than there are buckets.
+    let root = TempDir::new("rate-limits-eviction");
+    populate(&root, 17);
+    let files = file_offsets(&root, 17);
+    let mut fake = builder()
+        .clock(clock)
+        .path_read_rate_limits(limits)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+
+    // Every file but the first uses up its whole budget, and the first only
+    // uses a byte of it, both now and a little later, which leaves it the only
+    // bucket to have refilled by the time the last file is read, but not the
+    // one that was refilled the longest time ago.
+    assert_eq!(read(&mut fake, files[0], 1), 1);
+    for &file in &files[1..16] {
+        assert_eq!(read(&mut fake, file, 2048), 1000);
+    }
+    NOW.store(400, Ordering::SeqCst);
+    assert_eq!(read(&mut fake, files[0], 1), 1);
+
+    NOW.store(600, Ordering::SeqCst);
+    assert_eq!(read(&mut fake, files[16], 2048), 1000);
+    // The second file kept its bucket, and with it what it owes.
+    assert_eq!(read(&mut fake, files[1], 2048), 600);
+    // The first file gets a bucket again, which starts out full.
+    assert_eq!(read(&mut fake, files[0], 2048), 1000);
+}
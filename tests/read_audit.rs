@@ -0,0 +1,55 @@
+//! Keeping track of which files the host read, and how much of them.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{HostImage, TempDir};
+use fakefat::{FakeFatBuilder, StdFileSystem};
+
+use std::fs;
+use std::io::Read;
+
+#[test]
+fn reads_are_recorded_per_file() {
+    let root = TempDir::new("read-audit");
+    fs::write(root.0.join("hello.txt"), b"hello").unwrap();
+    fs::write(root.0.join("unread.txt"), b"unread").unwrap();
+    fs::create_dir(root.0.join("docs")).unwrap();
+    fs::write(root.0.join("docs").join("big.bin"), vec![7; 64 * 1024]).unwrap();
+    let mut fake = FakeFatBuilder::new()
+        .sectors_per_cluster(1)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    assert!(fake.read_audit().is_none());
+    fake.enable_read_audit();
+    fake.session_start().unwrap();
+
+    let fs = fatfs::FileSystem::new(HostImage(&mut fake), fatfs::FsOptions::new()).unwrap();
+    let mut contents = Vec::new();
+    fs.root_dir()
+        .open_file("hello.txt")
+        .unwrap()
+        .read_to_end(&mut contents)
+        .unwrap();
+    let mut head = [0; 100];
+    fs.root_dir()
+        .open_file("docs/big.bin")
+        .unwrap()
+        .read_exact(&mut head)
+        .unwrap();
+    drop(fs);
+
+    let audit = fake.read_audit().unwrap();
+    let hello = audit.get("/hello.txt").unwrap();
+    assert_eq!(hello.read_end, 5);
+    assert!(hello.bytes_read >= 5);
+    assert_eq!(hello.first_session, Some(1));
+    assert_eq!(hello.last_session, Some(1));
+    // Only the sector holding the head of the file was read, not all of it.
+    let big = audit.get("docs/big.bin").unwrap();
+    assert!(big.read_end >= 100 && big.read_end < 64 * 1024);
+    assert!(audit.get("unread.txt").is_none());
+
+    let mut paths: Vec<&str> = audit.iter().map(|(path, _)| path).collect();
+    paths.sort_unstable();
+    assert_eq!(paths, ["docs/big.bin", "hello.txt"]);
+}
@@ -0,0 +1,70 @@
+//! Remapping part of the tree after the backing filesystem changed under it.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFat, FakeFatBuilder, FatType, StdFileSystem};
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Reads the file at `path` off the device the way a host would.
+fn host_reads(fake: &mut FakeFat<StdFileSystem>, path: &str) -> Vec<u8> {
+    fake.seek(SeekFrom::Start(0)).unwrap();
+    let fs = fatfs::FileSystem::new(fake, fatfs::FsOptions::new()).unwrap();
+    let mut contents = Vec::new();
+    let mut file = fs.root_dir().open_file(path).unwrap();
+    file.read_to_end(&mut contents).unwrap();
+    contents
+}
+
+/// The bytes of the data region of the device.
+fn data_region(fake: &mut FakeFat<StdFileSystem>) -> Vec<u8> {
+    let start = fake.layout().bpb().data_start();
+    let mut image = vec![0; fake.image_size() - start];
+    fake.read_at(start, &mut image);
+    image
+}
+
+#[test]
+fn remapping_grows_and_shrinks_chains_in_place() {
+    let root = TempDir::new("remap");
+    fs::write(root.0.join("grows.bin"), vec![1; 3000]).unwrap();
+    fs::create_dir(root.0.join("dir")).unwrap();
+    fs::write(root.0.join("dir").join("shrinks.bin"), vec![2; 4000]).unwrap();
+    fs::write(root.0.join("dir").join("same.bin"), vec![3; 700]).unwrap();
+    let mut fake = FakeFatBuilder::new()
+        .fat_type(FatType::Fat16)
+        .sectors_per_cluster(1)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    let free = fake.free_clusters();
+    let before = data_region(&mut fake);
+
+    // 3000 bytes take 6 clusters and 5000 take 10.
+    fs::write(root.0.join("grows.bin"), vec![1; 5000]).unwrap();
+    assert_eq!(fake.remap_path("/grows.bin"), Ok(true));
+    assert_eq!(fake.free_clusters(), free - 4);
+    assert_eq!(fake.validate(), Ok(()));
+    assert_eq!(host_reads(&mut fake, "grows.bin"), vec![1; 5000]);
+
+    // 4000 bytes take 8 clusters and 600 take 2.
+    fs::write(root.0.join("dir").join("shrinks.bin"), vec![2; 600]).unwrap();
+    assert_eq!(fake.remap_path("/dir/"), Ok(true));
+    assert_eq!(fake.free_clusters(), free + 2);
+    assert_eq!(fake.validate(), Ok(()));
+    assert_eq!(host_reads(&mut fake, "dir/shrinks.bin"), vec![2; 600]);
+    assert_eq!(host_reads(&mut fake, "dir/same.bin"), vec![3; 700]);
+
+    // The clusters the files kept did not move.
+    let after = data_region(&mut fake);
+    let kept = before
+        .chunks(512)
+        .zip(after.chunks(512))
+        .filter(|(before, _)| before.iter().all(|&b| b == 1 || b == 3));
+    assert_eq!(kept.clone().count(), 5 + 1);
+    assert!(kept.clone().all(|(before, after)| before == after));
+
+    assert_eq!(fake.remap_path("/missing"), Ok(false));
+    assert_eq!(fake.free_clusters(), free + 2);
+}
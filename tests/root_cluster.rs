@@ -0,0 +1,91 @@
+//! FAT32 volumes whose root directory does not start at the first data
+//! cluster.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{HostImage, TempDir};
+use fakefat::geometry::{cluster_to_offset, fat_entry_offset, root_dir_cluster};
+use fakefat::{FakeFat, FakeFatBuilder, FatType, StdFileSystem};
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+const ROOT_CLUSTER: u32 = 7;
+
+fn build(root: &TempDir) -> FakeFat<StdFileSystem> {
+    fs::write(root.0.join("hello.txt"), b"hello").unwrap();
+    fs::create_dir(root.0.join("nested")).unwrap();
+    fs::write(root.0.join("nested").join("inner.bin"), vec![0x5A; 10_000]).unwrap();
+    FakeFatBuilder::new()
+        .fat_type(FatType::Fat32)
+        .root_dir_first_cluster(ROOT_CLUSTER)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap())
+}
+
+fn fat_entry(fake: &mut FakeFat<StdFileSystem>, cluster: u32) -> u32 {
+    let bpb = fake.layout().bpb().clone();
+    let mut raw = [0; 4];
+    fake.read_at(fat_entry_offset(&bpb, 0, cluster), &mut raw);
+    u32::from_le_bytes(raw) & 0x0FFF_FFFF
+}
+
+#[test]
+fn root_directory_starts_at_the_requested_cluster() {
+    let root = TempDir::new("root-cluster");
+    let mut fake = build(&root);
+    let bpb = fake.layout().bpb().clone();
+    assert_eq!(bpb.root_dir_first_cluster, ROOT_CLUSTER);
+    assert_eq!(root_dir_cluster(&bpb), ROOT_CLUSTER);
+
+    let mut boot_sector = [0; 512];
+    fake.read_at(0, &mut boot_sector);
+    assert_eq!(&boot_sector[44..48], &ROOT_CLUSTER.to_le_bytes());
+
+    // The clusters in front of the root directory are left free, and the
+    // root directory itself is a chain of its own.
+    for cluster in 2..ROOT_CLUSTER {
+        assert_eq!(fat_entry(&mut fake, cluster), 0, "cluster {}", cluster);
+    }
+    assert_ne!(fat_entry(&mut fake, ROOT_CLUSTER), 0);
+    let mut entry = [0; 11];
+    fake.read_at(cluster_to_offset(&bpb, ROOT_CLUSTER), &mut entry);
+    assert!(entry.iter().any(|&b| b != 0));
+}
+
+#[test]
+fn mounts_with_a_real_driver() {
+    let root = TempDir::new("root-cluster-mount");
+    let mut fake = build(&root);
+    {
+        let fs = fatfs::FileSystem::new(HostImage(&mut fake), fatfs::FsOptions::new()).unwrap();
+        let root_dir = fs.root_dir();
+        let mut names: Vec<String> = root_dir
+            .iter()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["hello.txt", "nested"]);
+
+        let mut contents = Vec::new();
+        let mut file = root_dir.open_file("hello.txt").unwrap();
+        file.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"hello");
+
+        let mut contents = Vec::new();
+        let mut file = root_dir.open_file("nested/inner.bin").unwrap();
+        file.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, vec![0x5A; 10_000]);
+
+        let mut file = root_dir.create_file("new.txt").unwrap();
+        file.write_all(b"written").unwrap();
+        file.flush().unwrap();
+    }
+
+    fake.seek(SeekFrom::Start(0)).unwrap();
+    let fs = fatfs::FileSystem::new(HostImage(&mut fake), fatfs::FsOptions::new()).unwrap();
+    let mut contents = Vec::new();
+    let mut file = fs.root_dir().open_file("new.txt").unwrap();
+    file.read_to_end(&mut contents).unwrap();
+    assert_eq!(contents, b"written");
+}
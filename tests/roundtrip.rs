@@ -0,0 +1,193 @@
+//! Backing trees read back through a real FAT driver with the `testing` module.
+#![cfg(feature = "testing")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::testing::{assert_roundtrip, check_roundtrip};
+use fakefat::{
+    FakeFat, FakeFatBuilder, FatType, HiddenEntries, NamePolicy, ShortName, ShortNameCharset,
+    ShortNameStrategy, StdFileSystem,
+};
+
+use std::fs;
+
+fn populate(root: &TempDir) {
+    let nested = root.0.join("nested").join("deeper");
+    fs::create_dir_all(&nested).unwrap();
+    fs::create_dir(root.0.join("empty dir")).unwrap();
+    fs::write(root.0.join("SHORT.TXT"), b"short").unwrap();
+    fs::write(root.0.join("A long file name.txt"), b"long").unwrap();
+    fs::write(root.0.join("Ünïcødé 日本語.txt"), b"unicode").unwrap();
+    fs::write(root.0.join("empty.bin"), b"").unwrap();
+    fs::write(nested.join("big.bin"), vec![0xA5; 100_000]).unwrap();
+    fs::write(root.0.join("trailing dot."), b"renamed").unwrap();
+}
+
+#[test]
+fn every_fat_type_round_trips() {
+    let root = TempDir::new("roundtrip");
+    populate(&root);
+    for &fat_type in [FatType::Fat12, FatType::Fat16, FatType::Fat32].iter() {
+        let mut fake = FakeFatBuilder::new()
+            .fat_type(fat_type)
+            .volume_label("ROUNDTRIP")
+            .build(StdFileSystem::new(), root.0.to_str().unwrap());
+        assert_roundtrip(&mut fake);
+    }
+}
+
+#[test]
+fn rejected_names_are_not_expected() {
+    let root = TempDir::new("roundtrip-rejected");
+    populate(&root);
+    let mut fake = FakeFatBuilder::new()
+        .name_policy(NamePolicy::Reject)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    // Every item but the one with the trailing dot is compared.
+    assert_eq!(check_roundtrip(&mut fake), Ok(8));
+}
+
+#[test]
+fn code_page_short_names_round_trip() {
+    let root = TempDir::new("roundtrip-cp437");
+    populate(&root);
+    let mut fake = FakeFatBuilder::new()
+        .short_name_charset(ShortNameCharset::Cp437)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    assert_roundtrip(&mut fake);
+}
+
+#[test]
+fn colliding_short_names_get_unique_tails() {
+    let root = TempDir::new("roundtrip-tails");
+    for idx in 0..12 {
+        fs::write(root.0.join(format!("Long file name {}.txt", idx)), b"tail").unwrap();
+    }
+    let mut fake = FakeFatBuilder::new().build(StdFileSystem::new(), root.0.to_str().unwrap());
+    let mut short_names = Vec::new();
+    fake.name_mappings(|mapping| short_names.push(mapping.short_name.to_owned()));
+    short_names.sort();
+    assert_eq!(short_names.len(), 12);
+    assert_eq!(short_names[0], "LONGFI~1.TXT");
+    assert_eq!(short_names[11], "LONGF~12.TXT");
+    short_names.dedup();
+    assert_eq!(short_names.len(), 12);
+    for short_name in short_names.iter() {
+        assert!(fake.backing_path(short_name).is_some(), "{}", short_name);
+    }
+    assert_roundtrip(&mut fake);
+}
+
+/// The short names of every item on `fake`, sorted.
+fn short_names(fake: &mut FakeFat<StdFileSystem>) -> Vec<String> {
+    let mut short_names = Vec::new();
+    fake.name_mappings(|mapping| short_names.push(mapping.short_name.to_owned()));
+    short_names.sort();
+    short_names
+}
+
+#[test]
+fn hashed_short_names_are_unique() {
+    let root = TempDir::new("roundtrip-hashed");
+    for idx in 0..12 {
+        fs::write(root.0.join(format!("Long file name {}.txt", idx)), b"tail").unwrap();
+    }
+    let build = |strategy| {
+        FakeFatBuilder::new()
+            .short_name_strategy(strategy)
+            .build(StdFileSystem::new(), root.0.to_str().unwrap())
+    };
+    let mut fake = build(ShortNameStrategy::Hashed);
+    let mut hashed = short_names(&mut fake);
+    assert_ne!(hashed, short_names(&mut build(ShortNameStrategy::Numeric)));
+    hashed.dedup();
+    assert_eq!(hashed.len(), 12);
+    for short_name in hashed.iter() {
+        assert!(short_name.starts_with("LONG"), "{}", short_name);
+        assert!(fake.backing_path(short_name).is_some(), "{}", short_name);
+    }
+    assert_roundtrip(&mut fake);
+}
+
+#[test]
+fn custom_short_names_fall_back_to_tails() {
+    fn generator(_name: &str) -> ShortName {
+        ShortName::wrap_str("CUSTOM.TXT").unwrap()
+    }
+
+    let root = TempDir::new("roundtrip-custom");
+    fs::write(root.0.join("First long name.txt"), b"first").unwrap();
+    fs::write(root.0.join("Second long name.txt"), b"second").unwrap();
+    let mut fake = FakeFatBuilder::new()
+        .short_name_strategy(ShortNameStrategy::Custom(generator))
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    // Only one of the items can have the short name the generator makes up.
+    let short_names = short_names(&mut fake);
+    assert_eq!(short_names.len(), 2);
+    assert_eq!(short_names[0], "CUSTOM.TXT");
+    assert!(short_names[1].contains('~'), "{}", short_names[1]);
+    assert_roundtrip(&mut fake);
+}
+
+#[test]
+fn clashing_names_get_free_numbers() {
+    let root = TempDir::new("roundtrip-clashes");
+    for name in ["FOO_.txt", "foo*.txt", "foo?.txt", "foo_ (2).txt"].iter() {
+        fs::write(root.0.join(name), name.as_bytes()).unwrap();
+    }
+    let mut fake = FakeFatBuilder::new().build(StdFileSystem::new(), root.0.to_str().unwrap());
+    let mut exposed = Vec::new();
+    fake.name_mappings(|mapping| {
+        let base = |path: &str| path.rsplit('/').next().unwrap().to_owned();
+        exposed.push((base(mapping.path), base(mapping.device_path)));
+    });
+    exposed.sort();
+    let expected = [
+        ("FOO_.txt", "FOO_.txt"),
+        ("foo*.txt", "foo_ (3).txt"),
+        ("foo?.txt", "foo_ (4).txt"),
+        ("foo_ (2).txt", "foo_ (2).txt"),
+    ];
+    let expected: Vec<_> = expected
+        .iter()
+        .map(|&(path, device_path)| (path.to_owned(), device_path.to_owned()))
+        .collect();
+    assert_eq!(exposed, expected);
+    assert_roundtrip(&mut fake);
+}
+
+#[test]
+fn omitted_hidden_items_are_not_listed() {
+    let root = TempDir::new("roundtrip-hidden");
+    fs::write(root.0.join(".hidden"), b"hidden").unwrap();
+    fs::create_dir(root.0.join(".config")).unwrap();
+    fs::write(root.0.join("shown.txt"), b"shown").unwrap();
+    let listed = |hidden: HiddenEntries| {
+        let mut fake = FakeFatBuilder::new()
+            .hidden_entries(hidden)
+            .build(StdFileSystem::new(), root.0.to_str().unwrap());
+        let fat = fatfs::FileSystem::new(&mut fake, fatfs::FsOptions::new()).unwrap();
+        let mut listed: Vec<_> = fat
+            .root_dir()
+            .iter()
+            .map(|ent| ent.unwrap())
+            .map(|ent| {
+                let is_hidden = ent.attributes().contains(fatfs::FileAttributes::HIDDEN);
+                (ent.file_name(), is_hidden)
+            })
+            .collect();
+        listed.sort();
+        listed
+    };
+    let marked = [(".config", true), (".hidden", true), ("shown.txt", false)];
+    let marked: Vec<_> = marked
+        .iter()
+        .map(|&(name, is_hidden)| (name.to_owned(), is_hidden))
+        .collect();
+    assert_eq!(listed(HiddenEntries::Mark), marked);
+    assert_eq!(
+        listed(HiddenEntries::Omit),
+        [("shown.txt".to_owned(), false)]
+    );
+}
@@ -0,0 +1,64 @@
+//! Laying out a volume a directory at a time with `FakeFatBuilder::scan`.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFat, FakeFatBuilder, FatType, StdFileSystem};
+
+use std::fs;
+use std::path::Path;
+
+/// Fills `root` with nested directories, some of them empty, and files, some
+/// of them with the same contents.
+fn populate(root: &Path) {
+    for (idx, dir) in ["a", "a/b", "a/b/c", "a/empty", "d", "d/e", "f"]
+        .iter()
+        .enumerate()
+    {
+        let dir = root.join(dir);
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("unique.txt"), format!("file number {}", idx)).unwrap();
+        fs::write(dir.join("shared.bin"), vec![7; 5000]).unwrap();
+    }
+    fs::write(root.join("top.txt"), b"at the top").unwrap();
+}
+
+fn image(fake: &mut FakeFat<StdFileSystem>) -> Vec<u8> {
+    let mut image = vec![0; fake.image_size()];
+    assert_eq!(fake.read_at(0, &mut image), image.len());
+    image
+}
+
+#[test]
+fn stepped_scans_match_build() {
+    let root = TempDir::new("scan");
+    populate(&root.0);
+    let path = root.0.to_str().unwrap();
+    let options = [
+        FakeFatBuilder::new().fat_type(FatType::Fat12),
+        FakeFatBuilder::new().sectors_per_cluster(1),
+        FakeFatBuilder::new()
+            .fat_type(FatType::Fat16)
+            .write_protected(true)
+            .dedup_files(true),
+    ];
+    for builder in options.iter() {
+        let builder = builder.total_capacity(4 * 1024 * 1024);
+        let mut scan = builder.scan(StdFileSystem::new(), path);
+        let mut steps = 0;
+        while !scan.step(1) {
+            steps += 1;
+            assert!(!scan.is_complete());
+        }
+        assert!(scan.is_complete());
+        assert!(scan.step(1));
+        // Every walk visits the root and the 7 directories below it.
+        assert_eq!(scan.visited() % 8, 0);
+        assert!(steps >= scan.visited());
+
+        let mut stepped = scan.finish();
+        let mut built = builder.build(StdFileSystem::new(), path);
+        assert!(image(&mut stepped) == image(&mut built));
+    }
+}
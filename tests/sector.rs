@@ -0,0 +1,88 @@
+//! Addressing the device by Logical Block Address.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFat, FakeFatBuilder, FatType, Region, SectorError, StdFileSystem};
+
+const SECTOR: usize = 512;
+
+fn build(root: &TempDir, write_protected: bool) -> FakeFat<StdFileSystem> {
+    FakeFatBuilder::new()
+        .fat_type(FatType::Fat32)
+        .total_capacity(64 * 1024 * 1024)
+        .write_protected(write_protected)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap())
+}
+
+#[test]
+fn written_sectors_read_back() {
+    let root = TempDir::new("sector-write");
+    let mut fake = build(&root, false);
+    let lba = (fake.layout().bpb().cluster_start(10) / SECTOR) as u32;
+    let data: Vec<u8> = (0..SECTOR).map(|n| n as u8).collect();
+    assert_eq!(fake.write_sector(lba, &data), Ok(()));
+    let mut buffer = [0; SECTOR];
+    assert_eq!(fake.read_sector(lba, &mut buffer), Ok(()));
+    assert_eq!(&buffer[..], &data[..]);
+    // The sectors around it are left alone.
+    assert_eq!(fake.read_sector(lba + 1, &mut buffer), Ok(()));
+    assert_eq!(buffer, [0; SECTOR]);
+}
+
+#[test]
+fn bad_sector_writes_are_rejected() {
+    let root = TempDir::new("sector-rejected");
+    let mut fake = build(&root, false);
+    let count = fake.sector_count();
+    assert_eq!(fake.sector_size(), SECTOR);
+
+    assert_eq!(
+        fake.write_sector(count - 1, &[0; SECTOR + 1]),
+        Err(SectorError::BadBufferSize {
+            expected: SECTOR,
+            actual: SECTOR + 1,
+        })
+    );
+    assert_eq!(
+        fake.write_sector(count, &[0; SECTOR]),
+        Err(SectorError::OutOfRange {
+            lba: count,
+            sector_count: count,
+        })
+    );
+    // The boot sector and FSInfo sector, and the backup copies of both.
+    for &(lba, region) in &[
+        (0, Region::BootSector),
+        (1, Region::FsInfo),
+        (6, Region::BootSector),
+        (7, Region::FsInfo),
+    ] {
+        let mut before = [0; SECTOR];
+        fake.read_sector(lba, &mut before).unwrap();
+        assert_eq!(
+            fake.write_sector(lba, &[0xFF; SECTOR]),
+            Err(SectorError::ReadOnly { lba, region })
+        );
+        let mut after = [0; SECTOR];
+        fake.read_sector(lba, &mut after).unwrap();
+        assert_eq!(&after[..], &before[..]);
+    }
+    // The last sector is still writable.
+    assert_eq!(fake.write_sector(count - 1, &[0xAA; SECTOR]), Ok(()));
+}
+
+#[test]
+fn write_protected_devices_reject_sector_writes() {
+    let root = TempDir::new("sector-protected");
+    let mut fake = build(&root, true);
+    let lba = (fake.layout().bpb().cluster_start(10) / SECTOR) as u32;
+    assert_eq!(
+        fake.write_sector(lba, &[0xAA; SECTOR]),
+        Err(SectorError::WriteProtected { lba })
+    );
+    let mut buffer = [0xFF; SECTOR];
+    fake.read_sector(lba, &mut buffer).unwrap();
+    assert_eq!(buffer, [0; SECTOR]);
+}
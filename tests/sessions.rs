@@ -0,0 +1,78 @@
+//! Scoping the per-host state of the device to host sessions.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFatBuilder, SessionEvent, SessionStats, StdFileSystem};
+
+use std::fs;
+use std::sync::Mutex;
+
+static EVENTS: Mutex<Vec<SessionEvent>> = Mutex::new(Vec::new());
+
+fn record(event: SessionEvent) {
+    EVENTS.lock().unwrap().push(event);
+}
+
+#[test]
+fn sessions_keep_their_own_stats() {
+    let root = TempDir::new("sessions");
+    fs::write(root.0.join("hello.txt"), b"hello").unwrap();
+    let mut fake = FakeFatBuilder::new()
+        .total_capacity(2 * 1024 * 1024)
+        .session_listener(record)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    fake.enable_host_detection();
+    let end = fake.image_size();
+    let mut buffer = [0; 512];
+
+    assert_eq!(fake.session_start(), Ok(1));
+    fake.read_at(0, &mut buffer);
+    fake.write_at(end - 512, &[0xAA; 16]).unwrap();
+    fake.read_at(end, &mut buffer);
+    assert!(fake.host_detector().unwrap().observations().sector_reads > 0);
+    let first = SessionStats {
+        bytes_read: 512,
+        bytes_written: 16,
+        clusters_changed: 1,
+        out_of_range_reads: 1,
+        backend_errors: 0,
+    };
+    assert_eq!(fake.session_stats(), first);
+
+    // Starting the next session ends the first, and nothing the first host did
+    // carries over to the second.
+    assert_eq!(fake.session_start(), Ok(2));
+    assert_eq!(fake.session(), Some(2));
+    assert_eq!(fake.session_stats(), SessionStats::default());
+    assert_eq!(fake.host_detector().unwrap().observations().sector_reads, 0);
+    fake.read_at(512, &mut buffer[..256]);
+    let second = SessionStats {
+        bytes_read: 256,
+        ..SessionStats::default()
+    };
+    assert_eq!(fake.session_end(), Ok(Some(second)));
+    assert_eq!(fake.session(), None);
+    assert_eq!(fake.session_end(), Ok(None));
+
+    // The write of the first session is still there.
+    fake.read_at(end - 512, &mut buffer);
+    assert_eq!(&buffer[..16], &[0xAA; 16]);
+
+    assert_eq!(
+        *EVENTS.lock().unwrap(),
+        [
+            SessionEvent::Started { id: 1 },
+            SessionEvent::Ended {
+                id: 1,
+                stats: first
+            },
+            SessionEvent::Started { id: 2 },
+            SessionEvent::Ended {
+                id: 2,
+                stats: second
+            },
+        ]
+    );
+}
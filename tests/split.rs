@@ -0,0 +1,70 @@
+//! Serving a layout computed from one source of the tree with the file
+//! contents of another.
+#![cfg(feature = "std")]
+
+use fakefat::{
+    DataProvider, FakeFat, FakeFatBuilder, FileSystemOps, ManifestEntry, ManifestFileSystem,
+    SplitFileSystem,
+};
+
+use std::io::{Read, Seek, SeekFrom};
+
+/// File contents that are `self.0` throughout.
+struct Fill(u8);
+
+impl DataProvider for Fill {
+    type Error = ();
+
+    fn read_at(&self, _path: &str, _offset: usize, buffer: &mut [u8]) -> Result<usize, ()> {
+        buffer.fill(self.0);
+        Ok(buffer.len())
+    }
+}
+
+fn entries() -> [ManifestEntry<'static>; 3] {
+    [
+        ManifestEntry::file("a.txt", 3000),
+        ManifestEntry::directory("dir"),
+        ManifestEntry::file("dir/b.bin", 5000),
+    ]
+}
+
+fn read_file<T: FileSystemOps>(fake: &mut FakeFat<T>, path: &str) -> Vec<u8> {
+    fake.seek(SeekFrom::Start(0)).unwrap();
+    let fs = fatfs::FileSystem::new(fake, fatfs::FsOptions::new()).unwrap();
+    let mut contents = Vec::new();
+    fs.root_dir()
+        .open_file(path)
+        .unwrap()
+        .read_to_end(&mut contents)
+        .unwrap();
+    contents
+}
+
+#[test]
+fn file_contents_come_from_the_data_source() {
+    let entries = entries();
+    let (tree, first, second) = (Fill(0), Fill(0x11), Fill(0x22));
+    let manifest = |provider| ManifestFileSystem::new(&entries, provider);
+    let builder = FakeFatBuilder::new().total_capacity(2 * 1024 * 1024);
+    // The tree is all the layout is computed from.
+    let layout = builder.volume_layout(&mut manifest(&tree), "/");
+
+    let mut split = SplitFileSystem::new(manifest(&tree), manifest(&tree));
+    let old = split.replace_data(manifest(&first));
+    assert_eq!(old.entries().len(), entries.len());
+    let mut fake = builder.build_with_layout(layout, split);
+    assert_eq!(read_file(&mut fake, "a.txt"), vec![0x11; 3000]);
+    assert_eq!(read_file(&mut fake, "dir/b.bin"), vec![0x11; 5000]);
+
+    // Moving the device onto another source of the same files keeps the
+    // layout, so the host sees the same volume with the new contents.
+    let mut preamble = vec![0; 4096];
+    fake.read_at(0, &mut preamble);
+    let mut fake = fake.rebind(SplitFileSystem::new(manifest(&tree), manifest(&second)));
+    let mut rebound = vec![0; 4096];
+    fake.read_at(0, &mut rebound);
+    assert_eq!(rebound, preamble);
+    assert_eq!(read_file(&mut fake, "a.txt"), vec![0x22; 3000]);
+    assert_eq!(read_file(&mut fake, "dir/b.bin"), vec![0x22; 5000]);
+}
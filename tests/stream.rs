@@ -0,0 +1,60 @@
+//! Reading the device as a stream through `std::io`.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFatBuilder, MbrWrapped, StdFileSystem};
+
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+
+#[test]
+fn reads_stop_at_the_end_of_the_device() {
+    let root = TempDir::new("stream");
+    fs::write(root.0.join("hello.txt"), b"hello").unwrap();
+    let mut fake = FakeFatBuilder::new()
+        .total_capacity(2 * 1024 * 1024)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    let len = fake.len();
+    assert_eq!(len, fake.image_size() as u64);
+    assert!(!fake.is_empty());
+
+    assert_eq!(io::copy(&mut fake, &mut io::sink()).unwrap(), len);
+    assert_eq!(fake.read(&mut [0; 16]).unwrap(), 0);
+
+    assert_eq!(fake.seek(SeekFrom::End(-4)).unwrap(), len - 4);
+    let mut buffer = [0xAA; 16];
+    assert_eq!(fake.read(&mut buffer).unwrap(), 4);
+    assert_eq!(fake.read(&mut buffer).unwrap(), 0);
+    assert_eq!(fake.seek(SeekFrom::End(100)).unwrap(), len + 100);
+    assert_eq!(fake.read(&mut buffer).unwrap(), 0);
+    assert!(fake.seek(SeekFrom::End(-(len as i64) - 1)).is_err());
+}
+
+#[test]
+fn partitioned_devices_seek_from_the_end() {
+    let root = TempDir::new("stream-mbr");
+    fs::write(root.0.join("hello.txt"), b"hello").unwrap();
+    let fake = FakeFatBuilder::new()
+        .total_capacity(2 * 1024 * 1024)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    let mut wrapped = MbrWrapped::new(fake);
+    let len = wrapped.len();
+    assert_eq!(len, wrapped.image_size() as u64);
+    assert_eq!(len, wrapped.inner().len() + 512);
+
+    assert_eq!(wrapped.seek(SeekFrom::End(0)).unwrap(), len);
+    assert_eq!(wrapped.read(&mut [0; 16]).unwrap(), 0);
+    assert_eq!(
+        wrapped.seek(SeekFrom::Current(510 - len as i64)).unwrap(),
+        510
+    );
+    let mut signature = [0; 2];
+    wrapped.read_exact(&mut signature).unwrap();
+    assert_eq!(signature, [0x55, 0xAA]);
+    assert!(wrapped.seek(SeekFrom::Current(-513)).is_err());
+
+    wrapped.seek(SeekFrom::Start(0)).unwrap();
+    assert_eq!(io::copy(&mut wrapped, &mut io::sink()).unwrap(), len);
+}
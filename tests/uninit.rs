@@ -0,0 +1,50 @@
+//! Reading the device into buffers that were never initialized.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{FakeFat, FakeFatBuilder, StdFileSystem};
+
+use std::fs;
+use std::mem::MaybeUninit;
+
+fn build(root: &TempDir) -> FakeFat<StdFileSystem> {
+    let mut fake = FakeFatBuilder::new()
+        .total_capacity(4 * 1024 * 1024)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    fake.enable_read_audit();
+    fake.enable_host_detection();
+    fake
+}
+
+#[test]
+fn uninit_reads_match_plain_reads() {
+    let root = TempDir::new("uninit");
+    let data: Vec<u8> = (0..20_000).map(|n| n as u8).collect();
+    fs::write(root.0.join("data.bin"), &data).unwrap();
+    let mut plain = build(&root);
+    let mut uninit = build(&root);
+    let size = plain.image_size();
+
+    // The whole device in reads several sectors long, the last of which runs
+    // past its end.
+    let mut buffer = vec![0; 4096];
+    let mut uninit_buffer = vec![MaybeUninit::uninit(); 4096];
+    for idx in (0..size).step_by(4096 - 100) {
+        let read = plain.read_at(idx, &mut buffer);
+        let uninit_read = uninit.read_at_uninit(idx, &mut uninit_buffer);
+        assert_eq!(uninit_read, &buffer[..read]);
+    }
+
+    // Every read counts as one access, however many sectors it spans.
+    assert_eq!(plain.session_stats(), uninit.session_stats());
+    assert_eq!(uninit.session_stats().out_of_range_reads, 1);
+    assert_eq!(
+        plain.host_detector().unwrap().observations(),
+        uninit.host_detector().unwrap().observations()
+    );
+    let record = uninit.read_audit().unwrap().get("/data.bin").unwrap();
+    assert_eq!(plain.read_audit().unwrap().get("/data.bin"), Some(record));
+    assert_eq!(record.read_end, data.len() as u32);
+}
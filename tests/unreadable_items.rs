@@ -0,0 +1,59 @@
+//! Backing items that exist but cannot be read, here symbolic links that lead
+//! nowhere or only to themselves.
+#![cfg(all(feature = "std", unix))]
+
+mod common;
+
+use common::TempDir;
+use fakefat::{
+    DirEntryOps, DirectoryOps, FakeFatBuilder, FileSystemOps, StdFileSystem, UnreadableItems,
+};
+
+use std::fs;
+use std::os::unix::fs::symlink;
+
+fn listing(fs: &mut StdFileSystem, path: &str) -> Vec<String> {
+    let dir = fs.get_dir(path).unwrap().unwrap();
+    let mut names: Vec<String> = dir
+        .entries()
+        .unwrap()
+        .into_iter()
+        .map(|ent| ent.name())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn unreadable_items_follow_the_policy() {
+    let root = TempDir::new("unreadable-items");
+    fs::write(root.0.join("good.txt"), b"good").unwrap();
+    symlink(root.0.join("missing"), root.0.join("dangling")).unwrap();
+    symlink(root.0.join("loop"), root.0.join("loop")).unwrap();
+    let path = root.0.to_str().unwrap();
+    let looped = format!("{}/loop", path);
+
+    let mut skipping = StdFileSystem::new();
+    assert_eq!(listing(&mut skipping, path), ["good.txt"]);
+    assert!(skipping.get_metadata(&looped).unwrap().is_none());
+    assert!(skipping.get_file(&looped).unwrap().is_none());
+
+    let mut reporting = StdFileSystem::with_unreadable_items(UnreadableItems::Report);
+    assert_eq!(
+        listing(&mut reporting, path),
+        ["dangling", "good.txt", "loop"]
+    );
+    assert!(reporting.get_metadata(&looped).is_err());
+    assert!(reporting.get_file(&looped).is_err());
+    let dangling = format!("{}/dangling", path);
+    assert!(reporting.get_metadata(&dangling).unwrap().is_none());
+
+    let fake = FakeFatBuilder::new().build(StdFileSystem::new(), path);
+    let fat = fatfs::FileSystem::new(fake, fatfs::FsOptions::new()).unwrap();
+    let names: Vec<String> = fat
+        .root_dir()
+        .iter()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["good.txt"]);
+}
@@ -0,0 +1,236 @@
+//! Serving the device as a USB Mass Storage stick over USB/IP.
+#![cfg(feature = "usbip")]
+
+mod common;
+
+use common::{build, build_with, Connection, Replies, TempDir};
+use fakefat::{FakeFatBuilder, UsbIpServer, USBIP_BUS_ID};
+
+const USBIP_VERSION: u16 = 0x0111;
+const OP_REQ_DEVLIST: u16 = 0x8005;
+const OP_REP_DEVLIST: u16 = 0x0005;
+const OP_REQ_IMPORT: u16 = 0x8003;
+const OP_REP_IMPORT: u16 = 0x0003;
+const CMD_SUBMIT: u32 = 0x0001;
+const RET_SUBMIT: u32 = 0x0003;
+const DIR_OUT: u32 = 0;
+const DIR_IN: u32 = 1;
+
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+
+/// The size of the device info in the device list and import replies.
+const DEVICE_INFO_SIZE: usize = 312;
+
+fn request(input: &mut Vec<u8>, code: u16) {
+    input.extend_from_slice(&USBIP_VERSION.to_be_bytes());
+    input.extend_from_slice(&code.to_be_bytes());
+    input.extend_from_slice(&0u32.to_be_bytes());
+}
+
+/// Submits a bulk transfer of `length` bytes to `endpoint`, sending `data`
+/// along with it for OUT transfers.
+fn submit(
+    input: &mut Vec<u8>,
+    seqnum: u32,
+    direction: u32,
+    endpoint: u32,
+    length: u32,
+    data: &[u8],
+) {
+    input.extend_from_slice(&CMD_SUBMIT.to_be_bytes());
+    input.extend_from_slice(&seqnum.to_be_bytes());
+    input.extend_from_slice(&0x0001_0001u32.to_be_bytes());
+    input.extend_from_slice(&direction.to_be_bytes());
+    input.extend_from_slice(&endpoint.to_be_bytes());
+    input.extend_from_slice(&0u32.to_be_bytes());
+    input.extend_from_slice(&length.to_be_bytes());
+    input.extend_from_slice(&[0; 20]);
+    input.extend_from_slice(data);
+}
+
+/// Checks the header of the reply to the URB `seqnum`, returning its status
+/// and the length of the data that follows it.
+fn ret_submit(replies: &mut Replies, seqnum: u32) -> (i32, usize) {
+    assert_eq!(replies.u32(), RET_SUBMIT);
+    assert_eq!(replies.u32(), seqnum);
+    replies.take(12);
+    let status = replies.u32() as i32;
+    let length = replies.u32() as usize;
+    replies.take(20);
+    (status, length)
+}
+
+/// A Command Block Wrapper for a WRITE(10) of `sectors` sectors at `lba`.
+fn write_cbw(tag: u32, lba: u32, sectors: u16) -> [u8; 31] {
+    let mut cbw = [0u8; 31];
+    cbw[0..4].copy_from_slice(&CBW_SIGNATURE.to_le_bytes());
+    cbw[4..8].copy_from_slice(&tag.to_le_bytes());
+    cbw[8..12].copy_from_slice(&(u32::from(sectors) * 512).to_le_bytes());
+    cbw[14] = 10;
+    cbw[15] = 0x2A;
+    cbw[17..21].copy_from_slice(&lba.to_be_bytes());
+    cbw[22..24].copy_from_slice(&sectors.to_be_bytes());
+    cbw
+}
+
+/// Imports the device, and sends each of `writes` as a WRITE(10) command
+/// with its data followed by a request for its Command Status Wrapper.
+fn import_and_write(input: &mut Vec<u8>, writes: &[(u32, &[u8])]) {
+    request(input, OP_REQ_IMPORT);
+    let mut bus_id = [0; 32];
+    bus_id[..USBIP_BUS_ID.len()].copy_from_slice(USBIP_BUS_ID.as_bytes());
+    input.extend_from_slice(&bus_id);
+    for (idx, &(lba, data)) in writes.iter().enumerate() {
+        let seqnum = 3 * idx as u32 + 1;
+        let cbw = write_cbw(idx as u32, lba, (data.len() / 512) as u16);
+        submit(input, seqnum, DIR_OUT, 2, cbw.len() as u32, &cbw);
+        submit(input, seqnum + 1, DIR_OUT, 2, data.len() as u32, data);
+        submit(input, seqnum + 2, DIR_IN, 1, 13, &[]);
+    }
+}
+
+/// Skips the import reply, returning the status of the Command Status
+/// Wrapper of each write `import_and_write` sent.
+fn write_statuses(replies: &mut Replies, writes: usize) -> Vec<u8> {
+    replies.take(8 + DEVICE_INFO_SIZE);
+    (0..writes)
+        .map(|idx| {
+            let seqnum = 3 * idx as u32 + 1;
+            ret_submit(replies, seqnum);
+            ret_submit(replies, seqnum + 1);
+            assert_eq!(ret_submit(replies, seqnum + 2), (0, 13));
+            replies.take(13)[12]
+        })
+        .collect()
+}
+
+/// Checks that `info` describes the one device under `USBIP_BUS_ID`.
+fn check_device_info(info: &[u8]) {
+    assert_eq!(
+        &info[256..256 + USBIP_BUS_ID.len()],
+        USBIP_BUS_ID.as_bytes()
+    );
+    assert_eq!(info[256 + USBIP_BUS_ID.len()], 0);
+}
+
+#[test]
+fn lists_the_device() {
+    let root = TempDir::new("usbip-devlist");
+    let mut server = UsbIpServer::new(build(&root));
+    let mut input = Vec::new();
+    request(&mut input, OP_REQ_DEVLIST);
+    let mut connection = Connection::new(input);
+    server.serve(&mut connection).unwrap();
+
+    let mut replies = Replies(&connection.output);
+    assert_eq!(replies.u16(), USBIP_VERSION);
+    assert_eq!(replies.u16(), OP_REP_DEVLIST);
+    assert_eq!(replies.u32(), 0);
+    assert_eq!(replies.u32(), 1);
+    check_device_info(replies.take(DEVICE_INFO_SIZE));
+    // The one Mass Storage, SCSI, Bulk Only interface.
+    assert_eq!(replies.take(4), &[0x08, 0x06, 0x50, 0x00]);
+    assert!(replies.0.is_empty());
+}
+
+#[test]
+fn imports_and_reads() {
+    let root = TempDir::new("usbip-read");
+    let mut fake = build(&root);
+    let mut expected = vec![0; 512];
+    fake.read_at(512, &mut expected);
+
+    let mut input = Vec::new();
+    request(&mut input, OP_REQ_IMPORT);
+    let mut bus_id = [0; 32];
+    bus_id[..USBIP_BUS_ID.len()].copy_from_slice(USBIP_BUS_ID.as_bytes());
+    input.extend_from_slice(&bus_id);
+
+    // READ(10) of the second sector, wrapped in a Command Block Wrapper.
+    let mut cbw = [0u8; 31];
+    cbw[0..4].copy_from_slice(&CBW_SIGNATURE.to_le_bytes());
+    cbw[4..8].copy_from_slice(&7u32.to_le_bytes());
+    cbw[8..12].copy_from_slice(&512u32.to_le_bytes());
+    cbw[12] = 0x80;
+    cbw[14] = 10;
+    cbw[15] = 0x28;
+    cbw[17..21].copy_from_slice(&1u32.to_be_bytes());
+    cbw[22..24].copy_from_slice(&1u16.to_be_bytes());
+    submit(&mut input, 1, DIR_OUT, 2, cbw.len() as u32, &cbw);
+    submit(&mut input, 2, DIR_IN, 1, 512, &[]);
+    submit(&mut input, 3, DIR_IN, 1, 13, &[]);
+
+    let mut server = UsbIpServer::new(fake);
+    let mut connection = Connection::new(input);
+    server.serve(&mut connection).unwrap();
+
+    let mut replies = Replies(&connection.output);
+    assert_eq!(replies.u16(), USBIP_VERSION);
+    assert_eq!(replies.u16(), OP_REP_IMPORT);
+    assert_eq!(replies.u32(), 0);
+    check_device_info(replies.take(DEVICE_INFO_SIZE));
+
+    assert_eq!(ret_submit(&mut replies, 1), (0, cbw.len()));
+    assert_eq!(ret_submit(&mut replies, 2), (0, 512));
+    assert_eq!(replies.take(512), &expected[..]);
+    assert_eq!(ret_submit(&mut replies, 3), (0, 13));
+    let csw = replies.take(13);
+    assert_eq!(&csw[0..4], &CSW_SIGNATURE.to_le_bytes());
+    assert_eq!(&csw[4..8], &7u32.to_le_bytes());
+    // No residue, and the command passed.
+    assert_eq!(&csw[8..13], &[0; 5]);
+    assert!(replies.0.is_empty());
+}
+
+#[test]
+fn preamble_writes_are_dropped() {
+    let root = TempDir::new("usbip-preamble");
+    let mut fake = build(&root);
+    let fat_start = fake.layout().bpb().fat_start();
+    let mut preamble = vec![0; fat_start];
+    fake.read_at(0, &mut preamble);
+
+    // Marking the volume dirty in the boot sector, and a write that runs on
+    // from the last reserved sector into the first File Allocation Table.
+    let mut boot_sector = preamble[..512].to_vec();
+    boot_sector[37] |= 0x01;
+    let mut spanning = preamble[fat_start - 512..].to_vec();
+    spanning.extend_from_slice(&[0xF8, 0xFF, 0xFF, 0xFF, 0xAB, 0xCD]);
+    spanning.resize(1024, 0);
+    let last_reserved = (fat_start / 512 - 1) as u32;
+    let mut input = Vec::new();
+    import_and_write(&mut input, &[(0, &boot_sector), (last_reserved, &spanning)]);
+
+    let mut server = UsbIpServer::new(fake);
+    let mut connection = Connection::new(input);
+    server.serve(&mut connection).unwrap();
+
+    let mut replies = Replies(&connection.output);
+    assert_eq!(write_statuses(&mut replies, 2), [0, 0]);
+    assert!(replies.0.is_empty());
+
+    let mut fake = server.into_inner();
+    let mut read_back = vec![0; fat_start + 6];
+    fake.read_at(0, &mut read_back);
+    assert_eq!(&read_back[..fat_start], &preamble[..]);
+    assert_eq!(&read_back[fat_start + 4..], &[0xAB, 0xCD]);
+}
+
+#[test]
+fn write_protected_devices_refuse_writes() {
+    let root = TempDir::new("usbip-write-protected");
+    let fake = build_with(&root, FakeFatBuilder::new().write_protected(true));
+    let data_start = (fake.layout().bpb().data_start() / 512) as u32;
+
+    let mut input = Vec::new();
+    import_and_write(&mut input, &[(0, &[0; 512]), (data_start, &[0xA5; 512])]);
+
+    let mut server = UsbIpServer::new(fake);
+    let mut connection = Connection::new(input);
+    server.serve(&mut connection).unwrap();
+
+    let mut replies = Replies(&connection.output);
+    assert_eq!(write_statuses(&mut replies, 2), [1, 1]);
+    assert!(replies.0.is_empty());
+}
@@ -0,0 +1,62 @@
+//! The bookkeeping behind a device staying consistent through host writes,
+//! write back and refreshes.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{HostImage, TempDir};
+use fakefat::{FakeFatBuilder, FatType, Inconsistency, StdFileSystem};
+
+use std::fs;
+use std::io::Write;
+
+#[test]
+fn stays_consistent_through_changes() {
+    let root = TempDir::new("validate");
+    fs::write(root.0.join("old.bin"), vec![7; 3000]).unwrap();
+    fs::create_dir(root.0.join("dir")).unwrap();
+    let mut fake = FakeFatBuilder::new()
+        .fat_type(FatType::Fat16)
+        .sectors_per_cluster(1)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    assert_eq!(fake.validate(), Ok(()));
+
+    let fs = fatfs::FileSystem::new(HostImage(&mut fake), fatfs::FsOptions::new()).unwrap();
+    let dir = fs.root_dir().open_dir("dir").unwrap();
+    let mut new = dir.create_file("new.txt").unwrap();
+    new.write_all(&[b'n'; 1500]).unwrap();
+    drop(new);
+    fs.root_dir().remove("old.bin").unwrap();
+    drop(dir);
+    fs.unmount().unwrap();
+    assert_eq!(fake.validate(), Ok(()));
+    let free = fake.free_clusters();
+
+    fake.write_back().unwrap();
+    assert_eq!(fake.validate(), Ok(()));
+
+    fs::write(root.0.join("dir").join("later.bin"), vec![9; 2000]).unwrap();
+    fake.refresh().unwrap();
+    assert_eq!(fake.validate(), Ok(()));
+    assert!(fake.free_clusters() < free);
+}
+
+#[test]
+fn inconsistencies_describe_themselves() {
+    let mismatch = Inconsistency::FreeCountMismatch {
+        reported: 10,
+        actual: 12,
+    };
+    assert_eq!(
+        mismatch.to_string(),
+        "FSInfo reports 10 free clusters but the FAT has 12 free"
+    );
+    let cross_linked = Inconsistency::CrossLinkedCluster {
+        cluster: 9,
+        head: 3,
+    };
+    assert_eq!(
+        cross_linked.to_string(),
+        "cluster 9 is cross-linked in the chain starting at 3"
+    );
+}
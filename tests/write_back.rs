@@ -0,0 +1,238 @@
+//! Host writes made through a real FAT driver, applied to the backing
+//! filesystem with `FakeFat::write_back`.
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{HostImage, TempDir};
+use fakefat::{CommitOp, FakeFat, FakeFatBuilder, FatType, StdFileSystem};
+
+use std::fs;
+use std::io::{Seek, SeekFrom, Write};
+
+/// The erase unit size the device is built with, which spans 8 clusters.
+const ERASE_UNIT: u32 = 4096;
+
+fn log_contents() -> Vec<u8> {
+    (0..64 * 1024).map(|idx| (idx % 251) as u8).collect()
+}
+
+/// Patches `log.bin` and creates `new.txt` through `fatfs`.
+fn write_through_driver(fake: &mut FakeFat<StdFileSystem>) {
+    let fs = fatfs::FileSystem::new(HostImage(fake), fatfs::FsOptions::new()).unwrap();
+    let root = fs.root_dir();
+    let mut log = root.open_file("log.bin").unwrap();
+    log.seek(SeekFrom::Start(5000)).unwrap();
+    log.write_all(b"patched!!!").unwrap();
+    log.flush().unwrap();
+    drop(log);
+    let mut new = root.create_file("new.txt").unwrap();
+    new.write_all(&[b'n'; 100]).unwrap();
+    new.flush().unwrap();
+    drop(new);
+    drop(root);
+    fs.unmount().unwrap();
+}
+
+#[test]
+fn plan_groups_writes_by_erase_unit() {
+    let root = TempDir::new("write-back-plan");
+    fs::write(root.0.join("log.bin"), log_contents()).unwrap();
+    let mut fake = FakeFatBuilder::new()
+        .fat_type(FatType::Fat32)
+        .sectors_per_cluster(1)
+        .erase_unit_size(ERASE_UNIT)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    write_through_driver(&mut fake);
+
+    let plan = fake.plan_write_back().unwrap();
+    assert!(plan.is_complete());
+    assert_eq!(plan.region_size(), ERASE_UNIT);
+    assert_eq!(plan.rejected(), None);
+    let writes: Vec<(u32, u32)> = plan
+        .ops()
+        .iter()
+        .filter_map(|op| match *op {
+            CommitOp::Write { offset, len, .. } => Some((offset, len)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(writes, vec![(4096, ERASE_UNIT), (0, 100)]);
+    assert_eq!(plan.bytes_written(), u64::from(ERASE_UNIT) + 100);
+    let creates = plan
+        .ops()
+        .iter()
+        .filter(|op| matches!(op, CommitOp::CreateFile { .. }))
+        .count();
+    assert_eq!(creates, 1);
+    assert!(matches!(
+        plan.ops().last(),
+        Some(CommitOp::Truncate { size: 100, .. })
+    ));
+
+    fake.write_back().unwrap();
+    let mut expected = log_contents();
+    expected[5000..5010].copy_from_slice(b"patched!!!");
+    assert_eq!(fs::read(root.0.join("log.bin")).unwrap(), expected);
+    assert_eq!(fs::read(root.0.join("new.txt")).unwrap(), vec![b'n'; 100]);
+    assert!(fake.plan_write_back().unwrap().is_empty());
+}
+
+#[test]
+#[cfg(unix)]
+fn backing_errors_are_passed_on() {
+    use fakefat::WriteBackError;
+    use std::io::ErrorKind;
+    use std::os::unix::fs::symlink;
+
+    let root = TempDir::new("write-back-error");
+    fs::write(root.0.join("log.bin"), log_contents()).unwrap();
+    let mut fake = FakeFatBuilder::new()
+        .fat_type(FatType::Fat32)
+        .sectors_per_cluster(1)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    write_through_driver(&mut fake);
+
+    // The file can no longer be opened, since it now links into a directory
+    // that does not exist.
+    fs::remove_file(root.0.join("log.bin")).unwrap();
+    symlink(root.0.join("missing/log.bin"), root.0.join("log.bin")).unwrap();
+    match fake.write_back() {
+        Err(WriteBackError::CreateFailed { error, .. }) => {
+            assert_eq!(error.kind(), ErrorKind::NotFound)
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+/// Creates `bad.txt` through `fatfs`, returning where its directory entry is
+/// on the device.
+fn create_bad_file(fake: &mut FakeFat<StdFileSystem>) -> usize {
+    let fs = fatfs::FileSystem::new(HostImage(fake), fatfs::FsOptions::new()).unwrap();
+    let mut file = fs.root_dir().create_file("bad.txt").unwrap();
+    file.write_all(b"bad").unwrap();
+    file.flush().unwrap();
+    drop(file);
+    fs.unmount().unwrap();
+
+    let mut image = vec![0; fake.image_size()];
+    fake.read_at(0, &mut image);
+    image
+        .chunks(32)
+        .position(|entry| entry.starts_with(b"BAD     TXT"))
+        .unwrap()
+        * 32
+}
+
+#[test]
+fn first_clusters_outside_the_volume_are_corrupt() {
+    use fakefat::WriteBackError;
+
+    // 0xFFFFFFFF, and 3000000000, which is still past the end of the volume
+    // once the reserved bits are dropped.
+    for &(name, high, low, masked) in &[
+        ("write-back-bad-cluster", 0xFFFFu16, 0xFFFFu16, 0x0FFF_FFFF),
+        ("write-back-bad-cluster-high", 0xB2D0, 0x5E00, 0x02D0_5E00),
+    ] {
+        let root = TempDir::new(name);
+        let mut fake = FakeFatBuilder::new()
+            .fat_type(FatType::Fat32)
+            .build(StdFileSystem::new(), root.0.to_str().unwrap());
+        let offset = create_bad_file(&mut fake);
+        fake.write_at(offset + 20, &high.to_le_bytes()).unwrap();
+        fake.write_at(offset + 26, &low.to_le_bytes()).unwrap();
+        match fake.write_back() {
+            Err(WriteBackError::CorruptChain { entry, .. }) => assert_eq!(entry, masked),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn high_cluster_word_is_ignored_on_fat16() {
+    let root = TempDir::new("write-back-fat16-high");
+    let mut fake = FakeFatBuilder::new()
+        .fat_type(FatType::Fat16)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    let offset = create_bad_file(&mut fake);
+    // FAT16 has no use for the high word, but some drivers leave junk in it.
+    fake.write_at(offset + 20, &[0xFF, 0xFF]).unwrap();
+    fake.write_back().unwrap();
+    assert_eq!(fs::read(root.0.join("bad.txt")).unwrap(), b"bad");
+}
+
+/// Builds an append-only device over `log.txt`, and has `fatfs` open it and
+/// pass it to `change`.
+fn append_only_change(
+    root: &TempDir,
+    change: impl FnOnce(&mut fatfs::File<HostImage>),
+) -> FakeFat<StdFileSystem> {
+    fs::write(root.0.join("log.txt"), b"0123456789").unwrap();
+    let mut fake = FakeFatBuilder::new()
+        .append_only(true)
+        .build(StdFileSystem::new(), root.0.to_str().unwrap());
+    let fs = fatfs::FileSystem::new(HostImage(&mut fake), fatfs::FsOptions::new()).unwrap();
+    let root_dir = fs.root_dir();
+    let mut log = root_dir.open_file("log.txt").unwrap();
+    change(&mut log);
+    log.flush().unwrap();
+    drop(log);
+    let mut new = root_dir.create_file("new.txt").unwrap();
+    new.write_all(b"new").unwrap();
+    new.flush().unwrap();
+    drop(new);
+    drop(root_dir);
+    fs.unmount().unwrap();
+    fake
+}
+
+#[test]
+fn append_only_devices_take_appends() {
+    let root = TempDir::new("write-back-append");
+    let mut fake = append_only_change(&root, |log| {
+        log.seek(SeekFrom::End(0)).unwrap();
+        log.write_all(b"abc").unwrap();
+    });
+    assert!(fake.append_only());
+    fake.write_back().unwrap();
+    assert_eq!(fs::read(root.0.join("log.txt")).unwrap(), b"0123456789abc");
+    assert_eq!(fs::read(root.0.join("new.txt")).unwrap(), b"new");
+}
+
+#[test]
+fn append_only_devices_reject_overwrites() {
+    use fakefat::WriteBackError;
+
+    let root = TempDir::new("write-back-overwrite");
+    let mut fake = append_only_change(&root, |log| {
+        log.seek(SeekFrom::Start(2)).unwrap();
+        log.write_all(b"XY").unwrap();
+        log.seek(SeekFrom::End(0)).unwrap();
+        log.write_all(b"abc").unwrap();
+    });
+    match fake.write_back() {
+        Err(WriteBackError::NotAppended { offset, .. }) => assert_eq!(offset, 2),
+        other => panic!("unexpected result {:?}", other),
+    }
+    // The log is left as it was, appended bytes and all, while the rest of the
+    // changes are still applied.
+    assert_eq!(fs::read(root.0.join("log.txt")).unwrap(), b"0123456789");
+    assert_eq!(fs::read(root.0.join("new.txt")).unwrap(), b"new");
+}
+
+#[test]
+fn append_only_devices_reject_truncation() {
+    use fakefat::WriteBackError;
+
+    let root = TempDir::new("write-back-truncate");
+    let mut fake = append_only_change(&root, |log| {
+        log.seek(SeekFrom::Start(4)).unwrap();
+        log.truncate().unwrap();
+    });
+    match fake.write_back() {
+        Err(WriteBackError::NotAppended { offset, .. }) => assert_eq!(offset, 4),
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert_eq!(fs::read(root.0.join("log.txt")).unwrap(), b"0123456789");
+    assert_eq!(fs::read(root.0.join("new.txt")).unwrap(), b"new");
+}